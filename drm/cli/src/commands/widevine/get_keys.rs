@@ -45,6 +45,13 @@ pub struct GetKeysCommand {
     */
     #[arg(short = 'H', long = "header")]
     headers: Vec<String>,
+
+    /**
+        Write a transcript of the license exchange to this file,
+        for debugging (secrets and keys are redacted).
+    */
+    #[arg(long)]
+    transcript: Option<PathBuf>,
 }
 
 impl GetKeysCommand {
@@ -61,6 +68,9 @@ impl GetKeysCommand {
 
         // Create session
         let mut session = drm_widevine::Session::new(device);
+        if self.transcript.is_some() {
+            session.enable_transcript();
+        }
 
         // Privacy mode
         if let Some(ref privacy) = self.privacy {
@@ -115,10 +125,14 @@ impl GetKeysCommand {
         let response_bytes = response.bytes().await.context("failed to read response")?;
         eprintln!("Received response ({} bytes)", response_bytes.len());
 
-        // Parse response
-        let keys = session
-            .parse_license_response(&response_bytes)
-            .context("failed to parse license response")?;
+        // Parse response, writing the transcript even if it fails
+        let parsed = session.parse_license_response(&response_bytes).map(|_| ());
+        if let (Some(path), Some(transcript)) = (&self.transcript, session.transcript()) {
+            std::fs::write(path, transcript.to_string()).context("failed to write transcript")?;
+            eprintln!("Wrote transcript to {}", path.display());
+        }
+        parsed.context("failed to parse license response")?;
+        let keys = session.keys();
 
         eprintln!("Extracted {} keys:", keys.len());
         eprintln!();
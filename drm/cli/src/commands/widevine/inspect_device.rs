@@ -32,25 +32,26 @@ impl InspectDeviceCommand {
             }
         }
 
-        if let Some(caps) = &client_id.client_capabilities {
-            println!();
-            println!("Capabilities:");
-            if let Some(v) = caps.session_token {
-                println!("  Session Token:       {v}");
-            }
-            if let Some(v) = caps.client_token {
-                println!("  Client Token:        {v}");
-            }
-            if let Some(v) = caps.max_hdcp_version {
-                println!("  Max HDCP Version:    {v}");
-            }
-            if let Some(v) = caps.oem_crypto_api_version {
-                println!("  OEMCrypto API:       {v}");
-            }
-            if let Some(v) = caps.anti_rollback_usage_table {
-                println!("  Anti-Rollback Table: {v}");
-            }
+        let caps = device.capabilities();
+        println!();
+        println!("Capabilities:");
+        if let Some(v) = caps.system_id {
+            println!("  System ID:           {v}");
+        }
+        if let Some(v) = caps.oem_crypto_api_version {
+            println!("  OEMCrypto API:       {v}");
+        }
+        println!("  Max HDCP Version:    {}", caps.max_hdcp_version);
+        if let Some(v) = caps.resource_rating_tier {
+            println!("  Resource Tier:       {v}");
         }
+        println!(
+            "  Resolution Limits:   {}",
+            caps.video_resolution_constraints
+        );
+        println!("  Session Token:       {}", caps.session_token);
+        println!("  Client Token:        {}", caps.client_token);
+        println!("  Anti-Rollback Table: {}", caps.anti_rollback_usage_table);
 
         Ok(())
     }
@@ -26,3 +26,18 @@ pub struct ParseError {
     pub kind: &'static str,
     pub value: String,
 }
+
+/**
+    Errors from parsing a `kid:key` pair.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum KeyStringError {
+    #[error("expected 'kid:key', got '{0}'")]
+    MissingSeparator(String),
+
+    #[error("invalid key ID '{0}': expected 32 hex digits, a UUID, or base64 of 16 bytes")]
+    InvalidKid(String),
+
+    #[error("invalid key '{0}': expected hex or base64")]
+    InvalidKey(String),
+}
@@ -0,0 +1,196 @@
+use core::fmt;
+use core::str::FromStr;
+
+use data_encoding::{BASE64, BASE64_NOPAD, BASE64URL, BASE64URL_NOPAD};
+
+use crate::error::KeyStringError;
+use crate::types::ContentKey;
+use crate::utils::parse_kid;
+
+/**
+    A content key in `kid:key` form, as given on the command line, in
+    manifests, or returned by key endpoints.
+
+    Both halves may be hex, base64 (standard or URL-safe, padded or not),
+    and the key ID may also be a UUID with dashes, optionally in braces.
+    Hex is tried first, since most hex strings are valid base64 too.
+
+    Displays normalized, as lowercase hex without dashes.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyString {
+    pub kid: [u8; 16],
+    pub key: Vec<u8>,
+}
+
+impl KeyString {
+    /**
+        Parse a single `kid:key` pair, ignoring surrounding whitespace.
+    */
+    pub fn parse(s: &str) -> Result<Self, KeyStringError> {
+        let s = s.trim();
+        let (kid, key) = s
+            .split_once(':')
+            .ok_or_else(|| KeyStringError::MissingSeparator(s.to_owned()))?;
+        let (kid, key) = (kid.trim(), key.trim());
+
+        let kid = parse_kid_str(kid).ok_or_else(|| KeyStringError::InvalidKid(kid.to_owned()))?;
+        let key = decode_flexible(key)
+            .filter(|key| !key.is_empty())
+            .ok_or_else(|| KeyStringError::InvalidKey(key.to_owned()))?;
+
+        Ok(Self { kid, key })
+    }
+
+    /**
+        Find the first line of the text that is a valid `kid:key` pair.
+    */
+    pub fn find(text: &str) -> Option<Self> {
+        text.lines().find_map(|line| Self::parse(line).ok())
+    }
+
+    /**
+        Key ID as a lowercase hex string.
+    */
+    pub fn kid_hex(&self) -> String {
+        hex::encode(self.kid)
+    }
+
+    /**
+        Key as a lowercase hex string.
+    */
+    pub fn key_hex(&self) -> String {
+        hex::encode(&self.key)
+    }
+}
+
+impl FromStr for KeyString {
+    type Err = KeyStringError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for KeyString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.kid_hex(), self.key_hex())
+    }
+}
+
+impl From<&ContentKey> for KeyString {
+    fn from(key: &ContentKey) -> Self {
+        Self {
+            kid: key.kid,
+            key: key.key.clone(),
+        }
+    }
+}
+
+/**
+    Parse a key ID given as hex, a UUID, or base64 of 16 bytes.
+*/
+fn parse_kid_str(s: &str) -> Option<[u8; 16]> {
+    let unbraced = s
+        .strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+        .unwrap_or(s);
+    if unbraced.len() == 36 && unbraced.bytes().filter(|&b| b == b'-').count() == 4 {
+        return parse_kid(unbraced.replace('-', "").as_str());
+    }
+    parse_kid(s).or_else(|| decode_base64(s)?.try_into().ok())
+}
+
+/**
+    Decode bytes given as hex or base64.
+*/
+fn decode_flexible(s: &str) -> Option<Vec<u8>> {
+    hex::decode(s).ok().or_else(|| decode_base64(s))
+}
+
+fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    [BASE64, BASE64_NOPAD, BASE64URL, BASE64URL_NOPAD]
+        .iter()
+        .find_map(|encoding| encoding.decode(s.as_bytes()).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    const KID: [u8; 16] = hex!("00112233445566778899aabbccddeeff");
+    const KEY: [u8; 16] = hex!("0f1e2d3c4b5a69788796a5b4c3d2e1f0");
+
+    #[test]
+    fn parse_hex() {
+        let key =
+            KeyString::parse("00112233445566778899AABBCCDDEEFF:0f1e2d3c4b5a69788796a5b4c3d2e1f0")
+                .unwrap();
+        assert_eq!(key.kid, KID);
+        assert_eq!(key.key, KEY);
+    }
+
+    #[test]
+    fn parse_uuid_kid() {
+        let key = KeyString::parse(
+            " {00112233-4455-6677-8899-aabbccddeeff}:0f1e2d3c4b5a69788796a5b4c3d2e1f0\n",
+        )
+        .unwrap();
+        assert_eq!(key.kid, KID);
+        let key = KeyString::parse(
+            "00112233-4455-6677-8899-aabbccddeeff:0f1e2d3c4b5a69788796a5b4c3d2e1f0",
+        )
+        .unwrap();
+        assert_eq!(key.kid, KID);
+    }
+
+    #[test]
+    fn parse_base64() {
+        let s = format!("{}:{}", BASE64.encode(&KID), BASE64URL_NOPAD.encode(&KEY));
+        let key: KeyString = s.parse().unwrap();
+        assert_eq!(key.kid, KID);
+        assert_eq!(key.key, KEY);
+    }
+
+    #[test]
+    fn display_normalizes() {
+        let key = KeyString::parse(
+            "00112233-4455-6677-8899-AABBCCDDEEFF:0F1E2D3C4B5A69788796A5B4C3D2E1F0",
+        )
+        .unwrap();
+        assert_eq!(
+            key.to_string(),
+            "00112233445566778899aabbccddeeff:0f1e2d3c4b5a69788796a5b4c3d2e1f0"
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(matches!(
+            KeyString::parse("00112233445566778899aabbccddeeff"),
+            Err(KeyStringError::MissingSeparator(_))
+        ));
+        assert!(matches!(
+            KeyString::parse("0011:0f1e2d3c4b5a69788796a5b4c3d2e1f0"),
+            Err(KeyStringError::InvalidKid(_))
+        ));
+        assert!(matches!(
+            KeyString::parse("00112233445566778899aabbccddeeff:"),
+            Err(KeyStringError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            KeyString::parse("00112233445566778899aabbccddeeff:not a key!"),
+            Err(KeyStringError::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn find_skips_invalid_lines() {
+        let text = "Content-Type: text/plain\n00112233445566778899aabbccddeeff:0f1e2d3c4b5a69788796a5b4c3d2e1f0\n";
+        let key = KeyString::find(text).unwrap();
+        assert_eq!(key.kid, KID);
+        assert!(KeyString::find("status: ok\n").is_none());
+    }
+}
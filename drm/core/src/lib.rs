@@ -2,6 +2,7 @@
 
 mod constants;
 mod error;
+mod key_string;
 mod pssh;
 mod reader;
 mod types;
@@ -11,7 +12,8 @@ pub mod utils;
 pub use self::constants::{
     CLEARKEY_SYSTEM_ID, FAIRPLAY_SYSTEM_ID, PLAYREADY_SYSTEM_ID, WIDEVINE_SYSTEM_ID,
 };
-pub use self::error::{ParseError, PsshError};
+pub use self::error::{KeyStringError, ParseError, PsshError};
+pub use self::key_string::KeyString;
 pub use self::pssh::PsshBox;
 pub use self::reader::{ReadError, Reader};
 pub use self::types::{ContentKey, KeyType, SystemId};
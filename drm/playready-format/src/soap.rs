@@ -1,7 +1,10 @@
 /*!
-    SOAP/XML namespace constants and algorithm URIs for PlayReady license acquisition.
+    SOAP/XML namespace constants, algorithm URIs, and canonical XML builders
+    for PlayReady license acquisition challenges.
 */
 
+use data_encoding::BASE64;
+
 /**
     SOAP 1.1 namespace.
 */
@@ -57,3 +60,398 @@ pub const AES128_CBC_ALGORITHM: &str = "http://www.w3.org/2001/04/xmlenc#aes128-
     Client version string included in license challenges.
 */
 pub const CLIENT_VERSION: &str = "10.0.16384.10011";
+
+/**
+    `Id` of the signed `<LA>` element, referenced from `<SignedInfo>`.
+*/
+pub const SIGNED_DATA_ID: &str = "SignedData";
+
+/**
+    XML declaration at the start of the envelope and the encrypted client data.
+*/
+const XML_DECLARATION: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>";
+
+/**
+    Writer for XML in the canonical form (C14N 1.0) license servers use
+    when verifying a signed challenge.
+
+    Servers digest the `<LA>` element and verify the signature over
+    `<SignedInfo>` exactly as they were sent, without re-serializing them,
+    so both must already be canonical when the client hashes and signs them:
+
+    - No XML declaration, and no whitespace between elements
+    - Empty elements are written as a start and end tag, never self-closing
+    - Attribute values are double quoted, with `&`, `<`, `"` and
+      whitespace other than spaces escaped
+    - Text has `&`, `<`, `>` and carriage returns escaped
+
+    Namespace declarations are written as attributes, in the order given.
+*/
+#[derive(Debug, Default, Clone)]
+pub struct CanonicalXml {
+    out: String,
+    open: Vec<String>,
+}
+
+impl CanonicalXml {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+        Write a start tag with the given attributes.
+    */
+    pub fn start(&mut self, name: &str, attributes: &[(&str, &str)]) -> &mut Self {
+        self.out.push('<');
+        self.out.push_str(name);
+        for (key, value) in attributes {
+            self.out.push(' ');
+            self.out.push_str(key);
+            self.out.push_str("=\"");
+            escape_attribute(&mut self.out, value);
+            self.out.push('"');
+        }
+        self.out.push('>');
+        self.open.push(name.to_string());
+        self
+    }
+
+    /**
+        Write the end tag of the innermost open element.
+    */
+    pub fn end(&mut self) -> &mut Self {
+        let name = self.open.pop().expect("no open element to end");
+        self.out.push_str("</");
+        self.out.push_str(&name);
+        self.out.push('>');
+        self
+    }
+
+    /**
+        Write escaped text content.
+    */
+    pub fn text(&mut self, text: &str) -> &mut Self {
+        escape_text(&mut self.out, text);
+        self
+    }
+
+    /**
+        Write XML that is already canonical, such as an embedded element
+        that was built separately, as is.
+    */
+    pub fn raw(&mut self, xml: &str) -> &mut Self {
+        self.out.push_str(xml);
+        self
+    }
+
+    /**
+        Write an element containing only text.
+    */
+    pub fn element(&mut self, name: &str, attributes: &[(&str, &str)], text: &str) -> &mut Self {
+        self.start(name, attributes).text(text).end()
+    }
+
+    /**
+        Write an element without content, as a start and end tag.
+    */
+    pub fn empty(&mut self, name: &str, attributes: &[(&str, &str)]) -> &mut Self {
+        self.start(name, attributes).end()
+    }
+
+    /**
+        Get the written XML. Panics if any element was left open.
+    */
+    pub fn finish(self) -> String {
+        assert!(
+            self.open.is_empty(),
+            "unclosed elements: {}",
+            self.open.join(", ")
+        );
+        self.out
+    }
+}
+
+fn escape_text(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn escape_attribute(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '"' => out.push_str("&quot;"),
+            '\t' => out.push_str("&#x9;"),
+            '\n' => out.push_str("&#xA;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/**
+    Contents of the signed `<LA>` (license acquisition) element of a challenge.
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct LicenseAcquisition<'a> {
+    /// Protocol version, from the WRM header version
+    pub protocol_version: u32,
+    /// The WRM header XML, embedded as is
+    pub content_header: &'a str,
+    pub nonce: &'a [u8; 16],
+    /// Client time, in seconds since the Unix epoch
+    pub client_time: u64,
+    /// The session key, ElGamal encrypted to the WMRM server key
+    pub encrypted_key: &'a [u8; 128],
+    /// The client data XML, AES-CBC encrypted with the session key
+    pub encrypted_client_data: &'a [u8],
+}
+
+impl LicenseAcquisition<'_> {
+    /**
+        Write the canonical `<LA>` element, which is the input of the
+        SHA-256 digest referenced from `<SignedInfo>`.
+
+        The content header is embedded without re-canonicalizing it,
+        so it must be given exactly as it appears in the PSSH.
+    */
+    pub fn to_xml(&self) -> String {
+        let mut xml = CanonicalXml::new();
+        xml.start(
+            "LA",
+            &[
+                ("xmlns", PROTOCOL_NS),
+                ("Id", SIGNED_DATA_ID),
+                ("xml:space", "preserve"),
+            ],
+        )
+        .element("Version", &[], &self.protocol_version.to_string())
+        .start("ContentHeader", &[])
+        .raw(self.content_header)
+        .end()
+        .start("CLIENTINFO", &[])
+        .element("CLIENTVERSION", &[], CLIENT_VERSION)
+        .end()
+        .element("LicenseNonce", &[], &BASE64.encode(self.nonce))
+        .element("ClientTime", &[], &self.client_time.to_string());
+
+        let element_type = format!("{XMLENC_NS}Element");
+        xml.start(
+            "EncryptedData",
+            &[("xmlns", XMLENC_NS), ("Type", &element_type)],
+        )
+        .empty("EncryptionMethod", &[("Algorithm", AES128_CBC_ALGORITHM)])
+        .start("KeyInfo", &[("xmlns", XMLDSIG_NS)])
+        .start("EncryptedKey", &[("xmlns", XMLENC_NS)])
+        .empty("EncryptionMethod", &[("Algorithm", ECC256_ALGORITHM)])
+        .start("KeyInfo", &[("xmlns", XMLDSIG_NS)])
+        .element("KeyName", &[], "WMRMServer")
+        .end()
+        .start("CipherData", &[])
+        .element("CipherValue", &[], &BASE64.encode(self.encrypted_key))
+        .end()
+        .end()
+        .end()
+        .start("CipherData", &[])
+        .element(
+            "CipherValue",
+            &[],
+            &BASE64.encode(self.encrypted_client_data),
+        )
+        .end()
+        .end()
+        .end();
+        xml.finish()
+    }
+}
+
+/**
+    Write the canonical `<SignedInfo>` element referencing the `<LA>`
+    element by its SHA-256 digest. Its bytes are what the client signs.
+*/
+pub fn signed_info_xml(la_digest: &[u8]) -> String {
+    let reference = format!("#{SIGNED_DATA_ID}");
+    let mut xml = CanonicalXml::new();
+    xml.start("SignedInfo", &[("xmlns", XMLDSIG_NS)])
+        .empty("CanonicalizationMethod", &[("Algorithm", C14N_ALGORITHM)])
+        .empty("SignatureMethod", &[("Algorithm", ECDSA_SHA256_ALGORITHM)])
+        .start("Reference", &[("URI", &reference)])
+        .empty("DigestMethod", &[("Algorithm", SHA256_ALGORITHM)])
+        .element("DigestValue", &[], &BASE64.encode(la_digest))
+        .end()
+        .end();
+    xml.finish()
+}
+
+/**
+    Write the client data XML holding the device's certificate chain,
+    which is encrypted into the `<LA>` element.
+*/
+pub fn client_data_xml(group_certificate: &[u8]) -> String {
+    // The certificate is padded with spaces, as the reference client does
+    let chain = format!(" {} ", BASE64.encode(group_certificate));
+    let mut xml = CanonicalXml::new();
+    xml.raw(XML_DECLARATION)
+        .start("Data", &[])
+        .start("CertificateChains", &[])
+        .element("CertificateChain", &[], &chain)
+        .end()
+        .start("Features", &[])
+        .empty("Feature", &[("Name", "AESCBC")])
+        .start("REE", &[])
+        .empty("AESCBCS", &[])
+        .end()
+        .end()
+        .end();
+    xml.finish()
+}
+
+/**
+    Write the complete `AcquireLicense` SOAP envelope around a signed
+    `<LA>` element, its `<SignedInfo>`, and the signature over it.
+
+    Both elements are embedded exactly as they were hashed and signed.
+*/
+pub fn challenge_envelope(
+    la_xml: &str,
+    signed_info_xml: &str,
+    signature: &[u8; 64],
+    signing_public_key: &[u8; 64],
+) -> String {
+    let mut xml = CanonicalXml::new();
+    xml.raw(XML_DECLARATION)
+        .start(
+            "soap:Envelope",
+            &[
+                ("xmlns:xsi", "http://www.w3.org/2001/XMLSchema-instance"),
+                ("xmlns:xsd", "http://www.w3.org/2001/XMLSchema"),
+                ("xmlns:soap", SOAP_NS),
+            ],
+        )
+        .start("soap:Body", &[])
+        .start("AcquireLicense", &[("xmlns", PROTOCOL_NS)])
+        .start("challenge", &[])
+        .start("Challenge", &[("xmlns", MESSAGE_NS)])
+        .raw(la_xml)
+        .start("Signature", &[("xmlns", XMLDSIG_NS)])
+        .raw(signed_info_xml)
+        .element("SignatureValue", &[], &BASE64.encode(signature))
+        .start("KeyInfo", &[("xmlns", XMLDSIG_NS)])
+        .start("KeyValue", &[])
+        .start("ECCKeyValue", &[])
+        .element("PublicKey", &[], &BASE64.encode(signing_public_key))
+        .end()
+        .end()
+        .end()
+        .end()
+        .end()
+        .end()
+        .end()
+        .end()
+        .end();
+    xml.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_form() {
+        let mut xml = CanonicalXml::new();
+        xml.start("A", &[("xmlns", "urn:a"), ("B", "x\"y&\n")])
+            .empty("C", &[])
+            .element("D", &[], "1 < 2 & 3 > 2\r\n")
+            .end();
+        assert_eq!(
+            xml.finish(),
+            "<A xmlns=\"urn:a\" B=\"x&quot;y&amp;&#xA;\"><C></C>\
+<D>1 &lt; 2 &amp; 3 &gt; 2&#xD;\n</D></A>"
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn unclosed_element_panics() {
+        let mut xml = CanonicalXml::new();
+        xml.start("A", &[]);
+        xml.finish();
+    }
+
+    #[test]
+    fn client_data_includes_certificate() {
+        let cert = b"test certificate data";
+        let xml = client_data_xml(cert);
+        assert!(xml.starts_with(XML_DECLARATION));
+        assert!(xml.contains("<Feature Name=\"AESCBC\"></Feature>"));
+        assert!(xml.contains("<AESCBCS></AESCBCS>"));
+        // Certificate should be base64-encoded with surrounding spaces
+        let cert_b64 = BASE64.encode(cert);
+        assert!(xml.contains(&format!(
+            "<CertificateChain> {cert_b64} </CertificateChain>"
+        )));
+    }
+
+    #[test]
+    fn la_element_includes_all_fields() {
+        let la = LicenseAcquisition {
+            protocol_version: 5,
+            content_header: "<WRMHEADER></WRMHEADER>",
+            nonce: &[0xAA; 16],
+            client_time: 1700000000,
+            encrypted_key: &[0xBB; 128],
+            encrypted_client_data: &[0xCC; 32],
+        }
+        .to_xml();
+
+        assert!(la.starts_with("<LA xmlns=\""));
+        assert!(la.contains("Id=\"SignedData\""));
+        assert!(la.contains("<Version>5</Version>"));
+        assert!(la.contains("<ContentHeader><WRMHEADER></WRMHEADER></ContentHeader>"));
+        assert!(la.contains(&format!("<CLIENTVERSION>{CLIENT_VERSION}</CLIENTVERSION>")));
+        assert!(la.contains("<LicenseNonce>"));
+        assert!(la.contains("<ClientTime>1700000000</ClientTime>"));
+        assert!(la.contains("<KeyName>WMRMServer</KeyName>"));
+        assert!(la.contains(ECC256_ALGORITHM));
+        assert!(la.contains(AES128_CBC_ALGORITHM));
+        assert!(la.ends_with("</EncryptedData></LA>"));
+    }
+
+    #[test]
+    fn signed_info_includes_digest() {
+        let si = signed_info_xml(&[0xDD; 32]);
+        assert!(si.contains(C14N_ALGORITHM));
+        assert!(si.contains(ECDSA_SHA256_ALGORITHM));
+        assert!(si.contains(SHA256_ALGORITHM));
+        assert!(si.contains(&format!(
+            "<DigestValue>{}</DigestValue>",
+            BASE64.encode(&[0xDD; 32])
+        )));
+        assert!(si.contains("<Reference URI=\"#SignedData\">"));
+    }
+
+    #[test]
+    fn envelope_structure() {
+        let envelope = challenge_envelope(
+            "<LA>test</LA>",
+            "<SignedInfo>test</SignedInfo>",
+            &[0xEE; 64],
+            &[0xFF; 64],
+        );
+
+        assert!(envelope.starts_with(XML_DECLARATION));
+        assert!(envelope.contains("<AcquireLicense"));
+        assert!(envelope.contains("<LA>test</LA><Signature"));
+        assert!(envelope.contains("<SignedInfo>test</SignedInfo><SignatureValue>"));
+        assert!(envelope.contains("<PublicKey>"));
+        assert!(envelope.ends_with("</soap:Body></soap:Envelope>"));
+    }
+}
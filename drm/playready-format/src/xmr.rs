@@ -1,5 +1,5 @@
 /*!
-    XMR (eXtensible Media Rights) binary license format parsing.
+    XMR (eXtensible Media Rights) binary license format parsing and serialization.
 */
 
 use drm_core::Reader;
@@ -721,6 +721,259 @@ fn parse_leaf(obj_type: u16, data: &[u8]) -> Result<XmrObjectData, FormatError>
     }
 }
 
+// ---------------------------------------------------------------------------
+// Serialization
+// ---------------------------------------------------------------------------
+
+/**
+    Flags of objects created with [`XmrObject::new`]: must-understand,
+    plus the container bit for containers.
+*/
+const MUST_UNDERSTAND: u16 = 0x0001;
+const CONTAINER: u16 = 0x0002;
+
+/**
+    Length of a TLV header: flags, type, and length.
+*/
+const OBJECT_HEADER_LEN: usize = 8;
+
+impl XmrLicense {
+    /**
+        Create an unsigned license from its objects.
+    */
+    pub fn new(version: u32, rights_id: [u8; 16], containers: Vec<XmrObject>) -> Self {
+        let mut license = Self {
+            version,
+            rights_id,
+            containers,
+            raw: Vec::new(),
+        };
+        license.raw = license.serialize();
+        license
+    }
+
+    /**
+        Create a license signed with the given function.
+
+        The signature object is added as the last object of the outer
+        container, as license servers do, or at the top level if there is
+        no outer container. The signing function gets the message bytes
+        returned by [`Self::signature_message_bytes`], and must return a
+        signature of exactly `signature_len` bytes, since the length is
+        part of the signed message.
+    */
+    pub fn new_signed(
+        version: u32,
+        rights_id: [u8; 16],
+        mut containers: Vec<XmrObject>,
+        signature_type: u16,
+        signature_len: usize,
+        sign: impl FnOnce(&[u8]) -> Vec<u8>,
+    ) -> Result<Self, FormatError> {
+        let placeholder = XmrObject::new(
+            object_type::SIGNATURE,
+            XmrObjectData::Signature(SignatureObject {
+                signature_type,
+                signature_data: vec![0; signature_len],
+            }),
+        );
+        let outer = containers.iter_mut().find_map(|obj| match &mut obj.data {
+            XmrObjectData::Container(children) if obj.obj_type == object_type::OUTER_CONTAINER => {
+                Some(children)
+            }
+            _ => None,
+        });
+        match outer {
+            Some(children) => children.push(placeholder),
+            None => containers.push(placeholder),
+        }
+
+        let unsigned = Self::new(version, rights_id, containers);
+        let message = unsigned
+            .signature_message_bytes()
+            .ok_or_else(|| FormatError::Malformed("license too short to sign".into()))?;
+        let signature = sign(message);
+        if signature.len() != signature_len {
+            return Err(FormatError::Malformed(format!(
+                "expected a {signature_len} byte signature, got {}",
+                signature.len()
+            )));
+        }
+
+        // The signature is the last field of the license either way
+        let mut raw = unsigned.raw;
+        let start = raw.len() - signature_len;
+        raw[start..].copy_from_slice(&signature);
+        Self::from_bytes(&raw)
+    }
+
+    /**
+        Serialize the license header and objects.
+    */
+    fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(XMR_MAGIC);
+        out.extend_from_slice(&self.version.to_be_bytes());
+        out.extend_from_slice(&self.rights_id);
+        for obj in &self.containers {
+            obj.write(&mut out);
+        }
+        out
+    }
+}
+
+impl XmrObject {
+    /**
+        Create an object with the must-understand flag set, and the
+        container flag set if its data is a container.
+    */
+    pub fn new(obj_type: u16, data: XmrObjectData) -> Self {
+        let flags = match data {
+            XmrObjectData::Container(_) => MUST_UNDERSTAND | CONTAINER,
+            _ => MUST_UNDERSTAND,
+        };
+        Self {
+            flags,
+            obj_type,
+            data,
+        }
+    }
+
+    /**
+        Create a container object holding the given objects.
+    */
+    pub fn container(obj_type: u16, children: Vec<XmrObject>) -> Self {
+        Self::new(obj_type, XmrObjectData::Container(children))
+    }
+
+    /**
+        Serialize the object, including its TLV header.
+    */
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write(&mut out);
+        out
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        let start = out.len();
+        out.extend_from_slice(&self.flags.to_be_bytes());
+        out.extend_from_slice(&self.obj_type.to_be_bytes());
+        out.extend_from_slice(&[0; 4]);
+        write_data(&self.data, out);
+
+        // The length excludes the header
+        let length = (out.len() - start - OBJECT_HEADER_LEN) as u32;
+        out[start + 4..start + OBJECT_HEADER_LEN].copy_from_slice(&length.to_be_bytes());
+    }
+}
+
+/**
+    Serialize an object's data, the inverse of `parse_leaf` for leaves.
+*/
+fn write_data(data: &XmrObjectData, out: &mut Vec<u8>) {
+    fn u16be(out: &mut Vec<u8>, value: u16) {
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+    fn u32be(out: &mut Vec<u8>, value: u32) {
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+    // Lengths of variable fields are 16 bits wide
+    fn sized(out: &mut Vec<u8>, bytes: &[u8]) {
+        u16be(out, bytes.len() as u16);
+        out.extend_from_slice(bytes);
+    }
+
+    match data {
+        XmrObjectData::Container(children) => {
+            for child in children {
+                child.write(out);
+            }
+        }
+        XmrObjectData::ContentKey(ck) => {
+            out.extend_from_slice(&ck.key_id);
+            u16be(out, ck.key_type.to_u16());
+            u16be(out, ck.cipher_type.to_u16());
+            sized(out, &ck.encrypted_key);
+        }
+        XmrObjectData::Signature(sig) => {
+            u16be(out, sig.signature_type);
+            sized(out, &sig.signature_data);
+        }
+        XmrObjectData::EccKey(key) => {
+            u16be(out, key.curve_type);
+            sized(out, &key.key);
+        }
+        XmrObjectData::AuxiliaryKeys(aux) => {
+            u16be(out, aux.keys.len() as u16);
+            for key in &aux.keys {
+                u32be(out, key.location);
+                out.extend_from_slice(&key.key);
+            }
+        }
+        XmrObjectData::OutputProtection(op) => {
+            u16be(out, op.compressed_digital_video);
+            u16be(out, op.uncompressed_digital_video);
+            u16be(out, op.analog_video);
+            u16be(out, op.compressed_digital_audio);
+            u16be(out, op.uncompressed_digital_audio);
+        }
+        XmrObjectData::Expiration(exp) => {
+            u32be(out, exp.begin_date);
+            u32be(out, exp.end_date);
+        }
+        XmrObjectData::IssueDate(obj) => u32be(out, obj.issue_date),
+        XmrObjectData::MeteringRestriction(obj) => out.extend_from_slice(&obj.metering_id),
+        XmrObjectData::GracePeriod(obj) => u32be(out, obj.grace_period),
+        XmrObjectData::SourceId(obj) => u32be(out, obj.source_id),
+        XmrObjectData::DomainRestriction(obj) => {
+            out.extend_from_slice(&obj.account_id);
+            u32be(out, obj.revision);
+        }
+        XmrObjectData::RightsSettings(obj) => u16be(out, obj.rights),
+        XmrObjectData::ExpirationAfterFirstPlay(obj) => u32be(out, obj.seconds),
+        XmrObjectData::RevInfoVersion(obj) => u32be(out, obj.sequence),
+        XmrObjectData::EmbeddedLicenseSettings(obj) => u16be(out, obj.indicator),
+        XmrObjectData::SecurityLevel(obj) => u16be(out, obj.minimum_security_level),
+        XmrObjectData::MoveEnabler(obj) => u32be(out, obj.minimum_move_protection_level),
+        XmrObjectData::PlayEnabler(obj) => out.extend_from_slice(&obj.play_enabler_type),
+        XmrObjectData::CopyEnabler(obj) => out.extend_from_slice(&obj.copy_enabler_type),
+        XmrObjectData::UplinkKid(obj) => {
+            out.extend_from_slice(&obj.uplink_kid);
+            u16be(out, obj.chained_checksum_type);
+            sized(out, &obj.chained_checksum);
+        }
+        XmrObjectData::CopyCount(obj) => u32be(out, obj.count),
+        XmrObjectData::RemovalDate(obj) => u32be(out, obj.removal_date),
+        XmrObjectData::SecureStop(obj) => out.extend_from_slice(&obj.metering_id),
+        XmrObjectData::PolicyMetadata(obj) => {
+            out.extend_from_slice(&obj.metadata_type);
+            out.extend_from_slice(&obj.policy_data);
+        }
+        XmrObjectData::UplinkKey3(obj) => {
+            out.extend_from_slice(&obj.uplink_key_id);
+            sized(out, &obj.checksum);
+            u16be(out, obj.entries.len() as u16);
+            for entry in &obj.entries {
+                u32be(out, *entry);
+            }
+        }
+        XmrObjectData::AnalogVideoOutput(obj) => {
+            out.extend_from_slice(&obj.video_output_protection_id);
+            out.extend_from_slice(&obj.config_data);
+        }
+        XmrObjectData::DigitalAudioOutput(obj) => {
+            out.extend_from_slice(&obj.audio_output_protection_id);
+            out.extend_from_slice(&obj.config_data);
+        }
+        XmrObjectData::DigitalVideoOutput(obj) => {
+            out.extend_from_slice(&obj.video_output_protection_id);
+            out.extend_from_slice(&obj.config_data);
+        }
+        XmrObjectData::Unknown(bytes) => out.extend_from_slice(bytes),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -851,4 +1104,88 @@ mod tests {
         let err = XmrLicense::from_bytes(data).unwrap_err();
         assert!(matches!(err, FormatError::InvalidMagic { .. }));
     }
+
+    #[test]
+    fn serialize_round_trip() {
+        let data = build_test_xmr();
+        let license = XmrLicense::from_bytes(&data).unwrap();
+        let rebuilt = XmrLicense::new(license.version, license.rights_id, license.containers);
+        assert_eq!(rebuilt.raw_bytes(), data.as_slice());
+    }
+
+    #[test]
+    fn serialize_policies_round_trip() {
+        let policies = vec![
+            XmrObject::new(
+                object_type::EXPIRATION,
+                XmrObjectData::Expiration(ExpirationObject {
+                    begin_date: 1_700_000_000,
+                    end_date: 1_800_000_000,
+                }),
+            ),
+            XmrObject::new(
+                object_type::SECURITY_LEVEL,
+                XmrObjectData::SecurityLevel(SecurityLevelObject {
+                    minimum_security_level: 2000,
+                }),
+            ),
+            XmrObject::new(
+                object_type::UPLINKX,
+                XmrObjectData::UplinkKey3(UplinkKey3Object {
+                    uplink_key_id: [0x11; 16],
+                    checksum: vec![0x22; 8],
+                    entries: vec![1, 2, 3],
+                }),
+            ),
+            XmrObject::new(0x7777, XmrObjectData::Unknown(vec![1, 2, 3])),
+        ];
+        let containers = vec![XmrObject::container(
+            object_type::OUTER_CONTAINER,
+            vec![XmrObject::container(
+                object_type::GLOBAL_POLICY_CONTAINER,
+                policies,
+            )],
+        )];
+
+        let license = XmrLicense::new(3, [0x33; 16], containers.clone());
+        let parsed = XmrLicense::from_bytes(license.raw_bytes()).unwrap();
+        assert_eq!(parsed.containers, containers);
+        assert_eq!(parsed, license);
+    }
+
+    #[test]
+    fn sign_inside_outer_container() {
+        let key = XmrObject::new(
+            object_type::CONTENT_KEY,
+            XmrObjectData::ContentKey(ContentKeyObject {
+                key_id: [0xBB; 16],
+                key_type: KeyType::Aes128Ctr,
+                cipher_type: CipherType::Ecc256,
+                encrypted_key: vec![0xCC; 128],
+            }),
+        );
+        let containers = vec![XmrObject::container(
+            object_type::OUTER_CONTAINER,
+            vec![key],
+        )];
+
+        let mut signed_message = Vec::new();
+        let license = XmrLicense::new_signed(3, [0xAA; 16], containers, 1, 16, |message| {
+            signed_message = message.to_vec();
+            vec![0xEE; 16]
+        })
+        .unwrap();
+
+        assert_eq!(license.containers.len(), 1);
+        let sig = license.find_signature().unwrap();
+        assert_eq!(sig.signature_data, vec![0xEE; 16]);
+        assert_eq!(license.signature_message_bytes().unwrap(), signed_message);
+        assert_eq!(license.find_content_keys().len(), 1);
+    }
+
+    #[test]
+    fn sign_rejects_wrong_length() {
+        let result = XmrLicense::new_signed(3, [0; 16], Vec::new(), 1, 16, |_| vec![0; 8]);
+        assert!(matches!(result, Err(FormatError::Malformed(_))));
+    }
 }
@@ -1,8 +1,7 @@
 use p256::{
     AffinePoint, FieldBytes, ProjectivePoint, Scalar,
     elliptic_curve::{
-        Field, PrimeField,
-        rand_core::OsRng,
+        PrimeField,
         sec1::{FromEncodedPoint, ToEncodedPoint},
     },
 };
@@ -13,18 +12,21 @@ use crate::error::{CdmError, CdmResult};
     ElGamal encrypt a message point to a public key on P-256.
 
     Steps:
-      k = random scalar
+      k = random scalar (passed in, so that recorded challenges can be reproduced)
       point1 = G * k
       point2 = message_point + public_key * k
       return point1.x || point1.y || point2.x || point2.y (128 bytes)
 
     Used for: encrypting the session key point to the WMRM server public key.
 */
-pub fn ecc256_encrypt(public_key: &[u8; 64], message_point: &[u8; 64]) -> CdmResult<[u8; 128]> {
+pub fn ecc256_encrypt(
+    public_key: &[u8; 64],
+    message_point: &[u8; 64],
+    k: &Scalar,
+) -> CdmResult<[u8; 128]> {
     let pk = parse_point(public_key)?;
     let msg = parse_point(message_point)?;
-
-    let k = Scalar::random(&mut OsRng);
+    let k = *k;
 
     let point1 = (ProjectivePoint::GENERATOR * k).to_affine();
     let point2 = (ProjectivePoint::from(msg) + ProjectivePoint::from(pk) * k).to_affine();
@@ -109,6 +111,7 @@ fn serialize_point(point: &AffinePoint) -> [u8; 64] {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use p256::elliptic_curve::{Field, rand_core::OsRng};
 
     #[test]
     fn encrypt_decrypt_round_trip() {
@@ -126,7 +129,8 @@ mod tests {
         let msg_bytes = serialize_point(&msg_point);
 
         // Encrypt and decrypt
-        let ciphertext = ecc256_encrypt(&pk_bytes, &msg_bytes).unwrap();
+        let ciphertext =
+            ecc256_encrypt(&pk_bytes, &msg_bytes, &Scalar::random(&mut OsRng)).unwrap();
         assert_eq!(ciphertext.len(), 128);
 
         let decrypted_x = ecc256_decrypt(&private_key, &ciphertext).unwrap();
@@ -147,8 +151,12 @@ mod tests {
         let msg_scalar = Scalar::random(&mut OsRng);
         let msg_point = (ProjectivePoint::GENERATOR * msg_scalar).to_affine();
 
-        let ciphertext =
-            ecc256_encrypt(&serialize_point(&pk1), &serialize_point(&msg_point)).unwrap();
+        let ciphertext = ecc256_encrypt(
+            &serialize_point(&pk1),
+            &serialize_point(&msg_point),
+            &Scalar::random(&mut OsRng),
+        )
+        .unwrap();
 
         let mut wrong_key = [0u8; 32];
         wrong_key.copy_from_slice(&sk2.to_bytes());
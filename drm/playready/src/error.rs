@@ -71,6 +71,8 @@ pub enum CdmError {
     UnsupportedCipherType(String),
     #[error("license integrity check failed")]
     IntegrityCheckFailed,
+    #[error("content key for KID {0} does not match the WRM header checksum")]
+    KeyChecksumMismatch(String),
 }
 
 impl From<FormatError> for CdmError {
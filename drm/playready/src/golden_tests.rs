@@ -0,0 +1,249 @@
+use std::path::Path;
+
+use data_encoding::BASE64;
+use p256::{
+    AffinePoint, FieldBytes, Scalar,
+    elliptic_curve::{PrimeField, point::DecompressPoint, sec1::ToEncodedPoint, subtle::Choice},
+};
+use sha2::{Digest, Sha256};
+
+use drm_core::PsshBox;
+use drm_playready_format::{
+    key::{CipherType, KeyType},
+    wrm_header::uuid_to_kid,
+    xmr::{ContentKeyObject, EccKeyObject, XmrLicense, XmrObject, XmrObjectData, object_type},
+};
+
+use crate::crypto::{aes, elgamal};
+use crate::device::Device;
+use crate::error::CdmError;
+use crate::session::{ChallengeInputs, Session};
+
+const TEST_PRD: &[u8] = include_bytes!("../testfiles/device.prd");
+
+/// A license exchange from `testfiles/transcripts`.
+struct Transcript {
+    pssh: &'static [u8],
+    inputs: &'static str,
+    challenge: &'static str,
+    response: &'static str,
+    keys: &'static str,
+}
+
+macro_rules! transcript {
+    ($name:literal) => {
+        Transcript {
+            pssh: include_bytes!(concat!("../testfiles/transcripts/", $name, "/pssh.bin")),
+            inputs: include_str!(concat!("../testfiles/transcripts/", $name, "/inputs.txt")),
+            challenge: include_str!(concat!(
+                "../testfiles/transcripts/",
+                $name,
+                "/challenge.xml"
+            )),
+            response: include_str!(concat!("../testfiles/transcripts/", $name, "/response.xml")),
+            keys: include_str!(concat!("../testfiles/transcripts/", $name, "/keys.txt")),
+        }
+    };
+}
+
+impl Transcript {
+    fn inputs(&self) -> ChallengeInputs {
+        parse_inputs(self.inputs)
+    }
+
+    /// Build the challenge of the exchange, returning the session it was built with.
+    fn challenge(&self) -> (Session, Vec<u8>) {
+        build_challenge(self.pssh, self.inputs())
+    }
+
+    /// Replay the exchange, asserting the challenge and keys match the recording.
+    fn replay(&self) {
+        let (mut session, challenge) = self.challenge();
+        assert_eq!(String::from_utf8(challenge).unwrap(), self.challenge);
+
+        let keys = session
+            .parse_license_response(self.response.as_bytes())
+            .unwrap();
+        let keys: Vec<String> = keys.iter().map(ToString::to_string).collect();
+        assert_eq!(keys, self.keys.lines().collect::<Vec<_>>());
+    }
+}
+
+fn parse_inputs(inputs: &str) -> ChallengeInputs {
+    let value = |name: &str| {
+        inputs
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .find(|(key, _)| key.trim() == name)
+            .map(|(_, value)| value.trim())
+            .unwrap_or_else(|| panic!("transcript inputs are missing {name}"))
+    };
+    let scalar = |name: &str| {
+        let bytes = hex::decode(value(name)).unwrap();
+        Option::<Scalar>::from(Scalar::from_repr(*FieldBytes::from_slice(&bytes))).unwrap()
+    };
+
+    ChallengeInputs {
+        session_key: scalar("session_key"),
+        elgamal_k: scalar("elgamal_k"),
+        nonce: hex::decode(value("nonce")).unwrap().try_into().unwrap(),
+        timestamp: value("timestamp").parse().unwrap(),
+    }
+}
+
+fn build_challenge(pssh: &[u8], inputs: ChallengeInputs) -> (Session, Vec<u8>) {
+    let mut session = Session::new(Device::from_bytes(TEST_PRD).unwrap());
+    let pssh = PsshBox::from_bytes(pssh).unwrap();
+    let challenge = session.build_license_challenge_with(&pssh, inputs).unwrap();
+    (session, challenge)
+}
+
+/// Hash a label and some data, for deterministic license values.
+fn derive(label: &str, data: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(label.as_bytes());
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+/**
+    Build a license for a content key, as a license server would for the
+    test device: the content key and an integrity key are ElGamal encrypted
+    to the device's encryption key, and the license is signed with an
+    AES-CMAC under the integrity key.
+
+    The content and integrity keys travel as the x coordinate of a curve
+    point, so the integrity key is picked such that the pair is on the curve.
+    Everything else is derived from the key ID, so the output is stable.
+*/
+fn synthesize_license(device: &Device, kid: [u8; 16], key: [u8; 16]) -> Vec<u8> {
+    let (integrity_key, message_point) = (0u32..)
+        .find_map(|counter| {
+            let seed = [&kid[..], &key, &counter.to_be_bytes()].concat();
+            let integrity_key: [u8; 16] = derive("integrity", &seed)[..16].try_into().unwrap();
+            let x = [integrity_key, key].concat();
+            let point = AffinePoint::decompress(FieldBytes::from_slice(&x), Choice::from(0));
+            Option::<AffinePoint>::from(point).map(|point| {
+                let encoded = point.to_encoded_point(false);
+                let message_point: [u8; 64] = encoded.as_bytes()[1..65].try_into().unwrap();
+                (integrity_key, message_point)
+            })
+        })
+        .unwrap();
+
+    let k = Option::<Scalar>::from(Scalar::from_repr(derive("elgamal_k", &kid).into())).unwrap();
+    let encrypted_key =
+        elgamal::ecc256_encrypt(device.encryption_public_key(), &message_point, &k).unwrap();
+
+    let key_material = XmrObject::container(
+        object_type::KEY_MATERIAL_CONTAINER,
+        vec![
+            XmrObject::new(
+                object_type::CONTENT_KEY,
+                XmrObjectData::ContentKey(ContentKeyObject {
+                    key_id: uuid_to_kid(&kid),
+                    key_type: KeyType::Aes128Ctr,
+                    cipher_type: CipherType::Ecc256,
+                    encrypted_key: encrypted_key.to_vec(),
+                }),
+            ),
+            XmrObject::new(
+                object_type::ECC_DEVICE_KEY,
+                XmrObjectData::EccKey(EccKeyObject {
+                    curve_type: 1,
+                    key: device.encryption_public_key().to_vec(),
+                }),
+            ),
+        ],
+    );
+    let outer = XmrObject::container(object_type::OUTER_CONTAINER, vec![key_material]);
+
+    let rights_id: [u8; 16] = derive("rights_id", &kid)[..16].try_into().unwrap();
+    let license = XmrLicense::new_signed(3, rights_id, vec![outer], 1, 16, |message| {
+        aes::aes_cmac(&integrity_key, message).to_vec()
+    })
+    .unwrap();
+    license.raw_bytes().to_vec()
+}
+
+/// Wrap licenses in a license server response, with placeholder signatures.
+fn synthesize_response(licenses: &[Vec<u8>]) -> String {
+    let licenses: String = licenses
+        .iter()
+        .map(|license| format!("<License>{}</License>", BASE64.encode(license)))
+        .collect();
+    format!(
+        concat!(
+            r##"<?xml version="1.0" encoding="utf-8"?><soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xmlns:xsd="http://www.w3.org/2001/XMLSchema"><soap:Body>"##,
+            r##"<AcquireLicenseResponse xmlns="http://schemas.microsoft.com/DRM/2007/03/protocols"><AcquireLicenseResult><Response>"##,
+            r##"<LicenseResponse xmlns="http://schemas.microsoft.com/DRM/2007/03/protocols/messages"><Version>1</Version><Licenses>{}</Licenses>"##,
+            r##"<Acknowledgement><TransactionID>0000000000000001</TransactionID></Acknowledgement><LicenseNonce>AAAAAAAAAAAAAAAAAAAAAA==</LicenseNonce>"##,
+            r##"<ResponseID>AAAAAAAAAAAAAAAAAAAAAA==</ResponseID><SigningCertificateChain>AAAA</SigningCertificateChain></LicenseResponse>"##,
+            r##"<Signature xmlns="http://www.w3.org/2000/09/xmldsig#"><SignedInfo><CanonicalizationMethod Algorithm="http://www.w3.org/TR/2001/REC-xml-c14n-20010315"></CanonicalizationMethod>"##,
+            r##"<SignatureMethod Algorithm="http://schemas.microsoft.com/DRM/2007/03/protocols#ecdsa-sha256"></SignatureMethod><Reference URI="#SignedData">"##,
+            r##"<DigestMethod Algorithm="http://schemas.microsoft.com/DRM/2007/03/protocols#sha256"></DigestMethod><DigestValue>AAAA</DigestValue></Reference></SignedInfo>"##,
+            r##"<SignatureValue>AAAA</SignatureValue></Signature></Response></AcquireLicenseResult></AcquireLicenseResponse></soap:Body></soap:Envelope>"##,
+        ),
+        licenses
+    )
+}
+
+/**
+    Write the challenge and response of every transcript from its PSSH box,
+    inputs and keys. Run this after an intended change to the challenge or
+    license format, with `cargo test -p drm-playready -- --ignored`.
+*/
+#[test]
+#[ignore = "writes testfiles/transcripts"]
+fn generate_transcripts() {
+    let device = Device::from_bytes(TEST_PRD).unwrap();
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("testfiles/transcripts");
+    for name in ["v4_0_single_key", "v4_3_two_keys"] {
+        let dir = root.join(name);
+        let pssh = std::fs::read(dir.join("pssh.bin")).unwrap();
+        let inputs = std::fs::read_to_string(dir.join("inputs.txt")).unwrap();
+        let keys = std::fs::read_to_string(dir.join("keys.txt")).unwrap();
+
+        let (_, challenge) = build_challenge(&pssh, parse_inputs(&inputs));
+        std::fs::write(dir.join("challenge.xml"), challenge).unwrap();
+
+        let licenses: Vec<Vec<u8>> = keys
+            .lines()
+            .map(|line| {
+                let (kid, key) = line.split_once(':').unwrap();
+                let kid = hex::decode(kid).unwrap().try_into().unwrap();
+                let key = hex::decode(key).unwrap().try_into().unwrap();
+                synthesize_license(&device, kid, key)
+            })
+            .collect();
+        std::fs::write(dir.join("response.xml"), synthesize_response(&licenses)).unwrap();
+    }
+}
+
+#[test]
+fn v4_0_single_key() {
+    transcript!("v4_0_single_key").replay();
+}
+
+#[test]
+fn v4_3_two_keys() {
+    transcript!("v4_3_two_keys").replay();
+}
+
+#[test]
+fn tampered_license_fails_integrity_check() {
+    let transcript = transcript!("v4_0_single_key");
+
+    // Flip a bit in the rights ID, which the license signature covers
+    let (start, rest) = transcript.response.split_once("<License>").unwrap();
+    let (blob, end) = rest.split_once("</License>").unwrap();
+    let mut license = BASE64.decode(blob.as_bytes()).unwrap();
+    license[8] ^= 0x01;
+    let response = format!("{start}<License>{}</License>{end}", BASE64.encode(&license));
+
+    let (mut session, _) = transcript.challenge();
+    let err = session
+        .parse_license_response(response.as_bytes())
+        .unwrap_err();
+    assert!(matches!(err, CdmError::CmacMismatch));
+}
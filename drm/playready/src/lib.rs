@@ -9,6 +9,9 @@ mod error;
 mod pssh_ext;
 mod session;
 
+#[cfg(test)]
+mod golden_tests;
+
 pub mod format {
     pub use drm_playready_format::*;
 }
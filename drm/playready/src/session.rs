@@ -12,7 +12,7 @@ use drm_core::{ContentKey, KeyType, PsshBox};
 use drm_playready_format::{
     key::CipherType,
     soap,
-    wrm_header::{WrmHeader, WrmHeaderVersion, kid_to_uuid},
+    wrm_header::{AlgId, WrmHeader, WrmHeaderVersion, kid_to_uuid, uuid_to_kid},
     xmr::XmrLicense,
 };
 
@@ -38,9 +38,8 @@ struct XmlKey {
 }
 
 impl XmlKey {
-    /// Generate a new random session key.
-    fn generate() -> Self {
-        let scalar = Scalar::random(&mut OsRng);
+    /// Derive the session key material from a private scalar.
+    fn from_scalar(scalar: Scalar) -> Self {
         let point = (ProjectivePoint::GENERATOR * scalar).to_affine();
         let encoded = point.to_encoded_point(false);
 
@@ -67,6 +66,45 @@ impl XmlKey {
     }
 }
 
+/**
+    The random and time-dependent inputs of a license challenge.
+
+    Generated fresh for every challenge, and fixed by the golden transcript
+    tests to reproduce recorded challenges byte for byte.
+*/
+pub(crate) struct ChallengeInputs {
+    /// Private scalar of the ephemeral session key.
+    pub(crate) session_key: Scalar,
+    /// ElGamal `k` for encrypting the session key to the WMRM server key.
+    pub(crate) elgamal_k: Scalar,
+    /// License nonce.
+    pub(crate) nonce: [u8; 16],
+    /// Client time, in seconds since the Unix epoch.
+    pub(crate) timestamp: u64,
+}
+
+impl ChallengeInputs {
+    /// Generate random inputs at the current time.
+    fn generate() -> Self {
+        let mut nonce = [0u8; 16];
+        {
+            use p256::elliptic_curve::rand_core::RngCore;
+            OsRng.fill_bytes(&mut nonce);
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        Self {
+            session_key: Scalar::random(&mut OsRng),
+            elgamal_k: Scalar::random(&mut OsRng),
+            nonce,
+            timestamp,
+        }
+    }
+}
+
 /**
     A PlayReady CDM session that builds license challenges and parses license responses.
 */
@@ -77,6 +115,8 @@ pub struct Session {
     device: Device,
     /// Ephemeral session key (generated during challenge building).
     xml_key: Option<XmlKey>,
+    /// WRM header of the last challenge, for checking the returned keys.
+    wrm_header: Option<WrmHeader>,
     /// Extracted content keys after a successful parse_license_response().
     content_keys: Vec<ContentKey>,
 }
@@ -90,6 +130,7 @@ impl Session {
             number: SESSION_COUNTER.fetch_add(1, Ordering::Relaxed),
             device,
             xml_key: None,
+            wrm_header: None,
             content_keys: Vec::new(),
         }
     }
@@ -108,6 +149,14 @@ impl Session {
         to a PlayReady license server.
     */
     pub fn build_license_challenge(&mut self, pssh: &PsshBox) -> CdmResult<Vec<u8>> {
+        self.build_license_challenge_with(pssh, ChallengeInputs::generate())
+    }
+
+    pub(crate) fn build_license_challenge_with(
+        &mut self,
+        pssh: &PsshBox,
+        inputs: ChallengeInputs,
+    ) -> CdmResult<Vec<u8>> {
         // 1. Extract WRM header XML from PSSH
         let wrm_header_xml = pssh.playready_wrm_header_xml()?;
         let wrm_header =
@@ -120,58 +169,53 @@ impl Session {
             _ => 1,
         };
 
-        // 3. Generate session key
-        let xml_key = XmlKey::generate();
+        // 3. Derive session key
+        let xml_key = XmlKey::from_scalar(inputs.session_key);
 
         // 4. ElGamal encrypt session public point to WMRM server key
-        let wrmserver_data = elgamal::ecc256_encrypt(&WMRM_SERVER_KEY, &xml_key.public_key)?;
+        let wrmserver_data =
+            elgamal::ecc256_encrypt(&WMRM_SERVER_KEY, &xml_key.public_key, &inputs.elgamal_k)?;
 
         // 5. Build encrypted client data
-        let client_data_xml = build_client_data_xml(&self.device.group_certificate);
-        let encrypted_client_data =
-            aes::aes_cbc_encrypt(&xml_key.aes_key, &xml_key.aes_iv, &client_data_xml);
-
-        // 6. Generate nonce and timestamp
-        let mut nonce = [0u8; 16];
-        {
-            use p256::elliptic_curve::rand_core::RngCore;
-            OsRng.fill_bytes(&mut nonce);
-        }
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
+        let client_data_xml = soap::client_data_xml(&self.device.group_certificate);
+        let encrypted_client_data = aes::aes_cbc_encrypt(
+            &xml_key.aes_key,
+            &xml_key.aes_iv,
+            client_data_xml.as_bytes(),
+        );
 
-        // 7. Build the <LA> element
-        let la_xml = build_la_element(
+        // 6. Build the <LA> element
+        let la_xml = soap::LicenseAcquisition {
             protocol_version,
-            &wrm_header_xml,
-            &nonce,
-            timestamp,
-            &wrmserver_data,
-            &encrypted_client_data,
-        );
+            content_header: &wrm_header_xml,
+            nonce: &inputs.nonce,
+            client_time: inputs.timestamp,
+            encrypted_key: &wrmserver_data,
+            encrypted_client_data: &encrypted_client_data,
+        }
+        .to_xml();
 
-        // 8. SHA-256 hash the LA element
+        // 7. SHA-256 hash the LA element
         let la_digest = Sha256::digest(la_xml.as_bytes());
 
-        // 9. Build <SignedInfo> and sign it
-        let signed_info_xml = build_signed_info_element(&la_digest);
+        // 8. Build <SignedInfo> and sign it
+        let signed_info_xml = soap::signed_info_xml(&la_digest);
         let signature = signing::ecdsa_sha256_sign(
             &self.device.signing_key.private_key,
             signed_info_xml.as_bytes(),
         )?;
 
-        // 10. Assemble full SOAP envelope
-        let soap_envelope = build_soap_envelope(
+        // 9. Assemble full SOAP envelope
+        let soap_envelope = soap::challenge_envelope(
             &la_xml,
             &signed_info_xml,
             &signature,
             self.device.signing_public_key(),
         );
 
-        // Store session key
+        // Store session key, and the header for checking the returned keys
         self.xml_key = Some(xml_key);
+        self.wrm_header = Some(wrm_header);
 
         Ok(soap_envelope.into_bytes())
     }
@@ -181,6 +225,10 @@ impl Session {
 
         Takes the raw SOAP XML bytes received from the license server.
         Returns the extracted content keys on success.
+
+        Keys are checked against the checksums in the WRM header the
+        challenge was built from, so a license for the wrong content fails
+        here with `KeyChecksumMismatch` instead of as undecryptable media.
     */
     pub fn parse_license_response(&mut self, raw: &[u8]) -> CdmResult<&[ContentKey]> {
         let response_str =
@@ -219,6 +267,11 @@ impl Session {
             return Err(CdmError::NoContentKeys);
         }
 
+        // 5. Confirm keys against the WRM header checksums
+        if let Some(wrm_header) = &self.wrm_header {
+            verify_key_checksums(wrm_header, &keys)?;
+        }
+
         self.content_keys = keys;
         Ok(&self.content_keys)
     }
@@ -255,138 +308,6 @@ impl Session {
     }
 }
 
-/// Build the client data XML containing the certificate chain and features.
-fn build_client_data_xml(group_certificate: &[u8]) -> Vec<u8> {
-    let cert_b64 = BASE64.encode(group_certificate);
-    let xml = format!(
-        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
-<Data>\
-<CertificateChains>\
-<CertificateChain> {cert_b64} </CertificateChain>\
-</CertificateChains>\
-<Features>\
-<Feature Name=\"AESCBC\"></Feature>\
-<REE>\
-<AESCBCS></AESCBCS>\
-</REE>\
-</Features>\
-</Data>"
-    );
-    xml.into_bytes()
-}
-
-/// Build the `<LA>` element of the challenge.
-fn build_la_element(
-    protocol_version: u32,
-    wrm_header_xml: &str,
-    nonce: &[u8; 16],
-    timestamp: u64,
-    wrmserver_data: &[u8; 128],
-    encrypted_client_data: &[u8],
-) -> String {
-    let nonce_b64 = BASE64.encode(nonce);
-    let wrmserver_b64 = BASE64.encode(wrmserver_data);
-    let client_data_b64 = BASE64.encode(encrypted_client_data);
-
-    format!(
-        "<LA xmlns=\"{protocol_ns}\" Id=\"SignedData\" xml:space=\"preserve\">\
-<Version>{protocol_version}</Version>\
-<ContentHeader>{wrm_header_xml}</ContentHeader>\
-<CLIENTINFO>\
-<CLIENTVERSION>{client_version}</CLIENTVERSION>\
-</CLIENTINFO>\
-<LicenseNonce>{nonce_b64}</LicenseNonce>\
-<ClientTime>{timestamp}</ClientTime>\
-<EncryptedData xmlns=\"{xmlenc_ns}\" Type=\"{xmlenc_ns}Element\">\
-<EncryptionMethod Algorithm=\"{aes_algorithm}\"></EncryptionMethod>\
-<KeyInfo xmlns=\"{xmldsig_ns}\">\
-<EncryptedKey xmlns=\"{xmlenc_ns}\">\
-<EncryptionMethod Algorithm=\"{ecc_algorithm}\"></EncryptionMethod>\
-<KeyInfo xmlns=\"{xmldsig_ns}\">\
-<KeyName>WMRMServer</KeyName>\
-</KeyInfo>\
-<CipherData>\
-<CipherValue>{wrmserver_b64}</CipherValue>\
-</CipherData>\
-</EncryptedKey>\
-</KeyInfo>\
-<CipherData>\
-<CipherValue>{client_data_b64}</CipherValue>\
-</CipherData>\
-</EncryptedData>\
-</LA>",
-        protocol_ns = soap::PROTOCOL_NS,
-        client_version = soap::CLIENT_VERSION,
-        xmlenc_ns = soap::XMLENC_NS,
-        xmldsig_ns = soap::XMLDSIG_NS,
-        aes_algorithm = soap::AES128_CBC_ALGORITHM,
-        ecc_algorithm = soap::ECC256_ALGORITHM,
-    )
-}
-
-/// Build the `<SignedInfo>` element referencing the LA digest.
-fn build_signed_info_element(la_digest: &[u8]) -> String {
-    let digest_b64 = BASE64.encode(la_digest);
-    format!(
-        "<SignedInfo xmlns=\"{xmldsig_ns}\">\
-<CanonicalizationMethod Algorithm=\"{c14n_algorithm}\"></CanonicalizationMethod>\
-<SignatureMethod Algorithm=\"{ecdsa_algorithm}\"></SignatureMethod>\
-<Reference URI=\"#SignedData\">\
-<DigestMethod Algorithm=\"{sha256_algorithm}\"></DigestMethod>\
-<DigestValue>{digest_b64}</DigestValue>\
-</Reference>\
-</SignedInfo>",
-        xmldsig_ns = soap::XMLDSIG_NS,
-        c14n_algorithm = soap::C14N_ALGORITHM,
-        ecdsa_algorithm = soap::ECDSA_SHA256_ALGORITHM,
-        sha256_algorithm = soap::SHA256_ALGORITHM,
-    )
-}
-
-/// Assemble the complete SOAP envelope.
-fn build_soap_envelope(
-    la_xml: &str,
-    signed_info_xml: &str,
-    signature: &[u8; 64],
-    signing_public_key: &[u8; 64],
-) -> String {
-    let signature_b64 = BASE64.encode(signature);
-    let pubkey_b64 = BASE64.encode(signing_public_key);
-
-    format!(
-        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
-<soap:Envelope \
-xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" \
-xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\" \
-xmlns:soap=\"{soap_ns}\">\
-<soap:Body>\
-<AcquireLicense xmlns=\"{protocol_ns}\">\
-<challenge>\
-<Challenge xmlns=\"{message_ns}\">\
-{la_xml}\
-<Signature xmlns=\"{xmldsig_ns}\">\
-{signed_info_xml}\
-<SignatureValue>{signature_b64}</SignatureValue>\
-<KeyInfo xmlns=\"{xmldsig_ns}\">\
-<KeyValue>\
-<ECCKeyValue>\
-<PublicKey>{pubkey_b64}</PublicKey>\
-</ECCKeyValue>\
-</KeyValue>\
-</KeyInfo>\
-</Signature>\
-</Challenge>\
-</challenge>\
-</AcquireLicense>\
-</soap:Body>\
-</soap:Envelope>",
-        soap_ns = soap::SOAP_NS,
-        protocol_ns = soap::PROTOCOL_NS,
-        message_ns = soap::MESSAGE_NS,
-        xmldsig_ns = soap::XMLDSIG_NS,
-    )
-}
-
 /// Extract base64-encoded license blobs from a SOAP license response.
 fn extract_license_blobs(xml: &str) -> CdmResult<Vec<String>> {
     use quick_xml::Reader;
@@ -495,6 +416,43 @@ fn local_name(name: &[u8]) -> &[u8] {
     }
 }
 
+/**
+    Compute the WRM header checksum of an AES content key: the full 16-byte
+    KID (in PlayReady GUID byte order) is encrypted with the key as a single
+    AES-ECB block, and the first 8 bytes of the ciphertext are kept.
+*/
+fn key_checksum(key: &[u8; 16], kid: &[u8; 16]) -> [u8; 8] {
+    let encrypted = aes::aes_ecb_encrypt_block(key, &uuid_to_kid(kid));
+    encrypted[..8].try_into().unwrap()
+}
+
+/**
+    Verify content keys against the checksums of their KIDs in a WRM header.
+
+    KIDs without a checksum, or with a COCKTAIL (RC4-era) checksum,
+    and keys that the header does not list, are not checked.
+*/
+fn verify_key_checksums(wrm_header: &WrmHeader, keys: &[ContentKey]) -> CdmResult<()> {
+    for signed_kid in &wrm_header.kids {
+        let Some(expected) = &signed_kid.checksum else {
+            continue;
+        };
+        if signed_kid.alg_id == Some(AlgId::Cocktail) {
+            continue;
+        }
+        let Some(key) = keys.iter().find(|k| k.kid == signed_kid.key_id) else {
+            continue;
+        };
+
+        let matches = <[u8; 16]>::try_from(key.key.as_slice())
+            .is_ok_and(|k| key_checksum(&k, &key.kid).as_slice() == expected.as_slice());
+        if !matches {
+            return Err(CdmError::KeyChecksumMismatch(key.kid_hex()));
+        }
+    }
+    Ok(())
+}
+
 /// Extract a content key from an XMR ContentKeyObject.
 fn extract_content_key(
     ck_obj: &drm_playready_format::xmr::ContentKeyObject,
@@ -647,7 +605,7 @@ mod tests {
 
     #[test]
     fn xml_key_generation() {
-        let key = XmlKey::generate();
+        let key = XmlKey::from_scalar(Scalar::random(&mut OsRng));
         // Private key should not be all zeros
         assert_ne!(key.private_key, [0u8; 32]);
         // Public key should not be all zeros
@@ -679,78 +637,6 @@ mod tests {
         );
     }
 
-    #[test]
-    fn build_client_data_produces_valid_xml() {
-        let cert = b"test certificate data";
-        let xml = build_client_data_xml(cert);
-        let xml_str = std::str::from_utf8(&xml).unwrap();
-        assert!(xml_str.contains("<CertificateChain>"));
-        assert!(xml_str.contains("</CertificateChain>"));
-        assert!(xml_str.contains("<Feature Name=\"AESCBC\">"));
-        assert!(xml_str.contains("<AESCBCS>"));
-        // Certificate should be base64-encoded with surrounding spaces
-        let cert_b64 = BASE64.encode(cert);
-        assert!(xml_str.contains(&format!(" {cert_b64} ")));
-    }
-
-    #[test]
-    fn build_la_element_includes_all_fields() {
-        let nonce = [0xAA; 16];
-        let wrmserver = [0xBB; 128];
-        let client_data = vec![0xCC; 32];
-        let la = build_la_element(
-            5,
-            "<WRMHEADER/>",
-            &nonce,
-            1700000000,
-            &wrmserver,
-            &client_data,
-        );
-
-        assert!(la.contains("<Version>5</Version>"));
-        assert!(la.contains("<ContentHeader><WRMHEADER/></ContentHeader>"));
-        assert!(la.contains(&format!(
-            "<CLIENTVERSION>{}</CLIENTVERSION>",
-            soap::CLIENT_VERSION
-        )));
-        assert!(la.contains("<LicenseNonce>"));
-        assert!(la.contains("<ClientTime>1700000000</ClientTime>"));
-        assert!(la.contains("WMRMServer"));
-        assert!(la.contains(soap::ECC256_ALGORITHM));
-        assert!(la.contains(soap::AES128_CBC_ALGORITHM));
-    }
-
-    #[test]
-    fn build_signed_info_includes_digest() {
-        let digest = [0xDD; 32];
-        let si = build_signed_info_element(&digest);
-        assert!(si.contains(soap::C14N_ALGORITHM));
-        assert!(si.contains(soap::ECDSA_SHA256_ALGORITHM));
-        assert!(si.contains(soap::SHA256_ALGORITHM));
-        assert!(si.contains("<DigestValue>"));
-        assert!(si.contains("#SignedData"));
-    }
-
-    #[test]
-    fn build_soap_envelope_structure() {
-        let la = "<LA>test</LA>";
-        let si = "<SignedInfo>test</SignedInfo>";
-        let sig = [0xEE; 64];
-        let pk = [0xFF; 64];
-        let envelope = build_soap_envelope(la, si, &sig, &pk);
-
-        assert!(envelope.starts_with("<?xml version=\"1.0\""));
-        assert!(envelope.contains("soap:Envelope"));
-        assert!(envelope.contains("soap:Body"));
-        assert!(envelope.contains("<AcquireLicense"));
-        assert!(envelope.contains("<Challenge"));
-        assert!(envelope.contains("<LA>test</LA>"));
-        assert!(envelope.contains("<SignatureValue>"));
-        assert!(envelope.contains("<ECCKeyValue>"));
-        assert!(envelope.contains("<PublicKey>"));
-        assert!(envelope.contains("</soap:Envelope>"));
-    }
-
     #[test]
     fn check_soap_fault_no_fault() {
         let xml = r#"<soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/">
@@ -793,4 +679,55 @@ mod tests {
         assert_eq!(blobs[0], "AQID");
         assert_eq!(blobs[1], "BAUG");
     }
+
+    fn checksum_header(kid: [u8; 16], checksum: &[u8]) -> WrmHeader {
+        WrmHeader {
+            version: WrmHeaderVersion::V4_0_0_0,
+            kids: vec![drm_playready_format::wrm_header::SignedKeyId {
+                key_id: kid,
+                alg_id: Some(AlgId::AesCtr),
+                checksum: Some(checksum.to_vec()),
+            }],
+            la_url: None,
+            lui_url: None,
+            ds_id: None,
+        }
+    }
+
+    #[test]
+    fn key_checksum_known_vector() {
+        // KID 00112233-4455-6677-8899-aabbccddeeff, key 000102..0f
+        let kid: [u8; 16] = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD,
+            0xEE, 0xFF,
+        ];
+        let key: [u8; 16] = core::array::from_fn(|i| i as u8);
+        let expected = BASE64.decode(b"KpOvXRLUXfs=").unwrap();
+        assert_eq!(key_checksum(&key, &kid).as_slice(), expected.as_slice());
+    }
+
+    #[test]
+    fn verify_key_checksums_detects_mismatch() {
+        let kid = [0x42; 16];
+        let key = ContentKey {
+            kid,
+            key: vec![0x07; 16],
+            key_type: KeyType::Content,
+        };
+        let checksum = key_checksum(&[0x07; 16], &kid);
+
+        let header = checksum_header(kid, &checksum);
+        assert!(verify_key_checksums(&header, std::slice::from_ref(&key)).is_ok());
+
+        let wrong = ContentKey {
+            key: vec![0x08; 16],
+            ..key
+        };
+        let err = verify_key_checksums(&header, &[wrong]).unwrap_err();
+        assert!(matches!(err, CdmError::KeyChecksumMismatch(_)));
+
+        // Keys the header doesn't list are not checked
+        let other = checksum_header([0x43; 16], &checksum);
+        assert!(verify_key_checksums(&other, &[key]).is_ok());
+    }
 }
@@ -0,0 +1,151 @@
+use core::fmt;
+
+use drm_widevine_proto::{
+    ClientIdentification, DrmCertificate, SignedDrmCertificate, client_identification::TokenType,
+    prost::Message,
+};
+
+use crate::types::SecurityLevel;
+
+/**
+    Highest HDCP version a client supports on its digital outputs.
+    Ref: license_protocol.proto, ClientCapabilities.HdcpVersion enum.
+
+    Ordered from least to most capable, so that a requirement can be checked
+    with `>=`. Having no digital output at all satisfies any HDCP requirement.
+*/
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HdcpVersion {
+    #[default]
+    None,
+    V1,
+    V2,
+    V2_1,
+    V2_2,
+    V2_3,
+    NoDigitalOutput,
+}
+
+impl HdcpVersion {
+    pub const fn from_proto(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Self::None),
+            1 => Some(Self::V1),
+            2 => Some(Self::V2),
+            3 => Some(Self::V2_1),
+            4 => Some(Self::V2_2),
+            5 => Some(Self::V2_3),
+            0xff => Some(Self::NoDigitalOutput),
+            _ => None,
+        }
+    }
+
+    pub const fn to_name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::V1 => "HDCP 1.x",
+            Self::V2 => "HDCP 2.0",
+            Self::V2_1 => "HDCP 2.1",
+            Self::V2_2 => "HDCP 2.2",
+            Self::V2_3 => "HDCP 2.3",
+            Self::NoDigitalOutput => "no digital output",
+        }
+    }
+}
+
+impl fmt::Display for HdcpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.to_name())
+    }
+}
+
+/**
+    What a device reports about itself to license servers.
+
+    Collected from the device's ClientIdentification: the system id comes
+    from the DRM certificate in its token, the rest from the client info
+    and capabilities. Fields the device does not report are `None`.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceCapabilities {
+    /// Widevine system id from the device certificate.
+    pub system_id: Option<u32>,
+    /// Security level of the device file.
+    pub security_level: SecurityLevel,
+    pub company_name: Option<String>,
+    pub model_name: Option<String>,
+    pub oem_crypto_api_version: Option<u32>,
+    pub max_hdcp_version: HdcpVersion,
+    /// Whether the client can enforce per-resolution key constraints.
+    pub video_resolution_constraints: bool,
+    /// OEMCrypto performance tier: 1 (low), 2 (medium) or 3 (high),
+    /// which bounds the resolutions the device should be served.
+    pub resource_rating_tier: Option<u32>,
+    pub anti_rollback_usage_table: bool,
+    pub client_token: bool,
+    pub session_token: bool,
+    pub srm_version: Option<u32>,
+    pub can_update_srm: bool,
+    pub can_disable_analog_output: bool,
+}
+
+impl DeviceCapabilities {
+    pub(crate) fn from_client_id(
+        client_id: &ClientIdentification,
+        security_level: SecurityLevel,
+    ) -> Self {
+        let info = |name: &str| {
+            client_id
+                .client_info
+                .iter()
+                .find(|nv| nv.name.as_deref() == Some(name))
+                .and_then(|nv| nv.value.clone())
+        };
+        let caps = client_id.client_capabilities.clone().unwrap_or_default();
+
+        Self {
+            system_id: system_id(client_id),
+            security_level,
+            company_name: info("company_name"),
+            model_name: info("model_name"),
+            oem_crypto_api_version: caps.oem_crypto_api_version,
+            max_hdcp_version: caps
+                .max_hdcp_version
+                .and_then(HdcpVersion::from_proto)
+                .unwrap_or_default(),
+            video_resolution_constraints: caps.video_resolution_constraints.unwrap_or(false),
+            resource_rating_tier: caps
+                .resource_rating_tier
+                .filter(|tier| (1..=3).contains(tier)),
+            anti_rollback_usage_table: caps.anti_rollback_usage_table.unwrap_or(false),
+            client_token: caps.client_token.unwrap_or(false),
+            session_token: caps.session_token.unwrap_or(false),
+            srm_version: caps.srm_version,
+            can_update_srm: caps.can_update_srm.unwrap_or(false),
+            can_disable_analog_output: caps.can_disable_analog_output.unwrap_or(false),
+        }
+    }
+
+    /**
+        Check whether the device meets a minimum security level and HDCP
+        version, such as those required by a channel's license policy.
+    */
+    pub fn satisfies(&self, security_level: SecurityLevel, hdcp: HdcpVersion) -> bool {
+        // L1 is the most secure level, so a lower number is better
+        self.security_level <= security_level && self.max_hdcp_version >= hdcp
+    }
+}
+
+/**
+    Decode the system id from the DRM certificate in a client token.
+
+    Keybox-provisioned clients carry no certificate, so have no system id here.
+*/
+fn system_id(client_id: &ClientIdentification) -> Option<u32> {
+    if client_id.r#type != Some(TokenType::DrmDeviceCertificate as i32) {
+        return None;
+    }
+    let signed = SignedDrmCertificate::decode(client_id.token.as_deref()?).ok()?;
+    let cert = DrmCertificate::decode(signed.drm_certificate.as_deref()?).ok()?;
+    cert.system_id
+}
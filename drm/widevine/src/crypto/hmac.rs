@@ -30,6 +30,29 @@ pub fn verify_license_signature(
         .map_err(|_| CdmError::HmacMismatch)
 }
 
+/**
+    HMAC-SHA256 signing of a license response.
+
+    The counterpart of verify_license_signature(), computing the signature
+    a license server puts in SignedMessage.signature (field 3).
+
+    Used only by the test license server.
+*/
+#[cfg(feature = "test-server")]
+pub fn sign_license(
+    mac_key_server: &[u8; 32],
+    oemcrypto_core_message: Option<&[u8]>,
+    msg: &[u8],
+) -> Vec<u8> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(mac_key_server)
+        .expect("HMAC key length is always valid for 32-byte key");
+    if let Some(ocm) = oemcrypto_core_message {
+        mac.update(ocm);
+    }
+    mac.update(msg);
+    mac.finalize().into_bytes().to_vec()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -95,6 +95,35 @@ pub fn rsa_oaep_sha1_encrypt(public_key_der: &[u8], plaintext: &[u8]) -> Result<
         .map_err(|e| CdmError::RsaOperation(e.to_string()))
 }
 
+/**
+    RSA-PSS-SHA1 verification of a license request signature.
+
+    The counterpart of rsa_pss_sha1_sign(), with the same parameters.
+    Key: DrmCertificate.public_key from the client's device certificate
+         (DER-encoded RSA public key).
+
+    Used only by the test license server, which checks challenges
+    the same way a real license server would.
+*/
+#[cfg(feature = "test-server")]
+pub fn rsa_pss_sha1_verify(
+    public_key_der: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), CdmError> {
+    use signature::Verifier;
+
+    let public_key = RsaPublicKey::from_pkcs1_der(public_key_der)
+        .map_err(|e| CdmError::RsaKeyParse(e.to_string()))?;
+    let verifying_key = pss::VerifyingKey::<Sha1>::new_with_salt_len(public_key, 20);
+
+    let signature =
+        pss::Signature::try_from(signature).map_err(|e| CdmError::RsaOperation(e.to_string()))?;
+    verifying_key
+        .verify(message, &signature)
+        .map_err(|e| CdmError::RsaOperation(e.to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -5,6 +5,7 @@ use rsa::{
 
 use drm_widevine_proto::{ClientIdentification, prost::Message};
 
+use crate::capabilities::DeviceCapabilities;
 use crate::error::{CdmError, CdmResult};
 use crate::types::{DeviceType, SecurityLevel};
 
@@ -58,6 +59,14 @@ impl Device {
         &self.client_id
     }
 
+    /**
+        Returns what the device reports about itself to license servers,
+        for checking it against a license policy's requirements.
+    */
+    pub fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities::from_client_id(&self.client_id, self.security_level)
+    }
+
     /**
         Parse a base64-encoded WVD v2 file.
     */
@@ -193,6 +202,7 @@ impl Device {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::capabilities::HdcpVersion;
 
     const TEST_WVD: &[u8] = include_bytes!("../testfiles/device.wvd");
 
@@ -236,6 +246,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn capabilities_report() {
+        let device = Device::from_bytes(TEST_WVD).unwrap();
+        let caps = device.capabilities();
+        assert_eq!(caps.system_id, Some(4464));
+        assert_eq!(caps.security_level, SecurityLevel::L3);
+        assert_eq!(caps.company_name.as_deref(), Some("Google"));
+        assert_eq!(caps.oem_crypto_api_version, Some(11));
+        assert_eq!(caps.max_hdcp_version, HdcpVersion::None);
+        assert!(caps.session_token);
+        assert!(caps.satisfies(SecurityLevel::L3, HdcpVersion::None));
+        assert!(!caps.satisfies(SecurityLevel::L1, HdcpVersion::None));
+        assert!(!caps.satisfies(SecurityLevel::L3, HdcpVersion::V2_2));
+    }
+
     #[test]
     fn bad_magic() {
         let mut data = TEST_WVD.to_vec();
@@ -60,6 +60,8 @@ pub enum CdmError {
     NoContentKeys,
     #[error("no session context for request_id")]
     ContextNotFound,
+    #[error("license request rejected: {0}")]
+    LicenseRequestRejected(String),
 }
 
 impl From<drm_widevine_proto::prost::DecodeError> for CdmError {
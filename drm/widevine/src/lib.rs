@@ -2,12 +2,14 @@
 
 pub use drm_core as core;
 
+mod capabilities;
 mod constants;
 mod crypto;
 mod device;
 mod error;
 mod pssh_ext;
 mod session;
+mod transcript;
 mod types;
 
 pub mod proto {
@@ -18,8 +20,13 @@ pub mod proto {
 #[cfg(feature = "static-devices")]
 pub mod static_devices;
 
+#[cfg(feature = "test-server")]
+pub mod test_server;
+
+pub use self::capabilities::{DeviceCapabilities, HdcpVersion};
 pub use self::device::Device;
 pub use self::error::{CdmError, CdmResult};
 pub use self::pssh_ext::WidevineExt;
 pub use self::session::Session;
+pub use self::transcript::{Transcript, TranscriptEntry};
 pub use self::types::{DeviceType, LicenseType, SecurityLevel};
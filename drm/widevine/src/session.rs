@@ -19,6 +19,7 @@ use crate::constants::{
 use crate::crypto::{aes, hmac, padding, privacy, rsa};
 use crate::device::Device;
 use crate::error::{CdmError, CdmResult};
+use crate::transcript::{Transcript, TranscriptEntry};
 use crate::types::{DeviceType, LicenseType};
 
 /**
@@ -68,6 +69,10 @@ pub struct Session {
         Extracted content keys after a successful parse_license_response().
     */
     content_keys: Vec<ContentKey>,
+    /**
+        Record of the license exchange, if enabled with enable_transcript().
+    */
+    transcript: Option<Transcript>,
 }
 
 impl Session {
@@ -81,6 +86,7 @@ impl Session {
             service_certificate: None,
             contexts: HashMap::new(),
             content_keys: Vec::new(),
+            transcript: None,
         }
     }
 
@@ -91,6 +97,55 @@ impl Session {
         self.number
     }
 
+    /**
+        Start recording a transcript of the license exchange, for debugging.
+
+        See [`Transcript`] for what is (and is not) recorded.
+    */
+    pub fn enable_transcript(&mut self) {
+        self.transcript.get_or_insert_with(Transcript::default);
+    }
+
+    /**
+        The transcript recorded so far, if enabled.
+    */
+    pub fn transcript(&self) -> Option<&Transcript> {
+        self.transcript.as_ref()
+    }
+
+    /**
+        Take the transcript recorded so far, if enabled, and
+        keep recording into a new one.
+    */
+    pub fn take_transcript(&mut self) -> Option<Transcript> {
+        self.transcript.as_mut().map(std::mem::take)
+    }
+
+    fn record(&mut self, entry: impl FnOnce() -> TranscriptEntry) {
+        if let Some(transcript) = &mut self.transcript {
+            transcript.push(entry());
+        }
+    }
+
+    fn record_decision(&mut self, decision: impl FnOnce() -> String) {
+        self.record(|| TranscriptEntry::Decision(decision()));
+    }
+
+    fn record_service_certificate(&mut self) {
+        let Some(cert) = self
+            .service_certificate
+            .as_ref()
+            .and_then(|signed| signed.drm_certificate.as_deref())
+            .and_then(|bytes| DrmCertificate::decode(bytes).ok())
+        else {
+            return;
+        };
+        self.record(|| TranscriptEntry::ServiceCertificate {
+            provider_id: cert.provider_id,
+            serial_number: cert.serial_number.unwrap_or_default(),
+        });
+    }
+
     /**
         Set (and verify) a service certificate for privacy mode.
 
@@ -102,6 +157,7 @@ impl Session {
         let root_der = build_root_public_key_der()?;
         let signed_cert = privacy::verify_service_certificate(raw, &root_der)?;
         self.service_certificate = Some(signed_cert);
+        self.record_service_certificate();
         Ok(())
     }
 
@@ -118,6 +174,7 @@ impl Session {
             &LICENSE_PRODUCTION_N,
             &LICENSE_PRODUCTION_E,
         )?);
+        self.record_service_certificate();
         Ok(())
     }
 
@@ -134,6 +191,7 @@ impl Session {
             &LICENSE_STAGING_N,
             &LICENSE_STAGING_E,
         )?);
+        self.record_service_certificate();
         Ok(())
     }
 
@@ -220,7 +278,7 @@ impl Session {
         // Store derivation contexts keyed by request_id
         let enc_ctx = aes::build_enc_context(&license_request_bytes);
         let mac_ctx = aes::build_mac_context(&license_request_bytes);
-        self.contexts.insert(request_id, (enc_ctx, mac_ctx));
+        self.contexts.insert(request_id.clone(), (enc_ctx, mac_ctx));
 
         // Sign the serialized LicenseRequest with RSA-PSS-SHA1
         let signature = rsa::rsa_pss_sha1_sign(&self.device.private_key, &license_request_bytes)?;
@@ -233,7 +291,20 @@ impl Session {
             ..Default::default()
         };
 
-        Ok(signed_message.encode_to_vec())
+        let challenge = signed_message.encode_to_vec();
+        if let Some(transcript) = &mut self.transcript {
+            let (enc_context, mac_context) = self.contexts[&request_id].clone();
+            transcript.push(TranscriptEntry::Request {
+                request_id,
+                license_type,
+                privacy_mode: self.service_certificate.is_some(),
+                enc_context,
+                mac_context,
+                challenge: challenge.clone(),
+            });
+        }
+
+        Ok(challenge)
     }
 
     /**
@@ -243,6 +314,22 @@ impl Session {
         extracted content keys on success.
     */
     pub fn parse_license_response(&mut self, raw: &[u8]) -> CdmResult<&[ContentKey]> {
+        self.record(|| TranscriptEntry::Response {
+            response: raw.to_vec(),
+        });
+        match self.decode_license(raw) {
+            Ok(keys) => {
+                self.content_keys = keys;
+                Ok(&self.content_keys)
+            }
+            Err(e) => {
+                self.record(|| TranscriptEntry::Error(e.to_string()));
+                Err(e)
+            }
+        }
+    }
+
+    fn decode_license(&mut self, raw: &[u8]) -> CdmResult<Vec<ContentKey>> {
         // Step 1: Decode the SignedMessage wrapper
         let signed_message = SignedMessage::decode(raw)?;
 
@@ -267,6 +354,17 @@ impl Session {
             CdmError::ProtobufDecode("missing session_key in SignedMessage".into())
         })?;
 
+        self.record_decision(|| {
+            format!(
+                "LICENSE message, oemcrypto core message {}",
+                if signed_message.oemcrypto_core_message.is_some() {
+                    "present"
+                } else {
+                    "absent"
+                }
+            )
+        });
+
         // Step 2: Decode the License from msg
         let license = License::decode(msg)?;
 
@@ -279,6 +377,8 @@ impl Session {
             CdmError::ProtobufDecode("missing request_id in LicenseIdentification".into())
         })?;
 
+        self.record_decision(|| format!("request_id={}", hex::encode(request_id)));
+
         // Step 4: Look up stored derivation contexts
         let (enc_context, mac_context) = self
             .contexts
@@ -292,6 +392,8 @@ impl Session {
             CdmError::RsaOperation(format!("session key is {} bytes, expected 16", v.len()))
         })?;
 
+        self.record_decision(|| "session key decrypted (redacted)".to_string());
+
         // Step 6: Derive encryption and MAC keys
         let derived = aes::derive_keys(&enc_context, &mac_context, &session_key);
 
@@ -302,17 +404,24 @@ impl Session {
             msg,
             signature,
         )?;
+        self.record_decision(|| "license signature verified".to_string());
 
         // Step 8: Extract and decrypt content keys from each KeyContainer
         let mut keys = Vec::new();
-        for container in &license.key {
+        for (index, container) in license.key.iter().enumerate() {
             let iv = match container.iv.as_deref() {
                 Some(iv) => iv,
-                None => continue,
+                None => {
+                    self.record_decision(|| format!("key container {index}: no IV, skipped"));
+                    continue;
+                }
             };
             let encrypted_key = match container.key.as_deref() {
                 Some(k) => k,
-                None => continue,
+                None => {
+                    self.record_decision(|| format!("key container {index}: no key, skipped"));
+                    continue;
+                }
             };
 
             // Decrypt and unpad the content key
@@ -324,13 +433,26 @@ impl Session {
             let key_type =
                 match drm_widevine_proto::license::key_container::KeyType::try_from(proto_type) {
                     Ok(kt) => KeyType::from(kt),
-                    Err(_) => continue,
+                    Err(_) => {
+                        self.record_decision(|| {
+                            format!("key container {index}: unknown type {proto_type}, skipped")
+                        });
+                        continue;
+                    }
                 };
 
             // Normalize the key ID to 16 bytes
             let kid_raw = container.id.as_deref().unwrap_or_default();
             let kid = kid_to_uuid(kid_raw);
 
+            self.record_decision(|| {
+                format!(
+                    "key container {index}: kid={} type={key_type} key redacted ({} bytes)",
+                    hex::encode(kid),
+                    key_bytes.len()
+                )
+            });
+
             keys.push(ContentKey {
                 kid,
                 key: key_bytes,
@@ -342,8 +464,7 @@ impl Session {
             return Err(CdmError::NoContentKeys);
         }
 
-        self.content_keys = keys;
-        Ok(&self.content_keys)
+        Ok(keys)
     }
 
     /**
@@ -683,6 +804,36 @@ mod tests {
         }
     }
 
+    #[test]
+    fn transcript_records_exchange() {
+        let mut session = Session::new(test_device());
+        assert!(session.transcript().is_none());
+        session.enable_transcript();
+
+        let challenge = session
+            .build_license_challenge(&test_pssh(), LicenseType::Streaming)
+            .unwrap();
+        assert!(session.parse_license_response(b"not a license").is_err());
+
+        let transcript = session.take_transcript().unwrap();
+        match &transcript.entries[..] {
+            [
+                TranscriptEntry::Request {
+                    challenge: recorded,
+                    privacy_mode: false,
+                    ..
+                },
+                TranscriptEntry::Response { .. },
+                TranscriptEntry::Error(_),
+            ] => assert_eq!(*recorded, challenge),
+            other => panic!("unexpected transcript entries: {other:?}"),
+        }
+        assert!(transcript.to_string().contains("enc_context="));
+
+        // Recording continues into a fresh transcript
+        assert!(session.transcript().unwrap().entries.is_empty());
+    }
+
     #[test]
     fn android_request_id_format() {
         let device = test_device();
@@ -0,0 +1,368 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ::rsa::pkcs1::EncodeRsaPublicKey;
+use drm_core::{ContentKey, KeyType};
+use drm_widevine_proto::{
+    ClientIdentification, DrmCertificate, License, LicenseIdentification, LicenseRequest,
+    SignedDrmCertificate, SignedMessage, WidevinePsshData,
+    license::{KeyContainer, Policy},
+    license_request::content_identification::ContentIdVariant,
+    prost::Message,
+    signed_message::MessageType,
+};
+
+use crate::crypto::{aes, hmac, padding, rsa};
+use crate::device::Device;
+use crate::error::{CdmError, CdmResult};
+
+/**
+    A license server emulator for tests, issuing licenses for a fixed set of keys.
+
+    It handles challenges the way a real license server does: the request
+    signature is verified against the public key in the client's device
+    certificate, a fresh session key is wrapped for that key, and the content
+    keys are encrypted and signed with keys derived from it. This makes full
+    round trips through [`Session`](crate::Session) possible without
+    contacting a real server.
+
+    Privacy mode is not supported, since service certificates must be signed
+    by the Widevine root - challenges with an encrypted client ID are rejected.
+
+    ```ignore
+    let server = LicenseServer::new().with_key(kid, key);
+    let mut session = Session::new(LicenseServer::provision(&device)?);
+
+    let challenge = session.build_license_challenge(&pssh, LicenseType::Streaming)?;
+    let response = server.respond(&challenge)?;
+    let keys = session.parse_license_response(&response)?;
+    ```
+*/
+#[derive(Debug, Clone, Default)]
+pub struct LicenseServer {
+    keys: Vec<ContentKey>,
+}
+
+impl LicenseServer {
+    /**
+        Create a license server without any keys.
+    */
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+        Add a content key that licenses are issued for.
+    */
+    pub fn with_key(mut self, kid: [u8; 16], key: impl Into<Vec<u8>>) -> Self {
+        self.keys.push(ContentKey {
+            kid,
+            key: key.into(),
+            key_type: KeyType::Content,
+        });
+        self
+    }
+
+    /**
+        The keys licenses are issued for.
+    */
+    pub fn keys(&self) -> &[ContentKey] {
+        &self.keys
+    }
+
+    /**
+        Reissue a device's certificate for its own private key.
+
+        Challenges are verified against the public key in the device
+        certificate, so devices whose certificate doesn't match their key,
+        like test devices with random keys, need this before the server
+        accepts them. The certificate chain is not checked, so the new
+        certificate is left unsigned.
+    */
+    pub fn provision(device: &Device) -> CdmResult<Device> {
+        let public_key = device
+            .private_key()
+            .to_public_key()
+            .to_pkcs1_der()
+            .map_err(|e| CdmError::RsaKeyParse(e.to_string()))?;
+
+        let mut cert = device_certificate(device.client_id()).unwrap_or_default();
+        cert.public_key = Some(public_key.as_bytes().to_vec());
+        let signed_cert = SignedDrmCertificate {
+            drm_certificate: Some(cert.encode_to_vec()),
+            ..Default::default()
+        };
+
+        let mut client_id = device.client_id().clone();
+        client_id.token = Some(signed_cert.encode_to_vec());
+        Ok(Device::new(
+            device.device_type,
+            device.security_level,
+            device.private_key().clone(),
+            client_id,
+        ))
+    }
+
+    /**
+        Respond to a license challenge with a signed license.
+
+        The license contains every configured key requested by the key IDs in
+        the challenge's PSSH data, or every configured key if it has none.
+    */
+    pub fn respond(&self, challenge: &[u8]) -> CdmResult<Vec<u8>> {
+        let signed_request = SignedMessage::decode(challenge)?;
+        if signed_request.r#type != Some(MessageType::LicenseRequest as i32) {
+            return Err(CdmError::LicenseRequestRejected(
+                "not a LICENSE_REQUEST message".into(),
+            ));
+        }
+        let request_bytes = signed_request
+            .msg
+            .as_deref()
+            .ok_or_else(|| CdmError::LicenseRequestRejected("missing msg".into()))?;
+        let request_signature = signed_request
+            .signature
+            .as_deref()
+            .ok_or_else(|| CdmError::LicenseRequestRejected("missing signature".into()))?;
+
+        // Step 1: Find the client's public key and verify the request signature
+        let request = LicenseRequest::decode(request_bytes)?;
+        if request.encrypted_client_id.is_some() {
+            return Err(CdmError::LicenseRequestRejected(
+                "privacy mode is not supported".into(),
+            ));
+        }
+        let client_id = request
+            .client_id
+            .as_ref()
+            .ok_or_else(|| CdmError::LicenseRequestRejected("missing client_id".into()))?;
+        let public_key = client_public_key(client_id)?;
+        rsa::rsa_pss_sha1_verify(&public_key, request_bytes, request_signature)
+            .map_err(|_| CdmError::LicenseRequestRejected("invalid signature".into()))?;
+
+        // Step 2: Pick the requested keys
+        let Some(ContentIdVariant::WidevinePsshData(content_id)) = request
+            .content_id
+            .as_ref()
+            .and_then(|content_id| content_id.content_id_variant.as_ref())
+        else {
+            return Err(CdmError::LicenseRequestRejected(
+                "missing Widevine PSSH content ID".into(),
+            ));
+        };
+        let requested_kids = content_id
+            .pssh_data
+            .iter()
+            .filter_map(|data| WidevinePsshData::decode(data.as_slice()).ok())
+            .flat_map(|data| data.key_ids)
+            .collect::<Vec<_>>();
+        let keys = self
+            .keys
+            .iter()
+            .filter(|key| {
+                requested_kids.is_empty() || requested_kids.iter().any(|kid| kid == &key.kid)
+            })
+            .collect::<Vec<_>>();
+        if keys.is_empty() {
+            return Err(CdmError::LicenseRequestRejected(
+                "no keys for the requested key IDs".into(),
+            ));
+        }
+
+        // Step 3: Wrap a fresh session key and derive keys from it
+        let mut session_key = [0u8; 16];
+        rand::RngCore::fill_bytes(&mut rand::rng(), &mut session_key);
+        let session_key_enc = rsa::rsa_oaep_sha1_encrypt(&public_key, &session_key)?;
+        let derived = aes::derive_keys(
+            &aes::build_enc_context(request_bytes),
+            &aes::build_mac_context(request_bytes),
+            &session_key,
+        );
+
+        // Step 4: Encrypt each content key into a KeyContainer
+        let containers = keys
+            .into_iter()
+            .map(|key| {
+                let mut iv = [0u8; 16];
+                rand::RngCore::fill_bytes(&mut rand::rng(), &mut iv);
+                let padded = padding::pkcs7_pad(&key.key, 16);
+                let proto_type: drm_widevine_proto::license::key_container::KeyType =
+                    key.key_type.into();
+                KeyContainer {
+                    id: Some(key.kid.to_vec()),
+                    iv: Some(iv.to_vec()),
+                    key: Some(aes::aes_cbc_encrypt(&derived.enc_key, &iv, &padded)),
+                    r#type: Some(proto_type as i32),
+                    ..Default::default()
+                }
+            })
+            .collect();
+
+        let license = License {
+            id: Some(LicenseIdentification {
+                request_id: content_id.request_id.clone(),
+                r#type: content_id.license_type,
+                version: Some(0),
+                ..Default::default()
+            }),
+            policy: Some(Policy {
+                can_play: Some(true),
+                ..Default::default()
+            }),
+            key: containers,
+            license_start_time: Some(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs() as i64,
+            ),
+            ..Default::default()
+        };
+        let license_bytes = license.encode_to_vec();
+
+        // Step 5: Sign the license with the derived server MAC key
+        let signature = hmac::sign_license(&derived.mac_key_server, None, &license_bytes);
+
+        let response = SignedMessage {
+            r#type: Some(MessageType::License as i32),
+            msg: Some(license_bytes),
+            signature: Some(signature),
+            session_key: Some(session_key_enc),
+            ..Default::default()
+        };
+        Ok(response.encode_to_vec())
+    }
+}
+
+/**
+    Get the device certificate from a client's identification.
+*/
+fn device_certificate(client_id: &ClientIdentification) -> CdmResult<DrmCertificate> {
+    let token = client_id
+        .token
+        .as_deref()
+        .ok_or_else(|| CdmError::CertificateDecode("missing token in client_id".into()))?;
+    let signed_cert = SignedDrmCertificate::decode(token)
+        .map_err(|e| CdmError::CertificateDecode(e.to_string()))?;
+    let cert_bytes = signed_cert
+        .drm_certificate
+        .as_deref()
+        .ok_or_else(|| CdmError::CertificateDecode("missing drm_certificate field".into()))?;
+    DrmCertificate::decode(cert_bytes).map_err(|e| CdmError::CertificateDecode(e.to_string()))
+}
+
+/**
+    Get the RSA public key (PKCS#1 DER) from the device certificate
+    in a client's identification.
+*/
+fn client_public_key(client_id: &ClientIdentification) -> CdmResult<Vec<u8>> {
+    device_certificate(client_id)?
+        .public_key
+        .ok_or_else(|| CdmError::CertificateDecode("missing public_key in DrmCertificate".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use drm_core::PsshBox;
+    use hex_literal::hex;
+
+    use crate::{LicenseType, Session};
+
+    const TEST_WVD: &[u8] = include_bytes!("../testfiles/device.wvd");
+    const KID_1: [u8; 16] = hex!("00000000000000000000000000000001");
+    const KID_2: [u8; 16] = hex!("00000000000000000000000000000002");
+
+    fn test_pssh(kids: &[[u8; 16]]) -> PsshBox {
+        let pssh_data = WidevinePsshData {
+            key_ids: kids.iter().map(|kid| kid.to_vec()).collect(),
+            ..Default::default()
+        };
+        let data = pssh_data.encode_to_vec();
+
+        let wv_sysid = hex!("edef8ba979d64acea3c827dcd51d21ed");
+        let box_size = (32 + data.len()) as u32;
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&box_size.to_be_bytes());
+        buf.extend_from_slice(b"pssh");
+        buf.extend_from_slice(&[0, 0, 0, 0]);
+        buf.extend_from_slice(&wv_sysid);
+        buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&data);
+
+        PsshBox::from_bytes(&buf).unwrap()
+    }
+
+    fn test_device() -> Device {
+        LicenseServer::provision(&Device::from_bytes(TEST_WVD).unwrap()).unwrap()
+    }
+
+    fn test_server() -> LicenseServer {
+        LicenseServer::new()
+            .with_key(KID_1, hex!("11111111111111111111111111111111"))
+            .with_key(KID_2, hex!("22222222222222222222222222222222"))
+    }
+
+    #[test]
+    fn round_trip_issues_requested_keys() {
+        let server = test_server();
+        let mut session = Session::new(test_device());
+
+        let challenge = session
+            .build_license_challenge(&test_pssh(&[KID_2]), LicenseType::Streaming)
+            .unwrap();
+        let response = server.respond(&challenge).unwrap();
+        let keys = session.parse_license_response(&response).unwrap();
+
+        assert_eq!(keys, &server.keys()[1..]);
+    }
+
+    #[test]
+    fn round_trip_without_key_ids_issues_all_keys() {
+        let server = test_server();
+        let mut session = Session::new(test_device());
+
+        let challenge = session
+            .build_license_challenge(&test_pssh(&[]), LicenseType::Offline)
+            .unwrap();
+        let response = server.respond(&challenge).unwrap();
+        let keys = session.parse_license_response(&response).unwrap();
+
+        assert_eq!(keys, server.keys());
+    }
+
+    #[test]
+    fn rejects_tampered_challenge() {
+        let mut session = Session::new(test_device());
+        let challenge = session
+            .build_license_challenge(&test_pssh(&[KID_1]), LicenseType::Streaming)
+            .unwrap();
+
+        let mut signed = SignedMessage::decode(challenge.as_slice()).unwrap();
+        if let Some(signature) = signed.signature.as_mut() {
+            signature[0] ^= 0xFF;
+        }
+        let err = test_server().respond(&signed.encode_to_vec()).unwrap_err();
+        assert!(matches!(err, CdmError::LicenseRequestRejected(_)));
+    }
+
+    #[test]
+    fn rejects_unknown_key_ids() {
+        let mut session = Session::new(test_device());
+        let challenge = session
+            .build_license_challenge(&test_pssh(&[[0xAB; 16]]), LicenseType::Streaming)
+            .unwrap();
+        let err = test_server().respond(&challenge).unwrap_err();
+        assert!(matches!(err, CdmError::LicenseRequestRejected(_)));
+    }
+
+    #[test]
+    fn rejects_privacy_mode() {
+        let mut session = Session::new(test_device());
+        session.set_service_certificate_common().unwrap();
+        let challenge = session
+            .build_license_challenge(&test_pssh(&[KID_1]), LicenseType::Streaming)
+            .unwrap();
+        let err = test_server().respond(&challenge).unwrap_err();
+        assert!(matches!(err, CdmError::LicenseRequestRejected(_)));
+    }
+}
@@ -0,0 +1,101 @@
+use core::fmt;
+
+use crate::types::LicenseType;
+
+/**
+    One step of a license exchange, as recorded in a [`Transcript`].
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TranscriptEntry {
+    /// A service certificate was set, enabling privacy mode.
+    ServiceCertificate {
+        provider_id: Option<String>,
+        serial_number: Vec<u8>,
+    },
+    /// A license challenge was built.
+    Request {
+        request_id: Vec<u8>,
+        license_type: LicenseType,
+        privacy_mode: bool,
+        /// Key derivation context for the encryption key.
+        enc_context: Vec<u8>,
+        /// Key derivation context for the MAC keys.
+        mac_context: Vec<u8>,
+        /// The serialized SignedMessage sent to the license server.
+        challenge: Vec<u8>,
+    },
+    /// A license response was received, as raw bytes.
+    Response { response: Vec<u8> },
+    /// A decision made while parsing a response, e.g. a skipped key container.
+    Decision(String),
+    /// Parsing the response failed.
+    Error(String),
+}
+
+impl fmt::Display for TranscriptEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let base64 = |bytes: &[u8]| data_encoding::BASE64.encode(bytes);
+        match self {
+            Self::ServiceCertificate {
+                provider_id,
+                serial_number,
+            } => write!(
+                f,
+                "service certificate: provider_id={} serial={}",
+                provider_id.as_deref().unwrap_or("?"),
+                hex::encode(serial_number)
+            ),
+            Self::Request {
+                request_id,
+                license_type,
+                privacy_mode,
+                enc_context,
+                mac_context,
+                challenge,
+            } => write!(
+                f,
+                "request: request_id={} license_type={} privacy_mode={}\n  \
+                 enc_context={}\n  mac_context={}\n  challenge={}",
+                hex::encode(request_id),
+                license_type,
+                privacy_mode,
+                hex::encode(enc_context),
+                hex::encode(mac_context),
+                base64(challenge)
+            ),
+            Self::Response { response } => write!(f, "response: {}", base64(response)),
+            Self::Decision(decision) => write!(f, "parse: {decision}"),
+            Self::Error(error) => write!(f, "error: {error}"),
+        }
+    }
+}
+
+/**
+    A record of the license exchanges of a session, for debugging failed ones.
+
+    Holds the serialized requests and responses, the key derivation contexts,
+    and what the response parser did with each part of the license. Secrets
+    (the device private key, the session key, derived keys and content keys)
+    are never recorded: keys show up only as their ID, type and length.
+
+    The `Display` output is a plain text dump, one entry per line.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    pub entries: Vec<TranscriptEntry>,
+}
+
+impl Transcript {
+    pub(crate) fn push(&mut self, entry: TranscriptEntry) {
+        self.entries.push(entry);
+    }
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, entry) in self.entries.iter().enumerate() {
+            writeln!(f, "{index:>3}. {entry}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,418 @@
+/*!
+    Miniature encrypted DASH assets for tests.
+
+    Generates a ClearKey-protected, CENC-encrypted fragmented MP4 with a
+    single PCM audio track playing a sine tone, split into an init segment
+    and a few media segments, along with the DASH manifest describing it.
+    Everything is derived from fixed key material and options, so the same
+    options always give the same bytes, and tests across the workspace get
+    deterministic encrypted inputs without binary blobs in the repository.
+*/
+
+use std::f64::consts::TAU;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use aes::Aes128;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use drm_core::{CLEARKEY_SYSTEM_ID, KeyString, PsshBox};
+use hex_literal::hex;
+
+mod mp4;
+
+use self::mp4::{EncryptedSample, TrackParams};
+
+/**
+    Key ID the fixtures are encrypted under.
+*/
+pub const KEY_ID: [u8; 16] = hex!("00112233445566778899aabbccddeeff");
+
+/**
+    Content key the fixtures are encrypted with.
+*/
+pub const KEY: [u8; 16] = hex!("100b6c20940f779a4589152b57d2dacb");
+
+/**
+    Name of the init segment, as referenced by the manifest.
+*/
+pub const INIT_SEGMENT: &str = "init.mp4";
+
+/**
+    Name of the manifest written by [`EncryptedAsset::write_to`].
+*/
+pub const MANIFEST: &str = "manifest.mpd";
+
+/**
+    Options for generating an asset.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AssetOptions {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Frequency of the tone, in Hz
+    pub frequency: f64,
+    /// Number of media segments
+    pub segments: u32,
+    /// Length of each media segment, in milliseconds
+    pub segment_ms: u32,
+    /// Length of each sample within a segment, in milliseconds
+    pub sample_ms: u32,
+}
+
+impl Default for AssetOptions {
+    fn default() -> Self {
+        Self {
+            sample_rate: 8000,
+            channels: 1,
+            frequency: 440.0,
+            segments: 3,
+            segment_ms: 1000,
+            sample_ms: 100,
+        }
+    }
+}
+
+/**
+    A generated asset, held in memory.
+*/
+#[derive(Debug, Clone)]
+pub struct EncryptedAsset {
+    pub options: AssetOptions,
+    /// The init segment, with the `pssh` box in its `moov`
+    pub init: Vec<u8>,
+    /// The media segments, numbered from 1 in the manifest
+    pub segments: Vec<Vec<u8>>,
+    /// The DASH manifest
+    pub manifest: String,
+    /// The clear samples of each media segment, as 16-bit little-endian PCM
+    pub clear_samples: Vec<Vec<Vec<u8>>>,
+}
+
+impl EncryptedAsset {
+    /**
+        Generate an asset with the default options.
+    */
+    pub fn generate() -> Self {
+        Self::with_options(AssetOptions::default())
+    }
+
+    /**
+        Generate an asset with the given options.
+
+        # Panics
+
+        Panics if the segment or sample length is zero, if the segment
+        length is not a whole number of samples, or if the sample rate
+        doesn't fit the 16.16 fixed point field of the sample entry.
+    */
+    pub fn with_options(options: AssetOptions) -> Self {
+        assert!(
+            (1..=u32::from(u16::MAX)).contains(&options.sample_rate),
+            "sample rate must be between 1 and 65535 Hz"
+        );
+        assert!(options.sample_ms > 0, "sample length must not be zero");
+        assert!(
+            options.segment_ms > 0 && options.segment_ms.is_multiple_of(options.sample_ms),
+            "segment length must be a whole number of samples"
+        );
+
+        let params = TrackParams {
+            sample_rate: options.sample_rate,
+            channels: options.channels,
+            key_id: KEY_ID,
+        };
+        let init = mp4::init_segment(&params, &pssh().to_bytes());
+
+        let frames_per_sample = options.sample_rate * options.sample_ms / 1000;
+        let samples_per_segment = options.segment_ms / options.sample_ms;
+
+        let mut segments = Vec::new();
+        let mut clear_samples = Vec::new();
+        let mut frame = 0u64;
+        let mut sample_index = 0u64;
+        for sequence in 1..=options.segments {
+            let decode_time = frame;
+            let mut clear = Vec::new();
+            let mut encrypted = Vec::new();
+            for _ in 0..samples_per_segment {
+                let data = tone(&options, frame, frames_per_sample);
+                let iv = sample_index.to_be_bytes();
+                encrypted.push(EncryptedSample {
+                    data: apply_ctr(&KEY, &iv, &data),
+                    iv,
+                    duration: frames_per_sample,
+                });
+                clear.push(data);
+                frame += u64::from(frames_per_sample);
+                sample_index += 1;
+            }
+            segments.push(mp4::media_segment(sequence, decode_time, &encrypted));
+            clear_samples.push(clear);
+        }
+
+        Self {
+            options,
+            init,
+            segments,
+            manifest: manifest(&options),
+            clear_samples,
+        }
+    }
+
+    /**
+        Get the key the asset is encrypted with, as a `kid:key` pair.
+    */
+    pub fn key() -> KeyString {
+        KeyString {
+            kid: KEY_ID,
+            key: KEY.to_vec(),
+        }
+    }
+
+    /**
+        Get the name of a media segment, as referenced by the manifest.
+        Segments are numbered from 1.
+    */
+    pub fn segment_name(number: u32) -> String {
+        format!("segment-{}.m4s", number)
+    }
+
+    /**
+        Write the manifest, init segment and media segments to a folder,
+        creating it if needed.
+    */
+    pub fn write_to(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join(MANIFEST), &self.manifest)?;
+        fs::write(dir.join(INIT_SEGMENT), &self.init)?;
+        for (index, segment) in self.segments.iter().enumerate() {
+            fs::write(dir.join(Self::segment_name(index as u32 + 1)), segment)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+    The ClearKey `pssh` box for the fixture key ID.
+*/
+pub fn pssh() -> PsshBox {
+    PsshBox {
+        version: 1,
+        flags: [0; 3],
+        system_id: CLEARKEY_SYSTEM_ID,
+        key_ids: vec![KEY_ID],
+        data: Vec::new(),
+    }
+}
+
+/**
+    Apply AES-128-CTR as the `cenc` scheme does, with the 8-byte IV in the
+    high half of the counter block and the block counter in the low half.
+
+    Encrypts and decrypts alike, for tests checking decrypted output.
+*/
+pub fn apply_ctr(key: &[u8; 16], iv: &[u8; 8], data: &[u8]) -> Vec<u8> {
+    let cipher = Aes128::new(key.into());
+    let mut out = data.to_vec();
+    for (counter, chunk) in out.chunks_mut(16).enumerate() {
+        let mut block = [0u8; 16];
+        block[..8].copy_from_slice(iv);
+        block[8..].copy_from_slice(&(counter as u64).to_be_bytes());
+        let mut block = block.into();
+        cipher.encrypt_block(&mut block);
+        for (byte, pad) in chunk.iter_mut().zip(block.iter()) {
+            *byte ^= pad;
+        }
+    }
+    out
+}
+
+/**
+    Generate interleaved 16-bit little-endian PCM frames of the tone,
+    starting at the given frame, at half of full scale.
+*/
+fn tone(options: &AssetOptions, start: u64, frames: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(frames as usize * options.channels as usize * 2);
+    for frame in start..start + u64::from(frames) {
+        let t = frame as f64 / f64::from(options.sample_rate);
+        let value = ((TAU * options.frequency * t).sin() * f64::from(i16::MAX) / 2.0) as i16;
+        for _ in 0..options.channels {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    out
+}
+
+/**
+    Write the static DASH manifest for an asset.
+*/
+fn manifest(options: &AssetOptions) -> String {
+    let duration_ms = options.segments * options.segment_ms;
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" xmlns:cenc="urn:mpeg:cenc:2013" type="static" profiles="urn:mpeg:dash:profile:isoff-live:2011" minBufferTime="PT1S" mediaPresentationDuration="PT{secs}.{millis:03}S">
+  <Period id="0" start="PT0S">
+    <AdaptationSet id="0" contentType="audio" mimeType="audio/mp4" segmentAlignment="true">
+      <ContentProtection schemeIdUri="urn:mpeg:dash:mp4protection:2011" value="cenc" cenc:default_KID="{default_kid}"/>
+      <ContentProtection schemeIdUri="urn:uuid:{system_id}" value="ClearKey1.0">
+        <cenc:pssh>{pssh}</cenc:pssh>
+      </ContentProtection>
+      <Representation id="audio" codecs="sowt" bandwidth="{bandwidth}" audioSamplingRate="{sample_rate}">
+        <AudioChannelConfiguration schemeIdUri="urn:mpeg:dash:23003:3:audio_channel_configuration:2011" value="{channels}"/>
+        <SegmentTemplate timescale="{sample_rate}" duration="{segment_frames}" startNumber="1" initialization="{init}" media="segment-$Number$.m4s"/>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>
+"#,
+        secs = duration_ms / 1000,
+        millis = duration_ms % 1000,
+        default_kid = uuid(&KEY_ID),
+        system_id = uuid(&CLEARKEY_SYSTEM_ID),
+        pssh = pssh().to_base64(),
+        bandwidth = options.sample_rate * u32::from(options.channels) * 16,
+        sample_rate = options.sample_rate,
+        channels = options.channels,
+        segment_frames = options.sample_rate * options.segment_ms / 1000,
+        init = INIT_SEGMENT,
+    )
+}
+
+/**
+    Format 16 bytes as a lowercase UUID with dashes, as DASH manifests write them.
+*/
+fn uuid(bytes: &[u8; 16]) -> String {
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+        Find the payload of the first box of a type, searching into the
+        container boxes on the way.
+    */
+    fn find_box<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+        let mut offset = 0;
+        while offset + 8 <= data.len() {
+            let size = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+            let kind = &data[offset + 4..offset + 8];
+            let payload = &data[offset + 8..offset + size];
+            if kind == path[0] {
+                return match path.len() {
+                    1 => Some(payload),
+                    _ => find_box(payload, &path[1..]),
+                };
+            }
+            offset += size;
+        }
+        None
+    }
+
+    fn be_u32(data: &[u8], at: usize) -> u32 {
+        u32::from_be_bytes(data[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_generation_is_deterministic() {
+        let a = EncryptedAsset::generate();
+        let b = EncryptedAsset::generate();
+        assert_eq!(a.init, b.init);
+        assert_eq!(a.segments, b.segments);
+        assert_eq!(a.manifest, b.manifest);
+        assert_eq!(a.segments.len(), 3);
+    }
+
+    #[test]
+    fn test_init_segment_carries_pssh() {
+        let asset = EncryptedAsset::generate();
+        let moov = find_box(&asset.init, &[b"moov"]).unwrap();
+        let pssh_payload = find_box(moov, &[b"pssh"]).unwrap();
+
+        let parsed = PsshBox::from_bytes(&mp4::boxed(b"pssh", pssh_payload)).unwrap();
+        assert_eq!(parsed.system_id, CLEARKEY_SYSTEM_ID);
+        assert_eq!(parsed.key_ids(), &[KEY_ID]);
+
+        assert!(asset.manifest.contains(&pssh().to_base64()));
+        assert!(
+            asset
+                .manifest
+                .contains("cenc:default_KID=\"00112233-4455-6677-8899-aabbccddeeff\"")
+        );
+    }
+
+    #[test]
+    fn test_segments_decrypt_to_clear_samples() {
+        let asset = EncryptedAsset::generate();
+        for (segment, clear) in asset.segments.iter().zip(&asset.clear_samples) {
+            let moof_start = segment.len()
+                - find_box(segment, &[b"moof"]).unwrap().len()
+                - find_box(segment, &[b"mdat"]).unwrap().len()
+                - 16;
+            let moof = &segment[moof_start..];
+            let traf = find_box(moof, &[b"moof", b"traf"]).unwrap();
+            let trun = find_box(traf, &[b"trun"]).unwrap();
+            let senc = find_box(traf, &[b"senc"]).unwrap();
+            let saio = find_box(traf, &[b"saio"]).unwrap();
+
+            let count = be_u32(trun, 4) as usize;
+            assert_eq!(count, clear.len());
+
+            // Offsets are relative to the start of moof
+            let mut data_offset = moof_start + be_u32(trun, 8) as usize;
+            let iv_offset = moof_start + be_u32(saio, 8) as usize;
+            assert_eq!(&segment[iv_offset..iv_offset + 8], &senc[8..16]);
+
+            for (index, clear) in clear.iter().enumerate() {
+                let size = be_u32(trun, 12 + index * 8 + 4) as usize;
+                let iv: [u8; 8] = senc[8 + index * 8..16 + index * 8].try_into().unwrap();
+                let encrypted = &segment[data_offset..data_offset + size];
+                assert_ne!(encrypted, clear.as_slice());
+                assert_eq!(&apply_ctr(&KEY, &iv, encrypted), clear);
+                data_offset += size;
+            }
+            assert_eq!(data_offset, segment.len());
+        }
+    }
+
+    #[test]
+    fn test_manifest_codec_matches_sample_entry() {
+        let asset = EncryptedAsset::generate();
+        let moov = find_box(&asset.init, &[b"moov"]).unwrap();
+        let stsd = find_box(moov, &[b"trak", b"mdia", b"minf", b"stbl", b"stsd"]).unwrap();
+        // Past the entry count, and the audio fields of the sample entry
+        let enca = find_box(&stsd[8..], &[b"enca"]).unwrap();
+        let frma = find_box(&enca[28..], &[b"sinf", b"frma"]).unwrap();
+        assert_eq!(frma, b"sowt");
+        assert!(asset.manifest.contains("codecs=\"sowt\""));
+    }
+
+    #[test]
+    #[should_panic(expected = "sample rate")]
+    fn test_rejects_sample_rates_past_16_bits() {
+        EncryptedAsset::with_options(AssetOptions {
+            sample_rate: 96_000,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_key_string() {
+        let key = EncryptedAsset::key();
+        assert_eq!(
+            key.to_string(),
+            "00112233445566778899aabbccddeeff:100b6c20940f779a4589152b57d2dacb"
+        );
+        assert_eq!(KeyString::parse(&key.to_string()).unwrap(), key);
+    }
+}
@@ -0,0 +1,308 @@
+/*!
+    Writers for the ISOBMFF boxes of a fragmented MP4 with a single
+    CENC-protected PCM audio track.
+
+    Only the boxes and fields needed by demuxers are written, with fixed
+    values wherever the format allows, so that the output is deterministic.
+*/
+
+/**
+    Track ID of the only track.
+*/
+const TRACK_ID: u32 = 1;
+
+/**
+    Size of the per-sample IVs written to `senc`.
+*/
+pub const IV_SIZE: u8 = 8;
+
+/**
+    Unity transformation matrix of `mvhd` and `tkhd`.
+*/
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/**
+    Parameters of the audio track.
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct TrackParams {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub key_id: [u8; 16],
+}
+
+/**
+    A sample of a media segment, already encrypted.
+*/
+pub struct EncryptedSample {
+    pub data: Vec<u8>,
+    pub iv: [u8; 8],
+    /// Duration in the track's timescale (its sample rate)
+    pub duration: u32,
+}
+
+/**
+    Write a box with the given type around its payload.
+*/
+pub fn boxed(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(8 + payload.len());
+    out.extend_from_slice(&(8 + payload.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(payload);
+    out
+}
+
+/**
+    Write a full box, with a version and flags, around its payload.
+*/
+pub fn full_box(kind: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(4 + payload.len());
+    body.push(version);
+    body.extend_from_slice(&flags.to_be_bytes()[1..]);
+    body.extend_from_slice(payload);
+    boxed(kind, &body)
+}
+
+/**
+    Write the initialization segment: `ftyp`, and `moov` with the track,
+    its protection scheme, and the given `pssh` box.
+*/
+pub fn init_segment(params: &TrackParams, pssh: &[u8]) -> Vec<u8> {
+    let mut out = ftyp(b"iso6");
+    let mut moov = mvhd();
+    moov.extend(trak(params));
+    moov.extend(boxed(
+        b"mvex",
+        &full_box(
+            b"trex",
+            0,
+            0,
+            &[
+                TRACK_ID.to_be_bytes(),
+                1u32.to_be_bytes(),
+                0u32.to_be_bytes(),
+                0u32.to_be_bytes(),
+                0u32.to_be_bytes(),
+            ]
+            .concat(),
+        ),
+    ));
+    moov.extend_from_slice(pssh);
+    out.extend(boxed(b"moov", &moov));
+    out
+}
+
+/**
+    Write a media segment: `styp`, `moof` with the sample sizes, durations
+    and IVs, and `mdat` with the encrypted samples.
+*/
+pub fn media_segment(sequence: u32, decode_time: u64, samples: &[EncryptedSample]) -> Vec<u8> {
+    let count = samples.len() as u32;
+
+    let mfhd = full_box(b"mfhd", 0, 0, &sequence.to_be_bytes());
+    // default-base-is-moof
+    let tfhd = full_box(b"tfhd", 0, 0x02_0000, &TRACK_ID.to_be_bytes());
+    let tfdt = full_box(b"tfdt", 1, 0, &decode_time.to_be_bytes());
+
+    let trun_for = |data_offset: u32| {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&count.to_be_bytes());
+        payload.extend_from_slice(&data_offset.to_be_bytes());
+        for sample in samples {
+            payload.extend_from_slice(&sample.duration.to_be_bytes());
+            payload.extend_from_slice(&(sample.data.len() as u32).to_be_bytes());
+        }
+        // data-offset-present, sample-duration-present, sample-size-present
+        full_box(b"trun", 0, 0x00_0301, &payload)
+    };
+
+    let mut senc_payload = count.to_be_bytes().to_vec();
+    for sample in samples {
+        senc_payload.extend_from_slice(&sample.iv);
+    }
+    let senc = full_box(b"senc", 0, 0, &senc_payload);
+    let saiz = full_box(
+        b"saiz",
+        0,
+        0,
+        &[&[IV_SIZE][..], &count.to_be_bytes()].concat(),
+    );
+
+    let saio_for = |offset: u32| {
+        full_box(
+            b"saio",
+            0,
+            0,
+            &[1u32, offset].map(u32::to_be_bytes).concat(),
+        )
+    };
+
+    // Sizes don't depend on the offsets, so lay the boxes out once to find them
+    let trun_size = trun_for(0).len();
+    let saio_size = saio_for(0).len();
+    let traf_size = 8 + tfhd.len() + tfdt.len() + trun_size + senc.len() + saiz.len() + saio_size;
+    let moof_size = 8 + mfhd.len() + traf_size;
+    // The IVs follow the senc header, version and flags, and sample count
+    let senc_offset = 8 + mfhd.len() + 8 + tfhd.len() + tfdt.len() + trun_size + 12 + 4;
+    // The samples follow the mdat header
+    let data_offset = moof_size + 8;
+
+    let mut traf = tfhd;
+    traf.extend(tfdt);
+    traf.extend(trun_for(data_offset as u32));
+    traf.extend(senc);
+    traf.extend(saiz);
+    traf.extend(saio_for(senc_offset as u32));
+
+    let mut moof = mfhd;
+    moof.extend(boxed(b"traf", &traf));
+
+    let mdat: Vec<u8> = samples
+        .iter()
+        .flat_map(|s| s.data.iter().copied())
+        .collect();
+
+    let mut out = styp();
+    out.extend(boxed(b"moof", &moof));
+    out.extend(boxed(b"mdat", &mdat));
+    out
+}
+
+fn ftyp(major: &[u8; 4]) -> Vec<u8> {
+    let mut payload = major.to_vec();
+    payload.extend_from_slice(&0u32.to_be_bytes());
+    for brand in [b"iso6", b"cmfc", b"dash"] {
+        payload.extend_from_slice(brand);
+    }
+    boxed(b"ftyp", &payload)
+}
+
+fn styp() -> Vec<u8> {
+    let mut payload = b"msdh".to_vec();
+    payload.extend_from_slice(&0u32.to_be_bytes());
+    payload.extend_from_slice(b"msdhmsix");
+    boxed(b"styp", &payload)
+}
+
+fn matrix() -> Vec<u8> {
+    MATRIX.iter().flat_map(|v| v.to_be_bytes()).collect()
+}
+
+fn mvhd() -> Vec<u8> {
+    let mut payload = Vec::new();
+    // creation and modification time, timescale, duration
+    for value in [0u32, 0, 1000, 0] {
+        payload.extend_from_slice(&value.to_be_bytes());
+    }
+    // rate 1.0, volume 1.0, reserved
+    payload.extend_from_slice(&0x0001_0000u32.to_be_bytes());
+    payload.extend_from_slice(&0x0100u16.to_be_bytes());
+    payload.extend_from_slice(&[0; 10]);
+    payload.extend(matrix());
+    payload.extend_from_slice(&[0; 24]);
+    // next track ID
+    payload.extend_from_slice(&(TRACK_ID + 1).to_be_bytes());
+    full_box(b"mvhd", 0, 0, &payload)
+}
+
+fn trak(params: &TrackParams) -> Vec<u8> {
+    let mut tkhd = Vec::new();
+    // creation and modification time, track ID, reserved, duration
+    for value in [0u32, 0, TRACK_ID, 0, 0] {
+        tkhd.extend_from_slice(&value.to_be_bytes());
+    }
+    // reserved, layer, alternate group, volume 1.0, reserved
+    tkhd.extend_from_slice(&[0; 8]);
+    tkhd.extend_from_slice(&[0, 0, 0, 0, 0x01, 0x00, 0, 0]);
+    tkhd.extend(matrix());
+    // width and height
+    tkhd.extend_from_slice(&[0; 8]);
+    // enabled, in movie, in preview
+    let tkhd = full_box(b"tkhd", 0, 0x00_0007, &tkhd);
+
+    let mut mdhd = Vec::new();
+    for value in [0u32, 0, params.sample_rate, 0] {
+        mdhd.extend_from_slice(&value.to_be_bytes());
+    }
+    // language "und", pre-defined
+    mdhd.extend_from_slice(&[0x55, 0xc4, 0, 0]);
+    let mdhd = full_box(b"mdhd", 0, 0, &mdhd);
+
+    let mut hdlr = 0u32.to_be_bytes().to_vec();
+    hdlr.extend_from_slice(b"soun");
+    hdlr.extend_from_slice(&[0; 12]);
+    hdlr.extend_from_slice(b"SoundHandler\0");
+    let hdlr = full_box(b"hdlr", 0, 0, &hdlr);
+
+    let smhd = full_box(b"smhd", 0, 0, &[0; 4]);
+    let dref = full_box(
+        b"dref",
+        0,
+        0,
+        &[&1u32.to_be_bytes()[..], &full_box(b"url ", 0, 1, &[])].concat(),
+    );
+    let dinf = boxed(b"dinf", &dref);
+
+    let mut stbl = full_box(
+        b"stsd",
+        0,
+        0,
+        &[&1u32.to_be_bytes()[..], &enca(params)].concat(),
+    );
+    stbl.extend(full_box(b"stts", 0, 0, &0u32.to_be_bytes()));
+    stbl.extend(full_box(b"stsc", 0, 0, &0u32.to_be_bytes()));
+    stbl.extend(full_box(
+        b"stsz",
+        0,
+        0,
+        &[0u32, 0].map(u32::to_be_bytes).concat(),
+    ));
+    stbl.extend(full_box(b"stco", 0, 0, &0u32.to_be_bytes()));
+
+    let mut minf = smhd;
+    minf.extend(dinf);
+    minf.extend(boxed(b"stbl", &stbl));
+
+    let mut mdia = mdhd;
+    mdia.extend(hdlr);
+    mdia.extend(boxed(b"minf", &minf));
+
+    let mut trak = tkhd;
+    trak.extend(boxed(b"mdia", &mdia));
+    boxed(b"trak", &trak)
+}
+
+/**
+    Audio sample entry of 16-bit little-endian PCM (`sowt`), protected
+    with the `cenc` scheme under the track's key ID.
+*/
+fn enca(params: &TrackParams) -> Vec<u8> {
+    let mut entry = vec![0; 6];
+    // data reference index
+    entry.extend_from_slice(&1u16.to_be_bytes());
+    entry.extend_from_slice(&[0; 8]);
+    entry.extend_from_slice(&params.channels.to_be_bytes());
+    // sample size, pre-defined, reserved
+    entry.extend_from_slice(&16u16.to_be_bytes());
+    entry.extend_from_slice(&[0; 4]);
+    // sample rate as 16.16 fixed point
+    entry.extend_from_slice(&(params.sample_rate << 16).to_be_bytes());
+
+    let frma = boxed(b"frma", b"sowt");
+    let schm = full_box(
+        b"schm",
+        0,
+        0,
+        &[&b"cenc"[..], &0x0001_0000u32.to_be_bytes()].concat(),
+    );
+    let mut tenc = vec![0, 0, 1, IV_SIZE];
+    tenc.extend_from_slice(&params.key_id);
+    let schi = boxed(b"schi", &full_box(b"tenc", 0, 0, &tenc));
+
+    let mut sinf = frma;
+    sinf.extend(schm);
+    sinf.extend(schi);
+    entry.extend(boxed(b"sinf", &sinf));
+
+    boxed(b"enca", &entry)
+}
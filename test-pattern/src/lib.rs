@@ -0,0 +1,273 @@
+/*!
+    Synthetic test media: color bars with a 1 kHz tone, encoded on the fly.
+
+    Lets pipeline, sink and decoder tests across the workspace run on real
+    encoded media without depending on fixture files.
+*/
+
+use std::collections::VecDeque;
+use std::f32::consts::TAU;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ffmpeg_encode::{AudioEncoder, AudioEncoderConfig, VideoEncoder, VideoEncoderConfig};
+use ffmpeg_sink::{Sink, SinkConfig};
+use ffmpeg_source::{Source, SourceConfig};
+use ffmpeg_types::{AudioFrame, Error, Packet, VideoFrame};
+use tempfile::TempDir;
+
+const SAMPLE_RATE: u32 = 48_000;
+const CHANNELS: usize = 2;
+const AUDIO_FRAME_SAMPLES: usize = 1024;
+
+/// Segment length of the encoded stream, short so that tests get several segments
+const SEGMENT_DURATION: Duration = Duration::from_secs(1);
+
+const TONE_FREQUENCY: f32 = 1000.0;
+/// -20 dBFS, the usual line-up level
+const TONE_AMPLITUDE: f32 = 0.1;
+
+/// 75% color bars, left to right, as BGRA
+const BARS: [[u8; 4]; 7] = [
+    [191, 191, 191, 255], // white
+    [0, 191, 191, 255],   // yellow
+    [191, 191, 0, 255],   // cyan
+    [0, 191, 0, 255],     // green
+    [191, 0, 191, 255],   // magenta
+    [0, 0, 191, 255],     // red
+    [191, 0, 0, 255],     // blue
+];
+const MARKER: [u8; 4] = [16, 16, 16, 255];
+
+/**
+    Render a frame of color bars as tightly packed BGRA. A dark marker moves
+    across the bottom of the frame, so that consecutive frames differ.
+*/
+fn color_bars(width: u32, height: u32, index: u32) -> Vec<u8> {
+    let mut frame = Vec::with_capacity((width * height * 4) as usize);
+    let marker_size = (height / 8).max(1);
+    let marker_x = (index * 8) % width.max(1);
+
+    for y in 0..height {
+        for x in 0..width {
+            let in_marker =
+                y >= height - marker_size && x >= marker_x && x < marker_x + marker_size;
+            if in_marker {
+                frame.extend_from_slice(&MARKER);
+            } else {
+                let bar = (x as usize * BARS.len()) / width as usize;
+                frame.extend_from_slice(&BARS[bar]);
+            }
+        }
+    }
+    frame
+}
+
+/**
+    Generate interleaved samples of the test tone, starting at the given sample offset.
+*/
+fn tone(offset: usize, samples: usize) -> Vec<f32> {
+    (offset..offset + samples)
+        .flat_map(|n| {
+            let t = n as f32 / SAMPLE_RATE as f32;
+            let sample = TONE_AMPLITUDE * (TAU * TONE_FREQUENCY * t).sin();
+            [sample; CHANNELS]
+        })
+        .collect()
+}
+
+/**
+    Get the presentation timestamp of a packet in seconds, if it has one.
+*/
+fn packet_seconds(packet: &Packet) -> Option<f64> {
+    let pts = packet.pts?;
+    let time_base = packet.time_base;
+    if time_base.den == 0 {
+        return None;
+    }
+    Some(pts as f64 * time_base.num as f64 / time_base.den as f64)
+}
+
+/**
+    Encoded packets waiting to be written, so that the packets of both
+    streams reach the sink interleaved by timestamp. Each stream keeps
+    the order its encoder produced its packets in.
+*/
+#[derive(Default)]
+struct Interleaver {
+    video: VecDeque<Packet>,
+    audio: VecDeque<Packet>,
+}
+
+impl Interleaver {
+    /**
+        Write the earliest waiting packet for as long as both streams have
+        one waiting. When finishing, packets left in either stream are
+        written as well.
+    */
+    fn write_ready(&mut self, sink: &mut Sink, finish: bool) -> Result<(), Error> {
+        loop {
+            let take_video = match (self.video.front(), self.audio.front()) {
+                (Some(video), Some(audio)) => packet_seconds(video) <= packet_seconds(audio),
+                (Some(_), None) if finish => true,
+                (None, Some(_)) if finish => false,
+                _ => return Ok(()),
+            };
+            let queue = if take_video {
+                &mut self.video
+            } else {
+                &mut self.audio
+            };
+            if let Some(packet) = queue.pop_front() {
+                sink.write(&packet)?;
+            }
+        }
+    }
+}
+
+/**
+    Encode color bars with a 1 kHz tone to an HLS stream in the given
+    directory, returning the path of its playlist.
+
+    Video frames and audio chunks are encoded side by side, in timestamp
+    order, so that the muxer never has to hold back one stream for the other.
+*/
+pub fn write_test_pattern(
+    dir: &Path,
+    width: u32,
+    height: u32,
+    fps: u32,
+    duration: Duration,
+) -> Result<PathBuf, Error> {
+    std::fs::create_dir_all(dir).map_err(|e| Error::codec(e.to_string()))?;
+
+    let mut video_encoder = VideoEncoder::new(VideoEncoderConfig::h264(width, height))?;
+    let mut audio_encoder = AudioEncoder::new(AudioEncoderConfig::aac())?;
+
+    let playlist_path = dir.join("playlist.m3u8");
+    let sink_config = SinkConfig::hls(SEGMENT_DURATION)
+        .with_video(video_encoder.stream_info())
+        .with_audio(audio_encoder.stream_info());
+    let mut sink = Sink::file(&playlist_path, sink_config)?;
+
+    let frames = (duration.as_secs_f64() * fps as f64).ceil() as u32;
+    let samples = (duration.as_secs_f64() * SAMPLE_RATE as f64) as usize;
+    let mut pending = Interleaver::default();
+    let mut index = 0;
+    let mut offset = 0;
+
+    while index < frames || offset < samples {
+        let video_secs = index as f64 / fps as f64;
+        let audio_secs = offset as f64 / SAMPLE_RATE as f64;
+
+        if index < frames && (offset >= samples || video_secs <= audio_secs) {
+            let pts = Duration::from_secs_f64(video_secs);
+            let frame = VideoFrame::from_bgra(color_bars(width, height, index), width, height, pts);
+            pending.video.extend(video_encoder.encode(&frame)?);
+            index += 1;
+        } else {
+            let pts = Duration::from_secs_f64(audio_secs);
+            let mut frame =
+                AudioFrame::silence(SAMPLE_RATE, CHANNELS as u32, AUDIO_FRAME_SAMPLES, pts);
+            let wave = tone(offset, AUDIO_FRAME_SAMPLES);
+            for (chunk, sample) in frame.data.chunks_exact_mut(4).zip(&wave) {
+                chunk.copy_from_slice(&sample.to_ne_bytes());
+            }
+            pending.audio.extend(audio_encoder.encode(&frame)?);
+            offset += AUDIO_FRAME_SAMPLES;
+        }
+
+        pending.write_ready(&mut sink, false)?;
+    }
+
+    pending.video.extend(video_encoder.flush()?);
+    pending.audio.extend(audio_encoder.flush()?);
+    pending.write_ready(&mut sink, true)?;
+    sink.finish()?;
+
+    Ok(playlist_path)
+}
+
+/**
+    A synthetic source of color bars with a 1 kHz tone, for tests that
+    need media without depending on fixture files.
+
+    The stream is encoded to a temporary directory, removed on drop.
+*/
+pub struct TestPattern {
+    pub source: Source,
+    playlist: PathBuf,
+    _dir: TempDir,
+}
+
+impl TestPattern {
+    /**
+        Path of the encoded stream's playlist, for opening it again.
+    */
+    pub fn playlist_path(&self) -> &Path {
+        &self.playlist
+    }
+}
+
+/**
+    Open a test pattern source of the given size, frame rate and duration.
+*/
+pub async fn open_test_pattern(
+    width: u32,
+    height: u32,
+    fps: u32,
+    duration: Duration,
+) -> Result<TestPattern, Error> {
+    let dir = TempDir::new().map_err(|e| Error::codec(e.to_string()))?;
+    let playlist = write_test_pattern(dir.path(), width, height, fps, duration)?;
+
+    let path = playlist.to_string_lossy().to_string();
+    let source = Source::open(&path, SourceConfig::default()).await?;
+
+    Ok(TestPattern {
+        source,
+        playlist,
+        _dir: dir,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_bars() {
+        let frame = color_bars(70, 40, 0);
+        assert_eq!(frame.len(), 70 * 40 * 4);
+        // One bar per 10 columns, top row is free of the marker
+        for (bar, color) in BARS.iter().enumerate() {
+            let offset = (bar * 10 + 5) * 4;
+            assert_eq!(&frame[offset..offset + 4], color);
+        }
+        // The marker moves between frames
+        assert_ne!(color_bars(70, 40, 1), frame);
+    }
+
+    #[test]
+    fn test_tone() {
+        let samples = tone(0, SAMPLE_RATE as usize);
+        assert_eq!(samples.len(), SAMPLE_RATE as usize * CHANNELS);
+        let peak = samples.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+        assert!((peak - TONE_AMPLITUDE).abs() < 1e-3);
+        // Channels carry the same signal
+        assert!(samples.chunks_exact(CHANNELS).all(|f| f[0] == f[1]));
+    }
+
+    #[tokio::test]
+    async fn test_open_test_pattern() {
+        let pattern = open_test_pattern(320, 180, 25, Duration::from_secs(3))
+            .await
+            .unwrap();
+        assert!(pattern.playlist_path().exists());
+
+        let media_info = pattern.source.media_info();
+        let video = media_info.video.as_ref().unwrap();
+        assert_eq!((video.width, video.height), (320, 180));
+        assert!(media_info.audio.is_some());
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{ConnectInfo, MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use http_body::{Frame, SizeHint};
+
+/**
+    Totals for a single route, across all of its requests.
+*/
+#[derive(Debug, Default)]
+struct RouteStats {
+    /// Number of requests by response status
+    statuses: BTreeMap<u16, u64>,
+    /// Response body bytes sent
+    bytes: u64,
+    /// Total time from receiving the request until the body was sent
+    seconds: f64,
+}
+
+/**
+    A request that has been answered, but whose body may still be sending.
+*/
+#[derive(Debug)]
+struct Entry {
+    method: String,
+    path: String,
+    route: String,
+    client: String,
+    status: u16,
+    started: Instant,
+    /// Time until the response headers were ready
+    latency: Duration,
+}
+
+/**
+    Access log and per-route request metrics for the HTTP server.
+
+    Every request is counted in the metrics, but only every Nth request
+    is logged (server errors are always logged), to keep segment requests
+    of busy channels from drowning out everything else.
+*/
+#[derive(Debug, Default)]
+pub struct AccessLog {
+    /// Log every Nth request, or none if zero
+    sample: u64,
+    /// Take client addresses from `X-Forwarded-For`, set by a trusted reverse proxy
+    trust_forwarded: bool,
+    seen: AtomicU64,
+    routes: Mutex<BTreeMap<String, RouteStats>>,
+}
+
+impl AccessLog {
+    pub fn new(sample: u64, trust_forwarded: bool) -> Self {
+        Self {
+            sample,
+            trust_forwarded,
+            ..Self::default()
+        }
+    }
+
+    fn record(&self, entry: &Entry, bytes: u64) {
+        let duration = entry.started.elapsed();
+
+        {
+            let mut routes = self.routes.lock().unwrap();
+            let stats = routes.entry(entry.route.clone()).or_default();
+            *stats.statuses.entry(entry.status).or_default() += 1;
+            stats.bytes += bytes;
+            stats.seconds += duration.as_secs_f64();
+        }
+
+        if self.sample == 0 {
+            return;
+        }
+        let nth = self.seen.fetch_add(1, Ordering::Relaxed);
+        if entry.status >= 500 || nth.is_multiple_of(self.sample) {
+            println!(
+                "[access] method={} path={} status={} latency_ms={:.1} duration_ms={:.1} bytes={} client={}",
+                entry.method,
+                entry.path,
+                entry.status,
+                entry.latency.as_secs_f64() * 1000.0,
+                duration.as_secs_f64() * 1000.0,
+                bytes,
+                entry.client,
+            );
+        }
+    }
+
+    /**
+        Render the request metrics in the Prometheus text format.
+    */
+    pub fn render_metrics(&self) -> String {
+        let routes = self.routes.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP vidproxy_http_requests_total HTTP requests by route and status\n");
+        out.push_str("# TYPE vidproxy_http_requests_total counter\n");
+        for (route, stats) in routes.iter() {
+            for (status, count) in &stats.statuses {
+                let _ = writeln!(
+                    out,
+                    "vidproxy_http_requests_total{{route=\"{}\",status=\"{}\"}} {}",
+                    escape_label(route),
+                    status,
+                    count
+                );
+            }
+        }
+
+        out.push_str(
+            "# HELP vidproxy_http_response_bytes_total Response body bytes sent by route\n",
+        );
+        out.push_str("# TYPE vidproxy_http_response_bytes_total counter\n");
+        for (route, stats) in routes.iter() {
+            let _ = writeln!(
+                out,
+                "vidproxy_http_response_bytes_total{{route=\"{}\"}} {}",
+                escape_label(route),
+                stats.bytes
+            );
+        }
+
+        out.push_str(
+            "# HELP vidproxy_http_request_duration_seconds_total Time spent serving requests by route\n",
+        );
+        out.push_str("# TYPE vidproxy_http_request_duration_seconds_total counter\n");
+        for (route, stats) in routes.iter() {
+            let _ = writeln!(
+                out,
+                "vidproxy_http_request_duration_seconds_total{{route=\"{}\"}} {:.6}",
+                escape_label(route),
+                stats.seconds
+            );
+        }
+
+        out
+    }
+}
+
+pub fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/**
+    Get the address of the client that made a request.
+
+    Any client can send `X-Forwarded-For`, so it is only used when the
+    server is known to sit behind a reverse proxy that sets it, to log
+    the original client rather than the proxy itself.
+*/
+fn client_address(request: &Request, trust_forwarded: bool) -> String {
+    let forwarded = trust_forwarded
+        .then(|| request.headers().get("x-forwarded-for"))
+        .flatten()
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|v| v.trim().to_string());
+
+    forwarded
+        .or_else(|| {
+            request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|info| info.0.ip().to_string())
+        })
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/**
+    Response body that counts the bytes sent through it, and records
+    the request once the body is finished (or the client went away).
+
+    The size hint of the wrapped body is passed on, so responses of a
+    known length keep their `Content-Length` instead of being chunked.
+*/
+struct CountingBody {
+    inner: Body,
+    bytes: u64,
+    entry: Option<Entry>,
+    log: Arc<AccessLog>,
+}
+
+impl HttpBody for CountingBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(ref frame))) = poll
+            && let Some(chunk) = frame.data_ref()
+        {
+            this.bytes += chunk.len() as u64;
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for CountingBody {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            self.log.record(&entry, self.bytes);
+        }
+    }
+}
+
+/**
+    Middleware that feeds every request into the access log.
+*/
+pub async fn track(State(log): State<Arc<AccessLog>>, request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let method = request.method().to_string();
+    // Query strings are left out, they may carry upstream tokens
+    let path = request.uri().path().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let client = client_address(&request, log.trust_forwarded);
+
+    let response = next.run(request).await;
+
+    let entry = Entry {
+        method,
+        path,
+        route,
+        client,
+        status: response.status().as_u16(),
+        started,
+        latency: started.elapsed(),
+    };
+    let (parts, body) = response.into_parts();
+    let body = CountingBody {
+        inner: body,
+        bytes: 0,
+        entry: Some(entry),
+        log,
+    };
+    Response::from_parts(parts, Body::new(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_metrics() {
+        let log = AccessLog::new(0, false);
+        let entry = |status| Entry {
+            method: "GET".to_string(),
+            path: "/src/ch/segment_1.ts".to_string(),
+            route: "/{source_id}/{channel_id}/{filename}".to_string(),
+            client: "127.0.0.1".to_string(),
+            status,
+            started: Instant::now(),
+            latency: Duration::ZERO,
+        };
+        log.record(&entry(200), 1000);
+        log.record(&entry(200), 500);
+        log.record(&entry(404), 0);
+
+        let metrics = log.render_metrics();
+        assert!(metrics.contains(
+            "vidproxy_http_requests_total{route=\"/{source_id}/{channel_id}/{filename}\",status=\"200\"} 2\n"
+        ));
+        assert!(metrics.contains(
+            "vidproxy_http_requests_total{route=\"/{source_id}/{channel_id}/{filename}\",status=\"404\"} 1\n"
+        ));
+        assert!(metrics.contains(
+            "vidproxy_http_response_bytes_total{route=\"/{source_id}/{channel_id}/{filename}\"} 1500\n"
+        ));
+    }
+
+    #[test]
+    fn test_client_address_forwarded_only_when_trusted() {
+        let mut request = Request::builder()
+            .header("x-forwarded-for", "203.0.113.7, 10.0.0.1")
+            .body(Body::empty())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 5000))));
+
+        assert_eq!(client_address(&request, false), "10.0.0.1");
+        assert_eq!(client_address(&request, true), "203.0.113.7");
+    }
+
+    #[test]
+    fn test_counting_body_keeps_size_hint() {
+        let body = CountingBody {
+            inner: Body::from("segment data"),
+            bytes: 0,
+            entry: None,
+            log: Arc::new(AccessLog::new(0, false)),
+        };
+        assert_eq!(body.size_hint().exact(), Some(12));
+        assert_eq!(Body::new(body).size_hint().exact(), Some(12));
+    }
+}
@@ -0,0 +1,429 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::manifest::request_headers;
+use crate::preflight::mpd::{children, mpd_root};
+
+/// Number of ad break events kept per channel
+const MAX_EVENTS: usize = 32;
+
+/// DASH event stream schemes carrying SCTE-35 splice information
+const SCTE35_SCHEMES: &[&str] = &[
+    "urn:scte:scte35:2013:xml",
+    "urn:scte:scte35:2013:bin",
+    "urn:scte:scte35:2014:xml+bin",
+];
+
+/// How often the upstream manifest is checked for new SCTE-35 markers
+const MANIFEST_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Mean luma (0.0 - 1.0) below which a frame counts as black
+const BLACK_LUMA: f32 = 0.08;
+/// RMS level below which audio counts as silent (about -50 dBFS)
+const SILENCE_RMS: f32 = 0.003;
+/// Black and silence must overlap this long (in seconds) to mark a break boundary
+const MIN_BOUNDARY_SECS: f64 = 0.25;
+
+/**
+    How an ad break was detected.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdBreakSource {
+    /// Signaled by an SCTE-35 marker in the upstream manifest
+    Scte35,
+    /// Guessed from black frames coinciding with silence
+    Heuristic,
+}
+
+/**
+    An ad break (or, for heuristics, a likely ad boundary) in a channel's stream.
+*/
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AdBreak {
+    pub id: String,
+    /// Start as a unix timestamp in seconds
+    pub start: f64,
+    /// Duration in seconds, if signaled
+    pub duration: Option<f64>,
+    pub source: AdBreakSource,
+}
+
+impl AdBreak {
+    /**
+        Check if the break is ongoing at the given time. Breaks
+        without a known duration are never considered ongoing.
+    */
+    pub fn is_active(&self, now: f64) -> bool {
+        self.duration
+            .is_some_and(|duration| now >= self.start && now < self.start + duration)
+    }
+}
+
+/**
+    Recent ad breaks of a channel, most recent last.
+*/
+#[derive(Debug, Default)]
+pub struct AdBreakLog {
+    events: VecDeque<AdBreak>,
+}
+
+impl AdBreakLog {
+    /**
+        Record an ad break. Returns false if it was already known.
+    */
+    pub fn record(&mut self, event: AdBreak) -> bool {
+        if self.events.iter().any(|e| e.id == event.id) {
+            return false;
+        }
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+        true
+    }
+
+    /**
+        Get the ad break ongoing at the given time, if any.
+    */
+    pub fn active(&self, now: f64) -> Option<&AdBreak> {
+        self.events.iter().rev().find(|e| e.is_active(now))
+    }
+
+    /**
+        Get all recorded ad breaks, oldest first.
+    */
+    pub fn events(&self) -> Vec<AdBreak> {
+        self.events.iter().cloned().collect()
+    }
+}
+
+/**
+    Parse an ISO 8601 duration as used in MPDs (e.g. "PT1H2M3.5S") into seconds.
+*/
+fn parse_iso_duration(value: &str) -> Option<f64> {
+    let rest = value.trim().strip_prefix('P')?;
+
+    let mut seconds = 0.0;
+    let mut number = String::new();
+    let mut in_time = false;
+    for c in rest.chars() {
+        let unit = match (in_time, c) {
+            (_, '0'..='9' | '.') => {
+                number.push(c);
+                continue;
+            }
+            (false, 'T') if number.is_empty() => {
+                in_time = true;
+                continue;
+            }
+            (false, 'D') => 86400.0,
+            (true, 'H') => 3600.0,
+            (true, 'M') => 60.0,
+            (true, 'S') => 1.0,
+            _ => return None,
+        };
+        seconds += number.parse::<f64>().ok()? * unit;
+        number.clear();
+    }
+    number.is_empty().then_some(seconds)
+}
+
+/**
+    Parse SCTE-35 events from the event streams of a DASH manifest.
+*/
+fn parse_mpd_events(mpd: &str) -> Result<Vec<AdBreak>> {
+    let package =
+        sxd_document::parser::parse(mpd).map_err(|e| anyhow!("Failed to parse MPD: {:?}", e))?;
+    let root = mpd_root(package.as_document())?;
+
+    let availability_start = root
+        .attribute_value("availabilityStartTime")
+        .and_then(|ast| chrono::DateTime::parse_from_rfc3339(ast).ok())
+        .map(|ast| ast.timestamp_millis() as f64 / 1000.0)
+        .unwrap_or(0.0);
+
+    let mut events = Vec::new();
+    for period in children(root, "Period") {
+        let period_start = period
+            .attribute_value("start")
+            .and_then(parse_iso_duration)
+            .unwrap_or(0.0);
+        let period_id = period.attribute_value("id").unwrap_or_default();
+
+        for stream in children(period, "EventStream") {
+            let scheme = stream.attribute_value("schemeIdUri").unwrap_or_default();
+            if !SCTE35_SCHEMES.contains(&scheme) {
+                continue;
+            }
+            let number = |name: &str| {
+                stream
+                    .attribute_value(name)
+                    .and_then(|v| v.parse::<f64>().ok())
+            };
+            let timescale = number("timescale").unwrap_or(1.0).max(1.0);
+            let offset = number("presentationTimeOffset").unwrap_or(0.0);
+
+            for (index, event) in children(stream, "Event").into_iter().enumerate() {
+                let number = |name: &str| {
+                    event
+                        .attribute_value(name)
+                        .and_then(|v| v.parse::<f64>().ok())
+                };
+                let presentation_time = number("presentationTime").unwrap_or(0.0);
+                let id = event
+                    .attribute_value("id")
+                    .map(ToString::to_string)
+                    .unwrap_or_else(|| format!("{}-{}", period_id, index));
+
+                events.push(AdBreak {
+                    id: format!("scte35-{}", id),
+                    start: availability_start
+                        + period_start
+                        + (presentation_time - offset) / timescale,
+                    duration: number("duration").map(|d| d / timescale),
+                    source: AdBreakSource::Scte35,
+                });
+            }
+        }
+    }
+
+    Ok(events)
+}
+
+/**
+    Split the attribute list of an HLS tag, respecting quoted values.
+*/
+fn parse_attributes(list: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    let mut rest = list;
+    while let Some((name, after)) = rest.split_once('=') {
+        let (value, next) = if let Some(quoted) = after.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            let next = quoted[end..].trim_start_matches('"');
+            (&quoted[..end], next)
+        } else {
+            let end = after.find(',').unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+        attributes.insert(name.trim().to_string(), value.to_string());
+        rest = next.trim_start_matches(',');
+    }
+    attributes
+}
+
+/**
+    Parse SCTE-35 splice-out date ranges from an HLS media playlist.
+*/
+fn parse_hls_dateranges(playlist: &str) -> Vec<AdBreak> {
+    playlist
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("#EXT-X-DATERANGE:"))
+        .map(parse_attributes)
+        .filter(|attributes| attributes.contains_key("SCTE35-OUT"))
+        .filter_map(|attributes| {
+            let start = chrono::DateTime::parse_from_rfc3339(attributes.get("START-DATE")?).ok()?;
+            let duration = attributes
+                .get("DURATION")
+                .or_else(|| attributes.get("PLANNED-DURATION"))
+                .and_then(|d| d.parse().ok());
+            Some(AdBreak {
+                id: format!("scte35-{}", attributes.get("ID")?),
+                start: start.timestamp_millis() as f64 / 1000.0,
+                duration,
+                source: AdBreakSource::Scte35,
+            })
+        })
+        .collect()
+}
+
+/**
+    Parse SCTE-35 ad breaks from an upstream manifest (DASH MPD or HLS playlist).
+*/
+pub fn parse_manifest_events(manifest: &str) -> Result<Vec<AdBreak>> {
+    if manifest.trim_start().starts_with("#EXTM3U") {
+        Ok(parse_hls_dateranges(manifest))
+    } else {
+        parse_mpd_events(manifest)
+    }
+}
+
+/**
+    Periodically fetch the upstream manifest and record the SCTE-35 markers
+    in it, until shutdown is signaled. Fetch and parse errors are logged and
+    retried on the next poll, since markers are best effort.
+*/
+pub async fn poll_manifest(
+    client: reqwest::Client,
+    channel_id: String,
+    manifest_url: String,
+    headers: Vec<(String, String)>,
+    log: Arc<Mutex<AdBreakLog>>,
+    mut shutdown_rx: watch::Receiver<bool>,
+) {
+    loop {
+        let mut request = client.get(&manifest_url);
+        for (name, value) in request_headers(&headers, &manifest_url) {
+            request = request.header(name, value);
+        }
+        let manifest = match request.send().await.and_then(|r| r.error_for_status()) {
+            Ok(response) => response.text().await.map_err(anyhow::Error::from),
+            Err(e) => Err(e.into()),
+        };
+
+        match manifest.and_then(|manifest| parse_manifest_events(&manifest)) {
+            Ok(events) => {
+                let mut log = log.lock().unwrap();
+                for event in events {
+                    let (id, start, duration) = (event.id.clone(), event.start, event.duration);
+                    if log.record(event) {
+                        println!(
+                            "[adbreak:{}] SCTE-35 ad break '{}' at {:.1} ({})",
+                            channel_id,
+                            id,
+                            start,
+                            duration
+                                .map(|d| format!("{:.1}s", d))
+                                .unwrap_or_else(|| "open-ended".to_string())
+                        );
+                    }
+                }
+            }
+            Err(e) => eprintln!(
+                "[adbreak:{}] Failed to check manifest for markers: {}",
+                channel_id, e
+            ),
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(MANIFEST_POLL_INTERVAL) => {}
+            _ = shutdown_rx.changed() => {}
+        }
+        if *shutdown_rx.borrow() {
+            break;
+        }
+    }
+}
+
+/**
+    Detects likely ad boundaries from black frames that coincide with silence.
+
+    Times are in stream seconds. Each overlap of black and silence is reported
+    once, at the time it started.
+*/
+#[derive(Debug, Default)]
+pub struct BlackSilenceDetector {
+    black_since: Option<f64>,
+    silent_since: Option<f64>,
+    reported: bool,
+}
+
+impl BlackSilenceDetector {
+    /**
+        Feed the mean luma (0.0 - 1.0) of a video frame.
+    */
+    pub fn video(&mut self, time: f64, mean_luma: f32) -> Option<f64> {
+        if mean_luma < BLACK_LUMA {
+            self.black_since.get_or_insert(time);
+        } else {
+            self.black_since = None;
+            self.reported = false;
+        }
+        self.check(time)
+    }
+
+    /**
+        Feed the RMS level (0.0 - 1.0) of a chunk of audio.
+    */
+    pub fn audio(&mut self, time: f64, rms: f32) -> Option<f64> {
+        if rms < SILENCE_RMS {
+            self.silent_since.get_or_insert(time);
+        } else {
+            self.silent_since = None;
+            self.reported = false;
+        }
+        self.check(time)
+    }
+
+    fn check(&mut self, time: f64) -> Option<f64> {
+        let (Some(black), Some(silent)) = (self.black_since, self.silent_since) else {
+            return None;
+        };
+        let since = black.max(silent);
+        if self.reported || time - since < MIN_BOUNDARY_SECS {
+            return None;
+        }
+        self.reported = true;
+        Some(since)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_iso_duration() {
+        assert_eq!(parse_iso_duration("PT1H2M3.5S"), Some(3723.5));
+        assert_eq!(parse_iso_duration("P1DT1S"), Some(86401.0));
+        assert_eq!(parse_iso_duration("PT0S"), Some(0.0));
+        assert_eq!(parse_iso_duration("1H"), None);
+        assert_eq!(parse_iso_duration("PT5X"), None);
+    }
+
+    #[test]
+    fn test_parse_hls_dateranges() {
+        let playlist = "#EXTM3U\n\
+            #EXT-X-DATERANGE:ID=\"splice-1\",START-DATE=\"2026-01-01T00:00:10Z\",PLANNED-DURATION=30.5,SCTE35-OUT=0xFC30\n\
+            #EXT-X-DATERANGE:ID=\"other\",START-DATE=\"2026-01-01T00:00:10Z\",CLASS=\"com.example\"\n\
+            #EXTINF:4.0,\nseg1.ts\n";
+        let events = parse_manifest_events(playlist).unwrap();
+        assert_eq!(
+            events,
+            vec![AdBreak {
+                id: "scte35-splice-1".to_string(),
+                start: 1767225610.0,
+                duration: Some(30.5),
+                source: AdBreakSource::Scte35,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_ad_break_log() {
+        let mut log = AdBreakLog::default();
+        let event = AdBreak {
+            id: "a".to_string(),
+            start: 100.0,
+            duration: Some(30.0),
+            source: AdBreakSource::Scte35,
+        };
+        assert!(log.record(event.clone()));
+        assert!(!log.record(event));
+        assert!(log.active(110.0).is_some());
+        assert!(log.active(130.0).is_none());
+        assert!(log.active(99.0).is_none());
+    }
+
+    #[test]
+    fn test_black_silence_detector() {
+        let mut detector = BlackSilenceDetector::default();
+        assert_eq!(detector.video(10.0, 0.01), None);
+        assert_eq!(detector.audio(10.1, 0.5), None);
+        assert_eq!(detector.audio(10.2, 0.0), None);
+        assert_eq!(detector.video(10.3, 0.01), None);
+        // Overlap started at 10.2 and has lasted long enough
+        assert_eq!(detector.video(10.5, 0.01), Some(10.2));
+        // Reported once per overlap
+        assert_eq!(detector.audio(10.6, 0.0), None);
+        // A new overlap after picture returns is reported again
+        assert_eq!(detector.video(11.0, 0.5), None);
+        assert_eq!(detector.video(12.0, 0.01), None);
+        assert_eq!(detector.video(12.3, 0.01), Some(12.0));
+    }
+}
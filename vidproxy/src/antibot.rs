@@ -0,0 +1,133 @@
+use std::fmt;
+use std::time::Duration;
+
+use chrome_browser::ChromeBrowserTab;
+
+/**
+    How long to wait for a page to answer the antibot probe
+*/
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/**
+    Elements that only show up on antibot interstitials, and what they belong to
+*/
+const MARKERS: &[(&str, &str)] = &[
+    ("#challenge-form", "Cloudflare challenge"),
+    ("#challenge-running", "Cloudflare challenge"),
+    ("#cf-challenge-running", "Cloudflare challenge"),
+    (
+        "iframe[src*='challenges.cloudflare.com']",
+        "Cloudflare Turnstile",
+    ),
+    (".cf-turnstile", "Cloudflare Turnstile"),
+    (".g-recaptcha", "reCAPTCHA"),
+    ("iframe[src*='/recaptcha/']", "reCAPTCHA"),
+    (".h-captcha", "hCaptcha"),
+    ("iframe[src*='hcaptcha.com']", "hCaptcha"),
+    ("iframe[src*='captcha-delivery.com']", "DataDome"),
+    ("#px-captcha", "PerimeterX"),
+];
+
+/**
+    Page titles of antibot interstitials (lowercase), and what they belong to
+*/
+const TITLES: &[(&str, &str)] = &[
+    ("just a moment...", "Cloudflare challenge"),
+    ("attention required! | cloudflare", "Cloudflare block"),
+];
+
+/**
+    Error returned when a page is showing an antibot interstitial (challenge
+    page or CAPTCHA) instead of its content. These don't go away by retrying,
+    someone has to solve them in the browser, or change the source's proxy.
+*/
+#[derive(Debug, Clone)]
+pub struct AntibotChallenge {
+    /// What kind of interstitial it is, e.g. "Cloudflare challenge"
+    pub kind: &'static str,
+    /// URL of the page showing it
+    pub url: String,
+}
+
+impl fmt::Display for AntibotChallenge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Blocked by {} at {}", self.kind, self.url)
+    }
+}
+
+impl std::error::Error for AntibotChallenge {}
+
+/**
+    Classify a page from its title and the markers found on it.
+*/
+fn classify(title: &str, found: &[String]) -> Option<&'static str> {
+    let title = title.trim().to_lowercase();
+    let by_title = TITLES
+        .iter()
+        .find(|(t, _)| title == *t)
+        .map(|(_, kind)| *kind);
+    let by_marker = MARKERS
+        .iter()
+        .find(|(selector, _)| found.iter().any(|f| f == selector))
+        .map(|(_, kind)| *kind);
+    by_marker.or(by_title)
+}
+
+/**
+    Check whether the page in a tab is an antibot interstitial.
+
+    Pages that don't respond, or can't be inspected, are not reported.
+*/
+pub async fn detect(tab: &ChromeBrowserTab) -> Option<AntibotChallenge> {
+    let selectors: Vec<&str> = MARKERS.iter().map(|(selector, _)| *selector).collect();
+    let script = format!(
+        r#"(() => ({{
+            title: document.title || "",
+            url: location.href,
+            found: {}.filter((selector) => document.querySelector(selector) !== null),
+        }}))()"#,
+        serde_json::to_string(&selectors).ok()?
+    );
+
+    let value = tokio::time::timeout(PROBE_TIMEOUT, tab.eval_json(script, false))
+        .await
+        .ok()?
+        .ok()?;
+
+    let title = value.get("title")?.as_str()?;
+    let url = value.get("url")?.as_str()?.to_string();
+    let found: Vec<String> = value
+        .get("found")?
+        .as_array()?
+        .iter()
+        .filter_map(|v| v.as_str().map(ToString::to_string))
+        .collect();
+
+    classify(title, &found).map(|kind| AntibotChallenge { kind, url })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(
+            classify("Just a moment...", &[]),
+            Some("Cloudflare challenge")
+        );
+        assert_eq!(
+            classify("Sign in", &[".g-recaptcha".to_string()]),
+            Some("reCAPTCHA")
+        );
+        // Markers are more specific than titles
+        assert_eq!(
+            classify("Just a moment...", &[".cf-turnstile".to_string()]),
+            Some("Cloudflare Turnstile")
+        );
+        assert_eq!(classify("Live TV", &[]), None);
+        // Plain denials are ordinary failures, retried with backoff
+        assert_eq!(classify("Access Denied", &[]), None);
+        assert_eq!(classify("Live TV", &["#player".to_string()]), None);
+    }
+}
@@ -1,12 +1,163 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
 use anyhow::{Result, anyhow};
+use drm_widevine::core::{KeyType, PsshBox, SystemId};
 use regex::Regex;
 
+use crate::manifest::request_headers;
+
+/**
+    Widevine device loaded from disk, used instead of the embedded devices if set.
+*/
+static DEVICE: OnceLock<drm_widevine::Device> = OnceLock::new();
+
+/**
+    Named devices from the devices directory, for manifests that need a specific one.
+*/
+static NAMED_DEVICES: OnceLock<BTreeMap<String, CdmDevice>> = OnceLock::new();
+
+/**
+    A CDM device that license requests can be made with.
+*/
+#[derive(Clone)]
+pub enum CdmDevice {
+    Widevine(drm_widevine::Device),
+    PlayReady(drm_playready::Device),
+}
+
+impl CdmDevice {
+    /**
+        Parse a device file, by its extension: .wvd for Widevine, .prd for PlayReady.
+    */
+    fn load(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path).map_err(|e| anyhow!("Failed to read {:?}: {}", path, e))?;
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("wvd") => drm_widevine::Device::from_bytes(&bytes)
+                .map(Self::Widevine)
+                .map_err(|e| anyhow!("Failed to parse Widevine device {:?}: {e}", path)),
+            Some("prd") => drm_playready::Device::from_bytes(&bytes)
+                .map(Self::PlayReady)
+                .map_err(|e| anyhow!("Failed to parse PlayReady device {:?}: {e}", path)),
+            _ => Err(anyhow!("Unknown device file type {:?}", path)),
+        }
+    }
+
+    /**
+        Short description for logs, e.g. "Widevine L3".
+    */
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Widevine(device) => format!("Widevine {}", device.security_level),
+            Self::PlayReady(device) => format!("PlayReady SL{}", device.security_level),
+        }
+    }
+}
+
+/**
+    Load a WVD device file to use for all license requests,
+    returning what the device reports to license servers.
+*/
+pub fn load_device(path: &Path) -> Result<drm_widevine::DeviceCapabilities> {
+    let bytes = std::fs::read(path).map_err(|e| anyhow!("Failed to read {:?}: {}", path, e))?;
+    let device = drm_widevine::Device::from_bytes(&bytes)
+        .map_err(|e| anyhow!("Failed to parse device {:?}: {e}", path))?;
+    let capabilities = device.capabilities();
+    let _ = DEVICE.set(device);
+    Ok(capabilities)
+}
+
+/**
+    Load all WVD and PRD files in a directory as named devices,
+    each named after its file (without the extension).
+*/
+pub fn load_devices_dir(dir: &Path) -> Result<&'static BTreeMap<String, CdmDevice>> {
+    let entries = std::fs::read_dir(dir).map_err(|e| anyhow!("Failed to read {:?}: {}", dir, e))?;
+
+    let mut devices = BTreeMap::new();
+    for entry in entries {
+        let path = entry?.path();
+        let is_device = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("wvd") || e.eq_ignore_ascii_case("prd"));
+        if !is_device {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        if devices.contains_key(name) {
+            return Err(anyhow!(
+                "Device '{}' exists more than once in {:?}",
+                name,
+                dir
+            ));
+        }
+        devices.insert(name.to_string(), CdmDevice::load(&path)?);
+    }
+
+    let _ = NAMED_DEVICES.set(devices);
+    Ok(NAMED_DEVICES.get().unwrap())
+}
+
+/**
+    Check that a named device was loaded, for validating manifests at startup.
+*/
+pub fn check_device(name: &str) -> Result<()> {
+    named_device(name).map(|_| ())
+}
+
+fn named_device(name: &str) -> Result<CdmDevice> {
+    let Some(devices) = NAMED_DEVICES.get() else {
+        return Err(anyhow!(
+            "DRM device '{}' is required, but no devices directory was given (--devices-dir)",
+            name
+        ));
+    };
+    devices.get(name).cloned().ok_or_else(|| {
+        let available: Vec<&str> = devices.keys().map(String::as_str).collect();
+        anyhow!(
+            "DRM device '{}' is not in the devices directory (available: {})",
+            name,
+            if available.is_empty() {
+                "none".to_string()
+            } else {
+                available.join(", ")
+            }
+        )
+    })
+}
+
+/**
+    Get the device to use for a license request - the named device if
+    one is required, then the loaded device if there is one, otherwise
+    a random embedded device.
+*/
+fn device(name: Option<&str>) -> Result<CdmDevice> {
+    if let Some(name) = name {
+        return named_device(name);
+    }
+    Ok(CdmDevice::Widevine(
+        DEVICE
+            .get()
+            .cloned()
+            .unwrap_or_else(drm_widevine::static_devices::random),
+    ))
+}
+
 /**
     Extract PSSH and default_KID from an MPD manifest
 */
 pub fn extract_drm_info_from_mpd(
     mpd_url: &str,
     mpd_content: &str,
+    system: SystemId,
 ) -> Result<(String, Option<String>)> {
     use ffmpeg_source::reader::stream::StreamFormat;
     use ffmpeg_source::reader::stream::dash::DashFormat;
@@ -16,14 +167,24 @@ pub fn extract_drm_info_from_mpd(
 
     let drm_info = dash.drm_info();
 
-    // Get Widevine PSSH first, fall back to any PSSH
-    let pssh = drm_info
-        .widevine_pssh()
-        .into_iter()
-        .next()
-        .map(|p| &p.data_base64)
-        .or_else(|| drm_info.pssh_boxes.first().map(|p| &p.data_base64))
-        .ok_or_else(|| anyhow!("No PSSH found in MPD"))?;
+    let pssh = match system {
+        SystemId::PlayReady => drm_info
+            .pssh_boxes
+            .iter()
+            .map(|p| &p.data_base64)
+            .find(|b64| {
+                PsshBox::from_base64(b64).is_ok_and(|p| p.system_id() == SystemId::PlayReady)
+            })
+            .ok_or_else(|| anyhow!("No PlayReady PSSH found in MPD"))?,
+        // Get Widevine PSSH first, fall back to any PSSH
+        _ => drm_info
+            .widevine_pssh()
+            .into_iter()
+            .next()
+            .map(|p| &p.data_base64)
+            .or_else(|| drm_info.pssh_boxes.first().map(|p| &p.data_base64))
+            .ok_or_else(|| anyhow!("No PSSH found in MPD"))?,
+    };
 
     // Extract default_KID from MPD content using regex
     // Format: cenc:default_KID="xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx"
@@ -49,28 +210,68 @@ fn extract_default_kid_from_mpd(mpd_content: &str) -> Option<String> {
     or an error if it couldn't be enabled (caller should fall back to plaintext).
 */
 async fn try_enable_privacy_mode(
+    client: &reqwest::Client,
     session: &mut drm_widevine::Session,
-    license_url: &str,
+    license: &LicenseServer<'_>,
 ) -> Result<()> {
     let cert_request = drm_widevine::Session::service_certificate_request();
-    let cert_response = license_request(license_url, cert_request).await?;
+    let cert_response = license_request(client, license, cert_request, WIDEVINE_HEADERS).await?;
     session
         .set_service_certificate(&cert_response)
         .map_err(|e| anyhow!("{e}"))?;
     Ok(())
 }
 
+/**
+    Headers of a PlayReady license request, which is a SOAP call.
+*/
+const PLAYREADY_HEADERS: &[(&str, &str)] = &[
+    ("Content-Type", "text/xml; charset=utf-8"),
+    (
+        "SOAPAction",
+        "http://schemas.microsoft.com/DRM/2007/03/protocols/AcquireLicense",
+    ),
+];
+
+/**
+    Headers of a Widevine license request, which is a raw protobuf message.
+*/
+const WIDEVINE_HEADERS: &[(&str, &str)] = &[("Content-Type", "application/octet-stream")];
+
+/**
+    A license server, with the channel's upstream headers resolved for its URL.
+*/
+struct LicenseServer<'a> {
+    url: &'a str,
+    headers: Vec<(String, String)>,
+}
+
 /**
     POST raw bytes to the license server and return the response body.
+
+    Sends the channel's upstream headers along with the DRM system's own,
+    which take precedence. Goes through the source's client, so that its
+    DNS overrides apply.
 */
-async fn license_request(license_url: &str, body: Vec<u8>) -> Result<Vec<u8>> {
-    let client = reqwest::Client::new();
-    let resp = client
-        .post(license_url)
-        .header("Content-Type", "application/octet-stream")
-        .body(body)
-        .send()
-        .await?;
+async fn license_request(
+    client: &reqwest::Client,
+    license: &LicenseServer<'_>,
+    body: Vec<u8>,
+    headers: &[(&str, &str)],
+) -> Result<Vec<u8>> {
+    let mut request = client.post(license.url).body(body);
+    for (name, value) in &license.headers {
+        if !headers
+            .iter()
+            .any(|(own, _)| own.eq_ignore_ascii_case(name))
+        {
+            request = request.header(name, value);
+        }
+    }
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let resp = request.send().await?;
 
     if !resp.status().is_success() {
         return Err(anyhow!("License server error: {}", resp.status()));
@@ -80,71 +281,142 @@ async fn license_request(license_url: &str, body: Vec<u8>) -> Result<Vec<u8>> {
 }
 
 /**
-    Fetch decryption keys by performing local Widevine license acquisition.
-
-    Fetches the server's service certificate first (for privacy mode), then
-    builds a license challenge using a random embedded CDM device, POSTs it to the
-    license server, and extracts content keys from the response.
+    Fetch decryption keys by performing local license acquisition with a device.
+    The channel's upstream headers are sent with every license request.
 
     Returns all content keys in "kid:key" hex format.
 */
-pub async fn fetch_decryption_keys(pssh_b64: &str, license_url: &str) -> Result<Vec<String>> {
+pub async fn fetch_decryption_keys(
+    client: &reqwest::Client,
+    pssh_b64: &str,
+    license_url: &str,
+    headers: &[(String, String)],
+    device: CdmDevice,
+) -> Result<Vec<String>> {
     println!("[cdrm] Performing local license acquisition...");
 
-    let pssh = drm_widevine::core::PsshBox::from_base64(pssh_b64)
-        .map_err(|e| anyhow!("Failed to parse PSSH: {e}"))?;
+    let pssh = PsshBox::from_base64(pssh_b64).map_err(|e| anyhow!("Failed to parse PSSH: {e}"))?;
+    let license = LicenseServer {
+        url: license_url,
+        headers: request_headers(headers, license_url),
+    };
+
+    let content_keys = match device {
+        CdmDevice::Widevine(device) => fetch_widevine_keys(client, device, &pssh, &license).await?,
+        CdmDevice::PlayReady(device) => {
+            fetch_playready_keys(client, device, &pssh, &license).await?
+        }
+    };
 
-    let device = drm_widevine::static_devices::random();
+    if content_keys.is_empty() {
+        return Err(anyhow!("No content keys found in license response"));
+    }
+
+    println!("[cdrm] Got {} content key(s)", content_keys.len());
+    Ok(content_keys)
+}
+
+/**
+    Widevine license acquisition.
+
+    Fetches the server's service certificate first (for privacy mode), then
+    builds a license challenge, POSTs it to the license server, and extracts
+    content keys from the response.
+*/
+async fn fetch_widevine_keys(
+    client: &reqwest::Client,
+    device: drm_widevine::Device,
+    pssh: &PsshBox,
+    license: &LicenseServer<'_>,
+) -> Result<Vec<String>> {
     let mut session = drm_widevine::Session::new(device);
 
     // Try to enable privacy mode by fetching the server's service certificate.
     // If the server doesn't support it or the cert fails to parse, fall back
     // to non-privacy mode (plaintext ClientIdentification).
-    match try_enable_privacy_mode(&mut session, license_url).await {
+    match try_enable_privacy_mode(client, &mut session, license).await {
         Ok(()) => println!("[cdrm] Privacy mode enabled"),
         Err(e) => println!("[cdrm] Privacy mode unavailable, using plaintext: {e}"),
     }
 
     // Build and send the license challenge
     let challenge = session
-        .build_license_challenge(&pssh, drm_widevine::LicenseType::Streaming)
+        .build_license_challenge(pssh, drm_widevine::LicenseType::Streaming)
         .map_err(|e| anyhow!("Failed to build license challenge: {e}"))?;
 
-    let response_bytes = license_request(license_url, challenge).await?;
+    let response_bytes = license_request(client, license, challenge, WIDEVINE_HEADERS).await?;
     let keys = session
         .parse_license_response(&response_bytes)
         .map_err(|e| anyhow!("Failed to parse license response: {e}"))?;
 
-    let content_keys: Vec<String> = keys
+    Ok(keys
         .iter()
-        .filter(|k| k.key_type == drm_widevine::core::KeyType::Content)
+        .filter(|k| k.key_type == KeyType::Content)
         .map(|k| format!("{}:{}", k.kid_hex(), k.key_hex()))
-        .collect();
+        .collect())
+}
 
-    if content_keys.is_empty() {
-        return Err(anyhow!("No content keys found in license response"));
-    }
+/**
+    PlayReady license acquisition, with a SOAP challenge built for the device.
+*/
+async fn fetch_playready_keys(
+    client: &reqwest::Client,
+    device: drm_playready::Device,
+    pssh: &PsshBox,
+    license: &LicenseServer<'_>,
+) -> Result<Vec<String>> {
+    let mut session = drm_playready::Session::new(device);
 
-    println!("[cdrm] Got {} content key(s)", content_keys.len());
-    Ok(content_keys)
+    let challenge = session
+        .build_license_challenge(pssh)
+        .map_err(|e| anyhow!("Failed to build license challenge: {e}"))?;
+
+    let response_bytes = license_request(client, license, challenge, PLAYREADY_HEADERS).await?;
+    session
+        .parse_license_response(&response_bytes)
+        .map_err(|e| anyhow!("Failed to parse license response: {e}"))?;
+
+    Ok(session
+        .content_keys()
+        .iter()
+        .map(|k| format!("{}:{}", k.kid_hex(), k.key_hex()))
+        .collect())
 }
 
 /**
-    Fetch MPD content and extract PSSH, then get all decryption keys.
+    Fetch MPD content and extract PSSH, then get all decryption keys
+    with the named device, or the default device if none is named.
+    The channel's upstream headers are resolved for both the MPD
+    and the license requests.
 
     Returns all keys in "kid:key" format.
 */
-pub async fn get_decryption_keys(mpd_url: &str, license_url: &str) -> Result<Vec<String>> {
+pub async fn get_decryption_keys(
+    client: &reqwest::Client,
+    mpd_url: &str,
+    headers: &[(String, String)],
+    license_url: &str,
+    device_name: Option<&str>,
+) -> Result<Vec<String>> {
+    let device = device(device_name)?;
+    let system = match device {
+        CdmDevice::Widevine(_) => SystemId::Widevine,
+        CdmDevice::PlayReady(_) => SystemId::PlayReady,
+    };
+
     println!("[cdrm] Fetching MPD to extract PSSH...");
 
-    let client = reqwest::Client::new();
-    let mpd_content = client.get(mpd_url).send().await?.text().await?;
+    let mut request = client.get(mpd_url);
+    for (name, value) in request_headers(headers, mpd_url) {
+        request = request.header(name, value);
+    }
+    let mpd_content = request.send().await?.text().await?;
 
-    let (pssh, default_kid) = extract_drm_info_from_mpd(mpd_url, &mpd_content)?;
+    let (pssh, default_kid) = extract_drm_info_from_mpd(mpd_url, &mpd_content, system)?;
     println!("[cdrm] Extracted PSSH: {}...", &pssh[..pssh.len().min(30)]);
     if let Some(ref kid) = default_kid {
         println!("[cdrm] MPD default_KID: {}...", &kid[..kid.len().min(8)]);
     }
 
-    fetch_decryption_keys(&pssh, license_url).await
+    fetch_decryption_keys(client, &pssh, license_url, headers, device).await
 }
@@ -0,0 +1,156 @@
+/// Score of a name that equals the search text
+const SCORE_EXACT: u32 = 1000;
+/// Score of a name that starts with the search text
+const SCORE_PREFIX: u32 = 900;
+/// Score of a name with a word that starts with the search text
+const SCORE_WORD_PREFIX: u32 = 800;
+/// Score of a name that contains the search text anywhere
+const SCORE_SUBSTRING: u32 = 700;
+/// Highest score of a name that only contains the search text as a subsequence
+const SCORE_SUBSEQUENCE: u32 = 500;
+
+/**
+    Score how well a channel name matches search text, ignoring case.
+
+    Exact, prefix and substring matches rank first. Otherwise the letters
+    and digits of the search text must appear in order in the name, so that
+    "bbc1" finds "BBC One HD" and "dsc" finds "Discovery", with matches that
+    are spread out scoring lower than tight ones.
+
+    Returns `None` if the name does not match at all.
+*/
+pub fn fuzzy_score(search: &str, name: &str) -> Option<u32> {
+    let search = search.trim().to_lowercase();
+    let name = name.to_lowercase();
+    if search.is_empty() {
+        return Some(0);
+    }
+
+    if name == search {
+        return Some(SCORE_EXACT);
+    }
+    if name.starts_with(&search) {
+        return Some(SCORE_PREFIX);
+    }
+    if let Some(position) = name.find(&search) {
+        let word_start = name[..position].ends_with(|c: char| !c.is_alphanumeric());
+        return Some(if word_start {
+            SCORE_WORD_PREFIX
+        } else {
+            SCORE_SUBSTRING
+        });
+    }
+
+    let mut wanted = search.chars().filter(|c| c.is_alphanumeric()).peekable();
+    wanted.peek()?;
+
+    let mut first = None;
+    let mut last = 0;
+    for (index, c) in name.chars().enumerate() {
+        if wanted.peek() == Some(&c) {
+            wanted.next();
+            first.get_or_insert(index);
+            last = index;
+            if wanted.peek().is_none() {
+                break;
+            }
+        }
+    }
+    if wanted.peek().is_some() {
+        return None;
+    }
+
+    // Penalize the characters skipped between the first and last match
+    let span = (last - first.unwrap_or(0)) as u32;
+    Some(SCORE_SUBSEQUENCE.saturating_sub(span * 10).max(1))
+}
+
+/**
+    Filters for listing channels across all sources.
+
+    Group and source filters must match exactly (ignoring case), while
+    the search text is matched fuzzily against the channel name.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct ChannelFilter {
+    pub search: Option<String>,
+    pub group: Option<String>,
+    pub source: Option<String>,
+}
+
+impl ChannelFilter {
+    /**
+        Score a channel against the filter, or `None` if it is filtered out.
+    */
+    pub fn score(&self, source: &str, group: &str, name: &str) -> Option<u32> {
+        if let Some(wanted) = non_empty(&self.source)
+            && !wanted.eq_ignore_ascii_case(source)
+        {
+            return None;
+        }
+        if let Some(wanted) = non_empty(&self.group)
+            && wanted.to_lowercase() != group.to_lowercase()
+        {
+            return None;
+        }
+        match non_empty(&self.search) {
+            Some(search) => fuzzy_score(search, name),
+            None => Some(0),
+        }
+    }
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_score_ranking() {
+        let exact = fuzzy_score("bbc one", "BBC One").unwrap();
+        let prefix = fuzzy_score("bbc", "BBC One").unwrap();
+        let word = fuzzy_score("one", "BBC One").unwrap();
+        let substring = fuzzy_score("ne", "BBC One").unwrap();
+        let subsequence = fuzzy_score("bbc1", "BBC One HD 1").unwrap();
+        assert!(exact > prefix);
+        assert!(prefix > word);
+        assert!(word > substring);
+        assert!(substring > subsequence);
+        assert!(subsequence > 0);
+    }
+
+    #[test]
+    fn test_fuzzy_score_subsequence() {
+        assert!(fuzzy_score("dsc", "Discovery").is_some());
+        assert!(fuzzy_score("bbc-news", "BBC World News").is_some());
+        // Tight matches beat spread out ones
+        assert!(fuzzy_score("nws", "News 24") > fuzzy_score("nws", "National Weather Service"));
+        // Letters must appear in order
+        assert_eq!(fuzzy_score("csd", "Discovery"), None);
+        assert_eq!(fuzzy_score("xyz", "Discovery"), None);
+        assert_eq!(fuzzy_score("--", "Discovery"), None);
+    }
+
+    #[test]
+    fn test_channel_filter() {
+        let filter = ChannelFilter {
+            search: Some("news".to_string()),
+            group: Some("news".to_string()),
+            source: Some("tv".to_string()),
+        };
+        assert!(filter.score("tv", "News", "World News").is_some());
+        assert_eq!(filter.score("radio", "News", "World News"), None);
+        assert_eq!(filter.score("tv", "Sports", "World News"), None);
+        assert_eq!(filter.score("tv", "News", "Weather"), None);
+
+        // Empty filters match everything
+        let filter = ChannelFilter {
+            search: Some(" ".to_string()),
+            ..ChannelFilter::default()
+        };
+        assert_eq!(filter.score("tv", "Sports", "Weather"), Some(0));
+    }
+}
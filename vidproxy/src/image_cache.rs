@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::hash::{DefaultHasher, Hash, Hasher};
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use anyhow::{Result, anyhow};
 use tokio::sync::RwLock;
@@ -14,6 +15,7 @@ use crate::registry::ChannelId;
 pub struct CachedImage {
     pub data: Arc<Vec<u8>>,
     pub content_type: String,
+    pub fetched_at: SystemTime,
 }
 
 /**
@@ -197,6 +199,7 @@ async fn fetch_image(url: &str, proxy: Option<&str>) -> Result<CachedImage> {
     Ok(CachedImage {
         data: Arc::new(data.to_vec()),
         content_type,
+        fetched_at: SystemTime::now(),
     })
 }
 
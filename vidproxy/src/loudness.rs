@@ -0,0 +1,393 @@
+/*!
+    EBU R128 / ITU-R BS.1770 loudness measurement and normalization
+    for interleaved f32 audio at 48 kHz.
+*/
+
+use std::collections::VecDeque;
+
+/// Sample rate the K-weighting filter coefficients are designed for
+pub const SAMPLE_RATE: u32 = 48_000;
+
+/// Gating sub-block length (100 ms), 400 ms blocks overlap by 75%
+const SUB_BLOCK_SAMPLES: usize = SAMPLE_RATE as usize / 10;
+const MOMENTARY_SUB_BLOCKS: usize = 4;
+const SHORT_TERM_SUB_BLOCKS: usize = 30;
+
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// Never boost quiet passages by more than this, so silence stays silent
+const MAX_GAIN_DB: f64 = 12.0;
+const MIN_GAIN_DB: f64 = -24.0;
+
+/// Time constant of the dynamic gain, in sub-blocks
+const GAIN_SMOOTHING_SUB_BLOCKS: f64 = 30.0;
+
+/// Peak ceiling of the output (-1 dBFS)
+const PEAK_CEILING: f32 = 0.891;
+/// Per-sample recovery of the peak limiter (about 200 ms to recover 12 dB)
+const LIMITER_RELEASE: f32 = 1.000_15;
+
+/**
+    A biquad filter in direct form I.
+*/
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    const fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self {
+            b,
+            a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/**
+    The two-stage K-weighting filter from BS.1770 (high shelf + high pass).
+*/
+#[derive(Debug, Clone, Copy)]
+struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    const fn new() -> Self {
+        Self {
+            shelf: Biquad::new(
+                [
+                    1.535_124_859_586_97,
+                    -2.691_696_189_406_38,
+                    1.198_392_810_852_85,
+                ],
+                [-1.690_659_293_182_41, 0.732_480_774_215_85],
+            ),
+            high_pass: Biquad::new(
+                [1.0, -2.0, 1.0],
+                [-1.990_047_454_833_98, 0.990_072_250_366_21],
+            ),
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        self.high_pass.process(self.shelf.process(x))
+    }
+}
+
+fn power_to_lufs(power: f64) -> f64 {
+    if power <= 0.0 {
+        f64::NEG_INFINITY
+    } else {
+        -0.691 + 10.0 * power.log10()
+    }
+}
+
+fn lufs_to_power(lufs: f64) -> f64 {
+    10f64.powf((lufs + 0.691) / 10.0)
+}
+
+fn db_to_linear(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+/**
+    Measures short-term and integrated loudness.
+*/
+#[derive(Debug, Clone)]
+pub struct LoudnessMeter {
+    channels: usize,
+    filters: Vec<KWeighting>,
+    /// Sum of squares of the current sub-block, per channel
+    sub_block_sums: Vec<f64>,
+    sub_block_len: usize,
+    /// Mean square power of the most recent sub-blocks
+    recent: VecDeque<f64>,
+    /// Power of every 400 ms gating block measured so far
+    blocks: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: usize) -> Self {
+        let channels = channels.max(1);
+        Self {
+            channels,
+            filters: vec![KWeighting::new(); channels],
+            sub_block_sums: vec![0.0; channels],
+            sub_block_len: 0,
+            recent: VecDeque::with_capacity(SHORT_TERM_SUB_BLOCKS),
+            blocks: Vec::new(),
+        }
+    }
+
+    /**
+        Feed interleaved samples. Returns the number of sub-blocks completed.
+    */
+    pub fn push(&mut self, samples: &[f32]) -> usize {
+        let mut completed = 0;
+        for frame in samples.chunks_exact(self.channels) {
+            if self.push_frame(frame) {
+                completed += 1;
+            }
+        }
+        completed
+    }
+
+    /**
+        Feed one frame (one sample per channel). Returns true if it completed a sub-block.
+    */
+    fn push_frame(&mut self, frame: &[f32]) -> bool {
+        for (channel, &sample) in frame.iter().enumerate() {
+            let weighted = self.filters[channel].process(sample as f64);
+            self.sub_block_sums[channel] += weighted * weighted;
+        }
+        self.sub_block_len += 1;
+        if self.sub_block_len < SUB_BLOCK_SAMPLES {
+            return false;
+        }
+
+        // All channels are weighted equally (no surround channels at 48k stereo)
+        let power = self.sub_block_sums.iter().sum::<f64>() / SUB_BLOCK_SAMPLES as f64;
+        self.sub_block_sums.fill(0.0);
+        self.sub_block_len = 0;
+
+        if self.recent.len() == SHORT_TERM_SUB_BLOCKS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(power);
+
+        if self.recent.len() >= MOMENTARY_SUB_BLOCKS {
+            self.blocks.push(self.window_power(MOMENTARY_SUB_BLOCKS));
+        }
+        true
+    }
+
+    fn window_power(&self, sub_blocks: usize) -> f64 {
+        let window = self.recent.iter().rev().take(sub_blocks);
+        window.sum::<f64>() / sub_blocks.min(self.recent.len()).max(1) as f64
+    }
+
+    /**
+        Loudness of the last 3 s (or everything so far, if shorter).
+    */
+    pub fn short_term(&self) -> Option<f64> {
+        (!self.recent.is_empty()).then(|| power_to_lufs(self.window_power(SHORT_TERM_SUB_BLOCKS)))
+    }
+
+    /**
+        Gated integrated loudness of everything measured so far.
+    */
+    pub fn integrated(&self) -> Option<f64> {
+        let absolute_gate = lufs_to_power(ABSOLUTE_GATE_LUFS);
+        let above_absolute: Vec<f64> = self
+            .blocks
+            .iter()
+            .copied()
+            .filter(|&p| p > absolute_gate)
+            .collect();
+        if above_absolute.is_empty() {
+            return None;
+        }
+
+        let mean = above_absolute.iter().sum::<f64>() / above_absolute.len() as f64;
+        let relative_gate = lufs_to_power(power_to_lufs(mean) + RELATIVE_GATE_LU);
+        let gated: Vec<f64> = above_absolute
+            .into_iter()
+            .filter(|&p| p > relative_gate)
+            .collect();
+        if gated.is_empty() {
+            return None;
+        }
+
+        Some(power_to_lufs(
+            gated.iter().sum::<f64>() / gated.len() as f64,
+        ))
+    }
+}
+
+/**
+    Applies gain towards a target loudness, with a peak ceiling.
+
+    In dynamic mode the gain follows the short-term loudness of the input,
+    which suits live streams. In fixed mode a single gain computed from a
+    previously measured integrated loudness is applied (the second pass
+    of two-pass normalization).
+*/
+#[derive(Debug, Clone)]
+pub struct LoudnessNormalizer {
+    target_lufs: f64,
+    channels: usize,
+    meter: LoudnessMeter,
+    adaptive: bool,
+    /// Gain currently being applied, in dB
+    gain_db: f64,
+    /// Gain the dynamic mode is moving towards, in dB
+    desired_gain_db: f64,
+    /// Gain reduction of the peak limiter (linear, <= 1)
+    limiter: f32,
+}
+
+impl LoudnessNormalizer {
+    /**
+        Create a normalizer that follows the input's short-term loudness.
+    */
+    pub fn dynamic(target_lufs: f64, channels: usize) -> Self {
+        Self {
+            target_lufs,
+            channels: channels.max(1),
+            meter: LoudnessMeter::new(channels),
+            adaptive: true,
+            gain_db: 0.0,
+            desired_gain_db: 0.0,
+            limiter: 1.0,
+        }
+    }
+
+    /**
+        Create a normalizer that applies a fixed gain, given the
+        integrated loudness of the whole input from a first pass.
+    */
+    pub fn fixed(target_lufs: f64, measured_lufs: f64, channels: usize) -> Self {
+        let gain_db = (target_lufs - measured_lufs).clamp(MIN_GAIN_DB, MAX_GAIN_DB);
+        Self {
+            adaptive: false,
+            gain_db,
+            desired_gain_db: gain_db,
+            ..Self::dynamic(target_lufs, channels)
+        }
+    }
+
+    /**
+        Current gain in dB, excluding peak limiting.
+    */
+    pub fn gain_db(&self) -> f64 {
+        self.gain_db
+    }
+
+    /**
+        Normalize interleaved samples in place.
+    */
+    pub fn process(&mut self, samples: &mut [f32]) {
+        // Spread each gain update over one sub-block to avoid zipper noise
+        let step = (self.desired_gain_db - self.gain_db) / SUB_BLOCK_SAMPLES as f64;
+        let mut gain_step = step;
+
+        for frame in samples.chunks_exact_mut(self.channels) {
+            if self.adaptive && self.meter.push_frame(frame) {
+                self.update_desired_gain();
+                gain_step = (self.desired_gain_db - self.gain_db) / SUB_BLOCK_SAMPLES as f64;
+            }
+            if self.adaptive {
+                self.gain_db += gain_step;
+            }
+
+            let gain = db_to_linear(self.gain_db) as f32;
+            let peak = frame.iter().fold(0f32, |peak, s| peak.max(s.abs())) * gain;
+
+            // Instant attack, slow release
+            self.limiter = (self.limiter * LIMITER_RELEASE).min(1.0);
+            if peak * self.limiter > PEAK_CEILING {
+                self.limiter = PEAK_CEILING / peak;
+            }
+
+            let total = gain * self.limiter;
+            for sample in frame.iter_mut() {
+                *sample = (*sample * total).clamp(-1.0, 1.0);
+            }
+        }
+    }
+
+    fn update_desired_gain(&mut self) {
+        // Hold the current gain through silence and pauses instead of boosting noise
+        let Some(loudness) = self.meter.short_term().filter(|&l| l > ABSOLUTE_GATE_LUFS) else {
+            return;
+        };
+        let wanted = (self.target_lufs - loudness).clamp(MIN_GAIN_DB, MAX_GAIN_DB);
+        self.desired_gain_db += (wanted - self.desired_gain_db) / GAIN_SMOOTHING_SUB_BLOCKS;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(amplitude: f32, frequency: f32, seconds: f32) -> Vec<f32> {
+        let frames = (SAMPLE_RATE as f32 * seconds) as usize;
+        (0..frames)
+            .flat_map(|i| {
+                let s = amplitude
+                    * (2.0 * std::f32::consts::PI * frequency * i as f32 / SAMPLE_RATE as f32)
+                        .sin();
+                [s, s]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_reference_tone_loudness() {
+        // A 1 kHz stereo sine at -18 dBFS per channel measures close to -18 LUFS
+        // (the K-weighting adds ~0.7 dB at 1 kHz, which the -0.691 offset cancels,
+        // and two channels add 3 dB over the -3 dB mean square of a sine)
+        let amplitude = db_to_linear(-18.0) as f32;
+        let mut meter = LoudnessMeter::new(2);
+        meter.push(&sine(amplitude, 1000.0, 5.0));
+
+        let integrated = meter.integrated().unwrap();
+        assert!((integrated - -18.0).abs() < 0.2, "got {}", integrated);
+        let short_term = meter.short_term().unwrap();
+        assert!((short_term - integrated).abs() < 0.2);
+    }
+
+    #[test]
+    fn test_silence_is_gated() {
+        let mut meter = LoudnessMeter::new(2);
+        meter.push(&vec![0.0; SAMPLE_RATE as usize * 2]);
+        assert_eq!(meter.integrated(), None);
+    }
+
+    #[test]
+    fn test_dynamic_normalizer_converges() {
+        let amplitude = db_to_linear(-30.0) as f32;
+        let mut samples = sine(amplitude, 1000.0, 30.0);
+        let mut normalizer = LoudnessNormalizer::dynamic(-23.0, 2);
+        normalizer.process(&mut samples);
+
+        assert!(
+            (normalizer.gain_db() - 7.0).abs() < 0.5,
+            "got {}",
+            normalizer.gain_db()
+        );
+
+        let mut meter = LoudnessMeter::new(2);
+        meter.push(&samples[samples.len() - SAMPLE_RATE as usize * 4..]);
+        let output = meter.integrated().unwrap();
+        assert!((output - -23.0).abs() < 0.5, "got {}", output);
+    }
+
+    #[test]
+    fn test_fixed_normalizer_respects_peak_ceiling() {
+        let mut samples = sine(0.5, 1000.0, 1.0);
+        let mut normalizer = LoudnessNormalizer::fixed(-5.0, -20.0, 2);
+        assert_eq!(normalizer.gain_db(), MAX_GAIN_DB);
+
+        normalizer.process(&mut samples);
+        let peak = samples.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak <= PEAK_CEILING + f32::EPSILON);
+    }
+}
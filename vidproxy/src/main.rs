@@ -1,25 +1,52 @@
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
 use clap::Parser;
 use tokio::{signal, sync::watch};
 
+mod access_log;
+mod adbreak;
+mod antibot;
 mod cdrm;
+mod channel_search;
 mod image_cache;
+mod loudness;
 mod manifest;
+mod network;
+mod overlay;
+mod passthrough;
 mod pipeline;
+mod preferences;
+mod preflight;
+mod prewarm;
+mod profile;
 mod proxy;
+mod quality;
 mod registry;
+mod scheduler;
+mod segment_check;
 mod segments;
 mod server;
+mod shutdown;
+mod slate;
 mod source;
 mod time;
+mod transcode;
+mod watchdog;
 
 use image_cache::ImageCache;
 use pipeline::{PipelineConfig, PipelineStore};
-use registry::ChannelRegistry;
-use server::ManifestStore;
+use preferences::ChannelPreferences;
+use prewarm::PrewarmPolicy;
+use profile::OutputProfiles;
+use registry::{ChannelId, ChannelRegistry};
+use scheduler::DiscoveryScheduler;
+use server::{ManifestStore, ServerOptions};
+use shutdown::Teardown;
+use source::BrowserOptions;
+use watchdog::{Incidents, MemoryMonitor};
 
 #[derive(Parser, Debug)]
 #[command(name = "vidproxy")]
@@ -41,6 +68,11 @@ struct Args {
     #[arg(short = 'd', long, default_value = "4")]
     segment_duration: u64,
 
+    /// Load named output profiles from this YAML file, selectable per channel
+    /// (with a `profile` transform) or per request (with `?profile=name`)
+    #[arg(long, env = "VIDPROXY_PROFILES_FILE")]
+    profiles_file: Option<PathBuf>,
+
     /// Idle timeout in seconds (stop pipeline after no activity)
     #[arg(long, default_value = "30")]
     idle_timeout: u64,
@@ -48,6 +80,123 @@ struct Args {
     /// Startup timeout in seconds (max wait for first segment)
     #[arg(long, default_value = "30")]
     startup_timeout: u64,
+
+    /// Channels to pre-warm at startup, as comma-separated "source:id" pairs
+    #[arg(long, value_delimiter = ',')]
+    channels: Vec<String>,
+
+    /// Also pre-warm this many of the most watched channels at startup
+    /// (requires a cache file, where watch history is kept)
+    #[arg(long, default_value = "0")]
+    prewarm_top: usize,
+
+    /// Refresh the credentials of this many of the most watched channels
+    /// before they expire, so tuning to them stays fast
+    #[arg(long, default_value = "0")]
+    refresh_top: usize,
+
+    /// Weight of how often a channel was tuned to, when ranking the most watched channels
+    #[arg(long, default_value = "1.0")]
+    prewarm_frequency_weight: f64,
+
+    /// Weight of how recently a channel was watched, when ranking the most watched channels
+    #[arg(long, default_value = "1.0")]
+    prewarm_recency_weight: f64,
+
+    /// Hours after which the recency of a watched channel counts half as much
+    #[arg(long, default_value = "24")]
+    prewarm_recency_hours: u64,
+
+    /// Persist discovered channels to this file and restore them on startup
+    #[arg(long, env = "VIDPROXY_CACHE_FILE")]
+    cache_file: Option<PathBuf>,
+
+    /// Persist favorite and hidden channels to this file
+    /// (defaults to a file next to the cache file, if any)
+    #[arg(long, env = "VIDPROXY_PREFERENCES_FILE")]
+    preferences_file: Option<PathBuf>,
+
+    /// Disable caching of manifest step responses
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Keep the browser window of a source that hits a CAPTCHA or antibot
+    /// challenge open, so it can be solved by hand (sources that aren't headless)
+    #[arg(long)]
+    keep_challenge_window: bool,
+
+    /// Load additional source manifests from this directory (overriding embedded ones)
+    #[arg(long, env = "VIDPROXY_MANIFESTS_DIR")]
+    manifests_dir: Option<PathBuf>,
+
+    /// Write channel segments to this directory instead of a temporary one
+    #[arg(long, env = "VIDPROXY_SEGMENTS_DIR")]
+    segments_dir: Option<PathBuf>,
+
+    /// Widevine device (.wvd) to use instead of the embedded devices
+    #[arg(long, env = "VIDPROXY_DEVICE")]
+    device: Option<PathBuf>,
+
+    /// Directory of named Widevine (.wvd) and PlayReady (.prd) devices,
+    /// for sources that require a specific device
+    #[arg(long, env = "VIDPROXY_DEVICES_DIR")]
+    devices_dir: Option<PathBuf>,
+
+    /// Disable the browser sandbox (required when running as root, e.g. in containers)
+    #[arg(long, env = "VIDPROXY_NO_BROWSER_SANDBOX")]
+    no_browser_sandbox: bool,
+
+    /// Run all sources in headless mode, regardless of their manifests
+    #[arg(long, env = "VIDPROXY_HEADLESS")]
+    headless: bool,
+
+    /// Respond with errors instead of a "temporarily unavailable" slate when a channel is down
+    #[arg(long)]
+    no_slate: bool,
+
+    /// Maximum number of sources to run discovery for at the same time
+    #[arg(long, default_value = "2")]
+    discovery_concurrency: usize,
+
+    /// Origins allowed to use the server from browsers, comma-separated ("*" for any)
+    #[arg(long, env = "VIDPROXY_CORS_ORIGINS", value_delimiter = ',')]
+    #[arg(value_parser = parse_cors_origin)]
+    cors_origins: Vec<String>,
+
+    /// Allow cross-origin requests to include credentials (cookies, authorization)
+    #[arg(long, env = "VIDPROXY_CORS_CREDENTIALS")]
+    cors_credentials: bool,
+
+    /// Log every Nth HTTP request (server errors are always logged), 0 to disable
+    #[arg(long, env = "VIDPROXY_ACCESS_LOG_SAMPLE", default_value = "0")]
+    access_log_sample: u64,
+
+    /// Log the client address from X-Forwarded-For, when running behind a
+    /// reverse proxy that sets it (any client could send it otherwise)
+    #[arg(long, env = "VIDPROXY_TRUST_PROXY")]
+    trust_proxy: bool,
+
+    /// Supervise discovery and pipelines, restarting them when they panic,
+    /// with incidents listed at /api/incidents
+    #[arg(long, env = "VIDPROXY_WATCHDOG")]
+    watchdog: bool,
+
+    /// With the watchdog, stop all pipelines when the process uses more memory than this
+    #[arg(long, env = "VIDPROXY_MEMORY_LIMIT_MB", requires = "watchdog")]
+    memory_limit_mb: Option<u64>,
+
+    /// Seconds to let pipelines flush and the server finish on shutdown before aborting them
+    #[arg(long, default_value = "10")]
+    shutdown_grace: u64,
+}
+
+fn parse_cors_origin(origin: &str) -> Result<String, String> {
+    let origin = origin.trim().trim_end_matches('/');
+    if origin == "*" || origin.starts_with("http://") || origin.starts_with("https://") {
+        Ok(origin.to_string())
+    } else {
+        Err("expected \"*\" or an origin like https://example.com".to_string())
+    }
 }
 
 #[tokio::main]
@@ -57,21 +206,89 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Handle --list-sources
     if args.list_sources {
         println!("Available sources:");
-        for name in manifest::list_sources()? {
+        for name in manifest::list_sources(args.manifests_dir.as_deref())? {
             println!("  - {}", name);
         }
         return Ok(());
     }
 
+    // Parse channels to pre-warm
+    let mut prewarm = Vec::new();
+    for channel in &args.channels {
+        let id = ChannelId::parse(channel)
+            .ok_or_else(|| format!("Invalid channel '{}', expected 'source:id'", channel))?;
+        prewarm.push(id);
+    }
+
+    // Use a specific CDM device if given
+    if let Some(ref path) = args.device {
+        let caps = cdrm::load_device(path)?;
+        println!(
+            "Using Widevine device: {} ({}, system id {}, HDCP {})",
+            path.display(),
+            caps.security_level,
+            caps.system_id
+                .map_or_else(|| "unknown".to_string(), |id| id.to_string()),
+            caps.max_hdcp_version
+        );
+    }
+    if let Some(ref dir) = args.devices_dir {
+        let devices = cdrm::load_devices_dir(dir)?;
+        println!(
+            "Loaded {} DRM device(s) from {}",
+            devices.len(),
+            dir.display()
+        );
+        for (name, device) in devices {
+            println!("  - {} ({})", name, device.describe());
+        }
+    }
+
+    let browser_options = BrowserOptions {
+        no_sandbox: args.no_browser_sandbox,
+        force_headless: args.headless,
+        keep_challenge_window: args.keep_challenge_window,
+    };
+
+    // Configure step response caching, stored alongside the registry cache file
+    manifest::cache::init(
+        !args.no_cache,
+        args.cache_file
+            .as_ref()
+            .map(|path| path.with_extension("responses")),
+    );
+
     // Create shutdown signal
     let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
     // Create channel registry
     let registry = Arc::new(ChannelRegistry::new());
 
-    // Create temp directory for segments
-    let temp_dir = tempfile::tempdir()?;
-    let base_output_dir = temp_dir.path().to_path_buf();
+    // Use the configured segments directory, or a temp directory removed on exit
+    let (temp_dir, base_output_dir) = match args.segments_dir {
+        Some(ref dir) => {
+            std::fs::create_dir_all(dir)?;
+            (None, dir.clone())
+        }
+        None => {
+            let temp_dir = tempfile::tempdir()?;
+            let path = temp_dir.path().to_path_buf();
+            (Some(temp_dir), path)
+        }
+    };
+
+    // Load output profiles
+    let profiles = match args.profiles_file {
+        Some(ref path) => {
+            let profiles = OutputProfiles::load(path)?;
+            println!(
+                "Loaded output profiles: {}",
+                profiles.names().collect::<Vec<_>>().join(", ")
+            );
+            profiles
+        }
+        None => OutputProfiles::default(),
+    };
 
     // Create pipeline store
     let pipeline_config = PipelineConfig {
@@ -80,8 +297,25 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         idle_timeout: Duration::from_secs(args.idle_timeout),
         startup_timeout: Duration::from_secs(args.startup_timeout),
         base_output_dir,
+        profiles: profiles.clone(),
+        watchdog: args.watchdog,
     };
-    let pipeline_store = Arc::new(PipelineStore::new(pipeline_config, shutdown_rx.clone()));
+    let incidents = Arc::new(Incidents::new());
+    let pipeline_store = Arc::new(PipelineStore::new(
+        pipeline_config,
+        Arc::clone(&incidents),
+        shutdown_rx.clone(),
+    ));
+
+    // Encode the slate for channels that are down in the background, it takes a moment
+    if !args.no_slate {
+        let pipeline_store = Arc::clone(&pipeline_store);
+        let segment_duration = Duration::from_secs(args.segment_duration);
+        tokio::task::spawn_blocking(move || match slate::Slate::generate(segment_duration) {
+            Ok(slate) => pipeline_store.set_slate(slate),
+            Err(e) => eprintln!("Failed to generate slate, serving errors instead: {}", e),
+        });
+    }
 
     // Create manifest store for refresh operations
     let manifest_store = Arc::new(ManifestStore::new());
@@ -89,19 +323,104 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Create image cache for on-demand image fetching
     let image_cache = Arc::new(ImageCache::new());
 
+    // Load favorite and hidden channels
+    let preferences_path = args.preferences_file.clone().or_else(|| {
+        args.cache_file
+            .as_ref()
+            .map(|path| path.with_extension("preferences"))
+    });
+    let preferences = match preferences_path {
+        Some(path) => ChannelPreferences::load(&path).unwrap_or_else(|e| {
+            eprintln!("Failed to load preferences: {}", e);
+            ChannelPreferences::new(Some(path))
+        }),
+        None => ChannelPreferences::new(None),
+    };
+    let preferences = Arc::new(preferences);
+
     // Load source manifests
     println!("Loading sources...");
-    let manifests = manifest::load_all()?;
+    let manifests = manifest::load_all(args.manifests_dir.as_deref())?;
 
     if manifests.is_empty() {
-        eprintln!("No source manifests found in channels/");
+        eprintln!("No source manifests found");
         return Ok(());
     }
 
-    // Mark all sources as loading and store manifests
+    // Fail early if a source needs a device or profile that isn't there, or has invalid
+    // segmenting, not on first playback
+    for manifest in &manifests {
+        if let Some(ref name) = manifest.content.outputs.drm_device {
+            cdrm::check_device(name)
+                .map_err(|e| format!("Source '{}': {}", manifest.source.id, e))?;
+        }
+        let transforms = manifest.process.iter().flat_map(|p| &p.transforms);
+        for transform in transforms {
+            match transform {
+                manifest::Transform::Profile { profile, .. } if profiles.get(profile).is_none() => {
+                    return Err(format!(
+                        "Source '{}': unknown output profile '{}'",
+                        manifest.source.id, profile
+                    )
+                    .into());
+                }
+                manifest::Transform::Segments { config, .. } => {
+                    config
+                        .validate()
+                        .map_err(|e| format!("Source '{}': {}", manifest.source.id, e))?;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Restore previously discovered channels so they can be served right away
+    let mut restored_sources = Vec::new();
+    if let Some(ref path) = args.cache_file
+        && path.exists()
+    {
+        match registry.load(path) {
+            Ok(sources) => {
+                println!(
+                    "Restored {} source(s) from {}",
+                    sources.len(),
+                    path.display()
+                );
+                restored_sources = sources;
+            }
+            Err(e) => eprintln!("Failed to restore cache: {}", e),
+        }
+    }
+
+    // Pre-warm the most watched channels, as far as they were restored
+    let prewarm_policy = PrewarmPolicy {
+        frequency_weight: args.prewarm_frequency_weight,
+        recency_weight: args.prewarm_recency_weight,
+        recency_half_life: Duration::from_secs(args.prewarm_recency_hours * 60 * 60),
+        prewarm_count: args.prewarm_top,
+        refresh_count: args.refresh_top,
+    };
+    if prewarm_policy.prewarm_count > 0 {
+        let top = prewarm_policy.top(
+            registry.list_usage(),
+            time::now(),
+            prewarm_policy.prewarm_count,
+        );
+        for id in top {
+            if !prewarm.contains(&id) {
+                println!("Pre-warming most watched channel {}", id.to_string());
+                prewarm.push(id);
+            }
+        }
+    }
+
+    // Mark all sources as loading (unless restored from cache) and store manifests
     for manifest in &manifests {
         println!("Source: {} ({})", manifest.source.name, manifest.source.id);
-        registry.mark_source_loading(&manifest.source.id);
+        if !restored_sources.contains(&manifest.source.id) {
+            registry.mark_source_loading(&manifest.source.id);
+        }
+        registry.set_source_priority(&manifest.source.id, manifest.source.priority);
         manifest_store.add(manifest.clone()).await;
     }
 
@@ -117,7 +436,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let server_pipeline_store = Arc::clone(&pipeline_store);
     let server_manifest_store = Arc::clone(&manifest_store);
     let server_image_cache = Arc::clone(&image_cache);
+    let server_preferences = Arc::clone(&preferences);
+    let server_incidents = Arc::clone(&incidents);
     let server_shutdown_rx = shutdown_rx.clone();
+    let server_options = ServerOptions {
+        cors_origins: args.cors_origins.clone(),
+        cors_credentials: args.cors_credentials,
+        access_log_sample: args.access_log_sample,
+        trust_proxy: args.trust_proxy,
+        prewarm_policy,
+    };
 
     let server_handle = tokio::spawn(async move {
         if let Err(e) = server::run_server(
@@ -126,6 +454,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             server_pipeline_store,
             server_manifest_store,
             server_image_cache,
+            server_preferences,
+            server_incidents,
+            server_options,
+            prewarm,
             server_shutdown_rx,
         )
         .await
@@ -134,69 +466,63 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
-    // Run discovery tasks sequentially to avoid browser interference
-    // Each source gets its own browser, but running them in parallel can cause issues
-    let discovery_registry = Arc::clone(&registry);
-    let discovery_manifest_store = Arc::clone(&manifest_store);
-    tokio::spawn(async move {
-        for manifest in manifests {
-            println!(
-                "[discovery] Starting source: {} ({})",
-                manifest.source.name, manifest.source.id
+    // Run discovery for all sources, refreshing them as their results expire
+    let start_discovery = {
+        let registry = Arc::clone(&registry);
+        let manifest_store = Arc::clone(&manifest_store);
+        let cache_file = args.cache_file.clone();
+        let concurrency = args.discovery_concurrency;
+        let shutdown_rx = shutdown_rx.clone();
+        move || {
+            let scheduler = DiscoveryScheduler::new(
+                Arc::clone(&registry),
+                Arc::clone(&manifest_store),
+                cache_file.clone(),
+                browser_options.clone(),
+                concurrency,
             );
-
-            // Create browser for this source
-            let browser = match source::create_browser(&manifest).await {
-                Ok(b) => b,
-                Err(e) => {
-                    eprintln!(
-                        "[discovery] Failed to create browser for '{}': {}",
-                        manifest.source.id, e
-                    );
-                    discovery_registry.mark_source_failed(&manifest.source.id, e.to_string());
-                    continue;
-                }
-            };
-
-            // Run discovery with the browser
-            match source::run_source_discovery_only(&manifest, &browser).await {
-                Ok(result) => {
-                    let channel_count = result.channels.len();
-
-                    // Store browser for later content resolution
-                    discovery_manifest_store
-                        .set_browser(&manifest.source.id, browser)
-                        .await;
-
-                    discovery_registry.register_source(
-                        &result.source_id,
-                        result.channels,
-                        result.discovery_expires_at,
-                    );
-                    println!(
-                        "[discovery] Source '{}' ready: {} channels (content on-demand)",
-                        manifest.source.id, channel_count
-                    );
-                }
-                Err(e) => {
-                    eprintln!("[discovery] Source '{}' failed: {}", manifest.source.id, e);
-                    discovery_registry.mark_source_failed(&manifest.source.id, e.to_string());
-                    // Close browser on failure
-                    let _ = browser.close().await;
-                }
-            }
+            scheduler.run(manifests.clone(), shutdown_rx.clone())
         }
-    });
+    };
+    if args.watchdog {
+        println!("Watchdog enabled, panicked subsystems are restarted");
+        tokio::spawn(watchdog::supervise(
+            "discovery",
+            Arc::clone(&incidents),
+            start_discovery,
+            shutdown_rx.clone(),
+        ));
+        if let Some(limit_mb) = args.memory_limit_mb {
+            let monitor = MemoryMonitor::new(limit_mb * 1024 * 1024);
+            tokio::spawn(monitor.run(
+                Arc::clone(&pipeline_store),
+                Arc::clone(&incidents),
+                shutdown_rx.clone(),
+            ));
+        }
+    } else {
+        tokio::spawn(start_discovery());
+    }
 
     // Wait for Ctrl+C
     signal::ctrl_c().await?;
-    println!("\nShutting down...");
-    let _ = shutdown_tx.send(true);
-
-    // Stop all pipelines
-    pipeline_store.stop_all().await;
-
-    let _ = server_handle.await;
+    println!("\nShutting down, press Ctrl+C again to exit right away...");
+
+    let teardown = Teardown {
+        shutdown_tx,
+        pipeline_store,
+        manifest_store,
+        registry,
+        cache_file: args.cache_file.clone(),
+        server_handle,
+    };
+    tokio::select! {
+        _ = teardown.run(Duration::from_secs(args.shutdown_grace)) => {}
+        _ = signal::ctrl_c() => {
+            eprintln!("Exiting without finishing shutdown");
+            std::process::exit(130);
+        }
+    }
 
     // Keep temp_dir alive until here
     drop(temp_dir);
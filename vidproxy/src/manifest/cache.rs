@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::{OnceLock, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::registry::write_private;
+
+/**
+    A cached step response body together with the URL it came from.
+*/
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CachedResponse {
+    pub url: String,
+    pub body: String,
+    pub expires_at: u64,
+}
+
+/**
+    TTL-based cache of step responses, keyed by resolved URL.
+
+    Entries are kept in memory and, if a directory is configured, also
+    written to disk so they survive restarts.
+*/
+struct ResponseCache {
+    enabled: bool,
+    dir: Option<PathBuf>,
+    entries: RwLock<HashMap<String, CachedResponse>>,
+}
+
+static RESPONSE_CACHE: OnceLock<ResponseCache> = OnceLock::new();
+
+/**
+    Configure the response cache. Must be called before any steps run;
+    if never called, responses are cached in memory only.
+*/
+pub fn init(enabled: bool, dir: Option<PathBuf>) {
+    if let Some(ref dir) = dir
+        && let Err(e) = std::fs::create_dir_all(dir)
+    {
+        eprintln!("[cache] Failed to create {:?}: {}", dir, e);
+    }
+
+    let _ = RESPONSE_CACHE.set(ResponseCache {
+        enabled,
+        dir,
+        entries: RwLock::new(HashMap::new()),
+    });
+}
+
+fn cache() -> &'static ResponseCache {
+    RESPONSE_CACHE.get_or_init(|| ResponseCache {
+        enabled: true,
+        dir: None,
+        entries: RwLock::new(HashMap::new()),
+    })
+}
+
+fn file_name(key: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    format!("{:016x}.json", hasher.finish())
+}
+
+/**
+    Get a cached response for a key, if present and not expired.
+*/
+pub fn get(key: &str) -> Option<CachedResponse> {
+    let cache = cache();
+    if !cache.enabled {
+        return None;
+    }
+
+    let now = crate::time::now();
+
+    if let Some(entry) = cache.entries.read().unwrap().get(key)
+        && entry.expires_at > now
+    {
+        return Some(entry.clone());
+    }
+
+    // Fall back to disk, e.g. after a restart
+    let path = cache.dir.as_ref()?.join(file_name(key));
+    let json = std::fs::read(&path).ok()?;
+    let entry: CachedResponse = serde_json::from_slice(&json).ok()?;
+    if entry.expires_at <= now {
+        let _ = std::fs::remove_file(path);
+        return None;
+    }
+
+    cache
+        .entries
+        .write()
+        .unwrap()
+        .insert(key.to_string(), entry.clone());
+    Some(entry)
+}
+
+/**
+    Store a response for a key with the given TTL in seconds.
+*/
+pub fn put(key: &str, url: &str, body: &str, ttl: u64) {
+    let cache = cache();
+    if !cache.enabled || ttl == 0 {
+        return;
+    }
+
+    let entry = CachedResponse {
+        url: url.to_string(),
+        body: body.to_string(),
+        expires_at: crate::time::now() + ttl,
+    };
+
+    // Bodies can hold session tokens, so they are only readable by the current user
+    if let Some(ref dir) = cache.dir {
+        let path = dir.join(file_name(key));
+        match serde_json::to_vec(&entry) {
+            Ok(json) => {
+                if let Err(e) = write_private(&path, &json) {
+                    eprintln!("[cache] Failed to write {:?}: {}", path, e);
+                }
+            }
+            Err(e) => eprintln!("[cache] Failed to serialize response: {}", e),
+        }
+    }
+
+    cache
+        .entries
+        .write()
+        .unwrap()
+        .insert(key.to_string(), entry);
+}
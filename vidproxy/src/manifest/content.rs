@@ -1,21 +1,29 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use chrome_browser::ChromeBrowserTab;
+use drm_widevine::core::KeyString;
+
+use crate::network::Network;
 
 use super::executor::execute_steps;
 use super::interpolate::InterpolationContext;
-use super::types::{ContentPhase, DiscoveredChannel, StreamInfo};
+use super::token::{resolve_token, token_expiry};
+use super::types::{ContentOutputs, ContentPhase, DiscoveredChannel, StreamInfo, StreamToken};
 
 /**
     Execute the content phase for a single channel, returning stream info.
+
+    Starts from the source's session (the outputs of its discovery, as
+    renewed by its refresh phase) if it has one, so that steps can use it.
 */
 pub async fn execute_content(
     phase: &ContentPhase,
     tab: &ChromeBrowserTab,
     channel: &DiscoveredChannel,
-    proxy: Option<&str>,
+    session: Option<&InterpolationContext>,
+    network: &Network,
 ) -> Result<StreamInfo> {
-    // Build initial context with channel fields
-    let mut context = InterpolationContext::new();
+    // Build initial context with the session and channel fields
+    let mut context = session.cloned().unwrap_or_default();
     context.set("channel", "id", channel.id.clone());
     if let Some(name) = &channel.name {
         context.set("channel", "name", name.clone());
@@ -24,7 +32,8 @@ pub async fn execute_content(
         context.set("channel", "image", image.clone());
     }
 
-    let (context, _) = execute_steps(&phase.steps, tab, context, proxy).await?;
+    let (mut context, _) = execute_steps(&phase.steps, tab, context, network).await?;
+    capture_browser_context(tab, &mut context).await;
 
     // Resolve outputs
     let manifest_url = context.interpolate(&phase.outputs.manifest_url)?;
@@ -36,7 +45,15 @@ pub async fn execute_content(
         .map(|t| context.interpolate(t))
         .transpose()?;
 
-    let expires_at = resolve_expiration(&phase.outputs, &context)?;
+    let decryption_keys = resolve_decryption_keys(&phase.outputs, &context)?;
+
+    let token = phase
+        .outputs
+        .token
+        .as_ref()
+        .map(|outputs| resolve_token(outputs, &context, phase.outputs.expires_in))
+        .transpose()?;
+    let expires_at = resolve_expiration(&phase.outputs, &context, token.as_ref(), &manifest_url)?;
     let headers = resolve_headers(&phase.outputs, &context)?;
 
     println!(
@@ -47,17 +64,44 @@ pub async fn execute_content(
     Ok(StreamInfo {
         manifest_url,
         license_url,
+        drm_device: phase.outputs.drm_device.clone(),
+        decryption_keys,
         expires_at,
         headers,
+        token,
     })
 }
 
 /**
-    Resolve expiration from outputs (either expires_at interpolation or expires_in static).
+    Resolve the static decryption keys of the outputs, normalized to hex.
+*/
+fn resolve_decryption_keys(
+    outputs: &ContentOutputs,
+    context: &InterpolationContext,
+) -> Result<Vec<String>> {
+    let Some(templates) = &outputs.decryption_keys else {
+        return Ok(Vec::new());
+    };
+    templates
+        .iter()
+        .map(|template| {
+            let value = context.interpolate(template)?;
+            let key = KeyString::parse(&value)
+                .map_err(|e| anyhow!("Invalid decryption key in content outputs: {}", e))?;
+            Ok(key.to_string())
+        })
+        .collect()
+}
+
+/**
+    Resolve expiration from outputs: expires_at interpolation, then
+    the expiry of the access token, then expires_in static.
 */
 fn resolve_expiration(
     outputs: &super::types::ContentOutputs,
     context: &InterpolationContext,
+    token: Option<&StreamToken>,
+    manifest_url: &str,
 ) -> Result<Option<u64>> {
     // Try expires_at first (interpolated)
     if let Some(expires_at_template) = &outputs.expires_at
@@ -67,6 +111,13 @@ fn resolve_expiration(
         return Ok(Some(expires));
     }
 
+    // Then the expiry embedded in the token (JWT claim or URL parameter)
+    if let Some(token) = token
+        && let Some(expires) = token_expiry(token, manifest_url)
+    {
+        return Ok(Some(expires));
+    }
+
     // Fall back to expires_in (static duration from now)
     if let Some(expires_in) = outputs.expires_in {
         return Ok(Some(crate::time::now() + expires_in));
@@ -75,8 +126,41 @@ fn resolve_expiration(
     Ok(None)
 }
 
+/**
+    Expose the state of the page the steps ended on as `browser.*` variables
+    (cookies, user_agent, url, origin), so that headers can mirror what the
+    browser itself sends.
+
+    Cookies are read from `document.cookie`, so HttpOnly cookies are left
+    out. The browser crate has no call for reading the cookie jar, which
+    would be needed to include them.
+*/
+async fn capture_browser_context(tab: &ChromeBrowserTab, context: &mut InterpolationContext) {
+    let script = r#"({
+        cookies: document.cookie,
+        user_agent: navigator.userAgent,
+        url: location.href,
+        origin: location.origin,
+    })"#;
+
+    match tab.eval_json(script, false).await {
+        Ok(serde_json::Value::Object(values)) => {
+            for (name, value) in values {
+                if let serde_json::Value::String(value) = value {
+                    context.set("browser", &name, value);
+                }
+            }
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("[content] Failed to read browser context: {}", e),
+    }
+}
+
 /**
     Resolve optional headers from content outputs.
+
+    Placeholders referencing `request.*` are kept, and resolved for each
+    upstream request by [`request_headers`].
 */
 fn resolve_headers(
     outputs: &super::types::ContentOutputs,
@@ -88,9 +172,92 @@ fn resolve_headers(
 
     let mut resolved = Vec::with_capacity(headers.len());
     for (key, value) in headers {
-        let value = context.interpolate(value)?;
+        let value = context.interpolate_deferring(value, "request")?;
         resolved.push((key.clone(), value));
     }
 
     Ok(resolved)
 }
+
+/**
+    Resolve the stream's upstream headers for a request to the given URL,
+    filling in `request.url`, `request.host`, `request.origin` and `request.path`.
+
+    Headers that can't be resolved are left out with a warning, since sending
+    a raw template upstream is never what was intended.
+*/
+pub fn request_headers(headers: &[(String, String)], url: &str) -> Vec<(String, String)> {
+    let mut context = InterpolationContext::new();
+    context.set("request", "url", url.to_string());
+    if let Ok(parsed) = reqwest::Url::parse(url) {
+        context.set(
+            "request",
+            "host",
+            parsed.host_str().unwrap_or_default().to_string(),
+        );
+        context.set("request", "origin", parsed.origin().ascii_serialization());
+        context.set("request", "path", parsed.path().to_string());
+    }
+
+    headers
+        .iter()
+        .filter_map(|(name, template)| match context.interpolate(template) {
+            Ok(value) => Some((name.clone(), value)),
+            Err(e) => {
+                eprintln!("[content] Skipping upstream header '{}': {}", name, e);
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_headers() {
+        let headers = vec![
+            ("Referer".to_string(), "${{ request.origin }}/".to_string()),
+            ("X-Host".to_string(), "${{ request.host }}".to_string()),
+            ("Accept".to_string(), "*/*".to_string()),
+            ("X-Bad".to_string(), "${{ request.nope }}".to_string()),
+        ];
+        let resolved = request_headers(&headers, "https://cdn.example.com:8443/live/seg1.m4s");
+        assert_eq!(
+            resolved,
+            vec![
+                (
+                    "Referer".to_string(),
+                    "https://cdn.example.com:8443/".to_string()
+                ),
+                ("X-Host".to_string(), "cdn.example.com".to_string()),
+                ("Accept".to_string(), "*/*".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_decryption_keys() {
+        let outputs: ContentOutputs = serde_json::from_value(serde_json::json!({
+            "manifest_url": "https://example.com/manifest.mpd",
+            "decryption_keys": [
+                "${{ channel.id }}:0F1E2D3C4B5A69788796A5B4C3D2E1F0",
+            ],
+        }))
+        .unwrap();
+        let mut context = InterpolationContext::new();
+        context.set(
+            "channel",
+            "id",
+            "00112233-4455-6677-8899-aabbccddeeff".to_string(),
+        );
+        assert_eq!(
+            resolve_decryption_keys(&outputs, &context).unwrap(),
+            vec!["00112233445566778899aabbccddeeff:0f1e2d3c4b5a69788796a5b4c3d2e1f0"]
+        );
+
+        context.set("channel", "id", "not-a-kid".to_string());
+        assert!(resolve_decryption_keys(&outputs, &context).is_err());
+    }
+}
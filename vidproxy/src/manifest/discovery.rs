@@ -1,6 +1,8 @@
 use anyhow::{Result, anyhow};
 use chrome_browser::ChromeBrowserTab;
 
+use crate::network::Network;
+
 use super::executor::execute_steps;
 use super::interpolate::InterpolationContext;
 use super::types::{DiscoveredChannel, DiscoveryPhase};
@@ -13,6 +15,8 @@ pub struct DiscoveryResult {
     pub channels: Vec<DiscoveredChannel>,
     /// Expiration timestamp (if extracted or specified)
     pub expires_at: Option<u64>,
+    /// Outputs of the discovery steps, for refreshing the session later
+    pub context: InterpolationContext,
 }
 
 /**
@@ -26,11 +30,11 @@ pub async fn execute_discovery(
     phase: &DiscoveryPhase,
     tab: &ChromeBrowserTab,
     source_id: &str,
-    proxy: Option<&str>,
+    network: &Network,
 ) -> Result<DiscoveryResult> {
     let context = InterpolationContext::new();
 
-    let (context, array_result) = execute_steps(&phase.steps, tab, context, proxy).await?;
+    let (context, array_result) = execute_steps(&phase.steps, tab, context, network).await?;
 
     let channels = if let Some((_array_key, items)) = array_result {
         // Multi-channel mode: build channels from the extracted array
@@ -54,6 +58,13 @@ pub async fn execute_discovery(
                 category: None,
                 description: None,
                 source: source_id.to_string(),
+                loudness: None,
+                overlay: None,
+                ad_breaks: None,
+                passthrough: false,
+                standby: false,
+                profile: None,
+                segments: Default::default(),
             });
         }
 
@@ -83,6 +94,13 @@ pub async fn execute_discovery(
             category: None,
             description: None,
             source: source_id.to_string(),
+            loudness: None,
+            overlay: None,
+            ad_breaks: None,
+            passthrough: false,
+            standby: false,
+            profile: None,
+            segments: Default::default(),
         }]
     };
 
@@ -97,33 +115,35 @@ pub async fn execute_discovery(
     );
 
     // Resolve expiration
-    let expires_at = resolve_expiration(&phase.outputs, &context)?;
+    let expires_at = resolve_expiration(
+        phase.outputs.expires_at.as_deref(),
+        phase.outputs.expires_in,
+        &context,
+    );
 
     Ok(DiscoveryResult {
         channels,
         expires_at,
+        context,
     })
 }
 
 /**
     Resolve expiration from outputs (either expires_at interpolation or expires_in static).
 */
-fn resolve_expiration(
-    outputs: &super::types::DiscoveryOutputs,
+pub(super) fn resolve_expiration(
+    expires_at: Option<&str>,
+    expires_in: Option<u64>,
     context: &InterpolationContext,
-) -> Result<Option<u64>> {
+) -> Option<u64> {
     // Try expires_at first (interpolated)
-    if let Some(expires_at_template) = &outputs.expires_at
+    if let Some(expires_at_template) = expires_at
         && let Ok(expires_str) = context.interpolate(expires_at_template)
         && let Ok(expires) = expires_str.parse::<u64>()
     {
-        return Ok(Some(expires));
+        return Some(expires);
     }
 
     // Fall back to expires_in (static duration from now)
-    if let Some(expires_in) = outputs.expires_in {
-        return Ok(Some(crate::time::now() + expires_in));
-    }
-
-    Ok(None)
+    expires_in.map(|expires_in| crate::time::now() + expires_in)
 }
@@ -1,10 +1,15 @@
 use std::collections::HashMap;
 
 use anyhow::{Result, anyhow};
-use chrome_browser::{ChromeBrowserTab, NetworkRequestStream};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrome_browser::{ChromeBrowserTab, NetworkRequest, NetworkRequestStream};
 use regex::Regex;
-use reqwest::{Client, Proxy};
+use reqwest::Client;
 
+use crate::network::Network;
+
+use super::cache;
 use super::extractors::{ExtractedArray, extract, extract_array};
 use super::interpolate::InterpolationContext;
 use super::types::{Extractor, ExtractorKind, Step, StepKind};
@@ -14,6 +19,99 @@ use super::types::{Extractor, ExtractorKind, Step, StepKind};
 */
 const FETCH_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36";
 
+/**
+    Default maximum response body size for Fetch and Sniff steps (16 MiB)
+*/
+const DEFAULT_MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/**
+    Get the maximum response body size for a step.
+*/
+fn max_body_size(step: &Step) -> usize {
+    step.max_body_size.unwrap_or(DEFAULT_MAX_BODY_SIZE)
+}
+
+/**
+    Turn a raw response body into the string that extractors run on.
+
+    Binary steps get the body base64-encoded so that arbitrary bytes survive
+    intact, other steps get it decoded as UTF-8 (lossily, if it isn't valid).
+    Returns `None` if the body exceeds the step's size limit.
+*/
+fn decode_body(step: &Step, bytes: &[u8], url: &str) -> Option<String> {
+    let limit = max_body_size(step);
+    if bytes.len() > limit {
+        println!(
+            "[executor] Discarding {} byte response (limit {}): {}",
+            bytes.len(),
+            limit,
+            &url[..url.len().min(80)]
+        );
+        return None;
+    }
+
+    if step.binary {
+        Some(BASE64.encode(bytes))
+    } else {
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+/**
+    Look up the size of a response the page has received, from its resource timing entry.
+
+    Returns `None` when the page has no entry with a known size, such as for
+    requests made by workers or cross-origin responses without `Timing-Allow-Origin`.
+*/
+async fn sniffed_body_size(tab: &ChromeBrowserTab, url: &str) -> Option<u64> {
+    let script = format!(
+        r#"(() => {{
+            const entries = performance.getEntriesByName({url:?});
+            const entry = entries[entries.length - 1];
+            return entry ? Math.max(entry.decodedBodySize, entry.encodedBodySize) : 0;
+        }})()"#
+    );
+    let size = tab.eval_json(script, false).await.ok()?.as_u64()?;
+    (size > 0).then_some(size)
+}
+
+/**
+    Read a sniffed response body, respecting the step's binary mode and size limit.
+    Returns `None` if the response is unavailable or too large.
+
+    Chrome hands over response bodies whole, so they can't be read in pieces.
+    Instead, the size the page saw is checked first, and bodies known to be
+    too large are skipped without being transferred from the browser at all.
+*/
+async fn read_sniffed_body(
+    step: &Step,
+    tab: &ChromeBrowserTab,
+    request: &NetworkRequest,
+) -> Option<String> {
+    let url = request.url().to_string();
+    let limit = max_body_size(step);
+    if let Some(size) = sniffed_body_size(tab, &url).await
+        && size > limit as u64
+    {
+        println!(
+            "[executor] Skipping {} byte response (limit {}): {}",
+            size,
+            limit,
+            &url[..url.len().min(80)]
+        );
+        return None;
+    }
+
+    let response = request.response().await.ok()?;
+    if step.binary {
+        let bytes = response.bytes().await.ok()?;
+        decode_body(step, &bytes, &url)
+    } else {
+        let text = response.text().await.ok()?;
+        decode_body(step, text.as_bytes(), &url)
+    }
+}
+
 /**
     Interpolate extractor fields that can contain templates.
 */
@@ -46,6 +144,71 @@ fn interpolate_extractor(
     Ok(interpolated)
 }
 
+/**
+    Run a step's extractors on a response body.
+
+    If the step has an array extractor, it alone is run and its items are
+    returned. Otherwise all scalar extractors are run and must succeed.
+*/
+fn extract_response(
+    step: &Step,
+    context: &InterpolationContext,
+    body: &str,
+    url: &str,
+) -> Result<SniffResult> {
+    // Handle array extractor specially
+    for (output_name, extractor) in &step.extract {
+        if is_array_extractor(extractor) {
+            let extractor = interpolate_extractor(extractor, context)?;
+            let items = extract_array(&extractor, body)?;
+            println!(
+                "[executor] Extracted {} items from {}.{}",
+                items.len(),
+                step.name,
+                output_name
+            );
+            return Ok(SniffResult::Array {
+                name: output_name.clone(),
+                items,
+            });
+        }
+    }
+
+    // Run normal extractors
+    let mut extracted = HashMap::new();
+    for (output_name, extractor) in &step.extract {
+        let extractor = interpolate_extractor(extractor, context)?;
+        let value = extract(&extractor, body, url)?;
+        println!("[executor] Extracted {}.{}", step.name, output_name);
+        extracted.insert(output_name.clone(), value);
+    }
+    insert_binary_body(step, &mut extracted, body);
+
+    Ok(SniffResult::Single(extracted))
+}
+
+/**
+    Expose the base64-encoded body of a binary step as its `body` output,
+    unless an extractor already uses that name.
+*/
+fn insert_binary_body(step: &Step, extracted: &mut HashMap<String, String>, body: &str) {
+    if step.binary {
+        extracted
+            .entry("body".to_string())
+            .or_insert_with(|| body.to_string());
+    }
+}
+
+/**
+    Check if an extractor returns an array of objects.
+*/
+fn is_array_extractor(extractor: &Extractor) -> bool {
+    extractor.kind == ExtractorKind::JsonPathArray
+        || extractor.kind == ExtractorKind::RegexArray
+        || extractor.kind == ExtractorKind::XPathArray
+        || extractor.kind == ExtractorKind::CssArray
+}
+
 /**
     Execute a Navigate step.
 */
@@ -97,6 +260,7 @@ pub enum SniffResult {
 */
 pub async fn execute_sniff(
     step: &Step,
+    tab: &ChromeBrowserTab,
     requests: &mut NetworkRequestStream,
     context: &InterpolationContext,
 ) -> Result<SniffResult> {
@@ -114,6 +278,21 @@ pub async fn execute_sniff(
     let url_regex = Regex::new(url_pattern)
         .map_err(|e| anyhow!("Invalid URL regex '{}': {}", url_pattern, e))?;
 
+    // Sniffed URLs aren't known up front, so scope by step and the current context
+    let cache_key = format!(
+        "sniff:{}:{}:{:016x}",
+        step.name,
+        url_pattern,
+        context.fingerprint()
+    );
+    if step.cache.is_some()
+        && let Some(cached) = cache::get(&cache_key)
+        && let Ok(result) = extract_response(step, context, &cached.body, &cached.url)
+    {
+        println!("[executor] Using cached response for: {}", url_pattern);
+        return Ok(result);
+    }
+
     println!(
         "[executor] Waiting for request matching: {} (timeout: {}s)",
         url_pattern, timeout_secs
@@ -122,12 +301,7 @@ pub async fn execute_sniff(
     let deadline = tokio::time::Instant::now() + Duration::from_secs_f64(timeout_secs);
 
     // Check if any extractor is array-capable
-    let has_array_extractor = step.extract.values().any(|e| {
-        e.kind == ExtractorKind::JsonPathArray
-            || e.kind == ExtractorKind::RegexArray
-            || e.kind == ExtractorKind::XPathArray
-            || e.kind == ExtractorKind::CssArray
-    });
+    let has_array_extractor = step.extract.values().any(is_array_extractor);
 
     // Wait for matching request
     loop {
@@ -167,22 +341,16 @@ pub async fn execute_sniff(
 
         println!("[executor] Matched request: {}", &url[..url.len().min(80)]);
 
-        // Get response body
-        let body = if let Ok(response) = request.response().await {
-            response.text().await.unwrap_or_default()
-        } else {
-            String::new()
+        // Get response body, skipping responses that are too large
+        let Some(body) = read_sniffed_body(step, tab, &request).await else {
+            continue;
         };
 
         // Handle array extractor specially
         if has_array_extractor {
             // Find the array extractor
             for (output_name, extractor) in &step.extract {
-                if extractor.kind == ExtractorKind::JsonPathArray
-                    || extractor.kind == ExtractorKind::RegexArray
-                    || extractor.kind == ExtractorKind::XPathArray
-                    || extractor.kind == ExtractorKind::CssArray
-                {
+                if is_array_extractor(extractor) {
                     let extractor = interpolate_extractor(extractor, context)?;
                     match extract_array(&extractor, &body) {
                         Ok(items) => {
@@ -192,6 +360,9 @@ pub async fn execute_sniff(
                                 step.name,
                                 output_name
                             );
+                            if let Some(ttl) = step.cache {
+                                cache::put(&cache_key, &url, &body, ttl);
+                            }
                             return Ok(SniffResult::Array {
                                 name: output_name.clone(),
                                 items,
@@ -228,9 +399,13 @@ pub async fn execute_sniff(
         }
 
         if all_succeeded {
+            insert_binary_body(step, &mut extracted, &body);
             for output_name in extracted.keys() {
                 println!("[executor] Extracted {}.{}", step.name, output_name);
             }
+            if let Some(ttl) = step.cache {
+                cache::put(&cache_key, &url, &body, ttl);
+            }
             return Ok(SniffResult::Single(extracted));
         }
 
@@ -244,6 +419,7 @@ pub async fn execute_sniff(
 */
 pub async fn execute_sniff_many(
     step: &Step,
+    tab: &ChromeBrowserTab,
     requests: &mut NetworkRequestStream,
     context: &InterpolationContext,
 ) -> Result<SniffResult> {
@@ -271,12 +447,7 @@ pub async fn execute_sniff_many(
     let idle_duration = Duration::from_secs_f64(idle_timeout_secs);
 
     // Check if any extractor is array-capable
-    let has_array_extractor = step.extract.values().any(|e| {
-        e.kind == ExtractorKind::JsonPathArray
-            || e.kind == ExtractorKind::RegexArray
-            || e.kind == ExtractorKind::XPathArray
-            || e.kind == ExtractorKind::CssArray
-    });
+    let has_array_extractor = step.extract.values().any(is_array_extractor);
 
     // Collect all matching requests
     let mut all_items: ExtractedArray = Vec::new();
@@ -339,21 +510,15 @@ pub async fn execute_sniff_many(
             &url[..url.len().min(80)]
         );
 
-        // Get response body
-        let body = if let Ok(response) = request.response().await {
-            response.text().await.unwrap_or_default()
-        } else {
+        // Get response body, skipping responses that are too large
+        let Some(body) = read_sniffed_body(step, tab, &request).await else {
             continue;
         };
 
         // Handle array extractor - aggregate items from all responses
         if has_array_extractor {
             for (output_name, extractor) in &step.extract {
-                if extractor.kind == ExtractorKind::JsonPathArray
-                    || extractor.kind == ExtractorKind::RegexArray
-                    || extractor.kind == ExtractorKind::XPathArray
-                    || extractor.kind == ExtractorKind::CssArray
-                {
+                if is_array_extractor(extractor) {
                     if array_extractor_name.is_none() {
                         array_extractor_name = Some(output_name.clone());
                     }
@@ -409,9 +574,18 @@ async fn execute_fetch(
         .ok_or_else(|| anyhow!("Fetch step '{}' requires 'url'", step.name))?;
 
     let url = context.interpolate(url_template)?;
+
+    let cache_key = format!("fetch:{}", url);
+    if step.cache.is_some()
+        && let Some(cached) = cache::get(&cache_key)
+    {
+        println!("[executor] Using cached response for: {}", url);
+        return extract_response(step, context, &cached.body, &url);
+    }
+
     println!("[executor] Fetching: {}", url);
 
-    let response = http_client
+    let mut response = http_client
         .get(&url)
         .header("User-Agent", FETCH_USER_AGENT)
         .send()
@@ -426,55 +600,45 @@ async fn execute_fetch(
         ));
     }
 
-    let body = response
-        .text()
-        .await
-        .map_err(|e| anyhow!("Failed to read response body: {}", e))?;
-
-    println!("[executor] Fetched {} bytes", body.len());
-
-    // Check if any extractor is array-capable
-    let has_array_extractor = step.extract.values().any(|e| {
-        e.kind == ExtractorKind::JsonPathArray
-            || e.kind == ExtractorKind::RegexArray
-            || e.kind == ExtractorKind::XPathArray
-            || e.kind == ExtractorKind::CssArray
-    });
+    // Stream the body so oversized responses are dropped without buffering them
+    let limit = max_body_size(step);
+    if let Some(length) = response.content_length()
+        && length > limit as u64
+    {
+        return Err(anyhow!(
+            "Response for '{}' is too large ({} bytes, limit {})",
+            url,
+            length,
+            limit
+        ));
+    }
 
-    // Handle array extractor specially
-    if has_array_extractor {
-        for (output_name, extractor) in &step.extract {
-            if extractor.kind == ExtractorKind::JsonPathArray
-                || extractor.kind == ExtractorKind::RegexArray
-                || extractor.kind == ExtractorKind::XPathArray
-                || extractor.kind == ExtractorKind::CssArray
-            {
-                let extractor = interpolate_extractor(extractor, context)?;
-                let items = extract_array(&extractor, &body)?;
-                println!(
-                    "[executor] Extracted {} items from {}.{}",
-                    items.len(),
-                    step.name,
-                    output_name
-                );
-                return Ok(SniffResult::Array {
-                    name: output_name.clone(),
-                    items,
-                });
-            }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| anyhow!("Failed to read response body: {}", e))?
+    {
+        if bytes.len() + chunk.len() > limit {
+            return Err(anyhow!(
+                "Response for '{}' exceeds the size limit of {} bytes",
+                url,
+                limit
+            ));
         }
+        bytes.extend_from_slice(&chunk);
     }
 
-    // Run normal extractors
-    let mut extracted = HashMap::new();
-    for (output_name, extractor) in &step.extract {
-        let extractor = interpolate_extractor(extractor, context)?;
-        let value = extract(&extractor, &body, &url)?;
-        println!("[executor] Extracted {}.{}", step.name, output_name);
-        extracted.insert(output_name.clone(), value);
+    println!("[executor] Fetched {} bytes", bytes.len());
+
+    let body = decode_body(step, &bytes, &url)
+        .ok_or_else(|| anyhow!("Response for '{}' is too large", url))?;
+
+    if let Some(ttl) = step.cache {
+        cache::put(&cache_key, &url, &body, ttl);
     }
 
-    Ok(SniffResult::Single(extracted))
+    extract_response(step, context, &body, &url)
 }
 
 /**
@@ -496,48 +660,7 @@ async fn execute_document(
         other => other.to_string(),
     };
 
-    // Check if any extractor is array-capable
-    let has_array_extractor = step.extract.values().any(|e| {
-        e.kind == ExtractorKind::JsonPathArray
-            || e.kind == ExtractorKind::RegexArray
-            || e.kind == ExtractorKind::XPathArray
-            || e.kind == ExtractorKind::CssArray
-    });
-
-    // Handle array extractor specially
-    if has_array_extractor {
-        for (output_name, extractor) in &step.extract {
-            if extractor.kind == ExtractorKind::JsonPathArray
-                || extractor.kind == ExtractorKind::RegexArray
-                || extractor.kind == ExtractorKind::XPathArray
-                || extractor.kind == ExtractorKind::CssArray
-            {
-                let extractor = interpolate_extractor(extractor, context)?;
-                let items = extract_array(&extractor, &body)?;
-                println!(
-                    "[executor] Extracted {} items from {}.{}",
-                    items.len(),
-                    step.name,
-                    output_name
-                );
-                return Ok(SniffResult::Array {
-                    name: output_name.clone(),
-                    items,
-                });
-            }
-        }
-    }
-
-    // Run normal extractors
-    let mut extracted = HashMap::new();
-    for (output_name, extractor) in &step.extract {
-        let extractor = interpolate_extractor(extractor, context)?;
-        let value = extract(&extractor, &body, "")?;
-        println!("[executor] Extracted {}.{}", step.name, output_name);
-        extracted.insert(output_name.clone(), value);
-    }
-
-    Ok(SniffResult::Single(extracted))
+    extract_response(step, context, &body, "")
 }
 
 /**
@@ -572,15 +695,58 @@ async fn execute_fetch_in_browser(
         .ok_or_else(|| anyhow!("FetchInBrowser step '{}' requires 'url'", step.name))?;
 
     let url = context.interpolate(url_template)?;
+
+    let cache_key = format!("fetch_in_browser:{}", url);
+    if step.cache.is_some()
+        && let Some(cached) = cache::get(&cache_key)
+    {
+        println!("[executor] Using cached response for: {}", url);
+        return extract_response(step, context, &cached.body, &cached.url);
+    }
+
     println!("[executor] FetchInBrowser: {}", url);
 
+    // Read the body in pieces in the page, cancelling it once it grows too large
+    let limit = max_body_size(step);
+    let binary = step.binary;
     let script = format!(
         r#"(async () => {{
+            const limit = {limit};
             const tryFetch = async (options) => {{
                 const res = await fetch({url:?}, options);
                 if (!res.ok) throw new Error('HTTP ' + res.status);
-                const body = await res.text();
-                return {{ url: res.url, body }};
+                const length = Number(res.headers.get('content-length'));
+                if (length > limit) {{
+                    res.body?.cancel();
+                    return {{ url: res.url, tooLarge: length }};
+                }}
+                const reader = res.body.getReader();
+                const chunks = [];
+                let size = 0;
+                for (;;) {{
+                    const {{ done, value }} = await reader.read();
+                    if (done) break;
+                    size += value.length;
+                    if (size > limit) {{
+                        reader.cancel();
+                        return {{ url: res.url, tooLarge: size }};
+                    }}
+                    chunks.push(value);
+                }}
+                const bytes = new Uint8Array(size);
+                let offset = 0;
+                for (const chunk of chunks) {{
+                    bytes.set(chunk, offset);
+                    offset += chunk.length;
+                }}
+                if ({binary}) {{
+                    let text = '';
+                    for (let i = 0; i < bytes.length; i += 0x8000) {{
+                        text += String.fromCharCode(...bytes.subarray(i, i + 0x8000));
+                    }}
+                    return {{ url: res.url, body: btoa(text) }};
+                }}
+                return {{ url: res.url, body: new TextDecoder().decode(bytes) }};
             }};
             try {{
                 return await tryFetch({{ credentials: 'include', mode: 'cors' }});
@@ -591,6 +757,14 @@ async fn execute_fetch_in_browser(
     );
 
     let value = tab.eval_json(script, true).await?;
+    if let Some(size) = value.get("tooLarge").and_then(|v| v.as_u64()) {
+        return Err(anyhow!(
+            "Response for '{}' is too large ({} bytes or more, limit {})",
+            url,
+            size,
+            limit
+        ));
+    }
     let (response_url, body) = match value {
         serde_json::Value::Object(mut obj) => {
             let response_url = obj
@@ -609,48 +783,98 @@ async fn execute_fetch_in_browser(
 
     println!("[executor] Browser fetched {} bytes", body.len());
 
-    // Check if any extractor is array-capable
-    let has_array_extractor = step.extract.values().any(|e| {
-        e.kind == ExtractorKind::JsonPathArray
-            || e.kind == ExtractorKind::RegexArray
-            || e.kind == ExtractorKind::XPathArray
-            || e.kind == ExtractorKind::CssArray
-    });
+    if let Some(ttl) = step.cache {
+        cache::put(&cache_key, &response_url, &body, ttl);
+    }
 
-    // Handle array extractor specially
-    if has_array_extractor {
-        for (output_name, extractor) in &step.extract {
-            if extractor.kind == ExtractorKind::JsonPathArray
-                || extractor.kind == ExtractorKind::RegexArray
-                || extractor.kind == ExtractorKind::XPathArray
-                || extractor.kind == ExtractorKind::CssArray
-            {
-                let extractor = interpolate_extractor(extractor, context)?;
-                let items = extract_array(&extractor, &body)?;
-                println!(
-                    "[executor] Extracted {} items from {}.{}",
-                    items.len(),
-                    step.name,
-                    output_name
+    extract_response(step, context, &body, &response_url)
+}
+
+/**
+    Execute a single step, returning its extracted values (if the step kind extracts any).
+*/
+async fn execute_step(
+    step: &Step,
+    tab: &ChromeBrowserTab,
+    context: &InterpolationContext,
+    requests: &mut NetworkRequestStream,
+    http_client: &Client,
+) -> Result<Option<SniffResult>> {
+    match step.kind {
+        StepKind::Navigate => {
+            execute_navigate(step, tab, context).await?;
+            Ok(None)
+        }
+        StepKind::Sniff => execute_sniff(step, tab, requests, context).await.map(Some),
+        StepKind::SniffMany => execute_sniff_many(step, tab, requests, context)
+            .await
+            .map(Some),
+        StepKind::Fetch => execute_fetch(step, context, http_client).await.map(Some),
+        StepKind::FetchInBrowser => execute_fetch_in_browser(step, tab, context).await.map(Some),
+        StepKind::Document => execute_document(step, tab, context).await.map(Some),
+        StepKind::Script => {
+            let _ = execute_script(step, tab, context).await?;
+            Ok(None)
+        }
+    }
+}
+
+/**
+    Execute a step, retrying it up to `retries` additional times on failure.
+*/
+async fn execute_step_with_retries(
+    step: &Step,
+    tab: &ChromeBrowserTab,
+    context: &InterpolationContext,
+    requests: &mut NetworkRequestStream,
+    http_client: &Client,
+) -> Result<Option<SniffResult>> {
+    let attempts = step.retries.unwrap_or(0).saturating_add(1);
+    let delay = std::time::Duration::from_secs_f64(step.retry_delay.unwrap_or(1.0));
+
+    let mut attempt = 1;
+    loop {
+        match execute_step(step, tab, context, requests, http_client).await {
+            Ok(result) => return Ok(result),
+            Err(e) if attempt < attempts => {
+                eprintln!(
+                    "[executor] Step '{}' failed (attempt {}/{}): {}",
+                    step.name, attempt, attempts, e
                 );
-                return Ok(SniffResult::Array {
-                    name: output_name.clone(),
-                    items,
-                });
+                tokio::time::sleep(delay).await;
+                attempt += 1;
             }
+            Err(e) => return Err(e),
         }
     }
+}
 
-    // Run normal extractors
-    let mut extracted = HashMap::new();
-    for (output_name, extractor) in &step.extract {
-        let extractor = interpolate_extractor(extractor, context)?;
-        let value = extract(&extractor, &body, &response_url)?;
-        println!("[executor] Extracted {}.{}", step.name, output_name);
-        extracted.insert(output_name.clone(), value);
+/**
+    Execute a step with its retries, then each of its fallback steps in turn
+    until one succeeds. The caller records outputs under the original step's
+    name, so later steps don't need to know which alternative succeeded.
+*/
+async fn execute_step_with_fallbacks(
+    step: &Step,
+    tab: &ChromeBrowserTab,
+    context: &InterpolationContext,
+    requests: &mut NetworkRequestStream,
+    http_client: &Client,
+) -> Result<Option<SniffResult>> {
+    let mut result = execute_step_with_retries(step, tab, context, requests, http_client).await;
+
+    for fallback in &step.fallback {
+        let Err(e) = &result else {
+            break;
+        };
+        eprintln!(
+            "[executor] Step '{}' failed: {}, trying fallback '{}'",
+            step.name, e, fallback.name
+        );
+        result = execute_step_with_retries(fallback, tab, context, requests, http_client).await;
     }
 
-    Ok(SniffResult::Single(extracted))
+    result
 }
 
 /**
@@ -661,94 +885,33 @@ pub async fn execute_steps(
     steps: &[Step],
     tab: &ChromeBrowserTab,
     initial_context: InterpolationContext,
-    proxy: Option<&str>,
+    network: &Network,
 ) -> Result<(InterpolationContext, Option<(String, ExtractedArray)>)> {
     let mut context = initial_context;
     let mut requests = tab.network().requests();
     let mut array_result: Option<(String, ExtractedArray)> = None;
 
-    // Create HTTP client for Fetch steps with optional proxy
-    let http_client = if let Some(proxy_url) = proxy {
-        let proxy = Proxy::all(proxy_url)
-            .map_err(|e| anyhow!("Invalid proxy URL '{}': {}", proxy_url, e))?;
-        Client::builder()
-            .proxy(proxy)
-            .build()
-            .map_err(|e| anyhow!("Failed to create HTTP client with proxy: {}", e))?
-    } else {
-        Client::new()
-    };
+    // Create HTTP client for Fetch steps with the source's proxy and DNS overrides
+    let http_client = network.http_client()?;
 
     for step in steps {
         println!("[executor] Running step: {}", step.name);
 
-        match step.kind {
-            StepKind::Navigate => {
-                execute_navigate(step, tab, &context).await?;
-            }
-            StepKind::Sniff => {
-                match execute_sniff(step, &mut requests, &context).await? {
-                    SniffResult::Single(values) => {
-                        for (output_name, value) in values {
-                            context.set(&step.name, &output_name, value);
-                        }
-                    }
-                    SniffResult::Array { name, items } => {
-                        // Store array result for later processing
-                        // The step.name and extractor name form the reference
-                        array_result = Some((format!("{}.{}", step.name, name), items));
-                    }
-                }
-            }
-            StepKind::SniffMany => {
-                match execute_sniff_many(step, &mut requests, &context).await? {
-                    SniffResult::Single(values) => {
-                        for (output_name, value) in values {
-                            context.set(&step.name, &output_name, value);
-                        }
-                    }
-                    SniffResult::Array { name, items } => {
-                        // Store array result for later processing
-                        // The step.name and extractor name form the reference
-                        array_result = Some((format!("{}.{}", step.name, name), items));
-                    }
-                }
-            }
-            StepKind::Fetch => match execute_fetch(step, &context, &http_client).await? {
-                SniffResult::Single(values) => {
-                    for (output_name, value) in values {
-                        context.set(&step.name, &output_name, value);
-                    }
-                }
-                SniffResult::Array { name, items } => {
-                    array_result = Some((format!("{}.{}", step.name, name), items));
-                }
-            },
-            StepKind::FetchInBrowser => {
-                match execute_fetch_in_browser(step, tab, &context).await? {
-                    SniffResult::Single(values) => {
-                        for (output_name, value) in values {
-                            context.set(&step.name, &output_name, value);
-                        }
-                    }
-                    SniffResult::Array { name, items } => {
-                        array_result = Some((format!("{}.{}", step.name, name), items));
-                    }
+        let result =
+            execute_step_with_fallbacks(step, tab, &context, &mut requests, &http_client).await?;
+
+        match result {
+            Some(SniffResult::Single(values)) => {
+                for (output_name, value) in values {
+                    context.set(&step.name, &output_name, value);
                 }
             }
-            StepKind::Document => match execute_document(step, tab, &context).await? {
-                SniffResult::Single(values) => {
-                    for (output_name, value) in values {
-                        context.set(&step.name, &output_name, value);
-                    }
-                }
-                SniffResult::Array { name, items } => {
-                    array_result = Some((format!("{}.{}", step.name, name), items));
-                }
-            },
-            StepKind::Script => {
-                let _ = execute_script(step, tab, &context).await?;
+            Some(SniffResult::Array { name, items }) => {
+                // Store array result for later processing
+                // The step.name and extractor name form the reference
+                array_result = Some((format!("{}.{}", step.name, name), items));
             }
+            None => {}
         }
     }
 
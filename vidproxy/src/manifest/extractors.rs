@@ -2,6 +2,7 @@ use std::collections::HashMap;
 use std::sync::OnceLock;
 
 use anyhow::{Result, anyhow};
+use drm_widevine::core::KeyString;
 use regex::Regex;
 use scraper::{ElementRef, Html, Selector};
 use sxd_xpath::nodeset::Node;
@@ -1013,14 +1014,12 @@ fn unescape_html_string(input: &str) -> String {
 }
 
 /**
-    Extract first line containing ":".
+    Extract the first line that is a `kid:key` pair, normalized to lowercase hex.
 */
 fn extract_line(content: &str) -> Result<String> {
-    content
-        .lines()
-        .find(|line| line.contains(':'))
-        .map(|s| s.to_string())
-        .ok_or_else(|| anyhow!("No line containing ':' found"))
+    KeyString::find(content)
+        .map(|key| key.to_string())
+        .ok_or_else(|| anyhow!("No 'kid:key' line found"))
 }
 
 /**
@@ -1075,9 +1074,15 @@ mod tests {
             each: None,
             unescape: false,
         };
-        let content = "some header\nabc123:def456\nmore stuff";
+        let content = "Status: OK\n\
+            00112233-4455-6677-8899-AABBCCDDEEFF:0F1E2D3C4B5A69788796A5B4C3D2E1F0\n\
+            more stuff";
         let result = extract(&extractor, content, "").unwrap();
-        assert_eq!(result, "abc123:def456");
+        assert_eq!(
+            result,
+            "00112233445566778899aabbccddeeff:0f1e2d3c4b5a69788796a5b4c3d2e1f0"
+        );
+        assert!(extract(&extractor, "Status: OK", "").is_err());
     }
 
     #[test]
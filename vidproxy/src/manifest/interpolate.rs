@@ -1,12 +1,43 @@
 use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
 
 use anyhow::{Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use md5::{Digest, Md5};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
 use regex::Regex;
 
+/**
+    A parsed template expression.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    /// Reference to a step output: `step_name.output_name`
+    Variable(String, String),
+    /// Quoted string literal: `'value'` or `"value"`
+    Literal(String),
+    /// Function call: `name(arg, ...)`, `name`, or `arg | name`
+    Call(String, Vec<Expr>),
+}
+
+impl Expr {
+    /**
+        Check if the expression reads any output of the given step.
+    */
+    fn references(&self, step: &str) -> bool {
+        match self {
+            Expr::Variable(step_name, _) => step_name == step,
+            Expr::Literal(_) => false,
+            Expr::Call(_, args) => args.iter().any(|arg| arg.references(step)),
+        }
+    }
+}
+
 /**
     Context for variable interpolation, storing outputs from each step.
 */
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct InterpolationContext {
     /// Map of step_name -> (output_name -> value)
     steps: HashMap<String, HashMap<String, String>>,
@@ -35,29 +66,79 @@ impl InterpolationContext {
     }
 
     /**
-        Interpolate a string, replacing `${{step_name.output_name}}` with values.
+        Stable fingerprint of all values in the context.
+        Used to scope cached responses to the inputs that produced them.
+    */
+    pub fn fingerprint(&self) -> u64 {
+        let mut entries: Vec<_> = self
+            .steps
+            .iter()
+            .flat_map(|(step, outputs)| outputs.iter().map(move |(k, v)| (step, k, v)))
+            .collect();
+        entries.sort();
+
+        let mut hasher = DefaultHasher::new();
+        entries.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /**
+        Interpolate a string, replacing `${{ expression }}` placeholders with values.
+
+        An expression is either a step output reference (`step_name.output_name`),
+        a quoted string literal, or a function call such as `base64(step.value)`
+        or `now_ms()`. Any expression can be piped through filters
+        (`step.value | urlencode`).
+
+        Placeholders that don't parse as an expression, such as a bare `${{ foo }}`,
+        are left untouched.
     */
     pub fn interpolate(&self, template: &str) -> Result<String> {
-        let re = Regex::new(r"\$\{\{([a-zA-Z_][a-zA-Z0-9_]*)\.([a-zA-Z_][a-zA-Z0-9_]*)\}\}")?;
+        self.interpolate_inner(template, None)
+    }
+
+    /**
+        Interpolate a string like [`interpolate`](Self::interpolate), but keep
+        placeholders that reference the `deferred` step as-is, without failing.
+
+        Used for templates with a part that is only known later, such as
+        upstream headers that depend on the URL of each request.
+    */
+    pub fn interpolate_deferring(&self, template: &str, deferred: &str) -> Result<String> {
+        self.interpolate_inner(template, Some(deferred))
+    }
+
+    fn interpolate_inner(&self, template: &str, deferred: Option<&str>) -> Result<String> {
+        let re = Regex::new(r"\$\{\{(.*?)\}\}")?;
 
-        let mut result = template.to_string();
+        let mut result = String::with_capacity(template.len());
+        let mut last_end = 0;
         let mut last_err: Option<anyhow::Error> = None;
 
-        // Find all matches and replace them
         for cap in re.captures_iter(template) {
-            let full_match = cap.get(0).unwrap().as_str();
-            let step_name = &cap[1];
-            let output_name = &cap[2];
+            let full_match = cap.get(0).unwrap();
+            result.push_str(&template[last_end..full_match.start()]);
+            last_end = full_match.end();
 
-            match self.get(step_name, output_name) {
-                Some(value) => {
-                    result = result.replace(full_match, value);
-                }
-                None => {
-                    last_err = Some(anyhow!("Undefined variable: {}.{}", step_name, output_name));
+            let Some(expr) = parse_expression(&cap[1]) else {
+                result.push_str(full_match.as_str());
+                continue;
+            };
+
+            if deferred.is_some_and(|step| expr.references(step)) {
+                result.push_str(full_match.as_str());
+                continue;
+            }
+
+            match self.evaluate(&expr) {
+                Ok(value) => result.push_str(&value),
+                Err(e) => {
+                    result.push_str(full_match.as_str());
+                    last_err = Some(e);
                 }
             }
         }
+        result.push_str(&template[last_end..]);
 
         if let Some(err) = last_err {
             return Err(err);
@@ -66,6 +147,26 @@ impl InterpolationContext {
         Ok(result)
     }
 
+    /**
+        Evaluate a parsed template expression.
+    */
+    fn evaluate(&self, expr: &Expr) -> Result<String> {
+        match expr {
+            Expr::Variable(step_name, output_name) => self
+                .get(step_name, output_name)
+                .cloned()
+                .ok_or_else(|| anyhow!("Undefined variable: {}.{}", step_name, output_name)),
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Call(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.evaluate(arg))
+                    .collect::<Result<Vec<_>>>()?;
+                call_function(name, &args)
+            }
+        }
+    }
+
     /**
         Check if a string contains any interpolation placeholders.
     */
@@ -75,6 +176,169 @@ impl InterpolationContext {
     }
 }
 
+/**
+    Call a template function with already-evaluated arguments.
+*/
+fn call_function(name: &str, args: &[String]) -> Result<String> {
+    let arg = |count: usize| -> Result<&str> {
+        if args.len() != count {
+            return Err(anyhow!(
+                "Function '{}' expects {} argument(s), got {}",
+                name,
+                count,
+                args.len()
+            ));
+        }
+        Ok(args.first().map(String::as_str).unwrap_or_default())
+    };
+
+    match name {
+        "now" => {
+            arg(0)?;
+            Ok(crate::time::now().to_string())
+        }
+        "now_ms" => {
+            arg(0)?;
+            Ok(chrono::Utc::now().timestamp_millis().to_string())
+        }
+        "base64" => Ok(BASE64.encode(arg(1)?)),
+        "base64_decode" => {
+            let bytes = BASE64
+                .decode(arg(1)?)
+                .map_err(|e| anyhow!("Invalid base64 in base64_decode: {}", e))?;
+            String::from_utf8(bytes)
+                .map_err(|e| anyhow!("base64_decode result is not UTF-8: {}", e))
+        }
+        "urlencode" => Ok(utf8_percent_encode(arg(1)?, NON_ALPHANUMERIC).to_string()),
+        "md5" => {
+            let digest = Md5::digest(arg(1)?.as_bytes());
+            Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+        }
+        "lower" => Ok(arg(1)?.to_lowercase()),
+        "upper" => Ok(arg(1)?.to_uppercase()),
+        "trim" => Ok(arg(1)?.trim().to_string()),
+        _ => Err(anyhow!("Unknown function: {}", name)),
+    }
+}
+
+/**
+    Parse the inside of a `${{ ... }}` placeholder into an expression.
+    Returns None if it is not a valid expression.
+*/
+fn parse_expression(input: &str) -> Option<Expr> {
+    let mut parser = ExprParser { input, pos: 0 };
+    let expr = parser.parse_filtered()?;
+    parser.skip_whitespace();
+    if parser.pos == input.len() {
+        Some(expr)
+    } else {
+        None
+    }
+}
+
+/**
+    Small recursive descent parser for template expressions.
+*/
+struct ExprParser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl ExprParser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek()
+            && c.is_whitespace()
+        {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(expected) {
+            self.pos += expected.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_ident(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            let valid = if self.pos == start {
+                c.is_ascii_alphabetic() || c == '_'
+            } else {
+                c.is_ascii_alphanumeric() || c == '_'
+            };
+            if !valid {
+                break;
+            }
+            self.pos += 1;
+        }
+        (self.pos > start).then(|| self.input[start..self.pos].to_string())
+    }
+
+    /**
+        expression ('|' function_name)*
+    */
+    fn parse_filtered(&mut self) -> Option<Expr> {
+        let mut expr = self.parse_term()?;
+        while self.eat('|') {
+            let name = self.parse_ident()?;
+            expr = Expr::Call(name, vec![expr]);
+        }
+        Some(expr)
+    }
+
+    /**
+        literal | step.output | function(args...)
+    */
+    fn parse_term(&mut self) -> Option<Expr> {
+        self.skip_whitespace();
+
+        if let Some(quote @ ('\'' | '"')) = self.peek() {
+            self.pos += 1;
+            let start = self.pos;
+            let len = self.input[start..].find(quote)?;
+            self.pos = start + len + 1;
+            return Some(Expr::Literal(self.input[start..start + len].to_string()));
+        }
+
+        let name = self.parse_ident()?;
+
+        if self.peek() == Some('.') {
+            self.pos += 1;
+            let output = self.parse_ident()?;
+            return Some(Expr::Variable(name, output));
+        }
+
+        // A bare name is not an expression, only `name(...)` is a call
+        if !self.eat('(') {
+            return None;
+        }
+        let mut args = Vec::new();
+        if !self.eat(')') {
+            loop {
+                args.push(self.parse_filtered()?);
+                if self.eat(')') {
+                    break;
+                }
+                if !self.eat(',') {
+                    return None;
+                }
+            }
+        }
+
+        Some(Expr::Call(name, args))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -111,4 +375,95 @@ mod tests {
         let result = ctx.interpolate("plain string").unwrap();
         assert_eq!(result, "plain string");
     }
+
+    #[test]
+    fn test_functions() {
+        let mut ctx = InterpolationContext::new();
+        ctx.set("step", "value", "a b&c".to_string());
+
+        assert_eq!(
+            ctx.interpolate("${{ base64(step.value) }}").unwrap(),
+            "YSBiJmM="
+        );
+        assert_eq!(
+            ctx.interpolate("?q=${{urlencode(step.value)}}").unwrap(),
+            "?q=a%20b%26c"
+        );
+        assert_eq!(
+            ctx.interpolate("${{ md5('hello') }}").unwrap(),
+            "5d41402abc4b2a76b9719d911017c592"
+        );
+        assert_eq!(
+            ctx.interpolate("${{ base64_decode(base64(step.value)) }}")
+                .unwrap(),
+            "a b&c"
+        );
+
+        let now_ms: u64 = ctx.interpolate("${{ now_ms() }}").unwrap().parse().unwrap();
+        assert!(now_ms > 1_000_000_000_000);
+    }
+
+    #[test]
+    fn test_filters() {
+        let mut ctx = InterpolationContext::new();
+        ctx.set("step", "value", " Hello ".to_string());
+
+        assert_eq!(
+            ctx.interpolate("${{ step.value | trim | lower }}").unwrap(),
+            "hello"
+        );
+        assert_eq!(
+            ctx.interpolate("${{ upper(step.value | trim) }}").unwrap(),
+            "HELLO"
+        );
+    }
+
+    #[test]
+    fn test_function_errors() {
+        let ctx = InterpolationContext::new();
+        assert!(ctx.interpolate("${{ nope('x') }}").is_err());
+        assert!(ctx.interpolate("${{ md5('a', 'b') }}").is_err());
+        assert!(ctx.interpolate("${{ base64(missing.value) }}").is_err());
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let mut a = InterpolationContext::new();
+        a.set("channel", "id", "1".to_string());
+        a.set("step", "value", "x".to_string());
+
+        let mut b = InterpolationContext::new();
+        b.set("step", "value", "x".to_string());
+        b.set("channel", "id", "1".to_string());
+        assert_eq!(a.fingerprint(), b.fingerprint());
+
+        b.set("channel", "id", "2".to_string());
+        assert_ne!(a.fingerprint(), b.fingerprint());
+    }
+
+    #[test]
+    fn test_interpolate_deferring() {
+        let mut ctx = InterpolationContext::new();
+        ctx.set("browser", "cookies", "a=1".to_string());
+
+        let template = "${{ browser.cookies }}; ${{ request.host | upper }}";
+        assert_eq!(
+            ctx.interpolate_deferring(template, "request").unwrap(),
+            "a=1; ${{ request.host | upper }}"
+        );
+        assert!(ctx.interpolate(template).is_err());
+        assert!(
+            ctx.interpolate_deferring("${{ missing.value }}", "request")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_unparsable_placeholder_is_kept() {
+        let ctx = InterpolationContext::new();
+        let result = ctx.interpolate("${{a.b.c}}").unwrap();
+        assert_eq!(result, "${{a.b.c}}");
+        let result = ctx.interpolate("${{ foo }}").unwrap();
+        assert_eq!(result, "${{ foo }}");
+    }
 }
@@ -3,6 +3,8 @@ use std::collections::HashMap;
 use anyhow::{Result, anyhow};
 use chrome_browser::ChromeBrowserTab;
 
+use crate::network::Network;
+
 use super::executor::execute_steps;
 use super::interpolate::InterpolationContext;
 use super::types::{MetadataPhase, Programme};
@@ -27,11 +29,11 @@ pub struct MetadataResult {
 pub async fn execute_metadata(
     phase: &MetadataPhase,
     tab: &ChromeBrowserTab,
-    proxy: Option<&str>,
+    network: &Network,
 ) -> Result<MetadataResult> {
     let context = InterpolationContext::new();
 
-    let (_context, array_result) = execute_steps(&phase.steps, tab, context, proxy).await?;
+    let (_context, array_result) = execute_steps(&phase.steps, tab, context, network).await?;
 
     // We expect an array result from metadata extraction
     // Each item in the array represents a channel with nested programmes
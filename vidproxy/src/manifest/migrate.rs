@@ -0,0 +1,137 @@
+use anyhow::{Result, anyhow};
+use serde_yaml::Value;
+
+use super::Manifest;
+
+/**
+    Version of the manifest format understood by this build.
+    Manifests without a `version` are taken to be version 1.
+*/
+pub const MANIFEST_VERSION: u32 = 1;
+
+/**
+    A change to the manifest format, upgrading manifests of one version to the next.
+*/
+struct Migration {
+    /// Version the migration upgrades from
+    from: u32,
+    /// What changed, for the deprecation warning
+    description: &'static str,
+    /// Rewrite a manifest, returning how many places were changed
+    apply: fn(&mut Value) -> usize,
+}
+
+/**
+    Migrations in version order. A breaking change to the format bumps
+    `MANIFEST_VERSION` and adds a migration from the previous version here.
+*/
+const MIGRATIONS: &[Migration] = &[];
+
+/**
+    Parse a manifest, upgrading it from older versions of the format.
+
+    Returns the manifest along with a deprecation warning for every
+    migration that changed something, so that it can be updated.
+*/
+pub fn parse_manifest(content: &str) -> Result<(Manifest, Vec<String>)> {
+    let mut value: Value = serde_yaml::from_str(content)?;
+    let warnings = migrate(&mut value, MIGRATIONS)?;
+    let manifest = serde_yaml::from_value(value)?;
+    Ok((manifest, warnings))
+}
+
+/**
+    Upgrade a manifest to the current version in place, returning warnings.
+*/
+fn migrate(value: &mut Value, migrations: &[Migration]) -> Result<Vec<String>> {
+    let Value::Mapping(mapping) = value else {
+        return Err(anyhow!("Manifest must be a mapping"));
+    };
+
+    let version = match mapping.get("version") {
+        None => 1,
+        Some(version) => version
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .filter(|v| *v >= 1)
+            .ok_or_else(|| anyhow!("Invalid manifest version {:?}", version))?,
+    };
+    if version > MANIFEST_VERSION {
+        return Err(anyhow!(
+            "Manifest version {} is newer than this build supports ({})",
+            version,
+            MANIFEST_VERSION
+        ));
+    }
+
+    let mut warnings = Vec::new();
+    for migration in migrations.iter().filter(|m| m.from >= version) {
+        let changed = (migration.apply)(value);
+        if changed > 0 {
+            warnings.push(format!(
+                "version {} is deprecated: {} ({} place(s)), set `version: {}` after updating",
+                migration.from, migration.description, changed, MANIFEST_VERSION
+            ));
+        }
+    }
+
+    if let Value::Mapping(mapping) = value {
+        mapping.insert("version".into(), MANIFEST_VERSION.into());
+    }
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+        Rename `kind: old` to `kind: new` at the top level, as a stand-in
+        for a real migration.
+    */
+    fn rename_kind(value: &mut Value) -> usize {
+        match value.get_mut("kind") {
+            Some(kind) if kind.as_str() == Some("old") => {
+                *kind = "new".into();
+                1
+            }
+            _ => 0,
+        }
+    }
+
+    const RENAME_KIND: &[Migration] = &[Migration {
+        from: 1,
+        description: "kind `old` was renamed to `new`",
+        apply: rename_kind,
+    }];
+
+    #[test]
+    fn test_migrates_unversioned_manifest() {
+        let mut value: Value = serde_yaml::from_str("kind: old").unwrap();
+
+        let warnings = migrate(&mut value, RENAME_KIND).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("renamed to `new`"));
+        assert_eq!(value["version"].as_u64(), Some(u64::from(MANIFEST_VERSION)));
+        assert_eq!(value["kind"].as_str(), Some("new"));
+    }
+
+    #[test]
+    fn test_current_manifest_is_left_alone() {
+        let mut value: Value = serde_yaml::from_str("version: 1\nkind: url").unwrap();
+        assert!(migrate(&mut value, MIGRATIONS).unwrap().is_empty());
+
+        // Nothing to rename, nothing to warn about
+        let mut value: Value = serde_yaml::from_str("kind: url").unwrap();
+        assert!(migrate(&mut value, RENAME_KIND).unwrap().is_empty());
+        assert_eq!(value["version"].as_u64(), Some(u64::from(MANIFEST_VERSION)));
+    }
+
+    #[test]
+    fn test_rejects_unknown_versions() {
+        let mut newer: Value = serde_yaml::from_str("version: 99").unwrap();
+        assert!(migrate(&mut newer, MIGRATIONS).is_err());
+        let mut invalid: Value = serde_yaml::from_str("version: zero").unwrap();
+        assert!(migrate(&mut invalid, MIGRATIONS).is_err());
+    }
+}
@@ -1,43 +1,117 @@
+use std::path::Path;
+
 use anyhow::{Result, anyhow};
 use include_dir::{Dir, include_dir};
 
+pub mod cache;
 mod content;
 mod discovery;
 mod executor;
 mod extractors;
 mod interpolate;
 mod metadata;
+mod migrate;
+mod refresh;
+mod token;
 mod types;
 
-pub use content::execute_content;
+pub use content::{execute_content, request_headers};
 pub use discovery::execute_discovery;
+pub use interpolate::InterpolationContext;
 pub use metadata::execute_metadata;
-pub use types::{ChannelEntry, DiscoveredChannel, Manifest, Programme, StreamInfo, Transform};
+pub use migrate::MANIFEST_VERSION;
+pub use refresh::execute_refresh;
+pub use token::refresh_token;
+pub use types::{
+    AdBreakConfig, AudioGapPolicy, ChannelEntry, DiscoveredChannel, DnsConfig, LoudnessConfig,
+    LoudnessMode, Manifest, OverlayConfig, OverlayCorner, Programme, SegmentConfig, StreamInfo,
+    Transform,
+};
 
 /**
     Embedded channel manifests directory.
 */
 static CHANNELS_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/channels");
 
+/**
+    Check if a path has a YAML file extension.
+*/
+fn is_yaml(path: &Path) -> bool {
+    path.extension()
+        .map(|e| e == "yaml" || e == "yml")
+        .unwrap_or(false)
+}
+
+/**
+    Parse a manifest file, upgrading it from older versions of the format
+    with a warning for each deprecated part.
+*/
+fn parse(content: &str, path: &Path) -> Result<Manifest> {
+    let (manifest, warnings) = migrate::parse_manifest(content)
+        .map_err(|e| anyhow!("Failed to parse {:?}: {}", path, e))?;
+    for warning in warnings {
+        eprintln!("[manifest] {:?}: {}", path, warning);
+    }
+    Ok(manifest)
+}
+
 /**
     Load all available source manifests.
+
+    Embedded manifests are loaded first, then any manifests in `extra_dir`.
+    A manifest from `extra_dir` replaces an embedded one with the same source id.
 */
-pub fn load_all() -> Result<Vec<Manifest>> {
+pub fn load_all(extra_dir: Option<&Path>) -> Result<Vec<Manifest>> {
+    let mut manifests = load_embedded()?;
+
+    if let Some(dir) = extra_dir {
+        for manifest in load_dir(dir)? {
+            manifests.retain(|m| m.source.id != manifest.source.id);
+            manifests.push(manifest);
+        }
+    }
+
+    Ok(manifests)
+}
+
+/**
+    Load source manifests from YAML files in a directory.
+*/
+fn load_dir(dir: &Path) -> Result<Vec<Manifest>> {
+    let mut manifests = Vec::new();
+
+    let entries = std::fs::read_dir(dir).map_err(|e| anyhow!("Failed to read {:?}: {}", dir, e))?;
+    for entry in entries {
+        let path = entry?.path();
+        if !is_yaml(&path) {
+            continue;
+        }
+
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read {:?}: {}", path, e))?;
+
+        let manifest = parse(&content, &path)?;
+
+        manifests.push(manifest);
+    }
+
+    Ok(manifests)
+}
+
+/**
+    Load the manifests embedded in the binary.
+*/
+fn load_embedded() -> Result<Vec<Manifest>> {
     let mut manifests = Vec::new();
 
     for file in CHANNELS_DIR.files() {
         let path = file.path();
-        if path
-            .extension()
-            .map(|e| e == "yaml" || e == "yml")
-            .unwrap_or(false)
-        {
+        if is_yaml(path) {
             let content = file
                 .contents_utf8()
                 .ok_or_else(|| anyhow!("Failed to read {:?} as UTF-8", path))?;
 
-            let manifest: Manifest = serde_yaml::from_str(content)
-                .map_err(|e| anyhow!("Failed to parse {:?}: {}", path, e))?;
+            let manifest = parse(content, path)?;
 
             manifests.push(manifest);
         }
@@ -51,7 +125,7 @@ pub fn load_all() -> Result<Vec<Manifest>> {
 */
 #[allow(dead_code)]
 pub fn find_by_id(id: &str) -> Result<Manifest> {
-    let manifests = load_all()?;
+    let manifests = load_all(None)?;
     let id_lower = id.to_lowercase();
 
     // Try exact match on id first
@@ -80,8 +154,7 @@ pub fn find_by_id(id: &str) -> Result<Manifest> {
                 .contents_utf8()
                 .ok_or_else(|| anyhow!("Failed to read {:?} as UTF-8", path))?;
 
-            let manifest: Manifest = serde_yaml::from_str(content)
-                .map_err(|e| anyhow!("Failed to parse {:?}: {}", path, e))?;
+            let manifest = parse(content, path)?;
 
             return Ok(manifest);
         }
@@ -93,7 +166,7 @@ pub fn find_by_id(id: &str) -> Result<Manifest> {
 /**
     List all available source IDs.
 */
-pub fn list_sources() -> Result<Vec<String>> {
-    let manifests = load_all()?;
+pub fn list_sources(extra_dir: Option<&Path>) -> Result<Vec<String>> {
+    let manifests = load_all(extra_dir)?;
     Ok(manifests.into_iter().map(|m| m.source.id).collect())
 }
@@ -0,0 +1,43 @@
+use anyhow::Result;
+use chrome_browser::ChromeBrowserTab;
+
+use crate::network::Network;
+
+use super::discovery::resolve_expiration;
+use super::executor::execute_steps;
+use super::interpolate::InterpolationContext;
+use super::types::RefreshPhase;
+
+/**
+    Result of running the refresh phase.
+*/
+pub struct RefreshResult {
+    /// New expiration timestamp of the discovery results (if extracted or specified)
+    pub expires_at: Option<u64>,
+    /// The discovery outputs, updated with the outputs of the refresh steps
+    pub context: InterpolationContext,
+}
+
+/**
+    Execute the refresh phase, starting from the outputs of a previous
+    discovery (or refresh) run.
+*/
+pub async fn execute_refresh(
+    phase: &RefreshPhase,
+    tab: &ChromeBrowserTab,
+    context: InterpolationContext,
+    network: &Network,
+) -> Result<RefreshResult> {
+    let (context, _) = execute_steps(&phase.steps, tab, context, network).await?;
+
+    let expires_at = resolve_expiration(
+        phase.outputs.expires_at.as_deref(),
+        phase.outputs.expires_in,
+        &context,
+    );
+
+    Ok(RefreshResult {
+        expires_at,
+        context,
+    })
+}
@@ -0,0 +1,203 @@
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL;
+use reqwest::Client;
+
+use super::extractors::extract;
+use super::interpolate::InterpolationContext;
+use super::types::{RefreshMethod, StreamInfo, StreamToken, TokenOutputs, TokenRefresh};
+
+/// Expiry timestamps above this are in milliseconds rather than seconds
+const MILLIS_THRESHOLD: u64 = 100_000_000_000;
+
+fn normalize_timestamp(timestamp: u64) -> u64 {
+    if timestamp > MILLIS_THRESHOLD {
+        timestamp / 1000
+    } else {
+        timestamp
+    }
+}
+
+/**
+    Read the `exp` claim of a JWT, without verifying its signature.
+*/
+fn jwt_expiry(token: &str) -> Option<u64> {
+    let payload = token.split('.').nth(1)?;
+    let decoded = BASE64_URL.decode(payload.trim_end_matches('=')).ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&decoded).ok()?;
+    claims
+        .get("exp")?
+        .as_f64()
+        .map(|exp| normalize_timestamp(exp as u64))
+}
+
+/**
+    Read an expiry timestamp from a URL query parameter. The parameter is
+    either the timestamp itself, or a token with an `exp=` field inside
+    (as in Akamai's `hdnts=st=...~exp=...~hmac=...`).
+*/
+fn url_param_expiry(url: &str, param: &str) -> Option<u64> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let (_, value) = url.query_pairs().find(|(name, _)| name == param)?;
+    let timestamp = value.parse().ok().or_else(|| {
+        value
+            .split(['~', '&', ','])
+            .find_map(|part| part.strip_prefix("exp=")?.parse().ok())
+    })?;
+    Some(normalize_timestamp(timestamp))
+}
+
+/**
+    Get the expiry of a stream's access token, if it can be determined.
+*/
+pub fn token_expiry(token: &StreamToken, manifest_url: &str) -> Option<u64> {
+    match token.expiry_param {
+        Some(ref param) => url_param_expiry(manifest_url, param),
+        None => jwt_expiry(&token.value),
+    }
+}
+
+/**
+    Resolve the token outputs of the content phase. References to `token.*`
+    in the refresh call are kept, to be filled in when refreshing.
+
+    The phase's static `expires_in` is kept with the token, so that
+    refreshed tokens without a readable expiry still expire.
+*/
+pub fn resolve_token(
+    outputs: &TokenOutputs,
+    context: &InterpolationContext,
+    expires_in: Option<u64>,
+) -> Result<StreamToken> {
+    let value = context.interpolate(&outputs.value)?;
+    if value.is_empty() {
+        return Err(anyhow!("Token output resolved to an empty value"));
+    }
+
+    let refresh = match outputs.refresh {
+        Some(ref refresh) => {
+            let deferred = |template: &str| context.interpolate_deferring(template, "token");
+            Some(TokenRefresh {
+                url: deferred(&refresh.url)?,
+                method: refresh.method,
+                headers: refresh
+                    .headers
+                    .iter()
+                    .map(|(name, value)| Ok((name.clone(), deferred(value)?)))
+                    .collect::<Result<_>>()?,
+                body: refresh.body.as_deref().map(deferred).transpose()?,
+                extract: refresh.extract.clone(),
+            })
+        }
+        None => None,
+    };
+
+    Ok(StreamToken {
+        value,
+        expiry_param: outputs.expiry_param.clone(),
+        expires_in,
+        refresh,
+    })
+}
+
+/**
+    Get a fresh access token with the stream's refresh call, and return
+    the stream info with the old token replaced by the new one everywhere.
+
+    This is a single HTTP request, compared to re-running the content
+    phase in the browser, so it is tried first when a stream expires.
+*/
+pub async fn refresh_token(stream_info: &StreamInfo, client: &Client) -> Result<StreamInfo> {
+    let token = stream_info
+        .token
+        .as_ref()
+        .ok_or_else(|| anyhow!("Stream has no access token"))?;
+    let refresh = token
+        .refresh
+        .as_ref()
+        .ok_or_else(|| anyhow!("Stream token has no refresh endpoint"))?;
+
+    let mut context = InterpolationContext::new();
+    context.set("token", "value", token.value.clone());
+
+    let url = context.interpolate(&refresh.url)?;
+    let mut request = match refresh.method {
+        RefreshMethod::Get => client.get(&url),
+        RefreshMethod::Post => client.post(&url),
+    };
+    for (name, value) in &refresh.headers {
+        request = request.header(name, context.interpolate(value)?);
+    }
+    if let Some(ref body) = refresh.body {
+        request = request.body(context.interpolate(body)?);
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| anyhow!("Token refresh request failed: {}", e))?
+        .error_for_status()
+        .map_err(|e| anyhow!("Token refresh request failed: {}", e))?;
+    let body = response.text().await?;
+
+    let new_value = match refresh.extract {
+        Some(ref extractor) => extract(extractor, &body, &url)?,
+        None => body.trim().to_string(),
+    };
+    if new_value.is_empty() {
+        return Err(anyhow!("Token refresh returned an empty token"));
+    }
+
+    let replace = |s: &str| s.replace(&token.value, &new_value);
+    let new_token = StreamToken {
+        value: new_value.clone(),
+        ..token.clone()
+    };
+    let manifest_url = replace(&stream_info.manifest_url);
+
+    Ok(StreamInfo {
+        license_url: stream_info.license_url.as_deref().map(replace),
+        drm_device: stream_info.drm_device.clone(),
+        decryption_keys: stream_info.decryption_keys.clone(),
+        headers: stream_info
+            .headers
+            .iter()
+            .map(|(name, value)| (name.clone(), replace(value)))
+            .collect(),
+        // Without a readable expiry or a static lifetime, the stream
+        // is only refreshed again on auth errors
+        expires_at: token_expiry(&new_token, &manifest_url).or_else(|| {
+            new_token
+                .expires_in
+                .map(|expires_in| crate::time::now() + expires_in)
+        }),
+        manifest_url,
+        token: Some(new_token),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jwt_expiry() {
+        // {"alg":"HS256"} . {"sub":"x","exp":1767225600} . signature
+        let payload = BASE64_URL.encode(r#"{"sub":"x","exp":1767225600}"#);
+        let token = format!("eyJhbGciOiJIUzI1NiJ9.{}.c2ln", payload);
+        assert_eq!(jwt_expiry(&token), Some(1767225600));
+        assert_eq!(jwt_expiry("not-a-jwt"), None);
+    }
+
+    #[test]
+    fn test_url_param_expiry() {
+        let url = "https://cdn.example.com/live.mpd?exp=1767225600000&sig=abc";
+        assert_eq!(url_param_expiry(url, "exp"), Some(1767225600));
+
+        let url =
+            "https://cdn.example.com/live.mpd?hdnts=st%3D1767220000~exp%3D1767225600~hmac%3Dff";
+        assert_eq!(url_param_expiry(url, "hdnts"), Some(1767225600));
+
+        assert_eq!(url_param_expiry(url, "missing"), None);
+    }
+}
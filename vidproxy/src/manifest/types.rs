@@ -1,4 +1,6 @@
 use std::collections::HashMap;
+
+use anyhow::{Result, anyhow};
 use serde::{Deserialize, Serialize};
 
 /**
@@ -6,6 +8,9 @@ use serde::{Deserialize, Serialize};
 */
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Manifest {
+    /// Version of the manifest format, always the current one once loaded
+    #[serde(default = "current_manifest_version")]
+    pub version: u32,
     pub source: Source,
     pub discovery: DiscoveryPhase,
     /// Optional processing phase to filter and transform channels
@@ -15,6 +20,13 @@ pub struct Manifest {
     #[serde(default)]
     pub metadata: Option<MetadataPhase>,
     pub content: ContentPhase,
+    /// Optional refresh phase to renew credentials when discovery results expire
+    #[serde(default)]
+    pub refresh: Option<RefreshPhase>,
+}
+
+fn current_manifest_version() -> u32 {
+    super::MANIFEST_VERSION
 }
 
 /**
@@ -83,6 +95,240 @@ pub enum Transform {
         /// New name to set
         to: String,
     },
+    /// Normalize the audio loudness of channels matching by name or id
+    Loudness {
+        /// Channel name to match (optional)
+        #[serde(default)]
+        name: Option<String>,
+        /// Channel ID to match (optional)
+        #[serde(default)]
+        id: Option<String>,
+        /// Loudness target and mode
+        #[serde(flatten)]
+        config: LoudnessConfig,
+    },
+    /// Burn a logo or label into the video of channels matching by name or id
+    Overlay {
+        /// Channel name to match (optional)
+        #[serde(default)]
+        name: Option<String>,
+        /// Channel ID to match (optional)
+        #[serde(default)]
+        id: Option<String>,
+        /// Image or text, placement and opacity
+        #[serde(flatten)]
+        config: OverlayConfig,
+    },
+    /// Detect ad breaks on channels matching by name or id
+    AdBreaks {
+        /// Channel name to match (optional)
+        #[serde(default)]
+        name: Option<String>,
+        /// Channel ID to match (optional)
+        #[serde(default)]
+        id: Option<String>,
+        /// Detection and replacement settings
+        #[serde(flatten)]
+        config: AdBreakConfig,
+    },
+    /// Proxy the upstream stream of channels matching by name or id untouched
+    Passthrough {
+        /// Channel name to match (optional)
+        #[serde(default)]
+        name: Option<String>,
+        /// Channel ID to match (optional)
+        #[serde(default)]
+        id: Option<String>,
+    },
+    /// Keep the pipelines of channels matching by name or id running at all times
+    Standby {
+        /// Channel name to match (optional)
+        #[serde(default)]
+        name: Option<String>,
+        /// Channel ID to match (optional)
+        #[serde(default)]
+        id: Option<String>,
+    },
+    /// Set the segment duration and playlist length of channels matching by name or id
+    Segments {
+        /// Channel name to match (optional)
+        #[serde(default)]
+        name: Option<String>,
+        /// Channel ID to match (optional)
+        #[serde(default)]
+        id: Option<String>,
+        /// Segment duration and count
+        #[serde(flatten)]
+        config: SegmentConfig,
+    },
+    /// Serve channels matching by name or id with a named output profile
+    Profile {
+        /// Channel name to match (optional)
+        #[serde(default)]
+        name: Option<String>,
+        /// Channel ID to match (optional)
+        #[serde(default)]
+        id: Option<String>,
+        /// Name of the profile, from the profiles file
+        profile: String,
+    },
+}
+
+/**
+    Ad break detection for a channel. SCTE-35 markers in the upstream
+    manifest are always used when present.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AdBreakConfig {
+    /// Also detect breaks from black frames and silence (decodes the stream)
+    #[serde(default)]
+    pub heuristic: bool,
+    /// Serve the slate instead of the stream during signaled ad breaks
+    #[serde(default)]
+    pub replace: bool,
+}
+
+/**
+    Segmenting of a channel's output. Settings left out fall back to the
+    output profile, if any, and then to the command line.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SegmentConfig {
+    /// Segment duration in seconds
+    #[serde(default)]
+    pub duration: Option<u64>,
+    /// Number of segments kept in the playlist
+    #[serde(default)]
+    pub count: Option<usize>,
+}
+
+impl SegmentConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.duration == Some(0) {
+            return Err(anyhow!("segment duration must be at least 1 second"));
+        }
+        if self.count == Some(0) {
+            return Err(anyhow!("segment count must be at least 1"));
+        }
+        Ok(())
+    }
+
+    /**
+        Fill in the settings left out with those of another config.
+    */
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            duration: self.duration.or(fallback.duration),
+            count: self.count.or(fallback.count),
+        }
+    }
+}
+
+/**
+    Audio loudness normalization of a channel's output (EBU R128).
+*/
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct LoudnessConfig {
+    /// Target integrated loudness in LUFS (default: -23)
+    #[serde(default = "default_loudness_target")]
+    pub target: f64,
+    /// How the gain is determined (default: dynamic)
+    #[serde(default)]
+    pub mode: LoudnessMode,
+    /// What to do about gaps in the input audio (default: fill). Only applies here,
+    /// since audio is only re-encoded for normalization, and remuxed as is otherwise
+    #[serde(default)]
+    pub gaps: AudioGapPolicy,
+}
+
+fn default_loudness_target() -> f64 {
+    -23.0
+}
+
+/**
+    A PNG image, or a line of text, composited onto a channel's video.
+
+    Setting `text` or `timecode` makes it a text overlay, and the image is
+    not used. The timecode is the stream time of each frame, as HH:MM:SS.mmm.
+*/
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct OverlayConfig {
+    /// Path or URL of a PNG image (default: the channel's own image)
+    #[serde(default)]
+    pub image: Option<String>,
+    /// Text to draw instead of an image
+    #[serde(default)]
+    pub text: Option<String>,
+    /// Draw the timecode of every frame, after the text if any
+    #[serde(default)]
+    pub timecode: bool,
+    /// Corner to place the image in (default: top_right)
+    #[serde(default)]
+    pub corner: OverlayCorner,
+    /// Opacity from 0.0 to 1.0 (default: 1.0)
+    #[serde(default = "default_overlay_opacity")]
+    pub opacity: f32,
+    /// Distance from the frame edges in pixels (default: 24)
+    #[serde(default = "default_overlay_margin")]
+    pub margin: u32,
+}
+
+fn default_overlay_opacity() -> f32 {
+    1.0
+}
+
+fn default_overlay_margin() -> u32 {
+    24
+}
+
+impl OverlayConfig {
+    /**
+        Whether this overlay draws text rather than an image.
+    */
+    pub fn is_text(&self) -> bool {
+        self.text.is_some() || self.timecode
+    }
+}
+
+/**
+    Corner of the frame an overlay is anchored to.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlayCorner {
+    TopLeft,
+    #[default]
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/**
+    How loudness normalization determines the gain to apply.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoudnessMode {
+    /// Follow the short-term loudness of the stream, for live channels
+    #[default]
+    Dynamic,
+    /// Measure the whole input first, then apply a single gain (recordings / VOD)
+    TwoPass,
+}
+
+/**
+    How gaps in a channel's audio timestamps (lost segments, upstream hiccups)
+    are handled when the audio is re-encoded, which only loudness
+    normalization does. Remuxed audio is passed through with its gaps.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioGapPolicy {
+    /// Insert silence, so the audio stays aligned with the video
+    #[default]
+    Fill,
+    /// Leave the gap and mark a discontinuity in the playlist
+    Discontinuity,
 }
 
 /**
@@ -106,6 +352,42 @@ pub struct Source {
     /// Run browser in headless mode for this source
     #[serde(default)]
     pub headless: bool,
+    /// Re-run discovery at least this often, in seconds (in addition to any extracted expiration)
+    #[serde(default)]
+    pub discovery_interval: Option<u64>,
+    /// Abort a discovery run after this many seconds (default: 300)
+    #[serde(default)]
+    pub discovery_timeout: Option<u64>,
+    /// Abort resolving a channel's content after this many seconds (default: 120)
+    #[serde(default)]
+    pub content_timeout: Option<u64>,
+    /// DNS overrides for upstream hosts, for providers that geo-block at DNS level
+    #[serde(default)]
+    pub dns: Option<DnsConfig>,
+    /// Preference when several sources have the same channel, higher wins (default: 0)
+    #[serde(default)]
+    pub priority: i32,
+}
+
+/**
+    DNS settings for a source, applied to the browser and to requests made
+    by vidproxy itself (steps, key requests, pre-flight and passthrough).
+
+    They do not apply to the FFmpeg pipeline: its manifest and segment
+    fetches resolve hosts with the system resolver.
+*/
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct DnsConfig {
+    /// Fixed addresses for hosts, bypassing DNS (e.g., "cdn.example.com": "203.0.113.7")
+    #[serde(default)]
+    pub hosts: HashMap<String, String>,
+    /**
+        Resolver for all other hosts: either a DNS-over-HTTPS endpoint
+        (e.g., "https://cloudflare-dns.com/dns-query") or the address of
+        a DNS server (e.g., "9.9.9.9" or "9.9.9.9:53")
+    */
+    #[serde(default)]
+    pub resolver: Option<String>,
 }
 
 /**
@@ -159,6 +441,35 @@ pub struct MetadataOutputs {
     pub expires_in: Option<u64>,
 }
 
+/**
+    Refresh phase - renews a source's session when its discovery results
+    expire, without discovering its channels again.
+
+    Steps start out with the outputs of the last discovery run, and run in
+    the same browser, so they can reuse its cookies, device IDs and tokens.
+    Content steps of sources with a refresh phase start out with these
+    outputs too, and resolve their channels again after each refresh.
+*/
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RefreshPhase {
+    pub steps: Vec<Step>,
+    #[serde(default)]
+    pub outputs: RefreshOutputs,
+}
+
+/**
+    Outputs from the refresh phase.
+*/
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RefreshOutputs {
+    /// New expiration timestamp for discovery results (optional, supports interpolation)
+    #[serde(default)]
+    pub expires_at: Option<String>,
+    /// Static expiration duration in seconds (alternative to expires_at)
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
 /**
     Content phase - fetches stream info for a single channel.
 */
@@ -178,15 +489,89 @@ pub struct ContentOutputs {
     /// License URL for DRM content (optional, supports interpolation)
     #[serde(default)]
     pub license_url: Option<String>,
+    /// Named device from the devices directory to request licenses with
+    /// (default: the Widevine device given on the command line, or an embedded one)
+    #[serde(default)]
+    pub drm_device: Option<String>,
+    /// Static `kid:key` pairs to decrypt the stream with, instead of
+    /// requesting a license (optional, supports interpolation)
+    #[serde(default)]
+    pub decryption_keys: Option<Vec<String>>,
     /// Expiration timestamp for stream info (optional, supports interpolation)
     #[serde(default)]
     pub expires_at: Option<String>,
     /// Static expiration duration in seconds (alternative to expires_at)
     #[serde(default)]
     pub expires_in: Option<u64>,
-    /// Optional headers to send when fetching the manifest/segments
+    /**
+        Optional headers to send when fetching the manifest/segments.
+        Values may use `browser.*` (cookies, user_agent, url, origin) and
+        `request.*` (url, host, origin, path).
+
+        `request.*` is resolved per request for requests made by vidproxy
+        itself, such as pre-flight checks, passthrough and license requests.
+        FFmpeg sends the same headers with every request of a pipeline, so
+        for its manifest and segment requests they are resolved once,
+        against the manifest URL.
+
+        `browser.cookies` only holds cookies visible to scripts on the page,
+        HttpOnly cookies are not included.
+    */
     #[serde(default)]
     pub headers: Option<HashMap<String, String>>,
+    /// Access token metadata, for refreshing the stream without re-running the content phase
+    #[serde(default)]
+    pub token: Option<TokenOutputs>,
+}
+
+/**
+    Access token outputs of the content phase.
+*/
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TokenOutputs {
+    /// The token as it appears in the manifest URL, license URL or headers (supports interpolation)
+    pub value: String,
+    /**
+        Manifest URL query parameter holding the expiry as a unix timestamp
+        (e.g., "exp", or "hdnts" for Akamai-style "exp=..." tokens).
+        If not set, the expiry is read from the `exp` claim of a JWT token.
+    */
+    #[serde(default)]
+    pub expiry_param: Option<String>,
+    /// Endpoint to get a fresh token from
+    #[serde(default)]
+    pub refresh: Option<TokenRefresh>,
+}
+
+/**
+    HTTP call that returns a fresh access token.
+
+    All fields support interpolation, and may use `token.value`
+    to refer to the current token at the time of the refresh.
+*/
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TokenRefresh {
+    pub url: String,
+    #[serde(default)]
+    pub method: RefreshMethod,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub body: Option<String>,
+    /// Extractor for the new token in the response (default: the whole response body)
+    #[serde(default)]
+    pub extract: Option<Extractor>,
+}
+
+/**
+    HTTP method of a token refresh.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum RefreshMethod {
+    #[default]
+    Get,
+    Post,
 }
 
 /**
@@ -213,6 +598,28 @@ pub struct Step {
     /// Extractors to run on the response
     #[serde(default)]
     pub extract: HashMap<String, Extractor>,
+    /// Cache the response for this many seconds (Fetch, FetchInBrowser and Sniff steps)
+    #[serde(default)]
+    pub cache: Option<u64>,
+    /// Number of additional attempts if this step fails
+    #[serde(default)]
+    pub retries: Option<u32>,
+    /// Delay in seconds between retry attempts (default: 1)
+    #[serde(default)]
+    pub retry_delay: Option<f64>,
+    /// Maximum response body size in bytes (Fetch, FetchInBrowser and Sniff steps,
+    /// default: 16 MiB). Larger responses are discarded instead of being extracted from.
+    #[serde(default)]
+    pub max_body_size: Option<usize>,
+    /// Treat the response body as binary (Fetch, FetchInBrowser and Sniff steps). The body is
+    /// base64-encoded before extraction and exposed as the `body` output.
+    #[serde(default)]
+    pub binary: bool,
+    /// Alternate steps to try in order if this step (and its retries) fails.
+    /// Outputs of a successful fallback are recorded under this step's name.
+    /// Fallbacks of fallback steps are not used.
+    #[serde(default)]
+    pub fallback: Vec<Step>,
 }
 
 /**
@@ -365,7 +772,7 @@ pub enum ExtractorKind {
     #[serde(rename = "regex_array")]
     RegexArray,
     /**
-        First line containing ":" (for CDRM key response)
+        First line that is a `kid:key` pair (for CDRM key response)
     */
     Line,
     /**
@@ -378,7 +785,7 @@ pub enum ExtractorKind {
     A discovered channel from the discovery phase.
 */
 #[allow(dead_code)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct DiscoveredChannel {
     pub id: String,
     pub name: Option<String>,
@@ -386,23 +793,62 @@ pub struct DiscoveredChannel {
     pub category: Option<String>,
     pub description: Option<String>,
     pub source: String,
+    #[serde(default)]
+    pub loudness: Option<LoudnessConfig>,
+    #[serde(default)]
+    pub overlay: Option<OverlayConfig>,
+    #[serde(default)]
+    pub ad_breaks: Option<AdBreakConfig>,
+    /// Proxy the upstream manifest and segments instead of running a pipeline
+    #[serde(default)]
+    pub passthrough: bool,
+    /// Start the pipeline at boot and never stop it for being idle
+    #[serde(default)]
+    pub standby: bool,
+    /// Output profile to serve the channel with, unless a request asks for another
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Segmenting of the channel, over that of the command line
+    #[serde(default)]
+    pub segments: SegmentConfig,
 }
 
 /**
     Stream info from the content phase.
 */
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct StreamInfo {
     pub manifest_url: String,
     pub license_url: Option<String>,
+    #[serde(default)]
+    pub drm_device: Option<String>,
+    /// Static keys from the manifest, normalized `kid:key` hex pairs
+    #[serde(default)]
+    pub decryption_keys: Vec<String>,
     pub expires_at: Option<u64>,
     pub headers: Vec<(String, String)>,
+    #[serde(default)]
+    pub token: Option<StreamToken>,
+}
+
+/**
+    The access token of a stream, and how to refresh it.
+*/
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StreamToken {
+    pub value: String,
+    pub expiry_param: Option<String>,
+    /// Static lifetime in seconds of the stream, for tokens without a readable expiry
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+    /// Refresh call, with everything but `token.*` already interpolated
+    pub refresh: Option<TokenRefresh>,
 }
 
 /**
     A single EPG programme entry.
 */
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Programme {
     pub title: String,
     pub description: Option<String>,
@@ -417,10 +863,11 @@ pub struct Programme {
 /**
     Full channel entry combining discovery, metadata, and content info.
 */
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ChannelEntry {
     pub channel: DiscoveredChannel,
     pub stream_info: Option<StreamInfo>,
     pub programmes: Vec<Programme>,
+    #[serde(skip)]
     pub last_error: Option<String>,
 }
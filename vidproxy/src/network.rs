@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL;
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use reqwest::{Client, Proxy};
+
+use crate::manifest::{DnsConfig, Manifest};
+
+/// How long to wait for an answer from the configured resolver
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+const RECORD_A: u16 = 1;
+const RECORD_AAAA: u16 = 28;
+
+/**
+    Where the resolver sends queries for hosts without a fixed address.
+*/
+#[derive(Debug, Clone)]
+enum Upstream {
+    /// DNS-over-HTTPS (RFC 8484) endpoint
+    Https(String),
+    /// Plain DNS server, queried over UDP
+    Udp(SocketAddr),
+}
+
+/**
+    Resolves upstream hosts for a source: fixed addresses first, then the
+    configured resolver, then the system resolver.
+*/
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    hosts: HashMap<String, IpAddr>,
+    upstream: Option<Upstream>,
+    client: Client,
+}
+
+impl Resolver {
+    pub fn new(config: &DnsConfig) -> Result<Self> {
+        let hosts = config
+            .hosts
+            .iter()
+            .map(|(host, ip)| {
+                let ip = ip
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid address '{}' for host '{}'", ip, host))?;
+                Ok((host.to_ascii_lowercase(), ip))
+            })
+            .collect::<Result<_>>()?;
+
+        let upstream = match config.resolver.as_deref() {
+            None => None,
+            Some(url) if url.starts_with("https://") => Some(Upstream::Https(url.to_string())),
+            Some(server) => {
+                let addr = server
+                    .parse::<SocketAddr>()
+                    .or_else(|_| server.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                    .map_err(|_| anyhow!("Invalid DNS resolver '{}'", server))?;
+                Some(Upstream::Udp(addr))
+            }
+        };
+
+        Ok(Self {
+            hosts,
+            upstream,
+            client: Client::builder().timeout(LOOKUP_TIMEOUT).build()?,
+        })
+    }
+
+    /**
+        Look up the addresses of a host.
+    */
+    pub async fn lookup(&self, host: &str) -> Result<Vec<IpAddr>> {
+        if let Some(ip) = self.hosts.get(&host.to_ascii_lowercase()) {
+            return Ok(vec![*ip]);
+        }
+
+        let Some(ref upstream) = self.upstream else {
+            let addrs = tokio::net::lookup_host((host, 0)).await?;
+            return Ok(addrs.map(|addr| addr.ip()).collect());
+        };
+
+        for record_type in [RECORD_A, RECORD_AAAA] {
+            let query = encode_query(host, record_type);
+            let response = match upstream {
+                Upstream::Https(url) => self.query_https(url, &query).await?,
+                Upstream::Udp(server) => query_udp(*server, &query).await?,
+            };
+            let ips = parse_response(&response)?;
+            if !ips.is_empty() {
+                return Ok(ips);
+            }
+        }
+
+        Err(anyhow!("No addresses found for '{}'", host))
+    }
+
+    async fn query_https(&self, url: &str, query: &[u8]) -> Result<Vec<u8>> {
+        let response = self
+            .client
+            .get(url)
+            .query(&[("dns", BASE64_URL.encode(query))])
+            .header("Accept", "application/dns-message")
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.bytes().await?.to_vec())
+    }
+}
+
+impl reqwest::dns::Resolve for Resolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let ips = resolver.lookup(name.as_str()).await?;
+            let addrs: reqwest::dns::Addrs =
+                Box::new(ips.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(addrs)
+        })
+    }
+}
+
+async fn query_udp(server: SocketAddr, query: &[u8]) -> Result<Vec<u8>> {
+    let bind: SocketAddr = if server.is_ipv4() {
+        "0.0.0.0:0".parse()?
+    } else {
+        "[::]:0".parse()?
+    };
+    let socket = tokio::net::UdpSocket::bind(bind).await?;
+    socket.connect(server).await?;
+    socket.send(query).await?;
+
+    let mut buffer = vec![0u8; 4096];
+    let len = tokio::time::timeout(LOOKUP_TIMEOUT, socket.recv(&mut buffer))
+        .await
+        .map_err(|_| anyhow!("DNS server {} did not answer", server))??;
+    buffer.truncate(len);
+    Ok(buffer)
+}
+
+/**
+    Encode a recursive DNS query for a single record type. The ID is left at
+    zero, as recommended for DoH, since queries are never multiplexed.
+*/
+fn encode_query(host: &str, record_type: u16) -> Vec<u8> {
+    let mut query = vec![0, 0, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in host.trim_end_matches('.').split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes());
+    query
+}
+
+/**
+    Skip over a (possibly compressed) name, returning the offset after it.
+*/
+fn skip_name(message: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(offset)? as usize;
+        if len & 0xC0 == 0xC0 {
+            return Some(offset + 2);
+        }
+        offset += 1;
+        if len == 0 {
+            return Some(offset);
+        }
+        offset += len;
+    }
+}
+
+/**
+    Extract the A and AAAA records from the answer section of a DNS response.
+*/
+fn parse_response(message: &[u8]) -> Result<Vec<IpAddr>> {
+    let truncated = || anyhow!("Truncated DNS response");
+    let read_u16 = |offset: usize| -> Result<u16> {
+        let bytes = message.get(offset..offset + 2).ok_or_else(truncated)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+
+    let rcode = read_u16(2)? & 0x000F;
+    if rcode != 0 {
+        return Err(anyhow!("DNS query failed with response code {}", rcode));
+    }
+    let questions = read_u16(4)?;
+    let answers = read_u16(6)?;
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = skip_name(message, offset).ok_or_else(truncated)? + 4;
+    }
+
+    let mut ips = Vec::new();
+    for _ in 0..answers {
+        offset = skip_name(message, offset).ok_or_else(truncated)?;
+        let record_type = read_u16(offset)?;
+        let len = read_u16(offset + 8)? as usize;
+        let data = message
+            .get(offset + 10..offset + 10 + len)
+            .ok_or_else(truncated)?;
+        match (record_type, len) {
+            (RECORD_A, 4) => ips.push(IpAddr::from(<[u8; 4]>::try_from(data)?)),
+            (RECORD_AAAA, 16) => ips.push(IpAddr::from(<[u8; 16]>::try_from(data)?)),
+            _ => {}
+        }
+        offset += 10 + len;
+    }
+
+    Ok(ips)
+}
+
+/**
+    Network settings used for a source's upstream requests:
+    its proxy and DNS overrides.
+
+    These reach Chrome and the HTTP clients created here. FFmpeg opens its
+    manifest and segment URLs itself, with the system resolver.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Network {
+    proxy: Option<String>,
+    dns: Option<DnsConfig>,
+    resolver: Option<Arc<Resolver>>,
+}
+
+impl Network {
+    pub fn for_manifest(manifest: &Manifest) -> Result<Self> {
+        let dns = manifest.source.dns.clone();
+        let resolver = dns.as_ref().map(Resolver::new).transpose()?.map(Arc::new);
+        Ok(Self {
+            proxy: manifest.source.proxy.clone(),
+            dns,
+            resolver,
+        })
+    }
+
+    /**
+        Create an HTTP client that goes through the proxy (if any)
+        and resolves hosts with the DNS overrides.
+    */
+    pub fn http_client(&self) -> Result<Client> {
+        let mut builder = self.resolving_client_builder();
+        if let Some(ref proxy_url) = self.proxy {
+            let proxy = Proxy::all(proxy_url)
+                .map_err(|e| anyhow!("Invalid proxy URL '{}': {}", proxy_url, e))?;
+            builder = builder.proxy(proxy);
+        }
+        builder
+            .build()
+            .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))
+    }
+
+    /**
+        Create an HTTP client with the DNS overrides but without the proxy,
+        for stream fetches that (like the remux pipeline) connect directly.
+    */
+    pub fn direct_client(&self) -> Result<Client> {
+        self.resolving_client_builder()
+            .build()
+            .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))
+    }
+
+    /**
+        Check if the source overrides DNS, which FFmpeg's own fetches don't follow.
+    */
+    pub fn overrides_dns(&self) -> bool {
+        self.dns.is_some()
+    }
+
+    fn resolving_client_builder(&self) -> reqwest::ClientBuilder {
+        let builder = Client::builder();
+        match self.resolver {
+            Some(ref resolver) => builder.dns_resolver(Arc::clone(resolver)),
+            None => builder,
+        }
+    }
+
+    /**
+        Command line arguments that make Chrome resolve hosts the same way.
+
+        Chrome can only be pointed at a DoH resolver, so a plain DNS server
+        only applies to requests made by vidproxy itself.
+    */
+    pub fn chrome_args(&self) -> Vec<String> {
+        let Some(ref dns) = self.dns else {
+            return Vec::new();
+        };
+
+        let mut args = Vec::new();
+        if !dns.hosts.is_empty() {
+            let mut rules: Vec<String> = dns
+                .hosts
+                .iter()
+                .map(|(host, ip)| match ip.parse::<IpAddr>() {
+                    Ok(IpAddr::V6(_)) => format!("MAP {} [{}]", host, ip),
+                    _ => format!("MAP {} {}", host, ip),
+                })
+                .collect();
+            rules.sort();
+            args.push(format!("--host-resolver-rules={}", rules.join(",")));
+        }
+
+        match dns.resolver.as_deref() {
+            Some(url) if url.starts_with("https://") => {
+                let template = utf8_percent_encode(url, NON_ALPHANUMERIC);
+                args.push(format!(
+                    "--enable-features=DnsOverHttps:Fallback/false/Templates/{}",
+                    template
+                ));
+            }
+            Some(server) => eprintln!(
+                "[network] Chrome can't use DNS server {}, only DoH resolvers apply to the browser",
+                server
+            ),
+            None => {}
+        }
+
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_query() {
+        assert_eq!(
+            encode_query("a.bc", RECORD_A),
+            [
+                0, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, // header
+                1, b'a', 2, b'b', b'c', 0, // name
+                0, 1, 0, 1, // type A, class IN
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let mut response = encode_query("a.bc", RECORD_A);
+        response[2] = 0x81;
+        response[3] = 0x80;
+        response[7] = 2;
+        // CNAME with a compressed name, followed by an A record
+        response.extend_from_slice(&[0xC0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xC0, 12]);
+        response.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 203, 0, 113, 7]);
+
+        assert_eq!(
+            parse_response(&response).unwrap(),
+            vec!["203.0.113.7".parse::<IpAddr>().unwrap()]
+        );
+
+        // NXDOMAIN
+        response[3] = 0x83;
+        assert!(parse_response(&response).is_err());
+        assert!(parse_response(&response[..5]).is_err());
+    }
+}
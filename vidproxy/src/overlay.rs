@@ -0,0 +1,335 @@
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+
+use crate::manifest::{OverlayConfig, OverlayCorner, request_headers};
+use crate::slate::glyph;
+
+const TEXT_COLOR: [u8; 3] = [235, 235, 235];
+/// Opacity of the box behind text, relative to the overlay opacity
+const TEXT_BACKGROUND_ALPHA: f32 = 0.6;
+/// Glyph pixels of padding around text, inside its box
+const TEXT_PADDING: u32 = 2;
+/// Time limit for fetching an overlay image, the same as for the pre-flight check
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/**
+    What an overlay draws.
+*/
+#[derive(Debug, Clone)]
+enum Content {
+    Image {
+        width: u32,
+        height: u32,
+        /// BGRA pixels, with the configured opacity already applied to alpha
+        bgra: Vec<u8>,
+    },
+    /// Rendered for every frame, since the timecode changes
+    Text {
+        text: Option<String>,
+        timecode: bool,
+        opacity: f32,
+    },
+}
+
+/**
+    An overlay, ready to be composited onto BGRA video frames.
+*/
+#[derive(Debug, Clone)]
+pub struct Overlay {
+    content: Content,
+    corner: OverlayCorner,
+    margin: u32,
+}
+
+impl Overlay {
+    /**
+        Decode a PNG image and apply the placement and opacity of the config.
+    */
+    pub fn from_png(data: &[u8], config: &OverlayConfig) -> Result<Self> {
+        let image = image::load_from_memory_with_format(data, image::ImageFormat::Png)
+            .map_err(|e| anyhow!("Failed to decode overlay image: {}", e))?
+            .to_rgba8();
+        let (width, height) = image.dimensions();
+        Ok(Self::from_rgba(width, height, image.into_raw(), config))
+    }
+
+    fn from_rgba(width: u32, height: u32, mut rgba: Vec<u8>, config: &OverlayConfig) -> Self {
+        let opacity = config.opacity.clamp(0.0, 1.0);
+        for pixel in rgba.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+            pixel[3] = (pixel[3] as f32 * opacity).round() as u8;
+        }
+        Self {
+            content: Content::Image {
+                width,
+                height,
+                bgra: rgba,
+            },
+            corner: config.corner,
+            margin: config.margin,
+        }
+    }
+
+    /**
+        Create a text overlay with the text, timecode, placement and opacity of the config.
+    */
+    pub fn text(config: &OverlayConfig) -> Self {
+        Self {
+            content: Content::Text {
+                text: config.text.clone(),
+                timecode: config.timecode,
+                opacity: config.opacity.clamp(0.0, 1.0),
+            },
+            corner: config.corner,
+            margin: config.margin,
+        }
+    }
+
+    /**
+        Top-left position of an overlay of the given size in a frame.
+        The overlay is clipped if it doesn't fit.
+    */
+    fn position(&self, width: u32, height: u32, frame_width: u32, frame_height: u32) -> (u32, u32) {
+        let right = frame_width.saturating_sub(width + self.margin);
+        let bottom = frame_height.saturating_sub(height + self.margin);
+        let left = self.margin.min(frame_width);
+        let top = self.margin.min(frame_height);
+        match self.corner {
+            OverlayCorner::TopLeft => (left, top),
+            OverlayCorner::TopRight => (right, top),
+            OverlayCorner::BottomLeft => (left, bottom),
+            OverlayCorner::BottomRight => (right, bottom),
+        }
+    }
+
+    /**
+        Alpha-blend the overlay onto a tightly packed BGRA frame
+        with the given presentation time.
+    */
+    pub fn composite(&self, frame: &mut [u8], frame_width: u32, frame_height: u32, time: Duration) {
+        match self.content {
+            Content::Image {
+                width,
+                height,
+                ref bgra,
+            } => self.blend(bgra, width, height, frame, frame_width, frame_height),
+            Content::Text {
+                ref text,
+                timecode,
+                opacity,
+            } => {
+                let line = match (text, timecode) {
+                    (Some(text), true) => format!("{}  {}", text, format_timecode(time)),
+                    (Some(text), false) => text.clone(),
+                    (None, _) => format_timecode(time),
+                };
+                // The text box is about 1/25 of the frame height
+                let scale = (frame_height / 280).max(1);
+                let (width, height, bgra) = render_text(&line, scale, opacity);
+                self.blend(&bgra, width, height, frame, frame_width, frame_height);
+            }
+        }
+    }
+
+    fn blend(
+        &self,
+        bgra: &[u8],
+        width: u32,
+        height: u32,
+        frame: &mut [u8],
+        frame_width: u32,
+        frame_height: u32,
+    ) {
+        let (x0, y0) = self.position(width, height, frame_width, frame_height);
+        let visible_width = width.min(frame_width - x0) as usize;
+        let visible_height = height.min(frame_height - y0) as usize;
+
+        for row in 0..visible_height {
+            let src_start = row * width as usize * 4;
+            let dst_start = ((y0 as usize + row) * frame_width as usize + x0 as usize) * 4;
+            let src = &bgra[src_start..src_start + visible_width * 4];
+            let Some(dst) = frame.get_mut(dst_start..dst_start + visible_width * 4) else {
+                return;
+            };
+
+            for (s, d) in src.chunks_exact(4).zip(dst.chunks_exact_mut(4)) {
+                let alpha = s[3] as u32;
+                if alpha == 0 {
+                    continue;
+                }
+                for c in 0..3 {
+                    d[c] = ((s[c] as u32 * alpha + d[c] as u32 * (255 - alpha) + 127) / 255) as u8;
+                }
+            }
+        }
+    }
+}
+
+/**
+    Format a presentation time as HH:MM:SS.mmm.
+*/
+fn format_timecode(time: Duration) -> String {
+    let millis = time.as_millis();
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/**
+    Render a line of text onto a translucent box, returning its size and BGRA pixels.
+*/
+fn render_text(line: &str, scale: u32, opacity: f32) -> (u32, u32, Vec<u8>) {
+    let columns = (line.chars().count() as u32 * 6).saturating_sub(1) + TEXT_PADDING * 2;
+    let rows = 7 + TEXT_PADDING * 2;
+    let (width, height) = (columns * scale, rows * scale);
+
+    let background = (255.0 * opacity * TEXT_BACKGROUND_ALPHA).round() as u8;
+    let foreground = (255.0 * opacity).round() as u8;
+    let mut bgra = [0, 0, 0, background].repeat((width * height) as usize);
+
+    for (index, c) in line.chars().enumerate() {
+        let x0 = (TEXT_PADDING + index as u32 * 6) * scale;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..5 {
+                if bits & (0x10 >> col) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    let y = (TEXT_PADDING + row as u32) * scale + dy;
+                    let start = ((y * width + x0 + col * scale) * 4) as usize;
+                    for pixel in bgra[start..start + scale as usize * 4].chunks_exact_mut(4) {
+                        pixel[..3].copy_from_slice(&TEXT_COLOR);
+                        pixel[3] = foreground;
+                    }
+                }
+            }
+        }
+    }
+
+    (width, height, bgra)
+}
+
+/**
+    Load the overlay image from a local path or an http(s) URL.
+
+    URLs are fetched with the source's HTTP client and user agent, so that
+    they go through its proxy and DNS overrides. Other upstream headers are
+    not sent, since the image may be hosted somewhere else entirely.
+*/
+pub async fn load(
+    client: &reqwest::Client,
+    headers: &[(String, String)],
+    image: &str,
+    config: &OverlayConfig,
+) -> Result<Overlay> {
+    let data = if image.starts_with("http://") || image.starts_with("https://") {
+        let mut request = client.get(image).timeout(FETCH_TIMEOUT);
+        for (name, value) in request_headers(headers, image) {
+            if name.eq_ignore_ascii_case("user-agent") {
+                request = request.header(reqwest::header::USER_AGENT, value);
+            }
+        }
+        let response = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| anyhow!("Failed to fetch overlay image '{}': {}", image, e))?;
+        response.bytes().await?.to_vec()
+    } else {
+        tokio::fs::read(image)
+            .await
+            .map_err(|e| anyhow!("Failed to read overlay image '{}': {}", image, e))?
+    };
+    Overlay::from_png(&data, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(corner: OverlayCorner, opacity: f32) -> OverlayConfig {
+        OverlayConfig {
+            image: None,
+            text: None,
+            timecode: false,
+            corner,
+            opacity,
+            margin: 1,
+        }
+    }
+
+    fn pixel(frame: &[u8], width: u32, x: u32, y: u32) -> &[u8] {
+        let offset = ((y * width + x) * 4) as usize;
+        &frame[offset..offset + 4]
+    }
+
+    #[test]
+    fn test_composite_corner_and_opacity() {
+        // 2x2 opaque red overlay at half opacity, bottom right of a 6x4 black frame
+        let overlay = Overlay::from_rgba(
+            2,
+            2,
+            [255, 0, 0, 255].repeat(4),
+            &config(OverlayCorner::BottomRight, 0.5),
+        );
+        let mut frame = [0, 0, 0, 255].repeat(6 * 4);
+        overlay.composite(&mut frame, 6, 4, Duration::ZERO);
+
+        // Overlay covers x 3..5, y 1..3
+        assert_eq!(pixel(&frame, 6, 3, 1), &[0, 0, 128, 255]);
+        assert_eq!(pixel(&frame, 6, 4, 2), &[0, 0, 128, 255]);
+        assert_eq!(pixel(&frame, 6, 5, 3), &[0, 0, 0, 255]);
+        assert_eq!(pixel(&frame, 6, 2, 1), &[0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_composite_clips_large_overlay() {
+        let overlay = Overlay::from_rgba(
+            8,
+            8,
+            [0, 255, 0, 255].repeat(64),
+            &config(OverlayCorner::TopLeft, 1.0),
+        );
+        let mut frame = [0, 0, 0, 255].repeat(4 * 4);
+        overlay.composite(&mut frame, 4, 4, Duration::ZERO);
+
+        assert_eq!(pixel(&frame, 4, 0, 0), &[0, 0, 0, 255]);
+        assert_eq!(pixel(&frame, 4, 3, 3), &[0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn test_format_timecode() {
+        assert_eq!(format_timecode(Duration::ZERO), "00:00:00.000");
+        assert_eq!(
+            format_timecode(Duration::from_millis(30 * 3_600_000 + 61_042)),
+            "30:01:01.042"
+        );
+    }
+
+    #[test]
+    fn test_composite_text() {
+        let mut config = config(OverlayCorner::TopLeft, 1.0);
+        config.text = Some("A".to_string());
+        let overlay = Overlay::text(&config);
+
+        // One glyph with padding is 9x11 pixels at scale 1, placed at (1, 1)
+        let mut frame = [0, 0, 0, 255].repeat(16 * 16);
+        overlay.composite(&mut frame, 16, 16, Duration::ZERO);
+
+        // Top of the "A" is lit, the padding only darkened (already black)
+        assert_eq!(pixel(&frame, 16, 1 + 2 + 1, 1 + 2), &[235, 235, 235, 255]);
+        assert_eq!(pixel(&frame, 16, 1, 1), &[0, 0, 0, 255]);
+        assert!(
+            frame
+                .chunks_exact(4)
+                .enumerate()
+                .filter(|(_, p)| p[0] != 0)
+                .all(|(i, _)| (1..10).contains(&(i % 16)) && (1..12).contains(&(i / 16)))
+        );
+    }
+}
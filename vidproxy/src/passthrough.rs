@@ -0,0 +1,405 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{LazyLock, Mutex};
+
+use regex::{Captures, Regex};
+use reqwest::Url;
+
+/// Quoted `URI` attributes in HLS tags (keys, maps, media renditions, ...)
+static HLS_URI_ATTRIBUTE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"URI="([^"]*)""#).unwrap());
+
+/// MPD attributes that hold segment or index URLs
+static MPD_URL_ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"\b(media|initialization|sourceURL|index|xlink:href)="([^"]*)""#).unwrap()
+});
+
+/// MPD element tags, opening, closing or empty
+static MPD_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<(/?)([A-Za-z][\w.:-]*)([^>]*?)(/?)>").unwrap());
+
+/**
+    Map an upstream URL to its path below a channel's `proxy/` route,
+    as `scheme/host[:port]/path?query`.
+*/
+pub fn proxy_path(url: &Url) -> String {
+    let mut path = format!("{}/{}", url.scheme(), url.host_str().unwrap_or_default());
+    if let Some(port) = url.port() {
+        path.push_str(&format!(":{}", port));
+    }
+    path.push_str(url.path());
+    if let Some(query) = url.query() {
+        path.push('?');
+        path.push_str(query);
+    }
+    path
+}
+
+/**
+    Map a path below a channel's `proxy/` route back to the upstream URL.
+    Only `http` and `https` upstreams are accepted.
+*/
+pub fn upstream_url(path: &str, query: Option<&str>) -> Option<Url> {
+    let (scheme, rest) = path.split_once('/')?;
+    if scheme != "http" && scheme != "https" {
+        return None;
+    }
+    let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+    if host.is_empty() {
+        return None;
+    }
+
+    let mut url = Url::parse(&format!("{}://{}/{}", scheme, host, path)).ok()?;
+    url.set_query(query);
+    Some(url)
+}
+
+/**
+    Origins that the proxy of each passthrough channel may fetch from.
+
+    A channel starts out with only the origin of its manifest. Rewriting a
+    manifest adds the origins it references, so that players can follow
+    them through the proxy, while any other upstream is refused.
+*/
+#[derive(Debug, Default)]
+pub struct UpstreamOrigins {
+    channels: Mutex<HashMap<String, HashSet<String>>>,
+}
+
+impl UpstreamOrigins {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+        Allow a channel to fetch from the given origins
+    */
+    pub fn allow(&self, channel: &str, origins: impl IntoIterator<Item = String>) {
+        let mut channels = self.channels.lock().unwrap();
+        channels
+            .entry(channel.to_string())
+            .or_default()
+            .extend(origins);
+    }
+
+    /**
+        Check if a channel may fetch the given URL, either from the origin
+        of its manifest or from one that a rewritten manifest referenced
+    */
+    pub fn is_allowed(&self, channel: &str, manifest_url: &Url, url: &Url) -> bool {
+        if url.origin() == manifest_url.origin() {
+            return true;
+        }
+        let channels = self.channels.lock().unwrap();
+        channels
+            .get(channel)
+            .is_some_and(|origins| origins.contains(&origin_of(url)))
+    }
+}
+
+/**
+    A manifest rewritten to go through the proxy.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RewrittenManifest {
+    pub body: String,
+    /// Origins of the upstream URLs that now point at the proxy
+    pub origins: HashSet<String>,
+}
+
+fn origin_of(url: &Url) -> String {
+    url.origin().ascii_serialization()
+}
+
+/**
+    Point a URL reference in a manifest at the proxy, recording its origin.
+
+    Relative references already resolve below the proxied manifest and are
+    left alone. Absolute and root-relative ones would escape the proxy, so
+    they are resolved against the base URL in scope and mapped into it.
+*/
+fn rewrite_reference(
+    reference: &str,
+    base: &Url,
+    proxy_base: &str,
+    origins: &mut HashSet<String>,
+) -> Option<String> {
+    let reference = reference.trim();
+    let escapes = reference.starts_with('/')
+        || reference.starts_with("http://")
+        || reference.starts_with("https://");
+    if !escapes {
+        return None;
+    }
+    let url = base.join(reference).ok()?;
+    origins.insert(origin_of(&url));
+    Some(format!("{}/{}", proxy_base, proxy_path(&url)))
+}
+
+/**
+    Rewrite the URLs in an HLS playlist to go through the proxy.
+*/
+pub fn rewrite_hls(playlist: &str, base: &Url, proxy_base: &str) -> RewrittenManifest {
+    let mut origins = HashSet::new();
+    let mut rewritten = String::with_capacity(playlist.len());
+    for line in playlist.lines() {
+        if line.starts_with('#') {
+            let line =
+                HLS_URI_ATTRIBUTE.replace_all(line, |caps: &Captures| {
+                    match rewrite_reference(&caps[1], base, proxy_base, &mut origins) {
+                        Some(url) => format!(r#"URI="{}""#, url),
+                        None => caps[0].to_string(),
+                    }
+                });
+            rewritten.push_str(&line);
+        } else {
+            match rewrite_reference(line, base, proxy_base, &mut origins) {
+                Some(url) => rewritten.push_str(&url),
+                None => rewritten.push_str(line),
+            }
+        }
+        rewritten.push('\n');
+    }
+    RewrittenManifest {
+        body: rewritten,
+        origins,
+    }
+}
+
+/**
+    Rewrite the URLs in a DASH manifest to go through the proxy.
+
+    Only `BaseURL`, `Location` and segment URL attributes are touched,
+    namespace and scheme URIs stay as they are. References are resolved
+    against the `BaseURL` in scope, following the MPD, Period, AdaptationSet
+    and Representation levels down from the manifest URL.
+*/
+pub fn rewrite_mpd(mpd: &str, base: &Url, proxy_base: &str) -> RewrittenManifest {
+    let mut origins = HashSet::new();
+    // Base URL of each open element, a `BaseURL` child replaces its parent's
+    let mut scopes = vec![base.clone()];
+    let mut body = String::with_capacity(mpd.len());
+    let mut rest = mpd;
+
+    while let Some(caps) = MPD_TAG.captures(rest) {
+        let tag = caps.get(0).unwrap();
+        body.push_str(&rest[..tag.start()]);
+        rest = &rest[tag.end()..];
+
+        let scope = scopes.last().unwrap_or(base).clone();
+        if &caps[1] == "/" {
+            if scopes.len() > 1 {
+                scopes.pop();
+            }
+            body.push_str(tag.as_str());
+            continue;
+        }
+
+        // Entities in URLs are left as is, since `&amp;` never starts a reference
+        let name = &caps[2];
+        let attributes = MPD_URL_ATTRIBUTE.replace_all(&caps[3], |caps: &Captures| {
+            match rewrite_reference(&caps[2], &scope, proxy_base, &mut origins) {
+                Some(url) => format!(r#"{}="{}""#, &caps[1], url),
+                None => caps[0].to_string(),
+            }
+        });
+        body.push_str(&format!("<{}{}{}>", name, attributes, &caps[4]));
+        if &caps[4] == "/" {
+            continue;
+        }
+
+        if name == "BaseURL" || name == "Location" {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = &rest[..end];
+            match rewrite_reference(text, &scope, proxy_base, &mut origins) {
+                Some(url) => body.push_str(&url),
+                None => body.push_str(text),
+            }
+            if name == "BaseURL"
+                && let Ok(url) = scope.join(text.trim())
+                && let Some(parent) = scopes.last_mut()
+            {
+                *parent = url;
+            }
+            rest = &rest[end..];
+        }
+        scopes.push(scopes.last().unwrap_or(base).clone());
+    }
+
+    body.push_str(rest);
+    RewrittenManifest { body, origins }
+}
+
+/**
+    Check if a response may be a manifest, going by its content type
+    or the extension of its URL. Anything else is passed through as is.
+*/
+pub fn may_be_manifest(content_type: &str, url: &Url) -> bool {
+    let content_type = content_type.to_ascii_lowercase();
+    let path = url.path().to_ascii_lowercase();
+    content_type.contains("mpegurl")
+        || content_type.contains("dash+xml")
+        || path.ends_with(".m3u8")
+        || path.ends_with(".mpd")
+}
+
+/**
+    Rewrite a manifest if the content looks like one, otherwise
+    (segments, keys, ...) return `None` to pass the body through as is.
+*/
+pub fn rewrite_manifest(
+    body: &[u8],
+    content_type: &str,
+    base: &Url,
+    proxy_base: &str,
+) -> Option<RewrittenManifest> {
+    let content_type = content_type.to_ascii_lowercase();
+    let path = base.path().to_ascii_lowercase();
+
+    let is_hls = content_type.contains("mpegurl") || path.ends_with(".m3u8");
+    let is_mpd = content_type.contains("dash+xml") || path.ends_with(".mpd");
+    if !is_hls && !is_mpd {
+        return None;
+    }
+
+    let text = std::str::from_utf8(body).ok()?;
+    if is_hls && text.trim_start().starts_with("#EXTM3U") {
+        Some(rewrite_hls(text, base, proxy_base))
+    } else if is_mpd && text.contains("<MPD") {
+        Some(rewrite_mpd(text, base, proxy_base))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROXY: &str = "http://vidproxy:8080/src/ch/proxy";
+
+    #[test]
+    fn test_proxy_path_roundtrip() {
+        let url = Url::parse("https://cdn.example.com:8443/live/master.m3u8?token=abc").unwrap();
+        let path = proxy_path(&url);
+        assert_eq!(
+            path,
+            "https/cdn.example.com:8443/live/master.m3u8?token=abc"
+        );
+
+        let (path, query) = path.split_once('?').unwrap();
+        assert_eq!(upstream_url(path, Some(query)), Some(url));
+
+        assert_eq!(upstream_url("file/etc/passwd", None), None);
+        assert_eq!(upstream_url("https//x", None), None);
+    }
+
+    #[test]
+    fn test_rewrite_hls() {
+        let base = Url::parse("https://cdn.example.com/live/index.m3u8").unwrap();
+        let playlist = "#EXTM3U\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"https://keys.example.com/k?id=1\"\n\
+            #EXT-X-MAP:URI=\"init.mp4\"\n\
+            #EXTINF:6.0,\n\
+            seg1.ts\n\
+            #EXTINF:6.0,\n\
+            /other/seg2.ts\n";
+
+        let rewritten = rewrite_hls(playlist, &base, PROXY);
+        assert_eq!(
+            rewritten.body,
+            "#EXTM3U\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"http://vidproxy:8080/src/ch/proxy/https/keys.example.com/k?id=1\"\n\
+            #EXT-X-MAP:URI=\"init.mp4\"\n\
+            #EXTINF:6.0,\n\
+            seg1.ts\n\
+            #EXTINF:6.0,\n\
+            http://vidproxy:8080/src/ch/proxy/https/cdn.example.com/other/seg2.ts\n"
+        );
+        assert_eq!(
+            rewritten.origins,
+            HashSet::from([
+                "https://keys.example.com".to_string(),
+                "https://cdn.example.com".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_rewrite_mpd() {
+        let base = Url::parse("https://cdn.example.com/live/manifest.mpd").unwrap();
+        let mpd = r#"<MPD xmlns="urn:mpeg:dash:schema:mpd:2011">
+  <BaseURL>https://media.example.com/dash/</BaseURL>
+  <SegmentTemplate media="$Number$.m4s" initialization="/init/$RepresentationID$.mp4"/>
+</MPD>"#;
+
+        let rewritten = rewrite_mpd(mpd, &base, PROXY);
+        assert_eq!(
+            rewritten.body,
+            r#"<MPD xmlns="urn:mpeg:dash:schema:mpd:2011">
+  <BaseURL>http://vidproxy:8080/src/ch/proxy/https/media.example.com/dash/</BaseURL>
+  <SegmentTemplate media="$Number$.m4s" initialization="http://vidproxy:8080/src/ch/proxy/https/media.example.com/init/$RepresentationID$.mp4"/>
+</MPD>"#
+        );
+        assert_eq!(
+            rewritten.origins,
+            HashSet::from(["https://media.example.com".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_rewrite_mpd_base_url_scopes() {
+        let base = Url::parse("https://cdn.example.com/live/manifest.mpd").unwrap();
+        let mpd = r#"<MPD>
+  <Period>
+    <BaseURL>https://media.example.com/dash/</BaseURL>
+    <AdaptationSet>
+      <Representation id="v">
+        <BaseURL>https://video.example.com/v/</BaseURL>
+        <SegmentBase><Initialization sourceURL="/init.mp4"/></SegmentBase>
+      </Representation>
+      <Representation id="a">
+        <SegmentBase><Initialization sourceURL="/init.mp4"/></SegmentBase>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+  <Period>
+    <SegmentTemplate initialization="/init.mp4"/>
+  </Period>
+</MPD>"#;
+
+        let body = rewrite_mpd(mpd, &base, PROXY).body;
+        let inits: Vec<&str> = body
+            .split('"')
+            .filter(|part| part.ends_with("/init.mp4"))
+            .collect();
+        assert_eq!(
+            inits,
+            vec![
+                "http://vidproxy:8080/src/ch/proxy/https/video.example.com/init.mp4",
+                "http://vidproxy:8080/src/ch/proxy/https/media.example.com/init.mp4",
+                "http://vidproxy:8080/src/ch/proxy/https/cdn.example.com/init.mp4",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_upstream_origins() {
+        let manifest = Url::parse("https://cdn.example.com/live/index.m3u8").unwrap();
+        let key = Url::parse("https://keys.example.com/k?id=1").unwrap();
+        let internal = Url::parse("http://169.254.169.254/latest/meta-data").unwrap();
+
+        let origins = UpstreamOrigins::new();
+        let segment = Url::parse("https://cdn.example.com/live/seg1.ts").unwrap();
+        assert!(origins.is_allowed("src/ch", &manifest, &segment));
+        assert!(!origins.is_allowed("src/ch", &manifest, &key));
+
+        origins.allow("src/ch", ["https://keys.example.com".to_string()]);
+        assert!(origins.is_allowed("src/ch", &manifest, &key));
+        assert!(!origins.is_allowed("src/other", &manifest, &key));
+        assert!(!origins.is_allowed("src/ch", &manifest, &internal));
+
+        // Same host on another scheme or port is another origin
+        let http = Url::parse("http://cdn.example.com/live/seg1.ts").unwrap();
+        assert!(!origins.is_allowed("src/ch", &manifest, &http));
+    }
+}
@@ -8,12 +8,21 @@ use std::time::{Duration, Instant};
 
 use anyhow::{Result, anyhow};
 use tokio::sync::{Mutex, RwLock, oneshot, watch};
+use tokio::task::JoinHandle;
 
+use crate::adbreak::{self, AdBreak, AdBreakLog};
 use crate::cdrm;
-use crate::manifest::StreamInfo;
-use crate::proxy;
+use crate::manifest::{DiscoveredChannel, SegmentConfig, StreamInfo};
+use crate::network::Network;
+use crate::preflight::{self, Preflight};
+use crate::profile::{OutputProfile, OutputProfiles};
+use crate::proxy::{self, StreamParams};
+use crate::quality::QualitySnapshot;
 use crate::registry::ChannelId;
-use crate::segments::SegmentManager;
+use crate::segments::{PlaylistContinuity, SegmentManager};
+use crate::slate::{Slate, SlateSplice};
+use crate::transcode::ProcessingConfig;
+use crate::watchdog::{IncidentKind, Incidents};
 
 /**
     State of a pipeline
@@ -55,6 +64,26 @@ pub struct ChannelPipeline {
     last_activity: AtomicU64,
     /// Set to true if pipeline failed due to auth error (needs refresh)
     needs_refresh: Arc<AtomicBool>,
+    /// Keeps the served playlist continuous across restarts
+    continuity: std::sync::Mutex<PlaylistContinuity>,
+    /// Segments of the playlist replaced by the slate during ad breaks
+    slate_splice: std::sync::Mutex<SlateSplice>,
+    /// Parameters of the source as of the latest run
+    stream_params: Arc<std::sync::Mutex<Option<StreamParams>>>,
+    /// Loudness normalization, overlay and ad break handling configured for the channel
+    processing: ProcessingConfig,
+    /// Ad breaks detected in the stream, kept across restarts
+    ad_breaks: Arc<std::sync::Mutex<AdBreakLog>>,
+    /// DNS overrides of the channel's source, for fetches made outside of FFmpeg
+    network: Network,
+    /// Keys supplied through the API, used over any keys of the stream info
+    static_keys: std::sync::Mutex<Vec<String>>,
+    /// Why the pipeline task last panicked, until the store has dealt with it
+    panic: Arc<std::sync::Mutex<Option<String>>>,
+    /// Set once the pipeline is removed from the store, to be replaced by a new one
+    retired: AtomicBool,
+    /// Task running the pipeline, until it is waited on at shutdown
+    task: std::sync::Mutex<Option<JoinHandle<()>>>,
 }
 
 impl ChannelPipeline {
@@ -65,6 +94,8 @@ impl ChannelPipeline {
         segment_duration: Duration,
         output_dir: PathBuf,
         startup_timeout: Duration,
+        processing: ProcessingConfig,
+        network: Network,
     ) -> Self {
         Self {
             channel_id,
@@ -76,6 +107,16 @@ impl ChannelPipeline {
             output_dir,
             startup_timeout,
             last_activity: AtomicU64::new(0),
+            continuity: std::sync::Mutex::new(PlaylistContinuity::default()),
+            slate_splice: std::sync::Mutex::new(SlateSplice::default()),
+            stream_params: Arc::new(std::sync::Mutex::new(None)),
+            processing,
+            ad_breaks: Arc::new(std::sync::Mutex::new(AdBreakLog::default())),
+            network,
+            static_keys: std::sync::Mutex::new(Vec::new()),
+            panic: Arc::new(std::sync::Mutex::new(None)),
+            retired: AtomicBool::new(false),
+            task: std::sync::Mutex::new(None),
         }
     }
 
@@ -83,6 +124,60 @@ impl ChannelPipeline {
         &self.output_dir
     }
 
+    /**
+        Read the current playlist, rewritten to continue across pipeline restarts.
+        If given a slate, it stands in for the segments of replaced ad breaks.
+    */
+    pub fn render_playlist(&self, slate: Option<&Slate>) -> std::io::Result<String> {
+        let playlist = std::fs::read_to_string(self.output_dir.join("playlist.m3u8"))?;
+        let playlist = self.continuity.lock().unwrap().rewrite(&playlist);
+        Ok(match slate {
+            Some(slate) => self.slate_splice.lock().unwrap().apply(
+                &playlist,
+                self.in_replaced_ad_break(),
+                slate.duration(),
+            ),
+            None => playlist,
+        })
+    }
+
+    /**
+        Get the source's codec parameters, once the pipeline has opened it
+    */
+    pub fn stream_params(&self) -> Option<StreamParams> {
+        self.stream_params.lock().unwrap().clone()
+    }
+
+    /**
+        Get the quality of the published stream, and its recent changes
+    */
+    pub fn quality(&self) -> QualitySnapshot {
+        self.segment_manager.quality()
+    }
+
+    /**
+        Get the ad breaks detected in the stream, oldest first
+    */
+    pub fn ad_breaks(&self) -> Vec<AdBreak> {
+        self.ad_breaks.lock().unwrap().events()
+    }
+
+    /**
+        Check if an ad break is ongoing that should be replaced by the slate
+    */
+    fn in_replaced_ad_break(&self) -> bool {
+        if !self
+            .processing
+            .ad_breaks
+            .as_ref()
+            .is_some_and(|a| a.replace)
+        {
+            return false;
+        }
+        let now = chrono::Utc::now().timestamp_millis() as f64 / 1000.0;
+        self.ad_breaks.lock().unwrap().active(now).is_some()
+    }
+
     pub async fn is_running(&self) -> bool {
         matches!(*self.state.lock().await, PipelineState::Running { .. })
     }
@@ -109,6 +204,13 @@ impl ChannelPipeline {
         self.needs_refresh.store(false, Ordering::Relaxed);
     }
 
+    /**
+        Set the keys supplied through the API, used from the next start on
+    */
+    pub fn set_static_keys(&self, keys: Vec<String>) {
+        *self.static_keys.lock().unwrap() = keys;
+    }
+
     /**
         Check if pipeline needs a credential refresh (failed due to auth error)
     */
@@ -116,6 +218,13 @@ impl ChannelPipeline {
         self.needs_refresh.load(Ordering::Relaxed)
     }
 
+    /**
+        Take the reason the pipeline task last panicked, if it did since the last call
+    */
+    pub fn take_panic(&self) -> Option<String> {
+        self.panic.lock().unwrap().take()
+    }
+
     /**
         Clear the refresh flag
     */
@@ -157,15 +266,33 @@ impl ChannelPipeline {
         }
 
         let stream_info = self.stream_info.read().await.clone();
+
+        // Continue the served playlist where the previous run left off
+        if let Ok(previous) = std::fs::read_to_string(self.output_dir.join("playlist.m3u8")) {
+            self.continuity.lock().unwrap().record_restart(&previous);
+        }
         self.segment_manager.clear();
         self.record_activity();
 
         let (stop_tx, stop_rx) = oneshot::channel();
 
         let mpd_url = stream_info.manifest_url.clone();
-        let license_url = stream_info.license_url.clone();
+        let drm_device = stream_info.drm_device.clone();
+        let static_keys = match self.static_keys.lock().unwrap().clone() {
+            keys if keys.is_empty() => stream_info.decryption_keys.clone(),
+            keys => keys,
+        };
+        // Static keys are obtained out-of-band, so no license is requested
+        let license_url = stream_info
+            .license_url
+            .clone()
+            .filter(|_| static_keys.is_empty());
         let headers = stream_info.headers.clone();
-        let output_dir = self.output_dir.clone();
+        let processing = self.processing.clone();
+        let ad_breaks = Arc::clone(&self.ad_breaks);
+        let network = self.network.clone();
+        // FFmpeg writes to the staging directory, segments are published from there
+        let output_dir = self.segment_manager.staging_dir().to_path_buf();
         let segment_duration = self.segment_duration;
         let segment_manager = Arc::clone(&self.segment_manager);
         let state = Arc::clone(&self.state);
@@ -173,8 +300,29 @@ impl ChannelPipeline {
 
         // Clone the Arc to needs_refresh so we can set it from the spawned task
         let needs_refresh = Arc::clone(&self.needs_refresh);
+        let panic = Arc::clone(&self.panic);
 
+        // Compare the source's parameters with the previous run once it is opened
+        let (params_tx, params_rx) = oneshot::channel::<StreamParams>();
+        let stream_params = Arc::clone(&self.stream_params);
+        let params_channel_id = channel_id.clone();
         tokio::spawn(async move {
+            let Ok(params) = params_rx.await else {
+                return;
+            };
+            let mut current = stream_params.lock().unwrap();
+            if let Some(ref previous) = *current
+                && *previous != params
+            {
+                println!(
+                    "[pipeline:{}] Stream parameters changed ({} -> {}), clients will see a discontinuity",
+                    params_channel_id, previous, params
+                );
+            }
+            *current = Some(params);
+        });
+
+        let task = tokio::spawn(async move {
             let reset_state = |set_needs_refresh: bool| {
                 let state = Arc::clone(&state);
                 let needs_refresh = Arc::clone(&needs_refresh);
@@ -189,9 +337,40 @@ impl ChannelPipeline {
                 }
             };
 
+            let client = match network.direct_client() {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("[pipeline:{}] {}", channel_id, e);
+                    reset_state(false).await;
+                    return;
+                }
+            };
+            if network.overrides_dns() {
+                println!(
+                    "[pipeline:{}] DNS overrides apply to key and pre-flight requests only, \
+                     FFmpeg resolves manifest and segment hosts with the system resolver",
+                    channel_id
+                );
+            }
+
             // Fetch decryption keys if needed
-            let decryption_keys: Vec<String> = if let Some(ref lic_url) = license_url {
-                match cdrm::get_decryption_keys(&mpd_url, lic_url).await {
+            let decryption_keys: Vec<String> = if !static_keys.is_empty() {
+                println!(
+                    "[pipeline:{}] Using {} static decryption key(s)",
+                    channel_id,
+                    static_keys.len()
+                );
+                static_keys
+            } else if let Some(ref lic_url) = license_url {
+                match cdrm::get_decryption_keys(
+                    &client,
+                    &mpd_url,
+                    &headers,
+                    lic_url,
+                    drm_device.as_deref(),
+                )
+                .await
+                {
                     Ok(keys) => {
                         println!(
                             "[pipeline:{}] Got {} decryption key(s)",
@@ -215,6 +394,58 @@ impl ChannelPipeline {
                 Vec::new()
             };
 
+            // Load overlay images up front, a channel that must be labeled is not served unlabeled
+            let ad_break_config = processing.ad_breaks.clone();
+            let loaded = match network.http_client() {
+                Ok(source_client) => processing.load(&source_client, &headers).await,
+                Err(e) => Err(e),
+            };
+            let mut processing = match loaded {
+                Ok(processing) => processing,
+                Err(e) => {
+                    eprintln!(
+                        "[pipeline:{}] Failed to set up output processing: {}",
+                        channel_id, e
+                    );
+                    reset_state(false).await;
+                    return;
+                }
+            };
+
+            // Verify the keys against the stream, so a bad key fails fast with a clear error
+            let preflight_result =
+                preflight::check(&client, &mpd_url, &headers, &decryption_keys).await;
+            let decryption_keys = match preflight_result {
+                Ok(Preflight::Clear) => {
+                    // Streams with a clear lead are only encrypted further in,
+                    // so the keys are kept for when encrypted segments show up
+                    if !decryption_keys.is_empty() {
+                        println!(
+                            "[pipeline:{}] Pre-flight: stream starts in the clear, keeping keys",
+                            channel_id
+                        );
+                    }
+                    decryption_keys
+                }
+                Ok(Preflight::Encrypted { scheme }) => {
+                    println!(
+                        "[pipeline:{}] Pre-flight: keys match the stream ({})",
+                        channel_id,
+                        scheme.as_deref().unwrap_or("unknown scheme")
+                    );
+                    decryption_keys
+                }
+                Ok(Preflight::Failed { reason }) => {
+                    eprintln!("[pipeline:{}] Pre-flight failed: {}", channel_id, reason);
+                    reset_state(false).await;
+                    return;
+                }
+                Err(e) => {
+                    println!("[pipeline:{}] Pre-flight check skipped: {}", channel_id, e);
+                    decryption_keys
+                }
+            };
+
             let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
             let shutdown_tx_clone = shutdown_tx.clone();
@@ -223,6 +454,49 @@ impl ChannelPipeline {
                 let _ = shutdown_tx_clone.send(true);
             });
 
+            // SCTE-35 markers are read from the manifest alongside the pipeline
+            if let Some(config) = ad_break_config {
+                tokio::spawn(adbreak::poll_manifest(
+                    client.clone(),
+                    channel_id.clone(),
+                    mpd_url.clone(),
+                    headers.clone(),
+                    Arc::clone(&ad_breaks),
+                    shutdown_rx.clone(),
+                ));
+                if config.heuristic {
+                    processing.ad_break_log = Some(ad_breaks);
+                }
+            }
+
+            // Fetch fresh keys from the same license server if the keys rotate mid-stream
+            let key_refresher: Option<proxy::KeyRefresher> = license_url.map(|lic_url| {
+                let client = client.clone();
+                let mpd_url = mpd_url.clone();
+                let headers = headers.clone();
+                let drm_device = drm_device.clone();
+                let channel_id = channel_id.clone();
+                Box::new(move || {
+                    let client = client.clone();
+                    let mpd_url = mpd_url.clone();
+                    let headers = headers.clone();
+                    let lic_url = lic_url.clone();
+                    let drm_device = drm_device.clone();
+                    let channel_id = channel_id.clone();
+                    Box::pin(async move {
+                        println!("[pipeline:{}] Refreshing decryption keys", channel_id);
+                        cdrm::get_decryption_keys(
+                            &client,
+                            &mpd_url,
+                            &headers,
+                            &lic_url,
+                            drm_device.as_deref(),
+                        )
+                        .await
+                    }) as proxy::KeyFuture
+                }) as proxy::KeyRefresher
+            });
+
             println!("[pipeline:{}] Starting remux pipeline", channel_id);
             let channel_id_clone = channel_id.clone();
             let result = tokio::task::spawn_blocking(move || {
@@ -231,9 +505,13 @@ impl ChannelPipeline {
                     &mpd_url,
                     &headers,
                     &decryption_keys,
+                    key_refresher,
+                    processing,
+                    Some(params_tx),
                     &output_dir,
                     segment_duration,
                     segment_manager,
+                    Some(client),
                     shutdown_rx,
                 ))
             })
@@ -268,12 +546,14 @@ impl ChannelPipeline {
                         "[pipeline:{}] Pipeline task panicked: {}",
                         channel_id_clone, e
                     );
+                    *panic.lock().unwrap() = Some(e.to_string());
                     false
                 }
             };
 
             reset_state(is_auth).await;
         });
+        *self.task.lock().unwrap() = Some(task);
 
         {
             let mut state = self.state.lock().await;
@@ -314,6 +594,40 @@ impl ChannelPipeline {
         }
     }
 
+    /**
+        Stop the pipeline and wait, up to a deadline, for it to flush its
+        sink. Returns false if it was still running at the deadline, in which
+        case its task is aborted.
+    */
+    pub async fn finish(&self, deadline: Instant) -> bool {
+        self.stop().await;
+        let Some(mut task) = self.task.lock().unwrap().take() else {
+            return true;
+        };
+        if tokio::time::timeout_at(deadline.into(), &mut task)
+            .await
+            .is_ok()
+        {
+            return true;
+        }
+        task.abort();
+        false
+    }
+
+    /**
+        Publish the segments the pipeline finished writing, and the playlist
+        listing them. Segments it was still writing are never published.
+    */
+    pub async fn publish(&self) {
+        if let Err(e) = self.segment_manager.publish_async().await {
+            eprintln!(
+                "[pipeline:{}] Failed to publish segments: {}",
+                self.channel_id.to_string(),
+                e
+            );
+        }
+    }
+
     pub async fn wait_for_ready(&self) -> Result<()> {
         let deadline = Instant::now() + self.startup_timeout;
 
@@ -348,81 +662,138 @@ pub struct PipelineConfig {
     pub idle_timeout: Duration,
     pub startup_timeout: Duration,
     pub base_output_dir: PathBuf,
+    /// Named output profiles, overriding the settings above for channels that use them
+    pub profiles: OutputProfiles,
+    /// Restart pipelines that panic while they have clients, instead of on the next request
+    pub watchdog: bool,
 }
 
 /**
-    Manages multiple channel pipelines
+    A channel served with an output profile, or with the default settings.
+*/
+type PipelineKey = (ChannelId, Option<String>);
+
+/**
+    Manages multiple channel pipelines, one for each output profile a channel is served with
 */
 pub struct PipelineStore {
-    pipelines: RwLock<HashMap<ChannelId, Arc<ChannelPipeline>>>,
+    pipelines: RwLock<HashMap<PipelineKey, Arc<ChannelPipeline>>>,
     config: PipelineConfig,
     shutdown_rx: watch::Receiver<bool>,
+    /// Served in place of channels whose pipeline can't run, once generated
+    slate: std::sync::OnceLock<Slate>,
+    /// Where pipelines that panic are recorded
+    incidents: Arc<Incidents>,
 }
 
 impl PipelineStore {
-    pub fn new(config: PipelineConfig, shutdown_rx: watch::Receiver<bool>) -> Self {
+    pub fn new(
+        config: PipelineConfig,
+        incidents: Arc<Incidents>,
+        shutdown_rx: watch::Receiver<bool>,
+    ) -> Self {
         Self {
             pipelines: RwLock::new(HashMap::new()),
             config,
             shutdown_rx,
+            slate: std::sync::OnceLock::new(),
+            incidents,
         }
     }
 
     /**
-        Get or create a pipeline for a channel
+        Get or create a pipeline for a channel, served with the given output profile.
+
+        Segmenting is taken from the given overrides first, then from the
+        output profile, then from the channel, then from the defaults.
     */
     pub async fn get_or_create(
         &self,
         channel_id: &ChannelId,
+        profile_name: Option<&str>,
         stream_info: &StreamInfo,
+        channel: &DiscoveredChannel,
+        segment_overrides: SegmentConfig,
+        network: &Network,
     ) -> Result<Arc<ChannelPipeline>> {
+        let key = (channel_id.clone(), profile_name.map(str::to_string));
+
         // Check if pipeline exists
         {
             let pipelines = self.pipelines.read().await;
-            if let Some(pipeline) = pipelines.get(channel_id) {
+            if let Some(pipeline) = pipelines.get(&key) {
                 return Ok(Arc::clone(pipeline));
             }
         }
 
+        let profile = match profile_name {
+            Some(name) => Some(
+                self.config
+                    .profiles
+                    .get(name)
+                    .ok_or_else(|| anyhow!("Unknown output profile '{}'", name))?,
+            ),
+            None => None,
+        };
+
         // Create new pipeline
         let mut pipelines = self.pipelines.write().await;
 
         // Double-check after acquiring write lock
-        if let Some(pipeline) = pipelines.get(channel_id) {
+        if let Some(pipeline) = pipelines.get(&key) {
             return Ok(Arc::clone(pipeline));
         }
 
-        // Create channel-specific output directory
-        let channel_dir = self
-            .config
-            .base_output_dir
-            .join(format!("{}__{}", channel_id.source, channel_id.id));
+        // Create channel-specific output directory, separate for each profile
+        let dir_name = match profile_name {
+            Some(name) => format!("{}__{}__{}", channel_id.source, channel_id.id, name),
+            None => format!("{}__{}", channel_id.source, channel_id.id),
+        };
+        let channel_dir = self.config.base_output_dir.join(dir_name);
         std::fs::create_dir_all(&channel_dir)?;
 
+        let segments = segment_overrides
+            .or(profile.map(OutputProfile::segments).unwrap_or_default())
+            .or(channel.segments);
         let segment_manager = Arc::new(SegmentManager::new(
             channel_dir.clone(),
-            self.config.segment_count,
+            segments.count.unwrap_or(self.config.segment_count),
         ));
+        let segment_duration = segments
+            .duration
+            .map(Duration::from_secs)
+            .unwrap_or(self.config.segment_duration);
 
         let pipeline = Arc::new(ChannelPipeline::new(
             channel_id.clone(),
             stream_info.clone(),
             segment_manager,
-            self.config.segment_duration,
+            segment_duration,
             channel_dir,
             self.config.startup_timeout,
+            ProcessingConfig::for_channel(channel, profile),
+            network.clone(),
         ));
 
-        // Start idle check task for this pipeline
+        // Start idle check task for this pipeline, standby channels never idle out
         let pipeline_clone = Arc::clone(&pipeline);
         let idle_timeout = self.config.idle_timeout;
+        let standby = channel.standby;
+        let watchdog = self.config.watchdog;
+        let incidents = Arc::clone(&self.incidents);
         let mut shutdown_rx = self.shutdown_rx.clone();
 
         tokio::spawn(async move {
             loop {
                 tokio::select! {
                     _ = tokio::time::sleep(Duration::from_secs(5)) => {
-                        if pipeline_clone.is_running().await {
+                        if pipeline_clone.retired.load(Ordering::Relaxed) {
+                            return;
+                        }
+                        if let Some(message) = pipeline_clone.take_panic() {
+                            recover_from_panic(&pipeline_clone, message, watchdog, idle_timeout, &incidents).await;
+                        }
+                        if !standby && pipeline_clone.is_running().await {
                             let idle_secs = pipeline_clone.seconds_since_activity();
                             if idle_secs > idle_timeout.as_secs() {
                                 println!(
@@ -444,15 +815,93 @@ impl PipelineStore {
             }
         });
 
-        pipelines.insert(channel_id.clone(), Arc::clone(&pipeline));
+        pipelines.insert(key, Arc::clone(&pipeline));
         Ok(pipeline)
     }
 
+    /**
+        Check if an output profile with the given name is configured
+    */
+    pub fn has_profile(&self, name: &str) -> bool {
+        self.config.profiles.get(name).is_some()
+    }
+
+    /**
+        Get the directory that channel output directories are created in
+    */
+    pub fn base_output_dir(&self) -> &std::path::Path {
+        &self.config.base_output_dir
+    }
+
+    /**
+        Set the slate served for channels that are down
+    */
+    pub fn set_slate(&self, slate: Slate) {
+        let _ = self.slate.set(slate);
+    }
+
+    /**
+        Get the slate served for channels that are down, if one was generated
+    */
+    pub fn slate(&self) -> Option<&Slate> {
+        self.slate.get()
+    }
+
     /**
         Get an existing pipeline (without creating)
     */
-    pub async fn get(&self, channel_id: &ChannelId) -> Option<Arc<ChannelPipeline>> {
-        self.pipelines.read().await.get(channel_id).cloned()
+    pub async fn get(
+        &self,
+        channel_id: &ChannelId,
+        profile_name: Option<&str>,
+    ) -> Option<Arc<ChannelPipeline>> {
+        let key = (channel_id.clone(), profile_name.map(str::to_string));
+        self.pipelines.read().await.get(&key).cloned()
+    }
+
+    /**
+        Get the pipelines of a channel, for all profiles it is served with
+    */
+    pub async fn for_channel(&self, channel_id: &ChannelId) -> Vec<Arc<ChannelPipeline>> {
+        let pipelines = self.pipelines.read().await;
+        pipelines
+            .iter()
+            .filter(|((id, _), _)| id == channel_id)
+            .map(|(_, pipeline)| Arc::clone(pipeline))
+            .collect()
+    }
+
+    /**
+        Get all pipelines, with their channel IDs and output profiles
+    */
+    pub async fn all(&self) -> Vec<(ChannelId, Option<String>, Arc<ChannelPipeline>)> {
+        let pipelines = self.pipelines.read().await;
+        pipelines
+            .iter()
+            .map(|((id, profile), pipeline)| (id.clone(), profile.clone(), Arc::clone(pipeline)))
+            .collect()
+    }
+
+    /**
+        Stop and remove the pipelines of a channel, so that the next request
+        creates them again with the channel's current settings
+    */
+    pub async fn remove_channel(&self, channel_id: &ChannelId) {
+        let removed: Vec<_> = {
+            let mut pipelines = self.pipelines.write().await;
+            let keys: Vec<PipelineKey> = pipelines
+                .keys()
+                .filter(|(id, _)| id == channel_id)
+                .cloned()
+                .collect();
+            keys.iter()
+                .filter_map(|key| pipelines.remove(key))
+                .collect()
+        };
+        for pipeline in removed {
+            pipeline.retired.store(true, Ordering::Relaxed);
+            pipeline.stop().await;
+        }
     }
 
     /**
@@ -464,4 +913,65 @@ impl PipelineStore {
             pipeline.stop().await;
         }
     }
+
+    /**
+        Stop all pipelines at once and wait, up to a deadline, for them to
+        flush their sinks. Returns the channels of the pipelines that were
+        still running at the deadline, which are aborted.
+    */
+    pub async fn finish_all(&self, deadline: Instant) -> Vec<ChannelId> {
+        let pipelines: Vec<_> = self.pipelines.read().await.values().cloned().collect();
+        let finished =
+            futures::future::join_all(pipelines.iter().map(|pipeline| pipeline.finish(deadline)))
+                .await;
+        pipelines
+            .iter()
+            .zip(finished)
+            .filter(|(_, finished)| !finished)
+            .map(|(pipeline, _)| pipeline.channel_id.clone())
+            .collect()
+    }
+
+    /**
+        Publish the finished segments and playlists of all pipelines
+    */
+    pub async fn publish_all(&self) {
+        for pipeline in self.pipelines.read().await.values() {
+            pipeline.publish().await;
+        }
+    }
+}
+
+/**
+    Record a pipeline that panicked, and with the watchdog enabled, start it
+    again right away if it still had clients. Its channel's credentials and
+    stream info are kept, so it restarts where it left off.
+*/
+async fn recover_from_panic(
+    pipeline: &ChannelPipeline,
+    message: String,
+    watchdog: bool,
+    idle_timeout: Duration,
+    incidents: &Incidents,
+) {
+    let subsystem = format!("pipeline:{}", pipeline.channel_id.to_string());
+    let watched = pipeline.seconds_since_activity() <= idle_timeout.as_secs();
+    if !watchdog || !watched {
+        incidents.record(
+            subsystem,
+            IncidentKind::Panic,
+            message,
+            "restarts on the next request",
+        );
+        return;
+    }
+
+    incidents.record(subsystem, IncidentKind::Panic, message, "restarted");
+    if let Err(e) = pipeline.ensure_running().await {
+        eprintln!(
+            "[pipeline:{}] Failed to restart after panic: {}",
+            pipeline.channel_id.to_string(),
+            e
+        );
+    }
 }
@@ -0,0 +1,486 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+use crate::manifest::{ChannelEntry, SegmentConfig};
+use crate::registry::ChannelId;
+
+/**
+    Preferences of a set of channels, with channels as "source:id".
+
+    Both the format of the preferences file and of exported channel sets,
+    so that the preferences of one installation can be imported into another.
+*/
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct ChannelSet {
+    /// Favorite channels, in playlist order
+    #[serde(default)]
+    pub favorites: Vec<String>,
+    #[serde(default)]
+    pub hidden: BTreeSet<String>,
+    /// Static `kid:key` pairs set through the API, by channel
+    #[serde(default)]
+    pub decryption_keys: BTreeMap<String, Vec<String>>,
+    /// Segmenting set through the API, by channel
+    #[serde(default)]
+    pub segments: BTreeMap<String, SegmentConfig>,
+}
+
+impl ChannelSet {
+    /**
+        Every channel that has any preference set.
+    */
+    pub fn channels(&self) -> BTreeSet<String> {
+        self.favorites
+            .iter()
+            .chain(&self.hidden)
+            .chain(self.decryption_keys.keys())
+            .chain(self.segments.keys())
+            .cloned()
+            .collect()
+    }
+
+    /**
+        Remove every preference of a channel.
+    */
+    fn remove(&mut self, key: &str) {
+        self.favorites.retain(|f| f != key);
+        self.hidden.remove(key);
+        self.decryption_keys.remove(key);
+        self.segments.remove(key);
+    }
+
+    /**
+        Copy every preference of a channel from another set.
+    */
+    fn copy_from(&mut self, other: &ChannelSet, key: &str) {
+        if other.favorites.iter().any(|f| f == key) {
+            self.favorites.push(key.to_string());
+        } else if other.hidden.contains(key) {
+            self.hidden.insert(key.to_string());
+        }
+        if let Some(keys) = other.decryption_keys.get(key) {
+            self.decryption_keys.insert(key.to_string(), keys.clone());
+        }
+        if let Some(segments) = other.segments.get(key) {
+            self.segments.insert(key.to_string(), *segments);
+        }
+    }
+}
+
+/**
+    How to settle a channel that has preferences both here and in an
+    imported channel set.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportConflicts {
+    /// Keep the preferences already here
+    #[default]
+    Keep,
+    /// Replace them with the imported ones
+    Replace,
+}
+
+/**
+    Which channels of an imported channel set were added, replaced or kept.
+*/
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct ImportSummary {
+    pub added: Vec<String>,
+    pub replaced: Vec<String>,
+    pub kept: Vec<String>,
+}
+
+/**
+    Favorite and hidden channels, and keys and segmenting supplied for
+    channels, shared by all clients.
+
+    Favorites are listed first in the channel playlists, in the order they
+    were added (or set through the API), and hidden channels are left out.
+    A channel is never both: favoriting a channel unhides it and vice versa.
+
+    Every change is written to the preferences file right away, if there is
+    one, so nothing is lost if the proxy is killed instead of shut down.
+*/
+pub struct ChannelPreferences {
+    path: Option<PathBuf>,
+    state: RwLock<ChannelSet>,
+    updated_at: RwLock<Option<SystemTime>>,
+}
+
+impl ChannelPreferences {
+    /**
+        Create empty preferences, persisted to the given file if any.
+    */
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            state: RwLock::new(ChannelSet::default()),
+            updated_at: RwLock::new(None),
+        }
+    }
+
+    /**
+        Load preferences from a file written by a previous run, starting
+        out empty if it does not exist yet.
+    */
+    pub fn load(path: &Path) -> Result<Self> {
+        let preferences = Self::new(Some(path.to_path_buf()));
+        if !path.exists() {
+            return Ok(preferences);
+        }
+
+        let json = std::fs::read(path).map_err(|e| anyhow!("Failed to read {:?}: {}", path, e))?;
+        let file: ChannelSet = serde_json::from_slice(&json)
+            .map_err(|e| anyhow!("Failed to parse {:?}: {}", path, e))?;
+        *preferences.state.write().unwrap() = file;
+        *preferences.updated_at.write().unwrap() =
+            std::fs::metadata(path).and_then(|m| m.modified()).ok();
+
+        Ok(preferences)
+    }
+
+    /**
+        When the preferences last changed, for cache validation of playlists.
+    */
+    pub fn updated_at(&self) -> Option<SystemTime> {
+        *self.updated_at.read().unwrap()
+    }
+
+    pub fn is_favorite(&self, id: &ChannelId) -> bool {
+        self.favorite_rank(id).is_some()
+    }
+
+    pub fn is_hidden(&self, id: &ChannelId) -> bool {
+        self.state.read().unwrap().hidden.contains(&id.to_string())
+    }
+
+    /**
+        Position of a channel among the favorites.
+    */
+    fn favorite_rank(&self, id: &ChannelId) -> Option<usize> {
+        let key = id.to_string();
+        self.state
+            .read()
+            .unwrap()
+            .favorites
+            .iter()
+            .position(|f| *f == key)
+    }
+
+    /**
+        Favorite channels, in playlist order.
+    */
+    pub fn favorites(&self) -> Vec<ChannelId> {
+        let state = self.state.read().unwrap();
+        state
+            .favorites
+            .iter()
+            .filter_map(|f| ChannelId::parse(f))
+            .collect()
+    }
+
+    pub fn hidden(&self) -> Vec<ChannelId> {
+        let state = self.state.read().unwrap();
+        state
+            .hidden
+            .iter()
+            .filter_map(|h| ChannelId::parse(h))
+            .collect()
+    }
+
+    /**
+        Add a channel to the end of the favorites, or remove it.
+    */
+    pub fn set_favorite(&self, id: &ChannelId, favorite: bool) -> Result<()> {
+        let key = id.to_string();
+        self.update(|state| {
+            state.favorites.retain(|f| *f != key);
+            if favorite {
+                state.hidden.remove(&key);
+                state.favorites.push(key);
+            }
+        })
+    }
+
+    /**
+        Replace the favorites, keeping the given order.
+    */
+    pub fn set_favorites(&self, ids: &[ChannelId]) -> Result<()> {
+        self.update(|state| {
+            state.favorites.clear();
+            for id in ids {
+                let key = id.to_string();
+                if !state.favorites.contains(&key) {
+                    state.hidden.remove(&key);
+                    state.favorites.push(key);
+                }
+            }
+        })
+    }
+
+    pub fn set_hidden(&self, id: &ChannelId, hidden: bool) -> Result<()> {
+        let key = id.to_string();
+        self.update(|state| {
+            if hidden {
+                state.favorites.retain(|f| *f != key);
+                state.hidden.insert(key);
+            } else {
+                state.hidden.remove(&key);
+            }
+        })
+    }
+
+    /**
+        Static decryption keys supplied for a channel through the API.
+    */
+    pub fn decryption_keys(&self, id: &ChannelId) -> Vec<String> {
+        let state = self.state.read().unwrap();
+        state
+            .decryption_keys
+            .get(&id.to_string())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /**
+        Set the static decryption keys of a channel, or clear them if empty.
+    */
+    pub fn set_decryption_keys(&self, id: &ChannelId, keys: Vec<String>) -> Result<()> {
+        let key = id.to_string();
+        self.update(|state| {
+            if keys.is_empty() {
+                state.decryption_keys.remove(&key);
+            } else {
+                state.decryption_keys.insert(key, keys);
+            }
+        })
+    }
+
+    /**
+        Segmenting set for a channel through the API, over that of its
+        manifest and output profile.
+    */
+    pub fn segments(&self, id: &ChannelId) -> SegmentConfig {
+        let state = self.state.read().unwrap();
+        state
+            .segments
+            .get(&id.to_string())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /**
+        Set the segmenting of a channel, or clear it if nothing is set.
+    */
+    pub fn set_segments(&self, id: &ChannelId, segments: SegmentConfig) -> Result<()> {
+        let key = id.to_string();
+        self.update(|state| {
+            if segments == SegmentConfig::default() {
+                state.segments.remove(&key);
+            } else {
+                state.segments.insert(key, segments);
+            }
+        })
+    }
+
+    /**
+        Export the preferences of the given channels, or of all channels.
+    */
+    pub fn export(&self, channels: Option<&[ChannelId]>) -> ChannelSet {
+        let state = self.state.read().unwrap();
+        let Some(channels) = channels else {
+            return state.clone();
+        };
+
+        let mut set = ChannelSet::default();
+        for id in channels {
+            set.copy_from(&state, &id.to_string());
+        }
+        set.favorites
+            .sort_by_key(|f| state.favorites.iter().position(|s| s == f));
+        set
+    }
+
+    /**
+        Import the preferences of a channel set, one channel at a time.
+
+        Channels without preferences here get the imported ones, and those
+        with preferences both here and in the set are kept or replaced
+        as a whole. Imported favorites go after the existing ones.
+    */
+    pub fn import(&self, set: &ChannelSet, conflicts: ImportConflicts) -> Result<ImportSummary> {
+        if let Some(invalid) = set
+            .channels()
+            .iter()
+            .find(|c| ChannelId::parse(c).is_none())
+        {
+            return Err(anyhow!(
+                "Invalid channel {:?}, expected \"source:id\"",
+                invalid
+            ));
+        }
+
+        let mut summary = ImportSummary::default();
+        self.update(|state| {
+            let existing = state.channels();
+            // Favorites first, so they keep their imported order
+            let mut keys: Vec<String> = set.favorites.clone();
+            keys.extend(
+                set.channels()
+                    .into_iter()
+                    .filter(|c| !set.favorites.contains(c)),
+            );
+
+            for key in keys {
+                if !existing.contains(&key) {
+                    state.copy_from(set, &key);
+                    summary.added.push(key);
+                } else if conflicts == ImportConflicts::Replace {
+                    state.remove(&key);
+                    state.copy_from(set, &key);
+                    summary.replaced.push(key);
+                } else {
+                    summary.kept.push(key);
+                }
+            }
+        })?;
+        Ok(summary)
+    }
+
+    /**
+        Arrange a source's channels for its playlist: hidden channels are
+        removed, and favorites are moved to the front in their own order.
+        The remaining channels keep their relative order.
+    */
+    pub fn arrange(&self, source: &str, mut channels: Vec<ChannelEntry>) -> Vec<ChannelEntry> {
+        channels.retain(|e| !self.is_hidden(&ChannelId::new(source, &e.channel.id)));
+        channels.sort_by_cached_key(|e| {
+            self.favorite_rank(&ChannelId::new(source, &e.channel.id))
+                .unwrap_or(usize::MAX)
+        });
+        channels
+    }
+
+    fn update(&self, change: impl FnOnce(&mut ChannelSet)) -> Result<()> {
+        let snapshot = {
+            let mut state = self.state.write().unwrap();
+            change(&mut state);
+            state.clone()
+        };
+        *self.updated_at.write().unwrap() = Some(SystemTime::now());
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let json = serde_json::to_vec_pretty(&snapshot)?;
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, json)
+            .map_err(|e| anyhow!("Failed to write {:?}: {}", tmp_path, e))?;
+        std::fs::rename(&tmp_path, path)
+            .map_err(|e| anyhow!("Failed to rename {:?} to {:?}: {}", tmp_path, path, e))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(channel: &str) -> ChannelId {
+        ChannelId::new("src", channel)
+    }
+
+    #[test]
+    fn test_favorite_and_hidden_are_exclusive() {
+        let preferences = ChannelPreferences::new(None);
+        preferences.set_favorite(&id("a"), true).unwrap();
+        preferences.set_hidden(&id("a"), true).unwrap();
+        assert!(preferences.is_hidden(&id("a")));
+        assert!(!preferences.is_favorite(&id("a")));
+
+        preferences.set_favorite(&id("a"), true).unwrap();
+        assert!(!preferences.is_hidden(&id("a")));
+        assert!(preferences.is_favorite(&id("a")));
+    }
+
+    #[test]
+    fn test_set_favorites_order() {
+        let preferences = ChannelPreferences::new(None);
+        preferences.set_favorite(&id("a"), true).unwrap();
+        preferences.set_favorite(&id("b"), true).unwrap();
+        assert_eq!(preferences.favorites(), vec![id("a"), id("b")]);
+
+        preferences
+            .set_favorites(&[id("c"), id("b"), id("c")])
+            .unwrap();
+        assert_eq!(preferences.favorites(), vec![id("c"), id("b")]);
+    }
+
+    #[test]
+    fn test_export_and_import() {
+        let source = ChannelPreferences::new(None);
+        source.set_favorite(&id("a"), true).unwrap();
+        source.set_favorite(&id("b"), true).unwrap();
+        source.set_hidden(&id("c"), true).unwrap();
+        source
+            .set_decryption_keys(&id("b"), vec!["aa:bb".to_string()])
+            .unwrap();
+
+        let set = source.export(Some(&[id("b"), id("c"), id("d")]));
+        assert_eq!(set.favorites, vec!["src:b"]);
+        assert_eq!(set.channels().len(), 2);
+
+        let target = ChannelPreferences::new(None);
+        target.set_favorite(&id("x"), true).unwrap();
+        target.set_favorite(&id("c"), true).unwrap();
+
+        let summary = target.import(&set, ImportConflicts::Keep).unwrap();
+        assert_eq!(summary.added, vec!["src:b"]);
+        assert_eq!(summary.kept, vec!["src:c"]);
+        assert_eq!(target.favorites(), vec![id("x"), id("c"), id("b")]);
+        assert_eq!(target.decryption_keys(&id("b")), vec!["aa:bb"]);
+
+        let summary = target.import(&set, ImportConflicts::Replace).unwrap();
+        assert_eq!(summary.replaced, vec!["src:b", "src:c"]);
+        assert!(target.is_hidden(&id("c")));
+        assert_eq!(target.favorites(), vec![id("x"), id("b")]);
+
+        let mut invalid = ChannelSet::default();
+        invalid.hidden.insert("no-source".to_string());
+        assert!(target.import(&invalid, ImportConflicts::Keep).is_err());
+    }
+
+    #[test]
+    fn test_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("preferences.json");
+
+        let preferences = ChannelPreferences::load(&path).unwrap();
+        assert!(preferences.updated_at().is_none());
+        preferences.set_favorite(&id("a"), true).unwrap();
+        preferences.set_hidden(&id("b"), true).unwrap();
+        preferences
+            .set_decryption_keys(&id("c"), vec!["aa:bb".to_string()])
+            .unwrap();
+        let segments = SegmentConfig {
+            duration: Some(2),
+            count: None,
+        };
+        preferences.set_segments(&id("d"), segments).unwrap();
+
+        let loaded = ChannelPreferences::load(&path).unwrap();
+        assert_eq!(loaded.favorites(), vec![id("a")]);
+        assert_eq!(loaded.hidden(), vec![id("b")]);
+        assert_eq!(loaded.decryption_keys(&id("c")), vec!["aa:bb"]);
+        assert!(loaded.decryption_keys(&id("a")).is_empty());
+        assert_eq!(loaded.segments(&id("d")), segments);
+        assert_eq!(loaded.segments(&id("a")), SegmentConfig::default());
+        assert!(loaded.updated_at().is_some());
+    }
+}
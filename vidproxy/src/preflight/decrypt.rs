@@ -0,0 +1,272 @@
+use aes::Aes128;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+
+use super::mp4::{EncryptedSample, TrackEncryption};
+
+/**
+    Decrypt a sample with a content key, using the given protection scheme.
+
+    Supports `cenc` (AES-CTR) and `cbcs` (AES-CBC with a pattern), which
+    cover what streaming services use. Returns `None` for other schemes, or
+    if the key or IV is malformed.
+*/
+pub fn decrypt_sample(
+    scheme: &str,
+    encryption: &TrackEncryption,
+    key: &[u8],
+    sample: &EncryptedSample,
+) -> Option<Vec<u8>> {
+    let cipher = Aes128::new_from_slice(key).ok()?;
+    let iv = match encryption.constant_iv {
+        Some(ref iv) => iv.as_slice(),
+        None => sample.iv.as_slice(),
+    };
+    if iv.len() != 8 && iv.len() != 16 {
+        return None;
+    }
+    let mut iv_block = [0u8; 16];
+    iv_block[..iv.len()].copy_from_slice(iv);
+
+    let mut data = sample.data.to_vec();
+    let ranges = protected_ranges(sample);
+    match scheme {
+        "cenc" => {
+            // The keystream runs on across all protected ranges of the sample
+            let mut counter = u128::from_be_bytes(iv_block);
+            let mut keystream = [0u8; 16];
+            let mut used = 16;
+            for (start, end) in ranges {
+                for byte in data.get_mut(start..end)? {
+                    if used == 16 {
+                        keystream = counter.to_be_bytes();
+                        cipher.encrypt_block((&mut keystream).into());
+                        counter = counter.wrapping_add(1);
+                        used = 0;
+                    }
+                    *byte ^= keystream[used];
+                    used += 1;
+                }
+            }
+        }
+        "cbcs" => {
+            // Each protected range restarts the chain, trailing partial blocks stay clear
+            let (crypt, skip) = match encryption.pattern {
+                (0, 0) => (1, 0),
+                (crypt, skip) => (crypt as usize, skip as usize),
+            };
+            for (start, end) in ranges {
+                let mut chain = iv_block;
+                let range = data.get_mut(start..end)?;
+                for (index, block) in range.chunks_exact_mut(16).enumerate() {
+                    if index % (crypt + skip) >= crypt {
+                        continue;
+                    }
+                    let encrypted: [u8; 16] = (*block).try_into().unwrap();
+                    cipher.decrypt_block(block.into());
+                    for (byte, previous) in block.iter_mut().zip(chain) {
+                        *byte ^= previous;
+                    }
+                    chain = encrypted;
+                }
+            }
+        }
+        _ => return None,
+    }
+    Some(data)
+}
+
+/**
+    Byte ranges of a sample that are encrypted, per its subsample entries.
+*/
+fn protected_ranges(sample: &EncryptedSample) -> Vec<(usize, usize)> {
+    if sample.subsamples.is_empty() {
+        return vec![(0, sample.data.len())];
+    }
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+    for &(clear, protected) in &sample.subsamples {
+        offset += clear;
+        ranges.push((offset, offset + protected));
+        offset += protected;
+    }
+    ranges
+}
+
+/**
+    Check if a decrypted sample is well-formed for its original format.
+
+    Video is checked for a valid sequence of length-prefixed NAL units,
+    AAC for a valid first syntax element, and AC-3 for its sync word.
+    Returns `None` for formats that can't be checked.
+
+    Packagers often leave NAL lengths and headers of video in the clear, in
+    which case only the encrypted slice data differs with a wrong key, and
+    the sample can't be told apart from a correctly decrypted one.
+*/
+pub fn is_well_formed(format: &str, nal_length_size: Option<usize>, sample: &[u8]) -> Option<bool> {
+    match format {
+        "avc1" | "avc3" => Some(is_nal_sequence(sample, nal_length_size?, |header| {
+            matches!(header[0] & 0x1f, 1..=23)
+        })),
+        "hvc1" | "hev1" | "dvh1" | "dvhe" => {
+            Some(is_nal_sequence(sample, nal_length_size?, |header| {
+                header.len() >= 2 && (header[0] >> 1) & 0x3f < 48 && header[1] & 0x07 != 0
+            }))
+        }
+        "mp4a" => Some(is_aac_frame(sample)),
+        "ac-3" | "ec-3" => Some(sample.starts_with(&[0x0b, 0x77])),
+        _ => None,
+    }
+}
+
+/**
+    Check that a sample is made up exactly of length-prefixed NAL units,
+    each with the forbidden zero bit clear and a header accepted by `valid`.
+*/
+fn is_nal_sequence(sample: &[u8], length_size: usize, valid: impl Fn(&[u8]) -> bool) -> bool {
+    let mut rest = sample;
+    while !rest.is_empty() {
+        let Some(prefix) = rest.get(..length_size) else {
+            return false;
+        };
+        let length = prefix
+            .iter()
+            .fold(0usize, |length, &b| (length << 8) | b as usize);
+        let Some(nal) = rest.get(length_size..length_size + length) else {
+            return false;
+        };
+        if nal.is_empty() || nal[0] & 0x80 != 0 || !valid(nal) {
+            return false;
+        }
+        rest = &rest[length_size + length..];
+    }
+    !sample.is_empty()
+}
+
+/**
+    Check the first syntax element of a raw AAC frame: it must be a single
+    channel, channel pair or LFE element, with the reserved bit of its
+    stream info clear.
+*/
+fn is_aac_frame(sample: &[u8]) -> bool {
+    let bit = |index: usize| sample.get(index / 8).map(|b| (b >> (7 - index % 8)) & 1);
+    let Some(element) = sample.first().map(|b| b >> 5) else {
+        return false;
+    };
+    // Bits before ics_reserved_bit: element id (3), instance tag (4), then
+    // global gain (8) for single channels, or common_window (1) for pairs
+    let reserved_bit = match element {
+        0 | 3 => 15,
+        1 => match bit(7) {
+            Some(1) => 8,
+            Some(_) => 16,
+            None => return false,
+        },
+        _ => return false,
+    };
+    bit(reserved_bit) == Some(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encryption(pattern: (u8, u8)) -> TrackEncryption {
+        TrackEncryption {
+            is_protected: true,
+            kid: "00".repeat(16),
+            iv_size: 16,
+            constant_iv: None,
+            pattern,
+        }
+    }
+
+    const KEY: [u8; 16] = [
+        0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f,
+        0x3c,
+    ];
+
+    const PLAINTEXT: [u8; 16] = [
+        0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17,
+        0x2a,
+    ];
+
+    #[test]
+    fn test_decrypt_cenc() {
+        // NIST SP 800-38A, F.5.1 CTR-AES128
+        let ciphertext = [
+            0x87, 0x4d, 0x61, 0x91, 0xb6, 0x20, 0xe3, 0x26, 0x1b, 0xef, 0x68, 0x64, 0x99, 0x0d,
+            0xb6, 0xce,
+        ];
+        let counter: Vec<u8> = (0xf0..=0xff).collect();
+
+        // A clear byte in front, then the protected block
+        let data = [&[0x09][..], &ciphertext].concat();
+        let sample = EncryptedSample {
+            data: &data,
+            iv: counter,
+            subsamples: vec![(1, 16)],
+        };
+        let decrypted = decrypt_sample("cenc", &encryption((0, 0)), &KEY, &sample).unwrap();
+        assert_eq!(decrypted, [&[0x09][..], &PLAINTEXT].concat());
+    }
+
+    #[test]
+    fn test_decrypt_cbcs() {
+        // NIST SP 800-38A, F.2.1 CBC-AES128
+        let ciphertext = [
+            0x76, 0x49, 0xab, 0xac, 0x81, 0x19, 0xb2, 0x46, 0xce, 0xe9, 0x8e, 0x9b, 0x12, 0xe9,
+            0x19, 0x7d,
+        ];
+        let iv: Vec<u8> = (0x00..=0x0f).collect();
+
+        // The second block is skipped by the 1:9 pattern, the partial block stays clear
+        let data = [&ciphertext[..], &[0xaa; 16], &[0xbb; 3]].concat();
+        let sample = EncryptedSample {
+            data: &data,
+            iv,
+            subsamples: vec![],
+        };
+        let decrypted = decrypt_sample("cbcs", &encryption((1, 9)), &KEY, &sample).unwrap();
+        assert_eq!(
+            decrypted,
+            [&PLAINTEXT[..], &[0xaa; 16], &[0xbb; 3]].concat()
+        );
+    }
+
+    #[test]
+    fn test_unsupported_scheme() {
+        let sample = EncryptedSample {
+            data: &[0; 16],
+            iv: vec![0; 8],
+            subsamples: vec![],
+        };
+        assert!(decrypt_sample("cens", &encryption((0, 0)), &KEY, &sample).is_none());
+    }
+
+    #[test]
+    fn test_nal_sequence() {
+        // IDR slice and SEI, with 4-byte lengths
+        let sample = [0, 0, 0, 2, 0x65, 0x88, 0, 0, 0, 1, 0x06];
+        assert_eq!(is_well_formed("avc1", Some(4), &sample), Some(true));
+        // Lengths that run past the end of the sample
+        assert_eq!(is_well_formed("avc1", Some(4), &sample[..10]), Some(false));
+        // Forbidden zero bit set
+        assert_eq!(
+            is_well_formed("avc1", Some(4), &[0, 0, 0, 1, 0xe5]),
+            Some(false)
+        );
+        assert_eq!(is_well_formed("avc1", None, &sample), None);
+    }
+
+    #[test]
+    fn test_aac_frame() {
+        // Channel pair with common_window set and ics_reserved_bit clear
+        assert_eq!(is_well_formed("mp4a", None, &[0x21, 0x00]), Some(true));
+        // Single channel with ics_reserved_bit set
+        assert_eq!(is_well_formed("mp4a", None, &[0x00, 0x01]), Some(false));
+        // END element first
+        assert_eq!(is_well_formed("mp4a", None, &[0xe0, 0x00]), Some(false));
+        assert_eq!(is_well_formed("opus", None, &[0x00]), None);
+    }
+}
@@ -0,0 +1,250 @@
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use drm_widevine::core::KeyString;
+
+use crate::manifest::request_headers;
+
+mod decrypt;
+mod mp4;
+pub mod mpd;
+
+use self::mp4::TrackEncryption;
+
+/**
+    Outcome of a pre-flight check.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Preflight {
+    /// The probed segments are not encrypted, though later ones may be (clear lead)
+    Clear,
+    /// The stream is encrypted and a key is available for every KID in use
+    Encrypted { scheme: Option<String> },
+    /// The stream can't be decrypted with the available keys
+    Failed { reason: String },
+}
+
+/**
+    Fetch a segment with the stream's upstream headers.
+*/
+async fn fetch_segment(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &[(String, String)],
+) -> Result<Vec<u8>> {
+    let mut request = client.get(url);
+    for (name, value) in request_headers(headers, url) {
+        request = request.header(name, value);
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to fetch segment '{}': {}", url, e))?;
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Failed to fetch segment '{}': status {}",
+            url,
+            response.status()
+        ));
+    }
+
+    Ok(response.bytes().await?.to_vec())
+}
+
+/**
+    Number of samples decrypted to verify a key.
+*/
+const VERIFY_SAMPLES: usize = 8;
+
+/**
+    Time limit for the whole check, after which the pipeline starts unchecked.
+*/
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/**
+    Check the stream against the obtained content keys before starting the
+    pipeline, by fetching the init segment and a recent media segment of
+    its video and audio, and decrypting their first samples.
+
+    Reports a failure with a descriptive reason if the stream is encrypted
+    but no key matches the KID it uses, or if there are no keys at all.
+    Samples that come out malformed are only warned about, since the check
+    for them is a heuristic that an unusual but valid stream may not pass.
+    A clear stream is reported as such, so that keys can be dropped.
+    Returns an error if the check itself could not be performed, or did
+    not finish within a few seconds.
+*/
+pub async fn check(
+    client: &reqwest::Client,
+    mpd_url: &str,
+    headers: &[(String, String)],
+    decryption_keys: &[String],
+) -> Result<Preflight> {
+    tokio::time::timeout(
+        CHECK_TIMEOUT,
+        check_stream(client, mpd_url, headers, decryption_keys),
+    )
+    .await
+    .map_err(|_| anyhow!("Timed out after {}s", CHECK_TIMEOUT.as_secs()))?
+}
+
+/**
+    Fetch the manifest and check its video and audio representations.
+*/
+async fn check_stream(
+    client: &reqwest::Client,
+    mpd_url: &str,
+    headers: &[(String, String)],
+    decryption_keys: &[String],
+) -> Result<Preflight> {
+    let mut request = client.get(mpd_url);
+    for (name, value) in request_headers(headers, mpd_url) {
+        request = request.header(name, value);
+    }
+    let mpd = request.send().await?.error_for_status()?.text().await?;
+
+    let now = crate::time::now();
+    let video = mpd::find_segments(mpd_url, &mpd, now)?;
+    let audio = mpd::find_audio_segments(mpd_url, &mpd, now)
+        .ok()
+        .flatten()
+        .filter(|audio| *audio != video);
+
+    let result = check_representation(client, &video, headers, decryption_keys).await?;
+    if let Preflight::Failed { .. } = result {
+        return Ok(result);
+    }
+
+    // Video often leaves NAL headers in the clear, audio is encrypted whole
+    let Some(audio) = audio else {
+        return Ok(result);
+    };
+    match check_representation(client, &audio, headers, decryption_keys).await {
+        Ok(failed @ Preflight::Failed { .. }) => Ok(failed),
+        Ok(encrypted @ Preflight::Encrypted { .. }) if result == Preflight::Clear => Ok(encrypted),
+        Ok(_) => Ok(result),
+        Err(e) => {
+            println!("[preflight] Skipping audio check: {}", e);
+            Ok(result)
+        }
+    }
+}
+
+/**
+    Check one representation of the stream against the content keys.
+*/
+async fn check_representation(
+    client: &reqwest::Client,
+    segments: &mpd::SegmentUrls,
+    headers: &[(String, String)],
+    decryption_keys: &[String],
+) -> Result<Preflight> {
+    println!(
+        "[preflight] Checking representation '{}': {}",
+        segments.representation_id, segments.media
+    );
+
+    let init = match segments.init {
+        Some(ref url) => mp4::parse_init(&fetch_segment(client, url, headers).await?),
+        None => mp4::InitInfo::default(),
+    };
+    let media = fetch_segment(client, &segments.media, headers).await?;
+
+    // Sample group KIDs in the media segment take precedence over the init segment
+    let mut encryptions: Vec<TrackEncryption> = mp4::parse_segment_encryption(&media)
+        .into_iter()
+        .filter(|e| e.is_protected)
+        .collect();
+    if encryptions.is_empty()
+        && let Some(encryption) = init.encryption.clone().filter(|e| e.is_protected)
+    {
+        encryptions.push(encryption);
+    }
+    let kids: Vec<String> = encryptions.iter().map(|e| e.kid.clone()).collect();
+
+    if kids.is_empty() {
+        return Ok(Preflight::Clear);
+    }
+
+    if decryption_keys.is_empty() {
+        return Ok(Preflight::Failed {
+            reason: format!(
+                "Stream is encrypted (KID {}) but no decryption keys are available",
+                kids.join(", ")
+            ),
+        });
+    }
+
+    let keys: Vec<KeyString> = decryption_keys
+        .iter()
+        .filter_map(|k| KeyString::parse(k).ok())
+        .collect();
+    let key_ids: Vec<String> = keys.iter().map(|k| k.kid_hex()).collect();
+    let missing: Vec<&String> = kids.iter().filter(|kid| !key_ids.contains(kid)).collect();
+    if !missing.is_empty() {
+        return Ok(Preflight::Failed {
+            reason: format!(
+                "No decryption key for KID {} (have keys for {})",
+                missing
+                    .iter()
+                    .map(|k| k.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                key_ids.join(", ")
+            ),
+        });
+    }
+
+    let encryption = &encryptions[0];
+    match verify_key(&init, encryption, &media, &keys) {
+        Some(true) => {}
+        Some(false) => eprintln!(
+            "[preflight] Decrypted {} samples for KID {} look malformed, the key may be wrong",
+            init.format.as_deref().unwrap_or_default(),
+            encryption.kid
+        ),
+        None => println!(
+            "[preflight] Can't verify key for KID {} ({} {}), only checked that it's present",
+            encryption.kid,
+            init.scheme.as_deref().unwrap_or("unknown scheme"),
+            init.format.as_deref().unwrap_or("unknown format")
+        ),
+    }
+
+    Ok(Preflight::Encrypted {
+        scheme: init.scheme,
+    })
+}
+
+/**
+    Decrypt the first samples of a media segment with the key for their KID,
+    and check that they come out well-formed for the track's format.
+
+    A few malformed samples are tolerated, since not every valid audio frame
+    starts with the syntax element checked for. Returns `None` if the scheme
+    or format can't be checked, or the segment has no samples to decrypt.
+*/
+fn verify_key(
+    init: &mp4::InitInfo,
+    encryption: &TrackEncryption,
+    media: &[u8],
+    keys: &[KeyString],
+) -> Option<bool> {
+    let scheme = init.scheme.as_deref()?;
+    let format = init.format.as_deref()?;
+    let key = keys.iter().find(|k| k.kid_hex() == encryption.kid)?;
+
+    let samples = mp4::encrypted_samples(media, encryption.iv_size as usize, VERIFY_SAMPLES);
+    let results: Vec<bool> = samples
+        .iter()
+        .filter_map(|sample| decrypt::decrypt_sample(scheme, encryption, &key.key, sample))
+        .filter_map(|sample| decrypt::is_well_formed(format, init.nal_length_size, &sample))
+        .collect();
+    if results.is_empty() {
+        return None;
+    }
+
+    let well_formed = results.iter().filter(|ok| **ok).count();
+    Some(well_formed * 2 >= results.len())
+}
@@ -0,0 +1,484 @@
+/**
+    Default encryption parameters of a track (`tenc`) or sample group (`seig`).
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackEncryption {
+    pub is_protected: bool,
+    /// Key ID as lowercase hex without dashes
+    pub kid: String,
+    /// Size of the per-sample IVs in `senc`, zero if a constant IV is used
+    pub iv_size: u8,
+    /// Constant IV used for every sample, if the IV size is zero
+    pub constant_iv: Option<Vec<u8>>,
+    /// Encryption pattern as (encrypted, skipped) blocks, zeros if unused
+    pub pattern: (u8, u8),
+}
+
+/**
+    A sample of a media segment, along with what's needed to decrypt it.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptedSample<'a> {
+    pub data: &'a [u8],
+    /// Per-sample IV, empty if a constant IV is used
+    pub iv: Vec<u8>,
+    /// Clear and protected byte counts, empty if the whole sample is protected
+    pub subsamples: Vec<(usize, usize)>,
+}
+
+/**
+    Protection info found in an init segment.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InitInfo {
+    /// Protection scheme from `schm`, e.g. "cenc" or "cbcs"
+    pub scheme: Option<String>,
+    /// Default encryption of the first protected track
+    pub encryption: Option<TrackEncryption>,
+    /// Original sample format from `frma`, e.g. "avc1" or "mp4a"
+    pub format: Option<String>,
+    /// Size of NAL unit length prefixes, from `avcC` or `hvcC`
+    pub nal_length_size: Option<usize>,
+}
+
+/**
+    Offset of the child boxes inside a container box payload,
+    or None if the box type is not a container we descend into.
+*/
+fn child_offset(kind: &[u8; 4]) -> Option<usize> {
+    match kind {
+        b"moov" | b"trak" | b"mdia" | b"minf" | b"stbl" | b"sinf" | b"schi" | b"moof" | b"traf" => {
+            Some(0)
+        }
+        // Full box header + entry count
+        b"stsd" => Some(8),
+        // Visual sample entry fields
+        b"encv" => Some(78),
+        // Audio sample entry fields
+        b"enca" => Some(28),
+        _ => None,
+    }
+}
+
+/**
+    Iterate over the boxes in a buffer, yielding their type and payload.
+    Stops at the first malformed box.
+*/
+fn boxes(mut data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    std::iter::from_fn(move || {
+        if data.len() < 8 {
+            return None;
+        }
+        let size = u32::from_be_bytes(data[0..4].try_into().unwrap()) as u64;
+        let kind: [u8; 4] = data[4..8].try_into().unwrap();
+
+        let (header_len, size) = match size {
+            0 => (8, data.len() as u64),
+            1 => {
+                if data.len() < 16 {
+                    return None;
+                }
+                (16, u64::from_be_bytes(data[8..16].try_into().unwrap()))
+            }
+            size => (8, size),
+        };
+
+        if size < header_len as u64 || size > data.len() as u64 {
+            return None;
+        }
+
+        let payload = &data[header_len..size as usize];
+        data = &data[size as usize..];
+        Some((kind, payload))
+    })
+}
+
+/**
+    Recursively collect the payloads of all boxes of the given type.
+*/
+fn find_all<'a>(data: &'a [u8], kind: &[u8; 4], out: &mut Vec<&'a [u8]>) {
+    for (box_kind, payload) in boxes(data) {
+        if &box_kind == kind {
+            out.push(payload);
+        }
+        if let Some(offset) = child_offset(&box_kind)
+            && payload.len() >= offset
+        {
+            find_all(&payload[offset..], kind, out);
+        }
+    }
+}
+
+fn find_first<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut found = Vec::new();
+    find_all(data, kind, &mut found);
+    found.into_iter().next()
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+}
+
+fn kid_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/**
+    Parse the encryption fields shared by `tenc` and `seig` entries:
+    reserved, pattern, isProtected, IV size, KID, and the optional constant IV.
+*/
+fn parse_encryption(fields: &[u8]) -> Option<TrackEncryption> {
+    let kid = fields.get(4..20)?;
+    let is_protected = fields[2] != 0;
+    let iv_size = fields[3];
+    let constant_iv = if is_protected && iv_size == 0 {
+        let size = *fields.get(20)? as usize;
+        Some(fields.get(21..21 + size)?.to_vec())
+    } else {
+        None
+    };
+    Some(TrackEncryption {
+        is_protected,
+        kid: kid_hex(kid),
+        iv_size,
+        constant_iv,
+        pattern: (fields[1] >> 4, fields[1] & 0x0f),
+    })
+}
+
+/**
+    Parse the protection scheme and default KID from an init segment.
+*/
+pub fn parse_init(data: &[u8]) -> InitInfo {
+    let scheme = find_first(data, b"schm")
+        .and_then(|schm| schm.get(4..8))
+        .map(|s| String::from_utf8_lossy(s).into_owned());
+
+    // tenc: full box header, then the fields shared with seig
+    let encryption = find_first(data, b"tenc")
+        .and_then(|tenc| tenc.get(4..))
+        .and_then(parse_encryption);
+
+    let format = find_first(data, b"frma")
+        .and_then(|frma| frma.get(0..4))
+        .map(|f| String::from_utf8_lossy(f).into_owned());
+
+    // lengthSizeMinusOne is in the low bits of byte 4 of avcC and byte 21 of hvcC
+    let nal_length_size = find_first(data, b"avcC")
+        .and_then(|avcc| avcc.get(4))
+        .or_else(|| find_first(data, b"hvcC").and_then(|hvcc| hvcc.get(21)))
+        .map(|b| (b & 0x03) as usize + 1);
+
+    InitInfo {
+        scheme,
+        encryption,
+        format,
+        nal_length_size,
+    }
+}
+
+/**
+    Parse the sample group encryption entries (`seig`) of a media segment.
+    These override the init segment's KID, e.g. for key rotation.
+*/
+pub fn parse_segment_encryption(data: &[u8]) -> Vec<TrackEncryption> {
+    let mut sgpds = Vec::new();
+    find_all(data, b"sgpd", &mut sgpds);
+
+    let mut entries = Vec::new();
+    for sgpd in sgpds {
+        if sgpd.len() < 8 || &sgpd[4..8] != b"seig" {
+            continue;
+        }
+        let version = sgpd[0];
+        let mut offset = 8;
+
+        let mut default_length = 0;
+        if version == 1 {
+            let Some(length) = read_u32(sgpd, offset) else {
+                continue;
+            };
+            default_length = length as usize;
+            offset += 4;
+        }
+        if version >= 2 {
+            offset += 4;
+        }
+
+        let Some(entry_count) = read_u32(sgpd, offset) else {
+            continue;
+        };
+        offset += 4;
+
+        for _ in 0..entry_count {
+            let length = if version == 1 && default_length == 0 {
+                let Some(length) = read_u32(sgpd, offset) else {
+                    break;
+                };
+                offset += 4;
+                length as usize
+            } else if version == 1 {
+                default_length
+            } else {
+                20
+            };
+
+            // seig: reserved, pattern, isProtected, IV size, KID, constant IV
+            let Some(entry) = sgpd.get(offset..offset + length.max(20)) else {
+                break;
+            };
+            if let Some(encryption) = parse_encryption(entry) {
+                entries.push(encryption);
+            }
+            offset += length.max(20);
+        }
+    }
+
+    entries
+}
+
+/**
+    Get up to `max` samples of the first track fragment of a media segment,
+    with their IVs and subsample ranges from `senc`.
+
+    Returns no samples if the segment has no `senc`, or if the fragment
+    points outside of the segment.
+*/
+pub fn encrypted_samples(data: &[u8], iv_size: usize, max: usize) -> Vec<EncryptedSample<'_>> {
+    // Sample data offsets are relative to the start of the moof box
+    let mut offset = 0;
+    for (kind, payload) in boxes(data) {
+        let start = offset;
+        let header = payload.as_ptr() as usize - data[offset..].as_ptr() as usize;
+        offset += header + payload.len();
+        if &kind == b"moof" {
+            return fragment_samples(data, start, payload, iv_size, max).unwrap_or_default();
+        }
+    }
+    Vec::new()
+}
+
+fn fragment_samples<'a>(
+    data: &'a [u8],
+    moof_start: usize,
+    moof: &[u8],
+    iv_size: usize,
+    max: usize,
+) -> Option<Vec<EncryptedSample<'a>>> {
+    let traf = find_first(moof, b"traf")?;
+    let tfhd = find_first(traf, b"tfhd")?;
+    let trun = find_first(traf, b"trun")?;
+    let senc = find_first(traf, b"senc")?;
+
+    // tfhd: flags, track ID, then optional fields in flag order
+    let tfhd_flags = read_u32(tfhd, 0)? & 0x00ff_ffff;
+    let mut pos = 8;
+    let mut base = moof_start as u64;
+    if tfhd_flags & 0x01 != 0 {
+        base = u64::from_be_bytes(tfhd.get(pos..pos + 8)?.try_into().unwrap());
+        pos += 8;
+    }
+    for flag in [0x02, 0x08] {
+        if tfhd_flags & flag != 0 {
+            pos += 4;
+        }
+    }
+    let default_size = if tfhd_flags & 0x10 != 0 {
+        read_u32(tfhd, pos)
+    } else {
+        None
+    };
+
+    // trun: flags, sample count, optional data offset and first sample flags, samples
+    let trun_flags = read_u32(trun, 0)? & 0x00ff_ffff;
+    let count = read_u32(trun, 4)? as usize;
+    let mut pos = 8;
+    let mut data_offset = 0i64;
+    if trun_flags & 0x01 != 0 {
+        data_offset = i64::from(read_u32(trun, pos)? as i32);
+        pos += 4;
+    }
+    if trun_flags & 0x04 != 0 {
+        pos += 4;
+    }
+
+    // senc: flags, sample count, then IVs and subsample ranges per sample
+    let has_subsamples = read_u32(senc, 0)? & 0x02 != 0;
+    let mut senc_pos = 8;
+
+    let mut sample_offset = usize::try_from(base as i64 + data_offset).ok()?;
+    let mut samples = Vec::new();
+    for _ in 0..count.min(max) {
+        if trun_flags & 0x100 != 0 {
+            pos += 4;
+        }
+        let size = if trun_flags & 0x200 != 0 {
+            let size = read_u32(trun, pos)?;
+            pos += 4;
+            size
+        } else {
+            default_size?
+        } as usize;
+        for flag in [0x400, 0x800] {
+            if trun_flags & flag != 0 {
+                pos += 4;
+            }
+        }
+
+        let iv = senc.get(senc_pos..senc_pos + iv_size)?.to_vec();
+        senc_pos += iv_size;
+        let mut subsamples = Vec::new();
+        if has_subsamples {
+            let entries = u16::from_be_bytes(senc.get(senc_pos..senc_pos + 2)?.try_into().unwrap());
+            senc_pos += 2;
+            for _ in 0..entries {
+                let clear =
+                    u16::from_be_bytes(senc.get(senc_pos..senc_pos + 2)?.try_into().unwrap());
+                let protected = read_u32(senc, senc_pos + 2)?;
+                subsamples.push((clear as usize, protected as usize));
+                senc_pos += 6;
+            }
+        }
+
+        samples.push(EncryptedSample {
+            data: data.get(sample_offset..sample_offset + size)?,
+            iv,
+            subsamples,
+        });
+        sample_offset += size;
+    }
+    Some(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    fn tenc(kid: [u8; 16]) -> Vec<u8> {
+        let mut payload = vec![0, 0, 0, 0, 0, 0, 1, 8];
+        payload.extend_from_slice(&kid);
+        mp4_box(b"tenc", &payload)
+    }
+
+    fn init_segment(kid: [u8; 16]) -> Vec<u8> {
+        let schm = mp4_box(b"schm", b"\0\0\0\0cenc\0\x01\0\0");
+        let schi = mp4_box(b"schi", &tenc(kid));
+        let sinf = mp4_box(b"sinf", &[schm, schi].concat());
+        let encv = mp4_box(b"encv", &[vec![0; 78], sinf].concat());
+        let stsd = mp4_box(b"stsd", &[vec![0, 0, 0, 0, 0, 0, 0, 1], encv].concat());
+        let stbl = mp4_box(b"stbl", &stsd);
+        let minf = mp4_box(b"minf", &stbl);
+        let mdia = mp4_box(b"mdia", &minf);
+        let trak = mp4_box(b"trak", &mdia);
+        let moov = mp4_box(b"moov", &trak);
+        [mp4_box(b"ftyp", b"iso6\0\0\0\0"), moov].concat()
+    }
+
+    #[test]
+    fn test_parse_init_encrypted() {
+        let info = parse_init(&init_segment([0xab; 16]));
+        assert_eq!(info.scheme.as_deref(), Some("cenc"));
+        assert_eq!(
+            info.encryption,
+            Some(TrackEncryption {
+                is_protected: true,
+                kid: "ab".repeat(16),
+                iv_size: 8,
+                constant_iv: None,
+                pattern: (0, 0),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_init_clear() {
+        let moov = mp4_box(b"moov", &mp4_box(b"trak", &[]));
+        let info = parse_init(&moov);
+        assert_eq!(info, InitInfo::default());
+    }
+
+    #[test]
+    fn test_parse_segment_encryption() {
+        let mut sgpd = vec![1, 0, 0, 0];
+        sgpd.extend_from_slice(b"seig");
+        sgpd.extend_from_slice(&20u32.to_be_bytes());
+        sgpd.extend_from_slice(&1u32.to_be_bytes());
+        sgpd.extend_from_slice(&[0, 0, 1, 8]);
+        sgpd.extend_from_slice(&[0x12; 16]);
+        let traf = mp4_box(b"traf", &mp4_box(b"sgpd", &sgpd));
+        let moof = mp4_box(b"moof", &traf);
+
+        let entries = parse_segment_encryption(&moof);
+        assert_eq!(
+            entries,
+            vec![TrackEncryption {
+                is_protected: true,
+                kid: "12".repeat(16),
+                iv_size: 8,
+                constant_iv: None,
+                pattern: (0, 0),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_encrypted_samples() {
+        let tfhd = mp4_box(b"tfhd", &[0, 2, 0, 0, 0, 0, 0, 1]);
+        let mut trun = vec![0, 0, 2, 1];
+        trun.extend_from_slice(&2u32.to_be_bytes());
+        trun.extend_from_slice(&0u32.to_be_bytes());
+        trun.extend_from_slice(&3u32.to_be_bytes());
+        trun.extend_from_slice(&5u32.to_be_bytes());
+        let mut senc = vec![0, 0, 0, 2];
+        senc.extend_from_slice(&2u32.to_be_bytes());
+        senc.extend_from_slice(&[1; 8]);
+        senc.extend_from_slice(&[0, 1, 0, 1, 0, 0, 0, 2]);
+        senc.extend_from_slice(&[2; 8]);
+        senc.extend_from_slice(&[0, 0]);
+
+        // The data offset points past the moof and the mdat header
+        let traf_len = 8 + tfhd.len() + 8 + trun.len() + 8 + senc.len();
+        let moof_len = 8 + traf_len;
+        trun[8..12].copy_from_slice(&(moof_len as u32 + 8).to_be_bytes());
+        let traf = mp4_box(
+            b"traf",
+            &[tfhd, mp4_box(b"trun", &trun), mp4_box(b"senc", &senc)].concat(),
+        );
+        let segment = [mp4_box(b"moof", &traf), mp4_box(b"mdat", b"abcdefgh")].concat();
+
+        let samples = encrypted_samples(&segment, 8, 8);
+        assert_eq!(
+            samples,
+            vec![
+                EncryptedSample {
+                    data: b"abc",
+                    iv: vec![1; 8],
+                    subsamples: vec![(1, 2)],
+                },
+                EncryptedSample {
+                    data: b"defgh",
+                    iv: vec![2; 8],
+                    subsamples: vec![],
+                },
+            ]
+        );
+        assert_eq!(encrypted_samples(&segment, 8, 1).len(), 1);
+    }
+
+    #[test]
+    fn test_malformed_boxes_are_ignored() {
+        assert_eq!(
+            parse_init(&[0, 0, 0, 200, b'm', b'o', b'o', b'v']),
+            InitInfo::default()
+        );
+        assert!(parse_segment_encryption(&[1, 2, 3]).is_empty());
+    }
+}
@@ -0,0 +1,371 @@
+use anyhow::{Result, anyhow};
+use regex::Regex;
+use reqwest::Url;
+use sxd_document::dom::{ChildOfElement, ChildOfRoot, Document, Element};
+
+/**
+    URLs of the init segment and a recent media segment of one representation.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentUrls {
+    pub representation_id: String,
+    pub init: Option<String>,
+    pub media: String,
+}
+
+/**
+    Segment template attributes, merged down from Period to Representation.
+*/
+#[derive(Debug, Clone, Default)]
+struct Template {
+    initialization: Option<String>,
+    media: Option<String>,
+    start_number: Option<u64>,
+    timescale: Option<u64>,
+    duration: Option<u64>,
+    /// SegmentTimeline entries as (t, d, r)
+    timeline: Vec<(Option<u64>, u64, i64)>,
+}
+
+impl Template {
+    fn merge(&mut self, element: Element) {
+        let Some(template) = child(element, "SegmentTemplate") else {
+            return;
+        };
+        let attr = |name: &str| template.attribute_value(name).map(ToString::to_string);
+        let num = |name: &str| template.attribute_value(name).and_then(|v| v.parse().ok());
+
+        if let Some(v) = attr("initialization") {
+            self.initialization = Some(v);
+        }
+        if let Some(v) = attr("media") {
+            self.media = Some(v);
+        }
+        if let Some(v) = num("startNumber") {
+            self.start_number = Some(v);
+        }
+        if let Some(v) = num("timescale") {
+            self.timescale = Some(v);
+        }
+        if let Some(v) = num("duration") {
+            self.duration = Some(v);
+        }
+
+        if let Some(timeline) = child(template, "SegmentTimeline") {
+            self.timeline = children(timeline, "S")
+                .into_iter()
+                .filter_map(|s| {
+                    let t = s.attribute_value("t").and_then(|v| v.parse().ok());
+                    let d = s.attribute_value("d")?.parse().ok()?;
+                    let r = s
+                        .attribute_value("r")
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(0);
+                    Some((t, d, r))
+                })
+                .collect();
+        }
+    }
+}
+
+/**
+    Child elements with the given local name.
+*/
+pub fn children<'d>(element: Element<'d>, name: &str) -> Vec<Element<'d>> {
+    element
+        .children()
+        .into_iter()
+        .filter_map(|c| match c {
+            ChildOfElement::Element(e) if e.name().local_part() == name => Some(e),
+            _ => None,
+        })
+        .collect()
+}
+
+/**
+    First child element with the given local name.
+*/
+pub fn child<'d>(element: Element<'d>, name: &str) -> Option<Element<'d>> {
+    children(element, name).into_iter().next()
+}
+
+/**
+    The MPD root element of a parsed document.
+*/
+pub fn mpd_root(document: Document<'_>) -> Result<Element<'_>> {
+    document
+        .root()
+        .children()
+        .into_iter()
+        .find_map(|c| match c {
+            ChildOfRoot::Element(e) if e.name().local_part() == "MPD" => Some(e),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("MPD has no root element"))
+}
+
+fn text(element: Element) -> String {
+    element
+        .children()
+        .into_iter()
+        .filter_map(|c| match c {
+            ChildOfElement::Text(t) => Some(t.text().to_string()),
+            _ => None,
+        })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/**
+    Resolve the BaseURL of an element (if any) against the current base.
+*/
+fn resolve_base(base: &Url, element: Element) -> Result<Url> {
+    match child(element, "BaseURL") {
+        Some(base_url) => base
+            .join(&text(base_url))
+            .map_err(|e| anyhow!("Invalid BaseURL: {}", e)),
+        None => Ok(base.clone()),
+    }
+}
+
+fn has_content(element: Element, content: &str) -> bool {
+    ["contentType", "mimeType"].into_iter().any(|attr| {
+        element
+            .attribute_value(attr)
+            .is_some_and(|v| v.contains(content))
+    })
+}
+
+/**
+    Fill in a DASH segment template, including `%0Nd` width formatting.
+*/
+pub fn fill_template(
+    template: &str,
+    representation_id: &str,
+    bandwidth: Option<&str>,
+    number: u64,
+    time: u64,
+) -> String {
+    let re = Regex::new(r"\$(RepresentationID|Number|Bandwidth|Time)(?:%0(\d+)d)?\$").unwrap();
+    let filled = re.replace_all(template, |caps: &regex::Captures| {
+        let width: usize = caps
+            .get(2)
+            .and_then(|w| w.as_str().parse().ok())
+            .unwrap_or(0);
+        match &caps[1] {
+            "RepresentationID" => representation_id.to_string(),
+            "Bandwidth" => bandwidth.unwrap_or_default().to_string(),
+            "Number" => format!("{:0width$}", number, width = width),
+            _ => format!("{:0width$}", time, width = width),
+        }
+    });
+    filled.replace("$$", "$")
+}
+
+/**
+    Pick the number and start time of a recent segment from a template.
+
+    With a SegmentTimeline this is the last listed segment. For number-based
+    live templates it is one segment behind the live edge, computed from the
+    availability start time.
+*/
+fn pick_segment(template: &Template, live_elapsed_secs: Option<u64>) -> (u64, u64) {
+    let start_number = template.start_number.unwrap_or(1);
+
+    if !template.timeline.is_empty() {
+        let mut number = start_number;
+        let mut time = 0;
+        let mut last = (start_number, 0);
+        for &(t, d, r) in &template.timeline {
+            if let Some(t) = t {
+                time = t;
+            }
+            for _ in 0..=r.max(0) {
+                last = (number, time);
+                number += 1;
+                time += d;
+            }
+        }
+        return last;
+    }
+
+    let timescale = template.timescale.unwrap_or(1).max(1);
+    match (template.duration, live_elapsed_secs) {
+        (Some(duration), Some(elapsed)) if duration > 0 => {
+            let index = (elapsed * timescale / duration).saturating_sub(1);
+            (start_number + index, index * duration)
+        }
+        _ => (start_number, 0),
+    }
+}
+
+/**
+    Check if an MPD describes a live stream (`type="dynamic"`).
+    Anything that doesn't parse as an MPD is not one.
+*/
+pub fn is_dynamic(mpd: &str) -> bool {
+    let Ok(package) = sxd_document::parser::parse(mpd) else {
+        return false;
+    };
+    mpd_root(package.as_document())
+        .is_ok_and(|root| root.attribute_value("type") == Some("dynamic"))
+}
+
+/**
+    Find the init segment and a recent media segment of the first video
+    representation in an MPD (or the first representation, if there is no video).
+
+    Only SegmentTemplate addressing is supported.
+*/
+pub fn find_segments(mpd_url: &str, mpd: &str, now: u64) -> Result<SegmentUrls> {
+    match find_content_segments(mpd_url, mpd, now, "video")? {
+        Some(segments) => Ok(segments),
+        None => segments_where(mpd_url, mpd, now, |_| true)?
+            .ok_or_else(|| anyhow!("MPD has no AdaptationSet")),
+    }
+}
+
+/**
+    Find the init segment and a recent media segment of the first audio
+    representation in an MPD, if it has one.
+*/
+pub fn find_audio_segments(mpd_url: &str, mpd: &str, now: u64) -> Result<Option<SegmentUrls>> {
+    find_content_segments(mpd_url, mpd, now, "audio")
+}
+
+fn find_content_segments(
+    mpd_url: &str,
+    mpd: &str,
+    now: u64,
+    content: &str,
+) -> Result<Option<SegmentUrls>> {
+    segments_where(mpd_url, mpd, now, |a| {
+        has_content(a, content)
+            || children(a, "Representation")
+                .into_iter()
+                .any(|r| has_content(r, content))
+    })
+}
+
+/**
+    Find the segments of the first representation in the first adaptation
+    set matching a predicate, or `None` if no adaptation set matches.
+*/
+fn segments_where(
+    mpd_url: &str,
+    mpd: &str,
+    now: u64,
+    matches: impl Fn(Element) -> bool,
+) -> Result<Option<SegmentUrls>> {
+    let package =
+        sxd_document::parser::parse(mpd).map_err(|e| anyhow!("Failed to parse MPD: {:?}", e))?;
+    let document = package.as_document();
+
+    let root = mpd_root(document)?;
+
+    let base = Url::parse(mpd_url).map_err(|e| anyhow!("Invalid MPD URL: {}", e))?;
+    let base = resolve_base(&base, root)?;
+
+    let period = child(root, "Period").ok_or_else(|| anyhow!("MPD has no Period"))?;
+    let base = resolve_base(&base, period)?;
+
+    let Some(adaptation_set) = children(period, "AdaptationSet")
+        .into_iter()
+        .find(|a| matches(*a))
+    else {
+        return Ok(None);
+    };
+    let base = resolve_base(&base, adaptation_set)?;
+
+    let representation = child(adaptation_set, "Representation")
+        .ok_or_else(|| anyhow!("AdaptationSet has no Representation"))?;
+    let base = resolve_base(&base, representation)?;
+
+    let mut template = Template::default();
+    template.merge(period);
+    template.merge(adaptation_set);
+    template.merge(representation);
+
+    let media = template
+        .media
+        .as_deref()
+        .ok_or_else(|| anyhow!("Only SegmentTemplate addressing is supported"))?;
+
+    // Live streams without a timeline are addressed relative to availabilityStartTime
+    let live_elapsed_secs = root
+        .attribute_value("availabilityStartTime")
+        .filter(|_| root.attribute_value("type") == Some("dynamic"))
+        .and_then(|ast| chrono::DateTime::parse_from_rfc3339(ast).ok())
+        .map(|ast| now.saturating_sub(ast.timestamp().max(0) as u64));
+
+    let representation_id = representation.attribute_value("id").unwrap_or_default();
+    let bandwidth = representation.attribute_value("bandwidth");
+    let (number, time) = pick_segment(&template, live_elapsed_secs);
+
+    let resolve = |template: &str| -> Result<String> {
+        let path = fill_template(template, representation_id, bandwidth, number, time);
+        base.join(&path)
+            .map(|url| url.to_string())
+            .map_err(|e| anyhow!("Invalid segment URL '{}': {}", path, e))
+    };
+
+    Ok(Some(SegmentUrls {
+        representation_id: representation_id.to_string(),
+        init: template
+            .initialization
+            .as_deref()
+            .map(resolve)
+            .transpose()?,
+        media: resolve(media)?,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_template() {
+        assert_eq!(
+            fill_template("$RepresentationID$/seg-$Number%05d$.m4s", "v1", None, 42, 0),
+            "v1/seg-00042.m4s"
+        );
+        assert_eq!(
+            fill_template("$Bandwidth$_$Time$.mp4?a=$$", "v1", Some("800000"), 1, 9000),
+            "800000_9000.mp4?a=$"
+        );
+    }
+
+    #[test]
+    fn test_pick_segment_timeline() {
+        let template = Template {
+            start_number: Some(10),
+            timeline: vec![(Some(1000), 100, 2), (None, 50, 0)],
+            ..Default::default()
+        };
+        // Segments: 1000, 1100, 1200 (d=100), then 1300 (d=50)
+        assert_eq!(pick_segment(&template, None), (13, 1300));
+    }
+
+    #[test]
+    fn test_pick_segment_live_duration() {
+        let template = Template {
+            start_number: Some(1),
+            timescale: Some(1000),
+            duration: Some(4000),
+            ..Default::default()
+        };
+        // 100s into the stream is segment index 25, pick the one before it
+        assert_eq!(pick_segment(&template, Some(100)), (25, 96000));
+        assert_eq!(pick_segment(&template, None), (1, 0));
+    }
+
+    #[test]
+    fn test_is_dynamic() {
+        let live = r#"<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="dynamic"><Period id="1"/><Period id="ad"/></MPD>"#;
+        assert!(is_dynamic(live));
+        assert!(!is_dynamic(&live.replace("dynamic", "static")));
+        assert!(!is_dynamic("#EXTM3U\n"));
+    }
+}
@@ -0,0 +1,128 @@
+use std::cmp::Ordering;
+use std::time::Duration;
+
+use crate::registry::{ChannelId, ChannelUsage};
+
+/**
+    Scoring of channels by how often and how recently they were watched,
+    deciding which channels are worth keeping warm.
+
+    A channel's score is the frequency weight times the logarithm of its
+    tune count, plus the recency weight times a value that halves every
+    half-life since it was last watched. Channels never watched score zero.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrewarmPolicy {
+    pub frequency_weight: f64,
+    pub recency_weight: f64,
+    pub recency_half_life: Duration,
+    /// Number of top channels to pre-warm at startup
+    pub prewarm_count: usize,
+    /// Number of top channels to refresh the credentials of before they expire
+    pub refresh_count: usize,
+}
+
+impl Default for PrewarmPolicy {
+    fn default() -> Self {
+        Self {
+            frequency_weight: 1.0,
+            recency_weight: 1.0,
+            recency_half_life: Duration::from_secs(24 * 60 * 60),
+            prewarm_count: 0,
+            refresh_count: 0,
+        }
+    }
+}
+
+impl PrewarmPolicy {
+    /**
+        Score a channel by its usage, as of the given time.
+    */
+    pub fn score(&self, usage: &ChannelUsage, now: u64) -> f64 {
+        let Some(last_watched_at) = usage.last_watched_at else {
+            return 0.0;
+        };
+        let frequency = (1.0 + f64::from(usage.tunes)).ln();
+        let age = now.saturating_sub(last_watched_at) as f64;
+        let half_life = self.recency_half_life.as_secs_f64().max(1.0);
+        let recency = 0.5f64.powf(age / half_life);
+        self.frequency_weight * frequency + self.recency_weight * recency
+    }
+
+    /**
+        Get the channels with the highest scores, best first, at most `limit`.
+    */
+    pub fn top(
+        &self,
+        usage: Vec<(ChannelId, ChannelUsage)>,
+        now: u64,
+        limit: usize,
+    ) -> Vec<ChannelId> {
+        let mut scored: Vec<(f64, ChannelId)> = usage
+            .into_iter()
+            .map(|(id, usage)| (self.score(&usage, now), id))
+            .filter(|(score, _)| *score > 0.0)
+            .collect();
+        scored.sort_by(|(a, a_id), (b, b_id)| {
+            b.partial_cmp(a)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a_id.to_string().cmp(&b_id.to_string()))
+        });
+        scored.into_iter().take(limit).map(|(_, id)| id).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn usage(tunes: u32, last_watched_at: Option<u64>) -> ChannelUsage {
+        ChannelUsage {
+            tunes,
+            last_watched_at,
+        }
+    }
+
+    #[test]
+    fn test_score_weights() {
+        let policy = PrewarmPolicy::default();
+        let now = 10 * DAY;
+        assert_eq!(policy.score(&usage(0, None), now), 0.0);
+
+        // Watched right now, and a half-life ago
+        let fresh = policy.score(&usage(1, Some(now)), now);
+        assert!((fresh - (2f64.ln() + 1.0)).abs() < 1e-9);
+        let stale = policy.score(&usage(1, Some(now - DAY)), now);
+        assert!((stale - (2f64.ln() + 0.5)).abs() < 1e-9);
+
+        let frequency_only = PrewarmPolicy {
+            recency_weight: 0.0,
+            ..policy
+        };
+        assert_eq!(
+            frequency_only.score(&usage(3, Some(now)), now),
+            frequency_only.score(&usage(3, Some(0)), now)
+        );
+    }
+
+    #[test]
+    fn test_top_channels() {
+        let policy = PrewarmPolicy::default();
+        let now = 10 * DAY;
+        let id = |channel: &str| ChannelId::new("src", channel);
+        let channels = vec![
+            (id("rare"), usage(1, Some(now - 5 * DAY))),
+            (id("daily"), usage(20, Some(now - DAY))),
+            (id("never"), usage(0, None)),
+            (id("just-now"), usage(1, Some(now))),
+        ];
+
+        assert_eq!(
+            policy.top(channels.clone(), now, 10),
+            vec![id("daily"), id("just-now"), id("rare")]
+        );
+        assert_eq!(policy.top(channels, now, 1), vec![id("daily")]);
+    }
+}
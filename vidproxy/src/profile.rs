@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+
+use crate::manifest::{LoudnessConfig, OverlayConfig, SegmentConfig};
+
+/**
+    A named output profile, changing how the channels served with it are
+    segmented and processed. Settings it leaves out are taken from the
+    command line (segmenting) and from the channel itself (processing).
+
+    The sink always writes unencrypted MPEG-TS segments, so the container
+    and encryption are not part of a profile, and unknown settings are
+    rejected rather than silently ignored.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputProfile {
+    /// Segment duration in seconds
+    #[serde(default)]
+    pub segment_duration: Option<u64>,
+    /// Number of segments to keep
+    #[serde(default)]
+    pub segment_count: Option<usize>,
+    /// Scale the video down to at most this height, re-encoding it
+    #[serde(default)]
+    pub max_height: Option<u32>,
+    /// Audio loudness normalization, replacing the channel's own
+    #[serde(default)]
+    pub loudness: Option<LoudnessConfig>,
+    /// Overlay burned into the video, replacing the channel's own
+    #[serde(default)]
+    pub overlay: Option<OverlayConfig>,
+}
+
+impl OutputProfile {
+    fn validate(&self) -> Result<()> {
+        if self.segment_duration == Some(0) {
+            return Err(anyhow!("segment_duration must be at least 1 second"));
+        }
+        if self.segment_count == Some(0) {
+            return Err(anyhow!("segment_count must be at least 1"));
+        }
+        if self.max_height.is_some_and(|height| height < 2) {
+            return Err(anyhow!("max_height must be at least 2 pixels"));
+        }
+        Ok(())
+    }
+
+    /**
+        Get the segmenting settings of the profile.
+    */
+    pub fn segments(&self) -> SegmentConfig {
+        SegmentConfig {
+            duration: self.segment_duration,
+            count: self.segment_count,
+        }
+    }
+}
+
+/**
+    Check that a profile name can be used as is in paths and query strings.
+*/
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/**
+    Output profiles loaded from the profiles file, by name.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct OutputProfiles {
+    profiles: BTreeMap<String, OutputProfile>,
+}
+
+impl OutputProfiles {
+    /**
+        Load profiles from a YAML file mapping profile names to their settings.
+    */
+    pub fn load(path: &Path) -> Result<Self> {
+        let yaml = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read {:?}: {}", path, e))?;
+        Self::parse(&yaml).map_err(|e| anyhow!("Failed to parse {:?}: {}", path, e))
+    }
+
+    fn parse(yaml: &str) -> Result<Self> {
+        let profiles: BTreeMap<String, OutputProfile> = serde_yaml::from_str(yaml)?;
+        for (name, profile) in &profiles {
+            if !is_valid_name(name) {
+                return Err(anyhow!(
+                    "Invalid profile name '{}', use letters, digits, '-' and '_'",
+                    name
+                ));
+            }
+            profile
+                .validate()
+                .map_err(|e| anyhow!("Profile '{}': {}", name, e))?;
+        }
+        Ok(Self { profiles })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&OutputProfile> {
+        self.profiles.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.profiles.keys().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_profiles() {
+        let profiles = OutputProfiles::parse(
+            "
+tv-ts:
+  segment_duration: 4
+lowband-720p:
+  segment_duration: 6
+  segment_count: 10
+  max_height: 720
+  loudness:
+    target: -16
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            profiles.names().collect::<Vec<_>>(),
+            vec!["lowband-720p", "tv-ts"]
+        );
+        let lowband = profiles.get("lowband-720p").unwrap();
+        assert_eq!(lowband.max_height, Some(720));
+        assert_eq!(lowband.loudness.as_ref().map(|l| l.target), Some(-16.0));
+        assert_eq!(
+            lowband.segments(),
+            SegmentConfig {
+                duration: Some(6),
+                count: Some(10),
+            }
+        );
+        assert_eq!(profiles.get("tv-ts").unwrap().max_height, None);
+        assert!(profiles.get("web").is_none());
+    }
+
+    #[test]
+    fn test_parse_profiles_rejects_invalid() {
+        // Settings the sink can't honor are errors, not ignored
+        assert!(OutputProfiles::parse("web:\n  container: fmp4\n").is_err());
+        assert!(OutputProfiles::parse("tv ts:\n  segment_duration: 4\n").is_err());
+        assert!(OutputProfiles::parse("tv:\n  segment_duration: 0\n").is_err());
+        assert!(OutputProfiles::parse("tv:\n  max_height: 1\n").is_err());
+    }
+}
@@ -1,38 +1,197 @@
+use std::future::Future;
 use std::path::Path;
+use std::pin::Pin;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use drm_widevine::core::KeyString;
 use ffmpeg_sink::{Sink, SinkConfig};
-use ffmpeg_source::{DecryptionKey, Source, SourceConfig};
-use tokio::sync::watch;
+use ffmpeg_source::{DecryptionKey, Packet, Source, SourceConfig};
+use ffmpeg_types::StreamType;
+use tokio::sync::{oneshot, watch};
 
+use crate::adbreak::{AdBreak, AdBreakSource};
+use crate::loudness::LoudnessNormalizer;
+use crate::manifest::{LoudnessConfig, LoudnessMode, request_headers};
+use crate::preflight::mpd;
+use crate::segment_check::SegmentExpectations;
 use crate::segments::SegmentManager;
+use crate::transcode::{
+    self, AdBreakAnalyzer, AudioTranscoder, OutputProcessing, StreamCodec, VideoTranscoder,
+};
 
 /**
-    Run the remux pipeline: read from source HLS/DASH, write to local HLS.
+    Minimum time between two content key refreshes, so that a stream that
+    can't be decrypted with any key doesn't hammer the license server
 */
-pub async fn run_remux_pipeline(
-    input_url: &str,
+const MIN_KEY_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/**
+    How often to check for finished segments to publish. Clients can't
+    fetch a segment before it is published, so this adds to the latency.
+*/
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(250);
+
+/**
+    Future resolving to a fresh set of content keys ("kid:key" hex).
+*/
+pub type KeyFuture = Pin<Box<dyn Future<Output = anyhow::Result<Vec<String>>> + Send>>;
+
+/**
+    Fetches a fresh set of content keys for a running pipeline.
+*/
+pub type KeyRefresher = Box<dyn Fn() -> KeyFuture + Send + Sync>;
+
+/**
+    Codec and format parameters of a source, used to detect changes across restarts.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamParams {
+    pub video: Option<String>,
+    pub audio: Option<String>,
+}
+
+impl std::fmt::Display for StreamParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "video: {}, audio: {}",
+            self.video.as_deref().unwrap_or("none"),
+            self.audio.as_deref().unwrap_or("none")
+        )
+    }
+}
+
+/**
+    Get the presentation timestamp of a packet in seconds, if it has one.
+*/
+fn packet_seconds(packet: &Packet) -> Option<f64> {
+    let pts = packet.pts?;
+    let time_base = packet.time_base;
+    if time_base.den == 0 {
+        return None;
+    }
+    Some(pts as f64 * time_base.num as f64 / time_base.den as f64)
+}
+
+/**
+    Detects jumps in the timestamps of a source, as happen when it is
+    reopened (and a new DASH period starts its timestamps over).
+*/
+#[derive(Debug, Default)]
+struct TimestampTracker {
+    video: Option<f64>,
+    audio: Option<f64>,
+    /// Any step back counts as a jump right after the source was reopened
+    reopened: bool,
+}
+
+impl TimestampTracker {
+    /**
+        Track the timestamp of a packet, returning the jump from the previous
+        packet of its stream if it is larger than the given gap (either way).
+    */
+    fn check(&mut self, stream_type: StreamType, secs: f64, max_gap: f64) -> Option<f64> {
+        let last = match stream_type {
+            StreamType::Video => &mut self.video,
+            StreamType::Audio => &mut self.audio,
+            _ => return None,
+        };
+        let jump = secs - last.replace(secs)?;
+        let after_reopen = std::mem::take(&mut self.reopened);
+        if jump.abs() <= max_gap && !(after_reopen && jump < 0.0) {
+            return None;
+        }
+
+        // Other streams jump along with this one, start them over
+        let current = Some(secs);
+        (self.video, self.audio) = match stream_type {
+            StreamType::Video => (current, None),
+            _ => (None, current),
+        };
+        Some(jump)
+    }
+}
+
+/**
+    Fetch a manifest or playlist of the source with its headers.
+*/
+async fn fetch_manifest(
+    client: &reqwest::Client,
+    url: &str,
     headers: &[(String, String)],
-    decryption_keys: &[String],
-    output_dir: &Path,
-    segment_duration: Duration,
-    segment_manager: Arc<SegmentManager>,
-    mut shutdown_rx: watch::Receiver<bool>,
-) -> Result<(), ffmpeg_types::Error> {
-    // Build source config with decryption keys if provided
+) -> Option<String> {
+    let mut request = client.get(url);
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    match request.send().await.and_then(|r| r.error_for_status()) {
+        Ok(response) => Some(response.text().await.unwrap_or_default()),
+        Err(e) => {
+            eprintln!("Failed to check whether the source is live: {}", e);
+            None
+        }
+    }
+}
+
+/**
+    Check if the source's manifest is a live DASH manifest, which only
+    ends when FFmpeg reaches the end of its current period.
+*/
+async fn is_live_dash(client: &reqwest::Client, url: &str, headers: &[(String, String)]) -> bool {
+    fetch_manifest(client, url, headers)
+        .await
+        .is_some_and(|manifest| mpd::is_dynamic(&manifest))
+}
+
+/**
+    Check if the source is live: a dynamic DASH manifest, or an HLS playlist
+    without `#EXT-X-ENDLIST`. Master playlists are checked by their first variant.
+
+    Returns None if the manifest can't be fetched.
+*/
+async fn is_live_source(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &[(String, String)],
+) -> Option<bool> {
+    let manifest = fetch_manifest(client, url, headers).await?;
+    if !manifest.trim_start().starts_with("#EXTM3U") {
+        return Some(mpd::is_dynamic(&manifest));
+    }
+    let Some(variant) = hls_first_variant(&manifest) else {
+        return Some(!manifest.contains("#EXT-X-ENDLIST"));
+    };
+    let variant_url = reqwest::Url::parse(url).ok()?.join(variant).ok()?;
+    let playlist = fetch_manifest(client, variant_url.as_str(), headers).await?;
+    Some(!playlist.contains("#EXT-X-ENDLIST"))
+}
+
+/**
+    Get the URI of the first variant of an HLS master playlist,
+    or None if the playlist is a media playlist.
+*/
+fn hls_first_variant(playlist: &str) -> Option<&str> {
+    let mut lines = playlist.lines().map(str::trim);
+    lines.find(|line| line.starts_with("#EXT-X-STREAM-INF"))?;
+    lines.find(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+/**
+    Build a source config with the given headers and decryption keys.
+*/
+fn source_config(headers: &[(String, String)], decryption_keys: &[String]) -> SourceConfig {
     let mut source_config = SourceConfig::default();
     if !decryption_keys.is_empty() {
         let keys: Vec<DecryptionKey> = decryption_keys
             .iter()
-            .filter_map(|key| {
-                if let Some((key_id, key_value)) = key.split_once(':') {
-                    Some(DecryptionKey {
-                        key_id: key_id.to_string(),
-                        key: key_value.to_string(),
-                    })
-                } else {
-                    eprintln!("Warning: decryption key must be in 'key_id:key' format, ignoring");
+            .filter_map(|key| match KeyString::parse(key) {
+                Ok(key) => Some(DecryptionKey {
+                    key_id: key.kid_hex(),
+                    key: key.key_hex(),
+                }),
+                Err(e) => {
+                    eprintln!("Warning: ignoring decryption key: {e}");
                     None
                 }
             })
@@ -48,10 +207,103 @@ pub async fn run_remux_pipeline(
         source_config = source_config.with_headers(headers.to_vec());
     }
 
+    source_config
+}
+
+/**
+    Create the loudness normalizer for a channel, running the
+    measurement pass first if two-pass normalization is configured.
+
+    Live inputs never end, so they skip the measurement pass and use
+    dynamic normalization right away instead of delaying the startup.
+    Inputs that can't be checked are taken to be live.
+*/
+async fn create_normalizer(
+    input_url: &str,
+    headers: &[(String, String)],
+    config: SourceConfig,
+    loudness: &LoudnessConfig,
+    manifest_client: Option<&reqwest::Client>,
+) -> Result<LoudnessNormalizer, ffmpeg_types::Error> {
+    let is_live = match manifest_client {
+        Some(client) if loudness.mode == LoudnessMode::TwoPass => {
+            is_live_source(client, input_url, headers)
+                .await
+                .unwrap_or(true)
+        }
+        _ => false,
+    };
+    if is_live {
+        println!("Input is live, using dynamic normalization");
+    } else if loudness.mode == LoudnessMode::TwoPass {
+        println!("Measuring input loudness (first pass)");
+        match transcode::measure_loudness(input_url, config).await? {
+            Some(measured) => {
+                println!(
+                    "Input loudness is {:.1} LUFS, applying {:+.1} dB",
+                    measured,
+                    loudness.target - measured
+                );
+                return Ok(LoudnessNormalizer::fixed(
+                    loudness.target,
+                    measured,
+                    transcode::CHANNELS,
+                ));
+            }
+            None => {
+                println!("Input could not be measured (live?), using dynamic normalization");
+            }
+        }
+    }
+    Ok(LoudnessNormalizer::dynamic(
+        loudness.target,
+        transcode::CHANNELS,
+    ))
+}
+
+/**
+    Run the remux pipeline: read from source HLS/DASH, write to local HLS.
+
+    If reading the source fails mid-stream and a key refresher is given, new
+    keys are fetched, and if they changed (the keys were rotated) the source
+    is reopened with them while the sink keeps running. A discontinuity is
+    only marked if the timestamps jump.
+
+    When a live DASH source reaches the end of its input (as FFmpeg does at
+    period boundaries, e.g. with server-side ad insertion), it is reopened
+    at the live edge, given a manifest client to check the manifest with.
+    Timestamps are not offset across the reopen, a discontinuity is marked
+    where they jump instead. Static multi-period manifests are left to
+    FFmpeg, and a source that stalls without ending is not reopened.
+
+    Streams that need output processing are partially transcoded: audio is
+    re-encoded for loudness normalization and video for an overlay, while
+    streams without processing are still remuxed as-is.
+*/
+#[allow(clippy::too_many_arguments)]
+pub async fn run_remux_pipeline(
+    input_url: &str,
+    headers: &[(String, String)],
+    decryption_keys: &[String],
+    key_refresher: Option<KeyRefresher>,
+    processing: OutputProcessing,
+    params_tx: Option<oneshot::Sender<StreamParams>>,
+    output_dir: &Path,
+    segment_duration: Duration,
+    segment_manager: Arc<SegmentManager>,
+    manifest_client: Option<reqwest::Client>,
+    mut shutdown_rx: watch::Receiver<bool>,
+) -> Result<(), ffmpeg_types::Error> {
+    let mut decryption_keys = decryption_keys.to_vec();
+
+    // FFmpeg sends the same headers with every request, so `request.*` in them
+    // is resolved once, against the manifest, and not for each segment
+    let headers = request_headers(headers, input_url);
+
     // Open source (now async)
-    let mut source = Source::open(input_url, source_config).await?;
+    let mut source = Source::open(input_url, source_config(&headers, &decryption_keys)).await?;
 
-    let media_info = source.media_info();
+    let media_info = source.media_info().clone();
     println!(
         "Source: {}x{}, {:?}",
         media_info.video.as_ref().map(|v| v.width).unwrap_or(0),
@@ -71,24 +323,118 @@ pub async fn run_remux_pipeline(
         );
     }
 
+    if let Some(tx) = params_tx {
+        let _ = tx.send(StreamParams {
+            video: media_info
+                .video
+                .as_ref()
+                .map(|v| format!("{:?} {}x{}", v.codec_id, v.width, v.height)),
+            audio: media_info
+                .audio
+                .as_ref()
+                .map(|a| format!("{:?}", a.codec_id)),
+        });
+    }
+
+    // Codec configs can only be taken from the source once, share them between stages
+    let needs_audio = processing.loudness.is_some() || processing.ad_break_log.is_some();
+    let (width, height) = media_info
+        .video
+        .as_ref()
+        .map(|v| (v.width, v.height))
+        .unwrap_or_default();
+    let output_size = transcode::scaled_size(width, height, processing.max_height);
+    let rescale = output_size != (width, height);
+    let needs_video = processing.overlay.is_some() || rescale || processing.ad_break_log.is_some();
+    let audio_codec = match media_info.audio {
+        Some(_) if needs_audio => Some(StreamCodec::audio(&mut source)?),
+        _ => None,
+    };
+    let video_codec = match media_info.video {
+        Some(_) if needs_video => Some(StreamCodec::video(&mut source)?),
+        _ => None,
+    };
+
+    // Route audio through the transcode path only when it needs processing
+    let mut audio_transcoder = match (processing.loudness, audio_codec.as_ref()) {
+        (Some(ref loudness), Some(codec)) => {
+            println!(
+                "Normalizing audio to {:.1} LUFS ({:?})",
+                loudness.target, loudness.mode
+            );
+            let normalizer = create_normalizer(
+                input_url,
+                &headers,
+                source_config(&headers, &decryption_keys),
+                loudness,
+                manifest_client.as_ref(),
+            )
+            .await?;
+            Some(AudioTranscoder::new(codec, normalizer, loudness.gaps)?)
+        }
+        _ => None,
+    };
+    let mut video_transcoder = match video_codec.as_ref() {
+        Some(codec) if processing.overlay.is_some() || rescale => {
+            if rescale {
+                println!("Scaling video to {}x{}", output_size.0, output_size.1);
+            }
+            if processing.overlay.is_some() {
+                println!("Burning overlay into video");
+            }
+            Some(VideoTranscoder::new(
+                codec,
+                output_size.0,
+                output_size.1,
+                processing.overlay,
+            )?)
+        }
+        _ => None,
+    };
+    let mut ad_break_analyzer = match processing.ad_break_log {
+        Some(ref log) => {
+            println!("Detecting ad breaks from black frames and silence");
+            Some((
+                AdBreakAnalyzer::new(video_codec.as_ref(), audio_codec.as_ref())?,
+                Arc::clone(log),
+            ))
+        }
+        None => None,
+    };
+
     // Configure HLS sink
     let playlist_path = output_dir.join("playlist.m3u8");
     let mut sink_config = SinkConfig::hls(segment_duration).rebase_timestamps();
 
-    if let Some(video_info) = media_info.video.clone() {
+    if let Some(ref transcoder) = video_transcoder {
+        sink_config = sink_config.with_video(transcoder.output_info());
+    } else if let Some(video_info) = media_info.video.clone() {
         sink_config = sink_config.with_video(video_info);
     }
-    if let Some(audio_info) = media_info.audio.clone() {
+    if let Some(ref transcoder) = audio_transcoder {
+        sink_config = sink_config.with_audio(transcoder.output_info());
+    } else if let Some(audio_info) = media_info.audio.clone() {
         sink_config = sink_config.with_audio(audio_info);
     }
 
     let mut sink = Sink::file(&playlist_path, sink_config)?;
     println!("Sink created: {:?}", sink);
 
+    segment_manager.set_expectations(SegmentExpectations {
+        video: media_info.video.is_some(),
+        audio: media_info.audio.is_some(),
+        target_duration: segment_duration,
+    });
+
     println!("Writing HLS to: {}", output_dir.display());
 
     let mut packet_count = 0u64;
-    let mut last_scan = std::time::Instant::now();
+    let mut last_scan = Instant::now();
+    let mut last_publish = Instant::now();
+    let mut last_key_refresh: Option<Instant> = None;
+    let mut timestamps = TimestampTracker::default();
+    let mut packets_since_open = 0u64;
+    let max_gap = segment_duration.as_secs_f64() * 2.0;
 
     // Remux loop
     loop {
@@ -106,34 +452,444 @@ pub async fn run_remux_pipeline(
         }
 
         // Read next packet
-        let packet = match source.next_packet()? {
-            Some(p) => p,
-            None => {
-                // End of stream (shouldn't happen for live)
-                println!("Source ended");
-                break;
+        let packet = match source.next_packet() {
+            Ok(Some(p)) => p,
+            Ok(None) => {
+                // Live DASH can end at period boundaries, reopen it at the live edge
+                let reopen = match manifest_client.as_ref() {
+                    Some(client) if packets_since_open > 0 => {
+                        is_live_dash(client, input_url, &headers).await
+                    }
+                    _ => false,
+                };
+                if !reopen {
+                    println!("Source ended");
+                    break;
+                }
+
+                println!("Live DASH source ended, reopening");
+                source = Source::open(input_url, source_config(&headers, &decryption_keys)).await?;
+                timestamps.reopened = true;
+                packets_since_open = 0;
+                continue;
+            }
+            Err(e) => {
+                // Source errors have no kinds to tell decryption failures apart,
+                // so any error may be rotated keys, unless the keys turn out unchanged
+                let can_refresh =
+                    last_key_refresh.is_none_or(|t| t.elapsed() >= MIN_KEY_REFRESH_INTERVAL);
+                let Some(refresh) = key_refresher.as_ref().filter(|_| can_refresh) else {
+                    return Err(e);
+                };
+
+                println!("Reading source failed ({}), refreshing content keys", e);
+                last_key_refresh = Some(Instant::now());
+
+                let new_keys = match refresh().await {
+                    Ok(keys) => keys,
+                    Err(refresh_err) => {
+                        eprintln!("Failed to refresh content keys: {}", refresh_err);
+                        return Err(e);
+                    }
+                };
+                if new_keys == decryption_keys {
+                    eprintln!("Content keys unchanged after refresh");
+                    return Err(e);
+                }
+
+                println!(
+                    "Got {} new content key(s), reopening source",
+                    new_keys.len()
+                );
+                decryption_keys = new_keys;
+                source = Source::open(input_url, source_config(&headers, &decryption_keys)).await?;
+                timestamps.reopened = true;
+                packets_since_open = 0;
+                continue;
             }
         };
 
+        // Mark a discontinuity only where timestamps jump, within the input or across reopens
+        packets_since_open += 1;
+        let packet_secs = packet_seconds(&packet);
+        if let Some(secs) = packet_secs
+            && let Some(jump) = timestamps.check(packet.stream_type, secs, max_gap)
+        {
+            println!("Timestamps jumped by {:.2}s, marking discontinuity", jump);
+            segment_manager.mark_discontinuity();
+        }
+
+        if let Some((analyzer, log)) = ad_break_analyzer.as_mut()
+            && let Some(secs) = packet_secs
+            && let Some(boundary) = analyzer.analyze(&packet, secs)
+        {
+            let now = chrono::Utc::now().timestamp_millis() as f64 / 1000.0;
+            println!(
+                "Black frames with silence at {:.2}s, likely ad boundary",
+                boundary
+            );
+            log.lock().unwrap().record(AdBreak {
+                id: format!("heuristic-{:.0}", boundary * 1000.0),
+                start: now - (secs - boundary),
+                duration: None,
+                source: AdBreakSource::Heuristic,
+            });
+        }
+
         // Write to sink
-        sink.write(&packet)?;
+        let transcoded = if packet.stream_type == StreamType::Audio {
+            audio_transcoder.as_mut().map(|t| t.transcode(&packet))
+        } else if packet.stream_type == StreamType::Video {
+            video_transcoder.as_mut().map(|t| t.transcode(&packet))
+        } else {
+            None
+        };
+        match transcoded {
+            Some(packets) => {
+                for encoded in packets? {
+                    sink.write(&encoded)?;
+                }
+            }
+            None => sink.write(&packet)?,
+        }
+        if audio_transcoder.as_mut().is_some_and(|t| t.take_gap()) {
+            segment_manager.mark_discontinuity();
+        }
         packet_count += 1;
 
-        // Periodically scan for new segments and log progress
+        // Publish finished segments soon after FFmpeg closes them
+        if last_publish.elapsed() > PUBLISH_INTERVAL {
+            if let Err(e) = segment_manager.publish_async().await {
+                eprintln!("Failed to publish segments: {}", e);
+            }
+            last_publish = Instant::now();
+        }
+
+        // Periodically log progress
         if last_scan.elapsed() > Duration::from_secs(2) {
-            segment_manager.scan_for_new_segments();
-            println!(
-                "Packets: {}, Segments: {}",
-                packet_count,
-                segment_manager.segment_count()
-            );
-            last_scan = std::time::Instant::now();
+            match audio_transcoder {
+                Some(ref transcoder) => println!(
+                    "Packets: {}, Segments: {}, Audio gain: {:+.1} dB",
+                    packet_count,
+                    segment_manager.segment_count(),
+                    transcoder.gain_db()
+                ),
+                None => println!(
+                    "Packets: {}, Segments: {}",
+                    packet_count,
+                    segment_manager.segment_count()
+                ),
+            }
+            last_scan = Instant::now();
         }
     }
 
     // Finalize
+    if let Some(ref mut transcoder) = audio_transcoder {
+        for encoded in transcoder.flush()? {
+            sink.write(&encoded)?;
+        }
+    }
+    if let Some(ref mut transcoder) = video_transcoder {
+        for encoded in transcoder.flush()? {
+            sink.write(&encoded)?;
+        }
+    }
     sink.finish()?;
+    if let Err(e) = segment_manager.publish_async().await {
+        eprintln!("Failed to publish segments: {}", e);
+    }
     println!("Remux pipeline stopped after {} packets", packet_count);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use axum::Router;
+    use ffmpeg_transform::{AudioTransform, AudioTransformConfig};
+    use media_fixtures::EncryptedAsset;
+    use media_test_pattern::write_test_pattern;
+    use tempfile::TempDir;
+    use tower_http::services::ServeDir;
+
+    use super::*;
+    use crate::loudness::SAMPLE_RATE;
+    use crate::manifest::AudioGapPolicy;
+    use crate::transcode::CHANNELS;
+
+    const FIXTURE_DURATION: Duration = Duration::from_secs(6);
+    const SEGMENT_DURATION: Duration = Duration::from_secs(2);
+    /// Give up on a pipeline that doesn't stop by itself after this long
+    const PIPELINE_TIMEOUT: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_hls_first_variant() {
+        let master = "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=800000\n\nlow/index.m3u8\n#EXT-X-STREAM-INF:BANDWIDTH=2000000\nhigh/index.m3u8\n";
+        assert_eq!(hls_first_variant(master), Some("low/index.m3u8"));
+
+        let media = "#EXTM3U\n#EXT-X-TARGETDURATION:2\n#EXTINF:2.0,\nsegment0.ts\n";
+        assert_eq!(hls_first_variant(media), None);
+    }
+
+    #[test]
+    fn test_timestamp_tracker() {
+        let mut timestamps = TimestampTracker::default();
+        assert_eq!(timestamps.check(StreamType::Video, 100.0, 4.0), None);
+        assert_eq!(timestamps.check(StreamType::Audio, 99.5, 4.0), None);
+        // Reordered frames step back a little
+        assert_eq!(timestamps.check(StreamType::Video, 99.9, 4.0), None);
+
+        // The next period starts its timestamps over, both streams jump once
+        assert_eq!(timestamps.check(StreamType::Video, 0.0, 4.0), Some(-99.9));
+        assert_eq!(timestamps.check(StreamType::Audio, 0.1, 4.0), None);
+        assert_eq!(timestamps.check(StreamType::Video, 0.04, 4.0), None);
+
+        timestamps.reopened = true;
+        assert_eq!(timestamps.check(StreamType::Video, 0.0, 4.0), Some(-0.04));
+    }
+
+    /**
+        Serve a directory over HTTP on a random local port, returning its base URL.
+    */
+    async fn serve_fixtures(dir: PathBuf) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().fallback_service(ServeDir::new(dir));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}", addr)
+    }
+
+    /**
+        Parse a media playlist into its target duration and segments,
+        checking the structure every client relies on along the way.
+    */
+    fn parse_media_playlist(playlist: &str) -> (f64, Vec<(f64, String)>) {
+        assert!(playlist.starts_with("#EXTM3U\n"), "missing header");
+        let target = playlist
+            .lines()
+            .find_map(|l| l.strip_prefix("#EXT-X-TARGETDURATION:"))
+            .and_then(|v| v.trim().parse::<f64>().ok())
+            .expect("missing target duration");
+        assert!(
+            playlist.contains("#EXT-X-MEDIA-SEQUENCE:0\n"),
+            "not a fresh run"
+        );
+
+        let mut segments = Vec::new();
+        let mut pending = None;
+        for line in playlist.lines() {
+            if let Some(info) = line.strip_prefix("#EXTINF:") {
+                assert!(pending.is_none(), "segment without URI");
+                let duration = info.split(',').next().unwrap().parse::<f64>().unwrap();
+                pending = Some(duration);
+            } else if !line.is_empty() && !line.starts_with('#') {
+                let duration = pending.take().expect("URI without duration");
+                segments.push((duration, line.to_string()));
+            }
+        }
+        assert!(pending.is_none(), "playlist ends in a segment without URI");
+        (target, segments)
+    }
+
+    /**
+        Count how often the first channel of interleaved samples changes sign,
+        per sample. Samples close to zero are skipped, so that the dither
+        around silence isn't counted.
+    */
+    fn zero_crossing_rate(samples: &[f32], channels: usize) -> f64 {
+        let mut crossings = 0;
+        let mut last_positive = None;
+        for sample in samples.iter().step_by(channels) {
+            if sample.abs() < 0.01 {
+                continue;
+            }
+            let positive = *sample > 0.0;
+            if last_positive.is_some_and(|last| last != positive) {
+                crossings += 1;
+            }
+            last_positive = Some(positive);
+        }
+        crossings as f64 / (samples.len() / channels).max(1) as f64
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_remux_pipeline_output() {
+        let fixtures = TempDir::new().unwrap();
+        let fixture_dir = fixtures.path().to_path_buf();
+        tokio::task::spawn_blocking({
+            let dir = fixture_dir.clone();
+            move || write_test_pattern(&dir, 640, 360, 25, FIXTURE_DURATION)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        let base_url = serve_fixtures(fixture_dir).await;
+
+        let output = TempDir::new().unwrap();
+        let segment_manager = Arc::new(SegmentManager::new(output.path().to_path_buf(), 100));
+        segment_manager.clear();
+
+        let (params_tx, params_rx) = oneshot::channel();
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let timeout = tokio::spawn(async move {
+            tokio::time::sleep(PIPELINE_TIMEOUT).await;
+            let _ = shutdown_tx.send(true);
+        });
+
+        run_remux_pipeline(
+            &format!("{}/playlist.m3u8", base_url),
+            &[],
+            &[],
+            None,
+            OutputProcessing::default(),
+            Some(params_tx),
+            segment_manager.staging_dir(),
+            SEGMENT_DURATION,
+            Arc::clone(&segment_manager),
+            None,
+            shutdown_rx,
+        )
+        .await
+        .unwrap();
+        timeout.abort();
+
+        let params = params_rx.await.unwrap();
+        assert!(params.video.unwrap().ends_with(" 640x360"));
+        assert!(params.audio.is_some());
+
+        // Playlist structure and segment durations
+        let playlist_path = segment_manager.playlist_path();
+        let playlist = std::fs::read_to_string(&playlist_path).unwrap();
+        let (target, segments) = parse_media_playlist(&playlist);
+        assert!(!segments.is_empty());
+        assert!(target >= SEGMENT_DURATION.as_secs_f64());
+        for (duration, uri) in &segments {
+            assert!(
+                *duration > 0.0 && *duration <= target + 0.5,
+                "{}: {}s",
+                uri,
+                duration
+            );
+            assert!(output.path().join(uri).is_file(), "{} not published", uri);
+        }
+        let total: f64 = segments.iter().map(|(d, _)| d).sum();
+        assert!(
+            (total - FIXTURE_DURATION.as_secs_f64()).abs() <= target,
+            "remuxed {}s of {}s",
+            total,
+            FIXTURE_DURATION.as_secs_f64()
+        );
+
+        // Nothing is left behind in staging once the pipeline has stopped
+        let staged = std::fs::read_dir(segment_manager.staging_dir())
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "ts"))
+            .count();
+        assert_eq!(staged, 0);
+
+        // The published output decodes
+        let path = playlist_path.to_string_lossy().to_string();
+        let mut source = Source::open(&path, SourceConfig::default()).await.unwrap();
+        let mut decoder = StreamCodec::video(&mut source)
+            .unwrap()
+            .video_decoder()
+            .unwrap();
+        let mut frames = 0;
+        while let Some(packet) = source.next_packet().unwrap() {
+            if packet.stream_type == StreamType::Video {
+                frames += decoder.decode(&packet).unwrap().len();
+            }
+        }
+        assert!(frames > 0);
+
+        let loudness = transcode::measure_loudness(&path, SourceConfig::default())
+            .await
+            .unwrap()
+            .expect("no measurable audio");
+        // A -20 dBFS sine on both channels
+        assert!((-23.0..-18.0).contains(&loudness), "{} LUFS", loudness);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_remux_pipeline_decrypts_dash() {
+        let asset = EncryptedAsset::generate();
+        let fixtures = TempDir::new().unwrap();
+        asset.write_to(fixtures.path()).unwrap();
+        let base_url = serve_fixtures(fixtures.path().to_path_buf()).await;
+
+        let output = TempDir::new().unwrap();
+        let segment_manager = Arc::new(SegmentManager::new(output.path().to_path_buf(), 100));
+        segment_manager.clear();
+
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let timeout = tokio::spawn(async move {
+            tokio::time::sleep(PIPELINE_TIMEOUT).await;
+            let _ = shutdown_tx.send(true);
+        });
+
+        // The fixture is PCM, which MPEG-TS can't carry, so its audio is re-encoded
+        let processing = OutputProcessing {
+            loudness: Some(LoudnessConfig {
+                target: -23.0,
+                mode: LoudnessMode::TwoPass,
+                gaps: AudioGapPolicy::Fill,
+            }),
+            ..Default::default()
+        };
+        run_remux_pipeline(
+            &format!("{}/{}", base_url, media_fixtures::MANIFEST),
+            &[],
+            &[EncryptedAsset::key().to_string()],
+            None,
+            processing,
+            None,
+            segment_manager.staging_dir(),
+            SEGMENT_DURATION,
+            Arc::clone(&segment_manager),
+            None,
+            shutdown_rx,
+        )
+        .await
+        .unwrap();
+        timeout.abort();
+
+        let path = segment_manager
+            .playlist_path()
+            .to_string_lossy()
+            .to_string();
+        let mut source = Source::open(&path, SourceConfig::default()).await.unwrap();
+        let mut decoder = StreamCodec::audio(&mut source)
+            .unwrap()
+            .audio_decoder()
+            .unwrap();
+        let mut transform = AudioTransform::new(AudioTransformConfig::playback());
+        let mut samples = Vec::new();
+        while let Some(packet) = source.next_packet().unwrap() {
+            for frame in decoder.decode(&packet).unwrap() {
+                let transformed = transform.transform(&frame).unwrap();
+                samples.extend(
+                    transformed
+                        .data
+                        .chunks_exact(4)
+                        .map(|b| f32::from_ne_bytes(b.try_into().unwrap())),
+                );
+            }
+        }
+
+        // Decrypted audio is the fixture's tone, audio left encrypted would be noise
+        let seconds = samples.len() as f64 / CHANNELS as f64 / f64::from(SAMPLE_RATE);
+        assert!(seconds > 1.0, "decoded {}s", seconds);
+        let expected = 2.0 * asset.options.frequency / f64::from(SAMPLE_RATE);
+        let rate = zero_crossing_rate(&samples, CHANNELS);
+        assert!(
+            (rate - expected).abs() < expected * 0.1,
+            "{} zero crossings per sample, expected {}",
+            rate,
+            expected
+        );
+    }
+}
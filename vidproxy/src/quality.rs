@@ -0,0 +1,319 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Write};
+
+use serde::Serialize;
+
+use crate::access_log::escape_label;
+use crate::segment_check::Resolution;
+
+/// Number of quality change events kept per channel
+const MAX_EVENTS: usize = 64;
+
+/// Segment bitrates differing from the current level by this factor suggest a switch
+const SWITCH_RATIO: f64 = 1.5;
+/// Consecutive segments that must agree before a bitrate switch is reported
+const SWITCH_SEGMENTS: usize = 2;
+
+/**
+    A change in the quality of a channel's stream, usually an upstream
+    ABR switch to another representation.
+*/
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum QualityChange {
+    Resolution {
+        from: Resolution,
+        to: Resolution,
+    },
+    /// Bitrates in bits per second
+    Bitrate {
+        from: u64,
+        to: u64,
+    },
+}
+
+impl fmt::Display for QualityChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QualityChange::Resolution { from, to } => {
+                write!(f, "resolution changed from {} to {}", from, to)
+            }
+            QualityChange::Bitrate { from, to } => write!(
+                f,
+                "bitrate changed from {} to {} kbps",
+                from / 1000,
+                to / 1000
+            ),
+        }
+    }
+}
+
+/**
+    A quality change, and when it was published.
+*/
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QualityEvent {
+    /// Unix timestamp in seconds
+    pub at: f64,
+    #[serde(flatten)]
+    pub change: QualityChange,
+}
+
+/**
+    Current quality of a channel's stream, and how it changed.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct QualitySnapshot {
+    pub resolution: Option<Resolution>,
+    /// Smoothed bitrate in bits per second
+    pub bitrate: Option<u64>,
+    /// Total changes seen, including those no longer kept as events
+    pub resolution_changes: u64,
+    pub bitrate_changes: u64,
+    /// Recent changes, oldest first
+    pub changes: Vec<QualityEvent>,
+}
+
+/**
+    Tracks the resolution and bitrate of a channel's published segments,
+    recording the changes between them.
+
+    Bitrates of single segments vary a lot with the content, so only
+    a sustained jump away from the current level counts as a switch.
+*/
+#[derive(Debug, Default)]
+pub struct QualityLog {
+    events: VecDeque<QualityEvent>,
+    resolution: Option<Resolution>,
+    /// Smoothed bitrate of the current representation
+    bitrate: Option<u64>,
+    /// Bitrates of recent segments that strayed from the current level
+    strays: Vec<u64>,
+    resolution_changes: u64,
+    bitrate_changes: u64,
+}
+
+impl QualityLog {
+    /**
+        Observe the next published segment. Returns true if its resolution
+        differs from the previous segment's, so clients should be told
+        about a discontinuity before it.
+    */
+    pub fn observe(
+        &mut self,
+        resolution: Option<Resolution>,
+        bitrate: Option<u64>,
+        now: f64,
+    ) -> bool {
+        let mut resolution_changed = false;
+        if let Some(to) = resolution {
+            if let Some(from) = self.resolution
+                && from != to
+            {
+                self.resolution_changes += 1;
+                self.record(QualityChange::Resolution { from, to }, now);
+                resolution_changed = true;
+            }
+            self.resolution = Some(to);
+        }
+
+        if let Some(bitrate) = bitrate {
+            self.observe_bitrate(bitrate, now);
+        }
+
+        resolution_changed
+    }
+
+    fn observe_bitrate(&mut self, bitrate: u64, now: f64) {
+        let Some(level) = self.bitrate else {
+            self.bitrate = Some(bitrate);
+            return;
+        };
+
+        let ratio = bitrate as f64 / level.max(1) as f64;
+        if (1.0 / SWITCH_RATIO..=SWITCH_RATIO).contains(&ratio) {
+            self.strays.clear();
+            self.bitrate = Some((level * 3 + bitrate) / 4);
+            return;
+        }
+
+        // Only count strays in the same direction towards a switch
+        if self
+            .strays
+            .first()
+            .is_some_and(|s| (*s > level) != (bitrate > level))
+        {
+            self.strays.clear();
+        }
+        self.strays.push(bitrate);
+        if self.strays.len() < SWITCH_SEGMENTS {
+            return;
+        }
+
+        let to = self.strays.iter().sum::<u64>() / self.strays.len() as u64;
+        self.strays.clear();
+        self.bitrate = Some(to);
+        self.bitrate_changes += 1;
+        self.record(QualityChange::Bitrate { from: level, to }, now);
+    }
+
+    fn record(&mut self, change: QualityChange, at: f64) {
+        println!("[quality] Stream {}", change);
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(QualityEvent { at, change });
+    }
+
+    /**
+        Get the current quality and the recorded changes.
+    */
+    pub fn snapshot(&self) -> QualitySnapshot {
+        QualitySnapshot {
+            resolution: self.resolution,
+            bitrate: self.bitrate,
+            resolution_changes: self.resolution_changes,
+            bitrate_changes: self.bitrate_changes,
+            changes: self.events.iter().cloned().collect(),
+        }
+    }
+}
+
+/**
+    Render the stream quality of channels in the Prometheus text format.
+*/
+pub fn render_metrics(channels: &[(String, QualitySnapshot)]) -> String {
+    let mut out = String::new();
+
+    out.push_str("# HELP vidproxy_stream_bitrate_bits Smoothed bitrate of published segments\n");
+    out.push_str("# TYPE vidproxy_stream_bitrate_bits gauge\n");
+    for (channel, quality) in channels {
+        if let Some(bitrate) = quality.bitrate {
+            let _ = writeln!(
+                out,
+                "vidproxy_stream_bitrate_bits{{channel=\"{}\"}} {}",
+                escape_label(channel),
+                bitrate
+            );
+        }
+    }
+
+    out.push_str("# HELP vidproxy_stream_height_pixels Video height of published segments\n");
+    out.push_str("# TYPE vidproxy_stream_height_pixels gauge\n");
+    for (channel, quality) in channels {
+        if let Some(resolution) = quality.resolution {
+            let _ = writeln!(
+                out,
+                "vidproxy_stream_height_pixels{{channel=\"{}\"}} {}",
+                escape_label(channel),
+                resolution.height
+            );
+        }
+    }
+
+    out.push_str(
+        "# HELP vidproxy_stream_quality_changes_total Resolution and bitrate changes by channel\n",
+    );
+    out.push_str("# TYPE vidproxy_stream_quality_changes_total counter\n");
+    for (channel, quality) in channels {
+        for (kind, count) in [
+            ("resolution", quality.resolution_changes),
+            ("bitrate", quality.bitrate_changes),
+        ] {
+            let _ = writeln!(
+                out,
+                "vidproxy_stream_quality_changes_total{{channel=\"{}\",kind=\"{}\"}} {}",
+                escape_label(channel),
+                kind,
+                count
+            );
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HD: Resolution = Resolution {
+        width: 1280,
+        height: 720,
+    };
+    const FULL_HD: Resolution = Resolution {
+        width: 1920,
+        height: 1080,
+    };
+
+    #[test]
+    fn test_resolution_change() {
+        let mut log = QualityLog::default();
+        assert!(!log.observe(Some(HD), None, 1.0));
+        assert!(!log.observe(None, None, 2.0));
+        assert!(!log.observe(Some(HD), None, 3.0));
+        assert!(log.observe(Some(FULL_HD), None, 4.0));
+
+        let snapshot = log.snapshot();
+        assert_eq!(
+            snapshot.changes,
+            vec![QualityEvent {
+                at: 4.0,
+                change: QualityChange::Resolution {
+                    from: HD,
+                    to: FULL_HD
+                },
+            }]
+        );
+        assert_eq!(snapshot.resolution, Some(FULL_HD));
+        assert_eq!(snapshot.resolution_changes, 1);
+    }
+
+    #[test]
+    fn test_bitrate_switch_must_be_sustained() {
+        let mut log = QualityLog::default();
+        for bitrate in [4_000_000, 4_400_000, 3_600_000, 9_000_000, 4_000_000] {
+            log.observe(None, Some(bitrate), 0.0);
+        }
+        assert!(log.snapshot().changes.is_empty());
+
+        log.observe(None, Some(1_500_000), 10.0);
+        log.observe(None, Some(1_300_000), 14.0);
+        let snapshot = log.snapshot();
+        let events = snapshot.changes;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].at, 14.0);
+        assert!(matches!(
+            events[0].change,
+            QualityChange::Bitrate { to: 1_400_000, .. }
+        ));
+        assert_eq!(snapshot.bitrate, Some(1_400_000));
+    }
+
+    #[test]
+    fn test_render_metrics() {
+        let mut log = QualityLog::default();
+        log.observe(Some(HD), Some(3_000_000), 0.0);
+        let out = render_metrics(&[("src:news".to_string(), log.snapshot())]);
+        assert!(out.contains("vidproxy_stream_bitrate_bits{channel=\"src:news\"} 3000000\n"));
+        assert!(out.contains("vidproxy_stream_height_pixels{channel=\"src:news\"} 720\n"));
+        assert!(out.contains(
+            "vidproxy_stream_quality_changes_total{channel=\"src:news\",kind=\"bitrate\"} 0\n"
+        ));
+    }
+
+    #[test]
+    fn test_event_json() {
+        let event = QualityEvent {
+            at: 1.5,
+            change: QualityChange::Bitrate {
+                from: 2_000_000,
+                to: 1_000_000,
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({"at": 1.5, "kind": "bitrate", "from": 2_000_000, "to": 1_000_000})
+        );
+    }
+}
@@ -1,7 +1,10 @@
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::{Arc, RwLock};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
 use tokio::sync::Notify;
 
 use crate::manifest::{ChannelEntry, StreamInfo};
@@ -46,6 +49,90 @@ impl ChannelContentState {
     }
 }
 
+/**
+    Discovery schedule for a source, maintained by the discovery scheduler.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct DiscoverySchedule {
+    /// When discovery will next run (None = not scheduled)
+    pub next_run_at: Option<u64>,
+    /// When discovery last ran
+    pub last_run_at: Option<u64>,
+    /// Number of consecutive failed discovery runs
+    pub consecutive_failures: u32,
+    /// Error from the last discovery run, if it failed
+    pub last_error: Option<String>,
+    /// Whether the last discovery run failed by exceeding its deadline
+    pub timed_out: bool,
+}
+
+/**
+    Time without watching a channel after which watching it again counts
+    as a new tune, rather than the same viewing (5 minutes)
+*/
+const TUNE_SESSION_GAP: u64 = 5 * 60;
+
+/**
+    Delay before retrying a channel whose first tune failed (10 seconds)
+*/
+const TUNE_BACKOFF_BASE: u64 = 10;
+
+/**
+    Longest delay between retries of a channel that keeps failing to tune (5 minutes)
+*/
+const TUNE_BACKOFF_MAX: u64 = 5 * 60;
+
+/**
+    How often a channel is watched, for deciding which channels to keep warm.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ChannelUsage {
+    /// Number of times the channel was tuned to
+    pub tunes: u32,
+    /// When the channel was last watched
+    pub last_watched_at: Option<u64>,
+}
+
+impl ChannelUsage {
+    /**
+        Record that the channel is being watched. Playlist requests repeat
+        while a client watches, so only the first after a while is a tune.
+    */
+    pub fn record_watch(&mut self, now: u64) {
+        let new_tune = self
+            .last_watched_at
+            .is_none_or(|last| now.saturating_sub(last) >= TUNE_SESSION_GAP);
+        if new_tune {
+            self.tunes = self.tunes.saturating_add(1);
+        }
+        self.last_watched_at = Some(now);
+    }
+}
+
+/**
+    Failed tunes of a channel, for backing off instead of retrying on every request.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TuneFailures {
+    /// Number of consecutive failed tunes
+    pub consecutive_failures: u32,
+    /// When the channel last failed to tune
+    pub failed_at: u64,
+}
+
+impl TuneFailures {
+    /**
+        When the channel may be tuned again, doubling the delay with each failure.
+    */
+    pub fn retry_at(&self) -> u64 {
+        let exponent = self.consecutive_failures.saturating_sub(1).min(16);
+        let delay = TUNE_BACKOFF_BASE
+            .saturating_mul(1 << exponent)
+            .min(TUNE_BACKOFF_MAX);
+        self.failed_at.saturating_add(delay)
+    }
+}
+
 /**
     Full channel ID combining source and channel ID.
 */
@@ -66,7 +153,6 @@ impl ChannelId {
     /**
         Parse from "source:id" format
     */
-    #[allow(dead_code)]
     pub fn parse(s: &str) -> Option<Self> {
         let (source, id) = s.split_once(':')?;
         Some(Self::new(source, id))
@@ -81,6 +167,146 @@ impl ChannelId {
     }
 }
 
+/**
+    A single source's channels as persisted in the registry cache file.
+*/
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct CachedSource {
+    channels: Vec<ChannelEntry>,
+    discovery_expires_at: Option<u64>,
+}
+
+/**
+    On-disk format of the registry cache file, keyed by source ID.
+*/
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct RegistryCache {
+    sources: HashMap<String, CachedSource>,
+    /// How often channels were watched, keyed by "source:id"
+    #[serde(default)]
+    usage: HashMap<String, ChannelUsage>,
+}
+
+/**
+    Write a file that only the current user can read or write.
+
+    Any file left at the path is removed first, since the mode
+    only applies when the file is created.
+*/
+pub(crate) fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let _ = std::fs::remove_file(path);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/**
+    Keys that identify the same channel across sources: the channel name
+    with case, spacing and punctuation removed (or its ID if unnamed), and
+    the resolved manifest URL without scheme and query.
+*/
+fn dedup_keys(entry: &ChannelEntry) -> Vec<String> {
+    let mut keys = Vec::new();
+
+    let name = entry.channel.name.as_deref().unwrap_or(&entry.channel.id);
+    let name: String = name
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect();
+    if !name.is_empty() {
+        keys.push(format!("name:{}", name));
+    }
+
+    if let Some(stream_info) = &entry.stream_info {
+        let url = stream_info.manifest_url.as_str();
+        let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+        let url = url.split(['?', '#']).next().unwrap_or(url);
+        keys.push(format!("url:{}", url.to_lowercase()));
+    }
+
+    keys
+}
+
+/**
+    Sort key for choosing between duplicates: highest source priority first,
+    then by ID so that the choice is stable.
+*/
+fn preference_key(id: &ChannelId, priorities: &HashMap<String, i32>) -> (i32, String, String) {
+    let priority = priorities.get(&id.source).copied().unwrap_or(0);
+    (-priority, id.source.clone(), id.id.clone())
+}
+
+/**
+    Group channels of different sources that share a dedup key, and map
+    every channel in a group except the preferred one to the preferred one.
+
+    Channels of the same source are never merged, a source listing two
+    channels with the same name usually has a reason to.
+*/
+fn find_alternates(
+    channels: &HashMap<ChannelId, ChannelEntry>,
+    priorities: &HashMap<String, i32>,
+) -> HashMap<ChannelId, ChannelId> {
+    let ids: Vec<&ChannelId> = channels.keys().collect();
+
+    // Union-find over channels that share any key
+    let mut parent: Vec<usize> = (0..ids.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    let mut first_with_key: HashMap<String, usize> = HashMap::new();
+    for (index, id) in ids.iter().enumerate() {
+        for key in dedup_keys(&channels[*id]) {
+            match first_with_key.get(&key) {
+                Some(&other) => {
+                    let (a, b) = (root(&mut parent, index), root(&mut parent, other));
+                    parent[a] = b;
+                }
+                None => {
+                    first_with_key.insert(key, index);
+                }
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<&ChannelId>> = HashMap::new();
+    for (index, id) in ids.iter().enumerate() {
+        groups.entry(root(&mut parent, index)).or_default().push(id);
+    }
+
+    let mut alternates = HashMap::new();
+    for mut group in groups.into_values() {
+        if group.iter().all(|id| id.source == group[0].source) {
+            continue;
+        }
+        group.sort_by_cached_key(|id| preference_key(id, priorities));
+        let primary = group[0];
+        for id in &group[1..] {
+            if id.source != primary.source {
+                alternates.insert((*id).clone(), primary.clone());
+            }
+        }
+    }
+    alternates
+}
+
 /**
     In-memory registry of all discovered channels.
 */
@@ -88,14 +314,28 @@ pub struct ChannelRegistry {
     channels: RwLock<HashMap<ChannelId, ChannelEntry>>,
     /// When each source's discovery results expire
     discovery_expiration: RwLock<HashMap<String, Option<u64>>>,
+    /// When each source's channels were last registered
+    source_updated_at: RwLock<HashMap<String, SystemTime>>,
     /// Current state of each source (Loading, Ready, Failed)
     source_state: RwLock<HashMap<String, SourceState>>,
     /// Notification handles for waiters on each source
     source_notify: RwLock<HashMap<String, Arc<Notify>>>,
+    /// Discovery schedule of each source
+    discovery_schedule: RwLock<HashMap<String, DiscoverySchedule>>,
+    /// Sources blocked by an antibot challenge, with what blocked them
+    needs_attention: RwLock<HashMap<String, String>>,
     /// Per-channel content resolution state
     channel_content_state: RwLock<HashMap<ChannelId, ChannelContentState>>,
     /// Notification handles for waiters on channel content resolution
     channel_content_notify: RwLock<HashMap<ChannelId, Arc<Notify>>>,
+    /// Priority of each source, for choosing between duplicate channels
+    source_priority: RwLock<HashMap<String, i32>>,
+    /// Channels that duplicate a channel of a preferred source, mapped to that channel
+    alternates: RwLock<HashMap<ChannelId, ChannelId>>,
+    /// How often each channel was watched, kept across restarts
+    usage: RwLock<HashMap<ChannelId, ChannelUsage>>,
+    /// Channels whose last tunes failed
+    tune_failures: RwLock<HashMap<ChannelId, TuneFailures>>,
 }
 
 impl ChannelRegistry {
@@ -103,10 +343,17 @@ impl ChannelRegistry {
         Self {
             channels: RwLock::new(HashMap::new()),
             discovery_expiration: RwLock::new(HashMap::new()),
+            source_updated_at: RwLock::new(HashMap::new()),
             source_state: RwLock::new(HashMap::new()),
             source_notify: RwLock::new(HashMap::new()),
+            discovery_schedule: RwLock::new(HashMap::new()),
+            needs_attention: RwLock::new(HashMap::new()),
             channel_content_state: RwLock::new(HashMap::new()),
             channel_content_notify: RwLock::new(HashMap::new()),
+            source_priority: RwLock::new(HashMap::new()),
+            alternates: RwLock::new(HashMap::new()),
+            usage: RwLock::new(HashMap::new()),
+            tune_failures: RwLock::new(HashMap::new()),
         }
     }
 
@@ -150,6 +397,47 @@ impl ChannelRegistry {
         self.source_state.read().unwrap().get(source_id).cloned()
     }
 
+    /**
+        Set the discovery schedule of a source.
+    */
+    pub fn set_discovery_schedule(&self, source_id: &str, schedule: DiscoverySchedule) {
+        let mut schedules = self.discovery_schedule.write().unwrap();
+        schedules.insert(source_id.to_string(), schedule);
+    }
+
+    /**
+        Get the discovery schedule of a source.
+    */
+    pub fn get_discovery_schedule(&self, source_id: &str) -> Option<DiscoverySchedule> {
+        self.discovery_schedule
+            .read()
+            .unwrap()
+            .get(source_id)
+            .cloned()
+    }
+
+    /**
+        Flag a source (and so all of its channels) as needing an operator,
+        because its pages are blocked by something retrying won't get past.
+    */
+    pub fn set_needs_attention(&self, source_id: &str, reason: impl ToString) {
+        self.needs_attention
+            .write()
+            .unwrap()
+            .insert(source_id.to_string(), reason.to_string());
+    }
+
+    pub fn clear_needs_attention(&self, source_id: &str) {
+        self.needs_attention.write().unwrap().remove(source_id);
+    }
+
+    /**
+        Get why a source needs attention, if it does.
+    */
+    pub fn needs_attention(&self, source_id: &str) -> Option<String> {
+        self.needs_attention.read().unwrap().get(source_id).cloned()
+    }
+
     /**
         Wait for a source to finish loading (with timeout).
         Returns the final state (Ready or Failed), or None if timeout.
@@ -213,12 +501,24 @@ impl ChannelRegistry {
         {
             let mut registry = self.channels.write().unwrap();
 
-            // Remove old channels from this source
-            registry.retain(|id, _| id.source != source_name);
+            // Remove old channels from this source, keeping their resolved stream info
+            let mut previous_stream_info = HashMap::new();
+            registry.retain(|id, entry| {
+                if id.source != source_name {
+                    return true;
+                }
+                if let Some(stream_info) = entry.stream_info.take() {
+                    previous_stream_info.insert(id.clone(), stream_info);
+                }
+                false
+            });
 
-            // Add new channels
-            for entry in channels {
+            // Add new channels, carrying over stream info for channels that still exist
+            for mut entry in channels {
                 let id = ChannelId::new(source_name, &entry.channel.id);
+                if entry.stream_info.is_none() {
+                    entry.stream_info = previous_stream_info.remove(&id);
+                }
                 registry.insert(id, entry);
             }
         }
@@ -228,6 +528,10 @@ impl ChannelRegistry {
             let mut expirations = self.discovery_expiration.write().unwrap();
             expirations.insert(source_name.to_string(), discovery_expires_at);
         }
+        {
+            let mut updated_at = self.source_updated_at.write().unwrap();
+            updated_at.insert(source_name.to_string(), SystemTime::now());
+        }
 
         // Mark source as ready
         {
@@ -235,6 +539,8 @@ impl ChannelRegistry {
             states.insert(source_name.to_string(), SourceState::Ready);
         }
 
+        self.update_alternates();
+
         // Notify any waiters
         let notifies = self.source_notify.read().unwrap();
         if let Some(notify) = notifies.get(source_name) {
@@ -242,6 +548,17 @@ impl ChannelRegistry {
         }
     }
 
+    /**
+        Get when a source's channels (and their EPG) were last registered.
+    */
+    pub fn source_updated_at(&self, source_id: &str) -> Option<SystemTime> {
+        self.source_updated_at
+            .read()
+            .unwrap()
+            .get(source_id)
+            .copied()
+    }
+
     /**
         Get a channel by its full ID.
     */
@@ -289,10 +606,40 @@ impl ChannelRegistry {
         Update stream info for a channel.
     */
     pub fn update_stream_info(&self, id: &ChannelId, stream_info: StreamInfo) {
-        let mut registry = self.channels.write().unwrap();
-        if let Some(entry) = registry.get_mut(id) {
-            entry.stream_info = Some(stream_info);
-            entry.last_error = None;
+        let manifest_changed = {
+            let mut registry = self.channels.write().unwrap();
+            match registry.get_mut(id) {
+                Some(entry) => {
+                    let previous = entry.stream_info.replace(stream_info);
+                    entry.last_error = None;
+                    previous.map(|s| s.manifest_url)
+                        != entry.stream_info.as_ref().map(|s| s.manifest_url.clone())
+                }
+                None => false,
+            }
+        };
+        // Resolved manifests may reveal duplicates that names didn't
+        if manifest_changed {
+            self.update_alternates();
+        }
+    }
+
+    /**
+        Drop the stream info of a source's channels, so that it is resolved
+        again on the next request. Running pipelines keep what they have.
+    */
+    pub fn invalidate_stream_info(&self, source: &str) {
+        let ids: Vec<ChannelId> = {
+            let mut registry = self.channels.write().unwrap();
+            registry
+                .iter_mut()
+                .filter(|(id, _)| id.source == source)
+                .filter_map(|(id, entry)| entry.stream_info.take().map(|_| id.clone()))
+                .collect()
+        };
+        let mut states = self.channel_content_state.write().unwrap();
+        for id in ids {
+            states.insert(id, ChannelContentState::Pending);
         }
     }
 
@@ -335,6 +682,17 @@ impl ChannelRegistry {
         false
     }
 
+    /**
+        Set when a source's discovery results expire, after its session
+        was refreshed without discovering its channels again.
+    */
+    pub fn set_discovery_expiration(&self, source: &str, expires_at: Option<u64>) {
+        self.discovery_expiration
+            .write()
+            .unwrap()
+            .insert(source.to_string(), expires_at);
+    }
+
     /**
         Get total channel count.
     */
@@ -346,11 +704,221 @@ impl ChannelRegistry {
     /**
         Check if registry is empty.
     */
-    #[allow(dead_code)]
     pub fn is_empty(&self) -> bool {
         self.channels.read().unwrap().is_empty()
     }
 
+    // ===== Duplicate Channels =====
+
+    /**
+        Set the priority of a source, used to pick which of several
+        sources with the same channel lists it.
+    */
+    pub fn set_source_priority(&self, source_id: &str, priority: i32) {
+        self.source_priority
+            .write()
+            .unwrap()
+            .insert(source_id.to_string(), priority);
+        self.update_alternates();
+    }
+
+    /**
+        Get the channel of a preferred source that the given channel
+        duplicates, or `None` if the channel is not a duplicate.
+    */
+    pub fn primary_of(&self, id: &ChannelId) -> Option<ChannelId> {
+        self.alternates.read().unwrap().get(id).cloned()
+    }
+
+    pub fn is_alternate(&self, id: &ChannelId) -> bool {
+        self.alternates.read().unwrap().contains_key(id)
+    }
+
+    /**
+        Get the duplicates of a channel in other sources, most preferred first,
+        to fall back to when the channel itself can't be played.
+    */
+    pub fn alternates_of(&self, id: &ChannelId) -> Vec<ChannelId> {
+        let priorities = self.source_priority.read().unwrap();
+        let mut alternates: Vec<ChannelId> = self
+            .alternates
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, primary)| *primary == id)
+            .map(|(alternate, _)| alternate.clone())
+            .collect();
+        alternates.sort_by_cached_key(|a| preference_key(a, &priorities));
+        alternates
+    }
+
+    fn update_alternates(&self) {
+        let alternates = {
+            let channels = self.channels.read().unwrap();
+            let priorities = self.source_priority.read().unwrap();
+            find_alternates(&channels, &priorities)
+        };
+        *self.alternates.write().unwrap() = alternates;
+    }
+
+    // ===== Usage =====
+
+    /**
+        Record that a client is watching a channel.
+    */
+    pub fn record_watch(&self, id: &ChannelId) {
+        let mut usage = self.usage.write().unwrap();
+        usage
+            .entry(id.clone())
+            .or_default()
+            .record_watch(crate::time::now());
+    }
+
+    /**
+        Get how often a channel was watched.
+    */
+    pub fn usage(&self, id: &ChannelId) -> ChannelUsage {
+        self.usage
+            .read()
+            .unwrap()
+            .get(id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /**
+        Get the usage of all registered channels that were ever watched.
+    */
+    pub fn list_usage(&self) -> Vec<(ChannelId, ChannelUsage)> {
+        let channels = self.channels.read().unwrap();
+        let usage = self.usage.read().unwrap();
+        usage
+            .iter()
+            .filter(|(id, _)| channels.contains_key(id))
+            .map(|(id, usage)| (id.clone(), *usage))
+            .collect()
+    }
+
+    // ===== Tune Failures =====
+
+    /**
+        Record that a channel failed to tune, extending its backoff.
+    */
+    pub fn record_tune_failure(&self, id: &ChannelId) {
+        let mut failures = self.tune_failures.write().unwrap();
+        let failures = failures.entry(id.clone()).or_default();
+        failures.consecutive_failures = failures.consecutive_failures.saturating_add(1);
+        failures.failed_at = crate::time::now();
+    }
+
+    /**
+        Record that a channel tuned successfully, ending its backoff.
+    */
+    pub fn clear_tune_failures(&self, id: &ChannelId) {
+        self.tune_failures.write().unwrap().remove(id);
+    }
+
+    /**
+        Check whether a channel failed to tune recently enough
+        that it should not be retried yet.
+    */
+    pub fn is_tune_backing_off(&self, id: &ChannelId) -> bool {
+        self.tune_failures
+            .read()
+            .unwrap()
+            .get(id)
+            .is_some_and(|failures| crate::time::now() < failures.retry_at())
+    }
+
+    // ===== Persistence =====
+
+    /**
+        Save all ready sources and their channels to a JSON cache file.
+
+        The file is written to a temporary path first and then renamed,
+        so a crash mid-write never leaves a truncated cache behind. The
+        cache holds decryption keys, request headers and tokens, so on
+        Unix it is only readable by the current user.
+    */
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut cache = RegistryCache::default();
+
+        {
+            let states = self.source_state.read().unwrap();
+            let expirations = self.discovery_expiration.read().unwrap();
+            for (source, state) in states.iter() {
+                if matches!(state, SourceState::Ready) {
+                    cache.sources.insert(
+                        source.clone(),
+                        CachedSource {
+                            channels: Vec::new(),
+                            discovery_expires_at: expirations.get(source).copied().flatten(),
+                        },
+                    );
+                }
+            }
+        }
+
+        {
+            let channels = self.channels.read().unwrap();
+            for (id, entry) in channels.iter() {
+                if let Some(source) = cache.sources.get_mut(&id.source) {
+                    source.channels.push(entry.clone());
+                }
+            }
+        }
+
+        cache.usage = self
+            .usage
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(id, usage)| (id.to_string(), *usage))
+            .collect();
+
+        let json = serde_json::to_vec_pretty(&cache)?;
+        let tmp_path = path.with_extension("tmp");
+        write_private(&tmp_path, &json)
+            .map_err(|e| anyhow!("Failed to write {:?}: {}", tmp_path, e))?;
+        std::fs::rename(&tmp_path, path)
+            .map_err(|e| anyhow!("Failed to rename {:?} to {:?}: {}", tmp_path, path, e))?;
+
+        Ok(())
+    }
+
+    /**
+        Load sources and channels from a JSON cache file written by `save`.
+
+        Each cached source is registered as Ready, so its channel list and EPG
+        can be served immediately while discovery refreshes in the background.
+        Returns the IDs of the sources that were restored.
+    */
+    pub fn load(&self, path: &Path) -> Result<Vec<String>> {
+        let json = std::fs::read(path).map_err(|e| anyhow!("Failed to read {:?}: {}", path, e))?;
+        let cache: RegistryCache = serde_json::from_slice(&json)
+            .map_err(|e| anyhow!("Failed to parse {:?}: {}", path, e))?;
+
+        {
+            let mut usage = self.usage.write().unwrap();
+            for (id, channel_usage) in cache.usage {
+                if let Some(id) = ChannelId::parse(&id) {
+                    usage.insert(id, channel_usage);
+                }
+            }
+        }
+
+        let mut restored = Vec::new();
+        for (source, cached) in cache.sources {
+            if cached.channels.is_empty() {
+                continue;
+            }
+            self.register_source(&source, cached.channels, cached.discovery_expires_at);
+            restored.push(source);
+        }
+
+        Ok(restored)
+    }
+
     // ===== Channel Content Resolution State =====
 
     /**
@@ -478,3 +1046,179 @@ impl Default for ChannelRegistry {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::DiscoveredChannel;
+
+    fn entry(source: &str, id: &str, name: &str, manifest_url: Option<&str>) -> ChannelEntry {
+        ChannelEntry {
+            channel: DiscoveredChannel {
+                id: id.to_string(),
+                name: Some(name.to_string()),
+                image: None,
+                category: None,
+                description: None,
+                source: source.to_string(),
+                loudness: None,
+                overlay: None,
+                ad_breaks: None,
+                passthrough: false,
+                standby: false,
+                profile: None,
+                segments: Default::default(),
+            },
+            stream_info: manifest_url.map(|url| StreamInfo {
+                manifest_url: url.to_string(),
+                license_url: None,
+                drm_device: None,
+                decryption_keys: Vec::new(),
+                expires_at: None,
+                headers: Vec::new(),
+                token: None,
+            }),
+            programmes: Vec::new(),
+            last_error: None,
+        }
+    }
+
+    #[test]
+    fn test_alternates_by_priority() {
+        let registry = ChannelRegistry::new();
+        registry.register_source("a", vec![entry("a", "1", "News 24", None)], None);
+        registry.register_source(
+            "b",
+            vec![
+                entry("b", "news", "NEWS-24", None),
+                entry("b", "sport", "Sport", None),
+            ],
+            None,
+        );
+
+        // Without priorities the choice falls back to the source ID
+        let (a, b) = (ChannelId::new("a", "1"), ChannelId::new("b", "news"));
+        assert_eq!(registry.primary_of(&b), Some(a.clone()));
+        assert_eq!(registry.alternates_of(&a), vec![b.clone()]);
+        assert!(!registry.is_alternate(&ChannelId::new("b", "sport")));
+
+        registry.set_source_priority("b", 10);
+        assert_eq!(registry.primary_of(&a), Some(b.clone()));
+        assert!(!registry.is_alternate(&b));
+    }
+
+    #[test]
+    fn test_alternates_by_manifest_url() {
+        let registry = ChannelRegistry::new();
+        registry.register_source(
+            "a",
+            vec![entry(
+                "a",
+                "1",
+                "Channel One",
+                Some("https://cdn.example.com/one/manifest.mpd?token=x"),
+            )],
+            None,
+        );
+        registry.register_source("b", vec![entry("b", "1", "Uno", None)], None);
+        assert!(!registry.is_alternate(&ChannelId::new("b", "1")));
+
+        // Same manifest once resolved, despite different names and tokens
+        registry.update_stream_info(
+            &ChannelId::new("b", "1"),
+            entry(
+                "b",
+                "1",
+                "Uno",
+                Some("http://cdn.example.com/one/manifest.mpd?token=y"),
+            )
+            .stream_info
+            .unwrap(),
+        );
+        assert_eq!(
+            registry.primary_of(&ChannelId::new("b", "1")),
+            Some(ChannelId::new("a", "1"))
+        );
+    }
+
+    #[test]
+    fn test_usage_counts_tunes_not_requests() {
+        let mut usage = ChannelUsage::default();
+        usage.record_watch(1000);
+        usage.record_watch(1004);
+        usage.record_watch(1008);
+        assert_eq!(usage.tunes, 1);
+        assert_eq!(usage.last_watched_at, Some(1008));
+
+        usage.record_watch(1008 + TUNE_SESSION_GAP);
+        assert_eq!(usage.tunes, 2);
+    }
+
+    #[test]
+    fn test_tune_backoff_doubles_until_cleared() {
+        let failures = |consecutive_failures| TuneFailures {
+            consecutive_failures,
+            failed_at: 1000,
+        };
+        assert_eq!(failures(1).retry_at(), 1000 + TUNE_BACKOFF_BASE);
+        assert_eq!(failures(2).retry_at(), 1000 + 2 * TUNE_BACKOFF_BASE);
+        assert_eq!(failures(20).retry_at(), 1000 + TUNE_BACKOFF_MAX);
+
+        let registry = ChannelRegistry::new();
+        let id = ChannelId::new("a", "1");
+        assert!(!registry.is_tune_backing_off(&id));
+        registry.record_tune_failure(&id);
+        assert!(registry.is_tune_backing_off(&id));
+        registry.clear_tune_failures(&id);
+        assert!(!registry.is_tune_backing_off(&id));
+    }
+
+    #[test]
+    fn test_usage_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+
+        let registry = ChannelRegistry::new();
+        registry.register_source("a", vec![entry("a", "1", "News", None)], None);
+        registry.record_watch(&ChannelId::new("a", "1"));
+        registry.save(&path).unwrap();
+
+        let loaded = ChannelRegistry::new();
+        loaded.load(&path).unwrap();
+        let usage = loaded.list_usage();
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].0, ChannelId::new("a", "1"));
+        assert_eq!(usage[0].1.tunes, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cache_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+
+        // A leftover temporary file must not keep its looser mode
+        std::fs::write(path.with_extension("tmp"), b"{}").unwrap();
+
+        let registry = ChannelRegistry::new();
+        registry.register_source("a", vec![entry("a", "1", "News", None)], None);
+        registry.save(&path).unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_same_source_not_deduplicated() {
+        let registry = ChannelRegistry::new();
+        registry.register_source(
+            "a",
+            vec![entry("a", "1", "News", None), entry("a", "2", "News", None)],
+            None,
+        );
+        assert!(!registry.is_alternate(&ChannelId::new("a", "1")));
+        assert!(!registry.is_alternate(&ChannelId::new("a", "2")));
+    }
+}
@@ -0,0 +1,450 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use tokio::sync::watch;
+
+use crate::antibot::AntibotChallenge;
+use crate::manifest::Manifest;
+use crate::registry::{ChannelRegistry, DiscoverySchedule, SourceState};
+use crate::server::ManifestStore;
+use crate::source::{self, BrowserOptions};
+
+/**
+    Delay before retrying a source after its first discovery failure (30 seconds)
+*/
+const BACKOFF_BASE_SECS: u64 = 30;
+
+/**
+    Upper bound for the failure backoff delay (1 hour)
+*/
+const BACKOFF_MAX_SECS: u64 = 3600;
+
+/**
+    How often the scheduler checks for due sources when nothing is scheduled sooner
+*/
+const TICK_INTERVAL: Duration = Duration::from_secs(5);
+
+/**
+    Compute the backoff delay in seconds after the given number of consecutive failures.
+*/
+fn backoff_secs(failures: u32) -> u64 {
+    let exponent = failures.saturating_sub(1).min(16);
+    BACKOFF_BASE_SECS
+        .saturating_mul(1 << exponent)
+        .min(BACKOFF_MAX_SECS)
+}
+
+/**
+    A source's session after a successful discovery, kept for its refresh phase.
+    The session's context lives in the manifest store, for content resolution.
+*/
+struct Session {
+    /// When the source's channels were last discovered in full
+    discovered_at: u64,
+}
+
+/**
+    Runs discovery for every source, then re-runs it whenever a source's
+    discovery results expire or its configured interval elapses.
+
+    Healthy sources are left alone until they are due, and sources whose
+    discovery fails are retried with exponential backoff. Sources blocked by
+    an antibot challenge are paused instead, until resumed through the API.
+    The schedule of each source is published to the registry so the API can
+    report it.
+*/
+pub struct DiscoveryScheduler {
+    registry: Arc<ChannelRegistry>,
+    manifest_store: Arc<ManifestStore>,
+    cache_file: Option<PathBuf>,
+    browser_options: BrowserOptions,
+    max_concurrent: usize,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl DiscoveryScheduler {
+    pub fn new(
+        registry: Arc<ChannelRegistry>,
+        manifest_store: Arc<ManifestStore>,
+        cache_file: Option<PathBuf>,
+        browser_options: BrowserOptions,
+        max_concurrent: usize,
+    ) -> Self {
+        Self {
+            registry,
+            manifest_store,
+            cache_file,
+            browser_options,
+            max_concurrent: max_concurrent.max(1),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /**
+        Run the scheduler loop until shutdown is signalled.
+    */
+    pub async fn run(self, manifests: Vec<Manifest>, mut shutdown_rx: watch::Receiver<bool>) {
+        let manifests: HashMap<String, Manifest> = manifests
+            .into_iter()
+            .map(|m| (m.source.id.clone(), m))
+            .collect();
+
+        // Every source is due immediately at startup, including ones restored from cache
+        let now = crate::time::now();
+        for source_id in manifests.keys() {
+            self.registry.set_discovery_schedule(
+                source_id,
+                DiscoverySchedule {
+                    next_run_at: Some(now),
+                    ..Default::default()
+                },
+            );
+        }
+
+        // Run due sources concurrently, each with its own browser, but bounded
+        // so that too many Chrome instances don't interfere with each other.
+        // Sources start as soon as they are due and a slot is free, so a slow
+        // source only holds up its own slot, and results are registered as
+        // each source finishes.
+        let this = &self;
+        let mut running = HashSet::new();
+        let mut discoveries = FuturesUnordered::new();
+        loop {
+            if *shutdown_rx.borrow() {
+                break;
+            }
+
+            while discoveries.len() < self.max_concurrent
+                && let Some(manifest) = manifests
+                    .values()
+                    .find(|m| !running.contains(&m.source.id) && self.is_due(&m.source.id))
+            {
+                running.insert(manifest.source.id.clone());
+                discoveries.push(async move {
+                    this.run_discovery(manifest).await;
+                    &manifest.source.id
+                });
+            }
+
+            let idle = manifests.keys().filter(|id| !running.contains(*id));
+            let sleep_for = self.time_until_next_run(idle);
+            tokio::select! {
+                Some(source_id) = discoveries.next() => {
+                    running.remove(source_id);
+                }
+                _ = tokio::time::sleep(sleep_for) => {}
+                res = shutdown_rx.changed() => {
+                    if res.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Let the discoveries that already started finish
+        while discoveries.next().await.is_some() {}
+    }
+
+    fn is_due(&self, source_id: &str) -> bool {
+        self.registry
+            .get_discovery_schedule(source_id)
+            .and_then(|s| s.next_run_at)
+            .is_some_and(|next| crate::time::now() >= next)
+    }
+
+    fn time_until_next_run<'a>(&self, source_ids: impl Iterator<Item = &'a String>) -> Duration {
+        let now = crate::time::now();
+        source_ids
+            .filter_map(|id| self.registry.get_discovery_schedule(id)?.next_run_at)
+            .map(|next| Duration::from_secs(next.saturating_sub(now)))
+            .min()
+            .unwrap_or(TICK_INTERVAL)
+            .clamp(Duration::from_secs(1), TICK_INTERVAL)
+    }
+
+    /**
+        Run discovery for a single source and update its schedule.
+    */
+    async fn run_discovery(&self, manifest: &Manifest) {
+        let source_id = &manifest.source.id;
+        println!(
+            "[discovery] Starting source: {} ({})",
+            manifest.source.name, source_id
+        );
+
+        let mut schedule = self
+            .registry
+            .get_discovery_schedule(source_id)
+            .unwrap_or_default();
+        schedule.last_run_at = Some(crate::time::now());
+
+        match self.discover(manifest).await {
+            Ok(expires_at) => {
+                self.registry.clear_needs_attention(source_id);
+                schedule.consecutive_failures = 0;
+                schedule.last_error = None;
+                schedule.timed_out = false;
+                schedule.next_run_at = next_run_after_success(manifest, expires_at);
+                if let Some(next) = schedule.next_run_at {
+                    println!(
+                        "[discovery] Source '{}' next refresh in {}s",
+                        source_id,
+                        next.saturating_sub(crate::time::now())
+                    );
+                }
+            }
+            Err(e) => self.record_failure(source_id, &mut schedule, e),
+        }
+
+        self.registry.set_discovery_schedule(source_id, schedule);
+    }
+
+    /**
+        Record a failed discovery in a source's schedule, backing off before
+        the next run, or pausing the source if it hit an antibot challenge.
+    */
+    fn record_failure(&self, source_id: &str, schedule: &mut DiscoverySchedule, e: anyhow::Error) {
+        eprintln!("[discovery] Source '{}' failed: {}", source_id, e);
+        schedule.last_error = Some(e.to_string());
+        schedule.timed_out = e.downcast_ref::<source::RunTimeout>().is_some();
+        self.mark_failed(source_id, &e);
+
+        schedule.consecutive_failures += 1;
+        if let Some(challenge) = e.downcast_ref::<AntibotChallenge>() {
+            // Retrying only digs deeper with the antibot, wait for an operator
+            self.registry.set_needs_attention(source_id, challenge);
+            schedule.next_run_at = None;
+            eprintln!(
+                "[discovery] Source '{}' needs attention ({}), paused until resumed",
+                source_id, challenge.kind
+            );
+        } else {
+            let delay = backoff_secs(schedule.consecutive_failures);
+            schedule.next_run_at = Some(crate::time::now() + delay);
+            eprintln!(
+                "[discovery] Source '{}' failed {} time(s) in a row, retrying in {}s",
+                source_id, schedule.consecutive_failures, delay
+            );
+        }
+    }
+
+    /**
+        Run discovery with the source's browser (creating one if needed) and
+        register the results. Returns when the discovery results expire.
+    */
+    async fn discover(&self, manifest: &Manifest) -> Result<Option<u64>> {
+        let source_id = &manifest.source.id;
+
+        if let Some(expires_at) = self.refresh(manifest).await {
+            return Ok(expires_at);
+        }
+
+        // Reuse the existing browser so content resolution keeps its session
+        let (browser, is_new) = match self.manifest_store.get_browser(source_id).await {
+            Some(browser) => (browser, false),
+            None => (
+                source::create_browser(manifest, &self.browser_options).await?,
+                true,
+            ),
+        };
+
+        let result = match source::run_source_discovery_only(manifest, &browser).await {
+            Ok(result) => result,
+            Err(e) => {
+                // Leave the challenge up to be solved by hand, the next run
+                // reuses the browser along with the session that solved it
+                if e.downcast_ref::<AntibotChallenge>().is_some()
+                    && self.browser_options.keep_challenge_window
+                {
+                    if is_new {
+                        self.manifest_store.set_browser(source_id, browser).await;
+                    }
+                    return Err(e);
+                }
+
+                // A browser that is stuck after a timeout is closed even if shared
+                // with content resolution, so that the next run starts a fresh one
+                let stuck = e
+                    .downcast_ref::<source::RunTimeout>()
+                    .is_some_and(|t| t.tab_unresponsive);
+                if stuck && !is_new {
+                    eprintln!("[discovery] Relaunching stuck browser for '{}'", source_id);
+                    self.manifest_store.remove_browser(source_id).await;
+                }
+                // Close browser on failure, unless it is shared with content resolution
+                if is_new || stuck {
+                    let _ = browser.close().await;
+                }
+                return Err(e);
+            }
+        };
+
+        let channel_count = result.channels.len();
+        let expires_at = result.discovery_expires_at;
+
+        // Store browser for later content resolution
+        if is_new {
+            self.manifest_store.set_browser(source_id, browser).await;
+        }
+
+        self.registry
+            .register_source(&result.source_id, result.channels, expires_at);
+        if manifest.refresh.is_some() {
+            self.manifest_store
+                .set_session(source_id, result.discovery_context)
+                .await;
+            let session = Session {
+                discovered_at: crate::time::now(),
+            };
+            self.sessions
+                .lock()
+                .unwrap()
+                .insert(source_id.clone(), session);
+        }
+        println!(
+            "[discovery] Source '{}' ready: {} channels (content on-demand)",
+            source_id, channel_count
+        );
+
+        if let Some(ref path) = self.cache_file
+            && let Err(e) = self.registry.save(path)
+        {
+            eprintln!("[discovery] Failed to save cache: {}", e);
+        }
+
+        Ok(expires_at)
+    }
+
+    /**
+        Renew a source's session with its refresh phase instead of running
+        discovery again, returning when the discovery results now expire.
+
+        Returns `None` to fall back to full discovery: when the source has no
+        refresh phase or no session from an earlier discovery, when its
+        channels are older than its discovery interval, or when refreshing fails.
+    */
+    async fn refresh(&self, manifest: &Manifest) -> Option<Option<u64>> {
+        manifest.refresh.as_ref()?;
+        let source_id = &manifest.source.id;
+
+        {
+            let sessions = self.sessions.lock().unwrap();
+            let session = sessions.get(source_id)?;
+            if let Some(interval) = manifest.source.discovery_interval
+                && crate::time::now() >= session.discovered_at + interval
+            {
+                return None;
+            }
+        }
+        let context = self.manifest_store.get_session(source_id).await?;
+        let browser = self.manifest_store.get_browser(source_id).await?;
+
+        match source::run_source_refresh(manifest, &browser, context).await {
+            Ok((context, expires_at)) => {
+                self.manifest_store.set_session(source_id, context).await;
+                // Stream info was resolved with the old session, resolve it again on next use
+                self.registry.invalidate_stream_info(source_id);
+                self.registry
+                    .set_discovery_expiration(source_id, expires_at);
+                println!(
+                    "[discovery] Source '{}' refreshed without rediscovery",
+                    source_id
+                );
+                Some(expires_at)
+            }
+            Err(e) => {
+                eprintln!(
+                    "[discovery] Refresh of '{}' failed, running full discovery: {}",
+                    source_id, e
+                );
+                None
+            }
+        }
+    }
+
+    /**
+        Mark a source's discovery as failed, unless it is already serving
+        channels (restored from cache or from an earlier run) - those are kept.
+    */
+    fn mark_failed(&self, source_id: &str, error: impl ToString) {
+        if matches!(
+            self.registry.get_source_state(source_id),
+            Some(SourceState::Ready)
+        ) {
+            eprintln!("[discovery] Keeping existing channels for '{}'", source_id);
+            return;
+        }
+        self.registry.mark_source_failed(source_id, error);
+    }
+}
+
+/**
+    Pick the next run time after a successful discovery: whichever comes first
+    of the extracted expiration and the source's configured interval.
+*/
+fn next_run_after_success(manifest: &Manifest, expires_at: Option<u64>) -> Option<u64> {
+    let interval_at = manifest
+        .source
+        .discovery_interval
+        .map(|secs| crate::time::now() + secs);
+
+    match (expires_at, interval_at) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_and_caps() {
+        assert_eq!(backoff_secs(1), 30);
+        assert_eq!(backoff_secs(2), 60);
+        assert_eq!(backoff_secs(3), 120);
+        assert_eq!(backoff_secs(8), BACKOFF_MAX_SECS);
+        assert_eq!(backoff_secs(u32::MAX), BACKOFF_MAX_SECS);
+    }
+
+    fn scheduler() -> DiscoveryScheduler {
+        DiscoveryScheduler::new(
+            Arc::new(ChannelRegistry::new()),
+            Arc::new(ManifestStore::new()),
+            None,
+            BrowserOptions::default(),
+            1,
+        )
+    }
+
+    #[test]
+    fn test_antibot_challenge_pauses_source() {
+        let scheduler = scheduler();
+        let mut schedule = DiscoverySchedule::default();
+        let challenge = AntibotChallenge {
+            kind: "Cloudflare challenge",
+            url: "https://example.com/".to_string(),
+        };
+        scheduler.record_failure("src", &mut schedule, challenge.into());
+
+        assert_eq!(schedule.consecutive_failures, 1);
+        assert_eq!(schedule.next_run_at, None);
+        assert!(scheduler.registry.needs_attention("src").is_some());
+    }
+
+    #[test]
+    fn test_other_failures_back_off() {
+        let scheduler = scheduler();
+        let mut schedule = DiscoverySchedule::default();
+        scheduler.record_failure("src", &mut schedule, anyhow::anyhow!("page failed to load"));
+
+        assert_eq!(schedule.consecutive_failures, 1);
+        assert!(schedule.next_run_at.is_some());
+        assert!(scheduler.registry.needs_attention("src").is_none());
+    }
+}
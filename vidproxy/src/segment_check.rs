@@ -0,0 +1,730 @@
+use std::fmt;
+use std::time::Duration;
+
+use serde::Serialize;
+
+const TS_PACKET_LEN: usize = 188;
+const TS_SYNC_BYTE: u8 = 0x47;
+
+/// H.264 stream type in a PMT
+const TS_STREAM_H264: u8 = 0x1B;
+/// Video payload collected from the start of a segment, to find its SPS
+const MAX_VIDEO_SAMPLE: usize = 4 * TS_PACKET_LEN;
+
+/**
+    Longest a segment may be, relative to the target segment duration.
+    Segments are cut at keyframes, so they run long when the source has
+    long GOPs, but never by this much in a healthy stream.
+*/
+const MAX_DURATION_FACTOR: f64 = 3.0;
+
+/**
+    What every segment of a pipeline run should contain.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SegmentExpectations {
+    pub video: bool,
+    pub audio: bool,
+    pub target_duration: Duration,
+}
+
+/**
+    Why a segment failed its integrity check.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum SegmentFault {
+    Empty,
+    UnknownFormat,
+    Corrupt(String),
+    MissingStream(&'static str),
+    Duration { actual: f64, max: f64 },
+}
+
+impl fmt::Display for SegmentFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SegmentFault::Empty => write!(f, "segment is empty"),
+            SegmentFault::UnknownFormat => write!(f, "segment is neither MPEG-TS nor fMP4"),
+            SegmentFault::Corrupt(reason) => write!(f, "segment is corrupt: {}", reason),
+            SegmentFault::MissingStream(kind) => write!(f, "segment has no {} stream", kind),
+            SegmentFault::Duration { actual, max } => {
+                write!(
+                    f,
+                    "segment is {:.2}s long, at most {:.2}s expected",
+                    actual, max
+                )
+            }
+        }
+    }
+}
+
+/**
+    Video resolution in pixels.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/**
+    What was learned about a segment while checking it.
+*/
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentInfo {
+    /// Resolution of the video, if it could be read from the segment
+    pub resolution: Option<Resolution>,
+}
+
+/**
+    Elementary streams found in a segment.
+*/
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Streams {
+    video: bool,
+    audio: bool,
+    /// Number of tracks, for formats where the kind of a track is unknown
+    tracks: usize,
+    /// Start of the first H.264 access unit, if any
+    h264_sample: Vec<u8>,
+}
+
+/**
+    Check that a segment parses as MPEG-TS or fMP4, has the expected
+    elementary streams, and has a plausible duration (from its `#EXTINF`).
+*/
+pub fn check_segment(
+    data: &[u8],
+    duration: Option<f64>,
+    expected: &SegmentExpectations,
+) -> Result<SegmentInfo, SegmentFault> {
+    if data.is_empty() {
+        return Err(SegmentFault::Empty);
+    }
+
+    let mut info = SegmentInfo::default();
+    if data[0] == TS_SYNC_BYTE {
+        let streams = ts_streams(data)?;
+        if expected.video && !streams.video {
+            return Err(SegmentFault::MissingStream("video"));
+        }
+        if expected.audio && !streams.audio {
+            return Err(SegmentFault::MissingStream("audio"));
+        }
+        info.resolution = h264_resolution(&streams.h264_sample);
+    } else if data.get(4..8).is_some_and(is_mp4_box) {
+        // Without the init segment the kind of each track is unknown, only count them
+        let streams = mp4_streams(data)?;
+        let expected_tracks = usize::from(expected.video) + usize::from(expected.audio);
+        if streams.tracks < expected_tracks {
+            return Err(SegmentFault::MissingStream(match streams.tracks {
+                0 => "media",
+                _ => "video or audio",
+            }));
+        }
+    } else {
+        return Err(SegmentFault::UnknownFormat);
+    }
+
+    if let Some(actual) = duration {
+        let max = expected.target_duration.as_secs_f64() * MAX_DURATION_FACTOR;
+        if actual <= 0.0 || actual > max {
+            return Err(SegmentFault::Duration { actual, max });
+        }
+    }
+
+    Ok(info)
+}
+
+/**
+    Find the streams of an MPEG-TS segment from its PAT and PMT, counting
+    a stream as present once a PES packet of it starts.
+*/
+fn ts_streams(data: &[u8]) -> Result<Streams, SegmentFault> {
+    if !data.len().is_multiple_of(TS_PACKET_LEN) {
+        return Err(SegmentFault::Corrupt(format!(
+            "length {} is not a multiple of {}",
+            data.len(),
+            TS_PACKET_LEN
+        )));
+    }
+
+    let mut pmt_pids = Vec::new();
+    let mut video_pids = Vec::new();
+    let mut audio_pids = Vec::new();
+    let mut h264_pid = None;
+    let mut streams = Streams::default();
+
+    for (index, packet) in data.chunks_exact(TS_PACKET_LEN).enumerate() {
+        if packet[0] != TS_SYNC_BYTE {
+            return Err(SegmentFault::Corrupt(format!(
+                "lost sync at packet {}",
+                index
+            )));
+        }
+        let unit_start = packet[1] & 0x40 != 0;
+        let pid = u16::from_be_bytes([packet[1] & 0x1F, packet[2]]);
+        let Some(payload) = ts_payload(packet) else {
+            continue;
+        };
+
+        // Continue collecting the first H.264 access unit, past its PES header
+        if h264_pid == Some(pid) {
+            if unit_start && streams.h264_sample.is_empty() {
+                let header_len = payload
+                    .get(8)
+                    .map_or(payload.len(), |len| 9 + usize::from(*len));
+                streams.h264_sample.extend(payload.iter().skip(header_len));
+            } else if !unit_start && !streams.h264_sample.is_empty() {
+                streams.h264_sample.extend_from_slice(payload);
+            } else if !streams.h264_sample.is_empty() {
+                h264_pid = None;
+            }
+            if streams.h264_sample.len() >= MAX_VIDEO_SAMPLE {
+                h264_pid = None;
+            }
+        }
+
+        if !unit_start {
+            continue;
+        }
+
+        if pid == 0 {
+            for (program, pmt_pid) in psi_entries(payload, 8, 4) {
+                if u16::from_be_bytes([program[0], program[1]]) != 0 {
+                    pmt_pids.push(pmt_pid);
+                }
+            }
+        } else if pmt_pids.contains(&pid) {
+            for (stream_type, es_pid) in pmt_streams(payload) {
+                if stream_type == TS_STREAM_H264 && h264_pid.is_none() {
+                    h264_pid = Some(es_pid);
+                }
+                match stream_type {
+                    0x01 | 0x02 | 0x10 | 0x1B | 0x24 | 0x33 => video_pids.push(es_pid),
+                    0x03 | 0x04 | 0x0F | 0x11 | 0x81 | 0x87 => audio_pids.push(es_pid),
+                    _ => {}
+                }
+            }
+        } else if video_pids.contains(&pid) {
+            streams.video = true;
+        } else if audio_pids.contains(&pid) {
+            streams.audio = true;
+        }
+    }
+
+    if pmt_pids.is_empty() {
+        return Err(SegmentFault::Corrupt("no program table".to_string()));
+    }
+    streams.tracks = usize::from(streams.video) + usize::from(streams.audio);
+    Ok(streams)
+}
+
+/**
+    Get the payload of a TS packet, after its adaptation field if any.
+*/
+fn ts_payload(packet: &[u8]) -> Option<&[u8]> {
+    let adaptation = (packet[3] >> 4) & 0x03;
+    if adaptation & 0x01 == 0 {
+        return None;
+    }
+    let start = match adaptation & 0x02 {
+        0 => 4,
+        _ => 5 + usize::from(packet[4]),
+    };
+    packet.get(start..).filter(|payload| !payload.is_empty())
+}
+
+/**
+    Get the section of a PSI payload, skipping its pointer field, and
+    cut to its section length without the CRC.
+*/
+fn psi_section(payload: &[u8]) -> Option<&[u8]> {
+    let section = payload.get(1 + usize::from(*payload.first()?)..)?;
+    let length = usize::from(u16::from_be_bytes([
+        *section.get(1)? & 0x0F,
+        *section.get(2)?,
+    ]));
+    section.get(..(3 + length).checked_sub(4)?)
+}
+
+/**
+    Iterate over the fixed size entries of a PSI section starting at the
+    given offset, yielding each entry and the PID in its last two bytes.
+*/
+fn psi_entries(payload: &[u8], offset: usize, size: usize) -> Vec<(&[u8], u16)> {
+    let Some(entries) = psi_section(payload).and_then(|section| section.get(offset..)) else {
+        return Vec::new();
+    };
+    entries
+        .chunks_exact(size)
+        .map(|entry| {
+            let pid = u16::from_be_bytes([entry[size - 2] & 0x1F, entry[size - 1]]);
+            (entry, pid)
+        })
+        .collect()
+}
+
+/**
+    Get the stream type and PID of every elementary stream in a PMT.
+*/
+fn pmt_streams(payload: &[u8]) -> Vec<(u8, u16)> {
+    let Some(section) = psi_section(payload) else {
+        return Vec::new();
+    };
+    let Some(&[high, low]) = section.get(10..12) else {
+        return Vec::new();
+    };
+    let mut offset = 12 + usize::from(u16::from_be_bytes([high & 0x0F, low]));
+
+    let mut streams = Vec::new();
+    while let Some(entry) = section.get(offset..offset + 5) {
+        let pid = u16::from_be_bytes([entry[1] & 0x1F, entry[2]]);
+        let info_len = usize::from(u16::from_be_bytes([entry[3] & 0x0F, entry[4]]));
+        streams.push((entry[0], pid));
+        offset += 5 + info_len;
+    }
+    streams
+}
+
+/**
+    Read the resolution from the first SPS in a sample of an H.264
+    elementary stream (in Annex B format).
+*/
+fn h264_resolution(sample: &[u8]) -> Option<Resolution> {
+    let start = sample
+        .windows(4)
+        .position(|w| w[..3] == [0, 0, 1] && w[3] & 0x1F == 7)?;
+    let nal = &sample[start + 4..];
+    let end = nal
+        .windows(3)
+        .position(|w| w == [0, 0, 1])
+        .unwrap_or(nal.len());
+
+    // Remove emulation prevention bytes
+    let mut rbsp = Vec::with_capacity(end);
+    for &byte in &nal[..end] {
+        if byte == 3 && rbsp.ends_with(&[0, 0]) {
+            continue;
+        }
+        rbsp.push(byte);
+    }
+    parse_sps(&rbsp)
+}
+
+/**
+    Parse the frame size out of an H.264 sequence parameter set.
+*/
+fn parse_sps(rbsp: &[u8]) -> Option<Resolution> {
+    let mut bits = BitReader::new(rbsp);
+    let profile_idc = bits.read(8)?;
+    bits.read(16)?; // Constraint flags and level
+    bits.read_ue()?; // SPS id
+
+    let mut chroma_format_idc = 1;
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format_idc = bits.read_ue()?;
+        if chroma_format_idc == 3 {
+            bits.read(1)?; // Separate colour planes
+        }
+        bits.read_ue()?; // Luma bit depth
+        bits.read_ue()?; // Chroma bit depth
+        bits.read(1)?; // Transform bypass
+        if bits.read(1)? == 1 {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for list in 0..lists {
+                if bits.read(1)? == 1 {
+                    skip_scaling_list(&mut bits, if list < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    bits.read_ue()?; // Max frame num
+    match bits.read_ue()? {
+        0 => {
+            bits.read_ue()?;
+        }
+        1 => {
+            bits.read(1)?;
+            bits.read_se()?;
+            bits.read_se()?;
+            for _ in 0..bits.read_ue()? {
+                bits.read_se()?;
+            }
+        }
+        _ => {}
+    }
+    bits.read_ue()?; // Max reference frames
+    bits.read(1)?; // Gaps in frame num allowed
+
+    let width_mbs = bits.read_ue()? + 1;
+    let height_map_units = bits.read_ue()? + 1;
+    let frame_mbs_only = bits.read(1)?;
+    if frame_mbs_only == 0 {
+        bits.read(1)?; // Adaptive frame/field
+    }
+    bits.read(1)?; // Direct 8x8 inference
+
+    let mut width = width_mbs * 16;
+    let mut height = (2 - frame_mbs_only) * height_map_units * 16;
+    if bits.read(1)? == 1 {
+        let (crop_x, crop_y) = match chroma_format_idc {
+            0 => (1, 2 - frame_mbs_only),
+            1 => (2, 2 * (2 - frame_mbs_only)),
+            2 => (2, 2 - frame_mbs_only),
+            _ => (1, 2 - frame_mbs_only),
+        };
+        let (left, right) = (bits.read_ue()?, bits.read_ue()?);
+        let (top, bottom) = (bits.read_ue()?, bits.read_ue()?);
+        width = width.checked_sub(crop_x * (left + right))?;
+        height = height.checked_sub(crop_y * (top + bottom))?;
+    }
+
+    (width > 0 && height > 0).then_some(Resolution { width, height })
+}
+
+fn skip_scaling_list(bits: &mut BitReader, size: usize) -> Option<()> {
+    let mut last = 8;
+    let mut next = 8;
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + bits.read_se()? + 256) % 256;
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Some(())
+}
+
+/**
+    Reads bits most significant first, including Exp-Golomb codes.
+*/
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn read(&mut self, count: u32) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..count {
+            let byte = self.data.get(self.position / 8)?;
+            let bit = (byte >> (7 - self.position % 8)) & 1;
+            value = (value << 1) | u32::from(bit);
+            self.position += 1;
+        }
+        Some(value)
+    }
+
+    fn read_ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.read(1)? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << zeros) - 1 + self.read(zeros)?)
+    }
+
+    fn read_se(&mut self) -> Option<i32> {
+        let value = self.read_ue()? as i64;
+        let signed = if value % 2 == 1 {
+            (value + 1) / 2
+        } else {
+            -(value / 2)
+        };
+        i32::try_from(signed).ok()
+    }
+}
+
+fn is_mp4_box(kind: &[u8]) -> bool {
+    matches!(
+        kind,
+        b"styp" | b"ftyp" | b"moof" | b"sidx" | b"prft" | b"emsg"
+    )
+}
+
+/**
+    Read the size and header length of the box at the start of `data`.
+
+    A size of 1 means the real size follows the type as a 64-bit value, and
+    a size of 0 means the box runs to the end of the data. Returns `None` if
+    the header is cut off, or the size doesn't fit the header or the data.
+*/
+fn mp4_box_size(data: &[u8]) -> Option<(usize, usize)> {
+    let size = u32::from_be_bytes(data.get(0..4)?.try_into().unwrap());
+    let (size, header) = match size {
+        0 => (data.len(), 8),
+        1 => {
+            let size = u64::from_be_bytes(data.get(8..16)?.try_into().unwrap());
+            (usize::try_from(size).ok()?, 16)
+        }
+        size => (size as usize, 8),
+    };
+    (size >= header && size <= data.len()).then_some((size, header))
+}
+
+/**
+    Check the top level boxes of an fMP4 segment, and count its tracks.
+*/
+fn mp4_streams(mut data: &[u8]) -> Result<Streams, SegmentFault> {
+    let mut streams = Streams::default();
+    let mut has_mdat = false;
+
+    while !data.is_empty() {
+        let Some((size, header)) = mp4_box_size(data) else {
+            return Err(SegmentFault::Corrupt(format!(
+                "invalid box with {} bytes left",
+                data.len()
+            )));
+        };
+
+        let (current, rest) = data.split_at(size);
+        match &current[4..8] {
+            b"moof" => streams.tracks += mp4_children(&current[header..], b"traf"),
+            b"mdat" => has_mdat = true,
+            _ => {}
+        }
+        data = rest;
+    }
+
+    if !has_mdat {
+        return Err(SegmentFault::Corrupt("no media data".to_string()));
+    }
+    Ok(streams)
+}
+
+/**
+    Count the direct children of a box with the given type.
+*/
+fn mp4_children(mut data: &[u8], kind: &[u8; 4]) -> usize {
+    let mut count = 0;
+    while let Some((size, _)) = mp4_box_size(data) {
+        if &data[4..8] == kind {
+            count += 1;
+        }
+        data = &data[size..];
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPECTED: SegmentExpectations = SegmentExpectations {
+        video: true,
+        audio: true,
+        target_duration: Duration::from_secs(4),
+    };
+
+    fn ts_packet(pid: u16, unit_start: bool, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![
+            TS_SYNC_BYTE,
+            (u8::from(unit_start) << 6) | (pid >> 8) as u8,
+            pid as u8,
+            0x10,
+        ];
+        packet.extend_from_slice(payload);
+        packet.resize(TS_PACKET_LEN, 0xFF);
+        packet
+    }
+
+    fn psi(table_id: u8, header: &[u8], body: &[u8]) -> Vec<u8> {
+        // Section length covers the header after it, the body, and the CRC
+        let length = (5 + header.len() + body.len() + 4) as u16;
+        let mut payload = vec![0, table_id, 0xB0 | (length >> 8) as u8, length as u8];
+        payload.extend_from_slice(&[0, 1, 0xC1, 0, 0]);
+        payload.extend_from_slice(header);
+        payload.extend_from_slice(body);
+        payload.extend_from_slice(&[0; 4]);
+        payload
+    }
+
+    fn ts_segment(streams: &[(u8, u16)]) -> Vec<u8> {
+        let pat = psi(0x00, &[], &[0, 1, 0xF0, 0x00]);
+        let mut body = Vec::new();
+        for (stream_type, pid) in streams {
+            body.extend_from_slice(&[*stream_type, 0xE0 | (pid >> 8) as u8, *pid as u8, 0xF0, 0]);
+        }
+        let pmt = psi(0x02, &[0xE1, 0x00, 0xF0, 0x00], &body);
+
+        let mut data = ts_packet(0, true, &pat);
+        data.extend(ts_packet(0x1000, true, &pmt));
+        for (_, pid) in streams {
+            data.extend(ts_packet(*pid, true, &[0, 0, 1, 0xE0]));
+            data.extend(ts_packet(*pid, false, &[0xAA]));
+        }
+        data
+    }
+
+    #[test]
+    fn test_valid_ts_segment() {
+        let data = ts_segment(&[(0x1B, 0x100), (0x0F, 0x101)]);
+        assert!(check_segment(&data, Some(4.0), &EXPECTED).is_ok());
+    }
+
+    #[test]
+    fn test_ts_segment_missing_audio() {
+        let data = ts_segment(&[(0x1B, 0x100)]);
+        assert_eq!(
+            check_segment(&data, Some(4.0), &EXPECTED),
+            Err(SegmentFault::MissingStream("audio"))
+        );
+
+        let video_only = SegmentExpectations {
+            audio: false,
+            ..EXPECTED
+        };
+        assert!(check_segment(&data, Some(4.0), &video_only).is_ok());
+    }
+
+    #[test]
+    fn test_corrupt_ts_segment() {
+        let mut data = ts_segment(&[(0x1B, 0x100), (0x0F, 0x101)]);
+        data[TS_PACKET_LEN * 2] = 0;
+        assert!(matches!(
+            check_segment(&data, Some(4.0), &EXPECTED),
+            Err(SegmentFault::Corrupt(_))
+        ));
+
+        let data = ts_segment(&[(0x1B, 0x100), (0x0F, 0x101)]);
+        assert!(matches!(
+            check_segment(&data[..data.len() - 10], Some(4.0), &EXPECTED),
+            Err(SegmentFault::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_segment_duration() {
+        let data = ts_segment(&[(0x1B, 0x100), (0x0F, 0x101)]);
+        assert!(check_segment(&data, Some(1.5), &EXPECTED).is_ok());
+        assert!(matches!(
+            check_segment(&data, Some(30.0), &EXPECTED),
+            Err(SegmentFault::Duration { .. })
+        ));
+        assert!(matches!(
+            check_segment(&data, Some(0.0), &EXPECTED),
+            Err(SegmentFault::Duration { .. })
+        ));
+    }
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = ((8 + payload.len()) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    /// A styp and a moof with two tracks, to be followed by media data
+    fn mp4_fragment() -> Vec<u8> {
+        let trafs = [mp4_box(b"traf", &[]), mp4_box(b"traf", &[])].concat();
+        let mut data = mp4_box(b"styp", b"msdh");
+        data.extend(mp4_box(
+            b"moof",
+            &[mp4_box(b"mfhd", &[0; 8]), trafs].concat(),
+        ));
+        data
+    }
+
+    #[test]
+    fn test_fmp4_segment() {
+        let mut data = mp4_fragment();
+        data.extend(mp4_box(b"mdat", &[0; 32]));
+        assert!(check_segment(&data, Some(4.0), &EXPECTED).is_ok());
+
+        // Cut off in the middle of the media data
+        assert!(matches!(
+            check_segment(&data[..data.len() - 8], Some(4.0), &EXPECTED),
+            Err(SegmentFault::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_fmp4_segment_largesize_box() {
+        let mut data = mp4_fragment();
+        data.extend(1u32.to_be_bytes());
+        data.extend(b"mdat");
+        data.extend((16u64 + 32).to_be_bytes());
+        data.extend([0; 32]);
+        assert!(check_segment(&data, Some(4.0), &EXPECTED).is_ok());
+
+        // Cut off in the middle of the media data
+        assert!(matches!(
+            check_segment(&data[..data.len() - 8], Some(4.0), &EXPECTED),
+            Err(SegmentFault::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_fmp4_segment_box_to_end() {
+        let mut data = mp4_fragment();
+        data.extend(0u32.to_be_bytes());
+        data.extend(b"mdat");
+        data.extend([0; 32]);
+        assert!(check_segment(&data, Some(4.0), &EXPECTED).is_ok());
+    }
+
+    /// High profile SPS of a 1920x1080 stream, cropped from 1088 lines
+    const SPS_1080P: [u8; 11] = [
+        0x67, 0x64, 0x00, 0x28, 0xAC, 0xE5, 0x01, 0xE0, 0x08, 0x9F, 0x95,
+    ];
+
+    #[test]
+    fn test_h264_resolution() {
+        let mut sample = vec![0, 0, 0, 1, 0x09, 0xF0, 0, 0, 0, 1];
+        sample.extend_from_slice(&SPS_1080P);
+        sample.extend_from_slice(&[0, 0, 1, 0x68, 0xEB]);
+        assert_eq!(
+            h264_resolution(&sample),
+            Some(Resolution {
+                width: 1920,
+                height: 1080
+            })
+        );
+        assert_eq!(h264_resolution(&sample[..8]), None);
+    }
+
+    #[test]
+    fn test_ts_segment_resolution() {
+        let mut data = ts_segment(&[(0x1B, 0x100), (0x0F, 0x101)]);
+        let mut pes = vec![0, 0, 1, 0xE0, 0, 0, 0x80, 0x80, 5, 0x21, 0, 1, 0, 1];
+        pes.extend_from_slice(&[0, 0, 0, 1]);
+        pes.extend_from_slice(&SPS_1080P);
+        data.extend(ts_packet(0x100, true, &pes));
+
+        let info = check_segment(&data, Some(4.0), &EXPECTED).unwrap();
+        assert_eq!(
+            info.resolution.map(|r| r.to_string()).as_deref(),
+            Some("1920x1080")
+        );
+    }
+
+    #[test]
+    fn test_unknown_format() {
+        assert_eq!(
+            check_segment(b"<html>", None, &EXPECTED),
+            Err(SegmentFault::UnknownFormat)
+        );
+        assert_eq!(
+            check_segment(&[], None, &EXPECTED),
+            Err(SegmentFault::Empty)
+        );
+    }
+}
@@ -1,16 +1,106 @@
 use std::collections::VecDeque;
+use std::fmt::Write;
 use std::fs;
+use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+
+use crate::quality::{QualityLog, QualitySnapshot};
+use crate::segment_check::{SegmentExpectations, check_segment};
+
+const PLAYLIST: &str = "playlist.m3u8";
+const QUARANTINE_DIR: &str = ".quarantine";
+
+/**
+    Number of quarantined segments kept around for inspection.
+*/
+const MAX_QUARANTINED: usize = 20;
+
+/**
+    Segments shorter than this (in seconds) are left out of bitrate
+    tracking, their size says more about their content than the stream.
+*/
+const MIN_BITRATE_SECS: f64 = 1.0;
+
+/**
+    Segments that have been published to the output directory.
+*/
+#[derive(Debug, Default)]
+struct Published {
+    /// Published segments, oldest first
+    segments: VecDeque<PublishedSegment>,
+    /// Media sequence following the last segment of the published playlist
+    next_sequence: u64,
+    /// Media sequences of segments that failed their check, as numbered by FFmpeg
+    quarantined: Vec<u64>,
+    /// Media sequences of published segments to mark with a discontinuity
+    discontinuities: Vec<u64>,
+    /// Media sequences of segments the pipeline saw its input jump in, as numbered by FFmpeg
+    jumps: Vec<u64>,
+    /// Wall-clock time where the segment after the last one starts
+    next_start: Option<DateTime<Utc>>,
+}
+
+/**
+    A segment in the output directory.
+*/
+#[derive(Debug, Clone)]
+struct PublishedSegment {
+    /// Media sequence, as numbered by FFmpeg
+    sequence: u64,
+    /// Filename in the output directory, from its start time
+    name: String,
+    /// Wall-clock time of the segment's first frame
+    start: DateTime<Utc>,
+}
+
+/**
+    A segment listed in a playlist.
+*/
+#[derive(Debug, Clone, PartialEq)]
+struct ListedSegment {
+    name: String,
+    /// Duration from its `#EXTINF`
+    duration: Option<f64>,
+    /// Whether FFmpeg marked a discontinuity before it
+    discontinuity: bool,
+}
 
 /**
     Manages HLS segments in a directory.
-    Handles cleanup of old segments to prevent unbounded disk usage.
+
+    FFmpeg writes into a staging directory, and segments are only moved into
+    the output directory once complete and synced to disk. The playlist is
+    replaced after its segments, so a client never sees a playlist that
+    references a partially written segment.
+
+    Once the expected contents of segments are known, each segment is also
+    checked before it is published. Segments that fail are moved aside into
+    a quarantine directory instead, and left out of the published playlist
+    with a discontinuity in their place. Checked segments are also tracked
+    for changes in resolution and bitrate, with a discontinuity before
+    every segment where the resolution changes.
+
+    Published segments are named after the wall-clock time of their first
+    frame, which is also given as their `#EXT-X-PROGRAM-DATE-TIME`, so that
+    clients can address the stream by time. Times follow the media timeline
+    from when the source made the first segment of a run available (which,
+    for live sources, is when the segment was received) and are anchored
+    again after every discontinuity.
+
+    Also handles cleanup of old segments to prevent unbounded disk usage.
 */
 pub struct SegmentManager {
     output_dir: PathBuf,
+    staging_dir: PathBuf,
     max_segments: usize,
-    segments: Mutex<VecDeque<String>>,
+    expectations: Mutex<Option<SegmentExpectations>>,
+    published: Mutex<Published>,
+    /// Quality changes of the stream, kept across runs
+    quality: Mutex<QualityLog>,
 }
 
 impl SegmentManager {
@@ -19,12 +109,30 @@ impl SegmentManager {
     */
     pub fn new(output_dir: PathBuf, max_segments: usize) -> Self {
         Self {
+            staging_dir: output_dir.join(".staging"),
             output_dir,
             max_segments,
-            segments: Mutex::new(VecDeque::new()),
+            expectations: Mutex::new(None),
+            published: Mutex::new(Published::default()),
+            quality: Mutex::new(QualityLog::default()),
         }
     }
 
+    /**
+        Set what segments of the current run should contain, checking
+        every segment published from now on against it.
+    */
+    pub fn set_expectations(&self, expectations: SegmentExpectations) {
+        *self.expectations.lock().unwrap() = Some(expectations);
+    }
+
+    /**
+        Get the quality of the published segments, and how it changed.
+    */
+    pub fn quality(&self) -> QualitySnapshot {
+        self.quality.lock().unwrap().snapshot()
+    }
+
     /**
         Get the output directory path.
     */
@@ -34,63 +142,191 @@ impl SegmentManager {
     }
 
     /**
-        Register a new segment and clean up old ones if needed.
+        Get the directory that FFmpeg should write segments and its playlist to.
     */
-    #[allow(dead_code)]
-    pub fn register_segment(&self, filename: &str) {
-        let mut segments = self.segments.lock().unwrap();
-
-        // Add new segment
-        segments.push_back(filename.to_string());
+    pub fn staging_dir(&self) -> &Path {
+        &self.staging_dir
+    }
 
-        // Remove old segments if over limit
-        while segments.len() > self.max_segments {
-            if let Some(old_segment) = segments.pop_front() {
-                let path = self.output_dir.join(&old_segment);
-                let _ = fs::remove_file(path);
-            }
-        }
+    /**
+        Publish finished segments and the playlist, like [`publish`](Self::publish),
+        on a blocking thread so that the file system work doesn't stall the runtime.
+    */
+    pub async fn publish_async(self: &Arc<Self>) -> std::io::Result<()> {
+        let manager = Arc::clone(self);
+        tokio::task::spawn_blocking(move || manager.publish())
+            .await
+            .map_err(std::io::Error::other)?
     }
 
     /**
-        Scan the output directory for new .ts segments.
-        Call this periodically to detect segments written by FFmpeg.
+        Publish the segments that FFmpeg has finished, then the playlist
+        referencing them. Call this frequently, since segments only become
+        visible to clients once published.
+
+        FFmpeg only lists a segment in its playlist once the segment is
+        closed, so every listed segment is complete.
+
+        This reads, syncs and moves files, so async code should use
+        [`publish_async`](Self::publish_async) instead.
     */
-    pub fn scan_for_new_segments(&self) {
-        let dir = &self.output_dir;
-        let Ok(entries) = fs::read_dir(dir) else {
-            return;
+    pub fn publish(&self) -> std::io::Result<()> {
+        let staged = match fs::read_to_string(self.staging_dir.join(PLAYLIST)) {
+            Ok(staged) => staged,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
         };
 
-        let mut segments = self.segments.lock().unwrap();
-        let known: std::collections::HashSet<_> = segments.iter().cloned().collect();
+        // The playlist may be read while FFmpeg is rewriting it, try again next time
+        let Some(listed) = listed_segments(&staged) else {
+            return Ok(());
+        };
+        let next_sequence = next_media_sequence(&staged).unwrap_or(0);
+        let first_sequence = next_sequence - listed.len() as u64;
+        let expectations = *self.expectations.lock().unwrap();
 
-        let mut new_segments: Vec<String> = entries
-            .filter_map(|e| e.ok())
-            .filter_map(|e| {
-                let name = e.file_name().to_string_lossy().to_string();
-                if name.ends_with(".ts") && !known.contains(&name) {
-                    Some(name)
-                } else {
-                    None
-                }
-            })
-            .collect();
+        let mut published = self.published.lock().unwrap();
+        if next_sequence < published.next_sequence {
+            return Ok(());
+        }
+
+        let mut changed = next_sequence > published.next_sequence;
+        for (sequence, segment) in (first_sequence..).zip(listed) {
+            let name = segment.name;
+            if published.segments.iter().any(|s| s.sequence == sequence)
+                || published.quarantined.contains(&sequence)
+            {
+                continue;
+            }
+
+            let staged_path = self.staging_dir.join(&name);
+            match fs::File::open(&staged_path) {
+                Ok(file) => file.sync_all()?,
+                // Already published and cleaned up again
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            }
 
-        // Sort by name (FFmpeg uses sequential numbering)
-        new_segments.sort();
+            // Segments are closed right as the next one starts, the first frame is a duration ago
+            let duration = TimeDelta::milliseconds(
+                (segment.duration.unwrap_or_default() * 1000.0).round() as i64,
+            );
+            let jumped = published.jumps.contains(&sequence);
+            let start = match published.next_start {
+                Some(next_start) if !segment.discontinuity && !jumped => next_start,
+                _ => Utc::now() - duration,
+            };
+            published.next_start = Some(start + duration);
+
+            let mut discontinuity = segment.discontinuity
+                || jumped
+                || (sequence > 0 && published.quarantined.contains(&(sequence - 1)));
+            if let Some(expectations) = &expectations {
+                let data = fs::read(&staged_path)?;
+                let info = match check_segment(&data, segment.duration, expectations) {
+                    Ok(info) => info,
+                    Err(fault) => {
+                        eprintln!("[segments] Quarantined {}: {}", name, fault);
+                        self.quarantine(&staged_path, &name);
+                        published.quarantined.push(sequence);
+                        changed = true;
+                        continue;
+                    }
+                };
+
+                let bitrate = segment
+                    .duration
+                    .filter(|d| *d >= MIN_BITRATE_SECS)
+                    .map(|d| (data.len() as f64 * 8.0 / d) as u64);
+                let now = chrono::Utc::now().timestamp_millis() as f64 / 1000.0;
+                discontinuity |=
+                    self.quality
+                        .lock()
+                        .unwrap()
+                        .observe(info.resolution, bitrate, now);
+            }
+            if discontinuity {
+                published.discontinuities.push(sequence);
+            }
+
+            let mut published_name = time_name(start, &name);
+            if published.segments.iter().any(|s| s.name == published_name) {
+                published_name = format!("{}-{}", sequence, published_name);
+            }
+            fs::rename(&staged_path, self.output_dir.join(&published_name))?;
 
-        for segment in new_segments {
-            segments.push_back(segment);
+            published.segments.push_back(PublishedSegment {
+                sequence,
+                name: published_name,
+                start,
+            });
+            changed = true;
+        }
+
+        published.jumps.retain(|jump| *jump >= first_sequence);
+
+        // Nothing to serve yet if every listed segment was quarantined
+        let playlist = render_playlist(&staged, &published);
+        if changed && let Some(playlist) = playlist {
+            let temp_path = self.output_dir.join(format!("{}.tmp", PLAYLIST));
+            fs::write(&temp_path, &playlist)?;
+            fs::File::open(&temp_path)?.sync_all()?;
+            fs::rename(&temp_path, self.output_dir.join(PLAYLIST))?;
+            published.next_sequence = next_sequence;
         }
 
         // Cleanup old segments
-        while segments.len() > self.max_segments {
-            if let Some(old_segment) = segments.pop_front() {
-                let path = dir.join(&old_segment);
-                let _ = fs::remove_file(path);
+        while published.segments.len() > self.max_segments {
+            if let Some(old_segment) = published.segments.pop_front() {
+                let _ = fs::remove_file(self.output_dir.join(&old_segment.name));
             }
         }
+
+        Ok(())
+    }
+
+    /**
+        Mark a discontinuity before the segment FFmpeg is writing, for when
+        the timestamps of the input jump (the source was reopened, or moved
+        on to a new period). FFmpeg can't be told to start a new segment at
+        the jump, so the marked segment still starts before it.
+    */
+    pub fn mark_discontinuity(&self) {
+        let writing = fs::read_to_string(self.staging_dir.join(PLAYLIST))
+            .ok()
+            .and_then(|staged| next_media_sequence(&staged))
+            .unwrap_or(0);
+        self.published.lock().unwrap().jumps.push(writing);
+    }
+
+    /**
+        Move a bad segment into the quarantine directory, keeping only
+        the most recent ones. Failing that, the segment is removed.
+    */
+    fn quarantine(&self, path: &Path, name: &str) {
+        let dir = self.output_dir.join(QUARANTINE_DIR);
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let moved = fs::create_dir_all(&dir)
+            .and_then(|_| fs::rename(path, dir.join(format!("{}-{}", millis, name))));
+        if let Err(e) = moved {
+            eprintln!("[segments] Failed to quarantine {}: {}", name, e);
+            let _ = fs::remove_file(path);
+            return;
+        }
+
+        // Names start with the time of quarantine, so they sort oldest first
+        let Ok(entries) = fs::read_dir(&dir) else {
+            return;
+        };
+        let mut names: Vec<_> = entries.flatten().map(|e| e.file_name()).collect();
+        names.sort();
+        let excess = names.len().saturating_sub(MAX_QUARANTINED);
+        for old in &names[..excess] {
+            let _ = fs::remove_file(dir.join(old));
+        }
     }
 
     /**
@@ -98,30 +334,465 @@ impl SegmentManager {
     */
     #[allow(dead_code)]
     pub fn playlist_path(&self) -> PathBuf {
-        self.output_dir.join("playlist.m3u8")
+        self.output_dir.join(PLAYLIST)
     }
 
     /**
         Get current segment count.
     */
     pub fn segment_count(&self) -> usize {
-        self.segments.lock().unwrap().len()
+        self.published.lock().unwrap().segments.len()
     }
 
     /**
-        Clear all segments and remove files from disk.
+        Clear all segments and remove files from disk, leaving an empty
+        staging directory for the next run.
     */
     pub fn clear(&self) {
-        let mut segments = self.segments.lock().unwrap();
+        let mut published = self.published.lock().unwrap();
         let dir = &self.output_dir;
 
         // Remove segment files
-        for segment in segments.drain(..) {
-            let path = dir.join(&segment);
+        for segment in published.segments.drain(..) {
+            let path = dir.join(&segment.name);
             let _ = fs::remove_file(path);
         }
+        published.next_sequence = 0;
+        published.quarantined.clear();
+        published.discontinuities.clear();
+        published.jumps.clear();
+        published.next_start = None;
+        *self.expectations.lock().unwrap() = None;
 
         // Also remove playlist file
-        let _ = fs::remove_file(dir.join("playlist.m3u8"));
+        let _ = fs::remove_file(dir.join(PLAYLIST));
+
+        // Leftovers of the previous run's FFmpeg
+        let _ = fs::remove_dir_all(&self.staging_dir);
+        let _ = fs::create_dir_all(&self.staging_dir);
+    }
+}
+
+/**
+    Get the segments of a playlist, or `None` if the playlist looks
+    cut off (a segment without its URI, or a partial last line).
+*/
+fn listed_segments(playlist: &str) -> Option<Vec<ListedSegment>> {
+    if !playlist.starts_with("#EXTM3U") || !playlist.ends_with('\n') {
+        return None;
+    }
+
+    let mut segments = Vec::new();
+    let mut pending_segment = None;
+    let mut discontinuity = false;
+    for line in playlist.lines() {
+        if line == "#EXT-X-DISCONTINUITY" {
+            discontinuity = true;
+        } else if let Some(info) = line.strip_prefix("#EXTINF:") {
+            let duration = info.split(',').next().and_then(|d| d.trim().parse().ok());
+            pending_segment = Some(duration);
+        } else if !line.is_empty()
+            && !line.starts_with('#')
+            && let Some(duration) = pending_segment.take()
+        {
+            segments.push(ListedSegment {
+                name: line.trim().to_string(),
+                duration,
+                discontinuity: std::mem::take(&mut discontinuity),
+            });
+        }
+    }
+
+    pending_segment.is_none().then_some(segments)
+}
+
+/**
+    Render the playlist to publish from FFmpeg's playlist.
+
+    Quarantined segments are left out, renumbering the rest as if they
+    never existed, and segments are given their published names and start
+    times. The discontinuity sequence counts the discontinuities that have
+    left the playlist. Returns `None` if no published segments are left.
+*/
+fn render_playlist(staged: &str, published: &Published) -> Option<String> {
+    let first = staged
+        .lines()
+        .find_map(|l| l.strip_prefix("#EXT-X-MEDIA-SEQUENCE:"))
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .unwrap_or(0);
+
+    let mut header = String::new();
+    let mut body = String::new();
+    let mut entry = String::new();
+    let mut sequence = first;
+    let mut first_kept = None;
+    let mut in_segments = false;
+    for line in staged.lines() {
+        // Replaced with the published numbering, discontinuities and times
+        if line.starts_with("#EXT-X-MEDIA-SEQUENCE:")
+            || line.starts_with("#EXT-X-DISCONTINUITY-SEQUENCE:")
+            || line.starts_with("#EXT-X-PROGRAM-DATE-TIME:")
+            || line == "#EXT-X-DISCONTINUITY"
+        {
+            in_segments |= line == "#EXT-X-DISCONTINUITY";
+            continue;
+        }
+        in_segments |= is_segment_tag(line);
+        if !in_segments {
+            let _ = writeln!(header, "{}", line);
+            continue;
+        }
+
+        // The URI ends the entry of a segment, along with the tags before it
+        if !line.is_empty() && !line.starts_with('#') {
+            // Segments that were quarantined, or already cleaned up, are left out
+            if let Some(segment) = published.segments.iter().find(|s| s.sequence == sequence) {
+                first_kept.get_or_insert(sequence);
+                if published.discontinuities.contains(&sequence) {
+                    body.push_str("#EXT-X-DISCONTINUITY\n");
+                }
+                let _ = writeln!(
+                    body,
+                    "#EXT-X-PROGRAM-DATE-TIME:{}",
+                    segment.start.to_rfc3339_opts(SecondsFormat::Millis, true)
+                );
+                body.push_str(&entry);
+                let _ = writeln!(body, "{}", segment.name);
+            }
+            entry.clear();
+            sequence += 1;
+            continue;
+        }
+        let _ = writeln!(entry, "{}", line);
+    }
+    let first_kept = first_kept?;
+
+    let removed = published
+        .quarantined
+        .iter()
+        .filter(|q| **q < first_kept)
+        .count() as u64;
+    let rolled_out = published
+        .discontinuities
+        .iter()
+        .filter(|d| **d < first_kept)
+        .count();
+
+    let mut out = header;
+    let _ = writeln!(out, "#EXT-X-MEDIA-SEQUENCE:{}", first_kept - removed);
+    let _ = writeln!(out, "#EXT-X-DISCONTINUITY-SEQUENCE:{}", rolled_out);
+    out.push_str(&body);
+    out.push_str(&entry);
+    Some(out)
+}
+
+/**
+    Name a segment after its start time (in milliseconds since the
+    epoch), keeping the extension of its staged name.
+*/
+fn time_name(start: DateTime<Utc>, staged_name: &str) -> String {
+    match Path::new(staged_name).extension() {
+        Some(extension) => format!(
+            "{}.{}",
+            start.timestamp_millis(),
+            extension.to_string_lossy()
+        ),
+        None => start.timestamp_millis().to_string(),
+    }
+}
+
+/**
+    Whether a line belongs to a segment, rather than the playlist header.
+*/
+fn is_segment_tag(line: &str) -> bool {
+    (!line.is_empty() && !line.starts_with('#'))
+        || line.starts_with("#EXTINF")
+        || line == "#EXT-X-DISCONTINUITY"
+        || line.starts_with("#EXT-X-PROGRAM-DATE-TIME")
+        || line.starts_with("#EXT-X-BYTERANGE")
+}
+
+/**
+    Playlist continuity across pipeline restarts.
+
+    Each restart writes a fresh playlist starting at media sequence 0 with
+    rebased timestamps. To clients this is presented as one continuous
+    playlist, with the media sequence carried over and a discontinuity
+    before the first segment of every run after the first.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct PlaylistContinuity {
+    /// Number of restarts that produced a new run of segments
+    pub restarts: u64,
+    /// Added to the media sequence of the current run
+    pub sequence_offset: u64,
+}
+
+impl PlaylistContinuity {
+    /**
+        Record a restart, given the playlist written by the previous run.
+        Runs that never wrote a segment don't count as a restart.
+    */
+    pub fn record_restart(&mut self, previous_playlist: &str) {
+        if let Some(next) = next_media_sequence(previous_playlist) {
+            self.sequence_offset += next;
+            self.restarts += 1;
+        }
+    }
+
+    /**
+        Rewrite a playlist of the current run to continue the previous runs.
+    */
+    pub fn rewrite(&self, playlist: &str) -> String {
+        if self.restarts == 0 {
+            return playlist.to_string();
+        }
+
+        // Discontinuities within the run, left by quarantined segments
+        let run_discontinuities = playlist
+            .lines()
+            .find_map(|l| l.strip_prefix("#EXT-X-DISCONTINUITY-SEQUENCE:"))
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+
+        let mut out = String::with_capacity(playlist.len() + 64);
+        let mut run_start_pending = false;
+
+        for line in playlist.lines() {
+            if line.starts_with("#EXT-X-DISCONTINUITY-SEQUENCE:") {
+                continue;
+            }
+            if let Some(sequence) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+                let sequence: u64 = sequence.trim().parse().unwrap_or(0);
+                // The discontinuity before the run's first segment counts once it is gone
+                run_start_pending = sequence == 0;
+                let discontinuities = if run_start_pending {
+                    self.restarts - 1
+                } else {
+                    self.restarts
+                };
+                let _ = writeln!(
+                    out,
+                    "#EXT-X-MEDIA-SEQUENCE:{}",
+                    sequence + self.sequence_offset
+                );
+                let _ = writeln!(
+                    out,
+                    "#EXT-X-DISCONTINUITY-SEQUENCE:{}",
+                    discontinuities + run_discontinuities
+                );
+                continue;
+            }
+
+            if run_start_pending && line == "#EXT-X-DISCONTINUITY" {
+                run_start_pending = false;
+            } else if run_start_pending && line.starts_with("#EXTINF") {
+                out.push_str("#EXT-X-DISCONTINUITY\n");
+                run_start_pending = false;
+            }
+
+            // Segment names are reused by each run, so make their URIs unique
+            if !line.is_empty() && !line.starts_with('#') {
+                let _ = writeln!(out, "{}?r={}", line, self.restarts);
+            } else {
+                let _ = writeln!(out, "{}", line);
+            }
+        }
+
+        out
+    }
+}
+
+/**
+    Get the media sequence number that would follow the last segment of a playlist.
+*/
+fn next_media_sequence(playlist: &str) -> Option<u64> {
+    let first = playlist
+        .lines()
+        .find_map(|l| l.strip_prefix("#EXT-X-MEDIA-SEQUENCE:"))
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .unwrap_or(0);
+    let count = playlist
+        .lines()
+        .filter(|l| l.starts_with("#EXTINF"))
+        .count() as u64;
+    (count > 0).then_some(first + count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlist(sequence: u64, segments: &[&str]) -> String {
+        let mut out = format!(
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:4\n#EXT-X-MEDIA-SEQUENCE:{}\n",
+            sequence
+        );
+        for segment in segments {
+            out.push_str(&format!("#EXTINF:4.000000,\n{}\n", segment));
+        }
+        out
+    }
+
+    #[test]
+    fn test_first_run_is_unchanged() {
+        let continuity = PlaylistContinuity::default();
+        let p = playlist(0, &["s0.ts", "s1.ts"]);
+        assert_eq!(continuity.rewrite(&p), p);
+    }
+
+    #[test]
+    fn test_restart_continues_sequence() {
+        let mut continuity = PlaylistContinuity::default();
+        continuity.record_restart(&playlist(5, &["s5.ts", "s6.ts", "s7.ts"]));
+        assert_eq!(continuity.sequence_offset, 8);
+        assert_eq!(continuity.restarts, 1);
+
+        let out = continuity.rewrite(&playlist(0, &["s0.ts", "s1.ts"]));
+        assert!(out.contains("#EXT-X-MEDIA-SEQUENCE:8\n"));
+        assert!(out.contains("#EXT-X-DISCONTINUITY-SEQUENCE:0\n"));
+        assert!(out.contains("#EXT-X-DISCONTINUITY\n#EXTINF:4.000000,\ns0.ts?r=1\n"));
+        assert!(out.contains("s1.ts?r=1\n"));
+    }
+
+    #[test]
+    fn test_discontinuity_counted_after_run_start_rolls_out() {
+        let mut continuity = PlaylistContinuity::default();
+        continuity.record_restart(&playlist(0, &["s0.ts"]));
+
+        let out = continuity.rewrite(&playlist(3, &["s3.ts"]));
+        assert!(out.contains("#EXT-X-MEDIA-SEQUENCE:4\n"));
+        assert!(out.contains("#EXT-X-DISCONTINUITY-SEQUENCE:1\n"));
+        assert!(!out.contains("#EXT-X-DISCONTINUITY\n"));
+    }
+
+    #[test]
+    fn test_listed_segments() {
+        let p = playlist(3, &["s3.ts", "s4.ts"]);
+        let names = listed_segments(&p).map(|l| l.into_iter().map(|s| s.name).collect());
+        assert_eq!(names, Some(vec!["s3.ts".to_string(), "s4.ts".to_string()]));
+        assert_eq!(listed_segments(&p).unwrap()[0].duration, Some(4.0));
+
+        // Cut off while FFmpeg was rewriting it
+        assert_eq!(listed_segments(&p[..p.len() - 6]), None);
+        assert_eq!(listed_segments(&p[..p.len() - 1]), None);
+        assert_eq!(listed_segments(""), None);
+    }
+
+    #[test]
+    fn test_restart_without_segments_is_ignored() {
+        let mut continuity = PlaylistContinuity::default();
+        continuity.record_restart(&playlist(0, &[]));
+        assert_eq!(continuity.restarts, 0);
+    }
+
+    fn published(sequences: &[u64], quarantined: &[u64], discontinuities: &[u64]) -> Published {
+        let epoch = DateTime::parse_from_rfc3339("2026-01-01T00:00:00Z").unwrap();
+        Published {
+            segments: sequences
+                .iter()
+                .map(|sequence| PublishedSegment {
+                    sequence: *sequence,
+                    name: format!("p{}.ts", sequence),
+                    start: epoch.to_utc() + TimeDelta::seconds(*sequence as i64 * 4),
+                })
+                .collect(),
+            quarantined: quarantined.to_vec(),
+            discontinuities: discontinuities.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render_playlist() {
+        let p = playlist(3, &["s3.ts", "s4.ts", "s5.ts"]);
+        let out = render_playlist(&p, &published(&[3, 5], &[4], &[5])).unwrap();
+        assert!(out.starts_with("#EXTM3U\n"));
+        assert!(out.contains("#EXT-X-MEDIA-SEQUENCE:3\n#EXT-X-DISCONTINUITY-SEQUENCE:0\n"));
+        assert!(!out.contains("s4.ts") && !out.contains("p4.ts"));
+        assert!(out.contains(concat!(
+            "#EXT-X-PROGRAM-DATE-TIME:2026-01-01T00:00:12.000Z\n#EXTINF:4.000000,\np3.ts\n",
+            "#EXT-X-DISCONTINUITY\n",
+            "#EXT-X-PROGRAM-DATE-TIME:2026-01-01T00:00:20.000Z\n#EXTINF:4.000000,\np5.ts\n",
+        )));
+
+        // Renumbered as if the segment never existed, counting its discontinuity once gone
+        let p = playlist(6, &["s6.ts", "s7.ts"]);
+        let out = render_playlist(&p, &published(&[6, 7], &[4], &[5, 7])).unwrap();
+        assert!(out.contains("#EXT-X-MEDIA-SEQUENCE:5\n#EXT-X-DISCONTINUITY-SEQUENCE:1\n"));
+        assert!(out.contains("p6.ts\n#EXT-X-DISCONTINUITY\n#EXT-X-PROGRAM-DATE-TIME"));
+
+        // Segments already cleaned up are left out without renumbering
+        let out = render_playlist(&p, &published(&[7], &[], &[])).unwrap();
+        assert!(out.contains("#EXT-X-MEDIA-SEQUENCE:7\n"));
+        assert!(!out.contains("p6.ts"));
+
+        assert!(render_playlist(&playlist(0, &["s0.ts"]), &published(&[], &[0], &[])).is_none());
+    }
+
+    #[test]
+    fn test_time_name() {
+        let start = DateTime::from_timestamp_millis(1_700_000_000_123).unwrap();
+        assert_eq!(time_name(start, "segment_004.ts"), "1700000000123.ts");
+        assert_eq!(time_name(start, "segment"), "1700000000123");
+    }
+
+    #[test]
+    fn test_publish_quarantines_bad_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = SegmentManager::new(dir.path().to_path_buf(), 10);
+        manager.clear();
+        manager.set_expectations(SegmentExpectations {
+            video: false,
+            audio: false,
+            target_duration: std::time::Duration::from_secs(4),
+        });
+
+        let staging = manager.staging_dir();
+        let fmp4 = [&[0, 0, 0, 8][..], b"moof", &[0, 0, 0, 8], b"mdat"].concat();
+        fs::write(staging.join("s0.ts"), fmp4).unwrap();
+        fs::write(staging.join("s1.ts"), b"garbage").unwrap();
+        fs::write(staging.join(PLAYLIST), playlist(0, &["s0.ts", "s1.ts"])).unwrap();
+        manager.publish().unwrap();
+
+        assert_eq!(manager.segment_count(), 1);
+        let quarantined = fs::read_dir(dir.path().join(QUARANTINE_DIR)).unwrap();
+        assert_eq!(quarantined.count(), 1);
+
+        // Only the good segment is published, under its time name
+        let published = fs::read_to_string(manager.playlist_path()).unwrap();
+        let listed = listed_segments(&published).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert!(listed[0].name.ends_with(".ts"));
+        assert!(dir.path().join(&listed[0].name).exists());
+        assert!(published.contains("#EXT-X-PROGRAM-DATE-TIME:"));
+    }
+
+    #[test]
+    fn test_publish_marks_jumps() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = SegmentManager::new(dir.path().to_path_buf(), 10);
+        manager.clear();
+
+        let staging = manager.staging_dir();
+        fs::write(staging.join("s0.ts"), b"s0").unwrap();
+        fs::write(staging.join(PLAYLIST), playlist(0, &["s0.ts"])).unwrap();
+        manager.publish().unwrap();
+
+        // The jump is in the segment being written after the listed ones
+        manager.mark_discontinuity();
+        fs::write(staging.join("s1.ts"), b"s1").unwrap();
+        fs::write(staging.join("s2.ts"), b"s2").unwrap();
+        fs::write(
+            staging.join(PLAYLIST),
+            playlist(0, &["s0.ts", "s1.ts", "s2.ts"]),
+        )
+        .unwrap();
+        manager.publish().unwrap();
+
+        let published = fs::read_to_string(manager.playlist_path()).unwrap();
+        let listed = listed_segments(&published).unwrap();
+        let discontinuities: Vec<bool> = listed.iter().map(|s| s.discontinuity).collect();
+        assert_eq!(discontinuities, [false, true, false]);
     }
 }
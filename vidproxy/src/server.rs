@@ -1,36 +1,82 @@
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration as StdDuration;
+use std::time::{Duration as StdDuration, SystemTime};
 
 use axum::{
-    Router,
-    body::Body,
-    extract::{Path, State},
-    http::{HeaderMap, StatusCode, header},
+    Json, Router,
+    body::{Body, Bytes},
+    extract::{Path, Query, RawQuery, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, header, response::Builder},
+    middleware,
     response::{IntoResponse, Response},
-    routing::get,
+    routing::{get, post, put},
 };
-use chrono::{Duration, TimeZone, Utc};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use drm_widevine::core::KeyString;
+use md5::{Digest, Md5};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::sync::{RwLock, watch};
 use tokio_util::io::ReaderStream;
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{AllowHeaders, AllowOrigin, CorsLayer};
 
+use crate::access_log::{self, AccessLog};
+use crate::antibot::AntibotChallenge;
+use crate::channel_search::ChannelFilter;
 use crate::image_cache::ImageCache;
-use crate::manifest::Manifest;
-use crate::pipeline::PipelineStore;
+use crate::manifest::{
+    self, ChannelEntry, InterpolationContext, Manifest, SegmentConfig, StreamInfo, request_headers,
+};
+use crate::network::Network;
+use crate::passthrough;
+use crate::pipeline::{ChannelPipeline, PipelineStore};
+use crate::preferences::{ChannelPreferences, ChannelSet, ImportConflicts};
+use crate::prewarm::PrewarmPolicy;
+use crate::quality;
 use crate::registry::{ChannelContentState, ChannelId, ChannelRegistry, SourceState};
+use crate::slate::SLATE_ROUTE;
 use crate::source;
+use crate::watchdog::Incidents;
 
 /**
     Default timeout for waiting on source discovery (60 seconds)
 */
 const SOURCE_WAIT_TIMEOUT: StdDuration = StdDuration::from_secs(60);
 
+/**
+    Default and maximum page size of the channel list API
+*/
+const CHANNELS_PAGE_SIZE: usize = 50;
+const CHANNELS_PAGE_SIZE_MAX: usize = 500;
+
 /**
     Default timeout for waiting on channel content resolution (120 seconds)
 */
 const CONTENT_WAIT_TIMEOUT: StdDuration = StdDuration::from_secs(120);
 
+/**
+    How often to check that the pipelines of standby channels are running (30 seconds)
+*/
+const STANDBY_CHECK_INTERVAL: StdDuration = StdDuration::from_secs(30);
+
+/**
+    How often to check for popular channels whose stream info is about to expire (5 minutes)
+*/
+const CREDENTIAL_REFRESH_INTERVAL: StdDuration = StdDuration::from_secs(5 * 60);
+
+/**
+    How long before it expires the stream info of a popular channel is refreshed (10 minutes)
+*/
+const CREDENTIAL_REFRESH_AHEAD: StdDuration = StdDuration::from_secs(10 * 60);
+
+/**
+    Timeout for checking that a browser responds in readiness checks (5 seconds)
+*/
+const BROWSER_CHECK_TIMEOUT: StdDuration = StdDuration::from_secs(5);
+
 /**
     Wait for a source to be ready, returning appropriate error if not.
     - Returns Ok(()) if the source is ready
@@ -86,11 +132,132 @@ fn get_base_url(headers: &HeaderMap) -> String {
 }
 
 /**
-    Store for loaded manifests and their associated browsers, keyed by source name
+    Finish a response with `ETag` and `Last-Modified` validators, or send an
+    empty 304 Not Modified instead if the client already has the same body.
+
+    If-None-Match takes precedence over If-Modified-Since, as in RFC 9110.
+*/
+fn conditional_response(
+    headers: &HeaderMap,
+    response: Builder,
+    last_modified: Option<SystemTime>,
+    body: impl Into<Bytes>,
+) -> Response {
+    let body = body.into();
+    // Weak, since the compression layer may serve a different encoding of the body
+    let etag = format!("W/\"{:x}\"", Md5::digest(&body));
+    let last_modified = last_modified.map(DateTime::<Utc>::from);
+
+    let if_none_match = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok());
+    let if_modified_since = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| DateTime::parse_from_rfc2822(v).ok());
+    let not_modified = match (if_none_match, if_modified_since, last_modified) {
+        (Some(tags), _, _) => tags.split(',').any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/") == etag.trim_start_matches("W/")
+        }),
+        (None, Some(since), Some(modified)) => modified.timestamp() <= since.timestamp(),
+        _ => false,
+    };
+
+    let mut response = response.header(header::ETAG, &etag);
+    if let Some(modified) = last_modified {
+        response = response.header(
+            header::LAST_MODIFIED,
+            modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+        );
+    }
+
+    if not_modified {
+        response
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .unwrap()
+    } else {
+        response
+            .status(StatusCode::OK)
+            .body(Body::from(body))
+            .unwrap()
+    }
+}
+
+/**
+    Options for the HTTP server itself, rather than for the channels it serves.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct ServerOptions {
+    /// Origins allowed to make cross-origin requests, `*` for any
+    pub cors_origins: Vec<String>,
+    /// Whether cross-origin requests may include credentials
+    pub cors_credentials: bool,
+    /// Log every Nth request to the access log, or none if zero
+    pub access_log_sample: u64,
+    /// Whether the access log takes client addresses from `X-Forwarded-For`
+    pub trust_proxy: bool,
+    /// Which of the most watched channels to keep the credentials of fresh
+    pub prewarm_policy: PrewarmPolicy,
+}
+
+/**
+    Create the CORS layer for the configured origins, if any.
+
+    Browsers reject a wildcard origin for requests with credentials,
+    so `*` mirrors the requesting origin back when credentials are allowed.
+*/
+fn cors_layer(options: &ServerOptions) -> Option<CorsLayer> {
+    if options.cors_origins.is_empty() {
+        return None;
+    }
+
+    let any_origin = options.cors_origins.iter().any(|origin| origin == "*");
+    let allow_origin = match (any_origin, options.cors_credentials) {
+        (true, true) => AllowOrigin::mirror_request(),
+        (true, false) => AllowOrigin::any(),
+        (false, _) => AllowOrigin::list(
+            options
+                .cors_origins
+                .iter()
+                .filter_map(|origin| HeaderValue::from_str(origin).ok()),
+        ),
+    };
+    let allow_headers = if options.cors_credentials {
+        AllowHeaders::mirror_request()
+    } else {
+        AllowHeaders::any()
+    };
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([
+                Method::GET,
+                Method::HEAD,
+                Method::POST,
+                Method::PUT,
+                Method::DELETE,
+            ])
+            .allow_headers(allow_headers)
+            .allow_credentials(options.cors_credentials)
+            // Players seeking in segments need to see the range headers
+            .expose_headers([
+                header::ACCEPT_RANGES,
+                header::CONTENT_LENGTH,
+                header::CONTENT_RANGE,
+            ]),
+    )
+}
+
+/**
+    Store for loaded manifests and their associated browsers and sessions, keyed by source name
 */
 pub struct ManifestStore {
     manifests: RwLock<HashMap<String, Manifest>>,
     browsers: RwLock<HashMap<String, chrome_browser::ChromeBrowser>>,
+    sessions: RwLock<HashMap<String, InterpolationContext>>,
 }
 
 impl ManifestStore {
@@ -98,6 +265,7 @@ impl ManifestStore {
         Self {
             manifests: RwLock::new(HashMap::new()),
             browsers: RwLock::new(HashMap::new()),
+            sessions: RwLock::new(HashMap::new()),
         }
     }
 
@@ -129,6 +297,58 @@ impl ManifestStore {
         self.browsers.read().await.get(source).cloned()
     }
 
+    /**
+        Remove and return the browser instance for a source
+    */
+    pub async fn remove_browser(&self, source: &str) -> Option<chrome_browser::ChromeBrowser> {
+        self.browsers.write().await.remove(source)
+    }
+
+    /**
+        Store the session of a source with a refresh phase: the outputs of
+        its discovery, as renewed by its refresh phase
+    */
+    pub async fn set_session(&self, source: &str, context: InterpolationContext) {
+        let mut sessions = self.sessions.write().await;
+        sessions.insert(source.to_string(), context);
+    }
+
+    /**
+        Get the session of a source, if it has one
+    */
+    pub async fn get_session(&self, source: &str) -> Option<InterpolationContext> {
+        self.sessions.read().await.get(source).cloned()
+    }
+
+    /**
+        Close all browsers, for shutdown
+    */
+    pub async fn close_browsers(&self) {
+        let browsers: Vec<_> = self.browsers.write().await.drain().collect();
+        for (source, browser) in browsers {
+            println!("[server] Closing browser for '{}'", source);
+            let _ = browser.close().await;
+        }
+    }
+
+    /**
+        Check whether at least one browser is running and responds,
+        by evaluating a trivial script in one of its tabs
+    */
+    pub async fn any_browser_reachable(&self) -> bool {
+        let browsers: Vec<_> = self.browsers.read().await.values().cloned().collect();
+        for browser in browsers {
+            let check = async {
+                let tab = browser.get_tab(0).await?;
+                tab.eval_json("1", false).await.ok()
+            };
+            if let Ok(Some(_)) = tokio::time::timeout(BROWSER_CHECK_TIMEOUT, check).await {
+                return true;
+            }
+        }
+        false
+    }
+
     /**
         Get tab 0 from the browser for a source
     */
@@ -148,6 +368,10 @@ struct AppState {
     pipeline_store: Arc<PipelineStore>,
     manifest_store: Arc<ManifestStore>,
     image_cache: Arc<ImageCache>,
+    preferences: Arc<ChannelPreferences>,
+    access_log: Arc<AccessLog>,
+    incidents: Arc<Incidents>,
+    upstream_origins: Arc<passthrough::UpstreamOrigins>,
 }
 
 /**
@@ -169,10 +393,17 @@ async fn index(State(state): State<AppState>, headers: HeaderMap) -> impl IntoRe
                 None => "unknown",
             };
 
+            let schedule = state
+                .registry
+                .get_discovery_schedule(&m.source.id)
+                .unwrap_or_default();
+
             serde_json::json!({
                 "id": m.source.id,
                 "name": m.source.name,
                 "status": status,
+                "next_discovery_at": schedule.next_run_at,
+                "needs_attention": state.registry.needs_attention(&m.source.id),
                 "info": format!("{}/{}/info", base_url, m.source.id),
                 "m3u": format!("{}/{}/channels.m3u", base_url, m.source.id),
                 "epg": format!("{}/{}/epg.xml", base_url, m.source.id),
@@ -182,6 +413,8 @@ async fn index(State(state): State<AppState>, headers: HeaderMap) -> impl IntoRe
 
     let json = serde_json::json!({
         "sources": sources,
+        "channels": format!("{}/api/channels", base_url),
+        "favorites": format!("{}/favorites.m3u", base_url),
     });
 
     (
@@ -219,6 +452,11 @@ async fn source_info(
         _ => None,
     };
 
+    let schedule = state
+        .registry
+        .get_discovery_schedule(&source_id)
+        .unwrap_or_default();
+
     let channels = state.registry.list_by_source(&source_id);
     let channel_list: Vec<serde_json::Value> = channels
         .iter()
@@ -233,6 +471,7 @@ async fn source_info(
                     None
                 },
                 "playlist": format!("{}/{}/{}/playlist.m3u8", base_url, source_id, e.channel.id),
+                "tune": format!("{}/tune/{}/{}", base_url, source_id, e.channel.id),
                 "resolved": e.stream_info.is_some(),
             })
         })
@@ -243,6 +482,16 @@ async fn source_info(
         "name": manifest.source.name,
         "status": status,
         "error": error,
+        "discovery": {
+            "expired": state.registry.is_discovery_expired(&source_id),
+            "last_run_at": schedule.last_run_at,
+            "next_run_at": schedule.next_run_at,
+            "consecutive_failures": schedule.consecutive_failures,
+            "last_error": schedule.last_error,
+            "timed_out": schedule.timed_out,
+        },
+        "needs_attention": state.registry.needs_attention(&source_id),
+        "resume": format!("{}/api/sources/{}/resume", base_url, source_id),
         "m3u": format!("{}/{}/channels.m3u", base_url, source_id),
         "epg": format!("{}/{}/epg.xml", base_url, source_id),
         "channels": channel_list,
@@ -255,97 +504,187 @@ async fn source_info(
 }
 
 /**
-    Generate M3U playlist with channels from a specific source.
+    Query parameters of the channel list API.
 */
-async fn source_m3u(
+#[derive(Debug, Deserialize)]
+struct ChannelsQuery {
+    search: Option<String>,
+    group: Option<String>,
+    source: Option<String>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+/**
+    List channels across all sources (JSON), optionally filtered by
+    source and group, and fuzzily searched by name.
+
+    Results are sorted by how well they match the search, then by name,
+    and paginated with `offset` and `limit`.
+*/
+async fn list_channels(
     State(state): State<AppState>,
-    Path(source_id): Path<String>,
+    Query(query): Query<ChannelsQuery>,
     headers: HeaderMap,
-) -> Result<impl IntoResponse, StatusCode> {
-    // Wait for source to be ready
-    wait_for_source_ready(&state.registry, &source_id).await?;
+) -> impl IntoResponse {
+    let base_url = get_base_url(&headers);
 
-    let manifest = state
+    let source_names: HashMap<String, String> = state
         .manifest_store
-        .get(&source_id)
+        .list()
         .await
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .into_iter()
+        .map(|m| (m.source.id, m.source.name))
+        .collect();
 
-    let channels = state.registry.list_by_source(&source_id);
-    if channels.is_empty() {
-        // Source is ready but has no channels (all failed during content phase)
-        return Err(StatusCode::NOT_FOUND);
-    }
+    let filter = ChannelFilter {
+        search: query.search,
+        group: query.group,
+        source: query.source,
+    };
 
-    let base_url = get_base_url(&headers);
+    let mut matches: Vec<_> = state
+        .registry
+        .list_all()
+        .into_iter()
+        .filter(|(id, _)| !state.registry.is_alternate(id))
+        .filter_map(|(id, entry)| {
+            // Same grouping as the M3U playlists
+            let group = entry
+                .channel
+                .category
+                .clone()
+                .or_else(|| source_names.get(&id.source).cloned())
+                .unwrap_or_else(|| id.source.clone());
+            let name = entry.channel.name.as_deref().unwrap_or(&entry.channel.id);
+            let score = filter.score(&id.source, &group, name)?;
+            Some((score, name.to_lowercase(), id, entry, group))
+        })
+        .collect();
 
-    let mut playlist = format!("#EXTM3U url-tvg=\"{}/{}/epg.xml\"\n", base_url, source_id);
+    matches.sort_by(|a, b| {
+        b.0.cmp(&a.0)
+            .then_with(|| a.1.cmp(&b.1))
+            .then_with(|| a.2.to_string().cmp(&b.2.to_string()))
+    });
 
-    for entry in &channels {
-        // Include all channels - content will be resolved on-demand when played
-        let channel_name = entry.channel.name.as_deref().unwrap_or(&entry.channel.id);
+    let total = matches.len();
+    let offset = query.offset.unwrap_or(0);
+    let limit = query
+        .limit
+        .unwrap_or(CHANNELS_PAGE_SIZE)
+        .clamp(1, CHANNELS_PAGE_SIZE_MAX);
+
+    let channels: Vec<serde_json::Value> = matches
+        .into_iter()
+        .skip(offset)
+        .take(limit)
+        .map(|(_, _, id, entry, group)| {
+            let (source_id, channel_id) = (&id.source, &id.id);
+            serde_json::json!({
+                "id": channel_id,
+                "source": source_id,
+                "name": entry.channel.name,
+                "group": group,
+                "info": format!("{}/{}/{}/info", base_url, source_id, channel_id),
+                "image": if entry.channel.image.is_some() {
+                    Some(format!("{}/{}/{}/image", base_url, source_id, channel_id))
+                } else {
+                    None
+                },
+                "playlist": format!("{}/{}/{}/playlist.m3u8", base_url, source_id, channel_id),
+                "tune": format!("{}/tune/{}/{}", base_url, source_id, channel_id),
+                "resolved": entry.stream_info.is_some(),
+                "needs_attention": state.registry.needs_attention(source_id),
+                "favorite": state.preferences.is_favorite(&id),
+                "hidden": state.preferences.is_hidden(&id),
+                "alternates": state
+                    .registry
+                    .alternates_of(&id)
+                    .iter()
+                    .map(|a| a.to_string())
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
 
-        // Use local image URL if channel has an image
-        let logo_attr = if entry.channel.image.is_some() {
-            format!(
-                " tvg-logo=\"{}/{}/{}/image\"",
-                base_url, source_id, entry.channel.id
-            )
-        } else {
-            String::new()
-        };
+    let json = serde_json::json!({
+        "total": total,
+        "offset": offset,
+        "limit": limit,
+        "channels": channels,
+    });
 
-        // Add country attribute if configured
-        let country_attr = manifest
-            .source
-            .country
-            .as_ref()
-            .map(|c| format!(" tvg-country=\"{}\"", escape_xml(c)))
-            .unwrap_or_default();
+    (
+        [(header::CONTENT_TYPE, "application/json; charset=utf-8")],
+        json.to_string(),
+    )
+}
 
-        // Add language attribute if configured
-        let language_attr = manifest
-            .source
-            .language
-            .as_ref()
-            .map(|l| format!(" tvg-language=\"{}\"", escape_xml(l)))
-            .unwrap_or_default();
+/**
+    Format a channel's `#EXTINF` line and stream URL for an M3U playlist.
+*/
+fn m3u_entry(base_url: &str, manifest: &Manifest, entry: &ChannelEntry) -> String {
+    let channel_name = entry.channel.name.as_deref().unwrap_or(&entry.channel.id);
+
+    // Use local image URL if channel has an image
+    let logo_attr = if entry.channel.image.is_some() {
+        format!(
+            " tvg-logo=\"{}/{}/{}/image\"",
+            base_url, manifest.source.id, entry.channel.id
+        )
+    } else {
+        String::new()
+    };
 
-        let channel_id = format!("{}:{}", source_id, entry.channel.id);
+    // Add country attribute if configured
+    let country_attr = manifest
+        .source
+        .country
+        .as_ref()
+        .map(|c| format!(" tvg-country=\"{}\"", escape_xml(c)))
+        .unwrap_or_default();
 
-        // Use channel category if set, otherwise fall back to source name
-        let group = entry
-            .channel
-            .category
-            .as_ref()
-            .unwrap_or(&manifest.source.name);
+    // Add language attribute if configured
+    let language_attr = manifest
+        .source
+        .language
+        .as_ref()
+        .map(|l| format!(" tvg-language=\"{}\"", escape_xml(l)))
+        .unwrap_or_default();
 
-        playlist.push_str(&format!(
-            "#EXTINF:-1 tvg-id=\"{id}\" tvg-name=\"{name}\" tvg-type=\"live\" group-title=\"{group}\"{logo}{country}{language},{name}\n\
-             {base_url}/{source}/{channel}/playlist.m3u8\n",
-            id = escape_xml(&channel_id),
-            name = escape_xml(channel_name),
-            group = escape_xml(group),
-            logo = logo_attr,
-            country = country_attr,
-            language = language_attr,
-            base_url = base_url,
-            source = source_id,
-            channel = entry.channel.id,
-        ));
-    }
+    let channel_id = format!("{}:{}", manifest.source.id, entry.channel.id);
 
-    Ok(([(header::CONTENT_TYPE, "audio/x-mpegurl")], playlist))
+    // Use channel category if set, otherwise fall back to source name
+    let group = entry
+        .channel
+        .category
+        .as_ref()
+        .unwrap_or(&manifest.source.name);
+
+    format!(
+        "#EXTINF:-1 tvg-id=\"{id}\" tvg-name=\"{name}\" tvg-type=\"live\" group-title=\"{group}\"{logo}{country}{language},{name}\n\
+         {base_url}/{source}/{channel}/playlist.m3u8\n",
+        id = escape_xml(&channel_id),
+        name = escape_xml(channel_name),
+        group = escape_xml(group),
+        logo = logo_attr,
+        country = country_attr,
+        language = language_attr,
+        base_url = base_url,
+        source = manifest.source.id,
+        channel = entry.channel.id,
+    )
 }
 
 /**
-    Generate XMLTV EPG data for channels from a specific source.
+    Generate M3U playlist with channels from a specific source.
 */
-async fn source_epg(
+async fn source_m3u(
     State(state): State<AppState>,
     Path(source_id): Path<String>,
     headers: HeaderMap,
-) -> Result<impl IntoResponse, StatusCode> {
+) -> Result<Response, StatusCode> {
     // Wait for source to be ready
     wait_for_source_ready(&state.registry, &source_id).await?;
 
@@ -357,98 +696,552 @@ async fn source_epg(
 
     let channels = state.registry.list_by_source(&source_id);
     if channels.is_empty() {
+        // Source is ready but has no channels (all failed during content phase)
         return Err(StatusCode::NOT_FOUND);
     }
 
     let base_url = get_base_url(&headers);
 
-    let now = Utc::now();
-    let start_of_day = now.date_naive().and_hms_opt(0, 0, 0).unwrap();
-    let start = start_of_day.and_utc();
+    let mut playlist = format!("#EXTM3U url-tvg=\"{}/{}/epg.xml\"\n", base_url, source_id);
 
-    let mut channel_elements = String::new();
-    let mut programmes = String::new();
+    // Include all channels that aren't hidden - content will be resolved on-demand when played
+    for entry in &state.preferences.arrange(&source_id, channels) {
+        playlist.push_str(&m3u_entry(&base_url, &manifest, entry));
+    }
 
-    // Language attribute for titles/descriptions if configured
-    let lang_attr = manifest
-        .source
-        .language
-        .as_ref()
-        .map(|l| format!(" lang=\"{}\"", escape_xml(l)))
+    Ok(conditional_response(
+        &headers,
+        Response::builder().header(header::CONTENT_TYPE, "audio/x-mpegurl"),
+        state
+            .registry
+            .source_updated_at(&source_id)
+            .max(state.preferences.updated_at()),
+        playlist,
+    ))
+}
+
+/**
+    Resume discovery of a source that was paused by an antibot challenge,
+    once it has been solved (or the source's setup changed). Discovery runs
+    again right away, for any source.
+*/
+async fn resume_source(State(state): State<AppState>, Path(source_id): Path<String>) -> StatusCode {
+    if state.manifest_store.get(&source_id).await.is_none() {
+        return StatusCode::NOT_FOUND;
+    }
+
+    state.registry.clear_needs_attention(&source_id);
+    let mut schedule = state
+        .registry
+        .get_discovery_schedule(&source_id)
         .unwrap_or_default();
+    schedule.next_run_at = Some(crate::time::now());
+    state.registry.set_discovery_schedule(&source_id, schedule);
+    println!("[server] Resuming discovery of '{}'", source_id);
 
-    for entry in &channels {
-        // Include all channels - EPG data comes from metadata phase, not content phase
+    StatusCode::ACCEPTED
+}
 
-        let channel_name = entry.channel.name.as_deref().unwrap_or(&entry.channel.id);
-        let channel_id = format!("{}:{}", source_id, entry.channel.id);
+/**
+    Generate an M3U playlist of the favorite channels across all sources.
 
-        // Use local image URL if channel has an image
-        let icon_element = if entry.channel.image.is_some() {
-            format!(
-                "    <icon src=\"{}/{}/{}/image\"/>\n",
-                base_url, source_id, entry.channel.id
-            )
-        } else {
-            String::new()
+    Favorites of sources that haven't been discovered yet are left out,
+    rather than waiting for every source involved.
+*/
+async fn favorites_m3u(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let base_url = get_base_url(&headers);
+
+    let mut manifests: HashMap<String, Option<Manifest>> = HashMap::new();
+    let mut entries = String::new();
+    let mut epg_urls: Vec<String> = Vec::new();
+    for id in state.preferences.favorites() {
+        let Some(entry) = state.registry.get(&id) else {
+            continue;
+        };
+        if !manifests.contains_key(&id.source) {
+            let manifest = state.manifest_store.get(&id.source).await;
+            manifests.insert(id.source.clone(), manifest);
+        }
+        let Some(Some(manifest)) = manifests.get(&id.source) else {
+            continue;
         };
 
-        channel_elements.push_str(&format!(
-            "  <channel id=\"{id}\">\n\
-             \x20   <display-name{lang}>{name}</display-name>\n\
-             {icon}\
-             \x20 </channel>\n",
-            id = escape_xml(&channel_id),
-            name = escape_xml(channel_name),
-            lang = lang_attr,
-            icon = icon_element,
-        ));
+        let epg_url = format!("{}/{}/epg.xml", base_url, id.source);
+        if !epg_urls.contains(&epg_url) {
+            epg_urls.push(epg_url);
+        }
+        entries.push_str(&m3u_entry(&base_url, manifest, &entry));
+    }
 
-        // Build category element if channel has a category
-        let category_element = entry
-            .channel
-            .category
-            .as_ref()
-            .map(|c| format!("    <category{}>{}</category>\n", lang_attr, escape_xml(c)))
-            .unwrap_or_default();
+    let last_modified = manifests
+        .keys()
+        .filter_map(|source| state.registry.source_updated_at(source))
+        .chain(state.preferences.updated_at())
+        .max();
 
-        // Use real programme data if available, otherwise generate placeholder
-        if entry.programmes.is_empty() {
-            // Use channel description if available, otherwise default
-            let desc = entry
-                .channel
-                .description
-                .as_deref()
-                .unwrap_or("Live broadcast");
+    let playlist = format!("#EXTM3U url-tvg=\"{}\"\n{}", epg_urls.join(","), entries);
 
-            // Generate 7 days of placeholder programming
-            for day in 0..7 {
-                let day_start = start + Duration::days(day);
-                let day_end = day_start + Duration::days(1);
+    conditional_response(
+        &headers,
+        Response::builder().header(header::CONTENT_TYPE, "audio/x-mpegurl"),
+        last_modified,
+        playlist,
+    )
+}
 
-                programmes.push_str(&format!(
-                    "  <programme start=\"{start}\" stop=\"{stop}\" channel=\"{id}\">\n\
-                     \x20   <title{lang}>{name}</title>\n\
-                     \x20   <desc{lang}>{desc}</desc>\n\
-                     {category}\
-                     \x20 </programme>\n",
-                    start = day_start.format("%Y%m%d%H%M%S %z"),
-                    stop = day_end.format("%Y%m%d%H%M%S %z"),
-                    id = escape_xml(&channel_id),
-                    category = category_element,
-                    name = escape_xml(channel_name),
-                    desc = escape_xml(desc),
-                    lang = lang_attr,
-                ));
-            }
-        } else {
-            // Use real programme data from metadata phase
-            for programme in &entry.programmes {
-                // Parse ISO 8601 timestamps and convert to XMLTV format
-                let start_formatted = format_xmltv_time(&programme.start_time);
-                let stop_formatted = format_xmltv_time(&programme.end_time);
+/**
+    List the recorded incidents of the watchdog, newest first (JSON).
+*/
+async fn list_incidents(State(state): State<AppState>) -> impl IntoResponse {
+    let json = serde_json::json!({
+        "incidents": state.incidents.list(),
+    });
 
-                // Build description element
+    (
+        [(header::CONTENT_TYPE, "application/json; charset=utf-8")],
+        json.to_string(),
+    )
+}
+
+/**
+    Get the favorite and hidden channels (JSON).
+*/
+async fn get_preferences(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let base_url = get_base_url(&headers);
+
+    let ids =
+        |ids: Vec<ChannelId>| -> Vec<String> { ids.iter().map(|id| id.to_string()).collect() };
+    let json = serde_json::json!({
+        "favorites": ids(state.preferences.favorites()),
+        "hidden": ids(state.preferences.hidden()),
+        "m3u": format!("{}/favorites.m3u", base_url),
+    });
+
+    (
+        [(header::CONTENT_TYPE, "application/json; charset=utf-8")],
+        json.to_string(),
+    )
+}
+
+/**
+    Replace the favorites with a list of "source:id" channels, in playlist order.
+*/
+async fn set_favorites(
+    State(state): State<AppState>,
+    Json(favorites): Json<Vec<String>>,
+) -> StatusCode {
+    let Some(ids) = favorites
+        .iter()
+        .map(|f| ChannelId::parse(f))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return StatusCode::BAD_REQUEST;
+    };
+    preferences_result(state.preferences.set_favorites(&ids))
+}
+
+async fn add_favorite(
+    State(state): State<AppState>,
+    Path((source_id, channel_id)): Path<(String, String)>,
+) -> StatusCode {
+    let id = ChannelId::new(&source_id, &channel_id);
+    if state.registry.get(&id).is_none() {
+        return StatusCode::NOT_FOUND;
+    }
+    preferences_result(state.preferences.set_favorite(&id, true))
+}
+
+async fn remove_favorite(
+    State(state): State<AppState>,
+    Path((source_id, channel_id)): Path<(String, String)>,
+) -> StatusCode {
+    let id = ChannelId::new(&source_id, &channel_id);
+    preferences_result(state.preferences.set_favorite(&id, false))
+}
+
+async fn hide_channel(
+    State(state): State<AppState>,
+    Path((source_id, channel_id)): Path<(String, String)>,
+) -> StatusCode {
+    let id = ChannelId::new(&source_id, &channel_id);
+    if state.registry.get(&id).is_none() {
+        return StatusCode::NOT_FOUND;
+    }
+    preferences_result(state.preferences.set_hidden(&id, true))
+}
+
+async fn unhide_channel(
+    State(state): State<AppState>,
+    Path((source_id, channel_id)): Path<(String, String)>,
+) -> StatusCode {
+    let id = ChannelId::new(&source_id, &channel_id);
+    preferences_result(state.preferences.set_hidden(&id, false))
+}
+
+/**
+    Set static "kid:key" pairs for a channel, used instead of requesting a
+    license. Keys may be hex, base64 or UUIDs, and are stored as hex.
+*/
+async fn set_channel_keys(
+    State(state): State<AppState>,
+    Path((source_id, channel_id)): Path<(String, String)>,
+    Json(keys): Json<Vec<String>>,
+) -> StatusCode {
+    let id = ChannelId::new(&source_id, &channel_id);
+    if state.registry.get(&id).is_none() {
+        return StatusCode::NOT_FOUND;
+    }
+    let keys = match keys
+        .iter()
+        .map(|key| KeyString::parse(key).map(|key| key.to_string()))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("[server] Rejected keys for {}: {}", id.to_string(), e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    set_channel_keys_and_restart(&state, &id, keys).await
+}
+
+async fn clear_channel_keys(
+    State(state): State<AppState>,
+    Path((source_id, channel_id)): Path<(String, String)>,
+) -> StatusCode {
+    let id = ChannelId::new(&source_id, &channel_id);
+    set_channel_keys_and_restart(&state, &id, Vec::new()).await
+}
+
+/**
+    Store the static keys of a channel, and stop its pipeline so that
+    the next request starts it again with the new keys.
+*/
+async fn set_channel_keys_and_restart(
+    state: &AppState,
+    id: &ChannelId,
+    keys: Vec<String>,
+) -> StatusCode {
+    let status = preferences_result(state.preferences.set_decryption_keys(id, keys));
+    for pipeline in state.pipeline_store.for_channel(id).await {
+        pipeline.stop().await;
+    }
+    status
+}
+
+/**
+    Set the segment duration and count of a channel, over those of its
+    manifest and output profile. Its pipelines are restarted with them.
+*/
+async fn set_channel_segments(
+    State(state): State<AppState>,
+    Path((source_id, channel_id)): Path<(String, String)>,
+    Json(segments): Json<SegmentConfig>,
+) -> StatusCode {
+    let id = ChannelId::new(&source_id, &channel_id);
+    if state.registry.get(&id).is_none() {
+        return StatusCode::NOT_FOUND;
+    }
+    if let Err(e) = segments.validate() {
+        eprintln!("[server] Rejected segments for {}: {}", id.to_string(), e);
+        return StatusCode::BAD_REQUEST;
+    }
+    set_channel_segments_and_restart(&state, &id, segments).await
+}
+
+async fn clear_channel_segments(
+    State(state): State<AppState>,
+    Path((source_id, channel_id)): Path<(String, String)>,
+) -> StatusCode {
+    let id = ChannelId::new(&source_id, &channel_id);
+    set_channel_segments_and_restart(&state, &id, SegmentConfig::default()).await
+}
+
+/**
+    Store the segmenting of a channel, and restart its running pipelines
+    with it in the background. Segmenting is fixed when a pipeline is
+    created, so they are replaced rather than only stopped.
+*/
+async fn set_channel_segments_and_restart(
+    state: &AppState,
+    id: &ChannelId,
+    segments: SegmentConfig,
+) -> StatusCode {
+    let status = preferences_result(state.preferences.set_segments(id, segments));
+    let mut profiles = Vec::new();
+    for (channel_id, profile, pipeline) in state.pipeline_store.all().await {
+        if channel_id == *id && pipeline.is_running().await {
+            profiles.push(profile);
+        }
+    }
+    state.pipeline_store.remove_channel(id).await;
+
+    for profile in profiles {
+        let state = state.clone();
+        let id = id.clone();
+        tokio::spawn(async move {
+            if let Err(status) = tune_channel(&state, &id, profile.as_deref()).await {
+                eprintln!(
+                    "[server] Failed to restart {} with new segments: {}",
+                    id.to_string(),
+                    status
+                );
+            }
+        });
+    }
+    status
+}
+
+/**
+    Query parameters of the channel set export.
+*/
+#[derive(Debug, Deserialize)]
+struct ExportChannelsQuery {
+    /// Comma-separated "source:id" channels, all channels if left out
+    channels: Option<String>,
+    /// Include the decryption keys of the channels, left out by default
+    #[serde(default)]
+    include_keys: bool,
+}
+
+/**
+    Export the preferences of channels (favorites, hidden channels and
+    segmenting) as a channel set, to import into another installation.
+
+    Decryption keys are only exported with `?include_keys=true`, so that
+    they aren't handed to anyone who can reach the server by accident.
+*/
+async fn export_channels(
+    State(state): State<AppState>,
+    Query(query): Query<ExportChannelsQuery>,
+) -> Response {
+    let ids = match query.channels.as_deref().map(|channels| {
+        channels
+            .split(',')
+            .filter(|c| !c.is_empty())
+            .map(ChannelId::parse)
+            .collect::<Option<Vec<_>>>()
+    }) {
+        Some(Some(ids)) => Some(ids),
+        Some(None) => return StatusCode::BAD_REQUEST.into_response(),
+        None => None,
+    };
+
+    let mut set = state.preferences.export(ids.as_deref());
+    if !query.include_keys {
+        set.decryption_keys.clear();
+    }
+    let json = match serde_json::to_string_pretty(&set) {
+        Ok(json) => json,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    (
+        [(header::CONTENT_TYPE, "application/json; charset=utf-8")],
+        json,
+    )
+        .into_response()
+}
+
+/**
+    Query parameters of the channel set import.
+*/
+#[derive(Debug, Deserialize)]
+struct ImportChannelsQuery {
+    #[serde(default)]
+    conflicts: ImportConflicts,
+}
+
+/**
+    Import a channel set exported by another installation. Channels with
+    preferences here already keep them, unless `?conflicts=replace` is given.
+    Pipelines of the channels that changed are removed, so that they start
+    again with the imported keys and segmenting on the next request.
+*/
+async fn import_channels(
+    State(state): State<AppState>,
+    Query(query): Query<ImportChannelsQuery>,
+    Json(mut set): Json<ChannelSet>,
+) -> Response {
+    if let Some(invalid) = set
+        .channels()
+        .into_iter()
+        .find(|c| ChannelId::parse(c).is_none())
+    {
+        eprintln!("[server] Rejected import of invalid channel {:?}", invalid);
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    for (channel, keys) in set.decryption_keys.iter_mut() {
+        match keys
+            .iter()
+            .map(|key| KeyString::parse(key).map(|key| key.to_string()))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(parsed) => *keys = parsed,
+            Err(e) => {
+                eprintln!("[server] Rejected imported keys for {}: {}", channel, e);
+                return StatusCode::BAD_REQUEST.into_response();
+            }
+        }
+    }
+    for (channel, segments) in &set.segments {
+        if let Err(e) = segments.validate() {
+            eprintln!("[server] Rejected imported segments for {}: {}", channel, e);
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    }
+
+    let summary = match state.preferences.import(&set, query.conflicts) {
+        Ok(summary) => summary,
+        Err(e) => {
+            eprintln!("[server] Failed to import channels: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    for channel in summary.added.iter().chain(&summary.replaced) {
+        if let Some(id) = ChannelId::parse(channel) {
+            state.pipeline_store.remove_channel(&id).await;
+        }
+    }
+    println!(
+        "[server] Imported channels: {} added, {} replaced, {} kept",
+        summary.added.len(),
+        summary.replaced.len(),
+        summary.kept.len()
+    );
+
+    let json = serde_json::json!(summary);
+    (
+        [(header::CONTENT_TYPE, "application/json; charset=utf-8")],
+        json.to_string(),
+    )
+        .into_response()
+}
+
+/**
+    Map the result of changing preferences to a response status.
+    The change is kept in memory even if saving it failed.
+*/
+fn preferences_result(result: anyhow::Result<()>) -> StatusCode {
+    match result {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(e) => {
+            eprintln!("[server] Failed to save preferences: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/**
+    Generate XMLTV EPG data for channels from a specific source.
+*/
+async fn source_epg(
+    State(state): State<AppState>,
+    Path(source_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    // Wait for source to be ready
+    wait_for_source_ready(&state.registry, &source_id).await?;
+
+    let manifest = state
+        .manifest_store
+        .get(&source_id)
+        .await
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let channels = state.registry.list_by_source(&source_id);
+    if channels.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let base_url = get_base_url(&headers);
+
+    let now = Utc::now();
+    let start_of_day = now.date_naive().and_hms_opt(0, 0, 0).unwrap();
+    let start = start_of_day.and_utc();
+
+    let mut channel_elements = String::new();
+    let mut programmes = String::new();
+
+    // Language attribute for titles/descriptions if configured
+    let lang_attr = manifest
+        .source
+        .language
+        .as_ref()
+        .map(|l| format!(" lang=\"{}\"", escape_xml(l)))
+        .unwrap_or_default();
+
+    for entry in &channels {
+        // Include all channels - EPG data comes from metadata phase, not content phase
+
+        let channel_name = entry.channel.name.as_deref().unwrap_or(&entry.channel.id);
+        let channel_id = format!("{}:{}", source_id, entry.channel.id);
+
+        // Use local image URL if channel has an image
+        let icon_element = if entry.channel.image.is_some() {
+            format!(
+                "    <icon src=\"{}/{}/{}/image\"/>\n",
+                base_url, source_id, entry.channel.id
+            )
+        } else {
+            String::new()
+        };
+
+        channel_elements.push_str(&format!(
+            "  <channel id=\"{id}\">\n\
+             \x20   <display-name{lang}>{name}</display-name>\n\
+             {icon}\
+             \x20 </channel>\n",
+            id = escape_xml(&channel_id),
+            name = escape_xml(channel_name),
+            lang = lang_attr,
+            icon = icon_element,
+        ));
+
+        // Build category element if channel has a category
+        let category_element = entry
+            .channel
+            .category
+            .as_ref()
+            .map(|c| format!("    <category{}>{}</category>\n", lang_attr, escape_xml(c)))
+            .unwrap_or_default();
+
+        // Use real programme data if available, otherwise generate placeholder
+        if entry.programmes.is_empty() {
+            // Use channel description if available, otherwise default
+            let desc = entry
+                .channel
+                .description
+                .as_deref()
+                .unwrap_or("Live broadcast");
+
+            // Generate 7 days of placeholder programming
+            for day in 0..7 {
+                let day_start = start + Duration::days(day);
+                let day_end = day_start + Duration::days(1);
+
+                programmes.push_str(&format!(
+                    "  <programme start=\"{start}\" stop=\"{stop}\" channel=\"{id}\">\n\
+                     \x20   <title{lang}>{name}</title>\n\
+                     \x20   <desc{lang}>{desc}</desc>\n\
+                     {category}\
+                     \x20 </programme>\n",
+                    start = day_start.format("%Y%m%d%H%M%S %z"),
+                    stop = day_end.format("%Y%m%d%H%M%S %z"),
+                    id = escape_xml(&channel_id),
+                    category = category_element,
+                    name = escape_xml(channel_name),
+                    desc = escape_xml(desc),
+                    lang = lang_attr,
+                ));
+            }
+        } else {
+            // Use real programme data from metadata phase
+            for programme in &entry.programmes {
+                // Parse ISO 8601 timestamps and convert to XMLTV format
+                let start_formatted = format_xmltv_time(&programme.start_time);
+                let stop_formatted = format_xmltv_time(&programme.end_time);
+
+                // Build description element
                 let desc_element = programme
                     .description
                     .as_ref()
@@ -527,7 +1320,18 @@ async fn source_epg(
         programmes = programmes,
     );
 
-    Ok(([(header::CONTENT_TYPE, "application/xml")], xml))
+    // Placeholder programmes move along with the current day
+    let last_modified = state
+        .registry
+        .source_updated_at(&source_id)
+        .map(|updated_at| updated_at.max(SystemTime::from(start)));
+
+    Ok(conditional_response(
+        &headers,
+        Response::builder().header(header::CONTENT_TYPE, "application/xml"),
+        last_modified,
+        xml,
+    ))
 }
 
 /**
@@ -620,8 +1424,11 @@ async fn resolve_channel_content(
                 StatusCode::NOT_FOUND
             })?;
 
-            // Run content phase for this channel using the existing browser
-            match source::resolve_channel_content(&manifest, &entry.channel, &tab).await {
+            // Run content phase for this channel using the existing browser and session
+            let session = state.manifest_store.get_session(source_id).await;
+            match source::resolve_channel_content(&manifest, &entry.channel, session.as_ref(), &tab)
+                .await
+            {
                 Ok(stream_info) => {
                     println!(
                         "[server] Content resolved for {}: {}",
@@ -632,9 +1439,10 @@ async fn resolve_channel_content(
                     // Update registry
                     state.registry.update_stream_info(id, stream_info.clone());
                     state.registry.mark_channel_resolved(id);
+                    state.registry.clear_needs_attention(source_id);
 
-                    // Update pipeline if it exists (for refresh case)
-                    if let Some(pipeline) = state.pipeline_store.get(id).await {
+                    // Update pipelines if they exist (for refresh case)
+                    for pipeline in state.pipeline_store.for_channel(id).await {
                         pipeline.update_stream_info(stream_info.clone()).await;
                         pipeline.stop().await;
                     }
@@ -647,6 +1455,9 @@ async fn resolve_channel_content(
                         id.to_string(),
                         e
                     );
+                    if let Some(challenge) = e.downcast_ref::<AntibotChallenge>() {
+                        state.registry.set_needs_attention(source_id, challenge);
+                    }
                     state.registry.set_error(id, e.to_string());
                     state.registry.mark_channel_failed(id, &e.to_string());
                     Err(StatusCode::SERVICE_UNAVAILABLE)
@@ -662,123 +1473,656 @@ async fn resolve_channel_content(
     }
 }
 
+/**
+    Refresh an expired stream with the refresh call of its access token.
+    Returns None if the stream has no refresh call or refreshing failed.
+*/
+async fn refresh_stream_token(
+    state: &AppState,
+    id: &ChannelId,
+    stream_info: &StreamInfo,
+    network: &Network,
+) -> Option<StreamInfo> {
+    stream_info.token.as_ref()?.refresh.as_ref()?;
+
+    let refreshed = match network.http_client() {
+        Ok(client) => manifest::refresh_token(stream_info, &client).await,
+        Err(e) => Err(e),
+    };
+    match refreshed {
+        Ok(stream_info) => {
+            println!("[server] Refreshed access token for {}", id.to_string());
+            state.registry.update_stream_info(id, stream_info.clone());
+            for pipeline in state.pipeline_store.for_channel(id).await {
+                pipeline.update_stream_info(stream_info.clone()).await;
+                pipeline.stop().await;
+            }
+            Some(stream_info)
+        }
+        Err(e) => {
+            eprintln!(
+                "[server] Token refresh failed for {}, resolving content again: {}",
+                id.to_string(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/**
+    Get the network settings used for upstream fetches of a source.
+*/
+async fn source_network(state: &AppState, source_id: &str) -> Result<Network, StatusCode> {
+    match state.manifest_store.get(source_id).await {
+        Some(manifest) => Network::for_manifest(&manifest).map_err(|e| {
+            eprintln!("[server] Invalid network settings for {}: {}", source_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }),
+        None => Ok(Network::default()),
+    }
+}
+
+/**
+    Get usable stream info for a channel - either from cache, on-demand,
+    or refreshed when expired or after an upstream auth error.
+*/
+async fn current_stream_info(
+    state: &AppState,
+    id: &ChannelId,
+    existing: Option<&StreamInfo>,
+    network: &Network,
+    auth_error: bool,
+) -> Result<StreamInfo, StatusCode> {
+    let source_id = id.source.as_str();
+
+    let Some(existing) = existing else {
+        // No stream info - resolve on-demand
+        return resolve_channel_content(state, id, source_id).await;
+    };
+
+    if !state.registry.is_stream_expired(id) && !auth_error {
+        // Use existing valid stream info
+        return Ok(existing.clone());
+    }
+
+    if auth_error {
+        println!(
+            "[server] Upstream auth error for {}, refreshing...",
+            id.to_string()
+        );
+    } else {
+        println!(
+            "[server] Stream info expired for {}, refreshing...",
+            id.to_string()
+        );
+    }
+
+    // A token refresh is a single request, only fall back to the browser if it fails
+    match refresh_stream_token(state, id, existing, network).await {
+        Some(stream_info) => Ok(stream_info),
+        None => {
+            // Reset content state so we can re-resolve
+            state.registry.reset_channel_content_state(id);
+
+            resolve_channel_content(state, id, source_id).await
+        }
+    }
+}
+
+/**
+    Make sure a channel's pipeline is running and has produced its first segment.
+
+    Waits for the source to be ready, refreshes stream info when expired,
+    then starts the pipeline lazily. Used by both playlist requests
+    and explicit tune requests.
+*/
+async fn tune_channel(
+    state: &AppState,
+    id: &ChannelId,
+    requested_profile: Option<&str>,
+) -> Result<Arc<ChannelPipeline>, StatusCode> {
+    let source_id = id.source.as_str();
+
+    // Wait for source to be ready
+    wait_for_source_ready(&state.registry, source_id).await?;
+
+    // Check if channel exists
+    let entry = state.registry.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    let profile = channel_profile(state, &entry, requested_profile)?;
+
+    // Check if pipeline exists and needs refresh due to auth error
+    let pipeline_needs_refresh =
+        if let Some(pipeline) = state.pipeline_store.get(id, profile.as_deref()).await {
+            pipeline.needs_refresh()
+        } else {
+            false
+        };
+
+    // Upstream fetches follow the proxy and DNS overrides of the channel's source
+    let network = source_network(state, source_id).await?;
+
+    let stream_info = current_stream_info(
+        state,
+        id,
+        entry.stream_info.as_ref(),
+        &network,
+        pipeline_needs_refresh,
+    )
+    .await?;
+
+    // Get or create pipeline for this channel
+    let pipeline = state
+        .pipeline_store
+        .get_or_create(
+            id,
+            profile.as_deref(),
+            &stream_info,
+            &entry.channel,
+            state.preferences.segments(id),
+            &network,
+        )
+        .await
+        .map_err(|e| {
+            eprintln!(
+                "[server] Failed to create pipeline for {}: {}",
+                id.to_string(),
+                e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    // Keys supplied through the API apply from the next start of the pipeline
+    pipeline.set_static_keys(state.preferences.decryption_keys(id));
+
+    // Ensure pipeline is running
+    pipeline.ensure_running().await.map_err(|e| {
+        eprintln!(
+            "[server] Failed to start pipeline for {}: {}",
+            id.to_string(),
+            e
+        );
+        StatusCode::SERVICE_UNAVAILABLE
+    })?;
+
+    // Wait for first segment
+    pipeline.wait_for_ready().await.map_err(|e| {
+        eprintln!(
+            "[server] Timeout waiting for pipeline {}: {}",
+            id.to_string(),
+            e
+        );
+        StatusCode::GATEWAY_TIMEOUT
+    })?;
+
+    pipeline.record_activity();
+
+    Ok(pipeline)
+}
+
+/**
+    Query parameters selecting the output profile of a channel's stream.
+*/
+#[derive(Debug, Deserialize)]
+struct ProfileQuery {
+    profile: Option<String>,
+}
+
+/**
+    Get the output profile to serve a channel with: the one asked for
+    in the request if any, or the one configured for the channel.
+*/
+fn channel_profile(
+    state: &AppState,
+    entry: &ChannelEntry,
+    requested: Option<&str>,
+) -> Result<Option<String>, StatusCode> {
+    match requested {
+        Some(name) if !state.pipeline_store.has_profile(name) => Err(StatusCode::NOT_FOUND),
+        Some(name) => Ok(Some(name.to_string())),
+        None => Ok(entry.channel.profile.clone()),
+    }
+}
+
+/**
+    Characters left unencoded in query values: the unreserved characters of RFC 3986
+*/
+const QUERY_VALUE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/**
+    Format the query parameter selecting an output profile.
+*/
+fn profile_param(profile: &str) -> String {
+    format!("profile={}", utf8_percent_encode(profile, QUERY_VALUE))
+}
+
+/**
+    Point the segment URIs of a playlist at the given output profile,
+    since players request segments without the playlist's query string.
+*/
+fn with_profile_query(playlist: &str, profile: &str) -> String {
+    let mut out = String::with_capacity(playlist.len());
+    for line in playlist.lines() {
+        out.push_str(line);
+        if !line.is_empty() && !line.starts_with('#') {
+            out.push_str(if line.contains('?') { "&" } else { "?" });
+            out.push_str(&profile_param(profile));
+        }
+        out.push('\n');
+    }
+    out
+}
+
 /**
     Serve the HLS playlist for a channel, starting the pipeline if needed.
+
+    If the channel exists but its pipeline can't run (auth failure, source
+    offline, ...), a looping slate is served instead of an error, so that
+    clients keep the channel open and pick it up again once it recovers.
+    The slate also stands in for the segments of ad breaks on channels
+    configured to replace them, continuing the channel's playlist.
+
+    Passthrough channels redirect to their proxied upstream manifest instead,
+    and are not affected by output profiles.
 */
 async fn stream_playlist(
     State(state): State<AppState>,
     Path((source_id, channel_id)): Path<(String, String)>,
+    Query(query): Query<ProfileQuery>,
+    headers: HeaderMap,
 ) -> Result<Response, StatusCode> {
-    // Wait for source to be ready
-    wait_for_source_ready(&state.registry, &source_id).await?;
-
     let id = ChannelId::new(&source_id, &channel_id);
-
-    // Check if discovery has expired for this source - if so, re-run discovery only
-    if state.registry.is_discovery_expired(&source_id) {
-        println!(
-            "[server] Discovery expired for source '{}', refreshing...",
-            source_id
+    if is_passthrough(&state, &id).await {
+        let network = source_network(&state, &source_id).await?;
+        let stream_info = passthrough_stream_info(&state, &id, &network).await?;
+        let manifest_url = reqwest::Url::parse(&stream_info.manifest_url).map_err(|e| {
+            eprintln!(
+                "[server] Invalid manifest URL for {}: {}",
+                id.to_string(),
+                e
+            );
+            StatusCode::BAD_GATEWAY
+        })?;
+        let location = format!(
+            "{}/{}/{}/proxy/{}",
+            get_base_url(&headers),
+            source_id,
+            channel_id,
+            passthrough::proxy_path(&manifest_url)
         );
+        return Ok(Response::builder()
+            .status(StatusCode::FOUND)
+            .header(header::LOCATION, location)
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(header::CACHE_CONTROL, "no-cache")
+            .body(Body::empty())
+            .unwrap());
+    }
 
-        if let Some(manifest) = state.manifest_store.get(&source_id).await
-            && let Some(browser) = state.manifest_store.get_browser(&source_id).await
-        {
-            match source::run_source_discovery_only(&manifest, &browser).await {
-                Ok(result) => {
-                    state.registry.register_source(
-                        &result.source_id,
-                        result.channels,
-                        result.discovery_expires_at,
-                    );
-                    println!("[server] Refreshed source '{}'", source_id);
-                }
-                Err(e) => {
-                    eprintln!("[server] Failed to refresh source '{}': {}", source_id, e);
-                    // Continue with existing data
+    let profile = query.profile.as_deref();
+    state.registry.record_watch(&id);
+
+    // A channel that failed to tune is only retried once its backoff expires,
+    // rather than on every playlist poll of clients falling back from it
+    let backing_off = state.registry.is_tune_backing_off(&id);
+    let tuned = if backing_off {
+        Err(StatusCode::SERVICE_UNAVAILABLE)
+    } else {
+        tune_channel(&state, &id, profile).await
+    };
+    let pipeline = match tuned {
+        Ok(pipeline) => {
+            state.registry.clear_tune_failures(&id);
+            pipeline
+        }
+        Err(status) if status != StatusCode::NOT_FOUND => {
+            if !backing_off {
+                state.registry.record_tune_failure(&id);
+            }
+            // Play the same channel from another source, if one has it
+            if let Some(alternate) = state.registry.alternates_of(&id).first() {
+                println!(
+                    "[server] Falling back to {} for {} ({})",
+                    alternate.to_string(),
+                    id.to_string(),
+                    status
+                );
+                let mut location = format!(
+                    "{}/{}/{}/playlist.m3u8",
+                    get_base_url(&headers),
+                    alternate.source,
+                    alternate.id
+                );
+                if let Some(profile) = profile {
+                    location = format!("{}?{}", location, profile_param(profile));
                 }
+                return Ok(Response::builder()
+                    .status(StatusCode::FOUND)
+                    .header(header::LOCATION, location)
+                    .header(header::CACHE_CONTROL, "no-cache")
+                    .body(Body::empty())
+                    .unwrap());
             }
+            let slate = state.pipeline_store.slate().ok_or(status)?;
+            println!("[server] Serving slate for {} ({})", id.to_string(), status);
+            return Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/vnd.apple.mpegurl")
+                .header(header::CACHE_CONTROL, "no-cache")
+                .body(Body::from(slate.playlist()))
+                .unwrap());
         }
+        Err(status) => return Err(status),
+    };
+
+    // Serve the playlist, continued across pipeline restarts
+    let playlist = pipeline
+        .render_playlist(state.pipeline_store.slate())
+        .map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                StatusCode::NOT_FOUND
+            } else {
+                eprintln!(
+                    "[server] Error reading playlist for {}: {}",
+                    id.to_string(),
+                    e
+                );
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })?;
+    let playlist = match profile {
+        Some(profile) => with_profile_query(&playlist, profile),
+        None => playlist,
+    };
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/vnd.apple.mpegurl")
+        .body(Body::from(playlist))
+        .unwrap())
+}
+
+/**
+    Check whether a channel is served in passthrough mode, waiting for its source.
+*/
+async fn is_passthrough(state: &AppState, id: &ChannelId) -> bool {
+    wait_for_source_ready(&state.registry, &id.source)
+        .await
+        .is_ok()
+        && state
+            .registry
+            .get(id)
+            .is_some_and(|entry| entry.channel.passthrough)
+}
+
+/**
+    Get the stream info of a passthrough channel. No pipeline is started,
+    the upstream stream is proxied to clients as is.
+*/
+async fn passthrough_stream_info(
+    state: &AppState,
+    id: &ChannelId,
+    network: &Network,
+) -> Result<StreamInfo, StatusCode> {
+    let entry = state.registry.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    current_stream_info(state, id, entry.stream_info.as_ref(), network, false).await
+}
+
+/**
+    Proxy an upstream manifest, segment or key of a passthrough channel.
+
+    Only the origin of the channel's manifest and origins referenced by its
+    rewritten manifests are fetched from, anything else is refused. Requests
+    go out with the channel's upstream headers, and manifests are rewritten
+    so that players keep fetching through here. Responses allow any origin,
+    so browser players can use streams whose CDN lacks CORS.
+*/
+async fn passthrough_proxy(
+    State(state): State<AppState>,
+    Path((source_id, channel_id, upstream)): Path<(String, String, String)>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let id = ChannelId::new(&source_id, &channel_id);
+
+    if !is_passthrough(&state, &id).await {
+        return Err(StatusCode::NOT_FOUND);
     }
+    let url =
+        passthrough::upstream_url(&upstream, query.as_deref()).ok_or(StatusCode::BAD_REQUEST)?;
 
-    // Check if channel exists
-    let entry = state.registry.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+    let network = source_network(&state, &source_id).await?;
+    let stream_info = passthrough_stream_info(&state, &id, &network).await?;
+    let manifest_url = reqwest::Url::parse(&stream_info.manifest_url).map_err(|e| {
+        eprintln!(
+            "[server] Invalid manifest URL for {}: {}",
+            id.to_string(),
+            e
+        );
+        StatusCode::BAD_GATEWAY
+    })?;
+
+    // Upstream headers carry credentials, so never send them anywhere else
+    let key = id.to_string();
+    if !state.upstream_origins.is_allowed(&key, &manifest_url, &url) {
+        eprintln!(
+            "[server] Refusing passthrough request for {} to {}",
+            key,
+            url.origin().ascii_serialization()
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let client = network.direct_client().map_err(|e| {
+        eprintln!("[server] Invalid network settings for {}: {}", source_id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let mut request = client.get(url.clone());
+    for (name, value) in request_headers(&stream_info.headers, url.as_str()) {
+        request = request.header(name, value);
+    }
+    if let Some(range) = headers.get(header::RANGE) {
+        request = request.header(header::RANGE, range.clone());
+    }
+
+    let response = request.send().await.map_err(|e| {
+        eprintln!(
+            "[server] Passthrough request for {} failed: {}",
+            id.to_string(),
+            e
+        );
+        StatusCode::BAD_GATEWAY
+    })?;
+
+    let status =
+        StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    if !status.is_success() {
+        eprintln!(
+            "[server] Upstream returned {} for {} ({})",
+            status,
+            id.to_string(),
+            url
+        );
+    }
+
+    let final_url = response.url().clone();
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    let mut builder = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, &content_type)
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*");
+    for name in [header::CONTENT_RANGE, header::ACCEPT_RANGES] {
+        if let Some(value) = response.headers().get(&name) {
+            builder = builder.header(name, value.clone());
+        }
+    }
+
+    // Segments are streamed, only manifests need to be read in full
+    if !status.is_success() || !passthrough::may_be_manifest(&content_type, &final_url) {
+        return Ok(builder
+            .body(Body::from_stream(response.bytes_stream()))
+            .unwrap());
+    }
+    let body = response
+        .bytes()
+        .await
+        .map_err(|_| StatusCode::BAD_GATEWAY)?;
+
+    let proxy_base = format!(
+        "{}/{}/{}/proxy",
+        get_base_url(&headers),
+        source_id,
+        channel_id
+    );
+    if let Some(manifest) =
+        passthrough::rewrite_manifest(&body, &content_type, &final_url, &proxy_base)
+    {
+        state.upstream_origins.allow(&key, manifest.origins);
+
+        // Relative references resolve against where the upstream redirected to
+        if final_url != url {
+            state
+                .upstream_origins
+                .allow(&key, [final_url.origin().ascii_serialization()]);
+            let location = format!("{}/{}", proxy_base, passthrough::proxy_path(&final_url));
+            return Ok(Response::builder()
+                .status(StatusCode::FOUND)
+                .header(header::LOCATION, location)
+                .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+                .body(Body::empty())
+                .unwrap());
+        }
+        return Ok(builder
+            .header(header::CACHE_CONTROL, "no-cache")
+            .body(Body::from(manifest.body))
+            .unwrap());
+    }
+
+    Ok(builder.body(Body::from(body)).unwrap())
+}
 
-    // Check if pipeline exists and needs refresh due to auth error
-    let pipeline_needs_refresh = if let Some(pipeline) = state.pipeline_store.get(&id).await {
-        pipeline.needs_refresh()
+/**
+    Tune to a channel - start its pipeline without serving the playlist (JSON).
+*/
+async fn tune(
+    State(state): State<AppState>,
+    Path((source_id, channel_id)): Path<(String, String)>,
+    Query(query): Query<ProfileQuery>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    let id = ChannelId::new(&source_id, &channel_id);
+    if is_passthrough(&state, &id).await {
+        let network = source_network(&state, &source_id).await?;
+        passthrough_stream_info(&state, &id, &network).await?;
     } else {
-        false
-    };
+        state.registry.record_watch(&id);
+        tune_channel(&state, &id, query.profile.as_deref()).await?;
+    }
 
-    // Resolve stream info - either from cache, on-demand, or refresh
-    let stream_info = if let Some(ref existing) = entry.stream_info {
-        // Stream info exists - check if it needs refresh
-        if state.registry.is_stream_expired(&id) || pipeline_needs_refresh {
-            if pipeline_needs_refresh {
-                println!(
-                    "[server] Pipeline auth error for {}, refreshing...",
-                    id.to_string()
-                );
-            } else {
-                println!(
-                    "[server] Stream info expired for {}, refreshing...",
-                    id.to_string()
-                );
-            }
+    let base_url = get_base_url(&headers);
+    let mut playlist = format!("{}/{}/{}/playlist.m3u8", base_url, source_id, channel_id);
+    if let Some(ref profile) = query.profile {
+        playlist = format!("{}?{}", playlist, profile_param(profile));
+    }
 
-            // Reset content state so we can re-resolve
-            state.registry.reset_channel_content_state(&id);
+    let json = serde_json::json!({
+        "id": id.to_string(),
+        "status": "running",
+        "playlist": playlist,
+    });
 
-            resolve_channel_content(&state, &id, &source_id).await?
-        } else {
-            // Use existing valid stream info
-            existing.clone()
-        }
-    } else {
-        // No stream info - resolve on-demand
-        resolve_channel_content(&state, &id, &source_id).await?
-    };
+    Ok((
+        [(header::CONTENT_TYPE, "application/json; charset=utf-8")],
+        json.to_string(),
+    ))
+}
 
-    // Get or create pipeline for this channel
-    let pipeline = state
+/**
+    Request metrics endpoint, in the Prometheus text format.
+*/
+async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let channels: Vec<_> = state
         .pipeline_store
-        .get_or_create(&id, &stream_info)
+        .all()
         .await
-        .map_err(|e| {
-            eprintln!(
-                "[server] Failed to create pipeline for {}: {}",
-                id.to_string(),
-                e
-            );
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+        .into_iter()
+        .map(|(id, profile, pipeline)| {
+            let channel = match profile {
+                Some(profile) => format!("{}@{}", id.to_string(), profile),
+                None => id.to_string(),
+            };
+            (channel, pipeline.quality())
+        })
+        .collect();
 
-    // Ensure pipeline is running
-    pipeline.ensure_running().await.map_err(|e| {
-        eprintln!(
-            "[server] Failed to start pipeline for {}: {}",
-            id.to_string(),
-            e
-        );
+    let mut body = state.access_log.render_metrics();
+    body.push_str(&quality::render_metrics(&channels));
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+/**
+    Liveness endpoint - responds as long as the process is serving requests.
+*/
+async fn healthz() -> impl IntoResponse {
+    "ok"
+}
+
+/**
+    Readiness endpoint - checks that at least one channel has been discovered,
+    that the segment directory is writable, and that a browser is reachable.
+    Responds with 503 and the individual check results if any check fails.
+*/
+async fn readyz(State(state): State<AppState>) -> impl IntoResponse {
+    let channels_discovered = !state.registry.is_empty();
+
+    let probe = state.pipeline_store.base_output_dir().join(".readyz");
+    let segments_writable = tokio::fs::write(&probe, b"ok").await.is_ok();
+    let _ = tokio::fs::remove_file(&probe).await;
+
+    let browser_reachable = state.manifest_store.any_browser_reachable().await;
+
+    let ready = channels_discovered && segments_writable && browser_reachable;
+    let status = if ready {
+        StatusCode::OK
+    } else {
         StatusCode::SERVICE_UNAVAILABLE
-    })?;
+    };
 
-    // Wait for first segment
-    pipeline.wait_for_ready().await.map_err(|e| {
-        eprintln!(
-            "[server] Timeout waiting for pipeline {}: {}",
-            id.to_string(),
-            e
-        );
-        StatusCode::GATEWAY_TIMEOUT
-    })?;
+    let json = serde_json::json!({
+        "ready": ready,
+        "checks": {
+            "channels_discovered": channels_discovered,
+            "segments_writable": segments_writable,
+            "browser_reachable": browser_reachable,
+        },
+    });
 
-    pipeline.record_activity();
+    (
+        status,
+        [(header::CONTENT_TYPE, "application/json; charset=utf-8")],
+        json.to_string(),
+    )
+}
 
-    // Serve the playlist file
-    let playlist_path = pipeline.output_dir().join("playlist.m3u8");
-    serve_file(&playlist_path, "application/vnd.apple.mpegurl").await
+/**
+    Serve the slate segment, which playlists of all channels refer to.
+*/
+async fn slate_segment(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let slate = state.pipeline_store.slate().ok_or(StatusCode::NOT_FOUND)?;
+    serve_file(slate.segment_path(), "video/mp2t", &headers).await
 }
 
 /**
@@ -787,19 +2131,23 @@ async fn stream_playlist(
 async fn stream_segment(
     State(state): State<AppState>,
     Path((source_id, channel_id, filename)): Path<(String, String, String)>,
+    Query(query): Query<ProfileQuery>,
+    headers: HeaderMap,
 ) -> Result<Response, StatusCode> {
     let id = ChannelId::new(&source_id, &channel_id);
+    let entry = state.registry.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+    let profile = channel_profile(&state, &entry, query.profile.as_deref())?;
 
     let pipeline = state
         .pipeline_store
-        .get(&id)
+        .get(&id, profile.as_deref())
         .await
         .ok_or(StatusCode::NOT_FOUND)?;
 
     pipeline.record_activity();
 
     let segment_path = pipeline.output_dir().join(&filename);
-    serve_file(&segment_path, "video/mp2t").await
+    serve_file(&segment_path, "video/mp2t", &headers).await
 }
 
 /**
@@ -817,6 +2165,15 @@ async fn channel_info(
     let entry = state.registry.get(&id).ok_or(StatusCode::NOT_FOUND)?;
 
     let stream_info = entry.stream_info.as_ref();
+    let profile = entry.channel.profile.as_deref();
+    let (stream_params, quality, ad_breaks) = match state.pipeline_store.get(&id, profile).await {
+        Some(pipeline) => (
+            pipeline.stream_params(),
+            Some(pipeline.quality()),
+            pipeline.ad_breaks(),
+        ),
+        None => (None, None, Vec::new()),
+    };
 
     let json = serde_json::json!({
         "id": id.to_string(),
@@ -826,7 +2183,29 @@ async fn channel_info(
         "image": entry.channel.image,
         "manifest_url": stream_info.map(|s| &s.manifest_url),
         "license_url": stream_info.and_then(|s| s.license_url.as_ref()),
+        "drm_device": stream_info.and_then(|s| s.drm_device.as_ref()),
+        "static_keys": !state.preferences.decryption_keys(&id).is_empty()
+            || stream_info.is_some_and(|s| !s.decryption_keys.is_empty()),
         "expires_at": stream_info.and_then(|s| s.expires_at),
+        "segments": state.preferences.segments(&id).or(entry.channel.segments),
+        "usage": state.registry.usage(&id),
+        "stream": stream_params.map(|p| serde_json::json!({
+            "video": p.video,
+            "audio": p.audio,
+        })),
+        "quality": quality,
+        "ad_breaks": ad_breaks,
+        "passthrough": entry.channel.passthrough,
+        "standby": entry.channel.standby,
+        "profile": profile,
+        "needs_attention": state.registry.needs_attention(&source_id),
+        "primary": state.registry.primary_of(&id).map(|p| p.to_string()),
+        "alternates": state
+            .registry
+            .alternates_of(&id)
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>(),
         "error": entry.last_error,
     });
 
@@ -837,10 +2216,52 @@ async fn channel_info(
 }
 
 /**
-    Helper to serve a file
+    Parse a `Range` header into an inclusive byte range of a file.
+
+    - Returns Ok(None) if the whole file should be served, either because
+      the header is malformed or asks for multiple ranges
+    - Returns Err(()) if the range can't be satisfied
+*/
+fn parse_range(value: &str, len: u64) -> Result<Option<(u64, u64)>, ()> {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return Ok(None);
+    };
+
+    let (start, end) = match (start.parse::<u64>().ok(), end.parse::<u64>().ok()) {
+        // Suffix range, the last N bytes
+        (None, Some(suffix)) if start.is_empty() => {
+            if suffix == 0 || len == 0 {
+                return Err(());
+            }
+            (len.saturating_sub(suffix), len - 1)
+        }
+        (Some(start), None) if end.is_empty() => (start, len.saturating_sub(1)),
+        (Some(start), Some(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+        _ => return Ok(None),
+    };
+
+    if start >= len {
+        return Err(());
+    }
+    Ok(Some((start, end)))
+}
+
+/**
+    Helper to serve a file, honoring single byte ranges so that
+    players can seek in long files without downloading all of them.
 */
-async fn serve_file(path: &std::path::Path, content_type: &str) -> Result<Response, StatusCode> {
-    let file = tokio::fs::File::open(path).await.map_err(|e| {
+async fn serve_file(
+    path: &std::path::Path,
+    content_type: &str,
+    headers: &HeaderMap,
+) -> Result<Response, StatusCode> {
+    let mut file = tokio::fs::File::open(path).await.map_err(|e| {
         if e.kind() == std::io::ErrorKind::NotFound {
             StatusCode::NOT_FOUND
         } else {
@@ -848,15 +2269,56 @@ async fn serve_file(path: &std::path::Path, content_type: &str) -> Result<Respon
             StatusCode::INTERNAL_SERVER_ERROR
         }
     })?;
+    let len = file
+        .metadata()
+        .await
+        .map_err(|e| {
+            eprintln!("[server] Error reading file {:?}: {}", path, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .len();
 
-    let stream = ReaderStream::new(file);
-    let body = Body::from_stream(stream);
+    let range = match headers.get(header::RANGE).and_then(|v| v.to_str().ok()) {
+        Some(value) => parse_range(value, len),
+        None => Ok(None),
+    };
 
-    Ok(Response::builder()
-        .status(StatusCode::OK)
+    let response = Response::builder()
         .header(header::CONTENT_TYPE, content_type)
-        .body(body)
-        .unwrap())
+        .header(header::ACCEPT_RANGES, "bytes");
+
+    match range {
+        Ok(Some((start, end))) => {
+            file.seek(std::io::SeekFrom::Start(start))
+                .await
+                .map_err(|e| {
+                    eprintln!("[server] Error seeking file {:?}: {}", path, e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+            let stream = ReaderStream::new(file.take(end - start + 1));
+
+            Ok(response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, format!("bytes {start}-{end}/{len}"))
+                .header(header::CONTENT_LENGTH, end - start + 1)
+                .body(Body::from_stream(stream))
+                .unwrap())
+        }
+        Ok(None) => {
+            let stream = ReaderStream::new(file);
+
+            Ok(response
+                .status(StatusCode::OK)
+                .header(header::CONTENT_LENGTH, len)
+                .body(Body::from_stream(stream))
+                .unwrap())
+        }
+        Err(()) => Ok(response
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{len}"))
+            .body(Body::empty())
+            .unwrap()),
+    }
 }
 
 fn escape_xml(s: &str) -> String {
@@ -903,12 +2365,98 @@ fn format_xmltv_time(iso_time: &str) -> String {
     }
 }
 
+/**
+    Proxy a DRM license request (Widevine or PlayReady) from a downstream
+    player to the channel's license server, with the channel's upstream headers.
+
+    For players that decrypt the original stream themselves, since license
+    servers usually reject requests without the provider's headers and cookies.
+*/
+async fn license_proxy(
+    State(state): State<AppState>,
+    Path((source_id, channel_id)): Path<(String, String)>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, StatusCode> {
+    wait_for_source_ready(&state.registry, &source_id).await?;
+
+    let id = ChannelId::new(&source_id, &channel_id);
+    let entry = state.registry.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+    let stream_info = match entry.stream_info {
+        Some(stream_info) => stream_info,
+        None => resolve_channel_content(&state, &id, &source_id).await?,
+    };
+    let license_url = stream_info
+        .license_url
+        .as_deref()
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let network = match state.manifest_store.get(&source_id).await {
+        Some(manifest) => Network::for_manifest(&manifest),
+        None => Ok(Network::default()),
+    };
+    let client = network
+        .and_then(|network| network.http_client())
+        .map_err(|e| {
+            eprintln!("[server] Invalid network settings for {}: {}", source_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut request = client.post(license_url).body(body.to_vec());
+    for (name, value) in request_headers(&stream_info.headers, license_url) {
+        request = request.header(name, value);
+    }
+    // PlayReady needs its SOAP headers, Widevine only cares about the body
+    for name in [header::CONTENT_TYPE.as_str(), "soapaction"] {
+        if let Some(value) = headers.get(name).and_then(|v| v.to_str().ok()) {
+            request = request.header(name, value);
+        }
+    }
+
+    let response = request.send().await.map_err(|e| {
+        eprintln!(
+            "[server] License request for {} failed: {}",
+            id.to_string(),
+            e
+        );
+        StatusCode::BAD_GATEWAY
+    })?;
+
+    let status =
+        StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    if !status.is_success() {
+        eprintln!(
+            "[server] License server returned {} for {}",
+            status,
+            id.to_string()
+        );
+    }
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    let license = response
+        .bytes()
+        .await
+        .map_err(|_| StatusCode::BAD_GATEWAY)?;
+
+    Ok(Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::from(license))
+        .unwrap())
+}
+
 /**
     Serve a channel's image, fetching and caching on first request.
 */
 async fn channel_image(
     State(state): State<AppState>,
     Path((source_id, channel_id)): Path<(String, String)>,
+    headers: HeaderMap,
 ) -> Result<Response, StatusCode> {
     // Wait for source to be ready
     wait_for_source_ready(&state.registry, &source_id).await?;
@@ -941,12 +2489,14 @@ async fn channel_image(
             StatusCode::BAD_GATEWAY
         })?;
 
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, cached.content_type)
-        .header(header::CACHE_CONTROL, "public, max-age=86400")
-        .body(Body::from((*cached.data).clone()))
-        .unwrap())
+    Ok(conditional_response(
+        &headers,
+        Response::builder()
+            .header(header::CONTENT_TYPE, cached.content_type)
+            .header(header::CACHE_CONTROL, "public, max-age=86400"),
+        Some(cached.fetched_at),
+        (*cached.data).clone(),
+    ))
 }
 
 /**
@@ -955,56 +2505,269 @@ async fn channel_image(
 async fn proxy_image(
     State(state): State<AppState>,
     Path(image_id): Path<String>,
+    headers: HeaderMap,
 ) -> Result<Response, StatusCode> {
     let cached = state.image_cache.get_by_id(&image_id).await.map_err(|e| {
         eprintln!("[server] Failed to fetch image {}: {}", image_id, e);
         StatusCode::NOT_FOUND
     })?;
 
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, cached.content_type)
-        .header(header::CACHE_CONTROL, "public, max-age=86400")
-        .body(Body::from((*cached.data).clone()))
-        .unwrap())
+    Ok(conditional_response(
+        &headers,
+        Response::builder()
+            .header(header::CONTENT_TYPE, cached.content_type)
+            .header(header::CACHE_CONTROL, "public, max-age=86400"),
+        Some(cached.fetched_at),
+        (*cached.data).clone(),
+    ))
+}
+
+/**
+    Keep the pipelines of standby channels running. They are started as soon
+    as their source has been discovered, and started again if they stop.
+*/
+async fn supervise_standby(state: AppState, mut shutdown_rx: watch::Receiver<bool>) {
+    loop {
+        // Stopped channels are started concurrently, so one slow channel doesn't hold up the rest
+        let mut stopped = Vec::new();
+        for (id, entry) in state.registry.list_all() {
+            if !entry.channel.standby || entry.channel.passthrough {
+                continue;
+            }
+            let profile = entry.channel.profile.as_deref();
+            if let Some(pipeline) = state.pipeline_store.get(&id, profile).await
+                && pipeline.is_running().await
+            {
+                continue;
+            }
+            stopped.push(id);
+        }
+
+        futures::future::join_all(stopped.iter().map(|id| {
+            let state = &state;
+            async move {
+                println!("[server] Starting standby channel {}...", id.to_string());
+                if let Err(status) = tune_channel(state, id, None).await {
+                    eprintln!(
+                        "[server] Failed to start standby channel {}: {}",
+                        id.to_string(),
+                        status
+                    );
+                }
+            }
+        }))
+        .await;
+
+        tokio::select! {
+            _ = tokio::time::sleep(STANDBY_CHECK_INTERVAL) => {}
+            _ = shutdown_rx.changed() => {
+                if *shutdown_rx.borrow() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/**
+    Refresh the stream info of the most watched channels shortly before it
+    expires, so that tuning to them doesn't wait for a refresh. Channels with
+    a running pipeline are left alone, as refreshing them restarts it.
+*/
+async fn refresh_popular_credentials(
+    state: AppState,
+    policy: PrewarmPolicy,
+    mut shutdown_rx: watch::Receiver<bool>,
+) {
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(CREDENTIAL_REFRESH_INTERVAL) => {}
+            _ = shutdown_rx.changed() => {
+                if *shutdown_rx.borrow() {
+                    return;
+                }
+            }
+        }
+
+        let now = crate::time::now();
+        let top = policy.top(state.registry.list_usage(), now, policy.refresh_count);
+        for id in top {
+            let Some(entry) = state.registry.get(&id) else {
+                continue;
+            };
+            let Some(stream_info) = entry.stream_info else {
+                continue;
+            };
+            let expires_soon = stream_info
+                .expires_at
+                .is_some_and(|at| at <= now + CREDENTIAL_REFRESH_AHEAD.as_secs());
+            if entry.channel.passthrough || !expires_soon {
+                continue;
+            }
+            let mut running = false;
+            for pipeline in state.pipeline_store.for_channel(&id).await {
+                running |= pipeline.is_running().await;
+            }
+            if running {
+                continue;
+            }
+
+            let Ok(network) = source_network(&state, &id.source).await else {
+                continue;
+            };
+            println!(
+                "[server] Refreshing stream info of {} before it expires",
+                id.to_string()
+            );
+            if refresh_stream_token(&state, &id, &stream_info, &network)
+                .await
+                .is_none()
+            {
+                state.registry.reset_channel_content_state(&id);
+                if let Err(status) = resolve_channel_content(&state, &id, &id.source).await {
+                    eprintln!(
+                        "[server] Failed to refresh stream info of {}: {}",
+                        id.to_string(),
+                        status
+                    );
+                }
+            }
+        }
+    }
 }
 
 /**
     Run the HTTP server.
 */
+#[allow(clippy::too_many_arguments)]
 pub async fn run_server(
     addr: SocketAddr,
     registry: Arc<ChannelRegistry>,
     pipeline_store: Arc<PipelineStore>,
     manifest_store: Arc<ManifestStore>,
     image_cache: Arc<ImageCache>,
+    preferences: Arc<ChannelPreferences>,
+    incidents: Arc<Incidents>,
+    options: ServerOptions,
+    prewarm: Vec<ChannelId>,
     mut shutdown_rx: watch::Receiver<bool>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let access_log = Arc::new(AccessLog::new(
+        options.access_log_sample,
+        options.trust_proxy,
+    ));
     let state = AppState {
         registry,
         pipeline_store,
         manifest_store,
         image_cache,
+        preferences,
+        access_log: Arc::clone(&access_log),
+        incidents,
+        upstream_origins: Arc::new(passthrough::UpstreamOrigins::new()),
     };
 
-    let app = Router::new()
+    tokio::spawn(supervise_standby(state.clone(), shutdown_rx.clone()));
+    if options.prewarm_policy.refresh_count > 0 {
+        tokio::spawn(refresh_popular_credentials(
+            state.clone(),
+            options.prewarm_policy,
+            shutdown_rx.clone(),
+        ));
+    }
+
+    // Pre-warm requested channels in the background (waits for their sources)
+    for id in prewarm {
+        let state = state.clone();
+        tokio::spawn(async move {
+            println!("[server] Pre-warming {}...", id.to_string());
+            let result = if is_passthrough(&state, &id).await {
+                match source_network(&state, &id.source).await {
+                    Ok(network) => passthrough_stream_info(&state, &id, &network)
+                        .await
+                        .map(|_| ()),
+                    Err(status) => Err(status),
+                }
+            } else {
+                tune_channel(&state, &id, None).await.map(|_| ())
+            };
+            match result {
+                Ok(()) => println!("[server] Pre-warmed {}", id.to_string()),
+                Err(status) => {
+                    eprintln!("[server] Failed to pre-warm {}: {}", id.to_string(), status)
+                }
+            }
+        });
+    }
+
+    // Playlists, EPG and JSON compress well, segments and images are already compressed
+    let text_routes = Router::new()
         .route("/", get(index))
-        .route("/i/{image_id}", get(proxy_image))
+        .route("/metrics", get(metrics))
+        .route("/api/channels", get(list_channels))
+        .route("/api/preferences", get(get_preferences))
+        .route("/api/incidents", get(list_incidents))
+        .route("/favorites.m3u", get(favorites_m3u))
+        .route("/tune/{source_id}/{channel_id}", get(tune))
         .route("/{source_id}/info", get(source_info))
         .route("/{source_id}/channels.m3u", get(source_m3u))
         .route("/{source_id}/epg.xml", get(source_epg))
         .route("/{source_id}/{channel_id}/info", get(channel_info))
-        .route("/{source_id}/{channel_id}/image", get(channel_image))
         .route(
             "/{source_id}/{channel_id}/playlist.m3u8",
             get(stream_playlist),
         )
+        .layer(CompressionLayer::new());
+
+    let app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route(SLATE_ROUTE, get(slate_segment))
+        .route("/i/{image_id}", get(proxy_image))
+        .route("/{source_id}/{channel_id}/image", get(channel_image))
+        .route("/{source_id}/{channel_id}/license", post(license_proxy))
+        .route("/api/favorites", put(set_favorites))
+        .route("/api/channels/export", get(export_channels))
+        .route("/api/channels/import", post(import_channels))
+        .route("/api/sources/{source_id}/resume", post(resume_source))
+        .route(
+            "/api/channels/{source_id}/{channel_id}/favorite",
+            put(add_favorite).delete(remove_favorite),
+        )
+        .route(
+            "/api/channels/{source_id}/{channel_id}/hidden",
+            put(hide_channel).delete(unhide_channel),
+        )
+        .route(
+            "/api/channels/{source_id}/{channel_id}/keys",
+            put(set_channel_keys).delete(clear_channel_keys),
+        )
+        .route(
+            "/api/channels/{source_id}/{channel_id}/segments",
+            put(set_channel_segments).delete(clear_channel_segments),
+        )
+        .route(
+            "/{source_id}/{channel_id}/proxy/{*upstream}",
+            get(passthrough_proxy),
+        )
         .route("/{source_id}/{channel_id}/{filename}", get(stream_segment))
+        .merge(text_routes)
         .with_state(state);
+    let app = match cors_layer(&options) {
+        Some(cors) => app.layer(cors),
+        None => app,
+    };
+    let app = app.layer(middleware::from_fn_with_state(
+        access_log,
+        access_log::track,
+    ));
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
 
-    axum::serve(listener, app)
+    // Connection info gives the access log the client address
+    let service = app.into_make_service_with_connect_info::<SocketAddr>();
+
+    axum::serve(listener, service)
         .with_graceful_shutdown(async move {
             while !*shutdown_rx.borrow_and_update() {
                 if shutdown_rx.changed().await.is_err() {
@@ -1016,3 +2779,43 @@ pub async fn run_server(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Ok(Some((0, 99))));
+        assert_eq!(parse_range("bytes=900-", 1000), Ok(Some((900, 999))));
+        assert_eq!(parse_range("bytes=-100", 1000), Ok(Some((900, 999))));
+        assert_eq!(parse_range("bytes=500-5000", 1000), Ok(Some((500, 999))));
+
+        // Served whole
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000), Ok(None));
+        assert_eq!(parse_range("items=0-1", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=9-1", 1000), Ok(None));
+
+        // Not satisfiable
+        assert_eq!(parse_range("bytes=1000-", 1000), Err(()));
+        assert_eq!(parse_range("bytes=-0", 1000), Err(()));
+        assert_eq!(parse_range("bytes=0-", 0), Err(()));
+    }
+
+    #[test]
+    fn test_with_profile_query() {
+        let playlist = "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXTINF:6.0,\n1700000000000.ts\n";
+        assert_eq!(
+            with_profile_query(playlist, "lowband-720p"),
+            "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXTINF:6.0,\n1700000000000.ts?profile=lowband-720p\n"
+        );
+        assert_eq!(
+            with_profile_query("#EXTINF:6.0,\n../../slate.ts?n=12\n", "lowband-720p"),
+            "#EXTINF:6.0,\n../../slate.ts?n=12&profile=lowband-720p\n"
+        );
+        assert_eq!(
+            with_profile_query("#EXTINF:6.0,\n1.ts\n", "hd&x=1"),
+            "#EXTINF:6.0,\n1.ts?profile=hd%26x%3D1\n"
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::pipeline::PipelineStore;
+use crate::registry::ChannelRegistry;
+use crate::server::ManifestStore;
+
+/**
+    Everything torn down on shutdown, in the order it is torn down.
+
+    Intake is stopped first, so that no request or discovery run starts a
+    pipeline or browser that would then have to be torn down as well. All
+    pipelines are then stopped at once and waited on while they flush their
+    sinks, after which their finished segments and playlists are published.
+    Segments still being written are never published, so clients and the
+    next run don't see half-written files. The registry cache is saved,
+    browsers are closed, and the server finishes its last responses.
+
+    Every stage shares one grace period, stages still running at its end
+    are aborted so that a stuck pipeline or browser can't hold up exit.
+*/
+pub struct Teardown {
+    pub shutdown_tx: watch::Sender<bool>,
+    pub pipeline_store: Arc<PipelineStore>,
+    pub manifest_store: Arc<ManifestStore>,
+    pub registry: Arc<ChannelRegistry>,
+    pub cache_file: Option<PathBuf>,
+    pub server_handle: JoinHandle<()>,
+}
+
+impl Teardown {
+    pub async fn run(self, grace: Duration) {
+        let deadline = Instant::now() + grace;
+
+        println!("[shutdown] Stopping intake");
+        let _ = self.shutdown_tx.send(true);
+
+        println!("[shutdown] Flushing pipelines");
+        let unfinished = self.pipeline_store.finish_all(deadline).await;
+        if !unfinished.is_empty() {
+            let ids: Vec<_> = unfinished.iter().map(|id| id.to_string()).collect();
+            eprintln!(
+                "[shutdown] Aborted pipelines still running after {}s: {}",
+                grace.as_secs(),
+                ids.join(", ")
+            );
+        }
+
+        println!("[shutdown] Finalizing playlists");
+        self.pipeline_store.publish_all().await;
+
+        // Persist latest channel and stream info for the next startup
+        if let Some(ref path) = self.cache_file
+            && let Err(e) = self.registry.save(path)
+        {
+            eprintln!("[shutdown] Failed to save cache: {}", e);
+        }
+
+        println!("[shutdown] Closing browsers");
+        let close_browsers = self.manifest_store.close_browsers();
+        if tokio::time::timeout_at(deadline.into(), close_browsers)
+            .await
+            .is_err()
+        {
+            eprintln!("[shutdown] Gave up on closing browsers");
+        }
+
+        let mut server_handle = self.server_handle;
+        if tokio::time::timeout_at(deadline.into(), &mut server_handle)
+            .await
+            .is_err()
+        {
+            eprintln!("[shutdown] Aborted server with responses still in flight");
+            server_handle.abort();
+        }
+    }
+}
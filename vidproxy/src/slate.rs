@@ -0,0 +1,469 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ffmpeg_encode::{AudioEncoder, AudioEncoderConfig, VideoEncoder, VideoEncoderConfig};
+use ffmpeg_sink::{Sink, SinkConfig};
+use ffmpeg_types::{AudioFrame, Error, VideoFrame};
+use tempfile::TempDir;
+
+/// Route the slate segment is served under, shared by all channels
+pub const SLATE_ROUTE: &str = "/slate.ts";
+
+/// URI of the slate segment relative to a channel's playlist, at `/{source}/{channel}/`
+pub const SLATE_SEGMENT: &str = "../../slate.ts";
+
+const WIDTH: u32 = 1280;
+const HEIGHT: u32 = 720;
+const FRAME_RATE: u32 = 25;
+const SAMPLE_RATE: u32 = 48_000;
+const AUDIO_FRAME_SAMPLES: usize = 1024;
+
+/// Number of (identical) segments listed in the slate playlist
+const PLAYLIST_WINDOW: u64 = 3;
+
+const BACKGROUND: [u8; 4] = [40, 24, 16, 255];
+const FOREGROUND: [u8; 4] = [235, 235, 235, 255];
+
+/**
+    5x7 bitmap glyphs, one byte per row with the leftmost pixel in bit 4.
+*/
+pub fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '!' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],
+        '\'' => [0x04, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00],
+        ' ' => [0x00; 7],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+/**
+    Draw a line of text horizontally centered at the given row, as large as fits.
+*/
+fn draw_text(frame: &mut [u8], width: u32, top: u32, max_scale: u32, text: &str) {
+    let columns = text.chars().count() as u32 * 6;
+    if columns == 0 {
+        return;
+    }
+    let scale = (width * 9 / 10 / columns).clamp(1, max_scale);
+    let left = width.saturating_sub(columns * scale) / 2;
+
+    for (index, c) in text.chars().enumerate() {
+        let x0 = left + index as u32 * 6 * scale;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..5 {
+                if bits & (0x10 >> col) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    let y = top + row as u32 * scale + dy;
+                    let start = ((y * width + x0 + col * scale) * 4) as usize;
+                    let end = start + scale as usize * 4;
+                    if let Some(pixels) = frame.get_mut(start..end) {
+                        for pixel in pixels.chunks_exact_mut(4) {
+                            pixel.copy_from_slice(&FOREGROUND);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/**
+    Render the slate image as tightly packed BGRA.
+*/
+fn render(width: u32, height: u32) -> Vec<u8> {
+    let mut frame = BACKGROUND.repeat((width * height) as usize);
+    draw_text(
+        &mut frame,
+        width,
+        height * 2 / 5,
+        8,
+        "CHANNEL TEMPORARILY UNAVAILABLE",
+    );
+    draw_text(
+        &mut frame,
+        width,
+        height * 3 / 5,
+        4,
+        "PLEASE TRY AGAIN LATER",
+    );
+    frame
+}
+
+/**
+    Build a live playlist that endlessly repeats the slate segment.
+    Every repetition is marked as a discontinuity since its timestamps restart.
+*/
+pub fn playlist(segment_duration: f64, now: u64) -> String {
+    let sequence = (now as f64 / segment_duration.max(1.0)) as u64;
+
+    let mut out = String::from("#EXTM3U\n#EXT-X-VERSION:3\n");
+    let _ = writeln!(
+        out,
+        "#EXT-X-TARGETDURATION:{}",
+        segment_duration.ceil() as u64
+    );
+    let _ = writeln!(out, "#EXT-X-MEDIA-SEQUENCE:{}", sequence);
+    let _ = writeln!(out, "#EXT-X-DISCONTINUITY-SEQUENCE:{}", sequence);
+    for n in sequence..sequence + PLAYLIST_WINDOW {
+        let _ = writeln!(
+            out,
+            "#EXT-X-DISCONTINUITY\n#EXTINF:{:.6},\n{}?n={}",
+            segment_duration, SLATE_SEGMENT, n
+        );
+    }
+    out
+}
+
+/**
+    Segments of a channel's playlist that the slate stands in for, by media
+    sequence number, so that replaced ad breaks continue the channel's numbering.
+*/
+#[derive(Debug, Default)]
+pub struct SlateSplice {
+    /// Replaced sequence ranges, end exclusive, open while the break lasts
+    ranges: Vec<(u64, Option<u64>)>,
+    /// Discontinuities around replaced ranges that have left the playlist
+    rolled_out: u64,
+}
+
+impl SlateSplice {
+    /**
+        Replace the segments of a playlist that fall in an ad break with the slate.
+
+        Segments added to the playlist while `replacing` is set are replaced, and
+        each switch between the channel and the slate is marked as a discontinuity.
+    */
+    pub fn apply(&mut self, playlist: &str, replacing: bool, segment_duration: f64) -> String {
+        let first = playlist
+            .lines()
+            .find_map(|l| l.strip_prefix("#EXT-X-MEDIA-SEQUENCE:"))
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        let next = first
+            + playlist
+                .lines()
+                .filter(|l| l.starts_with("#EXTINF"))
+                .count() as u64;
+
+        match self.ranges.last_mut() {
+            Some((_, end @ None)) if !replacing => *end = Some(next),
+            Some((_, None)) => {}
+            _ if replacing => self.ranges.push((next, None)),
+            _ => {}
+        }
+        self.ranges.retain(|&(start, end)| end != Some(start));
+        let rolled_out = &mut self.rolled_out;
+        self.ranges.retain(|&(_, end)| match end {
+            Some(end) if end < first => {
+                *rolled_out += 2;
+                false
+            }
+            _ => true,
+        });
+
+        let replaced = |sequence: u64| {
+            self.ranges
+                .iter()
+                .any(|&(start, end)| sequence >= start && end.is_none_or(|end| sequence < end))
+        };
+        let boundaries = self.rolled_out
+            + self
+                .ranges
+                .iter()
+                .flat_map(|&(start, end)| [Some(start), end])
+                .flatten()
+                .filter(|&boundary| boundary < first)
+                .count() as u64;
+
+        let mut out = String::with_capacity(playlist.len() + 64);
+        let mut sequence = first;
+        let mut has_discontinuity = false;
+        let has_discontinuity_sequence = playlist
+            .lines()
+            .any(|l| l.starts_with("#EXT-X-DISCONTINUITY-SEQUENCE:"));
+        for line in playlist.lines() {
+            if line.starts_with("#EXT-X-MEDIA-SEQUENCE:")
+                && !has_discontinuity_sequence
+                && boundaries > 0
+            {
+                let _ = writeln!(out, "{}", line);
+                let _ = writeln!(out, "#EXT-X-DISCONTINUITY-SEQUENCE:{}", boundaries);
+                continue;
+            }
+            if let Some(count) = line.strip_prefix("#EXT-X-DISCONTINUITY-SEQUENCE:") {
+                let count: u64 = count.trim().parse().unwrap_or(0);
+                let _ = writeln!(out, "#EXT-X-DISCONTINUITY-SEQUENCE:{}", count + boundaries);
+                continue;
+            }
+            if line == "#EXT-X-DISCONTINUITY" {
+                has_discontinuity = true;
+            }
+            if line.starts_with("#EXTINF") {
+                let boundary = sequence > 0 && replaced(sequence) != replaced(sequence - 1);
+                if boundary && !has_discontinuity {
+                    out.push_str("#EXT-X-DISCONTINUITY\n");
+                }
+                if replaced(sequence) {
+                    let _ = writeln!(out, "#EXTINF:{:.6},", segment_duration);
+                    continue;
+                }
+            }
+            if !line.is_empty() && !line.starts_with('#') {
+                if replaced(sequence) {
+                    let _ = writeln!(out, "{}?n={}", SLATE_SEGMENT, sequence);
+                } else {
+                    let _ = writeln!(out, "{}", line);
+                }
+                sequence += 1;
+                has_discontinuity = false;
+                continue;
+            }
+            let _ = writeln!(out, "{}", line);
+        }
+
+        out
+    }
+}
+
+/**
+    Find the first segment of a playlist, with its duration.
+*/
+fn first_segment(playlist: &str) -> Option<(f64, &str)> {
+    let mut lines = playlist.lines().map(str::trim);
+    let duration = lines
+        .find_map(|l| l.strip_prefix("#EXTINF:"))?
+        .split(',')
+        .next()?
+        .parse()
+        .ok()?;
+    let uri = lines.find(|l| !l.is_empty() && !l.starts_with('#'))?;
+    Some((duration, uri))
+}
+
+/**
+    A pre-encoded "channel temporarily unavailable" segment, served
+    in place of a channel's stream while its pipeline can't run.
+*/
+#[derive(Debug)]
+pub struct Slate {
+    segment: PathBuf,
+    duration: f64,
+    /// Kept apart from the channels' segment directories, removed on drop
+    _dir: TempDir,
+}
+
+impl Slate {
+    /**
+        Encode the slate (static image and silence) into a segment in a temporary directory.
+    */
+    pub fn generate(segment_duration: Duration) -> Result<Self, Error> {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("vidproxy-slate-")
+            .tempdir()
+            .map_err(|e| Error::codec(e.to_string()))?;
+        let dir = temp_dir.path();
+
+        let mut video_encoder = VideoEncoder::new(VideoEncoderConfig::h264(WIDTH, HEIGHT))?;
+        let mut audio_encoder = AudioEncoder::new(AudioEncoderConfig::aac())?;
+
+        let playlist_path = dir.join("playlist.m3u8");
+        let sink_config = SinkConfig::hls(segment_duration)
+            .with_video(video_encoder.stream_info())
+            .with_audio(audio_encoder.stream_info());
+        let mut sink = Sink::file(&playlist_path, sink_config)?;
+
+        let image = render(WIDTH, HEIGHT);
+        let frames = segment_duration.as_secs_f64() * FRAME_RATE as f64;
+        for index in 0..frames.ceil() as u32 {
+            let pts = Duration::from_secs_f64(index as f64 / FRAME_RATE as f64);
+            let frame = VideoFrame::from_bgra(image.clone(), WIDTH, HEIGHT, pts);
+            for packet in video_encoder.encode(&frame)? {
+                sink.write(&packet)?;
+            }
+        }
+
+        let samples = (segment_duration.as_secs_f64() * SAMPLE_RATE as f64) as usize;
+        for offset in (0..samples).step_by(AUDIO_FRAME_SAMPLES) {
+            let pts = Duration::from_secs_f64(offset as f64 / SAMPLE_RATE as f64);
+            let frame = AudioFrame::silence(SAMPLE_RATE, 2, AUDIO_FRAME_SAMPLES, pts);
+            for packet in audio_encoder.encode(&frame)? {
+                sink.write(&packet)?;
+            }
+        }
+
+        for packet in video_encoder.flush()? {
+            sink.write(&packet)?;
+        }
+        for packet in audio_encoder.flush()? {
+            sink.write(&packet)?;
+        }
+        sink.finish()?;
+
+        let written = std::fs::read_to_string(&playlist_path)
+            .map_err(|e| Error::codec(format!("Failed to read slate playlist: {}", e)))?;
+        let (duration, uri) = first_segment(&written)
+            .ok_or_else(|| Error::codec("Slate encoding produced no segments"))?;
+
+        Ok(Self {
+            segment: dir.join(uri),
+            duration,
+            _dir: temp_dir,
+        })
+    }
+
+    /**
+        Path of the encoded slate segment.
+    */
+    pub fn segment_path(&self) -> &Path {
+        &self.segment
+    }
+
+    /**
+        Duration of the encoded slate segment, in seconds.
+    */
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    /**
+        Render the slate playlist for the current time.
+    */
+    pub fn playlist(&self) -> String {
+        playlist(self.duration, crate::time::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_playlist_repeats_segment() {
+        let out = playlist(4.0, 100);
+        assert!(out.contains("#EXT-X-MEDIA-SEQUENCE:25\n"));
+        assert!(out.contains("#EXT-X-DISCONTINUITY-SEQUENCE:25\n"));
+        assert!(out.contains("#EXT-X-DISCONTINUITY\n#EXTINF:4.000000,\n../../slate.ts?n=27\n"));
+        assert_eq!(out.matches("#EXTINF").count(), PLAYLIST_WINDOW as usize);
+        assert!(!out.contains("#EXT-X-ENDLIST"));
+    }
+
+    fn channel_playlist(first: u64, count: u64) -> String {
+        let mut out = format!(
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:4\n#EXT-X-MEDIA-SEQUENCE:{}\n",
+            first
+        );
+        for sequence in first..first + count {
+            let _ = writeln!(out, "#EXTINF:3.960000,\nsegment_{}.ts", sequence);
+        }
+        out
+    }
+
+    #[test]
+    fn test_splice_continues_channel_sequence() {
+        let mut splice = SlateSplice::default();
+        let before = channel_playlist(10, 2);
+        assert_eq!(splice.apply(&before, false, 4.0), before);
+
+        // The break starts after the segments already listed
+        assert_eq!(splice.apply(&before, true, 4.0), before);
+        let out = splice.apply(&channel_playlist(11, 3), true, 4.0);
+        assert!(out.contains("#EXT-X-MEDIA-SEQUENCE:11\n"));
+        assert!(out.contains(
+            "\nsegment_11.ts\n#EXT-X-DISCONTINUITY\n#EXTINF:4.000000,\n../../slate.ts?n=12\n"
+        ));
+        assert!(out.contains("#EXTINF:4.000000,\n../../slate.ts?n=13\n"));
+        assert!(!out.contains("DISCONTINUITY-SEQUENCE"));
+
+        // Segments added until the break is seen to be over are still replaced
+        let out = splice.apply(&channel_playlist(12, 3), false, 4.0);
+        assert!(out.contains("../../slate.ts?n=14\n"));
+        assert!(!out.contains("DISCONTINUITY-SEQUENCE"));
+        let out = splice.apply(&channel_playlist(13, 3), false, 4.0);
+        assert!(out.contains(
+            "../../slate.ts?n=14\n#EXT-X-DISCONTINUITY\n#EXTINF:3.960000,\nsegment_15.ts\n"
+        ));
+        assert!(out.contains("#EXT-X-DISCONTINUITY-SEQUENCE:1\n"));
+        let out = splice.apply(&channel_playlist(15, 3), false, 4.0);
+        assert!(out.contains("#EXT-X-DISCONTINUITY-SEQUENCE:1\n"));
+        assert!(!out.contains("slate.ts"));
+
+        // Both discontinuities are counted once the break has rolled out
+        let out = splice.apply(&channel_playlist(16, 3), false, 4.0);
+        assert!(out.contains("#EXT-X-DISCONTINUITY-SEQUENCE:2\n"));
+        assert!(!out.contains("#EXT-X-DISCONTINUITY\n"));
+    }
+
+    #[test]
+    fn test_splice_keeps_existing_discontinuity() {
+        let mut splice = SlateSplice::default();
+        splice.apply(&channel_playlist(0, 1), true, 4.0);
+        let playlist = "#EXTM3U\n#EXT-X-MEDIA-SEQUENCE:0\n#EXT-X-DISCONTINUITY-SEQUENCE:3\n#EXTINF:4.0,\nsegment_0.ts\n#EXT-X-DISCONTINUITY\n#EXTINF:4.0,\nsegment_1.ts\n";
+        let out = splice.apply(playlist, true, 4.0);
+        assert_eq!(out.matches("#EXT-X-DISCONTINUITY\n").count(), 1);
+        assert!(out.contains("#EXT-X-DISCONTINUITY-SEQUENCE:3\n"));
+        assert!(out.ends_with("#EXT-X-DISCONTINUITY\n#EXTINF:4.000000,\n../../slate.ts?n=1\n"));
+    }
+
+    #[test]
+    fn test_first_segment() {
+        let written = "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXTINF:3.960000,\nsegment_0.ts\n#EXTINF:0.04,\nsegment_1.ts\n";
+        assert_eq!(first_segment(written), Some((3.96, "segment_0.ts")));
+        assert_eq!(first_segment("#EXTM3U\n"), None);
+    }
+
+    #[test]
+    fn test_render_draws_text() {
+        let frame = render(320, 180);
+        assert_eq!(frame.len(), 320 * 180 * 4);
+        let lit = frame.chunks_exact(4).filter(|p| *p == FOREGROUND).count();
+        assert!(lit > 0);
+        // Top rows stay background
+        assert!(
+            frame[..320 * 4 * 10]
+                .chunks_exact(4)
+                .all(|p| p == BACKGROUND)
+        );
+    }
+}
@@ -1,13 +1,122 @@
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
 use anyhow::{Result, anyhow};
 use chrome_browser::{ChromeBrowser, ChromeBrowserTab, ChromeLaunchOptions};
 
-use crate::manifest::{self, ChannelEntry, DiscoveredChannel, Manifest, StreamInfo, Transform};
+use crate::antibot;
+use crate::manifest::{
+    self, ChannelEntry, DiscoveredChannel, InterpolationContext, Manifest, StreamInfo, Transform,
+};
+use crate::network::Network;
+
+/**
+    Default time limit for a source's discovery run (5 minutes)
+*/
+const DEFAULT_DISCOVERY_TIMEOUT_SECS: u64 = 300;
+
+/**
+    Default time limit for resolving a single channel's content (2 minutes)
+*/
+const DEFAULT_CONTENT_TIMEOUT_SECS: u64 = 120;
+
+/**
+    How long to wait for a timed out tab to navigate away before giving up on it
+*/
+const TAB_RESET_TIMEOUT: Duration = Duration::from_secs(10);
+
+/**
+    Error returned when a discovery or content run exceeds its deadline.
+*/
+#[derive(Debug)]
+pub struct RunTimeout {
+    /// What timed out, e.g. "Discovery for 'source'"
+    pub what: String,
+    /// The deadline that was exceeded, in seconds
+    pub secs: u64,
+    /// Whether the tab also failed to reset afterwards, meaning the
+    /// browser is stuck and should be relaunched
+    pub tab_unresponsive: bool,
+}
+
+impl fmt::Display for RunTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} timed out after {}s", self.what, self.secs)?;
+        if self.tab_unresponsive {
+            write!(f, " (browser unresponsive)")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RunTimeout {}
+
+/**
+    Run a future that drives a browser tab, aborting it after a deadline.
+
+    On timeout the tab is navigated to a blank page, which stops whatever
+    the page was doing and leaves it ready for the next run.
+
+    If the run failed because the page is an antibot interstitial, that is
+    returned as an `AntibotChallenge` instead, and the tab is left on the
+    interstitial so that it can be solved by hand.
+*/
+async fn with_deadline<T>(
+    what: String,
+    secs: u64,
+    tab: &ChromeBrowserTab,
+    fut: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let result = tokio::time::timeout(Duration::from_secs(secs), fut).await;
+    if !matches!(result, Ok(Ok(_)))
+        && let Some(challenge) = antibot::detect(tab).await
+    {
+        eprintln!("[source] {} failed: {}", what, challenge);
+        return Err(challenge.into());
+    }
+    if let Ok(result) = result {
+        return result;
+    }
+
+    eprintln!("[source] {} timed out after {}s, resetting tab", what, secs);
+    let tab_unresponsive = !matches!(
+        tokio::time::timeout(TAB_RESET_TIMEOUT, tab.navigate("about:blank")).await,
+        Ok(Ok(_))
+    );
+    if tab_unresponsive {
+        eprintln!("[source] Tab did not respond after timeout of {}", what);
+    }
+
+    Err(RunTimeout {
+        what,
+        secs,
+        tab_unresponsive,
+    }
+    .into())
+}
+
+/**
+    Process-wide browser launch settings, shared by all sources.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct BrowserOptions {
+    /// Disable the Chrome sandbox, needed when running as root in containers
+    pub no_sandbox: bool,
+    /// Run every source headless, regardless of its manifest
+    pub force_headless: bool,
+    /// Keep browsers that hit an antibot challenge open, for solving it by hand
+    pub keep_challenge_window: bool,
+}
 
 /**
     Create a browser instance configured for a manifest's source.
 */
-pub async fn create_browser(manifest: &Manifest) -> Result<ChromeBrowser> {
-    let headless = manifest.source.headless;
+pub async fn create_browser(
+    manifest: &Manifest,
+    browser_options: &BrowserOptions,
+) -> Result<ChromeBrowser> {
+    let headless = manifest.source.headless || browser_options.force_headless;
     let mut options = ChromeLaunchOptions::default()
         .headless(headless)
         .devtools(false)
@@ -17,6 +126,14 @@ pub async fn create_browser(manifest: &Manifest) -> Result<ChromeBrowser> {
         options = options.proxy_server(proxy);
     }
 
+    for arg in Network::for_manifest(manifest)?.chrome_args() {
+        options = options.arg(arg);
+    }
+
+    if browser_options.no_sandbox {
+        options = options.arg(String::from("--no-sandbox"));
+    }
+
     ChromeBrowser::new(options).await
 }
 
@@ -30,6 +147,8 @@ pub struct SourceResult {
     pub channels: Vec<ChannelEntry>,
     /// When discovery results expire (if any)
     pub discovery_expires_at: Option<u64>,
+    /// Outputs of the discovery steps, for the refresh phase
+    pub discovery_context: InterpolationContext,
 }
 
 /**
@@ -50,6 +169,8 @@ pub async fn run_source(manifest: &Manifest, headless: bool) -> Result<SourceRes
     let source_name = &manifest.source.name;
     println!("[source] Starting source: {} ({})", source_name, source_id);
 
+    let network = Network::for_manifest(manifest)?;
+
     // Launch browser
     let mut options = ChromeLaunchOptions::default()
         .headless(headless)
@@ -59,6 +180,10 @@ pub async fn run_source(manifest: &Manifest, headless: bool) -> Result<SourceRes
         options = options.proxy_server(proxy);
     }
 
+    for arg in network.chrome_args() {
+        options = options.arg(arg);
+    }
+
     let browser = ChromeBrowser::new(options).await?;
 
     // Get tab 0 for all operations (discovery + content must share same tab for auth)
@@ -69,9 +194,8 @@ pub async fn run_source(manifest: &Manifest, headless: bool) -> Result<SourceRes
 
     // Run discovery phase
     println!("[source] Running discovery phase...");
-    let proxy = manifest.source.proxy.as_deref();
     let discovery_result =
-        manifest::execute_discovery(&manifest.discovery, &tab, source_id, proxy).await?;
+        manifest::execute_discovery(&manifest.discovery, &tab, source_id, &network).await?;
 
     let channels = discovery_result.channels;
     println!("[source] Discovery found {} channels", channels.len());
@@ -124,7 +248,7 @@ pub async fn run_source(manifest: &Manifest, headless: bool) -> Result<SourceRes
     if let Some(ref metadata_phase) = manifest.metadata {
         println!("[source] Running metadata phase...");
 
-        match manifest::execute_metadata(metadata_phase, &tab, proxy).await {
+        match manifest::execute_metadata(metadata_phase, &tab, &network).await {
             Ok(result) => {
                 channel_programmes = result.programmes_by_channel;
             }
@@ -146,7 +270,13 @@ pub async fn run_source(manifest: &Manifest, headless: bool) -> Result<SourceRes
         let mut stream_info = None;
 
         for attempt in 1..=MAX_RETRIES {
-            match manifest::execute_content(&manifest.content, &tab, channel, proxy).await {
+            let session = manifest
+                .refresh
+                .is_some()
+                .then_some(&discovery_result.context);
+            match manifest::execute_content(&manifest.content, &tab, channel, session, &network)
+                .await
+            {
                 Ok(info) => {
                     println!("[source] Content phase completed for: {}", channel_name);
                     stream_info = Some(info);
@@ -199,6 +329,7 @@ pub async fn run_source(manifest: &Manifest, headless: bool) -> Result<SourceRes
         source_id: source_id.clone(),
         channels: channel_entries,
         discovery_expires_at: discovery_result.expires_at,
+        discovery_context: discovery_result.context,
     })
 }
 
@@ -227,11 +358,72 @@ pub async fn run_source_discovery_only(
         .await
         .ok_or_else(|| anyhow!("No browser tab available"))?;
 
+    let timeout = manifest
+        .source
+        .discovery_timeout
+        .unwrap_or(DEFAULT_DISCOVERY_TIMEOUT_SECS);
+    with_deadline(
+        format!("Discovery for '{}'", source_id),
+        timeout,
+        &tab,
+        discover_in_tab(manifest, &tab),
+    )
+    .await
+}
+
+/**
+    Run the refresh phase of a source in its existing browser, continuing
+    from the outputs of its last discovery. Returns the updated outputs and
+    when the discovery results now expire.
+
+    Errors if the source has no refresh phase.
+*/
+pub async fn run_source_refresh(
+    manifest: &Manifest,
+    browser: &ChromeBrowser,
+    context: InterpolationContext,
+) -> Result<(InterpolationContext, Option<u64>)> {
+    let source_id = &manifest.source.id;
+    let phase = manifest
+        .refresh
+        .as_ref()
+        .ok_or_else(|| anyhow!("Source '{}' has no refresh phase", source_id))?;
+
+    let tab = browser
+        .get_tab(0)
+        .await
+        .ok_or_else(|| anyhow!("No browser tab available"))?;
+
+    println!("[source] Refreshing session of '{}'...", source_id);
+    let network = Network::for_manifest(manifest)?;
+    let timeout = manifest
+        .source
+        .discovery_timeout
+        .unwrap_or(DEFAULT_DISCOVERY_TIMEOUT_SECS);
+    let result = with_deadline(
+        format!("Refresh for '{}'", source_id),
+        timeout,
+        &tab,
+        manifest::execute_refresh(phase, &tab, context, &network),
+    )
+    .await?;
+
+    let _ = tab.navigate("about:blank").await;
+
+    Ok((result.context, result.expires_at))
+}
+
+/**
+    Run the discovery, processing and metadata phases of a source in a tab.
+*/
+async fn discover_in_tab(manifest: &Manifest, tab: &ChromeBrowserTab) -> Result<SourceResult> {
+    let source_id = &manifest.source.id;
+
     // Run discovery phase
     println!("[source] Running discovery phase...");
-    let proxy = manifest.source.proxy.as_deref();
+    let network = Network::for_manifest(manifest)?;
     let discovery_result =
-        manifest::execute_discovery(&manifest.discovery, &tab, source_id, proxy).await?;
+        manifest::execute_discovery(&manifest.discovery, tab, source_id, &network).await?;
 
     let channels = discovery_result.channels;
     println!("[source] Discovery found {} channels", channels.len());
@@ -279,7 +471,7 @@ pub async fn run_source_discovery_only(
     if let Some(ref metadata_phase) = manifest.metadata {
         println!("[source] Running metadata phase...");
 
-        match manifest::execute_metadata(metadata_phase, &tab, proxy).await {
+        match manifest::execute_metadata(metadata_phase, tab, &network).await {
             Ok(result) => {
                 channel_programmes = result.programmes_by_channel;
             }
@@ -316,6 +508,7 @@ pub async fn run_source_discovery_only(
         source_id: source_id.clone(),
         channels: channel_entries,
         discovery_expires_at: discovery_result.expires_at,
+        discovery_context: discovery_result.context,
     })
 }
 
@@ -328,14 +521,25 @@ pub async fn run_source_discovery_only(
 pub async fn resolve_channel_content(
     manifest: &Manifest,
     channel: &DiscoveredChannel,
+    session: Option<&InterpolationContext>,
     tab: &ChromeBrowserTab,
 ) -> Result<StreamInfo> {
     let channel_name = channel.name.as_deref().unwrap_or(&channel.id);
     println!("[source] Resolving content for '{}'...", channel_name);
 
     // Run content phase using the channel data we already have
-    let proxy = manifest.source.proxy.as_deref();
-    let stream_info = manifest::execute_content(&manifest.content, tab, channel, proxy).await?;
+    let network = Network::for_manifest(manifest)?;
+    let timeout = manifest
+        .source
+        .content_timeout
+        .unwrap_or(DEFAULT_CONTENT_TIMEOUT_SECS);
+    let stream_info = with_deadline(
+        format!("Content resolution for '{}'", channel_name),
+        timeout,
+        tab,
+        manifest::execute_content(&manifest.content, tab, channel, session, &network),
+    )
+    .await?;
 
     println!(
         "[source] Content resolved for '{}': {}",
@@ -378,6 +582,55 @@ fn apply_transform(channels: &mut [DiscoveredChannel], transform: &Transform) {
                 }
             }
         }
+        Transform::Loudness { name, id, config } => {
+            for channel in channels.iter_mut() {
+                if channel_matches(channel, name, id) {
+                    channel.loudness = Some(config.clone());
+                }
+            }
+        }
+        Transform::Overlay { name, id, config } => {
+            for channel in channels.iter_mut() {
+                if channel_matches(channel, name, id) {
+                    channel.overlay = Some(config.clone());
+                }
+            }
+        }
+        Transform::AdBreaks { name, id, config } => {
+            for channel in channels.iter_mut() {
+                if channel_matches(channel, name, id) {
+                    channel.ad_breaks = Some(config.clone());
+                }
+            }
+        }
+        Transform::Passthrough { name, id } => {
+            for channel in channels.iter_mut() {
+                if channel_matches(channel, name, id) {
+                    channel.passthrough = true;
+                }
+            }
+        }
+        Transform::Standby { name, id } => {
+            for channel in channels.iter_mut() {
+                if channel_matches(channel, name, id) {
+                    channel.standby = true;
+                }
+            }
+        }
+        Transform::Segments { name, id, config } => {
+            for channel in channels.iter_mut() {
+                if channel_matches(channel, name, id) {
+                    channel.segments = config.or(channel.segments);
+                }
+            }
+        }
+        Transform::Profile { name, id, profile } => {
+            for channel in channels.iter_mut() {
+                if channel_matches(channel, name, id) {
+                    channel.profile = Some(profile.clone());
+                }
+            }
+        }
     }
 }
 
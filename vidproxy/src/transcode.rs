@@ -0,0 +1,609 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use ffmpeg_decode::{AudioDecoder, AudioDecoderConfig, VideoDecoder, VideoDecoderConfig};
+use ffmpeg_encode::{AudioEncoder, AudioEncoderConfig, VideoEncoder, VideoEncoderConfig};
+use ffmpeg_source::{Packet, Source, SourceConfig, StreamFilter};
+use ffmpeg_transform::{
+    AudioTransform, AudioTransformConfig, VideoTransform, VideoTransformConfig,
+};
+use ffmpeg_types::{
+    AudioFrame, AudioStreamInfo, CodecConfig, Error, Rational, StreamType, VideoFrame,
+    VideoStreamInfo,
+};
+
+use crate::adbreak::{AdBreakLog, BlackSilenceDetector};
+use crate::loudness::{LoudnessMeter, LoudnessNormalizer, SAMPLE_RATE};
+use crate::manifest::{
+    AdBreakConfig, AudioGapPolicy, DiscoveredChannel, LoudnessConfig, OverlayConfig,
+};
+use crate::overlay::{self, Overlay};
+use crate::profile::OutputProfile;
+
+/// Channel count of the transformed audio (48 kHz interleaved f32 stereo)
+pub const CHANNELS: usize = 2;
+
+/// Frame size video is scaled down to for black frame detection
+const ANALYSIS_WIDTH: u32 = 64;
+const ANALYSIS_HEIGHT: u32 = 36;
+
+/// Shorter gaps in audio timestamps are rounding and jitter, not lost audio
+const MIN_AUDIO_GAP: Duration = Duration::from_millis(50);
+/// Longer gaps are timestamp jumps (restarts, wraps), not lost audio
+const MAX_AUDIO_GAP: Duration = Duration::from_secs(10);
+/// Samples per frame of inserted silence
+const SILENCE_FRAME_SAMPLES: usize = 1024;
+
+/**
+    Give up on the first pass of two-pass normalization if the input hasn't
+    ended by then, since it is most likely a live stream that wasn't detected
+    as one. Kept well below the startup timeout, which the pass delays.
+*/
+const MEASURE_MAX_WAIT: Duration = Duration::from_secs(10);
+
+/**
+    Output processing configured for a channel.
+    Channels without any are remuxed as-is.
+*/
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProcessingConfig {
+    pub loudness: Option<LoudnessConfig>,
+    pub overlay: Option<OverlayConfig>,
+    pub ad_breaks: Option<AdBreakConfig>,
+    /// Scale the video down to at most this height
+    pub max_height: Option<u32>,
+}
+
+impl ProcessingConfig {
+    /**
+        Get the processing configured for a channel, with the settings of its
+        output profile (if any) taking precedence, resolving a missing overlay
+        image to the channel's own image.
+    */
+    pub fn for_channel(channel: &DiscoveredChannel, profile: Option<&OutputProfile>) -> Self {
+        let overlay = profile.and_then(|p| p.overlay.clone());
+        let overlay = overlay.or_else(|| channel.overlay.clone());
+        let overlay = overlay.and_then(|mut overlay| {
+            if overlay.is_text() {
+                return Some(overlay);
+            }
+            if overlay.image.is_none() {
+                overlay.image = channel.image.clone();
+            }
+            if overlay.image.is_none() {
+                eprintln!(
+                    "[transcode] Overlay configured for '{}' but it has no image, skipping",
+                    channel.id
+                );
+                return None;
+            }
+            Some(overlay)
+        });
+
+        Self {
+            loudness: profile
+                .and_then(|p| p.loudness.clone())
+                .or_else(|| channel.loudness.clone()),
+            overlay,
+            ad_breaks: channel.ad_breaks.clone(),
+            max_height: profile.and_then(|p| p.max_height),
+        }
+    }
+
+    /**
+        Load the resources needed by the configured processing,
+        fetching remote overlay images with the given client.
+    */
+    pub async fn load(
+        &self,
+        client: &reqwest::Client,
+        headers: &[(String, String)],
+    ) -> Result<OutputProcessing> {
+        let overlay = match self.overlay {
+            Some(ref config) if config.is_text() => Some(Overlay::text(config)),
+            Some(ref config) => {
+                let image = config.image.as_deref().unwrap_or_default();
+                Some(overlay::load(client, headers, image, config).await?)
+            }
+            None => None,
+        };
+
+        Ok(OutputProcessing {
+            loudness: self.loudness.clone(),
+            overlay,
+            max_height: self.max_height,
+            ad_break_log: None,
+        })
+    }
+}
+
+/**
+    Output processing with its resources loaded, ready for the pipeline.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct OutputProcessing {
+    pub loudness: Option<LoudnessConfig>,
+    pub overlay: Option<Overlay>,
+    /// Scale the video down to at most this height
+    pub max_height: Option<u32>,
+    /**
+        Where to record ad boundaries found from black frames with silence.
+        Set by the pipeline when heuristic detection is enabled, since the
+        log outlives restarts of the remux pipeline.
+    */
+    pub ad_break_log: Option<Arc<Mutex<AdBreakLog>>>,
+}
+
+/**
+    Codec parameters of an elementary stream, taken from the source once
+    and shared by every stage that decodes the stream.
+*/
+#[derive(Clone)]
+pub struct StreamCodec {
+    config: CodecConfig,
+    time_base: Rational,
+}
+
+impl StreamCodec {
+    pub fn audio(source: &mut Source) -> Result<Self, Error> {
+        Ok(Self {
+            config: source
+                .take_audio_codec_config()
+                .ok_or_else(|| Error::codec("No audio codec config"))?,
+            time_base: source
+                .audio_time_base()
+                .ok_or_else(|| Error::codec("No audio time base"))?,
+        })
+    }
+
+    pub fn video(source: &mut Source) -> Result<Self, Error> {
+        Ok(Self {
+            config: source
+                .take_video_codec_config()
+                .ok_or_else(|| Error::codec("No video codec config"))?,
+            time_base: source
+                .video_time_base()
+                .ok_or_else(|| Error::codec("No video time base"))?,
+        })
+    }
+
+    pub fn audio_decoder(&self) -> Result<AudioDecoder, Error> {
+        AudioDecoder::new(
+            self.config.clone(),
+            self.time_base,
+            AudioDecoderConfig::new(),
+        )
+    }
+
+    pub fn video_decoder(&self) -> Result<VideoDecoder, Error> {
+        VideoDecoder::new(
+            self.config.clone(),
+            self.time_base,
+            VideoDecoderConfig::new(),
+        )
+    }
+}
+
+fn samples_from_bytes(data: &[u8]) -> Vec<f32> {
+    data.chunks_exact(4)
+        .map(|b| f32::from_ne_bytes(b.try_into().unwrap()))
+        .collect()
+}
+
+fn samples_to_bytes(samples: &[f32], data: &mut [u8]) {
+    for (chunk, sample) in data.chunks_exact_mut(4).zip(samples) {
+        chunk.copy_from_slice(&sample.to_ne_bytes());
+    }
+}
+
+/**
+    Re-encodes the audio of a stream, passing it through a loudness
+    normalizer on the way. Video packets are not touched.
+*/
+pub struct AudioTranscoder {
+    decoder: AudioDecoder,
+    transform: AudioTransform,
+    normalizer: LoudnessNormalizer,
+    encoder: AudioEncoder,
+    gap_policy: AudioGapPolicy,
+    gaps: GapTracker,
+    /// A gap was left in the audio since the last call to `take_gap`
+    pending_gap: bool,
+}
+
+impl AudioTranscoder {
+    /**
+        Create a transcoder for an audio stream.
+    */
+    pub fn new(
+        codec: &StreamCodec,
+        normalizer: LoudnessNormalizer,
+        gap_policy: AudioGapPolicy,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            decoder: codec.audio_decoder()?,
+            transform: AudioTransform::new(AudioTransformConfig::playback()),
+            normalizer,
+            encoder: AudioEncoder::new(AudioEncoderConfig::aac())?,
+            gap_policy,
+            gaps: GapTracker::default(),
+            pending_gap: false,
+        })
+    }
+
+    /**
+        Check whether a gap was left in the audio since the last call,
+        for marking a discontinuity when gaps aren't filled.
+    */
+    pub fn take_gap(&mut self) -> bool {
+        std::mem::take(&mut self.pending_gap)
+    }
+
+    /**
+        Stream info of the re-encoded audio, for configuring the sink.
+    */
+    pub fn output_info(&self) -> AudioStreamInfo {
+        self.encoder.stream_info()
+    }
+
+    /**
+        Current normalization gain in dB.
+    */
+    pub fn gain_db(&self) -> f64 {
+        self.normalizer.gain_db()
+    }
+
+    /**
+        Transcode one audio packet, returning any encoded packets that are ready.
+    */
+    pub fn transcode(&mut self, packet: &Packet) -> Result<Vec<Packet>, Error> {
+        // A corrupt packet only drops a few ms of audio, don't fail the stream over it
+        let Ok(frames) = self.decoder.decode(packet) else {
+            return Ok(Vec::new());
+        };
+
+        let mut packets = Vec::new();
+        for frame in frames {
+            packets.extend(self.process_frame(&frame)?);
+        }
+        Ok(packets)
+    }
+
+    /**
+        Flush buffered audio at the end of the stream.
+    */
+    pub fn flush(&mut self) -> Result<Vec<Packet>, Error> {
+        let mut packets = Vec::new();
+        for frame in self.decoder.flush().unwrap_or_default() {
+            packets.extend(self.process_frame(&frame)?);
+        }
+        if let Some(mut frame) = self.transform.flush()? {
+            self.normalize(&mut frame);
+            packets.extend(self.encoder.encode(&frame)?);
+        }
+        packets.extend(self.encoder.flush()?);
+        Ok(packets)
+    }
+
+    fn process_frame(&mut self, frame: &AudioFrame) -> Result<Vec<Packet>, Error> {
+        let Ok(mut transformed) = self.transform.transform(frame) else {
+            return Ok(Vec::new());
+        };
+
+        let mut packets = Vec::new();
+        if let Some(start) = transformed.presentation_time() {
+            let samples = transformed.data.len() / 4 / CHANNELS;
+            if let Some((gap_start, gap)) = self.gaps.check(start, samples) {
+                match self.gap_policy {
+                    AudioGapPolicy::Fill => {
+                        println!(
+                            "[transcode] Filling {:.0} ms audio gap with silence",
+                            gap.as_secs_f64() * 1000.0
+                        );
+                        packets.extend(self.encode_silence(gap_start, gap)?);
+                    }
+                    AudioGapPolicy::Discontinuity => {
+                        println!(
+                            "[transcode] {:.0} ms audio gap, marking a discontinuity",
+                            gap.as_secs_f64() * 1000.0
+                        );
+                        self.pending_gap = true;
+                    }
+                }
+            }
+        }
+
+        self.normalize(&mut transformed);
+        packets.extend(self.encoder.encode(&transformed)?);
+        Ok(packets)
+    }
+
+    fn encode_silence(
+        &mut self,
+        start: Duration,
+        duration: Duration,
+    ) -> Result<Vec<Packet>, Error> {
+        let total = (duration.as_secs_f64() * SAMPLE_RATE as f64).round() as usize;
+        let mut packets = Vec::new();
+        for offset in (0..total).step_by(SILENCE_FRAME_SAMPLES) {
+            let samples = SILENCE_FRAME_SAMPLES.min(total - offset);
+            let pts = start + Duration::from_secs_f64(offset as f64 / SAMPLE_RATE as f64);
+            let frame = AudioFrame::silence(SAMPLE_RATE, CHANNELS as u32, samples, pts);
+            packets.extend(self.encoder.encode(&frame)?);
+        }
+        Ok(packets)
+    }
+
+    fn normalize(&mut self, frame: &mut AudioFrame) {
+        let mut samples = samples_from_bytes(&frame.data);
+        self.normalizer.process(&mut samples);
+        samples_to_bytes(&samples, &mut frame.data);
+    }
+}
+
+/**
+    Follows the timestamps of consecutive audio frames to find gaps between them.
+*/
+#[derive(Debug, Default)]
+struct GapTracker {
+    /// Where the next frame is expected to start
+    next: Option<Duration>,
+}
+
+impl GapTracker {
+    /**
+        Record a frame, returning the start and length of the gap before it, if any.
+    */
+    fn check(&mut self, start: Duration, samples: usize) -> Option<(Duration, Duration)> {
+        let length = Duration::from_secs_f64(samples as f64 / SAMPLE_RATE as f64);
+        let expected = self.next.replace(start + length)?;
+        let gap = start.checked_sub(expected)?;
+        (MIN_AUDIO_GAP..=MAX_AUDIO_GAP)
+            .contains(&gap)
+            .then_some((expected, gap))
+    }
+}
+
+/**
+    Get the size to scale video down to so that it is at most the given
+    height, keeping the aspect ratio. Dimensions are kept even for H.264.
+*/
+pub fn scaled_size(width: u32, height: u32, max_height: Option<u32>) -> (u32, u32) {
+    let Some(max_height) = max_height.filter(|max| *max < height) else {
+        return (width, height);
+    };
+    let even = |n: u32| (n & !1).max(2);
+    let scaled_width = (width as u64 * max_height as u64 / height.max(1) as u64) as u32;
+    (even(scaled_width), even(max_height))
+}
+
+/**
+    Re-encodes the video of a stream at the given size, with an
+    overlay (image or text) burned in if there is one.
+*/
+pub struct VideoTranscoder {
+    decoder: VideoDecoder,
+    transform: VideoTransform,
+    overlay: Option<Overlay>,
+    encoder: VideoEncoder,
+}
+
+impl VideoTranscoder {
+    /**
+        Create a transcoder for a video stream, encoding it at the given size.
+    */
+    pub fn new(
+        codec: &StreamCodec,
+        width: u32,
+        height: u32,
+        overlay: Option<Overlay>,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            decoder: codec.video_decoder()?,
+            transform: VideoTransform::new(VideoTransformConfig::to_bgra(width, height)),
+            overlay,
+            encoder: VideoEncoder::new(VideoEncoderConfig::h264(width, height))?,
+        })
+    }
+
+    /**
+        Stream info of the re-encoded video, for configuring the sink.
+    */
+    pub fn output_info(&self) -> VideoStreamInfo {
+        self.encoder.stream_info()
+    }
+
+    /**
+        Transcode one video packet, returning any encoded packets that are ready.
+    */
+    pub fn transcode(&mut self, packet: &Packet) -> Result<Vec<Packet>, Error> {
+        // The decoder recovers at the next keyframe
+        let Ok(frames) = self.decoder.decode(packet) else {
+            return Ok(Vec::new());
+        };
+
+        let mut packets = Vec::new();
+        for frame in frames {
+            packets.extend(self.process_frame(&frame)?);
+        }
+        Ok(packets)
+    }
+
+    /**
+        Flush buffered frames at the end of the stream.
+    */
+    pub fn flush(&mut self) -> Result<Vec<Packet>, Error> {
+        let mut packets = Vec::new();
+        for frame in self.decoder.flush().unwrap_or_default() {
+            packets.extend(self.process_frame(&frame)?);
+        }
+        packets.extend(self.encoder.flush()?);
+        Ok(packets)
+    }
+
+    fn process_frame(&mut self, frame: &VideoFrame) -> Result<Vec<Packet>, Error> {
+        let Ok(mut bgra) = self.transform.transform(frame) else {
+            return Ok(Vec::new());
+        };
+        if let Some(ref overlay) = self.overlay {
+            let time = bgra.presentation_time().unwrap_or_default();
+            overlay.composite(&mut bgra.data, bgra.width, bgra.height, time);
+        }
+        self.encoder.encode(&bgra)
+    }
+}
+
+/**
+    Decodes a stream to detect likely ad boundaries from black frames
+    coinciding with silence. Packets are only inspected, not modified.
+*/
+pub struct AdBreakAnalyzer {
+    video: Option<(VideoDecoder, VideoTransform)>,
+    audio: Option<(AudioDecoder, AudioTransform)>,
+    detector: BlackSilenceDetector,
+}
+
+impl AdBreakAnalyzer {
+    pub fn new(video: Option<&StreamCodec>, audio: Option<&StreamCodec>) -> Result<Self, Error> {
+        let video = video
+            .map(|codec| -> Result<_, Error> {
+                Ok((
+                    codec.video_decoder()?,
+                    VideoTransform::new(VideoTransformConfig::to_bgra(
+                        ANALYSIS_WIDTH,
+                        ANALYSIS_HEIGHT,
+                    )),
+                ))
+            })
+            .transpose()?;
+        let audio = audio
+            .map(|codec| -> Result<_, Error> {
+                Ok((
+                    codec.audio_decoder()?,
+                    AudioTransform::new(AudioTransformConfig::playback()),
+                ))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            video,
+            audio,
+            detector: BlackSilenceDetector::default(),
+        })
+    }
+
+    /**
+        Inspect a packet at the given stream time (in seconds).
+        Returns the stream time a likely ad boundary started at, if one was found.
+    */
+    pub fn analyze(&mut self, packet: &Packet, time: f64) -> Option<f64> {
+        let mut boundary = None;
+
+        if packet.stream_type == StreamType::Video
+            && let Some((decoder, transform)) = self.video.as_mut()
+        {
+            for frame in decoder.decode(packet).unwrap_or_default() {
+                let Ok(bgra) = transform.transform(&frame) else {
+                    continue;
+                };
+                // BT.601 luma, with the weights in BGRA order and scaled by 256
+                let pixels = (bgra.data.len() / 4).max(1);
+                let luma: u64 = bgra
+                    .data
+                    .chunks_exact(4)
+                    .map(|p| (p[0] as u64 * 29 + p[1] as u64 * 150 + p[2] as u64 * 77) >> 8)
+                    .sum();
+                let mean = luma as f32 / pixels as f32 / 255.0;
+                boundary = boundary.or(self.detector.video(time, mean));
+            }
+        }
+
+        if packet.stream_type == StreamType::Audio
+            && let Some((decoder, transform)) = self.audio.as_mut()
+        {
+            for frame in decoder.decode(packet).unwrap_or_default() {
+                let Ok(transformed) = transform.transform(&frame) else {
+                    continue;
+                };
+                let samples = samples_from_bytes(&transformed.data);
+                let power =
+                    samples.iter().map(|s| s * s).sum::<f32>() / samples.len().max(1) as f32;
+                boundary = boundary.or(self.detector.audio(time, power.sqrt()));
+            }
+        }
+
+        boundary
+    }
+}
+
+/**
+    First pass of two-pass normalization: decode all audio of the input
+    and measure its integrated loudness.
+
+    Returns None if the input has no measurable audio, or if it doesn't
+    end within a reasonable time (i.e. it is live and can't be measured).
+*/
+pub async fn measure_loudness(input_url: &str, config: SourceConfig) -> Result<Option<f64>, Error> {
+    let mut source = Source::open(
+        input_url,
+        SourceConfig {
+            stream_filter: Some(StreamFilter::AudioOnly),
+            ..config
+        },
+    )
+    .await?;
+
+    let mut decoder = StreamCodec::audio(&mut source)?.audio_decoder()?;
+    let mut transform = AudioTransform::new(AudioTransformConfig::playback());
+    let mut meter = LoudnessMeter::new(CHANNELS);
+
+    let started = Instant::now();
+    while let Some(packet) = source.next_packet()? {
+        if started.elapsed() > MEASURE_MAX_WAIT {
+            return Ok(None);
+        }
+        if packet.stream_type != StreamType::Audio {
+            continue;
+        }
+        for frame in decoder.decode(&packet).unwrap_or_default() {
+            if let Ok(transformed) = transform.transform(&frame) {
+                meter.push(&samples_from_bytes(&transformed.data));
+            }
+        }
+    }
+
+    Ok(meter.integrated())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gap_tracker() {
+        let mut gaps = GapTracker::default();
+        let ms = Duration::from_millis;
+
+        // 960 samples is 20 ms at 48 kHz
+        assert_eq!(gaps.check(ms(1000), 960), None);
+        assert_eq!(gaps.check(ms(1020), 960), None);
+        // Jitter below the threshold is ignored
+        assert_eq!(gaps.check(ms(1045), 960), None);
+        // A lost segment
+        assert_eq!(gaps.check(ms(3065), 960), Some((ms(1065), ms(2000))));
+        // Jumps, backwards or too far forwards, are not gaps
+        assert_eq!(gaps.check(ms(500), 960), None);
+        assert_eq!(gaps.check(ms(60_000), 960), None);
+    }
+
+    #[test]
+    fn test_scaled_size() {
+        assert_eq!(scaled_size(1920, 1080, None), (1920, 1080));
+        assert_eq!(scaled_size(1920, 1080, Some(720)), (1280, 720));
+        // Never scaled up
+        assert_eq!(scaled_size(1280, 720, Some(1080)), (1280, 720));
+        // Odd sizes are rounded down to even ones
+        assert_eq!(scaled_size(1920, 1080, Some(361)), (640, 360));
+    }
+}
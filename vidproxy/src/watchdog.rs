@@ -0,0 +1,260 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::pipeline::PipelineStore;
+
+/**
+    Maximum number of incidents kept, older ones are dropped
+*/
+const MAX_INCIDENTS: usize = 200;
+
+/**
+    How often memory usage is checked against the limit
+*/
+const MEMORY_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/**
+    How long to wait before restarting a subsystem that panicked,
+    so that one that panics right away doesn't spin
+*/
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/**
+    What went wrong in a subsystem
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IncidentKind {
+    /// The subsystem panicked
+    Panic,
+    /// The process used more memory than allowed
+    MemoryLimit,
+}
+
+/**
+    A failure of a subsystem, and what was done to recover from it
+*/
+#[derive(Debug, Clone, Serialize)]
+pub struct Incident {
+    /// Unix timestamp of when it happened
+    pub at: u64,
+    /// Subsystem that failed, such as "discovery" or "pipeline:source:channel"
+    pub subsystem: String,
+    pub kind: IncidentKind,
+    pub message: String,
+    /// What was done about it
+    pub recovery: String,
+}
+
+/**
+    Log of the most recent incidents, served from the API.
+*/
+#[derive(Debug, Default)]
+pub struct Incidents {
+    entries: Mutex<VecDeque<Incident>>,
+}
+
+impl Incidents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+        Record an incident, dropping the oldest one if the log is full
+    */
+    pub fn record(
+        &self,
+        subsystem: impl Into<String>,
+        kind: IncidentKind,
+        message: impl Into<String>,
+        recovery: impl Into<String>,
+    ) {
+        let incident = Incident {
+            at: crate::time::now(),
+            subsystem: subsystem.into(),
+            kind,
+            message: message.into(),
+            recovery: recovery.into(),
+        };
+        eprintln!(
+            "[watchdog] {} failed: {} ({})",
+            incident.subsystem, incident.message, incident.recovery
+        );
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_INCIDENTS {
+            entries.pop_front();
+        }
+        entries.push_back(incident);
+    }
+
+    /**
+        Get the recorded incidents, newest first
+    */
+    pub fn list(&self) -> Vec<Incident> {
+        self.entries.lock().unwrap().iter().rev().cloned().collect()
+    }
+}
+
+/**
+    Run a subsystem as a task, starting it again whenever it panics,
+    until it returns on its own or the server shuts down.
+*/
+pub async fn supervise<F, Fut>(
+    subsystem: &str,
+    incidents: Arc<Incidents>,
+    mut start: F,
+    shutdown_rx: watch::Receiver<bool>,
+) where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    loop {
+        let error = match tokio::spawn(start()).await {
+            Err(e) if e.is_panic() => e,
+            _ => return,
+        };
+        if *shutdown_rx.borrow() {
+            return;
+        }
+        incidents.record(
+            subsystem,
+            IncidentKind::Panic,
+            error.to_string(),
+            format!("restarted after {}s", RESTART_DELAY.as_secs()),
+        );
+        tokio::time::sleep(RESTART_DELAY).await;
+    }
+}
+
+/**
+    Tracks the resident memory of the process against a limit.
+
+    Only reports crossing the limit, not staying above it, so that a
+    process that can't get back under it isn't recovered over and over.
+*/
+#[derive(Debug)]
+pub struct MemoryMonitor {
+    limit: u64,
+    over_limit: bool,
+}
+
+impl MemoryMonitor {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            over_limit: false,
+        }
+    }
+
+    /**
+        Check a memory reading, returning true if it just went over the limit
+    */
+    pub fn check(&mut self, resident: u64) -> bool {
+        let was_over = self.over_limit;
+        self.over_limit = resident > self.limit;
+        self.over_limit && !was_over
+    }
+
+    /**
+        Check memory usage periodically, stopping all pipelines when it goes
+        over the limit. The channel registry and its credentials are kept,
+        so pipelines start again fresh on the next request.
+    */
+    pub async fn run(
+        mut self,
+        pipeline_store: Arc<PipelineStore>,
+        incidents: Arc<Incidents>,
+        mut shutdown_rx: watch::Receiver<bool>,
+    ) {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(MEMORY_CHECK_INTERVAL) => {}
+                _ = shutdown_rx.changed() => {
+                    if *shutdown_rx.borrow() {
+                        return;
+                    }
+                }
+            }
+
+            let Some(resident) = resident_memory() else {
+                continue;
+            };
+            if self.check(resident) {
+                incidents.record(
+                    "memory",
+                    IncidentKind::MemoryLimit,
+                    format!(
+                        "Using {} MB, over the limit of {} MB",
+                        resident / 1024 / 1024,
+                        self.limit / 1024 / 1024
+                    ),
+                    "stopped all pipelines",
+                );
+                pipeline_store.stop_all().await;
+            }
+        }
+    }
+}
+
+/**
+    Get the resident memory of the process in bytes, where the OS reports it
+*/
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    parse_vm_rss(&status)
+}
+
+/**
+    Parse the resident memory in bytes from the contents of /proc/self/status
+*/
+fn parse_vm_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line
+        .trim_start_matches("VmRSS:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vm_rss() {
+        let status = "Name:\tvidproxy\nVmPeak:\t  900000 kB\nVmRSS:\t  123456 kB\nThreads:\t12\n";
+        assert_eq!(parse_vm_rss(status), Some(123456 * 1024));
+        assert_eq!(parse_vm_rss("Name:\tvidproxy\n"), None);
+    }
+
+    #[test]
+    fn test_memory_monitor_reports_crossing_once() {
+        let mut monitor = MemoryMonitor::new(1000);
+        assert!(!monitor.check(500));
+        assert!(monitor.check(1500));
+        assert!(!monitor.check(2000));
+        assert!(!monitor.check(800));
+        assert!(monitor.check(1200));
+    }
+
+    #[test]
+    fn test_incidents_newest_first_and_bounded() {
+        let incidents = Incidents::new();
+        for i in 0..MAX_INCIDENTS + 5 {
+            incidents.record("discovery", IncidentKind::Panic, i.to_string(), "restarted");
+        }
+        let list = incidents.list();
+        assert_eq!(list.len(), MAX_INCIDENTS);
+        assert_eq!(list[0].message, (MAX_INCIDENTS + 4).to_string());
+        assert_eq!(list.last().unwrap().message, "5");
+    }
+}
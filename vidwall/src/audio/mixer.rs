@@ -2,10 +2,11 @@ use std::sync::{
     Arc,
     atomic::{AtomicBool, Ordering},
 };
+use std::time::Duration;
 
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 
-use super::stream::{AtomicF32, AudioStreamConsumer};
+use super::stream::{AtomicF32, AudioStreamConsumer, AudioWake};
 
 /**
     Maximum number of audio streams the mixer supports
@@ -17,16 +18,28 @@ pub const MIXER_MAX_STREAMS: usize = 4;
 */
 const MIX_BUFFER_SIZE: usize = 4096;
 
+/**
+    Largest delay that aligning streams adds to one of them. Streams
+    further ahead than this aren't playing the same content late, and
+    are left as they are.
+*/
+pub const MAX_ALIGN_DELAY: Duration = Duration::from_millis(300);
+
 /**
     Audio mixer that combines multiple audio streams into a single output.
-    Supports per-stream volume (via AudioStreamConsumer), master volume, and master mute.
+    Supports per-stream volume (via AudioStreamConsumer), per-stream delay,
+    master volume, and master mute.
 
     Designed for real-time audio: uses RwLock with try_read to avoid blocking.
 */
 pub struct AudioMixer {
     streams: RwLock<Vec<Option<Arc<AudioStreamConsumer>>>>,
+    /// Delay applied to each stream slot, reset when the slot changes
+    delays: Mutex<[Duration; MIXER_MAX_STREAMS]>,
     master_volume: AtomicF32,
     master_muted: AtomicBool,
+    /// Notified when the mix may have become audible
+    wake: Arc<AudioWake>,
     sample_rate: u32,
     channels: u16,
 }
@@ -35,8 +48,10 @@ impl AudioMixer {
     pub fn new(sample_rate: u32, channels: u16) -> Self {
         Self {
             streams: RwLock::new(Vec::new()),
+            delays: Mutex::new([Duration::ZERO; MIXER_MAX_STREAMS]),
             master_volume: AtomicF32::new(1.0),
             master_muted: AtomicBool::new(false),
+            wake: Arc::new(AudioWake::default()),
             sample_rate,
             channels,
         }
@@ -69,6 +84,7 @@ impl AudioMixer {
     pub fn set_master_volume(&self, volume: f32) {
         self.master_volume
             .store(volume.clamp(0.0, 1.0), Ordering::Relaxed);
+        self.wake.notify();
     }
 
     /**
@@ -83,6 +99,7 @@ impl AudioMixer {
     */
     pub fn unmute(&self) {
         self.master_muted.store(false, Ordering::Relaxed);
+        self.wake.notify();
     }
 
     /**
@@ -91,6 +108,7 @@ impl AudioMixer {
     pub fn toggle_mute(&self) -> bool {
         let was_muted = self.master_muted.load(Ordering::Relaxed);
         self.master_muted.store(!was_muted, Ordering::Relaxed);
+        self.wake.notify();
         !was_muted
     }
 
@@ -114,7 +132,12 @@ impl AudioMixer {
         while streams.len() <= index {
             streams.push(None);
         }
+        if let Some(ref stream) = stream {
+            stream.set_wake(Some(Arc::clone(&self.wake)));
+        }
         streams[index] = stream;
+        self.delays.lock()[index] = Duration::ZERO;
+        self.wake.notify();
     }
 
     /**
@@ -131,6 +154,88 @@ impl AudioMixer {
     pub fn clear_streams(&self) {
         let mut streams = self.streams.write();
         streams.clear();
+        *self.delays.lock() = [Duration::ZERO; MIXER_MAX_STREAMS];
+    }
+
+    /**
+        Get the delay applied to the stream at the given index
+    */
+    pub fn stream_delay(&self, index: usize) -> Duration {
+        self.delays.lock().get(index).copied().unwrap_or_default()
+    }
+
+    /**
+        Delay the stream at the given index relative to the other streams,
+        to compensate for sources with different latency.
+
+        The change from the current delay is applied to the stream right away,
+        as silence (delay grew) or skipped samples (delay shrank), so the
+        stream's clock and video follow. Delays are sample-accurate and reset
+        when the stream at the index is replaced.
+    */
+    pub fn set_stream_delay(&self, index: usize, delay: Duration) {
+        let Some(stream) = self.stream(index) else {
+            return;
+        };
+        let mut delays = self.delays.lock();
+        let previous = self.delay_samples(delays[index]);
+        stream.shift(self.delay_samples(delay) - previous);
+        delays[index] = delay;
+    }
+
+    /**
+        Align a sync group of streams, given by their indices, using their
+        clocks. Every active stream in the group that is ahead of the furthest
+        behind one is delayed by the difference, added to its existing delay.
+
+        Streams more than [`MAX_ALIGN_DELAY`] ahead are skipped, and their
+        indices returned.
+    */
+    pub fn align_streams(&self, group: &[usize]) -> Vec<usize> {
+        let positions = {
+            let streams = self.streams.read();
+            group
+                .iter()
+                .filter_map(|&index| {
+                    let stream = streams.get(index)?.as_ref()?;
+                    (!stream.is_paused() && !stream.is_ended())
+                        .then(|| (index, stream.clock().position()))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let (offsets, skipped) = alignment_offsets(&positions);
+        for (index, offset) in offsets {
+            let delay = self.stream_delay(index) + offset;
+            self.set_stream_delay(index, delay);
+        }
+        skipped
+    }
+
+    /**
+        Convert a delay to a number of interleaved output samples
+    */
+    fn delay_samples(&self, delay: Duration) -> i64 {
+        let frames = (delay.as_secs_f64() * self.sample_rate as f64).round() as i64;
+        frames * self.channels as i64
+    }
+
+    /**
+        Check if any stream has audio to play
+    */
+    pub fn has_pending_audio(&self) -> bool {
+        self.streams
+            .read()
+            .iter()
+            .flatten()
+            .any(|stream| stream.has_pending_audio())
+    }
+
+    /**
+        Get the wake notified when the mix may have become audible
+    */
+    pub(crate) fn wake(&self) -> Arc<AudioWake> {
+        Arc::clone(&self.wake)
     }
 
     /**
@@ -203,3 +308,57 @@ impl AudioMixer {
         }
     }
 }
+
+/**
+    Split stream positions into how far each stream is ahead of the furthest
+    behind one, for streams within [`MAX_ALIGN_DELAY`] of it, and the indices
+    of streams that are further ahead.
+*/
+fn alignment_offsets(positions: &[(usize, Duration)]) -> (Vec<(usize, Duration)>, Vec<usize>) {
+    let Some(behind) = positions.iter().map(|(_, position)| *position).min() else {
+        return (Vec::new(), Vec::new());
+    };
+
+    let mut offsets = Vec::new();
+    let mut skipped = Vec::new();
+    for &(index, position) in positions {
+        let offset = position - behind;
+        if offset > MAX_ALIGN_DELAY {
+            skipped.push(index);
+        } else if !offset.is_zero() {
+            offsets.push((index, offset));
+        }
+    }
+    (offsets, skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_alignment_offsets() {
+        let positions = [(0, ms(10_000)), (1, ms(10_120)), (3, ms(10_250))];
+        let (offsets, skipped) = alignment_offsets(&positions);
+        assert_eq!(offsets, vec![(1, ms(120)), (3, ms(250))]);
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn test_alignment_skips_distant_streams() {
+        // A stream minutes ahead is playing something else, not lagging
+        let positions = [(0, ms(10_000)), (1, ms(10_100)), (2, ms(190_000))];
+        let (offsets, skipped) = alignment_offsets(&positions);
+        assert_eq!(offsets, vec![(1, ms(100))]);
+        assert_eq!(skipped, vec![2]);
+    }
+
+    #[test]
+    fn test_alignment_empty_group() {
+        assert_eq!(alignment_offsets(&[]), (Vec::new(), Vec::new()));
+    }
+}
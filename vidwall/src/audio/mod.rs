@@ -2,7 +2,7 @@ mod mixer;
 mod output;
 mod stream;
 
-pub use mixer::{AudioMixer, MIXER_MAX_STREAMS};
+pub use mixer::{AudioMixer, MAX_ALIGN_DELAY, MIXER_MAX_STREAMS};
 pub use output::{AudioError, AudioOutput, DEFAULT_CHANNELS, DEFAULT_SAMPLE_RATE};
 pub use stream::{
     AudioStreamClock, AudioStreamConsumer, AudioStreamProducer, create_audio_stream,
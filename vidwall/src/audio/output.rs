@@ -1,4 +1,10 @@
-use std::sync::Arc;
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+    mpsc,
+};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use cpal::{
     BufferSize, SampleRate, Stream, StreamConfig,
@@ -6,6 +12,7 @@ use cpal::{
 };
 
 use super::mixer::AudioMixer;
+use super::stream::AudioWake;
 
 /**
     Default sample rate for audio output
@@ -22,6 +29,28 @@ pub const DEFAULT_CHANNELS: u16 = 2;
 */
 pub const DEFAULT_BUFFER_SIZE: u32 = 1024;
 
+/**
+    Default time of silence after which the output device is suspended
+*/
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/**
+    Samples below this level count as silence
+*/
+const SILENCE_THRESHOLD: f32 = 1.0 / 32768.0;
+
+/**
+    How often the output checks for silence while the device is running
+*/
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/**
+    How often the mixer is drained while the device is suspended and
+    streams have audio to play. With nothing to play, the device thread
+    parks until a stream gets audio or the mix changes.
+*/
+const SUSPENDED_TICK: Duration = Duration::from_millis(20);
+
 /**
     Error type for audio output operations
 */
@@ -44,12 +73,37 @@ impl std::fmt::Display for AudioError {
 
 impl std::error::Error for AudioError {}
 
+/**
+    State shared between the output callback, the device thread and the handle
+*/
+struct OutputShared {
+    /// Last time the callback produced audible output
+    last_audible: parking_lot::Mutex<Instant>,
+    /// Set while the device thread drains the mixer instead of the callback
+    suspended: AtomicBool,
+    /// Held while the mixer is drained, since stream consumers only support
+    /// a single reader at a time. The callback only tries to take it, and
+    /// the device thread takes it after pausing the stream, which waits out
+    /// a callback that was already running when the stream was paused
+    reader: parking_lot::Mutex<()>,
+    stop: AtomicBool,
+    /// The mixer's wake, to stop the device thread while it is parked
+    wake: Arc<AudioWake>,
+}
+
 /**
     Audio output device manager using cpal.
     Manages the audio stream and calls the mixer to fill buffers.
+
+    When the mixer output has been silent for the idle timeout (all streams
+    muted, paused or closed), the device stream is suspended so the system
+    can reach low-power states. While suspended, the mixer is still drained
+    in real time so that stream clocks (and video) keep advancing, and the
+    device resumes as soon as the mix is audible again. When no stream has
+    audio to play, the device thread parks instead of waking up to drain.
 */
 pub struct AudioOutput {
-    _stream: Stream,
+    shared: Arc<OutputShared>,
 }
 
 impl AudioOutput {
@@ -63,48 +117,214 @@ impl AudioOutput {
             DEFAULT_SAMPLE_RATE,
             DEFAULT_CHANNELS,
             DEFAULT_BUFFER_SIZE,
+            Some(DEFAULT_IDLE_TIMEOUT),
         )
     }
 
     /**
         Create a new audio output with custom configuration.
+        The device is never suspended if `idle_timeout` is `None`.
     */
     pub fn with_config(
         mixer: Arc<AudioMixer>,
         sample_rate: u32,
         channels: u16,
         buffer_size: u32,
+        idle_timeout: Option<Duration>,
     ) -> Result<Self, AudioError> {
-        let host = cpal::default_host();
+        let shared = Arc::new(OutputShared {
+            last_audible: parking_lot::Mutex::new(Instant::now()),
+            suspended: AtomicBool::new(false),
+            reader: parking_lot::Mutex::new(()),
+            stop: AtomicBool::new(false),
+            wake: mixer.wake(),
+        });
 
-        let device = host.default_output_device().ok_or(AudioError::NoDevice)?;
+        // The stream is not Send on all platforms, so it is created and
+        // owned by the thread that suspends and resumes it
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let thread_shared = Arc::clone(&shared);
+        thread::spawn(move || {
+            let stream = match build_stream(
+                Arc::clone(&mixer),
+                Arc::clone(&thread_shared),
+                sample_rate,
+                channels,
+                buffer_size,
+            ) {
+                Ok(stream) => {
+                    let _ = ready_tx.send(Ok(()));
+                    stream
+                }
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                    return;
+                }
+            };
+            run_device(
+                stream,
+                mixer,
+                thread_shared,
+                sample_rate,
+                channels,
+                buffer_size,
+                idle_timeout,
+            );
+        });
 
-        eprintln!("Audio device: {}", device.name().unwrap_or_default());
+        ready_rx
+            .recv()
+            .map_err(|e| AudioError::StreamError(e.to_string()))??;
 
-        let config = StreamConfig {
-            channels,
-            sample_rate: SampleRate(sample_rate),
-            buffer_size: BufferSize::Fixed(buffer_size),
+        Ok(Self { shared })
+    }
+}
+
+impl Drop for AudioOutput {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        self.shared.wake.notify();
+    }
+}
+
+fn build_stream(
+    mixer: Arc<AudioMixer>,
+    shared: Arc<OutputShared>,
+    sample_rate: u32,
+    channels: u16,
+    buffer_size: u32,
+) -> Result<Stream, AudioError> {
+    let host = cpal::default_host();
+
+    let device = host.default_output_device().ok_or(AudioError::NoDevice)?;
+
+    eprintln!("Audio device: {}", device.name().unwrap_or_default());
+
+    let config = StreamConfig {
+        channels,
+        sample_rate: SampleRate(sample_rate),
+        buffer_size: BufferSize::Fixed(buffer_size),
+    };
+
+    let stream = device
+        .build_output_stream(
+            &config,
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                // Stream consumers only support a single reader at a time
+                let reader = shared.reader.try_lock();
+                if reader.is_none() || shared.suspended.load(Ordering::Acquire) {
+                    data.fill(0.0);
+                    return;
+                }
+                // Fill the buffer directly - mixer uses lock-free reads
+                mixer.fill_buffer(data);
+                drop(reader);
+                if !is_silent(data)
+                    && let Some(mut last_audible) = shared.last_audible.try_lock()
+                {
+                    *last_audible = Instant::now();
+                }
+            },
+            |err| {
+                eprintln!("Audio stream error: {}", err);
+            },
+            None,
+        )
+        .map_err(|e| AudioError::StreamError(e.to_string()))?;
+
+    stream
+        .play()
+        .map_err(|e| AudioError::StreamError(e.to_string()))?;
+
+    Ok(stream)
+}
+
+/**
+    Suspend the device stream after the idle timeout of silence, and resume
+    it when the mix is audible again. Runs until the output is dropped.
+*/
+fn run_device(
+    stream: Stream,
+    mixer: Arc<AudioMixer>,
+    shared: Arc<OutputShared>,
+    sample_rate: u32,
+    channels: u16,
+    buffer_size: u32,
+    idle_timeout: Option<Duration>,
+) {
+    let mut buffer = vec![0.0f32; buffer_size as usize * channels as usize];
+    // Start of the suspension and the frames drained since
+    let mut suspended: Option<(Instant, u64)> = None;
+
+    while !shared.stop.load(Ordering::Relaxed) {
+        let Some((since, drained)) = suspended.as_mut() else {
+            thread::sleep(IDLE_CHECK_INTERVAL);
+            let idle = shared.last_audible.lock().elapsed();
+            if let Some(timeout) = idle_timeout
+                && idle >= timeout
+            {
+                shared.suspended.store(true, Ordering::Release);
+                match stream.pause() {
+                    Ok(()) => {
+                        eprintln!("Audio output suspended after {:.0?} of silence", idle);
+                        suspended = Some((Instant::now(), 0));
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to suspend audio output: {}", e);
+                        shared.suspended.store(false, Ordering::Release);
+                    }
+                }
+            }
+            continue;
         };
 
-        let stream = device
-            .build_output_stream(
-                &config,
-                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                    // Fill the buffer directly - mixer uses lock-free reads
-                    mixer.fill_buffer(data);
-                },
-                |err| {
-                    eprintln!("Audio stream error: {}", err);
-                },
-                None,
-            )
-            .map_err(|e| AudioError::StreamError(e.to_string()))?;
-
-        stream
-            .play()
-            .map_err(|e| AudioError::StreamError(e.to_string()))?;
-
-        Ok(Self { _stream: stream })
+        // Drain in real time while streams have audio to play, so their clocks keep
+        // advancing smoothly, and otherwise park until a stream gets some
+        let pending = mixer.has_pending_audio();
+        shared.wake.wait(pending.then_some(SUSPENDED_TICK));
+
+        // Drain the frames the device would have played since the last tick. Nothing
+        // was due while parked, so only the tick since waking up is drained after it
+        let due = (since.elapsed().as_secs_f64() * sample_rate as f64) as u64;
+        if !pending {
+            let tick = (SUSPENDED_TICK.as_secs_f64() * sample_rate as f64) as u64;
+            *drained = (*drained).max(due.saturating_sub(tick));
+        }
+        // Pausing doesn't wait for a callback that is already running, so
+        // the mixer is only drained once that callback has let go of it
+        let reader = shared.reader.lock();
+        let mut audible = false;
+        while *drained < due && !audible {
+            let frames = ((due - *drained) as usize).min(buffer.len() / channels as usize);
+            let chunk = &mut buffer[..frames * channels as usize];
+            mixer.fill_buffer(chunk);
+            audible = !is_silent(chunk);
+            *drained += frames as u64;
+        }
+        drop(reader);
+
+        if audible {
+            shared.suspended.store(false, Ordering::Release);
+            match stream.play() {
+                Ok(()) => {
+                    eprintln!("Audio output resumed");
+                    *shared.last_audible.lock() = Instant::now();
+                    suspended = None;
+                }
+                Err(e) => {
+                    eprintln!("Failed to resume audio output: {}", e);
+                    shared.suspended.store(true, Ordering::Release);
+                }
+            }
+        }
     }
 }
+
+/**
+    Check if a buffer of samples is silent
+*/
+fn is_silent(samples: &[f32]) -> bool {
+    samples
+        .iter()
+        .all(|sample| sample.abs() < SILENCE_THRESHOLD)
+}
@@ -1,12 +1,13 @@
 use std::cell::UnsafeCell;
+use std::collections::VecDeque;
 use std::sync::{
     Arc,
-    atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, Ordering},
 };
 use std::thread;
 use std::time::{Duration, Instant};
 
-use parking_lot::Mutex;
+use parking_lot::{Condvar, Mutex};
 use ringbuf::{
     HeapRb,
     traits::{Consumer, Observer, Producer, Split},
@@ -35,6 +36,55 @@ impl AtomicF32 {
     }
 }
 
+/**
+    Wakes a thread waiting for audio to play, such as a suspended
+    output device waiting for its mix to become audible again.
+*/
+#[derive(Default)]
+pub(crate) struct AudioWake {
+    pending: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl AudioWake {
+    /**
+        Wake the waiting thread, or the next one to wait if none is waiting
+    */
+    pub fn notify(&self) {
+        *self.pending.lock() = true;
+        self.condvar.notify_all();
+    }
+
+    /**
+        Wait until notified, or until the timeout passes if there is one.
+        Returns right away if notified since the last wait.
+    */
+    pub fn wait(&self, timeout: Option<Duration>) {
+        let mut pending = self.pending.lock();
+        if !*pending {
+            match timeout {
+                Some(timeout) => {
+                    self.condvar.wait_for(&mut pending, timeout);
+                }
+                None => self.condvar.wait(&mut pending),
+            }
+        }
+        *pending = false;
+    }
+}
+
+/**
+    The wake to notify when a stream gets audio to play, set by the mixer
+    playing it and shared between the producer and consumer halves
+*/
+type WakeSlot = Arc<Mutex<Option<Arc<AudioWake>>>>;
+
+fn notify_slot(slot: &WakeSlot) {
+    if let Some(wake) = slot.lock().as_ref() {
+        wake.notify();
+    }
+}
+
 /**
     Default ring buffer size (~2 seconds of stereo audio at 48kHz)
 */
@@ -50,6 +100,12 @@ const DEFAULT_SAMPLE_RATE: u32 = 48000;
 */
 const DEFAULT_CHANNELS: u16 = 2;
 
+/**
+    Maximum number of frame anchors waiting to be played, far more
+    than the frames that fit in the ring buffer
+*/
+const MAX_PENDING_ANCHORS: usize = 4096;
+
 /**
     Audio clock that tracks playback position based on samples consumed.
     This is shared between the audio consumer and video player for A/V sync.
@@ -59,16 +115,41 @@ const DEFAULT_CHANNELS: u16 = 2;
 
     When audio playback finishes (all samples consumed), the clock automatically
     switches to wall-time-based extrapolation so video frames continue to advance.
+
+    If the producer reports the PTS of the frames it pushes, the position is
+    anchored to the PTS of the frame being played instead, so audio frames
+    that were dropped or never decoded don't desync video from then on.
 */
 pub struct AudioStreamClock {
     /// Total number of samples consumed (interleaved, so L+R = 2 samples)
     samples_consumed: AtomicU64,
+    /// Total number of samples reported by the producer, in the same count
+    samples_produced: AtomicU64,
     sample_rate: u32,
     channels: u16,
+    /// Frame PTS anchors, reported by the producer and reached by the consumer
+    anchors: Mutex<Anchors>,
     /// When audio finishes, we record the position and wall time to extrapolate from
     finished_state: Mutex<Option<FinishedState>>,
 }
 
+/**
+    The PTS of a frame, and where its first sample is in the stream
+*/
+#[derive(Debug, Clone, Copy)]
+struct ClockAnchor {
+    sample: u64,
+    pts: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Anchors {
+    /// Anchors of frames not played yet, in stream order
+    pending: VecDeque<ClockAnchor>,
+    /// Anchor of the frame most recently started playing
+    current: Option<ClockAnchor>,
+}
+
 /**
     State recorded when audio playback finishes
 */
@@ -86,12 +167,53 @@ impl AudioStreamClock {
     pub fn new(sample_rate: u32, channels: u16) -> Self {
         Self {
             samples_consumed: AtomicU64::new(0),
+            samples_produced: AtomicU64::new(0),
             sample_rate,
             channels,
+            anchors: Mutex::new(Anchors::default()),
             finished_state: Mutex::new(None),
         }
     }
 
+    /**
+        Convert an interleaved sample count to a duration
+    */
+    fn samples_to_duration(&self, samples: u64) -> Duration {
+        // samples is interleaved (L,R,L,R...), so divide by channels to get audio frames
+        let audio_frames = samples / self.channels as u64;
+        Duration::from_secs_f64(audio_frames as f64 / self.sample_rate as f64)
+    }
+
+    /**
+        Position of the sample being played, from the current anchor
+        if there is one, or the count of samples consumed otherwise
+    */
+    fn playing_position(&self) -> Duration {
+        let samples = self.samples_consumed.load(Ordering::Relaxed);
+        match self.anchors.lock().current {
+            Some(anchor) => anchor.pts + self.samples_to_duration(samples - anchor.sample),
+            None => self.samples_to_duration(samples),
+        }
+    }
+
+    /**
+        Report a frame about to be pushed by the producer: its PTS, if known,
+        and its number of (interleaved) samples. Once playback reaches the
+        frame, the clock's position follows its PTS.
+    */
+    pub fn report_frame(&self, pts: Option<Duration>, samples: u64) {
+        let sample = self.samples_produced.fetch_add(samples, Ordering::Relaxed);
+        let Some(pts) = pts else {
+            return;
+        };
+
+        let mut anchors = self.anchors.lock();
+        if anchors.pending.len() == MAX_PENDING_ANCHORS {
+            anchors.pending.pop_front();
+        }
+        anchors.pending.push_back(ClockAnchor { sample, pts });
+    }
+
     /**
         Get the current playback position as a Duration.
         This is the primary method for A/V sync - video should display
@@ -108,11 +230,7 @@ impl AudioStreamClock {
         }
 
         // Normal case: return position based on samples consumed
-        let samples = self.samples_consumed.load(Ordering::Relaxed);
-        // samples is interleaved (L,R,L,R...), so divide by channels to get audio frames
-        let audio_frames = samples / self.channels as u64;
-        // Convert audio frames to duration
-        Duration::from_secs_f64(audio_frames as f64 / self.sample_rate as f64)
+        self.playing_position()
     }
 
     /**
@@ -123,13 +241,8 @@ impl AudioStreamClock {
     pub(crate) fn mark_finished(&self) {
         let mut finished = self.finished_state.lock();
         if finished.is_none() {
-            let current_position = {
-                let samples = self.samples_consumed.load(Ordering::Relaxed);
-                let audio_frames = samples / self.channels as u64;
-                Duration::from_secs_f64(audio_frames as f64 / self.sample_rate as f64)
-            };
             *finished = Some(FinishedState {
-                position_at_finish: current_position,
+                position_at_finish: self.playing_position(),
                 wall_time_at_finish: Instant::now(),
             });
         }
@@ -142,11 +255,24 @@ impl AudioStreamClock {
         self.samples_consumed.load(Ordering::Relaxed)
     }
 
+    /**
+        Check if the audio stream has finished, and the position follows wall time
+    */
+    pub fn is_finished(&self) -> bool {
+        self.finished_state.lock().is_some()
+    }
+
     /**
         Add to the consumed sample count. Called by AudioStreamConsumer.
     */
     pub(crate) fn add_samples(&self, count: u64) {
-        self.samples_consumed.fetch_add(count, Ordering::Relaxed);
+        let consumed = self.samples_consumed.fetch_add(count, Ordering::Relaxed) + count;
+
+        // Move on to the last frame that started playing
+        let mut anchors = self.anchors.lock();
+        while anchors.pending.front().is_some_and(|a| a.sample < consumed) {
+            anchors.current = anchors.pending.pop_front();
+        }
     }
 
     /**
@@ -166,13 +292,16 @@ impl AudioStreamClock {
     /**
         Reset the clock to a specific position (for seeking).
         This sets the samples_consumed to match the target position
-        and clears any finished state.
+        and clears any finished state and frame anchors, since the
+        ring buffer is replaced along with the clock being reset.
     */
     pub fn reset_to(&self, position: Duration) {
         // Calculate how many samples correspond to this position
         let audio_frames = (position.as_secs_f64() * self.sample_rate as f64) as u64;
         let samples = audio_frames * self.channels as u64;
         self.samples_consumed.store(samples, Ordering::Relaxed);
+        self.samples_produced.store(samples, Ordering::Relaxed);
+        *self.anchors.lock() = Anchors::default();
         // Clear finished state so clock resumes normal operation
         *self.finished_state.lock() = None;
     }
@@ -189,6 +318,10 @@ pub struct AudioStreamProducer {
     producer: UnsafeCell<ringbuf::HeapProd<f32>>,
     /// Shared with consumer to signal end of stream
     closed: Arc<AtomicBool>,
+    /// Shared clock, to report the PTS of pushed frames to
+    clock: Arc<AudioStreamClock>,
+    /// Shared with consumer, notified when the stream gets audio to play
+    wake: WakeSlot,
 }
 
 // SAFETY: HeapProd is safe to send between threads.
@@ -210,9 +343,15 @@ impl AudioStreamProducer {
 
             // SAFETY: Only one thread (decoder) calls push, and ringbuf's
             // producer is designed to work independently from consumer.
+            let was_empty = unsafe { (*self.producer.get()).is_empty() };
             let written = unsafe { (*self.producer.get()).push_slice(&samples[offset..]) };
             offset += written;
 
+            // A suspended output parks while there's nothing to play
+            if was_empty && written > 0 {
+                notify_slot(&self.wake);
+            }
+
             if offset < samples.len() {
                 // Buffer full, wait a bit for consumer to drain
                 thread::sleep(Duration::from_micros(500));
@@ -221,6 +360,16 @@ impl AudioStreamProducer {
         true
     }
 
+    /**
+        Push the samples of a decoded frame, reporting its PTS to the clock
+        so that playback position follows the frames actually played.
+        Returns false if the producer was closed while waiting.
+    */
+    pub fn push_frame(&self, pts: Option<Duration>, samples: &[f32]) -> bool {
+        self.clock.report_frame(pts, samples.len() as u64);
+        self.push(samples)
+    }
+
     /**
         Check if there's space for more samples
     */
@@ -234,6 +383,7 @@ impl AudioStreamProducer {
     */
     pub fn close(&self) {
         self.closed.store(true, Ordering::Release);
+        notify_slot(&self.wake);
     }
 
     /**
@@ -259,8 +409,13 @@ pub struct AudioStreamConsumer {
     paused: AtomicBool,
     /// When muted, fill_buffer outputs silence but still consumes samples
     muted: AtomicBool,
+    /// Pending playback shift in interleaved samples: silence still to output
+    /// before resuming if positive, samples still to drop if negative
+    shift: AtomicI64,
     /// Shared clock for tracking playback position
     clock: Arc<AudioStreamClock>,
+    /// Shared with producer, notified when the stream gets audio to play
+    wake: WakeSlot,
 }
 
 // SAFETY: HeapCons is safe to send between threads.
@@ -288,6 +443,7 @@ impl AudioStreamConsumer {
     */
     pub fn set_volume(&self, volume: f32) {
         self.volume.store(volume.clamp(0.0, 1.0), Ordering::Relaxed);
+        notify_slot(&self.wake);
     }
 
     /**
@@ -303,6 +459,7 @@ impl AudioStreamConsumer {
     */
     pub fn resume(&self) {
         self.paused.store(false, Ordering::Relaxed);
+        notify_slot(&self.wake);
     }
 
     /**
@@ -325,6 +482,7 @@ impl AudioStreamConsumer {
     */
     pub fn unmute(&self) {
         self.muted.store(false, Ordering::Relaxed);
+        notify_slot(&self.wake);
     }
 
     /**
@@ -334,6 +492,7 @@ impl AudioStreamConsumer {
         // Note: This isn't perfectly atomic but is fine for UI toggling
         let was_muted = self.muted.load(Ordering::Relaxed);
         self.muted.store(!was_muted, Ordering::Relaxed);
+        notify_slot(&self.wake);
         !was_muted
     }
 
@@ -344,6 +503,16 @@ impl AudioStreamConsumer {
         self.muted.load(Ordering::Relaxed)
     }
 
+    /**
+        Shift playback by a number of interleaved samples, for delay compensation.
+        Positive values delay the stream by outputting silence before resuming,
+        negative values advance it by dropping samples. The clock only counts
+        dropped samples, so video follows the shift.
+    */
+    pub fn shift(&self, samples: i64) {
+        self.shift.fetch_add(samples, Ordering::AcqRel);
+    }
+
     /**
         Check if the stream has ended
     */
@@ -357,6 +526,23 @@ impl AudioStreamConsumer {
     */
     pub fn mark_closed(&self) {
         self.closed.store(true, Ordering::Release);
+        notify_slot(&self.wake);
+    }
+
+    /**
+        Check if the stream has audio to play, or has ended without its clock
+        finishing yet. Paused streams have nothing to play until resumed.
+    */
+    pub fn has_pending_audio(&self) -> bool {
+        !self.is_paused()
+            && (self.available() > 0 || (self.is_ended() && !self.clock.is_finished()))
+    }
+
+    /**
+        Set the wake to notify when the stream gets audio to play
+    */
+    pub(crate) fn set_wake(&self, wake: Option<Arc<AudioWake>>) {
+        *self.wake.lock() = wake;
     }
 
     /**
@@ -389,6 +575,27 @@ impl AudioStreamConsumer {
             return 0;
         }
 
+        // Apply any pending shift before reading samples
+        let pending = self.shift.load(Ordering::Acquire);
+        if pending < 0 {
+            // SAFETY: Only the audio callback consumes, see below
+            let skipped = unsafe { (*self.consumer.get()).skip(pending.unsigned_abs() as usize) };
+            self.clock.add_samples(skipped as u64);
+            self.shift.fetch_add(skipped as i64, Ordering::AcqRel);
+        }
+        let silence = if pending > 0 {
+            (pending as usize).min(output.len())
+        } else {
+            0
+        };
+        if silence > 0 {
+            for sample in &mut output[..silence] {
+                *sample = 0.0;
+            }
+            self.shift.fetch_sub(silence as i64, Ordering::AcqRel);
+        }
+        let output = &mut output[silence..];
+
         let is_muted = self.muted.load(Ordering::Relaxed);
         let volume = self.volume();
 
@@ -464,11 +671,14 @@ pub fn create_audio_stream_with_clock(
 
     // Shared closed flag so consumer knows when producer is done
     let closed = Arc::new(AtomicBool::new(false));
+    let wake = WakeSlot::default();
 
     (
         AudioStreamProducer {
             producer: UnsafeCell::new(producer),
             closed: Arc::clone(&closed),
+            clock: Arc::clone(&clock),
+            wake: Arc::clone(&wake),
         },
         AudioStreamConsumer {
             consumer: UnsafeCell::new(consumer),
@@ -476,7 +686,66 @@ pub fn create_audio_stream_with_clock(
             closed,
             paused: AtomicBool::new(false),
             muted: AtomicBool::new(false),
+            shift: AtomicI64::new(0),
             clock,
+            wake,
         },
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Interleaved samples in 10ms of stereo audio at 48kHz
+    const TEN_MS: u64 = 960;
+
+    #[test]
+    fn test_counts_samples_without_anchors() {
+        let clock = AudioStreamClock::new(DEFAULT_SAMPLE_RATE, DEFAULT_CHANNELS);
+        clock.add_samples(TEN_MS * 3);
+        assert_eq!(clock.position(), Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_follows_frame_pts_across_gaps() {
+        let clock = AudioStreamClock::new(DEFAULT_SAMPLE_RATE, DEFAULT_CHANNELS);
+        clock.report_frame(Some(Duration::from_secs(5)), TEN_MS);
+        // A frame went missing between these two
+        clock.report_frame(Some(Duration::from_millis(5020)), TEN_MS);
+
+        clock.add_samples(TEN_MS / 2);
+        assert_eq!(clock.position(), Duration::from_millis(5005));
+        clock.add_samples(TEN_MS);
+        assert_eq!(clock.position(), Duration::from_millis(5025));
+    }
+
+    #[test]
+    fn test_push_wakes_only_when_empty() {
+        let (producer, consumer, _) = create_audio_stream();
+        let wake = Arc::new(AudioWake::default());
+        consumer.set_wake(Some(Arc::clone(&wake)));
+        assert!(!consumer.has_pending_audio());
+
+        producer.push(&[0.5; 4]);
+        assert!(*wake.pending.lock());
+        assert!(consumer.has_pending_audio());
+
+        wake.wait(None);
+        producer.push(&[0.5; 4]);
+        assert!(!*wake.pending.lock());
+    }
+
+    #[test]
+    fn test_reset_clears_anchors() {
+        let clock = AudioStreamClock::new(DEFAULT_SAMPLE_RATE, DEFAULT_CHANNELS);
+        clock.report_frame(Some(Duration::from_secs(5)), TEN_MS);
+        clock.add_samples(TEN_MS);
+
+        clock.reset_to(Duration::from_secs(1));
+        assert_eq!(clock.position(), Duration::from_secs(1));
+        clock.report_frame(Some(Duration::from_secs(1)), TEN_MS);
+        clock.add_samples(TEN_MS / 2);
+        assert_eq!(clock.position(), Duration::from_millis(1005));
+    }
+}
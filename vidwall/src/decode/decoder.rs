@@ -2,7 +2,7 @@ use std::path::Path;
 use std::ptr;
 use std::sync::{
     Arc,
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering},
     mpsc,
 };
 use std::time::Duration;
@@ -17,11 +17,16 @@ use ffmpeg_next::{
     util::frame::audio::Audio as AudioFrameFFmpeg,
     util::frame::video::Video as VideoFrameFFmpeg,
 };
+use serde::{Deserialize, Serialize};
 
 use crate::audio::{AudioStreamProducer, DEFAULT_SAMPLE_RATE};
 use crate::playback::{FrameQueue, VideoFrame};
 
 use super::packet_queue::{Packet, PacketQueue};
+use super::pts_repair::PtsRepair;
+
+/// Largest step back in video timestamps that is treated as jitter rather than a reset
+const PTS_JITTER_TOLERANCE: Duration = Duration::from_millis(500);
 
 /**
     Error type for video decoding operations
@@ -62,6 +67,7 @@ impl From<std::io::Error> for DecoderError {
 /**
     Information about a video file
 */
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VideoInfo {
     pub duration: Duration,
     pub width: u32,
@@ -87,6 +93,121 @@ pub struct VideoStreamInfo {
     pub height: u32,
 }
 
+/**
+    Options for decoding a video stream, trading fidelity for throughput
+*/
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VideoDecodeConfig {
+    /// Scale decoded frames to this width (default: the stream's width)
+    pub target_width: Option<u32>,
+    /// Scale decoded frames to this height (default: the stream's height)
+    pub target_height: Option<u32>,
+    /// Algorithm used to scale decoded frames
+    pub scaling: ScalingAlgorithm,
+    /// Only decode keyframes, for previews and seek scrubbing
+    pub keyframes_only: bool,
+    /// Decode at 1/2^n of the resolution (0-3), for codecs that support it
+    pub lowres: u8,
+}
+
+/**
+    Algorithm used when scaling decoded frames to their output size,
+    from fastest to sharpest
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScalingAlgorithm {
+    FastBilinear,
+    #[default]
+    Bilinear,
+    Bicubic,
+    Lanczos,
+}
+
+impl ScalingAlgorithm {
+    pub const ALL: [Self; 4] = [
+        Self::FastBilinear,
+        Self::Bilinear,
+        Self::Bicubic,
+        Self::Lanczos,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.to_name() == name)
+    }
+
+    pub const fn to_name(self) -> &'static str {
+        match self {
+            Self::FastBilinear => "fast-bilinear",
+            Self::Bilinear => "bilinear",
+            Self::Bicubic => "bicubic",
+            Self::Lanczos => "lanczos",
+        }
+    }
+
+    fn flags(self) -> ScalerFlags {
+        match self {
+            Self::FastBilinear => ScalerFlags::FAST_BILINEAR,
+            Self::Bilinear => ScalerFlags::BILINEAR,
+            Self::Bicubic => ScalerFlags::BICUBIC,
+            Self::Lanczos => ScalerFlags::LANCZOS,
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::FastBilinear,
+            2 => Self::Bicubic,
+            3 => Self::Lanczos,
+            _ => Self::Bilinear,
+        }
+    }
+}
+
+/**
+    Output size and scaling algorithm of a running video decoder.
+
+    Shared between the decode thread and the UI, so that tiles can change
+    them as they are resized. The decoder picks up changes on the next frame.
+*/
+#[derive(Debug)]
+pub struct VideoScaling {
+    /// Output width, or zero for the stream's width
+    width: AtomicU32,
+    /// Output height, or zero for the stream's height
+    height: AtomicU32,
+    algorithm: AtomicU8,
+}
+
+impl VideoScaling {
+    pub fn new(config: &VideoDecodeConfig) -> Self {
+        Self {
+            width: AtomicU32::new(config.target_width.unwrap_or(0)),
+            height: AtomicU32::new(config.target_height.unwrap_or(0)),
+            algorithm: AtomicU8::new(config.scaling as u8),
+        }
+    }
+
+    /**
+        Set the output size (`None` for the stream's size) and scaling algorithm
+    */
+    pub fn set(&self, size: Option<(u32, u32)>, algorithm: ScalingAlgorithm) {
+        let (width, height) = size.unwrap_or((0, 0));
+        self.width.store(width, Ordering::Relaxed);
+        self.height.store(height, Ordering::Relaxed);
+        self.algorithm.store(algorithm as u8, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> (u32, u32, ScalingAlgorithm) {
+        (
+            self.width.load(Ordering::Relaxed),
+            self.height.load(Ordering::Relaxed),
+            ScalingAlgorithm::from_u8(self.algorithm.load(Ordering::Relaxed)),
+        )
+    }
+}
+
 /**
     Get video info without fully opening for decoding
 */
@@ -177,7 +298,7 @@ pub fn get_video_stream_info<P: AsRef<Path>>(path: P) -> Result<VideoStreamInfo,
 /**
     Convert a PTS timestamp to Duration
 */
-fn pts_to_duration(pts: i64, time_base: Rational) -> Duration {
+pub(super) fn pts_to_duration(pts: i64, time_base: Rational) -> Duration {
     if pts < 0 {
         return Duration::ZERO;
     }
@@ -185,28 +306,41 @@ fn pts_to_duration(pts: i64, time_base: Rational) -> Duration {
     Duration::from_secs_f64(seconds.max(0.0))
 }
 
+/**
+    Check if a packet timestamp is at or after the end position, if any
+*/
+fn is_past_end(ts: Option<i64>, time_base: Rational, end_position: Option<Duration>) -> bool {
+    match (ts, end_position) {
+        (Some(ts), Some(end)) => pts_to_duration(ts, time_base) >= end,
+        _ => false,
+    }
+}
+
 /**
     Demux only audio packets from a video file.
     Opens its own file handle - completely independent from video demux.
     This is part of the separated pipeline architecture to prevent deadlocks.
 
     If `start_position` is provided, seeks to that position before demuxing.
+    If `end_position` is provided, stops at the first packet at or after it.
 */
 pub fn audio_demux<P: AsRef<Path>>(
     path: P,
     audio_packets: Arc<PacketQueue>,
     stop_flag: Arc<AtomicBool>,
     start_position: Option<Duration>,
+    end_position: Option<Duration>,
 ) -> Result<(), DecoderError> {
     ffmpeg_next::init()?;
 
     let mut input_ctx = input(&path)?;
 
-    let audio_stream_index = input_ctx
+    let audio_stream = input_ctx
         .streams()
         .best(Type::Audio)
-        .ok_or(DecoderError::NoAudioStream)?
-        .index();
+        .ok_or(DecoderError::NoAudioStream)?;
+    let audio_stream_index = audio_stream.index();
+    let time_base = audio_stream.time_base();
 
     // Seek to start position if specified
     if let Some(pos) = start_position {
@@ -225,6 +359,11 @@ pub fn audio_demux<P: AsRef<Path>>(
 
         // ONLY process audio packets - skip everything else
         if stream.index() == audio_stream_index {
+            if is_past_end(packet.pts(), time_base, end_position) {
+                eprintln!("[audio_demux] reached end position");
+                break;
+            }
+
             let pkt = Packet::new(
                 packet.data().map(|d| d.to_vec()).unwrap_or_default(),
                 packet.pts().unwrap_or(0),
@@ -256,6 +395,7 @@ pub fn audio_demux<P: AsRef<Path>>(
 
     If `start_position` is provided, seeks to that position before demuxing.
     If `actual_position_tx` is provided, sends the actual seek position (nearest keyframe).
+    If `end_position` is provided, stops at the first packet at or after it.
 */
 pub fn video_demux<P: AsRef<Path>>(
     path: P,
@@ -263,6 +403,7 @@ pub fn video_demux<P: AsRef<Path>>(
     stop_flag: Arc<AtomicBool>,
     start_position: Option<Duration>,
     actual_position_tx: Option<mpsc::Sender<Duration>>,
+    end_position: Option<Duration>,
 ) -> Result<(), DecoderError> {
     ffmpeg_next::init()?;
 
@@ -293,6 +434,13 @@ pub fn video_demux<P: AsRef<Path>>(
 
         // ONLY process video packets - skip everything else
         if stream.index() == video_stream_index {
+            // Packets are in decode order, every frame before the end
+            // position has been sent once the DTS reaches it
+            if is_past_end(packet.dts().or(packet.pts()), time_base, end_position) {
+                eprintln!("[video_demux] reached end position");
+                break;
+            }
+
             // Send actual position from first packet after seek
             if !actual_position_sent {
                 if let Some(ref tx) = actual_position_tx {
@@ -330,6 +478,65 @@ pub fn video_demux<P: AsRef<Path>>(
     Ok(())
 }
 
+/**
+    Demux the keyframe at or before each of the given positions of a video.
+
+    Seeks once per position and sends only the first video keyframe found,
+    so that decoding them gives one frame per position without decoding
+    anything in between. Positions that land on a keyframe already sent are
+    skipped, which happens for videos with few keyframes.
+*/
+pub fn keyframe_demux<P: AsRef<Path>>(
+    path: P,
+    video_packets: Arc<PacketQueue>,
+    stop_flag: Arc<AtomicBool>,
+    positions: &[Duration],
+) -> Result<(), DecoderError> {
+    ffmpeg_next::init()?;
+
+    let mut input_ctx = input(&path)?;
+
+    let video_stream_index = input_ctx
+        .streams()
+        .best(Type::Video)
+        .ok_or(DecoderError::NoVideoStream)?
+        .index();
+
+    let mut last_pts = None;
+    'positions: for pos in positions {
+        let ts = (pos.as_secs_f64() * ffmpeg_next::ffi::AV_TIME_BASE as f64) as i64;
+        input_ctx.seek(ts, ..ts)?;
+
+        for (stream, packet) in input_ctx.packets() {
+            if stop_flag.load(Ordering::Relaxed) {
+                break 'positions;
+            }
+            if stream.index() != video_stream_index || !packet.is_key() {
+                continue;
+            }
+            if packet.pts() == last_pts {
+                break;
+            }
+            last_pts = packet.pts();
+
+            let pkt = Packet::new(
+                packet.data().map(|d| d.to_vec()).unwrap_or_default(),
+                packet.pts().unwrap_or(0),
+                packet.dts().unwrap_or(0),
+                packet.duration(),
+                packet.flags().bits(),
+            );
+            if !video_packets.push(pkt) {
+                break 'positions;
+            }
+            break;
+        }
+    }
+
+    video_packets.close();
+    Ok(())
+}
+
 /**
     Create a VideoToolbox hardware device context (macOS only)
 */
@@ -380,6 +587,15 @@ fn transfer_hw_frame(hw_frame: &VideoFrameFFmpeg) -> Result<VideoFrameFFmpeg, De
     }
 }
 
+/**
+    Get the repaired presentation timestamp of a decoded video frame.
+*/
+fn repaired_pts(frame: &VideoFrameFFmpeg, repair: &mut PtsRepair) -> i64 {
+    let (dts, duration) = unsafe { ((*frame.as_ptr()).pkt_dts, (*frame.as_ptr()).duration) };
+    let dts = (dts != ffi::AV_NOPTS_VALUE).then_some(dts);
+    repair.repair(frame.pts(), dts, duration)
+}
+
 /**
     Decode video packets to frames.
     Runs until packet queue is closed and empty, or stop flag is set.
@@ -390,14 +606,39 @@ pub fn decode_video_packets(
     codec_params: codec::Parameters,
     time_base: Rational,
     stop_flag: Arc<AtomicBool>,
-    target_width: Option<u32>,
-    target_height: Option<u32>,
+    config: VideoDecodeConfig,
+    scaling: Arc<VideoScaling>,
 ) -> Result<(), DecoderError> {
     ffmpeg_next::init()?;
 
-    // Create decoder
-    let decoder_ctx = codec::context::Context::from_parameters(codec_params)?;
+    // Create decoder, lowres has to be set before it is opened
+    let mut decoder_ctx = codec::context::Context::from_parameters(codec_params)?;
+    if config.lowres > 0 {
+        unsafe {
+            let ctx = decoder_ctx.as_mut_ptr();
+            let codec = ffi::avcodec_find_decoder((*ctx).codec_id);
+            let max_lowres = if codec.is_null() {
+                0
+            } else {
+                (*codec).max_lowres
+            };
+            (*ctx).lowres = (config.lowres as i32).min(max_lowres as i32);
+            if (*ctx).lowres < config.lowres as i32 {
+                eprintln!(
+                    "[video_decode] codec supports lowres up to {}, requested {}",
+                    max_lowres, config.lowres
+                );
+            }
+        }
+    }
     let mut decoder = decoder_ctx.decoder().video()?;
+    if config.keyframes_only {
+        unsafe {
+            (*decoder.as_mut_ptr()).skip_frame = ffi::AVDiscard::AVDISCARD_NONKEY;
+        }
+    }
+
+    let mut pts_repair = PtsRepair::new(time_base, decoder.frame_rate(), PTS_JITTER_TOLERANCE);
 
     // Try hardware acceleration
     let hw_device_ctx = create_hw_device_ctx();
@@ -415,6 +656,7 @@ pub fn decode_video_packets(
     let mut scaler_src_format: Option<ffmpeg_next::format::Pixel> = None;
     let mut scaler_src_width: u32 = 0;
     let mut scaler_src_height: u32 = 0;
+    let mut scaler_output = (0, 0, ScalingAlgorithm::default());
 
     let mut decoded_frame = VideoFrameFFmpeg::empty();
     let mut bgra_frame = VideoFrameFFmpeg::empty();
@@ -475,14 +717,25 @@ pub fn decode_video_packets(
                 continue;
             }
 
+            let output = scaling.get();
             let needs_new_scaler = scaler.is_none()
                 || scaler_src_format != Some(src_format)
                 || scaler_src_width != src_width
-                || scaler_src_height != src_height;
+                || scaler_src_height != src_height
+                || scaler_output != output;
 
             if needs_new_scaler {
-                let dst_width = target_width.unwrap_or(src_width);
-                let dst_height = target_height.unwrap_or(src_height);
+                let (target_width, target_height, algorithm) = output;
+                let dst_width = if target_width > 0 {
+                    target_width
+                } else {
+                    src_width
+                };
+                let dst_height = if target_height > 0 {
+                    target_height
+                } else {
+                    src_height
+                };
 
                 // Ensure destination dimensions are also valid
                 if dst_width == 0 || dst_height == 0 {
@@ -500,13 +753,14 @@ pub fn decode_video_packets(
                     ffmpeg_next::format::Pixel::BGRA,
                     dst_width,
                     dst_height,
-                    ScalerFlags::BILINEAR,
+                    algorithm.flags(),
                 ) {
                     Ok(s) => {
                         scaler = Some(s);
                         scaler_src_format = Some(src_format);
                         scaler_src_width = src_width;
                         scaler_src_height = src_height;
+                        scaler_output = output;
                     }
                     Err(e) => {
                         eprintln!(
@@ -529,7 +783,7 @@ pub fn decode_video_packets(
             let dst_height = bgra_frame.height();
             let data = bgra_frame.data(0);
             let stride = bgra_frame.stride(0);
-            let pts = pts_to_duration(sw_frame.pts().unwrap_or(0), time_base);
+            let pts = pts_to_duration(repaired_pts(&decoded_frame, &mut pts_repair), time_base);
 
             // Copy data accounting for stride
             let mut bgra_data = Vec::with_capacity((dst_width * dst_height * 4) as usize);
@@ -585,7 +839,7 @@ pub fn decode_video_packets(
             let dst_height = bgra_frame.height();
             let data = bgra_frame.data(0);
             let stride = bgra_frame.stride(0);
-            let pts = pts_to_duration(sw_frame.pts().unwrap_or(0), time_base);
+            let pts = pts_to_duration(repaired_pts(&decoded_frame, &mut pts_repair), time_base);
 
             let mut bgra_data = Vec::with_capacity((dst_width * dst_height * 4) as usize);
             for y in 0..dst_height as usize {
@@ -622,7 +876,7 @@ pub fn decode_audio_packets(
     packets: Arc<PacketQueue>,
     producer: Arc<AudioStreamProducer>,
     codec_params: codec::Parameters,
-    _time_base: Rational,
+    time_base: Rational,
     stop_flag: Arc<AtomicBool>,
 ) -> Result<(), DecoderError> {
     ffmpeg_next::init()?;
@@ -702,8 +956,11 @@ pub fn decode_audio_packets(
                     .collect();
 
                 if !float_samples.is_empty() {
-                    // Push to ring buffer (blocks if full)
-                    if !producer.push(&float_samples) {
+                    // Push to ring buffer (blocks if full), anchoring the clock to the frame's PTS
+                    let pts = decoded_frame
+                        .pts()
+                        .map(|pts| pts_to_duration(pts, time_base));
+                    if !producer.push_frame(pts, &float_samples) {
                         eprintln!("[audio_decode] producer closed");
                         break;
                     }
@@ -748,7 +1005,10 @@ pub fn decode_audio_packets(
                 .map(|chunk| f32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
                 .collect();
 
-            if !float_samples.is_empty() && !producer.push(&float_samples) {
+            let pts = decoded_frame
+                .pts()
+                .map(|pts| pts_to_duration(pts, time_base));
+            if !float_samples.is_empty() && !producer.push_frame(pts, &float_samples) {
                 break;
             }
         }
@@ -0,0 +1,357 @@
+use std::path::Path;
+use std::time::Duration;
+
+use ffmpeg_next::{
+    Dictionary, Packet, Rational, Rescale, Rounding, codec, encoder, ffi,
+    format::{context::Output, input, output},
+    media::Type,
+};
+
+use super::decoder::{DecoderError, pts_to_duration};
+
+/**
+    Most packets held back while waiting for every stream to start, in case
+    a stream has no packets near the start of the clip.
+*/
+const MAX_PENDING_PACKETS: usize = 512;
+
+/**
+    Copy the part of a video between two positions to a new file, without
+    re-encoding. The container is picked from the output file's extension.
+
+    Packets are copied as they are, so the clip starts at the keyframe at or
+    before `start` - which may be a few seconds early - instead of re-encoding
+    the frames leading up to it. Only video and audio streams are copied, and
+    timestamps are shifted so that the clip starts at zero.
+
+    Streams start at different timestamps after seeking, and with B-frames a
+    video stream's DTS starts before its PTS, so the shift is the lowest DTS
+    across the first packets of all streams - never below any of them.
+*/
+pub fn export_clip(
+    input_path: &Path,
+    output_path: &Path,
+    start: Duration,
+    end: Duration,
+) -> Result<(), DecoderError> {
+    ffmpeg_next::init()?;
+
+    let mut input_ctx = input(&input_path)?;
+    let mut output_ctx = output(&output_path)?;
+
+    // Map input streams to output streams, skipping subtitles and data
+    let mut mapping: Vec<Option<(usize, Rational)>> = vec![None; input_ctx.nb_streams() as usize];
+    for stream in input_ctx.streams() {
+        let medium = stream.parameters().medium();
+        if medium != Type::Video && medium != Type::Audio {
+            continue;
+        }
+        let mut out_stream = output_ctx.add_stream(encoder::find(codec::Id::None))?;
+        out_stream.set_parameters(stream.parameters());
+        // Let the muxer pick the codec tag, the input's may not fit its container
+        unsafe {
+            (*out_stream.parameters().as_mut_ptr()).codec_tag = 0;
+        }
+        mapping[stream.index()] = Some((out_stream.index(), stream.time_base()));
+    }
+    if mapping.iter().all(Option::is_none) {
+        return Err(DecoderError::NoVideoStream);
+    }
+
+    output_ctx.set_metadata(input_ctx.metadata().to_owned());
+    // Anything still below zero is shifted up by the muxer, instead of failing
+    let mut options = Dictionary::new();
+    options.set("avoid_negative_ts", "make_zero");
+    output_ctx.write_header_with(options)?;
+    // The muxer may change time bases when writing the header
+    let out_time_bases: Vec<Rational> = output_ctx.streams().map(|s| s.time_base()).collect();
+
+    let ts = (start.as_secs_f64() * ffi::AV_TIME_BASE as f64) as i64;
+    input_ctx.seek(ts, ..ts)?;
+
+    let stream_count = mapping.iter().filter(|m| m.is_some()).count();
+    let mut offset: Option<(i64, Rational)> = None;
+    let mut pending: Vec<(usize, i64, Packet)> = Vec::new();
+    let mut started = vec![false; mapping.len()];
+    let mut unstarted = stream_count;
+    let mut ended = vec![false; mapping.len()];
+    let mut remaining = stream_count;
+
+    for (stream, packet) in input_ctx.packets() {
+        let index = stream.index();
+        let Some((out_index, time_base)) = mapping[index] else {
+            continue;
+        };
+        let Some(ts) = packet.dts().or(packet.pts()) else {
+            continue;
+        };
+
+        // Stop once every stream has reached the end position
+        if pts_to_duration(ts, time_base) >= end {
+            if !ended[index] {
+                ended[index] = true;
+                remaining -= 1;
+            }
+            if remaining == 0 {
+                break;
+            }
+            continue;
+        }
+
+        if let Some(offset) = offset {
+            let out_time_base = out_time_bases[out_index];
+            write_packet(
+                packet,
+                offset,
+                time_base,
+                out_index,
+                out_time_base,
+                &mut output_ctx,
+            )?;
+            continue;
+        }
+
+        // Hold packets back until the first timestamp of every stream is known
+        pending.push((index, ts, packet));
+        if !started[index] {
+            started[index] = true;
+            unstarted -= 1;
+        }
+        if unstarted == 0 || pending.len() >= MAX_PENDING_PACKETS {
+            offset = flush_pending(&mut pending, &mapping, &out_time_bases, &mut output_ctx)?;
+        }
+    }
+    // The clip ended before every stream started
+    flush_pending(&mut pending, &mapping, &out_time_bases, &mut output_ctx)?;
+
+    output_ctx.write_trailer()?;
+    Ok(())
+}
+
+/**
+    Write the packets held back before the clip's offset was known, shifted
+    by the lowest timestamp among them. Returns that offset.
+*/
+fn flush_pending(
+    pending: &mut Vec<(usize, i64, Packet)>,
+    mapping: &[Option<(usize, Rational)>],
+    out_time_bases: &[Rational],
+    output_ctx: &mut Output,
+) -> Result<Option<(i64, Rational)>, DecoderError> {
+    let offset = clip_offset(
+        pending
+            .iter()
+            .filter_map(|(index, ts, _)| Some((*ts, mapping[*index]?.1))),
+    );
+    let Some(offset) = offset else {
+        return Ok(None);
+    };
+    for (index, _, packet) in pending.drain(..) {
+        let (out_index, time_base) = mapping[index].unwrap();
+        write_packet(
+            packet,
+            offset,
+            time_base,
+            out_index,
+            out_time_bases[out_index],
+            output_ctx,
+        )?;
+    }
+    Ok(Some(offset))
+}
+
+/**
+    Get the lowest of the given timestamps, each in its own time base,
+    which the clip's timestamps are shifted down by so that it starts at zero.
+*/
+fn clip_offset(timestamps: impl IntoIterator<Item = (i64, Rational)>) -> Option<(i64, Rational)> {
+    let seconds = |(ts, time_base): &(i64, Rational)| *ts as f64 * f64::from(*time_base);
+    timestamps
+        .into_iter()
+        .min_by(|a, b| seconds(a).total_cmp(&seconds(b)))
+}
+
+/**
+    Convert the clip's offset to a stream's time base, rounding down so
+    that shifted timestamps never go below zero.
+*/
+fn offset_in(offset: (i64, Rational), time_base: Rational) -> i64 {
+    offset.0.rescale_with(offset.1, time_base, Rounding::Down)
+}
+
+/**
+    Shift a packet by the clip's offset and write it to its output stream.
+*/
+fn write_packet(
+    mut packet: Packet,
+    offset: (i64, Rational),
+    time_base: Rational,
+    out_index: usize,
+    out_time_base: Rational,
+    output_ctx: &mut Output,
+) -> Result<(), DecoderError> {
+    let shift = offset_in(offset, time_base);
+    packet.set_pts(packet.pts().map(|pts| pts - shift));
+    packet.set_dts(packet.dts().map(|dts| dts - shift));
+
+    packet.rescale_ts(time_base, out_time_base);
+    packet.set_stream(out_index);
+    packet.set_position(-1);
+    packet.write_interleaved(output_ctx)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ffmpeg_next::{format::Pixel, frame};
+    use media_test_pattern::write_test_pattern;
+
+    use super::*;
+
+    const FPS: i32 = 25;
+
+    #[test]
+    fn test_offset_is_lowest_timestamp() {
+        // After a seek, audio comes first, but the B-frame video's DTS starts
+        // two frames before its keyframe's PTS at 10s
+        let audio = (480_000, Rational::new(1, 48_000));
+        let video = (899_280, Rational::new(1, 90_000));
+        let offset = clip_offset([audio, video]).unwrap();
+        assert_eq!(offset, video);
+
+        assert_eq!(video.0 - offset_in(offset, video.1), 0);
+        assert_eq!(audio.0 - offset_in(offset, audio.1), 384);
+        assert_eq!(clip_offset([]), None);
+    }
+
+    /**
+        Write a video of a few seconds, with a B-frame MPEG-2 stream next to
+        one without B-frames, so the streams' first DTS differ after a seek.
+    */
+    fn write_b_frame_source(path: &Path) {
+        let mut output_ctx = output(&path).unwrap();
+        let mpeg2 = encoder::find(codec::Id::MPEG2VIDEO).unwrap();
+
+        let mut encoders = Vec::new();
+        for b_frames in [0, 2] {
+            let mut stream = output_ctx.add_stream(mpeg2).unwrap();
+            let mut video = codec::context::Context::new_with_codec(mpeg2)
+                .encoder()
+                .video()
+                .unwrap();
+            video.set_width(64);
+            video.set_height(64);
+            video.set_format(Pixel::YUV420P);
+            video.set_time_base(Rational::new(1, FPS));
+            video.set_frame_rate(Some(Rational::new(FPS, 1)));
+            video.set_gop(12);
+            video.set_max_b_frames(b_frames);
+            let video = video.open_as(mpeg2).unwrap();
+            stream.set_parameters(&video);
+            encoders.push(video);
+        }
+        output_ctx.write_header().unwrap();
+        let time_bases: Vec<Rational> = output_ctx.streams().map(|s| s.time_base()).collect();
+
+        for number in 0..(4 * FPS) {
+            let mut frame = frame::Video::new(Pixel::YUV420P, 64, 64);
+            for plane in 0..3 {
+                frame.data_mut(plane).fill((number * 2) as u8);
+            }
+            frame.set_pts(Some(number as i64));
+            for (index, encoder) in encoders.iter_mut().enumerate() {
+                encoder.send_frame(&frame).unwrap();
+                write_encoded(index, encoder, time_bases[index], &mut output_ctx);
+            }
+        }
+        for (index, encoder) in encoders.iter_mut().enumerate() {
+            encoder.send_eof().unwrap();
+            write_encoded(index, encoder, time_bases[index], &mut output_ctx);
+        }
+        output_ctx.write_trailer().unwrap();
+    }
+
+    fn write_encoded(
+        index: usize,
+        encoder: &mut encoder::video::Encoder,
+        time_base: Rational,
+        output_ctx: &mut Output,
+    ) {
+        let mut packet = Packet::empty();
+        while encoder.receive_packet(&mut packet).is_ok() {
+            packet.set_stream(index);
+            packet.rescale_ts(Rational::new(1, FPS), time_base);
+            packet.write_interleaved(output_ctx).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_export_b_frame_clip() {
+        let dir = std::env::temp_dir().join(format!("vidwall-export-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("source.ts");
+        let clip = dir.join("clip.ts");
+        write_b_frame_source(&source);
+
+        export_clip(
+            &source,
+            &clip,
+            Duration::from_millis(1500),
+            Duration::from_millis(2500),
+        )
+        .unwrap();
+
+        // Both streams start together, instead of at the one read first after the seek
+        let mut input_ctx = input(&clip).unwrap();
+        let mut first_dts = [None; 2];
+        for (stream, packet) in input_ctx.packets() {
+            let time_base = f64::from(stream.time_base());
+            let dts = packet.dts().unwrap() as f64 * time_base;
+            let pts = packet.pts().unwrap() as f64 * time_base;
+            assert!(pts >= dts, "pts {pts} before dts {dts}");
+            first_dts[stream.index()].get_or_insert(dts);
+        }
+        let [Some(first), Some(second)] = first_dts else {
+            panic!("clip is missing a stream: {first_dts:?}");
+        };
+        // The B-frame stream may only start earlier by its reorder delay
+        assert!((first - second).abs() <= 2.0 / FPS as f64 + 1e-6);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_export_keeps_audio_and_video() {
+        let dir = std::env::temp_dir().join(format!("vidwall-pattern-{}", std::process::id()));
+        let source = write_test_pattern(
+            &dir.join("source"),
+            160,
+            90,
+            FPS as u32,
+            Duration::from_secs(4),
+        )
+        .unwrap();
+        let clip = dir.join("clip.ts");
+
+        export_clip(
+            &source,
+            &clip,
+            Duration::from_secs(1),
+            Duration::from_secs(3),
+        )
+        .unwrap();
+
+        let input_ctx = input(&clip).unwrap();
+        let media: Vec<Type> = input_ctx
+            .streams()
+            .map(|stream| stream.parameters().medium())
+            .collect();
+        assert!(media.contains(&Type::Video), "clip has no video: {media:?}");
+        assert!(media.contains(&Type::Audio), "clip has no audio: {media:?}");
+        // Starts at the keyframe at or before 1s and ends at 3s
+        let duration = input_ctx.duration() as f64 / ffi::AV_TIME_BASE as f64;
+        assert!(duration > 1.5 && duration < 3.5, "clip lasts {duration}s");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,9 +1,12 @@
 mod decoder;
+mod export;
 mod packet_queue;
+mod pts_repair;
 
 pub use decoder::{
-    AudioStreamInfo, DecoderError, VideoInfo, VideoStreamInfo, audio_demux, decode_audio_packets,
-    decode_video_packets, get_audio_stream_info, get_video_info, get_video_stream_info,
-    video_demux,
+    AudioStreamInfo, DecoderError, ScalingAlgorithm, VideoDecodeConfig, VideoInfo, VideoScaling,
+    VideoStreamInfo, audio_demux, decode_audio_packets, decode_video_packets,
+    get_audio_stream_info, get_video_info, get_video_stream_info, keyframe_demux, video_demux,
 };
-pub use packet_queue::{Packet, PacketQueue};
+pub use export::export_clip;
+pub use packet_queue::{OverflowPolicy, Packet, PacketQueue};
@@ -1,6 +1,8 @@
 use std::collections::VecDeque;
 use std::sync::{Condvar, Mutex};
 
+use crate::queue_stats::{QueueOp, QueueStats};
+
 /**
     A decoded packet ready for the decode threads.
     Contains raw packet data and timing information.
@@ -26,10 +28,27 @@ impl Packet {
     }
 }
 
+/**
+    What pushing to a full packet queue does.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait until the consumer makes room
+    #[default]
+    Block,
+    /// Drop the oldest queued packet to make room, so that the producer
+    /// never waits. For streams where waiting would stall the connection.
+    DropOldest,
+}
+
 struct PacketQueueInner {
     packets: VecDeque<Packet>,
     capacity: usize,
     closed: bool,
+    /// Total size of the queued packets' data
+    bytes: usize,
+    /// Number of packets dropped to make room
+    dropped: u64,
 }
 
 /**
@@ -40,41 +59,73 @@ pub struct PacketQueue {
     inner: Mutex<PacketQueueInner>,
     not_full: Condvar,
     not_empty: Condvar,
+    overflow: OverflowPolicy,
+    stats: QueueStats,
 }
 
 impl PacketQueue {
     /**
-        Create a new packet queue with the given capacity
+        Create a new packet queue with the given capacity.
+        The name identifies the queue in instrumentation logs.
+    */
+    pub fn new(name: &'static str, capacity: usize) -> Self {
+        Self::with_overflow(name, capacity, OverflowPolicy::Block)
+    }
+
+    /**
+        Create a new packet queue with the given capacity, and what
+        pushing to it does when it is full.
     */
-    pub fn new(capacity: usize) -> Self {
+    pub fn with_overflow(name: &'static str, capacity: usize, overflow: OverflowPolicy) -> Self {
         Self {
             inner: Mutex::new(PacketQueueInner {
                 packets: VecDeque::with_capacity(capacity),
                 capacity,
                 closed: false,
+                bytes: 0,
+                dropped: 0,
             }),
             not_full: Condvar::new(),
             not_empty: Condvar::new(),
+            overflow,
+            stats: QueueStats::new(name, capacity),
         }
     }
 
     /**
-        Push a packet to the queue, blocking if full.
+        Push a packet to the queue, blocking if full, or dropping the
+        oldest packet if the queue drops packets when full.
         Returns false if the queue was closed.
     */
     pub fn push(&self, packet: Packet) -> bool {
         let mut inner = self.inner.lock().unwrap();
 
-        // Wait until there's space or queue is closed
-        while inner.packets.len() >= inner.capacity && !inner.closed {
-            inner = self.not_full.wait(inner).unwrap();
+        // Make room by dropping instead of waiting for the consumer
+        if self.overflow == OverflowPolicy::DropOldest {
+            while inner.packets.len() >= inner.capacity && !inner.closed {
+                let Some(oldest) = inner.packets.pop_front() else {
+                    break;
+                };
+                inner.bytes -= oldest.data.len();
+                inner.dropped += 1;
+                self.stats.record_drop();
+            }
         }
 
+        // Wait until there's space or queue is closed
+        let mut inner = self
+            .stats
+            .wait_while(&self.not_full, inner, QueueOp::Push, |inner| {
+                inner.packets.len() >= inner.capacity && !inner.closed
+            });
+
         if inner.closed {
             return false;
         }
 
+        inner.bytes += packet.data.len();
         inner.packets.push_back(packet);
+        self.stats.record_push(inner.packets.len());
         self.not_empty.notify_one();
         true
     }
@@ -84,16 +135,20 @@ impl PacketQueue {
         Returns None if the queue is closed and empty.
     */
     pub fn pop(&self) -> Option<Packet> {
-        let mut inner = self.inner.lock().unwrap();
+        let inner = self.inner.lock().unwrap();
 
         // Wait until there's a packet or queue is closed
-        while inner.packets.is_empty() && !inner.closed {
-            inner = self.not_empty.wait(inner).unwrap();
-        }
+        let mut inner = self
+            .stats
+            .wait_while(&self.not_empty, inner, QueueOp::Pop, |inner| {
+                inner.packets.is_empty() && !inner.closed
+            });
 
         let packet = inner.packets.pop_front();
 
-        if packet.is_some() {
+        if let Some(packet) = &packet {
+            inner.bytes -= packet.data.len();
+            self.stats.record_pop();
             self.not_full.notify_one();
         }
 
@@ -125,6 +180,31 @@ impl PacketQueue {
         self.inner.lock().unwrap().packets.len()
     }
 
+    /**
+        Change the maximum number of packets the queue holds. Packets already
+        queued past a smaller capacity are kept, and pushes wait until
+        they have been popped.
+    */
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.capacity = capacity.max(1);
+        self.not_full.notify_all();
+    }
+
+    /**
+        Get the number of packets dropped to make room since the queue was created.
+    */
+    pub fn dropped(&self) -> u64 {
+        self.inner.lock().unwrap().dropped
+    }
+
+    /**
+        Get the total size of the queued packets, in bytes.
+    */
+    pub fn bytes(&self) -> usize {
+        self.inner.lock().unwrap().bytes
+    }
+
     /**
         Clear all packets from the queue.
         Wakes any threads waiting to push.
@@ -132,6 +212,7 @@ impl PacketQueue {
     pub fn clear(&self) {
         let mut inner = self.inner.lock().unwrap();
         inner.packets.clear();
+        inner.bytes = 0;
         self.not_full.notify_all();
     }
 
@@ -142,7 +223,33 @@ impl PacketQueue {
     pub fn reopen(&self) {
         let mut inner = self.inner.lock().unwrap();
         inner.packets.clear();
+        inner.bytes = 0;
         inner.closed = false;
         self.not_full.notify_all();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(pts: i64) -> Packet {
+        Packet::new(vec![0; 10], pts, pts, 1, 0)
+    }
+
+    #[test]
+    fn test_drop_oldest_when_full() {
+        let queue = PacketQueue::with_overflow("test", 2, OverflowPolicy::DropOldest);
+        for pts in 0..5 {
+            assert!(queue.push(packet(pts)));
+        }
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.bytes(), 20);
+        assert_eq!(queue.dropped(), 3);
+        assert_eq!(queue.pop().map(|p| p.pts), Some(3));
+        assert_eq!(queue.pop().map(|p| p.pts), Some(4));
+
+        queue.close();
+        assert!(!queue.push(packet(5)));
+    }
+}
@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use ffmpeg_next::Rational;
+
+/// Frame rate assumed when neither the frames nor the stream have one
+const FALLBACK_FRAME_RATE: i32 = 25;
+
+/**
+    Repairs presentation timestamps of decoded video frames.
+
+    Some broadcast streams have frames with missing or duplicate timestamps,
+    or timestamps that go backwards, which makes the playback clock jump.
+    Missing timestamps are taken from the DTS, or derived from the previous
+    frame and the frame rate. Output timestamps always increase: small steps
+    back are treated as jitter and moved after the previous frame, while
+    larger ones are treated as a timestamp reset and the following frames
+    are rebased to continue from the previous frame.
+*/
+#[derive(Debug)]
+pub struct PtsRepair {
+    /// Frame duration in time base units, for frames that don't carry one
+    default_duration: i64,
+    /// Largest step back (in time base units) that is treated as jitter
+    tolerance: i64,
+    /// Added to incoming timestamps after a reset
+    offset: i64,
+    /// Timestamp and duration of the previous frame
+    last: Option<(i64, i64)>,
+}
+
+impl PtsRepair {
+    pub fn new(time_base: Rational, frame_rate: Option<Rational>, tolerance: Duration) -> Self {
+        let frame_rate = frame_rate
+            .filter(|r| r.numerator() > 0 && r.denominator() > 0)
+            .unwrap_or(Rational::new(FALLBACK_FRAME_RATE, 1));
+        let (tb_num, tb_den) = (
+            time_base.numerator().max(1) as i64,
+            time_base.denominator().max(1) as i64,
+        );
+
+        let default_duration =
+            (frame_rate.denominator() as i64 * tb_den) / (frame_rate.numerator() as i64 * tb_num);
+        let tolerance = (tolerance.as_secs_f64() * tb_den as f64 / tb_num as f64) as i64;
+
+        Self {
+            default_duration: default_duration.max(1),
+            tolerance,
+            offset: 0,
+            last: None,
+        }
+    }
+
+    /**
+        Get the repaired timestamp of the next frame, from its PTS,
+        its DTS and its duration (zero if unknown).
+    */
+    pub fn repair(&mut self, pts: Option<i64>, dts: Option<i64>, duration: i64) -> i64 {
+        let duration = if duration > 0 {
+            duration
+        } else {
+            self.default_duration
+        };
+
+        let mut repaired = match pts.or(dts) {
+            Some(ts) => ts + self.offset,
+            None => self.last.map(|(pts, dur)| pts + dur).unwrap_or(0),
+        };
+
+        if let Some((last, last_duration)) = self.last
+            && repaired <= last
+        {
+            let next = last + last_duration;
+            if last - repaired > self.tolerance {
+                self.offset += next - repaired;
+            }
+            repaired = next;
+        }
+
+        self.last = Some((repaired, duration));
+        repaired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repair() -> PtsRepair {
+        // 25 fps in a 1/1000 time base, so frames are 40 units apart
+        PtsRepair::new(
+            Rational::new(1, 1000),
+            Some(Rational::new(25, 1)),
+            Duration::from_millis(500),
+        )
+    }
+
+    #[test]
+    fn test_passes_valid_timestamps() {
+        let mut r = repair();
+        assert_eq!(r.repair(Some(1000), None, 40), 1000);
+        assert_eq!(r.repair(Some(1040), None, 40), 1040);
+        // Forward jumps are kept
+        assert_eq!(r.repair(Some(5000), None, 40), 5000);
+    }
+
+    #[test]
+    fn test_fills_missing_and_duplicate() {
+        let mut r = repair();
+        assert_eq!(r.repair(Some(1000), None, 0), 1000);
+        assert_eq!(r.repair(None, None, 0), 1040);
+        assert_eq!(r.repair(None, Some(1080), 0), 1080);
+        assert_eq!(r.repair(Some(1080), None, 0), 1120);
+    }
+
+    #[test]
+    fn test_rebases_after_reset() {
+        let mut r = repair();
+        assert_eq!(r.repair(Some(90_000), None, 40), 90_000);
+        // Jitter within the tolerance only moves this frame
+        assert_eq!(r.repair(Some(89_900), None, 40), 90_040);
+        assert_eq!(r.repair(Some(90_080), None, 40), 90_080);
+        // A reset rebases the frames that follow
+        assert_eq!(r.repair(Some(0), None, 40), 90_120);
+        assert_eq!(r.repair(Some(40), None, 40), 90_160);
+    }
+}
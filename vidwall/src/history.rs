@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use gpui::Global;
+use serde::{Deserialize, Serialize};
+
+/**
+    Maximum number of files remembered, the least recently watched are forgotten beyond this
+*/
+const MAX_ENTRIES: usize = 1000;
+
+/**
+    Positions closer than this to the start are not worth resuming from
+*/
+const MIN_RESUME_POSITION: Duration = Duration::from_secs(10);
+
+/**
+    Positions closer than this to the end count as having watched the whole file
+*/
+const END_MARGIN: Duration = Duration::from_secs(10);
+
+/**
+    Where a file was last watched to, and when.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub position: Duration,
+    pub duration: Duration,
+    /// Seconds since the Unix epoch
+    pub watched_at: u64,
+}
+
+/**
+    Files that have been watched, and the positions they were left at, so
+    that they can be resumed when they are played again on any tile.
+
+    Stored as a small JSON file in the data directory. Only local files are
+    recorded, streams and URLs have no position worth resuming.
+*/
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PlaybackHistory {
+    entries: HashMap<PathBuf, HistoryEntry>,
+}
+
+impl Global for PlaybackHistory {}
+
+impl PlaybackHistory {
+    /**
+        Get the path to the history file.
+    */
+    pub fn file_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|p| p.join("vidwall").join("history.json"))
+    }
+
+    /**
+        Load the history from disk, starting out empty if there is none.
+    */
+    pub fn load() -> Self {
+        let Some(path) = Self::file_path() else {
+            return Self::default();
+        };
+        let Ok(contents) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            eprintln!("Failed to parse history {}: {}", path.display(), e);
+            Self::default()
+        })
+    }
+
+    /**
+        Save the history to disk.
+    */
+    pub fn save(&self) {
+        let Some(path) = Self::file_path() else {
+            return;
+        };
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if let Ok(json) = serde_json::to_string(self) {
+            let _ = fs::write(path, json);
+        }
+    }
+
+    /**
+        Remember how far a file has been watched.
+    */
+    pub fn record(&mut self, path: &Path, position: Duration, duration: Duration, now: SystemTime) {
+        let watched_at = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        self.entries.insert(
+            path.to_path_buf(),
+            HistoryEntry {
+                position,
+                duration,
+                watched_at,
+            },
+        );
+
+        if self.entries.len() > MAX_ENTRIES {
+            let mut by_age: Vec<_> = self
+                .entries
+                .iter()
+                .map(|(path, entry)| (entry.watched_at, path.clone()))
+                .collect();
+            by_age.sort();
+            for (_, path) in by_age.into_iter().take(self.entries.len() - MAX_ENTRIES) {
+                self.entries.remove(&path);
+            }
+        }
+    }
+
+    /**
+        Get the position to offer resuming a file from, if it was left
+        somewhere between its start and end.
+    */
+    pub fn resume_position(&self, path: &Path) -> Option<Duration> {
+        let entry = self.entries.get(path)?;
+        let before_end = entry.duration.saturating_sub(END_MARGIN);
+        (entry.position >= MIN_RESUME_POSITION && entry.position < before_end)
+            .then_some(entry.position)
+    }
+}
+
+/**
+    Format a position as "12:34", or "1:02:03" past an hour.
+*/
+pub fn format_position(position: Duration) -> String {
+    let secs = position.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resume_position() {
+        let secs = Duration::from_secs;
+        let mut history = PlaybackHistory::default();
+        let path = Path::new("/videos/a.mp4");
+        assert_eq!(history.resume_position(path), None);
+
+        history.record(path, secs(754), secs(3600), UNIX_EPOCH);
+        assert_eq!(history.resume_position(path), Some(secs(754)));
+
+        // Barely started or watched to the end
+        history.record(path, secs(3), secs(3600), UNIX_EPOCH);
+        assert_eq!(history.resume_position(path), None);
+        history.record(path, secs(3595), secs(3600), UNIX_EPOCH);
+        assert_eq!(history.resume_position(path), None);
+    }
+
+    #[test]
+    fn test_oldest_entries_are_forgotten() {
+        let mut history = PlaybackHistory::default();
+        for index in 0..=MAX_ENTRIES {
+            let path = PathBuf::from(format!("/videos/{}.mp4", index));
+            let now = UNIX_EPOCH + Duration::from_secs(index as u64);
+            history.record(&path, Duration::ZERO, Duration::ZERO, now);
+        }
+        assert_eq!(history.entries.len(), MAX_ENTRIES);
+        assert!(!history.entries.contains_key(Path::new("/videos/0.mp4")));
+        assert!(history.entries.contains_key(Path::new("/videos/1.mp4")));
+    }
+
+    #[test]
+    fn test_format_position() {
+        assert_eq!(format_position(Duration::from_secs(754)), "12:34");
+        assert_eq!(format_position(Duration::from_secs(5)), "0:05");
+        assert_eq!(format_position(Duration::from_secs(3723)), "1:02:03");
+    }
+}
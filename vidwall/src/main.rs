@@ -6,10 +6,21 @@
     When a video ends, it's replaced with a new random video from the pool.
     Folders are scanned recursively for video files using ffprobe validation.
 
-    Keyboard Controls:
+    Keyboard Controls (defaults, see `keymap.json` in the config directory):
     - Space: Pause/Resume all videos
     - M: Mute/Unmute audio
-    - Up/Down: Adjust volume
+    - =/-: Adjust volume
+    - Enter: Skip all videos
+    - A: Align audio of all videos
+    - P: Switch the audio policy (mute all, follow hover, follow focus, manual)
+    - I/O: Mark the start/end of a clip on the selected tile
+    - E: Export the marked clip to the videos folder
+    - ,/.: Step the selected tile back/forward a frame
+    - S: Slow motion for the selected tile (0.5x, 0.25x, 0.1x)
+    - [/]: Set the in/out point of an A/B loop on the selected tile, \ to clear it
+    - Arrows, 1-9: Select a tile
+    - F: Focus mode for the selected tile
+    - Escape: Clear the selection
     - Cmd+Q: Quit
 
     Prerequisites:
@@ -19,43 +30,316 @@
       cargo run --release
       cargo run --release -- /path/to/videos
       cargo run --release -- /path/to/folder1 /path/to/video.mp4 /path/to/folder2
+
+    With `--tiles`, every given source gets a tile of its own instead, in a
+    grid laid out for the number of sources and their aspect ratios. Sources
+    may also be URLs - `file://`, `http(s)://` HLS and DASH streams, `rtsp://`
+    cameras, `image://` stills, and vidproxy channels as
+    `vidproxy://source/channel`, played from `VIDPROXY_URL` (by default
+    `http://localhost:8098`). Sources that don't fit within `--max-tiles`
+    share a slot that rotates every `--rotate-secs` seconds:
+      cargo run --release -- --tiles --max-tiles 4 rtsp://cam1/live vidproxy://news/1
+
+    With `--max-tiles 1`, a single tile cycles through all of the sources as
+    a carousel. Each next source is connected a few seconds before its turn,
+    so the switch doesn't show a black gap:
+      cargo run --release -- --max-tiles 1 --rotate-secs 10 rtsp://cam1/live rtsp://cam2/live
+
+    With `--motion`, RTSP camera tiles are watched for motion. A tile that
+    sees some is outlined for a few seconds, the event is printed, and a
+    snapshot of it is saved to the `motion` folder next to exported clips:
+      cargo run --release -- --motion rtsp://cam1/live rtsp://cam2/live
+
+    With `--span`, the wall covers every connected display with a fullscreen
+    window each, all sharing the same players and audio. `--bezel-x` and
+    `--bezel-y` give the size of the bezels between displays in pixels, so
+    that videos line up across them:
+      cargo run --release -- --span --bezel-x 40 /path/to/videos
+
+    `--audio` picks which tiles are heard: `mute` mutes them all, `hover` and
+    `focus` play only the tile under the mouse or the selected tile, and
+    `manual` (the default) leaves every tile playing:
+      cargo run --release -- --audio focus /path/to/videos
+
+    `--memory-mb` caps the memory used for buffered video across all tiles
+    (4096 MB by default). Past it, tiles buffer fewer frames, and decode at a
+    lower resolution if that isn't enough:
+      cargo run --release -- --memory-mb 2048 /path/to/videos
+
+    Decoded frames are scaled to the size of their tile, with `fast-bilinear`
+    for tiles under 720 device pixels high and `lanczos` for larger ones by
+    default. `--scaling-small` and `--scaling-large` pick other algorithms,
+    one of `fast-bilinear`, `bilinear`, `bicubic` or `lanczos`:
+      cargo run --release -- --scaling-small bilinear /path/to/videos
+
+    How far each local file was watched is remembered between sessions, and
+    a tile playing a file that was left partway offers to resume it.
+
+    Scanned files are indexed in a media library in the data directory, with
+    their resolution, duration and codecs, so that later launches only probe
+    files that are new or changed since.
+
+    Hovering a tile shows a seek bar along its bottom, with thumbnails of
+    the video while hovering it. Thumbnails are decoded in the background
+    and cached for every file.
+
+    `--export-wall` saves the sources and settings of the wall to a JSON file,
+    and `--import-wall` sets up a wall from one, to clone a wall on another
+    machine. Flags and sources given along with an import are applied over
+    the imported ones. The file also holds the settings of the vidproxy
+    channels the wall plays, fetched from the vidproxy server at
+    `VIDPROXY_URL` on export and imported into it on import. Decryption keys
+    are only included with `--export-wall-keys`, since anyone who gets the
+    file gets the keys:
+      cargo run --release -- --tiles --audio focus vidproxy://news/1 --export-wall wall.json
+      cargo run --release -- --import-wall wall.json --max-tiles 4
+
+    Set `VIDWALL_QUEUE_DEBUG=1` to log decoder queue statistics and
+    threads that stay blocked on a queue, when debugging hangs.
 */
 
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use gpui::{App, AppContext, Application, Bounds, WindowBounds, WindowOptions, px, size};
 use rand::seq::SliceRandom;
 
 mod audio;
 mod decode;
+mod history;
 mod playback;
+mod queue_stats;
 mod ui;
 mod video;
+mod wall_export;
 mod window_state;
 
 use audio::{AudioMixer, AudioOutput, DEFAULT_CHANNELS, DEFAULT_SAMPLE_RATE};
-use ui::{AppState, RootView, register_shortcuts};
-use video::{ReadyVideos, VideoScanner};
+use decode::ScalingAlgorithm;
+use history::PlaybackHistory;
+use playback::MemoryBudget;
+use ui::{
+    AppState, AudioPolicy, BezelCompensation, GridView, Keymap, RootView, ScalingConfig,
+    TileOptions, WallLayout, WallRect, register_shortcuts,
+};
+use video::{MediaLibrary, ReadyVideos, VideoScanner, vidproxy_url};
+use wall_export::WallExport;
 use window_state::WindowState;
 
 // Default window dimensions
 const DEFAULT_WIDTH: u32 = 1280;
 const DEFAULT_HEIGHT: u32 = 720;
 
+/**
+    Parsed command line arguments.
+*/
+struct CliArgs {
+    /// Video files, folders, and (with tiles) URLs to play
+    paths: Vec<PathBuf>,
+    /// Give every source a tile of its own instead of playing random videos
+    tiles: Option<TileOptions>,
+    /// Span the wall across all displays
+    span: bool,
+    bezels: BezelCompensation,
+    /// Which tiles are heard
+    audio_policy: AudioPolicy,
+    /// Memory the players may buffer video in
+    memory_budget: MemoryBudget,
+    /// How decoded frames are scaled to their tiles
+    scaling: ScalingConfig,
+}
+
+impl CliArgs {
+    /**
+        Parse the command line arguments, exiting with a message if they are invalid.
+    */
+    fn parse() -> Self {
+        let mut wall = WallExport::default();
+        let mut import = None;
+        let mut export = None;
+        let mut export_keys = false;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--tiles" => wall.tiles = Some(true),
+                "--max-tiles" => {
+                    wall.max_tiles = Some(Self::parse_count(&arg, args.next()));
+                    wall.tiles = Some(true);
+                }
+                "--rotate-secs" => {
+                    wall.rotate_secs = Some(Self::parse_count(&arg, args.next()));
+                    wall.tiles = Some(true);
+                }
+                "--motion" => {
+                    wall.motion = Some(true);
+                    wall.tiles = Some(true);
+                }
+                "--span" => wall.span = Some(true),
+                "--bezel-x" => wall.bezel_x = Some(Self::parse_count(&arg, args.next())),
+                "--bezel-y" => wall.bezel_y = Some(Self::parse_count(&arg, args.next())),
+                "--audio" => {
+                    let policy = Self::parse_audio_policy(&arg, args.next());
+                    wall.audio = Some(policy.to_name().to_string());
+                }
+                "--memory-mb" => wall.memory_mb = Some(Self::parse_count(&arg, args.next())),
+                "--scaling-small" => {
+                    let algorithm = Self::parse_scaling(&arg, args.next());
+                    wall.scaling_small = Some(algorithm.to_name().to_string());
+                }
+                "--scaling-large" => {
+                    let algorithm = Self::parse_scaling(&arg, args.next());
+                    wall.scaling_large = Some(algorithm.to_name().to_string());
+                }
+                "--import-wall" => import = Some(Self::parse_path(&arg, args.next())),
+                "--export-wall" => export = Some(Self::parse_path(&arg, args.next())),
+                "--export-wall-keys" => export_keys = true,
+                _ => wall.sources.push(arg),
+            }
+        }
+
+        if let Some(path) = import {
+            match WallExport::load(&path) {
+                Ok(imported) => {
+                    if let Err(e) = imported.import_channel_set(&vidproxy_url()) {
+                        eprintln!("{}, channel settings were not imported", e);
+                    }
+                    wall = wall.over(imported);
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        if let Some(path) = export {
+            if let Err(e) = wall.fetch_channel_set(&vidproxy_url(), export_keys) {
+                eprintln!("{}, channel settings were not exported", e);
+            }
+            if let Err(e) = wall.save(&path) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            println!("Exported wall to {}", path.display());
+        }
+
+        Self::from_wall(wall)
+    }
+
+    /**
+        Get the arguments for a wall, from the command line or imported.
+    */
+    fn from_wall(wall: WallExport) -> Self {
+        let defaults = TileOptions::default();
+        let options = TileOptions {
+            max_tiles: wall.max_tiles.unwrap_or(defaults.max_tiles),
+            rotate_interval: wall.rotate_secs.map_or(defaults.rotate_interval, |secs| {
+                Duration::from_secs(u64::from(secs))
+            }),
+            motion: wall.motion.unwrap_or(defaults.motion),
+        };
+        let bezels = BezelCompensation {
+            horizontal: wall.bezel_x.unwrap_or_default() as f32,
+            vertical: wall.bezel_y.unwrap_or_default() as f32,
+        };
+        let audio_policy = match wall.audio {
+            Some(name) => Self::parse_audio_policy("--audio", Some(name)),
+            None => AudioPolicy::default(),
+        };
+        let memory_budget = wall
+            .memory_mb
+            .map_or_else(MemoryBudget::default, |megabytes| {
+                MemoryBudget::from_megabytes(u64::from(megabytes))
+            });
+        let default_scaling = ScalingConfig::default();
+        let scaling = ScalingConfig {
+            small: wall.scaling_small.map_or(default_scaling.small, |name| {
+                Self::parse_scaling("--scaling-small", Some(name))
+            }),
+            large: wall.scaling_large.map_or(default_scaling.large, |name| {
+                Self::parse_scaling("--scaling-large", Some(name))
+            }),
+            ..default_scaling
+        };
+
+        Self {
+            paths: wall.sources.into_iter().map(PathBuf::from).collect(),
+            tiles: wall.tiles.unwrap_or(false).then_some(options),
+            span: wall.span.unwrap_or(false),
+            bezels,
+            audio_policy,
+            memory_budget,
+            scaling,
+        }
+    }
+
+    fn parse_audio_policy(flag: &str, value: Option<String>) -> AudioPolicy {
+        match value.as_deref().and_then(AudioPolicy::from_name) {
+            Some(policy) => policy,
+            None => {
+                let names: Vec<_> = AudioPolicy::ALL.iter().map(|p| p.to_name()).collect();
+                eprintln!("{} expects one of: {}", flag, names.join(", "));
+                std::process::exit(1);
+            }
+        }
+    }
+
+    fn parse_scaling(flag: &str, value: Option<String>) -> ScalingAlgorithm {
+        match value.as_deref().and_then(ScalingAlgorithm::from_name) {
+            Some(algorithm) => algorithm,
+            None => {
+                let names: Vec<_> = ScalingAlgorithm::ALL.iter().map(|a| a.to_name()).collect();
+                eprintln!("{} expects one of: {}", flag, names.join(", "));
+                std::process::exit(1);
+            }
+        }
+    }
+
+    fn parse_path(flag: &str, value: Option<String>) -> PathBuf {
+        match value {
+            Some(value) => PathBuf::from(value),
+            None => {
+                eprintln!("{} expects a file path", flag);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    fn parse_count(flag: &str, value: Option<String>) -> u32 {
+        match value.as_deref().map(str::parse::<u32>) {
+            Some(Ok(value)) if value > 0 => value,
+            _ => {
+                eprintln!("{} expects a positive number", flag);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
 fn main() {
-    Application::new().run(|cx: &mut App| {
+    let args = CliArgs::parse();
+
+    Application::new().run(move |cx: &mut App| {
         // Register keyboard shortcuts at the app level
         register_shortcuts(cx);
+        cx.set_global(args.audio_policy);
+        cx.set_global(args.memory_budget);
+        cx.set_global(args.scaling);
+        cx.set_global(PlaybackHistory::load());
 
-        let cli_paths: Vec<PathBuf> = std::env::args().skip(1).map(PathBuf::from).collect();
-
-        if !cli_paths.is_empty() {
-            // CLI paths provided - go directly to video wall
-            open_app_with_paths(cli_paths, cx);
-        } else {
+        if args.paths.is_empty() {
             // No paths - show welcome screen first
             open_app_with_welcome(cx);
+        } else if args.span {
+            // One window per display, sharing a single grid
+            open_app_spanning(&args, cx);
+        } else if let Some(options) = args.tiles {
+            // Fixed sources, one per tile
+            open_app_with_tiles(args.paths.clone(), options, cx);
+        } else {
+            // CLI paths provided - go directly to video wall
+            open_app_with_paths(args.paths.clone(), cx);
         }
     });
 }
@@ -107,16 +391,117 @@ fn open_app_with_paths(paths: Vec<PathBuf>, cx: &mut App) {
     // Initialize video playback system
     let ready_videos = initialize_video_playback(paths.clone(), cx);
 
-    // Determine window title
-    let window_title = if paths.len() == 1 {
+    let window_title = window_title(&paths);
+    open_grid_window(window_title, cx, move |cx| {
+        RootView::new_with_videos(ready_videos, cx)
+    });
+}
+
+/**
+    Open the app with a fixed list of sources, one per tile (CLI `--tiles` mode).
+*/
+fn open_app_with_tiles(paths: Vec<PathBuf>, options: TileOptions, cx: &mut App) {
+    let ready_videos = initialize_playback(cx);
+    let sources = VideoScanner::collect_sources(&paths);
+
+    let window_title = window_title(&paths);
+    open_grid_window(window_title, cx, move |cx| {
+        RootView::new_with_tiles(ready_videos, sources, options, cx)
+    });
+}
+
+/**
+    Open the app with one fullscreen window per display, together showing
+    a single wall laid out across all of them (CLI `--span` mode).
+*/
+fn open_app_spanning(args: &CliArgs, cx: &mut App) {
+    let displays = cx.displays();
+    if displays.is_empty() {
+        eprintln!("No displays found to span");
+        cx.quit();
+        return;
+    }
+
+    let rects: Vec<WallRect> = displays
+        .iter()
+        .map(|display| {
+            let bounds = display.bounds();
+            WallRect::new(
+                bounds.origin.x.into(),
+                bounds.origin.y.into(),
+                bounds.size.width.into(),
+                bounds.size.height.into(),
+            )
+        })
+        .collect();
+    let layout = WallLayout::new(&rects, args.bezels);
+    println!(
+        "Spanning {} display(s) with a {}x{} wall",
+        displays.len(),
+        layout.width,
+        layout.height
+    );
+
+    let ready_videos = match args.tiles {
+        Some(_) => initialize_playback(cx),
+        None => initialize_video_playback(args.paths.clone(), cx),
+    };
+
+    // All windows share this grid, and with it the players and mixer streams
+    let grid = cx.new(|cx| GridView::new(Arc::clone(&ready_videos), cx));
+    grid.update(cx, |grid, cx| {
+        grid.resize(layout.width, layout.height, cx);
+        if let Some(options) = args.tiles {
+            let sources = VideoScanner::collect_sources(&args.paths);
+            grid.load_sources(sources, options, cx);
+        }
+    });
+
+    for (index, display) in displays.iter().enumerate() {
+        let viewport = layout.viewport(index);
+        let grid = grid.clone();
+        let ready_videos = Arc::clone(&ready_videos);
+        cx.open_window(
+            WindowOptions {
+                window_bounds: Some(WindowBounds::Fullscreen(display.bounds())),
+                display_id: Some(display.id()),
+                focus: index == 0,
+                kind: gpui::WindowKind::PopUp,
+                titlebar: None,
+                ..Default::default()
+            },
+            move |_window, cx| {
+                cx.new(|cx| RootView::new_spanning(grid, ready_videos, viewport, index == 0, cx))
+            },
+        )
+        .expect("Failed to open window");
+    }
+
+    cx.activate(true);
+}
+
+/**
+    Get the window title for the given video paths.
+*/
+fn window_title(paths: &[PathBuf]) -> String {
+    if paths.len() == 1 {
         paths[0]
             .file_name()
             .map(|s| format!("Video Wall - {}", s.to_string_lossy()))
             .unwrap_or_else(|| "Video Wall".to_string())
     } else {
         format!("Video Wall - {} sources", paths.len())
-    };
+    }
+}
 
+/**
+    Open the main window, restoring its saved position and size if any.
+*/
+fn open_grid_window(
+    window_title: String,
+    cx: &mut App,
+    build_root_view: impl FnOnce(&mut gpui::Context<RootView>) -> RootView + 'static,
+) {
     // Try to load saved window state, or use defaults
     let (bounds, display_id) = if let Some(saved_state) = WindowState::load() {
         println!("Restored window state from saved state");
@@ -133,7 +518,6 @@ fn open_app_with_paths(paths: Vec<PathBuf>, cx: &mut App) {
     };
 
     // Open window with grid view
-    let ready_videos_for_window = Arc::clone(&ready_videos);
     let window = cx
         .open_window(
             WindowOptions {
@@ -148,7 +532,7 @@ fn open_app_with_paths(paths: Vec<PathBuf>, cx: &mut App) {
                 }),
                 ..Default::default()
             },
-            |_window, cx| cx.new(|cx| RootView::new_with_videos(ready_videos_for_window, cx)),
+            |_window, cx| cx.new(build_root_view),
         )
         .expect("Failed to open window");
 
@@ -162,6 +546,36 @@ fn open_app_with_paths(paths: Vec<PathBuf>, cx: &mut App) {
     This is called both from CLI mode and when transitioning from welcome screen.
 */
 pub fn initialize_video_playback(paths: Vec<PathBuf>, cx: &mut App) -> Arc<ReadyVideos> {
+    let ready_videos = initialize_playback(cx);
+
+    // Start video scanning in the background
+    let scanner = VideoScanner::new(Arc::clone(&ready_videos));
+    let mut candidates = scanner.scan_paths(paths.clone());
+
+    // Shuffle candidates for fairness across different sources
+    candidates.shuffle(&mut rand::thread_rng());
+
+    println!(
+        "\nScanning {} candidate file(s) from {} path(s)...",
+        candidates.len(),
+        paths.len()
+    );
+
+    // Process videos in parallel using worker threads, skipping those already in the library
+    let ready_videos_for_scan = Arc::clone(&ready_videos);
+    std::thread::spawn(move || {
+        let library = MediaLibrary::load();
+        VideoScanner::probe_all_parallel(ready_videos_for_scan, candidates, paths, library);
+    });
+
+    ready_videos
+}
+
+/**
+    Initialize audio output and the global application state, shared by
+    the random video wall and fixed tiles.
+*/
+fn initialize_playback(cx: &mut App) -> Arc<ReadyVideos> {
     let ready_videos = Arc::new(ReadyVideos::new());
     let mixer = Arc::new(AudioMixer::new(DEFAULT_SAMPLE_RATE, DEFAULT_CHANNELS));
 
@@ -189,31 +603,12 @@ pub fn initialize_video_playback(paths: Vec<PathBuf>, cx: &mut App) -> Arc<Ready
     }
 
     println!("\nKeyboard shortcuts:");
-    println!("  Space  - Pause/Resume");
-    println!("  M      - Mute/Unmute");
-    println!("  Up     - Volume up");
-    println!("  Down   - Volume down");
-    println!("  Enter  - Skip all videos");
-    println!("  Cmd+Q  - Quit");
-
-    // Start video scanning in the background
-    let scanner = VideoScanner::new(Arc::clone(&ready_videos));
-    let mut candidates = scanner.scan_paths(paths.clone());
-
-    // Shuffle candidates for fairness across different sources
-    candidates.shuffle(&mut rand::thread_rng());
-
-    println!(
-        "\nScanning {} candidate file(s) from {} path(s)...",
-        candidates.len(),
-        paths.len()
-    );
-
-    // Process videos in parallel using worker threads
-    let ready_videos_for_scan = Arc::clone(&ready_videos);
-    std::thread::spawn(move || {
-        VideoScanner::probe_all_parallel(ready_videos_for_scan, candidates);
-    });
+    for (command, keys) in cx.global::<Keymap>().bindings() {
+        println!("  {:<8} - {}", keys, command.replace('_', " "));
+    }
+    if let Some(path) = Keymap::file_path() {
+        println!("  (change them in {})", path.display());
+    }
 
     ready_videos
 }
@@ -11,7 +11,7 @@ use crate::audio::{
     create_audio_stream_with_clock,
 };
 use crate::decode::{
-    AudioStreamInfo, DecoderError, PacketQueue, audio_demux, decode_audio_packets,
+    AudioStreamInfo, DecoderError, OverflowPolicy, PacketQueue, audio_demux, decode_audio_packets,
     get_audio_stream_info,
 };
 
@@ -40,6 +40,7 @@ pub struct AudioPipeline {
     // Immutable config
     path: PathBuf,
     stream_info: AudioStreamInfo,
+    end_position: Option<Duration>,
 
     // Mutable state behind Mutex for seeking
     inner: Mutex<AudioPipelineInner>,
@@ -54,12 +55,23 @@ pub struct AudioPipeline {
 
 impl AudioPipeline {
     /**
-        Create and start a new audio pipeline for the given file.
+        Create and start a new audio pipeline for the given file,
+        optionally ending at the given position instead of the end of the file.
+
+        `overflow` decides what happens when the decoder falls behind and
+        the packet queue fills up: blocking the demuxer, or dropping the
+        oldest audio. The clock is anchored to the PTS of the audio played,
+        so video stays in sync across dropped audio.
+
         Returns Ok(None) if the file has no audio stream.
         Returns Err if there's an error opening or processing the file.
     */
-    pub fn new(path: PathBuf) -> Result<Option<Self>, DecoderError> {
-        Self::new_at(path, None)
+    pub fn new(
+        path: PathBuf,
+        end_position: Option<Duration>,
+        overflow: OverflowPolicy,
+    ) -> Result<Option<Self>, DecoderError> {
+        Self::new_at(path, None, end_position, overflow)
     }
 
     /**
@@ -68,6 +80,8 @@ impl AudioPipeline {
     fn new_at(
         path: PathBuf,
         start_position: Option<Duration>,
+        end_position: Option<Duration>,
+        overflow: OverflowPolicy,
     ) -> Result<Option<Self>, DecoderError> {
         // Check if file has audio and get stream info
         let stream_info: AudioStreamInfo = match get_audio_stream_info(&path) {
@@ -77,7 +91,11 @@ impl AudioPipeline {
         };
 
         let stop_flag = Arc::new(AtomicBool::new(false));
-        let packet_queue = Arc::new(PacketQueue::new(AUDIO_PACKET_QUEUE_CAPACITY));
+        let packet_queue = Arc::new(PacketQueue::with_overflow(
+            "audio packets",
+            AUDIO_PACKET_QUEUE_CAPACITY,
+            overflow,
+        ));
 
         // Create audio stream (producer, consumer, clock)
         let (producer, consumer, clock) = create_audio_stream();
@@ -94,7 +112,7 @@ impl AudioPipeline {
             let path = path.clone();
             let packets = Arc::clone(&packet_queue);
             let stop = Arc::clone(&stop_flag);
-            thread::spawn(move || audio_demux(path, packets, stop, start_position))
+            thread::spawn(move || audio_demux(path, packets, stop, start_position, end_position))
         };
 
         // Spawn decode thread
@@ -110,6 +128,7 @@ impl AudioPipeline {
         Ok(Some(Self {
             path,
             stream_info,
+            end_position,
             inner: Mutex::new(AudioPipelineInner {
                 demux_handle: Some(demux_handle),
                 decode_handle: Some(decode_handle),
@@ -183,7 +202,8 @@ impl AudioPipeline {
             let path = self.path.clone();
             let packets = Arc::clone(&self.packet_queue);
             let stop = Arc::clone(&self.stop_flag);
-            thread::spawn(move || audio_demux(path, packets, stop, Some(position)))
+            let end = self.end_position;
+            thread::spawn(move || audio_demux(path, packets, stop, Some(position), end))
         };
 
         let decode_handle = {
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+
+use crate::decode::VideoInfo;
+
+use super::frame::VideoFrame;
+
+/**
+    Maximum number of entries (a PNG and its JSON) in the cache,
+    oldest entries are removed beyond this
+*/
+const MAX_ENTRIES: usize = 512;
+
+/**
+    Stream info and timing of a cached first frame
+*/
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    info: VideoInfo,
+    pts: Duration,
+}
+
+/**
+    The first frame of a video and its stream info, from the frame cache
+*/
+pub struct CachedStart {
+    pub info: VideoInfo,
+    pub frame: VideoFrame,
+}
+
+/**
+    Get the cache directory for first frames.
+*/
+fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|p| p.join("vidwall").join("frames"))
+}
+
+/**
+    Get the cache key for a video, which changes when the file is modified.
+*/
+pub(super) fn cache_key(path: &Path, start_at: Option<Duration>) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    modified.hash(&mut hasher);
+    metadata.len().hash(&mut hasher);
+    start_at.hash(&mut hasher);
+    Some(format!("{:016x}", hasher.finish()))
+}
+
+/**
+    Load the cached first frame and stream info of a video, so that a tile
+    can show imagery right away while its decoders start up.
+*/
+pub fn load(path: &Path, start_at: Option<Duration>) -> Option<CachedStart> {
+    let dir = cache_dir()?;
+    let key = cache_key(path, start_at)?;
+
+    let contents = fs::read_to_string(dir.join(format!("{}.json", key))).ok()?;
+    let entry: CacheEntry = serde_json::from_str(&contents).ok()?;
+
+    // Pixel data is stored as is, so the PNG channels are really BGRA
+    let image = image::open(dir.join(format!("{}.png", key)))
+        .ok()?
+        .into_rgba8();
+    let (width, height) = image.dimensions();
+
+    Some(CachedStart {
+        info: entry.info,
+        frame: VideoFrame::new(image.into_raw(), width, height, entry.pts),
+    })
+}
+
+/**
+    Store the first frame and stream info of a video in the cache.
+*/
+pub fn store(path: &Path, start_at: Option<Duration>, info: &VideoInfo, frame: &VideoFrame) {
+    let (Some(dir), Some(key)) = (cache_dir(), cache_key(path, start_at)) else {
+        return;
+    };
+    if let Err(e) = write_entry(&dir, &key, info, frame) {
+        eprintln!("Warning: Failed to cache first frame: {}", e);
+        return;
+    }
+    prune(&dir);
+}
+
+fn write_entry(
+    dir: &Path,
+    key: &str,
+    info: &VideoInfo,
+    frame: &VideoFrame,
+) -> Result<(), Box<dyn std::error::Error>> {
+    fs::create_dir_all(dir)?;
+
+    let image = RgbaImage::from_raw(frame.width, frame.height, frame.data.clone())
+        .ok_or("frame data does not match its size")?;
+    image.save(dir.join(format!("{}.png", key)))?;
+
+    let entry = CacheEntry {
+        info: info.clone(),
+        pts: frame.pts,
+    };
+    // Written last, entries without it are never read
+    fs::write(
+        dir.join(format!("{}.json", key)),
+        serde_json::to_string(&entry)?,
+    )?;
+    Ok(())
+}
+
+/**
+    Remove the least recently written entries when a cache directory is over
+    its limit. The files of an entry share its cache key, and are removed
+    together so that no half of an entry is left behind.
+*/
+pub(super) fn prune(dir: &Path) {
+    prune_to(dir, MAX_ENTRIES);
+}
+
+fn prune_to(dir: &Path, max_entries: usize) {
+    let Ok(files) = fs::read_dir(dir) else {
+        return;
+    };
+
+    // Files by cache key, with the time the newest of them was written
+    let mut entries: HashMap<String, (SystemTime, Vec<PathBuf>)> = HashMap::new();
+    for file in files.flatten() {
+        let path = file.path();
+        let modified = file.metadata().and_then(|metadata| metadata.modified());
+        let (Some(key), Ok(modified)) = (path.file_stem().and_then(|s| s.to_str()), modified)
+        else {
+            continue;
+        };
+        let entry = entries
+            .entry(key.to_string())
+            .or_insert_with(|| (modified, Vec::new()));
+        entry.0 = entry.0.max(modified);
+        entry.1.push(path);
+    }
+    if entries.len() <= max_entries {
+        return;
+    }
+
+    let mut entries: Vec<_> = entries.into_values().collect();
+    entries.sort_by_key(|(modified, _)| *modified);
+    let excess = entries.len() - max_entries;
+    for (_, paths) in entries.into_iter().take(excess) {
+        for path in paths {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prune_removes_whole_entries() {
+        let dir = std::env::temp_dir().join(format!("vidwall-frame-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let write_entry = |key: &str, secs: u64| {
+            for extension in ["png", "json"] {
+                let path = dir.join(format!("{}.{}", key, extension));
+                fs::write(&path, b"").unwrap();
+                fs::File::options()
+                    .write(true)
+                    .open(&path)
+                    .unwrap()
+                    .set_modified(UNIX_EPOCH + Duration::from_secs(secs))
+                    .unwrap();
+            }
+        };
+        write_entry("a", 100);
+        write_entry("b", 300);
+        write_entry("c", 200);
+
+        // Two entries are four files, the oldest entry goes as a whole
+        prune_to(&dir, 2);
+        let mut left: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|file| file.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["b.json", "b.png", "c.json", "c.png"]);
+
+        // Nothing is removed within the limit
+        prune_to(&dir, 2);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
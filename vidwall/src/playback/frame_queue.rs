@@ -2,6 +2,8 @@ use std::collections::VecDeque;
 use std::sync::{Condvar, Mutex};
 use std::time::Duration;
 
+use crate::queue_stats::{QueueOp, QueueStats};
+
 use super::frame::VideoFrame;
 
 /**
@@ -11,24 +13,51 @@ pub struct FrameQueue {
     inner: Mutex<QueueInner>,
     not_full: Condvar,
     not_empty: Condvar,
+    stats: QueueStats,
 }
 
 struct QueueInner {
     frames: VecDeque<VideoFrame>,
     capacity: usize,
     closed: bool,
+    /// Total size of the queued frames' pixel data
+    bytes: usize,
+}
+
+impl QueueInner {
+    fn push_back(&mut self, frame: VideoFrame) {
+        self.bytes += frame.data.len();
+        self.frames.push_back(frame);
+    }
+
+    fn pop_front(&mut self) -> Option<VideoFrame> {
+        let frame = self.frames.pop_front()?;
+        self.bytes -= frame.data.len();
+        Some(frame)
+    }
+
+    fn clear(&mut self) {
+        self.frames.clear();
+        self.bytes = 0;
+    }
 }
 
 impl FrameQueue {
-    pub fn new(capacity: usize) -> Self {
+    /**
+        Create a new frame queue with the given capacity.
+        The name identifies the queue in instrumentation logs.
+    */
+    pub fn new(name: &'static str, capacity: usize) -> Self {
         Self {
             inner: Mutex::new(QueueInner {
                 frames: VecDeque::with_capacity(capacity),
                 capacity,
                 closed: false,
+                bytes: 0,
             }),
             not_full: Condvar::new(),
             not_empty: Condvar::new(),
+            stats: QueueStats::new(name, capacity),
         }
     }
 
@@ -37,18 +66,21 @@ impl FrameQueue {
         Returns false if the queue was closed.
     */
     pub fn push(&self, frame: VideoFrame) -> bool {
-        let mut inner = self.inner.lock().unwrap();
+        let inner = self.inner.lock().unwrap();
 
         // Wait until there's space or queue is closed
-        while inner.frames.len() >= inner.capacity && !inner.closed {
-            inner = self.not_full.wait(inner).unwrap();
-        }
+        let mut inner = self
+            .stats
+            .wait_while(&self.not_full, inner, QueueOp::Push, |inner| {
+                inner.frames.len() >= inner.capacity && !inner.closed
+            });
 
         if inner.closed {
             return false;
         }
 
-        inner.frames.push_back(frame);
+        inner.push_back(frame);
+        self.stats.record_push(inner.frames.len());
         self.not_empty.notify_one();
         true
     }
@@ -63,7 +95,8 @@ impl FrameQueue {
             return false;
         }
 
-        inner.frames.push_back(frame);
+        inner.push_back(frame);
+        self.stats.record_push(inner.frames.len());
         self.not_empty.notify_one();
         true
     }
@@ -73,15 +106,18 @@ impl FrameQueue {
         Returns None if the queue is closed and empty.
     */
     pub fn pop(&self) -> Option<VideoFrame> {
-        let mut inner = self.inner.lock().unwrap();
+        let inner = self.inner.lock().unwrap();
 
         // Wait until there's a frame or queue is closed
-        while inner.frames.is_empty() && !inner.closed {
-            inner = self.not_empty.wait(inner).unwrap();
-        }
+        let mut inner = self
+            .stats
+            .wait_while(&self.not_empty, inner, QueueOp::Pop, |inner| {
+                inner.frames.is_empty() && !inner.closed
+            });
 
-        let frame = inner.frames.pop_front();
+        let frame = inner.pop_front();
         if frame.is_some() {
+            self.stats.record_pop();
             self.not_full.notify_one();
         }
         frame
@@ -92,8 +128,9 @@ impl FrameQueue {
     */
     pub fn try_pop(&self) -> Option<VideoFrame> {
         let mut inner = self.inner.lock().unwrap();
-        let frame = inner.frames.pop_front();
+        let frame = inner.pop_front();
         if frame.is_some() {
+            self.stats.record_pop();
             self.not_full.notify_one();
         }
         frame
@@ -110,8 +147,9 @@ impl FrameQueue {
             inner = result.0;
         }
 
-        let frame = inner.frames.pop_front();
+        let frame = inner.pop_front();
         if frame.is_some() {
+            self.stats.record_pop();
             self.not_full.notify_one();
         }
         frame
@@ -132,6 +170,31 @@ impl FrameQueue {
         self.inner.lock().unwrap().frames.len()
     }
 
+    /**
+        Get the maximum number of frames the queue holds.
+    */
+    pub fn capacity(&self) -> usize {
+        self.inner.lock().unwrap().capacity
+    }
+
+    /**
+        Change the maximum number of frames the queue holds. Frames already
+        queued past a smaller capacity are kept, and pushes wait until
+        they have been popped.
+    */
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.capacity = capacity.max(1);
+        self.not_full.notify_all();
+    }
+
+    /**
+        Get the total size of the queued frames, in bytes.
+    */
+    pub fn bytes(&self) -> usize {
+        self.inner.lock().unwrap().bytes
+    }
+
     /**
         Check if the queue is empty.
     */
@@ -161,7 +224,7 @@ impl FrameQueue {
     */
     pub fn clear(&self) {
         let mut inner = self.inner.lock().unwrap();
-        inner.frames.clear();
+        inner.clear();
         self.not_full.notify_all();
     }
 
@@ -171,7 +234,7 @@ impl FrameQueue {
     */
     pub fn reopen(&self) {
         let mut inner = self.inner.lock().unwrap();
-        inner.frames.clear();
+        inner.clear();
         inner.closed = false;
         self.not_full.notify_all();
     }
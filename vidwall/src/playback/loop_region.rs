@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+/**
+    A/B loop markers on a video: once both are set, playback goes back
+    to the in point whenever it reaches the out point.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoopRegion {
+    pub start: Option<Duration>,
+    pub end: Option<Duration>,
+}
+
+impl LoopRegion {
+    /**
+        Get the looped range, if both markers are set with the out point
+        after the in point.
+    */
+    pub fn range(&self) -> Option<(Duration, Duration)> {
+        let (start, end) = (self.start?, self.end?);
+        (start < end).then_some((start, end))
+    }
+
+    /**
+        Get the position to go back to, if playback at the given position
+        has reached the out point.
+    */
+    pub fn restart_at(&self, position: Duration) -> Option<Duration> {
+        let (start, end) = self.range()?;
+        (position >= end).then_some(start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restart_at() {
+        let secs = Duration::from_secs;
+
+        let mut region = LoopRegion::default();
+        assert_eq!(region.restart_at(secs(10)), None);
+
+        region.start = Some(secs(5));
+        assert_eq!(region.restart_at(secs(10)), None);
+
+        region.end = Some(secs(8));
+        assert_eq!(region.range(), Some((secs(5), secs(8))));
+        assert_eq!(region.restart_at(secs(7)), None);
+        assert_eq!(region.restart_at(secs(8)), Some(secs(5)));
+
+        // An out point before the in point is no loop
+        region.end = Some(secs(2));
+        assert_eq!(region.range(), None);
+        assert_eq!(region.restart_at(secs(10)), None);
+    }
+}
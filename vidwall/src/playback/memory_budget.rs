@@ -0,0 +1,139 @@
+use gpui::Global;
+
+/**
+    Default memory budget for buffered video across all players, in megabytes
+*/
+const DEFAULT_BUDGET_MB: u64 = 4096;
+
+/**
+    Share of the budget players are planned to fill, leaving headroom
+    for the frames being decoded and shown
+*/
+const TARGET_SHARE: f64 = 0.8;
+
+/**
+    Smallest share of their full queue capacities players shrink to,
+    below which they stall on every hiccup of the decoder
+*/
+const MIN_QUEUE_SCALE: f32 = 0.1;
+
+/**
+    Smallest share of the requested resolution frames are decoded at
+*/
+const MIN_RESOLUTION_SCALE: f32 = 0.25;
+
+/**
+    How much the players shrink their buffers to fit in the memory budget.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryPlan {
+    /// Share of the full packet and frame queue capacities to use
+    pub queue_scale: f32,
+    /// Share of the requested width and height to decode frames at
+    pub resolution_scale: f32,
+}
+
+impl Default for MemoryPlan {
+    fn default() -> Self {
+        Self {
+            queue_scale: 1.0,
+            resolution_scale: 1.0,
+        }
+    }
+}
+
+/**
+    Cap on the memory used by buffered packets and decoded frames across
+    all players.
+
+    Many tiles of 4K video can easily take more memory than the machine
+    has, since every player buffers dozens of full size frames. Once the
+    players would use more than the budget, their queues shrink first, and
+    if that isn't enough, frames are decoded at a lower resolution too.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryBudget {
+    /// Budget in bytes
+    pub limit: u64,
+}
+
+impl Global for MemoryBudget {}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self::from_megabytes(DEFAULT_BUDGET_MB)
+    }
+}
+
+impl MemoryBudget {
+    pub fn from_megabytes(megabytes: u64) -> Self {
+        Self {
+            limit: megabytes * 1024 * 1024,
+        }
+    }
+
+    /**
+        Plan how far the players shrink, given the memory they would use
+        with full queues at the requested resolutions.
+    */
+    pub fn plan(&self, demand: u64) -> MemoryPlan {
+        let target = self.limit as f64 * TARGET_SHARE;
+        if demand as f64 <= target {
+            return MemoryPlan::default();
+        }
+
+        let ratio = target / demand as f64;
+        let queue_scale = (ratio as f32).max(MIN_QUEUE_SCALE);
+
+        // Frame memory goes with the square of the resolution
+        let remaining = ratio / f64::from(queue_scale);
+        let resolution_scale = (remaining.sqrt() as f32).clamp(MIN_RESOLUTION_SCALE, 1.0);
+
+        MemoryPlan {
+            queue_scale,
+            resolution_scale,
+        }
+    }
+}
+
+/**
+    Scale a full queue capacity by the plan, keeping at least the given minimum.
+*/
+pub fn scaled_capacity(full: usize, scale: f32, min: usize) -> usize {
+    ((full as f32 * scale).round() as usize).clamp(min.min(full), full)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    #[test]
+    fn test_plan() {
+        let budget = MemoryBudget::from_megabytes(1000);
+        assert_eq!(budget.plan(500 * MB), MemoryPlan::default());
+        assert_eq!(budget.plan(800 * MB), MemoryPlan::default());
+
+        // Shrinking the queues is enough
+        let plan = budget.plan(1600 * MB);
+        assert_eq!(plan.queue_scale, 0.5);
+        assert_eq!(plan.resolution_scale, 1.0);
+
+        // Queues at their smallest, and a quarter of the pixels
+        let plan = budget.plan(32000 * MB);
+        assert_eq!(plan.queue_scale, MIN_QUEUE_SCALE);
+        assert!((plan.resolution_scale - 0.5).abs() < 0.001);
+
+        let plan = budget.plan(u64::MAX);
+        assert_eq!(plan.resolution_scale, MIN_RESOLUTION_SCALE);
+    }
+
+    #[test]
+    fn test_scaled_capacity() {
+        assert_eq!(scaled_capacity(60, 1.0, 4), 60);
+        assert_eq!(scaled_capacity(60, 0.5, 4), 30);
+        assert_eq!(scaled_capacity(60, 0.01, 4), 4);
+        assert_eq!(scaled_capacity(2, 0.01, 4), 2);
+    }
+}
@@ -1,9 +1,21 @@
 mod audio_pipeline;
 mod frame;
+mod frame_cache;
 mod frame_queue;
+mod loop_region;
+mod memory_budget;
+mod motion;
 mod player;
+mod review_clock;
+mod thumbnails;
 mod video_pipeline;
 
 pub use frame::VideoFrame;
 pub use frame_queue::FrameQueue;
-pub use player::{PlaybackClock, PlaybackState, VideoPlayer};
+pub use loop_region::LoopRegion;
+pub use memory_budget::{MemoryBudget, MemoryPlan};
+pub use motion::{MotionDetector, MotionEvent, MotionSettings, save_snapshot, snapshot_file_name};
+pub use player::{
+    PlaybackClock, PlaybackError, PlaybackEvent, PlaybackState, PlayerOptions, VideoPlayer,
+};
+pub use thumbnails::{Thumbnails, sample_positions};
@@ -0,0 +1,288 @@
+use std::error::Error;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use image::RgbaImage;
+
+use super::VideoFrame;
+
+/**
+    Size of the luma grid that frames are downscaled to before comparing,
+    small enough to ignore sensor noise and compression artifacts
+*/
+const GRID_WIDTH: usize = 64;
+const GRID_HEIGHT: usize = 36;
+
+/**
+    How much the brightness of a grid cell must change, out of 255,
+    for the cell to count as changed
+*/
+const CELL_THRESHOLD: u8 = 24;
+
+/**
+    Settings for detecting motion in a video.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionSettings {
+    /// Fraction of the picture that must change between frames, from 0 to 1
+    pub sensitivity: f32,
+    /// Shortest time between two reported motion events
+    pub cooldown: Duration,
+}
+
+impl Default for MotionSettings {
+    fn default() -> Self {
+        Self {
+            sensitivity: 0.02,
+            cooldown: Duration::from_secs(10),
+        }
+    }
+}
+
+/**
+    Motion found between two frames of a video.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionEvent {
+    /// Fraction of the picture that changed, from 0 to 1
+    pub area: f32,
+    /// Presentation timestamp of the frame the motion was found in
+    pub pts: Duration,
+}
+
+/**
+    Detects motion in a video by differencing the brightness of consecutive
+    frames, downscaled to a coarse grid.
+
+    Frames are fed in as they are shown, and an event is reported whenever
+    enough of the picture changed since the previous frame - at most once
+    per cooldown, so that ongoing motion doesn't report an event per frame.
+*/
+pub struct MotionDetector {
+    settings: MotionSettings,
+    previous: Option<LumaGrid>,
+    last_event: Option<Instant>,
+}
+
+impl MotionDetector {
+    pub fn new(settings: MotionSettings) -> Self {
+        Self {
+            settings,
+            previous: None,
+            last_event: None,
+        }
+    }
+
+    /**
+        Compare a frame to the previous one, returning the motion found,
+        if any. The same frame observed twice in a row is ignored.
+    */
+    pub fn observe(&mut self, frame: &VideoFrame, now: Instant) -> Option<MotionEvent> {
+        let current = LumaGrid::from_frame(frame)?;
+        if self.previous.as_ref().is_some_and(|p| p.pts == current.pts) {
+            return None;
+        }
+        let area = self.previous.as_ref().map(|p| current.changed_area(p));
+        self.previous = Some(current);
+
+        let area = area?;
+        if area < self.settings.sensitivity {
+            return None;
+        }
+        let cooling_down = self
+            .last_event
+            .is_some_and(|last| now.saturating_duration_since(last) < self.settings.cooldown);
+        if cooling_down {
+            return None;
+        }
+
+        self.last_event = Some(now);
+        Some(MotionEvent {
+            area,
+            pts: frame.pts,
+        })
+    }
+
+    /**
+        Forget the previous frame, for when the video restarts or seeks.
+    */
+    pub fn reset(&mut self) {
+        self.previous = None;
+    }
+}
+
+/**
+    Get the file name of a snapshot of the given source taken at the given
+    time, with everything but letters and digits of the source replaced.
+*/
+pub fn snapshot_file_name(source: &str, time: SystemTime) -> String {
+    let source = source.split_once("://").map_or(source, |(_, rest)| rest);
+    let source: String = source
+        .trim_end_matches('/')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis())
+        .unwrap_or_default();
+    format!("{} {}.png", source, millis)
+}
+
+/**
+    Save a frame as a PNG image, converting it from BGRA.
+*/
+pub fn save_snapshot(frame: &VideoFrame, path: &Path) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut data = frame.data.clone();
+    for pixel in data.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+    let image = RgbaImage::from_raw(frame.width, frame.height, data)
+        .ok_or("frame data does not match its size")?;
+    image.save(path)?;
+    Ok(())
+}
+
+/**
+    Average brightness of each cell of a frame divided into a fixed grid.
+*/
+struct LumaGrid {
+    cells: Vec<u8>,
+    pts: Duration,
+}
+
+impl LumaGrid {
+    fn from_frame(frame: &VideoFrame) -> Option<Self> {
+        let width = frame.width as usize;
+        let height = frame.height as usize;
+        if width == 0 || height == 0 || frame.data.len() < width * height * 4 {
+            return None;
+        }
+
+        let mut sums = vec![0u32; GRID_WIDTH * GRID_HEIGHT];
+        let mut counts = vec![0u32; GRID_WIDTH * GRID_HEIGHT];
+        for (y, row) in frame.data.chunks_exact(width * 4).take(height).enumerate() {
+            let cell_row = y * GRID_HEIGHT / height * GRID_WIDTH;
+            for (x, pixel) in row.chunks_exact(4).enumerate() {
+                // BT.601 luma from BGRA, in fixed point
+                let luma = (29 * u32::from(pixel[0])
+                    + 150 * u32::from(pixel[1])
+                    + 77 * u32::from(pixel[2]))
+                    >> 8;
+                let cell = cell_row + x * GRID_WIDTH / width;
+                sums[cell] += luma;
+                counts[cell] += 1;
+            }
+        }
+
+        let cells = sums
+            .iter()
+            .zip(&counts)
+            .map(|(&sum, &count)| sum.checked_div(count).unwrap_or(0) as u8)
+            .collect();
+        Some(Self {
+            cells,
+            pts: frame.pts,
+        })
+    }
+
+    /**
+        Fraction of cells whose brightness changed noticeably since another grid.
+    */
+    fn changed_area(&self, other: &Self) -> f32 {
+        let changed = self
+            .cells
+            .iter()
+            .zip(&other.cells)
+            .filter(|(a, b)| a.abs_diff(**b) > CELL_THRESHOLD)
+            .count();
+        changed as f32 / self.cells.len() as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(width: u32, height: u32, pts_ms: u64, lit: impl Fn(u32, u32) -> bool) -> VideoFrame {
+        let mut data = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let value = if lit(x, y) { 255 } else { 0 };
+                data.extend_from_slice(&[value, value, value, 255]);
+            }
+        }
+        VideoFrame::new(data, width, height, Duration::from_millis(pts_ms))
+    }
+
+    #[test]
+    fn test_detects_changed_area() {
+        let mut detector = MotionDetector::new(MotionSettings::default());
+        let now = Instant::now();
+
+        assert_eq!(
+            detector.observe(&frame(128, 72, 0, |_, _| false), now),
+            None
+        );
+        assert_eq!(
+            detector.observe(&frame(128, 72, 40, |_, _| false), now),
+            None
+        );
+
+        // A quarter of the picture lights up
+        let event = detector
+            .observe(&frame(128, 72, 80, |x, y| x < 64 && y < 36), now)
+            .unwrap();
+        assert!((event.area - 0.25).abs() < 0.01);
+        assert_eq!(event.pts, Duration::from_millis(80));
+    }
+
+    #[test]
+    fn test_ignores_small_changes_and_repeats() {
+        let mut detector = MotionDetector::new(MotionSettings::default());
+        let now = Instant::now();
+        let dark = frame(128, 72, 0, |_, _| false);
+
+        detector.observe(&dark, now);
+        // A single pixel is noise
+        assert_eq!(
+            detector.observe(&frame(128, 72, 40, |x, y| x == 0 && y == 0), now),
+            None
+        );
+        // The same frame again is not compared against itself
+        let lit = frame(128, 72, 80, |_, _| true);
+        assert!(detector.observe(&lit, now).is_some());
+        assert_eq!(detector.observe(&lit, now), None);
+    }
+
+    #[test]
+    fn test_cooldown_between_events() {
+        let settings = MotionSettings::default();
+        let mut detector = MotionDetector::new(settings);
+        let start = Instant::now();
+        let dark = |pts| frame(64, 36, pts, |_, _| false);
+        let lit = |pts| frame(64, 36, pts, |_, _| true);
+
+        detector.observe(&dark(0), start);
+        assert!(detector.observe(&lit(40), start).is_some());
+        assert_eq!(
+            detector.observe(&dark(80), start + Duration::from_secs(1)),
+            None
+        );
+
+        let later = start + settings.cooldown;
+        assert!(detector.observe(&lit(120), later).is_some());
+    }
+
+    #[test]
+    fn test_snapshot_file_name() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        assert_eq!(
+            snapshot_file_name("rtsp://cam1:554/live/", time),
+            "cam1_554_live 1700000000123.png"
+        );
+    }
+}
@@ -3,16 +3,23 @@ use std::sync::{
     Arc, Mutex,
     atomic::{AtomicU64, Ordering},
 };
+use std::thread;
 use std::time::{Duration, Instant};
 
 use gpui::RenderImage;
 use image::{Frame, RgbaImage};
 
 use crate::audio::{AudioStreamClock, AudioStreamConsumer};
-use crate::decode::{DecoderError, get_video_info};
+use crate::decode::{
+    DecoderError, OverflowPolicy, ScalingAlgorithm, VideoDecodeConfig, VideoInfo, get_video_info,
+};
 
 use super::audio_pipeline::AudioPipeline;
 use super::frame::VideoFrame;
+use super::frame_cache;
+use super::loop_region::LoopRegion;
+use super::memory_budget::MemoryPlan;
+use super::review_clock::ReviewClock;
 use super::video_pipeline::VideoPipeline;
 
 /**
@@ -21,11 +28,70 @@ use super::video_pipeline::VideoPipeline;
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum PlaybackState {
     Playing,
+    /// Playing, but waiting for the decoder to refill the frame queue
+    Buffering,
     Paused,
     Ended,
     Error,
 }
 
+/**
+    Why playback failed, for showing on the tile
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaybackError {
+    /// The decoder error, prefixed with the pipeline stage that hit it
+    pub message: String,
+    /// Codec and size of the video stream, e.g. "h264 1920x1080"
+    pub codec: String,
+}
+
+/**
+    Buffering events, for showing rebuffering of network sources
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaybackEvent {
+    /// The frame queue ran dry and playback is waiting for it to refill
+    StallStarted,
+    /// Frame queue fill in percent while stalled, emitted when it changes
+    BufferFill(u8),
+    /// Playback continued after stalling for the given duration
+    StallEnded(Duration),
+}
+
+/**
+    Fraction of the frame queue that must be filled before playback
+    continues after a stall, so that it doesn't stall again right away
+*/
+const REBUFFER_FILL: f32 = 0.25;
+
+/**
+    How long past the current frame playback goes on without a next
+    frame before it stalls, to ride out short decoding hiccups
+*/
+const STALL_THRESHOLD: Duration = Duration::from_millis(250);
+
+/**
+    Time between frames assumed for stepping back, until two frames have
+    been shown to measure it from
+*/
+const DEFAULT_FRAME_INTERVAL: Duration = Duration::from_nanos(1_000_000_000 / 30);
+
+/**
+    Options for creating a video player
+*/
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlayerOptions {
+    /// Options for decoding the video
+    pub decode: VideoDecodeConfig,
+    /// Start playback here instead of at the beginning of the file
+    pub start_at: Option<Duration>,
+    /// End playback here instead of at the end of the file
+    pub end_at: Option<Duration>,
+    /// What happens to audio when its decoder falls behind the demuxer
+    pub audio_overflow: OverflowPolicy,
+}
+
 /**
     Playback clock abstraction.
 
@@ -157,9 +223,27 @@ pub struct VideoPlayer {
     current_frame: Mutex<Option<VideoFrame>>,
     next_frame: Mutex<Option<VideoFrame>>,
     base_pts: Mutex<Option<Duration>>,
+    /// Clock position that the first frame after starting or seeking is shown at
+    clock_start: Mutex<Duration>,
     duration: Duration,
     state: Mutex<PlaybackState>,
 
+    // Review
+    /// Clock used instead of the playback clock when stepping or in slow motion
+    review: Mutex<Option<ReviewClock>>,
+    /// Time between the last two frames shown, for stepping back one frame
+    frame_interval: Mutex<Duration>,
+    loop_region: Mutex<LoopRegion>,
+
+    // Buffering
+    /// Start of the current stall, and the last reported buffer fill
+    stall: Mutex<Option<(Instant, u8)>>,
+    events: Mutex<Vec<PlaybackEvent>>,
+
+    // First frame cache, the stream info is taken once the first frame is stored
+    start_at: Option<Duration>,
+    uncached_info: Mutex<Option<VideoInfo>>,
+
     // Render cache
     cached_render_image: Mutex<Option<Arc<RenderImage>>>,
     frame_generation: AtomicU64,
@@ -167,36 +251,43 @@ pub struct VideoPlayer {
 
 impl VideoPlayer {
     /**
-        Create a new video player for the given file
-    */
-    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, DecoderError> {
-        Self::with_options(path, None, None)
-    }
+        Create a new video player with options for decoding its video
+        (target dimensions, keyframes only, reduced resolution), and for
+        playing an excerpt of the file between `start_at` and `end_at`.
 
-    /**
-        Create a new video player with target dimensions
+        Playback starts at the keyframe at or before `start_at`, and
+        ends at `end_at` - the file itself is left untouched.
+
+        Audio packets are dropped or waited on, when the audio decoder
+        falls behind, as `audio_overflow` says.
     */
     pub fn with_options<P: AsRef<Path>>(
         path: P,
-        target_width: Option<u32>,
-        target_height: Option<u32>,
+        options: PlayerOptions,
     ) -> Result<Self, DecoderError> {
         let path = path.as_ref().to_path_buf();
-        let info = get_video_info(&path)?;
+
+        // A cached first frame is shown while the decoders start up
+        let cached = frame_cache::load(&path, options.start_at);
+        let info = match cached {
+            Some(ref cached) => cached.info.clone(),
+            None => get_video_info(&path)?,
+        };
 
         // Create audio pipeline (if file has audio)
         // This is completely independent - owns its own file handle and threads
-        let audio_pipeline = match AudioPipeline::new(path.clone()) {
-            Ok(pipeline) => pipeline,
-            Err(e) => {
-                eprintln!("Warning: Audio pipeline failed: {}. Using wall clock.", e);
-                None
-            }
-        };
+        let audio_pipeline =
+            match AudioPipeline::new(path.clone(), options.end_at, options.audio_overflow) {
+                Ok(pipeline) => pipeline,
+                Err(e) => {
+                    eprintln!("Warning: Audio pipeline failed: {}. Using wall clock.", e);
+                    None
+                }
+            };
 
         // Create video pipeline (always required)
         // This is completely independent - owns its own file handle and threads
-        let video_pipeline = VideoPipeline::new(path.clone(), target_width, target_height)?;
+        let video_pipeline = VideoPipeline::new(path.clone(), options.decode, options.end_at)?;
 
         // Determine clock source based on audio availability
         let playback_clock = if let Some(ref audio) = audio_pipeline {
@@ -205,7 +296,7 @@ impl VideoPlayer {
             PlaybackClock::wall_time()
         };
 
-        Ok(Self {
+        let player = Self {
             path,
             audio_pipeline,
             video_pipeline,
@@ -213,11 +304,33 @@ impl VideoPlayer {
             current_frame: Mutex::new(None),
             next_frame: Mutex::new(None),
             base_pts: Mutex::new(None),
-            duration: info.duration,
+            clock_start: Mutex::new(Duration::ZERO),
+            duration: options
+                .end_at
+                .map_or(info.duration, |end| end.min(info.duration)),
             state: Mutex::new(PlaybackState::Playing),
+            review: Mutex::new(None),
+            frame_interval: Mutex::new(DEFAULT_FRAME_INTERVAL),
+            loop_region: Mutex::new(LoopRegion::default()),
+            stall: Mutex::new(None),
+            events: Mutex::new(Vec::new()),
+            start_at: options.start_at,
+            uncached_info: Mutex::new(None),
             cached_render_image: Mutex::new(None),
             frame_generation: AtomicU64::new(0),
-        })
+        };
+
+        // Nothing has the audio consumer yet, so the new one can be ignored
+        if let Some(start) = options.start_at {
+            player.seek_to(start)?;
+        }
+
+        match cached {
+            Some(cached) => *player.current_frame.lock().unwrap() = Some(cached.frame),
+            None => *player.uncached_info.lock().unwrap() = Some(info),
+        }
+
+        Ok(player)
     }
 
     /**
@@ -228,7 +341,7 @@ impl VideoPlayer {
     }
 
     /**
-        Get the video duration
+        Get the video duration, up to the end point if one was given
     */
     pub fn duration(&self) -> Duration {
         self.duration
@@ -240,7 +353,10 @@ impl VideoPlayer {
         For videos without audio, this is based on wall clock.
     */
     pub fn position(&self) -> Duration {
-        self.playback_clock.position()
+        match *self.review.lock().unwrap() {
+            Some(review) => review.position(),
+            None => self.playback_clock.position(),
+        }
     }
 
     /**
@@ -250,6 +366,17 @@ impl VideoPlayer {
         *self.state.lock().unwrap()
     }
 
+    /**
+        Get the error that stopped playback, if it has failed
+    */
+    pub fn error(&self) -> Option<PlaybackError> {
+        let failure = self.video_pipeline.failure()?;
+        Some(PlaybackError {
+            message: format!("Video {} failed: {}", failure.stage, failure.message),
+            codec: self.video_pipeline.codec_description(),
+        })
+    }
+
     /**
         Check if playback has ended
     */
@@ -264,17 +391,47 @@ impl VideoPlayer {
         self.state() == PlaybackState::Paused
     }
 
+    /**
+        Check if playback is stalled waiting for frames
+    */
+    pub fn is_buffering(&self) -> bool {
+        self.state() == PlaybackState::Buffering
+    }
+
+    /**
+        Get how full the decoded frame queue is, from 0.0 to 1.0
+    */
+    pub fn buffer_fill(&self) -> f32 {
+        let frame_queue = self.video_pipeline.frame_queue();
+        frame_queue.len() as f32 / frame_queue.capacity().max(1) as f32
+    }
+
+    /**
+        Take the buffering events that happened since the last call
+    */
+    pub fn take_events(&self) -> Vec<PlaybackEvent> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+
     /**
         Pause video and audio playback
     */
     pub fn pause(&self) {
         let mut state = self.state.lock().unwrap();
-        if *state == PlaybackState::Playing {
-            *state = PlaybackState::Paused;
-            self.playback_clock.pause();
-            if let Some(ref audio) = self.audio_pipeline {
-                audio.consumer().pause();
+        match *state {
+            PlaybackState::Playing => {
+                *state = PlaybackState::Paused;
+                self.pause_clock();
+            }
+            // The clock is already paused while buffering
+            PlaybackState::Buffering => {
+                self.end_stall();
+                *state = PlaybackState::Paused;
             }
+            _ => {}
+        }
+        if let Some(review) = self.review.lock().unwrap().as_mut() {
+            review.pause();
         }
     }
 
@@ -285,10 +442,175 @@ impl VideoPlayer {
         let mut state = self.state.lock().unwrap();
         if *state == PlaybackState::Paused {
             *state = PlaybackState::Playing;
-            self.playback_clock.resume();
-            if let Some(ref audio) = self.audio_pipeline {
-                audio.consumer().resume();
+            match self.review.lock().unwrap().as_mut() {
+                Some(review) => review.resume(),
+                None => self.resume_clock(),
+            }
+        }
+    }
+
+    /**
+        Get the A/B loop markers
+    */
+    pub fn loop_region(&self) -> LoopRegion {
+        *self.loop_region.lock().unwrap()
+    }
+
+    /**
+        Set the A/B loop markers, looping once both are set
+    */
+    pub fn set_loop_region(&self, region: LoopRegion) {
+        *self.loop_region.lock().unwrap() = region;
+    }
+
+    /**
+        Get the position to seek back to, if playback has reached the out
+        point of the A/B loop, or ended before getting there
+    */
+    pub fn loop_restart(&self) -> Option<Duration> {
+        let region = self.loop_region();
+        if self.is_ended() {
+            return region.range().map(|(start, _)| start);
+        }
+        region.restart_at(self.position())
+    }
+
+    /**
+        Get the playback speed, below 1.0 in slow motion
+    */
+    pub fn speed(&self) -> f32 {
+        self.review
+            .lock()
+            .unwrap()
+            .map_or(1.0, |review| review.rate())
+    }
+
+    /**
+        Play in slow motion at the given speed, or at normal speed again if
+        it is 1.0 or more. Audio is paused while playing in slow motion.
+
+        Returns the new audio consumer when going back to normal speed,
+        if this video has audio (caller must update mixer).
+    */
+    pub fn set_speed(&self, speed: f32) -> Result<Option<Arc<AudioStreamConsumer>>, DecoderError> {
+        if speed >= 1.0 {
+            return self.end_review();
+        }
+        self.with_review(|review| review.set_rate(speed));
+        Ok(None)
+    }
+
+    /**
+        Pause and show the next frame.
+    */
+    pub fn step_forward(&self) {
+        self.pause();
+
+        let position = {
+            let mut next = self.next_frame.lock().unwrap();
+            if next.is_none() {
+                *next = self.video_pipeline.frame_queue().try_pop();
+            }
+            let base = self.base_pts.lock().unwrap().unwrap_or(Duration::ZERO);
+            match next.as_ref() {
+                Some(frame) => frame.pts.saturating_sub(base),
+                None => return,
+            }
+        };
+        self.with_review(|review| review.seek_to(position));
+    }
+
+    /**
+        Pause and show the previous frame.
+
+        Decoded frames are not kept around, so this seeks back to the keyframe
+        before the previous frame and decodes forward from there, skipping
+        frames until the previous one is reached.
+
+        Returns the new audio consumer if this video has audio (caller must update mixer).
+    */
+    pub fn step_backward(&self) -> Result<Option<Arc<AudioStreamConsumer>>, DecoderError> {
+        self.pause();
+
+        let current = self.current_frame.lock().unwrap().as_ref().map(|f| f.pts);
+        let Some(pts) = current else {
+            return Ok(None);
+        };
+        let base = self.base_pts.lock().unwrap().unwrap_or(Duration::ZERO);
+        // Aim between the previous frame and this one, so that small
+        // rounding errors in timestamps don't land on either side of it
+        let interval = *self.frame_interval.lock().unwrap();
+        let target = pts.saturating_sub(base).saturating_sub(interval / 2);
+
+        self.with_review(|review| review.seek_to(target));
+        self.seek_to(target)
+    }
+
+    /**
+        Stop reviewing and continue at normal speed from the reviewed position.
+
+        Returns the new audio consumer if this video has audio (caller must update mixer).
+    */
+    pub fn end_review(&self) -> Result<Option<Arc<AudioStreamConsumer>>, DecoderError> {
+        let Some(review) = self.review.lock().unwrap().take() else {
+            return Ok(None);
+        };
+
+        // The audio was left behind where reviewing started
+        let consumer = self.seek_to(review.position())?;
+        if self.state() == PlaybackState::Playing {
+            self.resume_clock();
+        }
+        Ok(consumer)
+    }
+
+    /**
+        Update the review clock, starting to review at the current position
+        first if needed. The playback clock and audio stay paused meanwhile.
+    */
+    fn with_review(&self, update: impl FnOnce(&mut ReviewClock)) {
+        // Locked in the same order as when pausing and resuming
+        let mut state = self.state.lock().unwrap();
+        let mut review = self.review.lock().unwrap();
+        let review = review.get_or_insert_with(|| {
+            match *state {
+                PlaybackState::Playing => self.pause_clock(),
+                // The clock is already paused while buffering
+                PlaybackState::Buffering => {
+                    self.end_stall();
+                    *state = PlaybackState::Playing;
+                }
+                _ => {}
             }
+            let running = *state == PlaybackState::Playing;
+            ReviewClock::new(self.playback_clock.position(), running)
+        });
+        update(review);
+    }
+
+    fn pause_clock(&self) {
+        self.playback_clock.pause();
+        if let Some(ref audio) = self.audio_pipeline {
+            audio.consumer().pause();
+        }
+    }
+
+    fn resume_clock(&self) {
+        self.playback_clock.resume();
+        if let Some(ref audio) = self.audio_pipeline {
+            audio.consumer().resume();
+        }
+    }
+
+    /**
+        Record the end of a stall, if one is in progress
+    */
+    fn end_stall(&self) {
+        if let Some((since, _)) = self.stall.lock().unwrap().take() {
+            self.events
+                .lock()
+                .unwrap()
+                .push(PlaybackEvent::StallEnded(since.elapsed()));
         }
     }
 
@@ -424,6 +746,7 @@ impl VideoPlayer {
             *self.current_frame.lock().unwrap() = None;
             *self.next_frame.lock().unwrap() = None;
             *self.base_pts.lock().unwrap() = None;
+            *self.clock_start.lock().unwrap() = actual_position;
             *self.cached_render_image.lock().unwrap() = None;
             self.frame_generation.fetch_add(1, Ordering::Relaxed);
         }
@@ -431,7 +754,14 @@ impl VideoPlayer {
         // Reset state to playing (unless it was paused)
         {
             let mut state = self.state.lock().unwrap();
-            if *state == PlaybackState::Ended || *state == PlaybackState::Error {
+            if *state == PlaybackState::Buffering {
+                self.end_stall();
+                self.resume_clock();
+            }
+            if matches!(
+                *state,
+                PlaybackState::Ended | PlaybackState::Error | PlaybackState::Buffering
+            ) {
                 *state = PlaybackState::Playing;
             }
         }
@@ -478,7 +808,9 @@ impl VideoPlayer {
         Returns (current_image, old_image_to_drop)
     */
     pub fn get_render_image(&self) -> (Option<Arc<RenderImage>>, Option<Arc<RenderImage>>) {
-        let elapsed = self.playback_clock.position();
+        let review = *self.review.lock().unwrap();
+        let reviewing = review.is_some();
+        let elapsed = review.map_or_else(|| self.playback_clock.position(), |r| r.position());
         let frame_queue = self.video_pipeline.frame_queue();
 
         let mut current = self.current_frame.lock().unwrap();
@@ -495,33 +827,96 @@ impl VideoPlayer {
             *next = frame_queue.try_pop();
         }
 
-        // Initialize base_pts from the first frame
+        // Initialize base_pts from the first frame, so that it is shown
+        // when the clock reaches the position playback started at
         if base_pts.is_none() {
             if let Some(ref frame) = *next {
-                *base_pts = Some(frame.pts);
+                let clock_start = *self.clock_start.lock().unwrap();
+                *base_pts = Some(frame.pts.saturating_sub(clock_start));
             }
         }
 
-        // Advance to the next frame if its PTS has passed
-        if let Some(ref frame) = *next {
+        // Advance to the next frame if its PTS has passed. While reviewing,
+        // skip ahead to the clock, as stepping back decodes from a keyframe
+        while let Some(ref frame) = *next {
             let base = base_pts.unwrap_or(Duration::ZERO);
             let relative_pts = frame.pts.saturating_sub(base);
+            if elapsed < relative_pts {
+                break;
+            }
+
+            if let Some(previous) = current.as_ref()
+                && frame.pts > previous.pts
+            {
+                *self.frame_interval.lock().unwrap() = frame.pts - previous.pts;
+            }
+            *current = next.take();
+            frame_changed = true;
+            self.cache_first_frame(current.as_ref());
+            self.frame_generation.fetch_add(1, Ordering::Relaxed);
+            *next = frame_queue.try_pop();
 
-            if elapsed >= relative_pts {
-                *current = next.take();
-                frame_changed = true;
-                self.frame_generation.fetch_add(1, Ordering::Relaxed);
-                *next = frame_queue.try_pop();
+            if !reviewing {
+                break;
             }
         }
 
+        // Stall when the next frame is due but the decoder hasn't produced it
+        // yet, and continue once the queue has refilled (or will get no more).
+        // The review clock doesn't stall, it only waits for the frames
+        match *state {
+            _ if reviewing => {}
+            PlaybackState::Playing if next.is_none() && !frame_queue.is_closed() => {
+                let base = base_pts.unwrap_or(Duration::ZERO);
+                let overdue = current.as_ref().is_some_and(|frame| {
+                    elapsed > frame.pts.saturating_sub(base) + STALL_THRESHOLD
+                });
+                if overdue {
+                    *state = PlaybackState::Buffering;
+                    self.pause_clock();
+                    *self.stall.lock().unwrap() = Some((Instant::now(), 0));
+                    self.events
+                        .lock()
+                        .unwrap()
+                        .push(PlaybackEvent::StallStarted);
+                }
+            }
+            PlaybackState::Buffering => {
+                let fill = self.buffer_fill();
+                if fill >= REBUFFER_FILL || frame_queue.is_closed() {
+                    *state = PlaybackState::Playing;
+                    self.end_stall();
+                    self.resume_clock();
+                } else if let Some((_, reported)) = self.stall.lock().unwrap().as_mut() {
+                    let percent = (fill * 100.0) as u8;
+                    if *reported != percent {
+                        *reported = percent;
+                        self.events
+                            .lock()
+                            .unwrap()
+                            .push(PlaybackEvent::BufferFill(percent));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        // A failed pipeline closes its frame queue, which must not look like
+        // the end of the video, so stop and keep showing the last frame
+        let failed = self.video_pipeline.failure().is_some();
+        if failed && *state != PlaybackState::Error {
+            *state = PlaybackState::Error;
+            self.end_stall();
+            self.pause_clock();
+        }
+
         // Check for end of playback
         // Only mark as ended when:
         // 1. No next frame buffered
         // 2. Frame queue is closed (no more frames coming)
         // 3. Frame queue is empty (all frames have been consumed)
         // 4. We've shown the current frame long enough (elapsed > its PTS)
-        if next.is_none() && frame_queue.is_closed() && frame_queue.is_empty() {
+        if !failed && next.is_none() && frame_queue.is_closed() && frame_queue.is_empty() {
             if let Some(ref frame) = *current {
                 let base = base_pts.unwrap_or(Duration::ZERO);
                 let frame_pts = frame.pts.saturating_sub(base);
@@ -549,6 +944,23 @@ impl VideoPlayer {
         (cached.clone(), old_image)
     }
 
+    /**
+        Store the first decoded frame in the frame cache, if it isn't cached yet.
+        Encoding happens on a separate thread to keep rendering smooth.
+    */
+    fn cache_first_frame(&self, frame: Option<&VideoFrame>) {
+        let Some(frame) = frame else {
+            return;
+        };
+        let Some(info) = self.uncached_info.lock().unwrap().take() else {
+            return;
+        };
+        let path = self.path.clone();
+        let start_at = self.start_at;
+        let frame = frame.clone();
+        thread::spawn(move || frame_cache::store(&path, start_at, &info, &frame));
+    }
+
     /**
         Get the current frame for rendering based on elapsed time.
     */
@@ -556,6 +968,38 @@ impl VideoPlayer {
         self.current_frame.lock().unwrap().clone()
     }
 
+    /**
+        Set the size that video frames are decoded at, and the algorithm
+        used to scale them, for example when the tile showing them is resized
+    */
+    pub fn set_output_size(&self, width: u32, height: u32, algorithm: ScalingAlgorithm) {
+        self.video_pipeline
+            .set_output_size(width, height, algorithm);
+    }
+
+    /**
+        Estimate the memory this player's buffers use when they are full,
+        at the size its tile asks for.
+    */
+    pub fn memory_demand(&self) -> u64 {
+        self.video_pipeline.memory_demand()
+    }
+
+    /**
+        Get the memory used by the buffered video packets and frames.
+    */
+    pub fn buffered_bytes(&self) -> u64 {
+        self.video_pipeline.buffered_bytes()
+    }
+
+    /**
+        Shrink or grow the player's buffers and decoded resolution to fit
+        its share of the memory budget.
+    */
+    pub fn set_memory_plan(&self, plan: MemoryPlan) {
+        self.video_pipeline.set_memory_plan(plan);
+    }
+
     /**
         Get the number of buffered video frames
     */
@@ -588,7 +1032,7 @@ impl VideoPlayer {
 /**
     Convert a VideoFrame to a RenderImage
 */
-fn frame_to_render_image(frame: &VideoFrame) -> Option<RenderImage> {
+pub(super) fn frame_to_render_image(frame: &VideoFrame) -> Option<RenderImage> {
     let image = RgbaImage::from_raw(frame.width, frame.height, frame.data.clone())?;
     let img_frame = Frame::new(image);
     Some(RenderImage::new(vec![img_frame]))
@@ -0,0 +1,72 @@
+use std::time::{Duration, Instant};
+
+/**
+    Clock used instead of the playback clock while reviewing a video,
+    frame by frame or in slow motion.
+
+    Runs at a fraction of real time, or stands still to show a single frame.
+    Audio is paused while reviewing, so the audio clock can't drive playback.
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct ReviewClock {
+    /// Position when the clock was last started, stopped or moved
+    anchor: Duration,
+    /// When the clock was last started, None if it stands still
+    running_since: Option<Instant>,
+    /// Speed relative to real time
+    rate: f32,
+}
+
+impl ReviewClock {
+    pub fn new(position: Duration, running: bool) -> Self {
+        Self {
+            anchor: position,
+            running_since: running.then(Instant::now),
+            rate: 1.0,
+        }
+    }
+
+    pub fn position(&self) -> Duration {
+        match self.running_since {
+            Some(since) => self.anchor + since.elapsed().mul_f32(self.rate),
+            None => self.anchor,
+        }
+    }
+
+    pub fn rate(&self) -> f32 {
+        self.rate
+    }
+
+    /**
+        Change the speed, keeping the current position.
+    */
+    pub fn set_rate(&mut self, rate: f32) {
+        self.reanchor();
+        self.rate = rate;
+    }
+
+    pub fn pause(&mut self) {
+        self.reanchor();
+        self.running_since = None;
+    }
+
+    pub fn resume(&mut self) {
+        if self.running_since.is_none() {
+            self.running_since = Some(Instant::now());
+        }
+    }
+
+    pub fn seek_to(&mut self, position: Duration) {
+        self.anchor = position;
+        if self.running_since.is_some() {
+            self.running_since = Some(Instant::now());
+        }
+    }
+
+    fn reanchor(&mut self) {
+        self.anchor = self.position();
+        if self.running_since.is_some() {
+            self.running_since = Some(Instant::now());
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::thread;
+use std::time::Duration;
+
+use gpui::RenderImage;
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+
+use crate::decode::{
+    DecoderError, PacketQueue, ScalingAlgorithm, VideoDecodeConfig, VideoScaling,
+    decode_video_packets, get_video_stream_info, keyframe_demux,
+};
+
+use super::frame::VideoFrame;
+use super::frame_cache::{cache_key, prune};
+use super::frame_queue::FrameQueue;
+use super::player::frame_to_render_image;
+
+/**
+    Number of thumbnails generated along a video, one per seek bar segment
+*/
+const THUMBNAIL_COUNT: usize = 24;
+
+/**
+    Width of the thumbnails, their height follows the video's aspect ratio
+*/
+const THUMBNAIL_WIDTH: u32 = 160;
+
+/**
+    Thumbnails are decoded at 1/2^n of the video's resolution, since they
+    are scaled down far below it anyway
+*/
+const THUMBNAIL_LOWRES: u8 = 2;
+
+/**
+    Size and positions of a cached thumbnail strip
+*/
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    width: u32,
+    height: u32,
+    positions: Vec<Duration>,
+}
+
+/**
+    Small previews of a video at evenly spaced positions along its duration,
+    shown while hovering the seek bar of a tile.
+*/
+pub struct Thumbnails {
+    images: Vec<(Duration, Arc<RenderImage>)>,
+}
+
+impl Thumbnails {
+    /**
+        Load the thumbnails of a video from the disk cache, or generate them
+        with a keyframe-only, low resolution decode and cache them.
+
+        Blocks until they are decoded, which may take a moment for long
+        videos - call it from a background thread.
+    */
+    pub fn load_or_generate(
+        path: &Path,
+        duration: Duration,
+        width: u32,
+        height: u32,
+    ) -> Result<Self, DecoderError> {
+        let frames = match load(path) {
+            Some(frames) => frames,
+            None => {
+                let frames = generate(path, duration, width, height)?;
+                store(path, &frames);
+                frames
+            }
+        };
+
+        let images = frames
+            .iter()
+            .filter_map(|frame| Some((frame.pts, Arc::new(frame_to_render_image(frame)?))))
+            .collect();
+        Ok(Self { images })
+    }
+
+    /**
+        Get the thumbnail closest to the given position.
+    */
+    pub fn nearest(&self, position: Duration) -> Option<Arc<RenderImage>> {
+        let positions: Vec<_> = self.images.iter().map(|(pts, _)| *pts).collect();
+        let index = nearest_index(&positions, position)?;
+        Some(Arc::clone(&self.images[index].1))
+    }
+
+    /**
+        Get all of the thumbnail images, so that they can be released.
+    */
+    pub fn images(&self) -> impl Iterator<Item = &Arc<RenderImage>> {
+        self.images.iter().map(|(_, image)| image)
+    }
+}
+
+/**
+    Get the positions thumbnails are taken at, in the middle of each of
+    the `THUMBNAIL_COUNT` equal segments of the duration.
+*/
+pub fn sample_positions(duration: Duration) -> Vec<Duration> {
+    let count = THUMBNAIL_COUNT as u32;
+    (0..count)
+        .map(|segment| duration * (2 * segment + 1) / (2 * count))
+        .collect()
+}
+
+/**
+    Get the index of the position closest to the given one.
+*/
+fn nearest_index(positions: &[Duration], position: Duration) -> Option<usize> {
+    positions
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, pts)| pts.abs_diff(position))
+        .map(|(index, _)| index)
+}
+
+/**
+    Decode one frame per sample position, scaled down to thumbnail size.
+*/
+fn generate(
+    path: &Path,
+    duration: Duration,
+    width: u32,
+    height: u32,
+) -> Result<Vec<VideoFrame>, DecoderError> {
+    let stream_info = get_video_stream_info(path)?;
+    let thumb_height = (THUMBNAIL_WIDTH * height / width.max(1)).max(2) & !1;
+    let config = VideoDecodeConfig {
+        target_width: Some(THUMBNAIL_WIDTH),
+        target_height: Some(thumb_height),
+        scaling: ScalingAlgorithm::FastBilinear,
+        keyframes_only: true,
+        lowres: THUMBNAIL_LOWRES,
+    };
+
+    let packets = Arc::new(PacketQueue::new("thumbnail packets", THUMBNAIL_COUNT));
+    let frames = Arc::new(FrameQueue::new("thumbnail frames", THUMBNAIL_COUNT));
+    let stop_flag = Arc::new(AtomicBool::new(false));
+
+    let decode_handle = {
+        let packets = Arc::clone(&packets);
+        let frames = Arc::clone(&frames);
+        let stop = Arc::clone(&stop_flag);
+        let scaling = Arc::new(VideoScaling::new(&config));
+        thread::spawn(move || {
+            decode_video_packets(
+                packets,
+                frames,
+                stream_info.codec_params,
+                stream_info.time_base,
+                stop,
+                config,
+                scaling,
+            )
+        })
+    };
+
+    // Every position fits in the packet queue, so this never waits on the decoder
+    if let Err(e) = keyframe_demux(
+        path,
+        Arc::clone(&packets),
+        stop_flag,
+        &sample_positions(duration),
+    ) {
+        packets.close();
+        frames.close();
+        let _ = decode_handle.join();
+        return Err(e);
+    }
+
+    let mut thumbnails = Vec::new();
+    while let Some(frame) = frames.pop() {
+        thumbnails.push(frame);
+    }
+    match decode_handle.join() {
+        Ok(result) => result?,
+        Err(_) => eprintln!("[thumbnails] decode thread panicked"),
+    }
+
+    thumbnails.sort_by_key(|frame| frame.pts);
+    Ok(thumbnails)
+}
+
+/**
+    Get the cache directory for thumbnails.
+*/
+fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|p| p.join("vidwall").join("thumbnails"))
+}
+
+/**
+    Load the cached thumbnails of a video, stored as one vertical strip.
+*/
+fn load(path: &Path) -> Option<Vec<VideoFrame>> {
+    let dir = cache_dir()?;
+    let key = cache_key(path, None)?;
+
+    let contents = fs::read_to_string(dir.join(format!("{}.json", key))).ok()?;
+    let entry: CacheEntry = serde_json::from_str(&contents).ok()?;
+
+    // Pixel data is stored as is, so the PNG channels are really BGRA
+    let strip = image::open(dir.join(format!("{}.png", key)))
+        .ok()?
+        .into_rgba8();
+    let frame_len = (entry.width * entry.height * 4) as usize;
+    if strip.as_raw().len() != frame_len * entry.positions.len() {
+        return None;
+    }
+
+    let frames = strip
+        .as_raw()
+        .chunks_exact(frame_len)
+        .zip(entry.positions)
+        .map(|(data, pts)| VideoFrame::new(data.to_vec(), entry.width, entry.height, pts))
+        .collect();
+    Some(frames)
+}
+
+/**
+    Store the thumbnails of a video in the cache.
+*/
+fn store(path: &Path, frames: &[VideoFrame]) {
+    let (Some(dir), Some(key), Some(first)) = (cache_dir(), cache_key(path, None), frames.first())
+    else {
+        return;
+    };
+    if let Err(e) = write_entry(&dir, &key, first.width, first.height, frames) {
+        eprintln!("Warning: Failed to cache thumbnails: {}", e);
+        return;
+    }
+    prune(&dir);
+}
+
+fn write_entry(
+    dir: &Path,
+    key: &str,
+    width: u32,
+    height: u32,
+    frames: &[VideoFrame],
+) -> Result<(), Box<dyn std::error::Error>> {
+    fs::create_dir_all(dir)?;
+
+    let mut data = Vec::with_capacity(frames.len() * (width * height * 4) as usize);
+    for frame in frames {
+        if frame.width != width || frame.height != height {
+            return Err("thumbnails differ in size".into());
+        }
+        data.extend_from_slice(&frame.data);
+    }
+    let strip = RgbaImage::from_raw(width, height * frames.len() as u32, data)
+        .ok_or("thumbnail data does not match its size")?;
+    strip.save(dir.join(format!("{}.png", key)))?;
+
+    let entry = CacheEntry {
+        width,
+        height,
+        positions: frames.iter().map(|frame| frame.pts).collect(),
+    };
+    // Written last, entries without it are never read
+    fs::write(
+        dir.join(format!("{}.json", key)),
+        serde_json::to_string(&entry)?,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_positions() {
+        let positions = sample_positions(Duration::from_secs(48));
+        assert_eq!(positions.len(), THUMBNAIL_COUNT);
+        assert_eq!(positions[0], Duration::from_secs(1));
+        assert_eq!(positions[THUMBNAIL_COUNT - 1], Duration::from_secs(47));
+    }
+
+    #[test]
+    fn test_nearest_index() {
+        let secs = Duration::from_secs;
+        let positions = [secs(1), secs(5), secs(9)];
+        assert_eq!(nearest_index(&positions, secs(0)), Some(0));
+        assert_eq!(nearest_index(&positions, secs(6)), Some(1));
+        assert_eq!(nearest_index(&positions, secs(100)), Some(2));
+        assert_eq!(nearest_index(&[], secs(1)), None);
+    }
+}
@@ -8,15 +8,74 @@ use std::thread::{self, JoinHandle};
 use std::time::Duration;
 
 use crate::decode::{
-    DecoderError, PacketQueue, VideoStreamInfo, decode_video_packets, get_video_stream_info,
-    video_demux,
+    DecoderError, PacketQueue, ScalingAlgorithm, VideoDecodeConfig, VideoScaling, VideoStreamInfo,
+    decode_video_packets, get_video_stream_info, video_demux,
 };
 
 use super::frame_queue::FrameQueue;
+use super::memory_budget::{MemoryPlan, scaled_capacity};
 
 const VIDEO_PACKET_QUEUE_CAPACITY: usize = 120;
 const VIDEO_FRAME_QUEUE_CAPACITY: usize = 60;
 
+/**
+    Smallest queue capacities the memory budget shrinks the queues to
+*/
+const MIN_PACKET_QUEUE_CAPACITY: usize = 16;
+const MIN_FRAME_QUEUE_CAPACITY: usize = 4;
+
+/**
+    Error that stopped one of the pipeline's threads.
+*/
+#[derive(Debug, Clone)]
+pub struct PipelineError {
+    /// Which thread failed, "demux" or "decode"
+    pub stage: &'static str,
+    pub message: String,
+}
+
+/**
+    Record the error a pipeline thread failed with, keeping only the first one.
+
+    The queues are closed so that the other thread and the player stop waiting
+    for data that will never come. Errors after the pipeline was told to stop
+    are not failures and are ignored.
+*/
+fn record_failure(
+    failure: &Mutex<Option<PipelineError>>,
+    stage: &'static str,
+    result: &Result<(), DecoderError>,
+    stop_flag: &AtomicBool,
+    packets: &PacketQueue,
+    frames: &FrameQueue,
+) {
+    let Err(e) = result else {
+        return;
+    };
+    if stop_flag.load(Ordering::Relaxed) {
+        return;
+    }
+
+    eprintln!("[video_{}] failed: {}", stage, e);
+    failure.lock().unwrap().get_or_insert(PipelineError {
+        stage,
+        message: e.to_string(),
+    });
+    packets.close();
+    frames.close();
+}
+
+/**
+    Output size asked for by the tile showing the video, before the memory
+    budget scales it down.
+*/
+#[derive(Debug, Clone, Copy)]
+struct OutputRequest {
+    width: u32,
+    height: u32,
+    algorithm: ScalingAlgorithm,
+}
+
 /**
     Internal mutable state for seeking support.
 */
@@ -36,8 +95,9 @@ pub struct VideoPipeline {
     // Configuration (immutable)
     path: PathBuf,
     stream_info: VideoStreamInfo,
-    target_width: Option<u32>,
-    target_height: Option<u32>,
+    config: VideoDecodeConfig,
+    end_position: Option<Duration>,
+    scaling: Arc<VideoScaling>,
 
     // Thread handles behind mutex for seeking
     inner: Mutex<VideoPipelineInner>,
@@ -45,6 +105,11 @@ pub struct VideoPipeline {
     // Control
     stop_flag: Arc<AtomicBool>,
     packet_queue: Arc<PacketQueue>,
+    failure: Arc<Mutex<Option<PipelineError>>>,
+
+    // Memory use
+    output_request: Mutex<Option<OutputRequest>>,
+    memory_plan: Mutex<MemoryPlan>,
 
     // Output
     frame_queue: Arc<FrameQueue>,
@@ -52,25 +117,44 @@ pub struct VideoPipeline {
 
 impl VideoPipeline {
     /**
-        Create and start a new video pipeline for the given file.
+        Create and start a new video pipeline for the given file,
+        optionally ending at the given position instead of the end of the file.
     */
     pub fn new(
         path: PathBuf,
-        target_width: Option<u32>,
-        target_height: Option<u32>,
+        config: VideoDecodeConfig,
+        end_position: Option<Duration>,
     ) -> Result<Self, DecoderError> {
         let stream_info = get_video_stream_info(&path)?;
 
         let stop_flag = Arc::new(AtomicBool::new(false));
-        let packet_queue = Arc::new(PacketQueue::new(VIDEO_PACKET_QUEUE_CAPACITY));
-        let frame_queue = Arc::new(FrameQueue::new(VIDEO_FRAME_QUEUE_CAPACITY));
+        let packet_queue = Arc::new(PacketQueue::new(
+            "video packets",
+            VIDEO_PACKET_QUEUE_CAPACITY,
+        ));
+        let frame_queue = Arc::new(FrameQueue::new("video frames", VIDEO_FRAME_QUEUE_CAPACITY));
+        let scaling = Arc::new(VideoScaling::new(&config));
+        let failure = Arc::new(Mutex::new(None));
 
         // Spawn demux thread (opens its own file handle)
         let demux_handle = {
             let path = path.clone();
             let packets = Arc::clone(&packet_queue);
+            let frames = Arc::clone(&frame_queue);
             let stop = Arc::clone(&stop_flag);
-            thread::spawn(move || video_demux(path, packets, stop, None, None))
+            let failure = Arc::clone(&failure);
+            thread::spawn(move || {
+                let result = video_demux(
+                    path,
+                    Arc::clone(&packets),
+                    Arc::clone(&stop),
+                    None,
+                    None,
+                    end_position,
+                );
+                record_failure(&failure, "demux", &result, &stop, &packets, &frames);
+                result
+            })
         };
 
         // Spawn decode thread
@@ -80,34 +164,61 @@ impl VideoPipeline {
             let params = stream_info.codec_params.clone();
             let tb = stream_info.time_base;
             let stop = Arc::clone(&stop_flag);
+            let scaling = Arc::clone(&scaling);
+            let failure = Arc::clone(&failure);
             thread::spawn(move || {
-                decode_video_packets(
-                    packets,
-                    frames,
+                let result = decode_video_packets(
+                    Arc::clone(&packets),
+                    Arc::clone(&frames),
                     params,
                     tb,
-                    stop,
-                    target_width,
-                    target_height,
-                )
+                    Arc::clone(&stop),
+                    config,
+                    scaling,
+                );
+                record_failure(&failure, "decode", &result, &stop, &packets, &frames);
+                result
             })
         };
 
         Ok(Self {
             path,
             stream_info,
-            target_width,
-            target_height,
+            config,
+            end_position,
+            scaling,
             inner: Mutex::new(VideoPipelineInner {
                 demux_handle: Some(demux_handle),
                 decode_handle: Some(decode_handle),
             }),
             stop_flag,
             packet_queue,
+            failure,
+            output_request: Mutex::new(None),
+            memory_plan: Mutex::new(MemoryPlan::default()),
             frame_queue,
         })
     }
 
+    /**
+        Get the error that stopped the pipeline, if any.
+    */
+    pub fn failure(&self) -> Option<PipelineError> {
+        self.failure.lock().unwrap().clone()
+    }
+
+    /**
+        Describe the video stream's codec and size, e.g. "h264 1920x1080".
+    */
+    pub fn codec_description(&self) -> String {
+        format!(
+            "{} {}x{}",
+            self.stream_info.codec_params.id().name(),
+            self.stream_info.width,
+            self.stream_info.height
+        )
+    }
+
     /**
         Get the frame queue for reading decoded frames.
     */
@@ -115,6 +226,101 @@ impl VideoPipeline {
         &self.frame_queue
     }
 
+    /**
+        Scale decoded frames to the given size with the given algorithm.
+        Frames are never scaled up, the stream's size is used instead.
+    */
+    pub fn set_output_size(&self, width: u32, height: u32, algorithm: ScalingAlgorithm) {
+        *self.output_request.lock().unwrap() = Some(OutputRequest {
+            width,
+            height,
+            algorithm,
+        });
+        self.apply_output_size();
+    }
+
+    /**
+        Scale frames to the requested output size, scaled down further
+        by the memory plan.
+    */
+    fn apply_output_size(&self) {
+        let request = *self.output_request.lock().unwrap();
+        let scale = self.memory_plan.lock().unwrap().resolution_scale;
+        let (width, height, algorithm) = match request {
+            Some(request) => (request.width, request.height, request.algorithm),
+            None => (
+                self.config.target_width.unwrap_or(self.stream_info.width),
+                self.config.target_height.unwrap_or(self.stream_info.height),
+                self.config.scaling,
+            ),
+        };
+        let width = (width as f32 * scale) as u32;
+        let height = (height as f32 * scale) as u32;
+
+        let size = if width >= self.stream_info.width || height >= self.stream_info.height {
+            None
+        } else {
+            Some((width.max(1), height.max(1)))
+        };
+        self.scaling.set(size, algorithm);
+    }
+
+    /**
+        Estimate the memory this pipeline's queues use when they are full,
+        at the requested output size.
+
+        Independent of the current memory plan, so that applying a plan
+        doesn't change the demand it was made for.
+    */
+    pub fn memory_demand(&self) -> u64 {
+        let (width, height) = match *self.output_request.lock().unwrap() {
+            Some(request) => (request.width, request.height),
+            None => (self.stream_info.width, self.stream_info.height),
+        };
+        let frame_bytes = u64::from(width.min(self.stream_info.width))
+            * u64::from(height.min(self.stream_info.height))
+            * 4;
+
+        // Packets are averaged over the ones queued right now
+        let packet_count = self.packet_queue.len().max(1) as u64;
+        let packet_bytes = self.packet_queue.bytes() as u64 / packet_count;
+
+        frame_bytes * VIDEO_FRAME_QUEUE_CAPACITY as u64
+            + packet_bytes * VIDEO_PACKET_QUEUE_CAPACITY as u64
+    }
+
+    /**
+        Get the memory used by the packets and frames queued right now.
+    */
+    pub fn buffered_bytes(&self) -> u64 {
+        (self.packet_queue.bytes() + self.frame_queue.bytes()) as u64
+    }
+
+    /**
+        Shrink or grow the queues and output size to the given memory plan.
+    */
+    pub fn set_memory_plan(&self, plan: MemoryPlan) {
+        {
+            let mut current = self.memory_plan.lock().unwrap();
+            if *current == plan {
+                return;
+            }
+            *current = plan;
+        }
+
+        self.packet_queue.set_capacity(scaled_capacity(
+            VIDEO_PACKET_QUEUE_CAPACITY,
+            plan.queue_scale,
+            MIN_PACKET_QUEUE_CAPACITY,
+        ));
+        self.frame_queue.set_capacity(scaled_capacity(
+            VIDEO_FRAME_QUEUE_CAPACITY,
+            plan.queue_scale,
+            MIN_FRAME_QUEUE_CAPACITY,
+        ));
+        self.apply_output_size();
+    }
+
     /**
         Seek to a new position in the video.
         Stops current threads, clears queues, and restarts from the new position.
@@ -143,6 +349,7 @@ impl VideoPipeline {
         self.stop_flag.store(false, Ordering::Relaxed);
         self.packet_queue.reopen();
         self.frame_queue.reopen();
+        *self.failure.lock().unwrap() = None;
 
         // Channel to receive actual position from demux thread
         let (position_tx, position_rx) = mpsc::channel();
@@ -151,9 +358,21 @@ impl VideoPipeline {
         let demux_handle = {
             let path = self.path.clone();
             let packets = Arc::clone(&self.packet_queue);
+            let frames = Arc::clone(&self.frame_queue);
             let stop = Arc::clone(&self.stop_flag);
+            let end = self.end_position;
+            let failure = Arc::clone(&self.failure);
             thread::spawn(move || {
-                video_demux(path, packets, stop, Some(position), Some(position_tx))
+                let result = video_demux(
+                    path,
+                    Arc::clone(&packets),
+                    Arc::clone(&stop),
+                    Some(position),
+                    Some(position_tx),
+                    end,
+                );
+                record_failure(&failure, "demux", &result, &stop, &packets, &frames);
+                result
             })
         };
 
@@ -163,9 +382,22 @@ impl VideoPipeline {
             let params = self.stream_info.codec_params.clone();
             let tb = self.stream_info.time_base;
             let stop = Arc::clone(&self.stop_flag);
-            let tw = self.target_width;
-            let th = self.target_height;
-            thread::spawn(move || decode_video_packets(packets, frames, params, tb, stop, tw, th))
+            let config = self.config;
+            let scaling = Arc::clone(&self.scaling);
+            let failure = Arc::clone(&self.failure);
+            thread::spawn(move || {
+                let result = decode_video_packets(
+                    Arc::clone(&packets),
+                    Arc::clone(&frames),
+                    params,
+                    tb,
+                    Arc::clone(&stop),
+                    config,
+                    scaling,
+                );
+                record_failure(&failure, "decode", &result, &stop, &packets, &frames);
+                result
+            })
         };
 
         // 5. Store new handles
@@ -0,0 +1,164 @@
+use std::sync::{
+    Condvar, LazyLock, MutexGuard,
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/**
+    Environment variable that enables queue instrumentation
+*/
+const DEBUG_ENV_VAR: &str = "VIDWALL_QUEUE_DEBUG";
+
+/**
+    How long a thread may block on a queue before it is reported as stuck
+*/
+const BLOCKED_THRESHOLD: Duration = Duration::from_secs(5);
+
+/**
+    A blocking queue operation
+*/
+#[derive(Debug, Clone, Copy)]
+pub enum QueueOp {
+    Push,
+    Pop,
+}
+
+static ENABLED: LazyLock<bool> = LazyLock::new(|| {
+    std::env::var(DEBUG_ENV_VAR).is_ok_and(|value| !value.is_empty() && value != "0")
+});
+
+/**
+    Optional instrumentation for the bounded packet and frame queues.
+
+    Disabled unless `VIDWALL_QUEUE_DEBUG` is set, in which case it tracks
+    how long producers and consumers wait, and the highest occupancy seen,
+    logging a summary when the queue is dropped. It also logs threads that
+    stay blocked on a push or pop past a threshold, since mistakes in the
+    order of stopping and closing queues otherwise show up as silent hangs.
+*/
+pub struct QueueStats {
+    name: &'static str,
+    capacity: usize,
+    enabled: bool,
+    pushes: AtomicU64,
+    pops: AtomicU64,
+    drops: AtomicU64,
+    push_wait_micros: AtomicU64,
+    pop_wait_micros: AtomicU64,
+    high_water: AtomicUsize,
+}
+
+impl QueueStats {
+    pub fn new(name: &'static str, capacity: usize) -> Self {
+        Self {
+            name,
+            capacity,
+            enabled: *ENABLED,
+            pushes: AtomicU64::new(0),
+            pops: AtomicU64::new(0),
+            drops: AtomicU64::new(0),
+            push_wait_micros: AtomicU64::new(0),
+            pop_wait_micros: AtomicU64::new(0),
+            high_water: AtomicUsize::new(0),
+        }
+    }
+
+    /**
+        Block on a condition variable while `blocked` returns true,
+        reporting the thread if it stays blocked past the threshold.
+    */
+    pub fn wait_while<'a, T>(
+        &self,
+        condvar: &Condvar,
+        mut guard: MutexGuard<'a, T>,
+        op: QueueOp,
+        mut blocked: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        if !self.enabled {
+            return condvar.wait_while(guard, blocked).unwrap();
+        }
+
+        let started = Instant::now();
+        let mut reported = false;
+        while blocked(&mut guard) {
+            let (next, timeout) = condvar.wait_timeout(guard, BLOCKED_THRESHOLD).unwrap();
+            guard = next;
+            if timeout.timed_out() && !reported && blocked(&mut guard) {
+                eprintln!(
+                    "[queue] {}: thread {:?} blocked on {:?} for {:.1?}, possible deadlock",
+                    self.name,
+                    thread::current().id(),
+                    op,
+                    started.elapsed()
+                );
+                reported = true;
+            }
+        }
+
+        let waited = started.elapsed();
+        if reported {
+            eprintln!(
+                "[queue] {}: thread {:?} unblocked on {:?} after {:.1?}",
+                self.name,
+                thread::current().id(),
+                op,
+                waited
+            );
+        }
+        let total = match op {
+            QueueOp::Push => &self.push_wait_micros,
+            QueueOp::Pop => &self.pop_wait_micros,
+        };
+        total.fetch_add(waited.as_micros() as u64, Ordering::Relaxed);
+        guard
+    }
+
+    /**
+        Record a push, with the queue length after it
+    */
+    pub fn record_push(&self, len: usize) {
+        if self.enabled {
+            self.pushes.fetch_add(1, Ordering::Relaxed);
+            self.high_water.fetch_max(len, Ordering::Relaxed);
+        }
+    }
+
+    /**
+        Record a pop
+    */
+    pub fn record_pop(&self) {
+        if self.enabled {
+            self.pops.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /**
+        Record an item dropped to make room instead of waiting for the consumer
+    */
+    pub fn record_drop(&self) {
+        if self.enabled {
+            self.drops.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for QueueStats {
+    fn drop(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        let micros = |total: &AtomicU64| Duration::from_micros(total.load(Ordering::Relaxed));
+        eprintln!(
+            "[queue] {}: pushes={} pops={} drops={} high_water={}/{} push_wait={:.1?} pop_wait={:.1?}",
+            self.name,
+            self.pushes.load(Ordering::Relaxed),
+            self.pops.load(Ordering::Relaxed),
+            self.drops.load(Ordering::Relaxed),
+            self.high_water.load(Ordering::Relaxed),
+            self.capacity,
+            micros(&self.push_wait_micros),
+            micros(&self.pop_wait_micros),
+        );
+    }
+}
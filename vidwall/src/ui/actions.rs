@@ -1,25 +1,71 @@
+use std::sync::Arc;
+
 use gpui::{App, KeyBinding};
 
+use crate::audio::MAX_ALIGN_DELAY;
+use crate::decode::{DecoderError, export_clip};
+use crate::playback::{LoopRegion, VideoPlayer};
+
 use super::app_state::AppState;
+use super::audio_policy::AudioPolicy;
+use super::clip_marks::{ClipMarks, clips_dir};
+use super::keymap::Keymap;
 
 gpui::actions!(
     vidwall,
     [
-        TogglePause, // Space - pause/resume all videos
-        ToggleMute,  // M - mute/unmute all videos
-        VolumeUp,    // Up arrow - increase master volume
-        VolumeDown,  // Down arrow - decrease master volume
-        SkipAll,     // Enter - skip all videos and load new ones
-        Quit,        // Cmd+Q - quit the application
+        TogglePause,      // Pause/resume all videos
+        ToggleMute,       // Mute/unmute all videos
+        VolumeUp,         // Increase master volume
+        VolumeDown,       // Decrease master volume
+        SkipAll,          // Skip all videos and load new ones
+        AlignAudio,       // Align audio of all unmuted videos using their clocks
+        CycleAudioPolicy, // Switch to the next audio policy
+        MarkIn,           // Mark the start of a clip on the selected tile
+        MarkOut,          // Mark the end of a clip on the selected tile
+        ExportClip,       // Export the marked clip to a new file
+        StepForward,      // Pause the selected tile and show its next frame
+        StepBack,         // Pause the selected tile and show its previous frame
+        SlowMotion,       // Cycle the selected tile through slow motion speeds
+        LoopIn,           // Set the in point of the selected tile's A/B loop
+        LoopOut,          // Set the out point of the selected tile's A/B loop
+        ClearLoop,        // Stop looping the selected tile
+        SelectLeft,       // Move the tile selection left
+        SelectRight,      // Move the tile selection right
+        SelectUp,         // Move the tile selection up
+        SelectDown,       // Move the tile selection down
+        SelectTile1,      // Select tile 1
+        SelectTile2,      // Select tile 2
+        SelectTile3,      // Select tile 3
+        SelectTile4,      // Select tile 4
+        SelectTile5,      // Select tile 5
+        SelectTile6,      // Select tile 6
+        SelectTile7,      // Select tile 7
+        SelectTile8,      // Select tile 8
+        SelectTile9,      // Select tile 9
+        ToggleFocus,      // Show the selected tile across the whole grid
+        ClearSelection,   // Deselect the tile, leaving focus mode
+        Quit,             // Quit the application
     ]
 );
 
+/**
+    Slow motion speeds, in the order they are cycled through before
+    going back to normal speed
+*/
+const SLOW_MOTION_SPEEDS: &[f32] = &[0.5, 0.25, 0.1];
+
 /**
     Register all keyboard shortcuts and their handlers at the app level.
+
+    Keys are bound from the keymap, which is also stored as a global so
+    that the bindings can be listed later.
 */
 pub fn register_shortcuts(app: &mut App) {
     // Bind keys to actions
-    app.bind_keys(key_bindings());
+    let keymap = Keymap::load();
+    app.bind_keys(key_bindings(&keymap));
+    app.set_global(keymap);
 
     // Register action handlers
     app.on_action(|_: &TogglePause, app: &mut App| {
@@ -52,6 +98,149 @@ pub fn register_shortcuts(app: &mut App) {
         println!("Skipping all videos...");
     });
 
+    app.on_action(|_: &AlignAudio, app: &mut App| {
+        let state = app.global::<AppState>();
+        // The tiles being listened to together are the ones to keep in sync
+        let group: Vec<usize> = state
+            .players
+            .iter()
+            .enumerate()
+            .filter(|(_, player)| {
+                player
+                    .as_ref()
+                    .is_some_and(|p| p.has_audio() && !p.is_muted())
+            })
+            .map(|(index, _)| index)
+            .collect();
+        let skipped = state.mixer.align_streams(&group);
+        if skipped.is_empty() {
+            println!("Aligned audio of {} tiles", group.len());
+        } else {
+            println!(
+                "Aligned audio of {} tiles, skipped tiles more than {}ms apart: {:?}",
+                group.len() - skipped.len(),
+                MAX_ALIGN_DELAY.as_millis(),
+                skipped.iter().map(|index| index + 1).collect::<Vec<_>>()
+            );
+        }
+    });
+
+    app.on_action(|_: &CycleAudioPolicy, app: &mut App| {
+        let policy = app.global::<AudioPolicy>().next();
+        app.set_global(policy);
+        // Tiles muted by the previous policy are heard again in manual mode
+        if policy == AudioPolicy::Manual {
+            for player in app.global::<AppState>().players.iter().flatten() {
+                player.unmute();
+            }
+        }
+        println!("Audio policy: {}", policy);
+        app.refresh_windows();
+    });
+
+    app.on_action(|_: &MarkIn, app: &mut App| mark_clip(app, true));
+    app.on_action(|_: &MarkOut, app: &mut App| mark_clip(app, false));
+
+    app.on_action(|_: &ExportClip, app: &mut App| {
+        let marks = app.global::<AppState>().clip_marks.clone();
+        let range = marks.as_ref().and_then(ClipMarks::range);
+        let (Some(marks), Some((start, end))) = (marks, range) else {
+            println!("Mark the start and end of a clip first");
+            return;
+        };
+        let Some(dir) = clips_dir() else {
+            eprintln!("No directory to export clips to");
+            return;
+        };
+        let Some(output) = marks.output_path(&dir) else {
+            return;
+        };
+
+        println!("Exporting clip to {}...", output.display());
+        std::thread::spawn(move || {
+            let result = std::fs::create_dir_all(&dir)
+                .map_err(DecoderError::Io)
+                .and_then(|()| export_clip(&marks.path, &output, start, end));
+            match result {
+                Ok(()) => println!("Exported clip to {}", output.display()),
+                Err(e) => eprintln!("Failed to export clip to {}: {}", output.display(), e),
+            }
+        });
+    });
+
+    app.on_action(|_: &StepForward, app: &mut App| {
+        if let Some((_, player)) = selected_player(app.global::<AppState>()) {
+            player.step_forward();
+        }
+    });
+
+    app.on_action(|_: &StepBack, app: &mut App| {
+        let state = app.global::<AppState>();
+        if let Some((index, player)) = selected_player(state) {
+            match player.step_backward() {
+                Ok(Some(consumer)) => state.mixer.set_stream(index, Some(consumer)),
+                Ok(None) => {}
+                Err(e) => eprintln!("Failed to step back: {}", e),
+            }
+        }
+    });
+
+    app.on_action(|_: &SlowMotion, app: &mut App| {
+        let state = app.global::<AppState>();
+        let Some((index, player)) = selected_player(state) else {
+            return;
+        };
+        let speed = SLOW_MOTION_SPEEDS
+            .iter()
+            .copied()
+            .find(|speed| *speed < player.speed())
+            .unwrap_or(1.0);
+        match player.set_speed(speed) {
+            Ok(Some(consumer)) => state.mixer.set_stream(index, Some(consumer)),
+            Ok(None) => {}
+            Err(e) => eprintln!("Failed to change speed: {}", e),
+        }
+        println!("Speed: {}x", speed);
+    });
+
+    app.on_action(|_: &LoopIn, app: &mut App| mark_loop(app, true));
+    app.on_action(|_: &LoopOut, app: &mut App| mark_loop(app, false));
+
+    app.on_action(|_: &ClearLoop, app: &mut App| {
+        if let Some((_, player)) = selected_player(app.global::<AppState>()) {
+            player.set_loop_region(LoopRegion::default());
+            println!("Loop cleared");
+        }
+    });
+
+    app.on_action(|_: &SelectLeft, app: &mut App| move_selection(app, -1, 0));
+    app.on_action(|_: &SelectRight, app: &mut App| move_selection(app, 1, 0));
+    app.on_action(|_: &SelectUp, app: &mut App| move_selection(app, 0, -1));
+    app.on_action(|_: &SelectDown, app: &mut App| move_selection(app, 0, 1));
+
+    app.on_action(|_: &SelectTile1, app: &mut App| select_tile(app, 0));
+    app.on_action(|_: &SelectTile2, app: &mut App| select_tile(app, 1));
+    app.on_action(|_: &SelectTile3, app: &mut App| select_tile(app, 2));
+    app.on_action(|_: &SelectTile4, app: &mut App| select_tile(app, 3));
+    app.on_action(|_: &SelectTile5, app: &mut App| select_tile(app, 4));
+    app.on_action(|_: &SelectTile6, app: &mut App| select_tile(app, 5));
+    app.on_action(|_: &SelectTile7, app: &mut App| select_tile(app, 6));
+    app.on_action(|_: &SelectTile8, app: &mut App| select_tile(app, 7));
+    app.on_action(|_: &SelectTile9, app: &mut App| select_tile(app, 8));
+
+    app.on_action(|_: &ToggleFocus, app: &mut App| {
+        let state = app.global_mut::<AppState>();
+        let count = state.player_count();
+        let focused = state.selection.toggle_focus(count);
+        println!("Focus mode {}", if focused { "on" } else { "off" });
+        app.refresh_windows();
+    });
+
+    app.on_action(|_: &ClearSelection, app: &mut App| {
+        app.global_mut::<AppState>().selection.clear();
+        app.refresh_windows();
+    });
+
     app.on_action(|_: &Quit, app: &mut App| {
         println!("Quitting...");
         app.quit();
@@ -59,15 +248,144 @@ pub fn register_shortcuts(app: &mut App) {
 }
 
 /**
-    Define key bindings for all actions.
+    Get the index and player of the selected tile, or of the only tile if there is one.
 */
-fn key_bindings() -> Vec<KeyBinding> {
-    vec![
-        KeyBinding::new("space", TogglePause, None),
-        KeyBinding::new("m", ToggleMute, None),
-        KeyBinding::new("up", VolumeUp, None),
-        KeyBinding::new("down", VolumeDown, None),
-        KeyBinding::new("enter", SkipAll, None),
-        KeyBinding::new("cmd-q", Quit, None),
-    ]
+fn selected_player(state: &AppState) -> Option<(usize, Arc<VideoPlayer>)> {
+    let index = match state.selection.selected {
+        Some(index) => index,
+        None if state.player_count() == 1 => 0,
+        None => return None,
+    };
+    let player = state.players.get(index)?.as_ref()?;
+    Some((index, Arc::clone(player)))
+}
+
+/**
+    Mark the start or end of a clip at the selected tile's current position.
+*/
+fn mark_clip(app: &mut App, start: bool) {
+    let state = app.global_mut::<AppState>();
+    let Some((_, player)) = selected_player(state) else {
+        println!("Select a tile to mark a clip on");
+        return;
+    };
+
+    let position = player.position();
+    let marks = ClipMarks::for_video(&mut state.clip_marks, player.path());
+    if start {
+        marks.start = Some(position);
+    } else {
+        marks.end = Some(position);
+    }
+    println!(
+        "Clip {} at {:.1}s",
+        if start { "starts" } else { "ends" },
+        position.as_secs_f64()
+    );
+}
+
+/**
+    Set the in or out point of the selected tile's A/B loop at its current position.
+*/
+fn mark_loop(app: &mut App, start: bool) {
+    let Some((_, player)) = selected_player(app.global::<AppState>()) else {
+        println!("Select a tile to loop");
+        return;
+    };
+
+    let position = player.position();
+    let mut region = player.loop_region();
+    if start {
+        region.start = Some(position);
+    } else {
+        region.end = Some(position);
+    }
+    player.set_loop_region(region);
+
+    match region.range() {
+        Some((start, end)) => println!(
+            "Looping {:.1}s to {:.1}s",
+            start.as_secs_f64(),
+            end.as_secs_f64()
+        ),
+        None => println!(
+            "Loop {} at {:.1}s",
+            if start { "starts" } else { "ends" },
+            position.as_secs_f64()
+        ),
+    }
+}
+
+/**
+    Move the tile selection by the given number of columns and rows.
+*/
+fn move_selection(app: &mut App, dx: i32, dy: i32) {
+    let state = app.global_mut::<AppState>();
+    let count = state.player_count();
+    let cols = state.grid_cols;
+    state.selection.move_by(dx, dy, cols, count);
+    app.refresh_windows();
+}
+
+/**
+    Select the tile at the given index.
+*/
+fn select_tile(app: &mut App, index: usize) {
+    let state = app.global_mut::<AppState>();
+    let count = state.player_count();
+    state.selection.select(index, count);
+    app.refresh_windows();
+}
+
+/**
+    Create the key bindings for all commands in the keymap.
+*/
+fn key_bindings(keymap: &Keymap) -> Vec<KeyBinding> {
+    keymap
+        .bindings()
+        .into_iter()
+        .filter_map(|(command, keys)| key_binding(command, keys))
+        .collect()
+}
+
+/**
+    Bind keys to the action for the command with the given keymap name.
+*/
+fn key_binding(command: &str, keys: &str) -> Option<KeyBinding> {
+    let binding = match command {
+        "toggle_pause" => KeyBinding::new(keys, TogglePause, None),
+        "toggle_mute" => KeyBinding::new(keys, ToggleMute, None),
+        "volume_up" => KeyBinding::new(keys, VolumeUp, None),
+        "volume_down" => KeyBinding::new(keys, VolumeDown, None),
+        "skip_all" => KeyBinding::new(keys, SkipAll, None),
+        "align_audio" => KeyBinding::new(keys, AlignAudio, None),
+        "cycle_audio_policy" => KeyBinding::new(keys, CycleAudioPolicy, None),
+        "mark_in" => KeyBinding::new(keys, MarkIn, None),
+        "mark_out" => KeyBinding::new(keys, MarkOut, None),
+        "export_clip" => KeyBinding::new(keys, ExportClip, None),
+        "step_forward" => KeyBinding::new(keys, StepForward, None),
+        "step_back" => KeyBinding::new(keys, StepBack, None),
+        "slow_motion" => KeyBinding::new(keys, SlowMotion, None),
+        "loop_in" => KeyBinding::new(keys, LoopIn, None),
+        "loop_out" => KeyBinding::new(keys, LoopOut, None),
+        "clear_loop" => KeyBinding::new(keys, ClearLoop, None),
+        "select_left" => KeyBinding::new(keys, SelectLeft, None),
+        "select_right" => KeyBinding::new(keys, SelectRight, None),
+        "select_up" => KeyBinding::new(keys, SelectUp, None),
+        "select_down" => KeyBinding::new(keys, SelectDown, None),
+        "select_tile_1" => KeyBinding::new(keys, SelectTile1, None),
+        "select_tile_2" => KeyBinding::new(keys, SelectTile2, None),
+        "select_tile_3" => KeyBinding::new(keys, SelectTile3, None),
+        "select_tile_4" => KeyBinding::new(keys, SelectTile4, None),
+        "select_tile_5" => KeyBinding::new(keys, SelectTile5, None),
+        "select_tile_6" => KeyBinding::new(keys, SelectTile6, None),
+        "select_tile_7" => KeyBinding::new(keys, SelectTile7, None),
+        "select_tile_8" => KeyBinding::new(keys, SelectTile8, None),
+        "select_tile_9" => KeyBinding::new(keys, SelectTile9, None),
+        "toggle_focus" => KeyBinding::new(keys, ToggleFocus, None),
+        "clear_selection" => KeyBinding::new(keys, ClearSelection, None),
+        "quit" => KeyBinding::new(keys, Quit, None),
+        _ => return None,
+    };
+    Some(binding)
 }
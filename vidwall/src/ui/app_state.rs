@@ -3,9 +3,52 @@ use std::sync::Arc;
 use gpui::Global;
 
 use crate::audio::AudioMixer;
+use crate::decode::ScalingAlgorithm;
 use crate::playback::VideoPlayer;
 use crate::video::ReadyVideos;
 
+use super::clip_marks::ClipMarks;
+use super::selection::TileSelection;
+
+/**
+    Scaling algorithms for video tiles, picked by the size a tile is drawn at.
+    Set with `--scaling-small` and `--scaling-large`.
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct ScalingConfig {
+    /// Algorithm for small tiles, where speed matters more than sharpness
+    pub small: ScalingAlgorithm,
+    /// Algorithm for large tiles
+    pub large: ScalingAlgorithm,
+    /// Tiles drawn at least this many device pixels high count as large
+    pub large_height: u32,
+}
+
+impl Default for ScalingConfig {
+    fn default() -> Self {
+        Self {
+            small: ScalingAlgorithm::FastBilinear,
+            large: ScalingAlgorithm::Lanczos,
+            large_height: 720,
+        }
+    }
+}
+
+impl Global for ScalingConfig {}
+
+impl ScalingConfig {
+    /**
+        Get the algorithm for a tile drawn at the given height in device pixels.
+    */
+    pub fn algorithm_for(&self, height: u32) -> ScalingAlgorithm {
+        if height >= self.large_height {
+            self.large
+        } else {
+            self.small
+        }
+    }
+}
+
 /**
     Global application state shared across all views.
 
@@ -17,8 +60,9 @@ pub struct AppState {
     pub ready_videos: Arc<ReadyVideos>,
     /// Audio mixer for combining all video streams
     pub mixer: Arc<AudioMixer>,
-    /// Current video players (dynamic length based on grid configuration)
-    pub players: Vec<Arc<VideoPlayer>>,
+    /// Current video players (dynamic length based on grid configuration),
+    /// None for tiles whose player is still starting
+    pub players: Vec<Option<Arc<VideoPlayer>>>,
     /// Master volume level (0.0 to 1.0)
     pub master_volume: f32,
     /// Whether master audio is muted
@@ -27,6 +71,14 @@ pub struct AppState {
     pub paused: bool,
     /// Flag to request skipping all videos (set by action, consumed by grid)
     pub skip_all_requested: bool,
+    /// Tile selected with the keyboard, and whether it's in focus mode
+    pub selection: TileSelection,
+    /// Number of columns in the grid, for moving the selection up and down
+    pub grid_cols: usize,
+    /// Tile the mouse was last over, for audio that follows it
+    pub hovered: Option<usize>,
+    /// In and out points for exporting a clip
+    pub clip_marks: Option<ClipMarks>,
 }
 
 impl Global for AppState {}
@@ -44,6 +96,10 @@ impl AppState {
             master_muted: false,
             paused: false,
             skip_all_requested: false,
+            selection: TileSelection::default(),
+            grid_cols: 0,
+            hovered: None,
+            clip_marks: None,
         }
     }
 
@@ -71,12 +127,21 @@ impl AppState {
     pub fn toggle_pause(&mut self) -> bool {
         self.paused = !self.paused;
 
-        for player in &self.players {
+        for (index, player) in self.players.iter().enumerate() {
+            let Some(player) = player else {
+                continue;
+            };
             if self.paused {
                 player.pause();
-            } else {
-                player.resume();
+                continue;
             }
+            // Leave frame stepping and slow motion at the reviewed frame
+            match player.end_review() {
+                Ok(Some(consumer)) => self.mixer.set_stream(index, Some(consumer)),
+                Ok(None) => {}
+                Err(e) => eprintln!("Failed to continue playback of slot {}: {}", index, e),
+            }
+            player.resume();
         }
 
         self.paused
@@ -121,12 +186,20 @@ impl AppState {
         Automatically grows the players vector if needed.
     */
     pub fn set_player(&mut self, index: usize, player: Arc<VideoPlayer>) {
-        // Grow vector if needed
-        while self.players.len() <= index {
-            // This is a placeholder - will be replaced immediately
-            self.players.push(Arc::clone(&player));
+        // Grow vector if needed, tiles in between are still starting
+        if self.players.len() <= index {
+            self.players.resize(index + 1, None);
+        }
+        self.players[index] = Some(player);
+    }
+
+    /**
+        Remove the player at the given index, while its tile starts another.
+    */
+    pub fn clear_player(&mut self, index: usize) {
+        if let Some(player) = self.players.get_mut(index) {
+            *player = None;
         }
-        self.players[index] = player;
     }
 
     /**
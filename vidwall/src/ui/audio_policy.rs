@@ -0,0 +1,125 @@
+use std::fmt;
+
+use gpui::Global;
+
+/**
+    Which tiles are heard, so that audio doesn't have to be managed per tile.
+
+    The grid applies the policy to its players whenever it renders, so it
+    follows the mouse and the tile selection as they change.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AudioPolicy {
+    /// Every tile is muted
+    MuteAll,
+    /// Only the tile under the mouse is heard
+    FollowHover,
+    /// Only the selected (or focused) tile is heard
+    FollowFocus,
+    /// Tiles are left as they are
+    #[default]
+    Manual,
+}
+
+impl Global for AudioPolicy {}
+
+impl AudioPolicy {
+    /**
+        All policies, in the order they are cycled through.
+    */
+    pub const ALL: [Self; 4] = [
+        Self::MuteAll,
+        Self::FollowHover,
+        Self::FollowFocus,
+        Self::Manual,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|policy| policy.to_name() == name)
+    }
+
+    pub const fn to_name(self) -> &'static str {
+        match self {
+            Self::MuteAll => "mute",
+            Self::FollowHover => "hover",
+            Self::FollowFocus => "focus",
+            Self::Manual => "manual",
+        }
+    }
+
+    /**
+        Get the policy after this one, wrapping around.
+    */
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|p| *p == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    /**
+        Check whether the tile at the given index should be heard, given the
+        tile under the mouse and the selected tile.
+
+        Returns `None` if the policy leaves the tile's mute state alone.
+    */
+    pub fn is_audible(
+        self,
+        index: usize,
+        hovered: Option<usize>,
+        selected: Option<usize>,
+    ) -> Option<bool> {
+        match self {
+            Self::MuteAll => Some(false),
+            Self::FollowHover => Some(hovered == Some(index)),
+            Self::FollowFocus => Some(selected == Some(index)),
+            Self::Manual => None,
+        }
+    }
+}
+
+impl fmt::Display for AudioPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.to_name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        for policy in AudioPolicy::ALL {
+            assert_eq!(AudioPolicy::from_name(policy.to_name()), Some(policy));
+        }
+        assert_eq!(AudioPolicy::from_name("loud"), None);
+        assert_eq!(AudioPolicy::Manual.next(), AudioPolicy::MuteAll);
+    }
+
+    #[test]
+    fn test_is_audible() {
+        assert_eq!(
+            AudioPolicy::MuteAll.is_audible(0, Some(0), Some(0)),
+            Some(false)
+        );
+        assert_eq!(
+            AudioPolicy::FollowHover.is_audible(1, Some(1), Some(0)),
+            Some(true)
+        );
+        assert_eq!(
+            AudioPolicy::FollowHover.is_audible(0, Some(1), Some(0)),
+            Some(false)
+        );
+        assert_eq!(
+            AudioPolicy::FollowFocus.is_audible(0, Some(1), Some(0)),
+            Some(true)
+        );
+        // Nothing selected means nothing is heard
+        assert_eq!(
+            AudioPolicy::FollowFocus.is_audible(0, Some(0), None),
+            Some(false)
+        );
+        assert_eq!(AudioPolicy::Manual.is_audible(0, None, None), None);
+    }
+}
@@ -0,0 +1,130 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/**
+    In and out points marked on a tile, for exporting the part between them.
+
+    The points belong to a single video: marking a point on another video
+    starts over with new marks.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipMarks {
+    /// Video the points were marked on
+    pub path: PathBuf,
+    pub start: Option<Duration>,
+    pub end: Option<Duration>,
+}
+
+impl ClipMarks {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            start: None,
+            end: None,
+        }
+    }
+
+    /**
+        Get the marks for the given video, replacing marks on any other video.
+    */
+    pub fn for_video<'a>(marks: &'a mut Option<Self>, path: &Path) -> &'a mut Self {
+        if marks.as_ref().is_none_or(|m| m.path != path) {
+            *marks = Some(Self::new(path));
+        }
+        marks.as_mut().unwrap()
+    }
+
+    /**
+        Get the marked range, if both points are set and they are apart.
+        Points marked in reverse order are swapped.
+    */
+    pub fn range(&self) -> Option<(Duration, Duration)> {
+        let (a, b) = (self.start?, self.end?);
+        let (start, end) = (a.min(b), a.max(b));
+        (start < end).then_some((start, end))
+    }
+
+    /**
+        Get the path to export the marked range to, in the given directory,
+        named after the video and the range, e.g. "match 00.12.34-00.13.00.mp4".
+    */
+    pub fn output_path(&self, dir: &Path) -> Option<PathBuf> {
+        let (start, end) = self.range()?;
+        let stem = self
+            .path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "clip".to_string());
+        let name = format!(
+            "{} {}-{}.mp4",
+            stem,
+            format_position(start),
+            format_position(end)
+        );
+        Some(dir.join(name))
+    }
+}
+
+/**
+    Get the directory clips are exported to.
+*/
+pub fn clips_dir() -> Option<PathBuf> {
+    dirs::video_dir()
+        .or_else(dirs::home_dir)
+        .map(|p| p.join("vidwall"))
+}
+
+/**
+    Format a position as hours, minutes and seconds for use in file names.
+*/
+fn format_position(position: Duration) -> String {
+    let secs = position.as_secs();
+    format!("{:02}.{:02}.{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range() {
+        let mut marks = ClipMarks::new("match.mkv");
+        assert_eq!(marks.range(), None);
+
+        marks.start = Some(Duration::from_secs(754));
+        assert_eq!(marks.range(), None);
+
+        marks.end = Some(Duration::from_secs(780));
+        assert_eq!(
+            marks.range(),
+            Some((Duration::from_secs(754), Duration::from_secs(780)))
+        );
+        assert_eq!(
+            marks.output_path(Path::new("clips")),
+            Some(PathBuf::from("clips/match 00.12.34-00.13.00.mp4"))
+        );
+
+        // Reversed points are swapped, equal ones are no range
+        marks.end = Some(Duration::from_secs(700));
+        assert_eq!(
+            marks.range(),
+            Some((Duration::from_secs(700), Duration::from_secs(754)))
+        );
+        marks.end = marks.start;
+        assert_eq!(marks.range(), None);
+    }
+
+    #[test]
+    fn test_for_video() {
+        let mut marks = None;
+        ClipMarks::for_video(&mut marks, Path::new("a.mp4")).start = Some(Duration::from_secs(1));
+        ClipMarks::for_video(&mut marks, Path::new("a.mp4")).end = Some(Duration::from_secs(2));
+        assert!(marks.as_ref().unwrap().range().is_some());
+
+        // Marking another video starts over
+        ClipMarks::for_video(&mut marks, Path::new("b.mp4")).end = Some(Duration::from_secs(3));
+        let marks = marks.unwrap();
+        assert_eq!(marks.path, PathBuf::from("b.mp4"));
+        assert_eq!(marks.start, None);
+    }
+}
@@ -1,8 +1,25 @@
+use std::time::Duration;
+
 /**
     Threshold for classifying a video as portrait (aspect ratio <= this value)
 */
 const PORTRAIT_THRESHOLD: f32 = 0.8;
 
+/**
+    Scores of source grids closer than this are considered equal
+*/
+const SCORE_EPSILON: f32 = 1e-4;
+
+/**
+    Default maximum number of tiles shown at once for a source list
+*/
+const DEFAULT_MAX_TILES: u32 = 9;
+
+/**
+    Default time between rotations of the rotating slot
+*/
+const DEFAULT_ROTATE_INTERVAL: Duration = Duration::from_secs(15);
+
 /**
     Video orientation category based on aspect ratio.
 */
@@ -130,6 +147,73 @@ impl GridConfig {
 
         best_config
     }
+
+    /**
+        Find the optimal grid for a fixed list of sources, given their aspect ratios.
+
+        Every grid of up to `max_tiles` slots is considered, scored by how much
+        of the window the sources would cover once letterboxed into their cells.
+        Grids must have a slot for every source if there are at most `max_tiles`
+        of them. Otherwise the score is scaled by how many slots the grid has
+        out of `max_tiles`, and the sources that don't fit share a rotating slot
+        (see [`SourceAssignment`]).
+
+        Among grids with the same score, the one with fewer slots wins, and then
+        the one whose shape is closest to the window's.
+    */
+    pub fn for_sources(aspect_ratios: &[f32], width: f32, height: f32, max_tiles: u32) -> Self {
+        let count = aspect_ratios.len() as u32;
+        let max_tiles = max_tiles.max(1);
+        if count == 0 {
+            return Self::optimal_for_window(width, height);
+        }
+
+        let mean_ratio = aspect_ratios.iter().sum::<f32>() / count as f32;
+        let orientation = VideoOrientation::from_aspect_ratio(mean_ratio);
+        let target = count.min(max_tiles);
+        let min_slots = if count <= max_tiles { count } else { 1 };
+
+        let window_ratio = width / height;
+        let shape_difference =
+            |cols: u32, rows: u32| ((cols as f32 / rows as f32) / window_ratio).ln().abs();
+
+        let mut best_config = GridConfig::new(1, 1, orientation);
+        let mut best_score = f32::MIN;
+
+        for slots in min_slots..=max_tiles {
+            for cols in 1..=slots {
+                if slots % cols != 0 {
+                    continue;
+                }
+                let rows = slots / cols;
+
+                // Fraction of each cell a source covers when letterboxed into it
+                let cell_ratio = (width / cols as f32) / (height / rows as f32);
+                let mean_fill = aspect_ratios
+                    .iter()
+                    .map(|&ratio| (ratio / cell_ratio).min(cell_ratio / ratio))
+                    .sum::<f32>()
+                    / count as f32;
+
+                let shown = count.min(slots) as f32;
+                let score = mean_fill * (shown / slots as f32) * (shown / target as f32);
+
+                // Slots are tried in increasing order, so only a clearly better
+                // score may replace a grid with fewer slots
+                let better = score > best_score + SCORE_EPSILON
+                    || (score > best_score - SCORE_EPSILON
+                        && slots == best_config.total_slots()
+                        && shape_difference(cols, rows)
+                            < shape_difference(best_config.cols, best_config.rows));
+                if better {
+                    best_config = GridConfig::new(cols, rows, orientation);
+                    best_score = score;
+                }
+            }
+        }
+
+        best_config
+    }
 }
 
 impl Default for GridConfig {
@@ -138,6 +222,76 @@ impl Default for GridConfig {
     }
 }
 
+/**
+    Options for showing a fixed list of sources, one per tile.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileOptions {
+    /// Maximum number of tiles shown at once
+    pub max_tiles: u32,
+    /// How long each source stays in the rotating slot
+    pub rotate_interval: Duration,
+    /// Watch camera tiles for motion, saving snapshots when they see any
+    pub motion: bool,
+}
+
+impl Default for TileOptions {
+    fn default() -> Self {
+        Self {
+            max_tiles: DEFAULT_MAX_TILES,
+            rotate_interval: DEFAULT_ROTATE_INTERVAL,
+            motion: false,
+        }
+    }
+}
+
+/**
+    Assignment of a fixed list of sources to the slots of a grid.
+
+    Sources fill the slots in order. When there are more sources than slots,
+    the last slot becomes a rotating slot that cycles through all of the
+    sources that did not get a slot of their own, one per rotation tick.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceAssignment {
+    slots: usize,
+    sources: usize,
+}
+
+impl SourceAssignment {
+    /**
+        Create an assignment of `sources` sources to a grid with `slots` slots.
+    */
+    pub fn new(slots: usize, sources: usize) -> Self {
+        Self { slots, sources }
+    }
+
+    /**
+        Get the index of the rotating slot, if there are sources left over.
+    */
+    pub fn rotating_slot(&self) -> Option<usize> {
+        (self.slots > 0 && self.sources > self.slots).then(|| self.slots - 1)
+    }
+
+    /**
+        Get the index of the source shown in a slot at the given rotation tick.
+
+        Returns None for slots left empty because there are fewer sources than slots.
+    */
+    pub fn source_for_slot(&self, slot: usize, tick: usize) -> Option<usize> {
+        if slot >= self.slots || slot >= self.sources {
+            return None;
+        }
+        match self.rotating_slot() {
+            Some(rotating) if slot == rotating => {
+                let overflow = self.sources - rotating;
+                Some(rotating + tick % overflow)
+            }
+            _ => Some(slot),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -239,4 +393,57 @@ mod tests {
         let config = GridConfig::optimal_for_window(540.0, 1920.0);
         assert_eq!(config.orientation, VideoOrientation::Portrait);
     }
+
+    #[test]
+    fn test_for_sources_fits_all() {
+        // 6 landscape sources in a 16:9 window fit best as 3x2
+        let ratios = [16.0 / 9.0; 6];
+        let config = GridConfig::for_sources(&ratios, 1920.0, 1080.0, 9);
+        assert_eq!((config.cols, config.rows), (3, 2));
+        assert_eq!(config.orientation, VideoOrientation::Landscape);
+
+        // 4 sources fill a 2x2 exactly, even though 3x2 is allowed
+        let config = GridConfig::for_sources(&ratios[..4], 1920.0, 1080.0, 9);
+        assert_eq!((config.cols, config.rows), (2, 2));
+
+        // A single source always gets the whole window
+        let config = GridConfig::for_sources(&ratios[..1], 1920.0, 1080.0, 9);
+        assert_eq!((config.cols, config.rows), (1, 1));
+    }
+
+    #[test]
+    fn test_for_sources_respects_aspect_ratios() {
+        // 3 portrait sources in a 16:9 window sit side by side
+        let ratios = [9.0 / 16.0; 3];
+        let config = GridConfig::for_sources(&ratios, 1920.0, 1080.0, 9);
+        assert_eq!((config.cols, config.rows), (3, 1));
+        assert_eq!(config.orientation, VideoOrientation::Portrait);
+    }
+
+    #[test]
+    fn test_for_sources_max_tiles() {
+        // 12 sources with at most 4 tiles use a full 2x2 and rotate the rest
+        let ratios = [16.0 / 9.0; 12];
+        let config = GridConfig::for_sources(&ratios, 1920.0, 1080.0, 4);
+        assert_eq!((config.cols, config.rows), (2, 2));
+    }
+
+    #[test]
+    fn test_source_assignment() {
+        // Enough slots: every source has its own, extra slots stay empty
+        let assignment = SourceAssignment::new(4, 3);
+        assert_eq!(assignment.rotating_slot(), None);
+        assert_eq!(assignment.source_for_slot(2, 5), Some(2));
+        assert_eq!(assignment.source_for_slot(3, 0), None);
+
+        // Too many sources: the last slot cycles through the overflow
+        let assignment = SourceAssignment::new(4, 6);
+        assert_eq!(assignment.rotating_slot(), Some(3));
+        assert_eq!(assignment.source_for_slot(0, 7), Some(0));
+        assert_eq!(assignment.source_for_slot(3, 0), Some(3));
+        assert_eq!(assignment.source_for_slot(3, 1), Some(4));
+        assert_eq!(assignment.source_for_slot(3, 2), Some(5));
+        assert_eq!(assignment.source_for_slot(3, 3), Some(3));
+        assert_eq!(assignment.source_for_slot(4, 0), None);
+    }
 }
@@ -1,40 +1,628 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
-use gpui::{Context, Entity, IntoElement, Render, Window, div, prelude::*, rgb};
+use gpui::{
+    Context, Div, Entity, ExternalPaths, IntoElement, MouseMoveEvent, Render, Timer, Window, div,
+    img, prelude::*, px, relative, rgb, rgba,
+};
 
-use crate::playback::VideoPlayer;
-use crate::video::ReadyVideos;
+use crate::decode::{DecoderError, OverflowPolicy};
+use crate::history::{PlaybackHistory, format_position};
+use crate::playback::{
+    LoopRegion, MemoryBudget, MemoryPlan, MotionDetector, MotionEvent, MotionSettings,
+    PlaybackError, PlaybackEvent, PlayerOptions, Thumbnails, VideoFrame, VideoPlayer,
+    sample_positions, save_snapshot, snapshot_file_name,
+};
+use crate::video::{MediaSource, ReadyVideos, VideoInfo, VideoScanner};
 
 use super::app_state::AppState;
-use super::grid_config::GridConfig;
+use super::audio_policy::AudioPolicy;
+use super::clip_marks::clips_dir;
+use super::grid_config::{GridConfig, SourceAssignment, TileOptions};
+use super::startup::{MAX_CONCURRENT_STARTS, StartupQueue, start_priority};
 use super::video_element::video_element;
-use super::video_slot::{VideoEnded, VideoSlot};
+use super::video_slot::{LoopRestart, VideoEnded, VideoFailed, VideoSlot};
+
+/**
+    Color of the outline around the selected tile
+*/
+const SELECTION_COLOR: u32 = 0xffffff;
+
+/**
+    Color of the outline around a camera tile that recently saw motion
+*/
+const MOTION_COLOR: u32 = 0xef4444;
+
+/**
+    Color of the outline around a tile that files are dragged over
+*/
+const DROP_TARGET_COLOR: u32 = 0x3b82f6;
+
+/**
+    Background of the overlay shown on a tile whose video failed,
+    dark but translucent so that the last frame stays visible
+*/
+const ERROR_OVERLAY_COLOR: u32 = 0x000000cc;
+
+/**
+    Background of the seek bar shown along the bottom of the hovered tile,
+    and of the segment under the mouse
+*/
+const SEEK_BAR_COLOR: u32 = 0xffffff40;
+const SEEK_BAR_HOVER_COLOR: u32 = 0xffffffcc;
+
+/**
+    Height of the seek bar, in pixels
+*/
+const SEEK_BAR_HEIGHT: f32 = 8.0;
+
+/**
+    Width of the thumbnail shown above the seek bar, in pixels
+*/
+const SCRUB_PREVIEW_WIDTH: f32 = 160.0;
+
+/**
+    Furthest a tile that was hidden in focus mode skips ahead when it is shown
+    again, to catch up on the time it was paused without a long seek
+*/
+const OCCLUSION_CATCH_UP_LIMIT: Duration = Duration::from_secs(10);
+
+/**
+    Interval for saving the positions of the playing videos to the history
+*/
+const HISTORY_SAVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/**
+    Interval for checking how much memory the players want against the budget
+*/
+const MEMORY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/**
+    Interval for checking the frames of camera tiles for motion
+*/
+const MOTION_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/**
+    How long a camera tile stays outlined after it last saw motion
+*/
+const MOTION_ALERT_DURATION: Duration = Duration::from_secs(5);
+
+/**
+    How long before the rotating slot moves on the next source's player is
+    started, so that it is connected and has a frame by the switch
+*/
+const PRECONNECT_LEAD: Duration = Duration::from_secs(3);
+
+/**
+    A fixed list of sources shown one per tile, instead of random videos.
+*/
+struct SourceTiles {
+    sources: Vec<VideoInfo>,
+    options: TileOptions,
+    /// Number of times the rotating slot has moved on to the next source
+    tick: usize,
+    /// Player of the source the rotating slot moves on to next, started ahead of time
+    preconnected: Option<Preconnected>,
+}
+
+/**
+    A player started ahead of the rotation tick that shows it.
+*/
+struct Preconnected {
+    tick: usize,
+    video_info: VideoInfo,
+    player: Arc<VideoPlayer>,
+}
+
+/**
+    A tile whose player was paused because the focused tile hides it.
+*/
+struct OccludedTile {
+    player: Arc<VideoPlayer>,
+    since: Instant,
+}
 
 /**
     The main grid view that displays videos in a dynamic grid layout.
 
     Uses VideoSlot entities for each video position and subscribes to their
-    VideoEnded events for automatic video replacement.
-    Videos are filtered by orientation to match the grid's orientation.
+    VideoEnded events for automatic video replacement. Players are started
+    in the background a few at a time, and their slots stay empty until then.
+    Videos are filtered by orientation to match the grid's orientation,
+    unless the grid was given a fixed list of sources to show instead.
 */
 pub struct GridView {
-    slots: Vec<Entity<VideoSlot>>,
+    /// Slot entities by index, None while a slot's player is starting
+    slots: Vec<Option<Entity<VideoSlot>>>,
+    /// Slots waiting for their players to start
+    startup: StartupQueue,
     config: GridConfig,
     ready_videos: Arc<ReadyVideos>,
+    tiles: Option<SourceTiles>,
+    /// Size the grid is laid out for, in pixels
+    size: Option<(f32, f32)>,
+    /// Tiles paused while hidden behind the focused tile, by slot index
+    occluded: HashMap<usize, OccludedTile>,
+    /// Seek bar thumbnails of the shown videos, None while they are generated
+    thumbnails: HashMap<PathBuf, Option<Arc<Thumbnails>>>,
+    /// Slot and seek bar segment under the mouse
+    scrub: Option<(usize, usize)>,
+    /// How far the players are shrunk to fit in the memory budget
+    memory_plan: MemoryPlan,
+    /// Motion detectors of the shown camera sources
+    motion: HashMap<PathBuf, MotionDetector>,
+    /// When tiles last saw motion, by slot index
+    motion_alerts: HashMap<usize, Instant>,
 }
 
 impl GridView {
     /**
         Create a new empty grid view that will pull videos from the given storage.
     */
-    pub fn new(ready_videos: Arc<ReadyVideos>, _cx: &mut Context<Self>) -> Self {
+    pub fn new(ready_videos: Arc<ReadyVideos>, cx: &mut Context<Self>) -> Self {
+        Self::start_history_saving(cx);
+        Self::start_memory_budgeting(cx);
         Self {
             slots: Vec::new(),
+            startup: StartupQueue::new(MAX_CONCURRENT_STARTS),
             config: GridConfig::default(),
             ready_videos,
+            tiles: None,
+            size: None,
+            occluded: HashMap::new(),
+            thumbnails: HashMap::new(),
+            scrub: None,
+            memory_plan: MemoryPlan::default(),
+            motion: HashMap::new(),
+            motion_alerts: HashMap::new(),
+        }
+    }
+
+    /**
+        Lay the grid out for the given size in pixels - the window it's shown
+        in, or the whole wall when it spans several displays.
+    */
+    pub fn resize(&mut self, width: f32, height: f32, cx: &mut Context<Self>) {
+        self.size = Some((width, height));
+        let config = self.layout_for_window(width, height);
+        self.reconfigure(config, cx);
+    }
+
+    /**
+        Probe a fixed list of sources in the background, and show them
+        once all of them have answered (or failed to).
+    */
+    pub fn load_sources(
+        &mut self,
+        sources: Vec<PathBuf>,
+        options: TileOptions,
+        cx: &mut Context<Self>,
+    ) {
+        println!("\nProbing {} source(s)...", sources.len());
+        let probe = cx
+            .background_executor()
+            .spawn(async move { VideoScanner::probe_sources(sources) });
+
+        cx.spawn(async move |this, cx| {
+            let infos = probe.await;
+            if infos.is_empty() {
+                eprintln!("No valid sources found.");
+                return;
+            }
+            this.update(cx, |grid, cx| grid.set_sources(infos, options, cx))
+                .ok();
+        })
+        .detach();
+    }
+
+    /**
+        Show a fixed list of sources, one per tile, in the given order.
+
+        Sources that don't fit in the grid share its last slot, which moves on
+        to the next of them every `options.rotate_interval`.
+    */
+    pub fn set_sources(
+        &mut self,
+        sources: Vec<VideoInfo>,
+        options: TileOptions,
+        cx: &mut Context<Self>,
+    ) {
+        println!(
+            "Showing {} source(s), at most {} at once",
+            sources.len(),
+            options.max_tiles
+        );
+
+        let restart_timer = self
+            .tiles
+            .as_ref()
+            .is_none_or(|tiles| tiles.options.rotate_interval != options.rotate_interval);
+        let start_motion = options.motion
+            && !self
+                .tiles
+                .as_ref()
+                .is_some_and(|tiles| tiles.options.motion);
+
+        let previous = self.tiles.replace(SourceTiles {
+            sources,
+            options,
+            tick: 0,
+            preconnected: None,
+        });
+        if let Some(preconnected) = previous.and_then(|tiles| tiles.preconnected) {
+            preconnected.player.stop();
+        }
+
+        if restart_timer {
+            Self::start_rotation(options, cx);
+        }
+        if start_motion {
+            Self::start_motion_detection(cx);
+        }
+
+        if let Some((width, height)) = self.size {
+            self.resize(width, height, cx);
+        }
+    }
+
+    /**
+        Compute the grid configuration for a window of the given size.
+    */
+    fn layout_for_window(&self, width: f32, height: f32) -> GridConfig {
+        match &self.tiles {
+            Some(tiles) => {
+                let ratios: Vec<f32> = tiles.sources.iter().map(VideoInfo::aspect_ratio).collect();
+                GridConfig::for_sources(&ratios, width, height, tiles.options.max_tiles)
+            }
+            None => GridConfig::optimal_for_window(width, height),
+        }
+    }
+
+    /**
+        Start the background task that cycles the rotating slot, connecting
+        to each next source shortly before it is shown.
+        Stops when the rotation interval changes or the grid is dropped.
+    */
+    fn start_rotation(options: TileOptions, cx: &mut Context<Self>) {
+        let interval = options.rotate_interval;
+        let lead = PRECONNECT_LEAD.min(interval / 2);
+        cx.spawn(async move |this, cx| {
+            loop {
+                Timer::after(interval - lead).await;
+                let keep_going = this
+                    .update(cx, |grid, cx| {
+                        let current = grid.tiles.as_ref().map(|t| t.options.rotate_interval);
+                        if current != Some(interval) {
+                            return false;
+                        }
+                        grid.preconnect(cx);
+                        true
+                    })
+                    .unwrap_or(false);
+                if !keep_going {
+                    break;
+                }
+
+                Timer::after(lead).await;
+                let keep_going = this
+                    .update(cx, |grid, cx| {
+                        let current = grid.tiles.as_ref().map(|t| t.options.rotate_interval);
+                        if current != Some(interval) {
+                            return false;
+                        }
+                        grid.rotate(cx);
+                        true
+                    })
+                    .unwrap_or(false);
+                if !keep_going {
+                    break;
+                }
+            }
+        })
+        .detach();
+    }
+
+    /**
+        Start the timer that checks the camera tiles for motion.
+        Stops when motion detection is turned off or the grid is dropped.
+    */
+    fn start_motion_detection(cx: &mut Context<Self>) {
+        cx.spawn(async move |this, cx| {
+            loop {
+                Timer::after(MOTION_CHECK_INTERVAL).await;
+                let keep_going = this
+                    .update(cx, |grid, cx| {
+                        let enabled = grid.tiles.as_ref().is_some_and(|t| t.options.motion);
+                        if !enabled {
+                            grid.motion.clear();
+                            grid.motion_alerts.clear();
+                            return false;
+                        }
+                        grid.detect_motion(cx);
+                        true
+                    })
+                    .unwrap_or(false);
+                if !keep_going {
+                    break;
+                }
+            }
+        })
+        .detach();
+    }
+
+    /**
+        Compare the current frames of the camera tiles to their previous
+        ones, reporting the ones that saw motion, and let the outlines of
+        tiles that haven't seen any for a while fade.
+    */
+    fn detect_motion(&mut self, cx: &mut Context<Self>) {
+        let now = Instant::now();
+        let alerts = self.motion_alerts.len();
+        let mut shown = HashSet::new();
+
+        for index in 0..self.slots.len() {
+            let Some(slot) = self.slot(index) else {
+                continue;
+            };
+            let slot = slot.read(cx);
+            let path = slot.video_info().path.clone();
+            let player = slot.player().clone();
+            if !matches!(MediaSource::from_path(&path), Ok(MediaSource::Rtsp(_))) {
+                continue;
+            }
+            let Some(frame) = player.get_frame() else {
+                continue;
+            };
+
+            shown.insert(path.clone());
+            let detector = self
+                .motion
+                .entry(path.clone())
+                .or_insert_with(|| MotionDetector::new(MotionSettings::default()));
+            if let Some(event) = detector.observe(&frame, now) {
+                self.motion_alerts.insert(index, now);
+                Self::report_motion(&path, frame, event);
+                cx.notify();
+            }
+        }
+
+        self.motion.retain(|path, _| shown.contains(path));
+        self.motion_alerts
+            .retain(|_, seen| now.duration_since(*seen) < MOTION_ALERT_DURATION);
+        if self.motion_alerts.len() < alerts {
+            cx.notify();
+        }
+    }
+
+    /**
+        Print a motion event, and save a snapshot of the frame it was seen
+        in to the motion folder in the background.
+    */
+    fn report_motion(path: &Path, frame: VideoFrame, event: MotionEvent) {
+        println!(
+            "Motion on {} ({:.0}% of the picture)",
+            path.display(),
+            event.area * 100.0
+        );
+        let Some(dir) = clips_dir().map(|dir| dir.join("motion")) else {
+            return;
+        };
+        let output = dir.join(snapshot_file_name(
+            &path.to_string_lossy(),
+            SystemTime::now(),
+        ));
+        std::thread::spawn(move || {
+            if let Err(e) = save_snapshot(&frame, &output) {
+                eprintln!("Failed to save snapshot to {}: {}", output.display(), e);
+            }
+        });
+    }
+
+    /**
+        Start the timer that saves the positions of the playing videos to
+        the history, so that they can be resumed in a later session.
+    */
+    fn start_history_saving(cx: &mut Context<Self>) {
+        cx.spawn(async move |this, cx| {
+            loop {
+                Timer::after(HISTORY_SAVE_INTERVAL).await;
+                let result = this.update(cx, |grid, cx| {
+                    for index in 0..grid.slots.len() {
+                        grid.record_history(index, cx);
+                    }
+                    cx.global::<PlaybackHistory>().save();
+                });
+                if result.is_err() {
+                    break; // Grid was dropped
+                }
+            }
+        })
+        .detach();
+    }
+
+    /**
+        Start the timer that keeps the players within the memory budget.
+    */
+    fn start_memory_budgeting(cx: &mut Context<Self>) {
+        cx.spawn(async move |this, cx| {
+            loop {
+                Timer::after(MEMORY_CHECK_INTERVAL).await;
+                let result = this.update(cx, |grid, cx| grid.apply_memory_budget(cx));
+                if result.is_err() {
+                    break; // Grid was dropped
+                }
+            }
+        })
+        .detach();
+    }
+
+    /**
+        Shrink the players' buffers and resolutions as far as needed to fit
+        what they want in the memory budget, or grow them back once it fits.
+    */
+    fn apply_memory_budget(&mut self, cx: &mut Context<Self>) {
+        let budget = *cx.global::<MemoryBudget>();
+        let players: Vec<_> = self
+            .slots
+            .iter()
+            .flatten()
+            .map(|slot| Arc::clone(slot.read(cx).player()))
+            .collect();
+
+        let demand: u64 = players.iter().map(|player| player.memory_demand()).sum();
+        let plan = budget.plan(demand);
+        for player in &players {
+            player.set_memory_plan(plan);
+        }
+
+        if plan != self.memory_plan {
+            const MB: u64 = 1024 * 1024;
+            let buffered: u64 = players.iter().map(|player| player.buffered_bytes()).sum();
+            println!(
+                "Memory budget: players want {} MB of {} MB ({} MB buffered), \
+                 queues at {:.0}%, resolution at {:.0}%",
+                demand / MB,
+                budget.limit / MB,
+                buffered / MB,
+                plan.queue_scale * 100.0,
+                plan.resolution_scale * 100.0
+            );
+            self.memory_plan = plan;
+        }
+    }
+
+    /**
+        Record how far the video in the given slot has been watched.
+    */
+    fn record_history(&self, index: usize, cx: &mut Context<Self>) {
+        let Some(slot) = self.slot(index) else {
+            return;
+        };
+        let slot = slot.read(cx);
+        let path = slot.video_info().path.clone();
+        if !path.is_file() {
+            return;
+        }
+        let player = Arc::clone(slot.player());
+
+        cx.update_global::<PlaybackHistory, _>(|history, _cx| {
+            history.record(
+                &path,
+                player.position(),
+                player.duration(),
+                SystemTime::now(),
+            );
+        });
+    }
+
+    /**
+        Move the rotating slot on to the next source that doesn't fit in the grid.
+    */
+    fn rotate(&mut self, cx: &mut Context<Self>) {
+        let Some(tiles) = &mut self.tiles else {
+            return;
+        };
+        let assignment =
+            SourceAssignment::new(self.config.total_slots() as usize, tiles.sources.len());
+        let preconnected = tiles.preconnected.take();
+        let Some(slot) = assignment.rotating_slot() else {
+            if let Some(preconnected) = preconnected {
+                preconnected.player.stop();
+            }
+            return;
+        };
+
+        tiles.tick += 1;
+        // Sources may have changed since, such as by dropping files on the grid
+        let next_path = assignment
+            .source_for_slot(slot, tiles.tick)
+            .and_then(|source| tiles.sources.get(source))
+            .map(|info| info.path.clone());
+        match preconnected {
+            Some(preconnected)
+                if preconnected.tick == tiles.tick
+                    && Some(&preconnected.video_info.path) == next_path.as_ref() =>
+            {
+                self.swap_in_player(slot, preconnected.player, preconnected.video_info, cx);
+            }
+            stale => {
+                if let Some(preconnected) = stale {
+                    preconnected.player.stop();
+                }
+                self.replace_video(slot, cx);
+            }
         }
     }
 
+    /**
+        Start the player of the source the rotating slot shows next, in the
+        background, so that it can take over the slot without a black gap.
+        It is thrown away if the slot has moved on by the time it started.
+    */
+    fn preconnect(&mut self, cx: &mut Context<Self>) {
+        let Some(tiles) = &self.tiles else {
+            return;
+        };
+        let assignment =
+            SourceAssignment::new(self.config.total_slots() as usize, tiles.sources.len());
+        let Some(slot) = assignment.rotating_slot() else {
+            return;
+        };
+        let tick = tiles.tick + 1;
+        let Some(video_info) = assignment
+            .source_for_slot(slot, tick)
+            .and_then(|source| tiles.sources.get(source).cloned())
+        else {
+            return;
+        };
+
+        let path = video_info.path.clone();
+        let create = cx
+            .background_executor()
+            .spawn(async move { VideoPlayer::with_options(&path, player_options(&path)) });
+        cx.spawn(async move |this, cx| {
+            let result = create.await;
+            this.update(cx, |grid, _cx| {
+                let player = match result {
+                    Ok(player) => Arc::new(player),
+                    Err(e) => {
+                        eprintln!("Failed to preconnect {:?}: {}", video_info.path, e);
+                        return;
+                    }
+                };
+                match &mut grid.tiles {
+                    Some(tiles) if tiles.tick + 1 == tick => {
+                        let previous = tiles.preconnected.replace(Preconnected {
+                            tick,
+                            video_info,
+                            player,
+                        });
+                        if let Some(previous) = previous {
+                            previous.player.stop();
+                        }
+                    }
+                    _ => player.stop(),
+                }
+            })
+            .ok();
+        })
+        .detach();
+    }
+
+    /**
+        Get the source shown in a slot, if the grid has a fixed list of sources.
+        Returns None when it doesn't, or when the slot has no source.
+    */
+    fn source_for_slot(&self, index: usize) -> Option<VideoInfo> {
+        let tiles = self.tiles.as_ref()?;
+        let assignment =
+            SourceAssignment::new(self.config.total_slots() as usize, tiles.sources.len());
+        let source = assignment.source_for_slot(index, tiles.tick)?;
+        tiles.sources.get(source).cloned()
+    }
+
     /**
         Get the current grid configuration.
     */
@@ -57,8 +645,9 @@ impl GridView {
         let old_count = self.slots.len();
         let new_count = new_config.total_slots() as usize;
 
-        if orientation_changed {
-            // Clear all slots when orientation changes - we need different videos
+        if orientation_changed || self.tiles.is_some() {
+            // Clear all slots when orientation changes - we need different videos.
+            // Fixed sources are reassigned to slots whenever the grid changes.
             let app_state = cx.global::<AppState>();
             let mixer = Arc::clone(&app_state.mixer);
 
@@ -67,11 +656,12 @@ impl GridView {
             }
 
             // Explicitly stop all players before dropping to release file handles
-            for slot in &self.slots {
+            for slot in self.slots.iter().flatten() {
                 slot.read(cx).player().stop();
             }
 
             self.slots.clear();
+            self.startup.clear();
             cx.update_global::<AppState, _>(|state, _cx| {
                 state.truncate_players(0);
             });
@@ -84,11 +674,7 @@ impl GridView {
         } else if new_count > old_count {
             self.config = new_config;
             // Add new slots
-            for index in old_count..new_count {
-                if let Some(slot) = self.create_slot(index, cx) {
-                    self.slots.push(slot);
-                }
-            }
+            self.fill_empty_slots(cx);
         } else if new_count < old_count {
             // Remove excess slots
             let app_state = cx.global::<AppState>();
@@ -101,7 +687,10 @@ impl GridView {
 
             // Explicitly stop players being removed to release file handles
             for index in new_count..old_count {
-                self.slots[index].read(cx).player().stop();
+                if let Some(slot) = self.slot(index) {
+                    slot.read(cx).player().stop();
+                }
+                self.startup.cancel(index);
             }
 
             // Remove slots and update AppState
@@ -113,6 +702,11 @@ impl GridView {
             self.config = new_config;
         }
 
+        let cols = self.config.cols as usize;
+        cx.update_global::<AppState, _>(|state, _cx| {
+            state.grid_cols = cols;
+        });
+
         cx.notify();
     }
 
@@ -123,47 +717,59 @@ impl GridView {
         let target_count = self.config.total_slots() as usize;
         let orientation = self.config.orientation;
 
-        // First, ensure we have enough slot entities
-        while self.slots.len() < target_count {
-            if let Some(slot) = self.create_slot_for_orientation(self.slots.len(), orientation, cx)
-            {
-                self.slots.push(slot);
-            } else {
+        if self.slots.len() < target_count {
+            self.slots.resize_with(target_count, || None);
+        }
+
+        for index in 0..target_count {
+            if self.slots[index].is_some() || self.startup.contains(index) {
+                continue;
+            }
+            if !self.create_slot_for_orientation(index, orientation, cx) {
                 break; // No more videos available for this orientation
             }
         }
 
+        // Every slot is queued before any start, so that they start in priority order
+        self.start_queued(cx);
         cx.notify();
     }
 
     /**
-        Create a new slot at the given index with a video of the specified orientation.
+        Check if any slot has neither a player nor one starting.
+    */
+    fn has_empty_slots(&self) -> bool {
+        let target_count = self.config.total_slots() as usize;
+        (0..target_count).any(|index| self.slot(index).is_none() && !self.startup.contains(index))
+    }
+
+    /**
+        Get the slot entity at the given index, if its player has started.
+    */
+    fn slot(&self, index: usize) -> Option<&Entity<VideoSlot>> {
+        self.slots.get(index)?.as_ref()
+    }
+
+    /**
+        Queue a video of the specified orientation to start in the slot at the
+        given index. Returns false if there was no video for it.
     */
     fn create_slot_for_orientation(
-        &self,
+        &mut self,
         index: usize,
         orientation: crate::ui::grid_config::VideoOrientation,
         cx: &mut Context<Self>,
-    ) -> Option<Entity<VideoSlot>> {
-        // Get paths of currently playing videos
-        let current_paths: Vec<_> = self
-            .slots
-            .iter()
-            .map(|slot| slot.read(cx).video_info().path.clone())
-            .collect();
-
-        // Pick a video of the correct orientation not currently playing
-        let video_info = self
-            .ready_videos
-            .pick_random_except_for_orientation(orientation, &current_paths)?;
-
-        // Create the player
-        let player = match VideoPlayer::with_options(&video_info.path, None, None) {
-            Ok(p) => Arc::new(p),
-            Err(e) => {
-                eprintln!("Failed to create player: {}", e);
-                return None;
-            }
+    ) -> bool {
+        let video_info = if self.tiles.is_some() {
+            self.source_for_slot(index)
+        } else {
+            // Pick a video of the correct orientation not currently playing
+            let current_paths = self.current_paths(None, cx);
+            self.ready_videos
+                .pick_random_except_for_orientation(orientation, &current_paths)
+        };
+        let Some(video_info) = video_info else {
+            return false;
         };
 
         println!(
@@ -177,84 +783,336 @@ impl GridView {
                 .to_string_lossy()
         );
 
-        // Set up audio
-        let app_state = cx.global::<AppState>();
-        let mixer = Arc::clone(&app_state.mixer);
-        if let Some(audio_consumer) = player.audio_consumer() {
-            mixer.set_stream(index, Some(audio_consumer));
-        }
-
-        // Update AppState with the new player
-        cx.update_global::<AppState, _>(|state, _cx| {
-            state.set_player(index, Arc::clone(&player));
-        });
-
-        // Create the slot entity
-        let slot = cx.new(|cx| VideoSlot::new(player, video_info, index, cx));
-        cx.subscribe(&slot, Self::on_video_ended).detach();
-
-        Some(slot)
+        self.queue_slot(index, video_info, cx);
+        true
     }
 
     /**
-        Create a new slot using the current grid's orientation.
+        Get the paths of the videos playing or starting in every slot,
+        except for the given one.
     */
-    fn create_slot(&self, index: usize, cx: &mut Context<Self>) -> Option<Entity<VideoSlot>> {
-        self.create_slot_for_orientation(index, self.config.orientation, cx)
+    fn current_paths(&self, except: Option<usize>, cx: &Context<Self>) -> Vec<PathBuf> {
+        let playing = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| Some(*index) != except)
+            .filter_map(|(_, slot)| Some(slot.as_ref()?.read(cx).video_info().path.clone()));
+        playing.chain(self.startup.paths().cloned()).collect()
     }
 
     /**
-        Handle VideoEnded event from a slot - replace the video.
+        Start playing the given video in a slot, replacing its current video.
+
+        The player is created in the background once the slots ahead of it
+        in the startup order have started, and the slot is empty until then.
     */
-    fn on_video_ended(
-        &mut self,
-        slot: Entity<VideoSlot>,
-        _event: &VideoEnded,
-        cx: &mut Context<Self>,
-    ) {
-        let index = slot.read(cx).index();
-        self.replace_video(index, cx);
+    fn start_slot(&mut self, index: usize, video_info: VideoInfo, cx: &mut Context<Self>) {
+        self.queue_slot(index, video_info, cx);
+        self.start_queued(cx);
+        cx.notify();
     }
 
     /**
-        Replace the video at the given slot index with a new random video.
+        Stop the video in a slot and queue the given video to start in it,
+        without starting any queued players yet.
     */
-    fn replace_video(&mut self, index: usize, cx: &mut Context<Self>) {
-        if index >= self.slots.len() {
-            return;
+    fn queue_slot(&mut self, index: usize, video_info: VideoInfo, cx: &mut Context<Self>) {
+        if self.slots.len() <= index {
+            self.slots.resize_with(index + 1, || None);
         }
 
-        let orientation = self.config.orientation;
-
         // Stop the old player first to release file handles before opening new ones
-        self.slots[index].read(cx).player().stop();
+        if let Some(slot) = self.slots[index].take() {
+            slot.read(cx).player().stop();
+        }
+        cx.global::<AppState>().mixer.set_stream(index, None);
+        cx.update_global::<AppState, _>(|state, _cx| {
+            state.clear_player(index);
+        });
 
-        // Get paths of currently playing videos (excluding the one being replaced)
-        let current_paths: Vec<_> = self
-            .slots
-            .iter()
-            .enumerate()
-            .filter(|(i, _)| *i != index)
-            .map(|(_, slot)| slot.read(cx).video_info().path.clone())
-            .collect();
+        self.startup.push(index, video_info);
+    }
 
-        // Pick a video of the correct orientation not currently playing
-        let video_info = match self
-            .ready_videos
-            .pick_random_except_for_orientation(orientation, &current_paths)
-        {
-            Some(info) => info,
-            None => return, // No videos available for this orientation
-        };
+    /**
+        Create the players of queued slots in the background, the focused
+        and visible tiles first, while few enough are starting.
+    */
+    fn start_queued(&mut self, cx: &mut Context<Self>) {
+        let selection = cx.global::<AppState>().selection;
+        let count = self.slots.len();
+        let priority = |index| start_priority(index, selection, count);
+
+        while let Some((index, video_info, ticket)) = self.startup.next(&priority) {
+            let path = video_info.path.clone();
+            let create = cx
+                .background_executor()
+                .spawn(async move { VideoPlayer::with_options(&path, player_options(&path)) });
+
+            cx.spawn(async move |this, cx| {
+                let result = create.await;
+                this.update(cx, |grid, cx| {
+                    grid.finish_start(index, ticket, video_info, result, cx);
+                })
+                .ok();
+            })
+            .detach();
+        }
+    }
 
-        // Create new player
-        let new_player = match VideoPlayer::with_options(&video_info.path, None, None) {
+    /**
+        Put a player that finished starting in its slot, if the slot still
+        wants it, and move on to the next queued slot.
+    */
+    fn finish_start(
+        &mut self,
+        index: usize,
+        ticket: u64,
+        video_info: VideoInfo,
+        result: Result<VideoPlayer, DecoderError>,
+        cx: &mut Context<Self>,
+    ) {
+        let current = self.startup.finish(index, ticket) && index < self.slots.len();
+        self.start_queued(cx);
+
+        let player = match result {
             Ok(player) => Arc::new(player),
             Err(e) => {
                 eprintln!("Failed to create player for {:?}: {}", video_info.path, e);
                 return;
             }
         };
+        if !current {
+            // The slot was given another video, or removed, while this one started
+            player.stop();
+            return;
+        }
+
+        self.install_player(index, player, video_info, cx);
+    }
+
+    /**
+        Replace the video in a slot with a player that has already started,
+        so that the slot never goes empty in between.
+    */
+    fn swap_in_player(
+        &mut self,
+        index: usize,
+        player: Arc<VideoPlayer>,
+        video_info: VideoInfo,
+        cx: &mut Context<Self>,
+    ) {
+        if index >= self.slots.len() {
+            player.stop();
+            return;
+        }
+        self.record_history(index, cx);
+        self.startup.cancel(index);
+        if let Some(slot) = self.slots[index].take() {
+            slot.read(cx).player().stop();
+        }
+        self.install_player(index, player, video_info, cx);
+    }
+
+    /**
+        Put a started player in a slot: create the slot entity for it, and
+        hook it up to the mixer and AppState.
+    */
+    fn install_player(
+        &mut self,
+        index: usize,
+        player: Arc<VideoPlayer>,
+        video_info: VideoInfo,
+        cx: &mut Context<Self>,
+    ) {
+        // Set up audio
+        let app_state = cx.global::<AppState>();
+        let mixer = Arc::clone(&app_state.mixer);
+        mixer.set_stream(index, None);
+        if let Some(audio_consumer) = player.audio_consumer() {
+            mixer.set_stream(index, Some(audio_consumer));
+        }
+
+        // Update AppState with the new player
+        cx.update_global::<AppState, _>(|state, _cx| {
+            state.set_player(index, Arc::clone(&player));
+        });
+
+        // Offer to resume the video from where it was last left
+        let resume = cx
+            .global::<PlaybackHistory>()
+            .resume_position(&video_info.path);
+
+        // Create the slot entity and subscribe to its events
+        let slot = cx.new(|cx| {
+            let mut slot = VideoSlot::new(player, video_info, index, cx);
+            if let Some(position) = resume {
+                slot.offer_resume(position);
+            }
+            slot
+        });
+        cx.subscribe(&slot, Self::on_video_ended).detach();
+        cx.subscribe(&slot, Self::on_video_failed).detach();
+        cx.subscribe(&slot, Self::on_loop_restart).detach();
+        cx.subscribe(&slot, Self::on_playback_event).detach();
+
+        self.slots[index] = Some(slot);
+        cx.notify();
+    }
+
+    /**
+        Handle VideoEnded event from a slot - replace the video.
+    */
+    fn on_video_ended(
+        &mut self,
+        slot: Entity<VideoSlot>,
+        _event: &VideoEnded,
+        cx: &mut Context<Self>,
+    ) {
+        let index = slot.read(cx).index();
+        self.replace_video(index, cx);
+    }
+
+    /**
+        Handle VideoFailed event from a slot - keep the tile on its last frame
+        and show the error, until it is retried or replaced.
+    */
+    fn on_video_failed(
+        &mut self,
+        slot: Entity<VideoSlot>,
+        event: &VideoFailed,
+        cx: &mut Context<Self>,
+    ) {
+        let VideoFailed(error) = event;
+        let slot = slot.read(cx);
+        eprintln!(
+            "Slot {} failed playing {:?} ({}): {}",
+            slot.index(),
+            slot.video_info().path,
+            error.codec,
+            error.message
+        );
+        cx.notify();
+    }
+
+    /**
+        Handle LoopRestart event from a slot - seek back to the loop's in point.
+    */
+    fn on_loop_restart(
+        &mut self,
+        slot: Entity<VideoSlot>,
+        event: &LoopRestart,
+        cx: &mut Context<Self>,
+    ) {
+        let LoopRestart(start) = *event;
+        let slot = slot.read(cx);
+        let index = slot.index();
+        let player = Arc::clone(slot.player());
+
+        let mixer = Arc::clone(&cx.global::<AppState>().mixer);
+        match player.seek_to(start) {
+            Ok(Some(consumer)) => mixer.set_stream(index, Some(consumer)),
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("Failed to loop slot {}: {}", index, e);
+                player.set_loop_region(LoopRegion::default());
+            }
+        }
+    }
+
+    /**
+        Seek the video in the given slot to where it was last left.
+    */
+    fn resume_slot(&mut self, index: usize, cx: &mut Context<Self>) {
+        let Some(slot) = self.slot(index) else {
+            return;
+        };
+        let Some(position) = slot.update(cx, |slot, _cx| slot.take_resume_offer()) else {
+            return;
+        };
+        self.seek_slot(index, position, cx);
+    }
+
+    /**
+        Seek the video in the given slot, handing its new audio stream to the mixer.
+    */
+    fn seek_slot(&mut self, index: usize, position: Duration, cx: &mut Context<Self>) {
+        let Some(slot) = self.slot(index) else {
+            return;
+        };
+        let player = Arc::clone(slot.read(cx).player());
+
+        let mixer = Arc::clone(&cx.global::<AppState>().mixer);
+        match player.seek_to(position) {
+            Ok(Some(consumer)) => mixer.set_stream(index, Some(consumer)),
+            Ok(None) => {}
+            Err(e) => eprintln!("Failed to seek slot {}: {}", index, e),
+        }
+        cx.notify();
+    }
+
+    /**
+        Restart the video in the given slot from the beginning,
+        with a fresh player, after it failed.
+    */
+    fn retry_slot(&mut self, index: usize, cx: &mut Context<Self>) {
+        let Some(slot) = self.slot(index) else {
+            return;
+        };
+        let video_info = slot.read(cx).video_info().clone();
+        self.start_slot(index, video_info, cx);
+    }
+
+    /**
+        Handle buffering events from a slot - log stalls.
+    */
+    fn on_playback_event(
+        &mut self,
+        slot: Entity<VideoSlot>,
+        event: &PlaybackEvent,
+        cx: &mut Context<Self>,
+    ) {
+        let index = slot.read(cx).index();
+        match event {
+            PlaybackEvent::StallStarted => println!("Slot {} stalled, rebuffering", index),
+            PlaybackEvent::StallEnded(duration) => {
+                println!("Slot {} resumed after stalling for {:.1?}", index, duration)
+            }
+            PlaybackEvent::BufferFill(_) => {}
+        }
+    }
+
+    /**
+        Replace the video at the given slot index with a new random video,
+        or with the slot's source again if the grid has a fixed list of them.
+    */
+    fn replace_video(&mut self, index: usize, cx: &mut Context<Self>) {
+        if index >= self.slots.len() {
+            return;
+        }
+
+        let orientation = self.config.orientation;
+
+        self.record_history(index, cx);
+
+        let video_info = if self.tiles.is_some() {
+            match self.source_for_slot(index) {
+                Some(info) => info,
+                None => return,
+            }
+        } else {
+            // Get paths of currently playing videos (excluding the one being replaced)
+            let current_paths = self.current_paths(Some(index), cx);
+
+            // Pick a video of the correct orientation not currently playing
+            match self
+                .ready_videos
+                .pick_random_except_for_orientation(orientation, &current_paths)
+            {
+                Some(info) => info,
+                None => return, // No videos available for this orientation
+            }
+        };
 
         println!(
             "Slot {} ({:?}): replaced with {}",
@@ -267,36 +1125,104 @@ impl GridView {
                 .to_string_lossy()
         );
 
-        // Update mixer with new audio consumer
-        let app_state = cx.global::<AppState>();
-        let mixer = Arc::clone(&app_state.mixer);
-        mixer.set_stream(index, None); // Remove old stream
-        if let Some(audio_consumer) = new_player.audio_consumer() {
-            mixer.set_stream(index, Some(audio_consumer));
-        }
+        // Replace the slot
+        self.start_slot(index, video_info, cx);
+    }
 
-        // Update the player in AppState
-        cx.update_global::<AppState, _>(|state, _cx| {
-            state.set_player(index, Arc::clone(&new_player));
+    /**
+        Handle files dropped onto a slot, or onto an empty cell of the grid.
+
+        The files are probed in the background (folders are expanded), and the
+        first valid video starts playing at the drop target. In the random
+        wall, it's replaced by a random video once it ends, and any other
+        dropped videos join the pool. With a fixed list of sources, it replaces
+        the source of the slot instead, and the rest are added as new sources.
+    */
+    fn drop_paths(&mut self, index: usize, paths: Vec<PathBuf>, cx: &mut Context<Self>) {
+        let probe = cx.background_executor().spawn(async move {
+            let sources = VideoScanner::collect_sources(&paths);
+            VideoScanner::probe_sources(sources)
         });
 
-        // Create new slot entity and subscribe to its events
-        let new_slot = cx.new(|cx| VideoSlot::new(new_player, video_info, index, cx));
-        cx.subscribe(&new_slot, Self::on_video_ended).detach();
+        cx.spawn(async move |this, cx| {
+            let infos = probe.await;
+            this.update(cx, |grid, cx| grid.play_dropped(index, infos, cx))
+                .ok();
+        })
+        .detach();
+    }
 
-        // Replace the slot
-        self.slots[index] = new_slot;
-        cx.notify();
+    /**
+        Start playing probed videos that were dropped onto the grid.
+    */
+    fn play_dropped(&mut self, index: usize, infos: Vec<VideoInfo>, cx: &mut Context<Self>) {
+        let mut infos = infos.into_iter();
+        let Some(first) = infos.next() else {
+            eprintln!("Nothing playable was dropped onto slot {}", index);
+            return;
+        };
+        println!("Dropped onto slot {}: {}", index, first.path.display());
+
+        if let Some(tiles) = &mut self.tiles {
+            let assignment =
+                SourceAssignment::new(self.config.total_slots() as usize, tiles.sources.len());
+            let replaced = assignment.source_for_slot(index, tiles.tick);
+            match replaced {
+                Some(source) => tiles.sources[source] = first,
+                None => tiles.sources.push(first),
+            }
+            tiles.sources.extend(infos);
+
+            // New sources may need a different grid, which restarts every slot,
+            // or just fill empty cells of the current one
+            let config = self.config;
+            if let Some((width, height)) = self.size {
+                self.resize(width, height, cx);
+            }
+            if self.config == config && replaced.is_some() {
+                self.replace_video(index, cx);
+            }
+            self.fill_empty_slots(cx);
+            return;
+        }
+
+        for info in infos {
+            self.ready_videos.push(info);
+        }
+
+        if index < self.config.total_slots() as usize {
+            self.start_slot(index, first, cx);
+        }
+    }
+
+    /**
+        Make an element accept files dropped onto it, for the slot at the given index.
+    */
+    fn drop_target(&self, element: Div, index: usize, cx: &Context<Self>) -> Div {
+        element
+            .drag_over::<ExternalPaths>(|style, _, _, _| {
+                style.border_2().border_color(rgb(DROP_TARGET_COLOR))
+            })
+            .on_drop(
+                cx.listener(move |grid, paths: &ExternalPaths, _window, cx| {
+                    grid.drop_paths(index, paths.paths().to_vec(), cx);
+                }),
+            )
     }
 
     /**
         Skip all videos and load new ones.
+        With a fixed list of sources, this restarts them and rotates once.
     */
     pub fn skip_all(&mut self, cx: &mut Context<Self>) {
         if self.slots.is_empty() {
             return;
         }
 
+        if let Some(tiles) = &mut self.tiles {
+            tiles.tick += 1;
+        }
+
         // Clear audio streams
         let app_state = cx.global::<AppState>();
         let mixer = Arc::clone(&app_state.mixer);
@@ -305,12 +1231,16 @@ impl GridView {
         }
 
         // Explicitly stop all players before dropping them to ensure file handles are released
-        for slot in &self.slots {
-            slot.read(cx).player().stop();
+        for index in 0..self.slots.len() {
+            self.record_history(index, cx);
+            if let Some(slot) = self.slot(index) {
+                slot.read(cx).player().stop();
+            }
         }
 
         // Clear all slots
         self.slots.clear();
+        self.startup.clear();
         cx.update_global::<AppState, _>(|state, _cx| {
             state.truncate_players(0);
         });
@@ -324,7 +1254,7 @@ impl GridView {
         Pause all videos in the grid.
     */
     pub fn pause_all(&self, cx: &Context<Self>) {
-        for slot in &self.slots {
+        for slot in self.slots.iter().flatten() {
             slot.read(cx).pause();
         }
     }
@@ -333,39 +1263,453 @@ impl GridView {
         Resume all videos in the grid.
     */
     pub fn resume_all(&self, cx: &Context<Self>) {
-        for slot in &self.slots {
+        for slot in self.slots.iter().flatten() {
             slot.read(cx).resume();
         }
     }
 
     /**
-        Render a single slot at the given index.
+        Pause the players of tiles that are hidden behind the focused tile,
+        so that they stop decoding, and resume them once they are visible
+        again, skipping ahead by the time they were hidden.
+    */
+    fn update_occlusion(&mut self, cx: &mut Context<Self>) {
+        let app_state = cx.global::<AppState>();
+        let selection = app_state.selection;
+        let paused = app_state.paused;
+        let mixer = Arc::clone(&app_state.mixer);
+        let count = self.slots.len();
+
+        for index in 0..count {
+            let Some(slot) = self.slot(index) else {
+                continue;
+            };
+            let player = Arc::clone(slot.read(cx).player());
+
+            if selection.is_occluded(index, count) {
+                let tile = self.occluded.entry(index).or_insert_with(|| OccludedTile {
+                    player: Arc::clone(&player),
+                    since: Instant::now(),
+                });
+                // The slot may have been given a new video while hidden
+                if !Arc::ptr_eq(&tile.player, &player) {
+                    tile.player = Arc::clone(&player);
+                    tile.since = Instant::now();
+                }
+                // Also undoes resuming all videos while this one is hidden
+                player.pause();
+                continue;
+            }
+
+            let Some(tile) = self.occluded.remove(&index) else {
+                continue;
+            };
+            if !Arc::ptr_eq(&tile.player, &player) || paused {
+                continue;
+            }
+
+            let catch_up = tile.since.elapsed().min(OCCLUSION_CATCH_UP_LIMIT);
+            match player.seek_forward(catch_up) {
+                Ok(Some(consumer)) => mixer.set_stream(index, Some(consumer)),
+                Ok(None) => {}
+                Err(e) => eprintln!("Failed to catch up slot {}: {}", index, e),
+            }
+            player.resume();
+        }
+
+        self.occluded.retain(|&index, _| index < count);
+    }
+
+    /**
+        Mute and unmute the players as the audio policy says, for the tile
+        under the mouse and the selected tile.
+    */
+    fn apply_audio_policy(&self, cx: &Context<Self>) {
+        let policy = *cx.global::<AudioPolicy>();
+        let app_state = cx.global::<AppState>();
+        let hovered = app_state.hovered;
+        let selected = app_state.selection.selected;
+
+        for (index, slot) in self.slots.iter().enumerate() {
+            let Some(slot) = slot else {
+                continue;
+            };
+            let player = slot.read(cx).player();
+            match policy.is_audible(index, hovered, selected) {
+                Some(true) if player.is_muted() => player.unmute(),
+                Some(false) if !player.is_muted() => player.mute(),
+                _ => {}
+            }
+        }
+    }
+
+    /**
+        Remember the tile under the mouse, re-rendering when it changes.
+    */
+    fn hover_slot(&mut self, index: usize, cx: &mut Context<Self>) {
+        self.request_thumbnails(index, cx);
+        if cx.global::<AppState>().hovered == Some(index) {
+            return;
+        }
+        cx.global_mut::<AppState>().hovered = Some(index);
+        cx.notify();
+    }
+
+    /**
+        Remember the seek bar segment under the mouse, or forget it once
+        the mouse leaves it.
+    */
+    fn scrub_slot(&mut self, index: usize, segment: usize, hovered: bool, cx: &mut Context<Self>) {
+        let scrub = if hovered {
+            Some((index, segment))
+        } else if self.scrub == Some((index, segment)) {
+            None
+        } else {
+            self.scrub
+        };
+        if scrub != self.scrub {
+            self.scrub = scrub;
+            cx.notify();
+        }
+    }
+
+    /**
+        Start generating the seek bar thumbnails of the video in the given
+        slot in the background, unless they already exist.
+    */
+    fn request_thumbnails(&mut self, index: usize, cx: &mut Context<Self>) {
+        let Some(slot) = self.slot(index) else {
+            return;
+        };
+        let slot = slot.read(cx);
+        let info = slot.video_info().clone();
+        let duration = slot.player().duration();
+        if !info.path.is_file() || duration.is_zero() || self.thumbnails.contains_key(&info.path) {
+            return;
+        }
+        self.thumbnails.insert(info.path.clone(), None);
+
+        let generate = cx.background_executor().spawn(async move {
+            let result =
+                Thumbnails::load_or_generate(&info.path, duration, info.width, info.height);
+            (info.path, result)
+        });
+
+        cx.spawn(async move |this, cx| {
+            let (path, result) = generate.await;
+            this.update(cx, |grid, cx| match result {
+                Ok(thumbnails) => {
+                    grid.thumbnails.insert(path, Some(Arc::new(thumbnails)));
+                    cx.notify();
+                }
+                Err(e) => eprintln!("Failed to generate thumbnails for {:?}: {}", path, e),
+            })
+            .ok();
+        })
+        .detach();
+    }
+
+    /**
+        Forget the thumbnails of videos that are no longer shown,
+        releasing their images.
+    */
+    fn release_thumbnails(&mut self, window: &mut Window, cx: &Context<Self>) {
+        let shown: HashSet<PathBuf> = self
+            .slots
+            .iter()
+            .flatten()
+            .map(|slot| slot.read(cx).video_info().path.clone())
+            .collect();
+        self.thumbnails.retain(|path, thumbnails| {
+            if shown.contains(path) {
+                return true;
+            }
+            for image in thumbnails.iter().flat_map(|thumbnails| thumbnails.images()) {
+                let _ = window.drop_image(Arc::clone(image));
+            }
+            false
+        });
+    }
+
+    /**
+        Render a single slot at the given index, outlined if it's selected.
     */
-    fn render_slot(&self, index: usize, cx: &Context<Self>) -> impl IntoElement {
-        let slot = &self.slots[index];
+    fn render_slot(
+        &self,
+        index: usize,
+        slot: &Entity<VideoSlot>,
+        selected: bool,
+        cx: &Context<Self>,
+    ) -> impl IntoElement {
         let slot_data = slot.read(cx);
         let player = slot_data.player().clone();
         let aspect_ratio = slot_data.video_info().aspect_ratio();
+        let error = player.error();
+        let motion = self.motion_alerts.contains_key(&index);
+        let show_seek_bar = error.is_none() && cx.global::<AppState>().hovered == Some(index);
+        let resume_offer = slot_data.resume_offer();
+        let file_name = slot_data
+            .video_info()
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
         let id = ("video", index);
 
-        div()
+        let element = div()
             .flex_1()
+            .relative()
             .overflow_hidden()
+            .when(motion, |this| {
+                this.border_2().border_color(rgb(MOTION_COLOR))
+            })
+            .when(selected, |this| {
+                this.border_2().border_color(rgb(SELECTION_COLOR))
+            })
             .child(video_element(player, aspect_ratio, id))
+            .on_mouse_move(
+                cx.listener(move |grid, _event: &MouseMoveEvent, _window, cx| {
+                    grid.hover_slot(index, cx);
+                }),
+            )
+            .when(show_seek_bar, |this| {
+                this.child(self.render_seek_bar(index, slot_data, cx))
+            })
+            .when_some(resume_offer, |this, position| {
+                this.child(self.render_resume_offer(index, position, cx))
+            })
+            .when_some(error, |this, error| {
+                this.child(self.render_error(index, error, file_name, cx))
+            });
+        self.drop_target(element, index, cx)
+    }
+
+    /**
+        Render the seek bar along the bottom of a tile. Each segment seeks
+        to its middle when clicked, and shows a thumbnail of it when hovered.
+    */
+    fn render_seek_bar(
+        &self,
+        index: usize,
+        slot: &VideoSlot,
+        cx: &Context<Self>,
+    ) -> impl IntoElement {
+        let positions = sample_positions(slot.player().duration());
+        let count = positions.len();
+        let aspect_ratio = slot.video_info().aspect_ratio();
+        let thumbnails = self
+            .thumbnails
+            .get(&slot.video_info().path)
+            .cloned()
+            .flatten();
+        let scrubbed = self
+            .scrub
+            .filter(|(scrub_index, segment)| *scrub_index == index && *segment < count)
+            .map(|(_, segment)| segment);
+
+        let segments = positions.iter().enumerate().map(|(segment, &position)| {
+            div()
+                .id(("seek", index * count + segment))
+                .flex_1()
+                .h_full()
+                .cursor_pointer()
+                .when(scrubbed == Some(segment), |el| {
+                    el.bg(rgba(SEEK_BAR_HOVER_COLOR))
+                })
+                .on_hover(cx.listener(move |grid, hovered: &bool, _window, cx| {
+                    grid.scrub_slot(index, segment, *hovered, cx);
+                }))
+                .on_click(cx.listener(move |grid, _event, _window, cx| {
+                    grid.seek_slot(index, position, cx);
+                }))
+        });
+
+        // Anchored on the side of the segment closest to the middle, so it stays on the tile
+        let preview = scrubbed.map(|segment| {
+            let image = thumbnails
+                .as_ref()
+                .and_then(|thumbnails| thumbnails.nearest(positions[segment]));
+            let preview = div()
+                .absolute()
+                .bottom(px(SEEK_BAR_HEIGHT + 4.0))
+                .flex()
+                .flex_col()
+                .items_center()
+                .gap(px(2.0))
+                .p(px(2.0))
+                .bg(rgba(ERROR_OVERLAY_COLOR))
+                .rounded(px(4.0))
+                .when_some(image, |el, image| {
+                    el.child(
+                        img(image)
+                            .w(px(SCRUB_PREVIEW_WIDTH))
+                            .h(px(SCRUB_PREVIEW_WIDTH / aspect_ratio)),
+                    )
+                })
+                .child(
+                    div()
+                        .text_size(px(12.0))
+                        .text_color(rgb(0xffffff))
+                        .child(format_position(positions[segment])),
+                );
+            if segment < count / 2 {
+                preview.left(relative(segment as f32 / count as f32))
+            } else {
+                preview.right(relative((count - segment - 1) as f32 / count as f32))
+            }
+        });
+
+        div()
+            .absolute()
+            .left_0()
+            .right_0()
+            .bottom_0()
+            .h(px(SEEK_BAR_HEIGHT))
+            .flex()
+            .flex_row()
+            .bg(rgba(SEEK_BAR_COLOR))
+            .children(segments)
+            .children(preview)
+    }
+
+    /**
+        Render the button offering to resume a video from where it was last left.
+    */
+    fn render_resume_offer(
+        &self,
+        index: usize,
+        position: Duration,
+        cx: &Context<Self>,
+    ) -> impl IntoElement {
+        div()
+            .id(("resume", index))
+            .absolute()
+            .left(px(12.0))
+            .bottom(px(12.0))
+            .px(px(12.0))
+            .py(px(6.0))
+            .bg(rgba(ERROR_OVERLAY_COLOR))
+            .rounded(px(6.0))
+            .cursor_pointer()
+            .hover(|el| el.bg(rgb(0x2563eb)))
+            .child(
+                div()
+                    .text_size(px(13.0))
+                    .text_color(rgb(0xffffff))
+                    .child(format!("Resume from {}", format_position(position))),
+            )
+            .on_click(cx.listener(move |grid, _event, _window, cx| {
+                grid.resume_slot(index, cx);
+            }))
+    }
+
+    /**
+        Render the overlay for a failed video: what went wrong,
+        the codec details, and a button to try playing it again.
+    */
+    fn render_error(
+        &self,
+        index: usize,
+        error: PlaybackError,
+        file_name: String,
+        cx: &Context<Self>,
+    ) -> impl IntoElement {
+        div()
+            .absolute()
+            .inset_0()
+            .bg(rgba(ERROR_OVERLAY_COLOR))
+            .flex()
+            .flex_col()
+            .items_center()
+            .justify_center()
+            .gap(px(8.0))
+            .p(px(12.0))
+            .child(
+                div()
+                    .text_size(px(16.0))
+                    .text_color(rgb(0xffffff))
+                    .child("Playback failed"),
+            )
+            .child(
+                div()
+                    .text_size(px(13.0))
+                    .text_color(rgb(0xf87171))
+                    .child(error.message),
+            )
+            .child(
+                div()
+                    .text_size(px(12.0))
+                    .text_color(rgb(0x888888))
+                    .child(format!("{} - {}", file_name, error.codec)),
+            )
+            .child(
+                div()
+                    .id(("retry", index))
+                    .mt(px(4.0))
+                    .px(px(16.0))
+                    .py(px(6.0))
+                    .bg(rgb(0x3b82f6))
+                    .rounded(px(6.0))
+                    .cursor_pointer()
+                    .hover(|el| el.bg(rgb(0x2563eb)))
+                    .child(
+                        div()
+                            .text_size(px(13.0))
+                            .text_color(rgb(0xffffff))
+                            .child("Retry"),
+                    )
+                    .on_click(cx.listener(move |grid, _event, _window, cx| {
+                        grid.retry_slot(index, cx);
+                    })),
+            )
+    }
+}
+
+/**
+    Options for the player of a tile. Streams drop their oldest audio when
+    it backs up rather than stall their connection, local files can wait.
+*/
+fn player_options(path: &Path) -> PlayerOptions {
+    let local = matches!(
+        MediaSource::from_path(path),
+        Ok(MediaSource::File(_) | MediaSource::Image(_))
+    );
+    PlayerOptions {
+        audio_overflow: match local {
+            true => OverflowPolicy::Block,
+            false => OverflowPolicy::DropOldest,
+        },
+        ..PlayerOptions::default()
     }
 }
 
 impl Render for GridView {
-    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+    fn render(&mut self, window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
         // Try to fill empty slots if videos of the right orientation are available
-        let target_slots = self.config.total_slots() as usize;
         let orientation = self.config.orientation;
-        if self.slots.len() < target_slots
+        if self.tiles.is_none()
+            && self.has_empty_slots()
             && self.ready_videos.has_videos_for_orientation(orientation)
         {
             self.fill_empty_slots(cx);
         }
 
+        self.update_occlusion(cx);
+        self.apply_audio_policy(cx);
+        self.release_thumbnails(window, cx);
+        let selection = cx.global::<AppState>().selection;
+
+        // In focus mode, the focused slot takes up the whole grid
+        if let Some(index) = selection.focused_tile(self.slots.len()) {
+            return div()
+                .size_full()
+                .bg(rgb(0x000000))
+                .flex()
+                .when_some(self.slot(index), |this, slot| {
+                    this.child(self.render_slot(index, slot, false, cx))
+                });
+        }
+
         let cols = self.config.cols as usize;
         let rows = self.config.rows as usize;
 
@@ -376,11 +1720,16 @@ impl Render for GridView {
             let mut col_elements: Vec<_> = Vec::new();
             for col in 0..cols {
                 let index = row * cols + col;
-                if index < self.slots.len() {
-                    col_elements.push(self.render_slot(index, cx).into_any_element());
+                if let Some(slot) = self.slot(index) {
+                    let selected = selection.selected == Some(index);
+                    col_elements.push(
+                        self.render_slot(index, slot, selected, cx)
+                            .into_any_element(),
+                    );
                 } else {
-                    // Empty slot placeholder (black)
-                    col_elements.push(div().flex_1().bg(rgb(0x000000)).into_any_element());
+                    // Empty slot placeholder (black), also shown while the slot starts
+                    let placeholder = div().flex_1().bg(rgb(0x000000));
+                    col_elements.push(self.drop_target(placeholder, index, cx).into_any_element());
                 }
             }
 
@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use gpui::{Global, Keystroke};
+use serde::{Deserialize, Serialize};
+
+/**
+    Every command that can be bound to keys, with its default keystrokes.
+*/
+const DEFAULT_BINDINGS: &[(&str, &[&str])] = &[
+    ("toggle_pause", &["space"]),
+    ("toggle_mute", &["m"]),
+    ("volume_up", &["="]),
+    ("volume_down", &["-"]),
+    ("skip_all", &["enter"]),
+    ("align_audio", &["a"]),
+    ("cycle_audio_policy", &["p"]),
+    ("mark_in", &["i"]),
+    ("mark_out", &["o"]),
+    ("export_clip", &["e"]),
+    ("step_forward", &["."]),
+    ("step_back", &[","]),
+    ("slow_motion", &["s"]),
+    ("loop_in", &["["]),
+    ("loop_out", &["]"]),
+    ("clear_loop", &["\\"]),
+    ("select_left", &["left"]),
+    ("select_right", &["right"]),
+    ("select_up", &["up"]),
+    ("select_down", &["down"]),
+    ("select_tile_1", &["1"]),
+    ("select_tile_2", &["2"]),
+    ("select_tile_3", &["3"]),
+    ("select_tile_4", &["4"]),
+    ("select_tile_5", &["5"]),
+    ("select_tile_6", &["6"]),
+    ("select_tile_7", &["7"]),
+    ("select_tile_8", &["8"]),
+    ("select_tile_9", &["9"]),
+    ("toggle_focus", &["f"]),
+    ("clear_selection", &["escape"]),
+    ("quit", &["cmd-q"]),
+];
+
+/**
+    Keyboard bindings for all commands, by command name.
+
+    Starts out with the default bindings, which the keymap file can override
+    per command. The file is a JSON object from command names to lists of
+    keystrokes, such as `{ "toggle_focus": ["f", "shift-enter"] }`, and an
+    empty list leaves a command unbound.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Keymap {
+    bindings: BTreeMap<String, Vec<String>>,
+}
+
+impl Global for Keymap {}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings = DEFAULT_BINDINGS
+            .iter()
+            .map(|(command, keys)| {
+                let keys = keys.iter().map(ToString::to_string).collect();
+                (command.to_string(), keys)
+            })
+            .collect();
+        Self { bindings }
+    }
+}
+
+impl Keymap {
+    /**
+        Get the path to the keymap file.
+    */
+    pub fn file_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|p| p.join("vidwall").join("keymap.json"))
+    }
+
+    /**
+        Load the keymap, applying overrides from the keymap file if there is one.
+    */
+    pub fn load() -> Self {
+        let mut keymap = Self::default();
+
+        let Some(path) = Self::file_path() else {
+            return keymap;
+        };
+        let Ok(contents) = fs::read_to_string(&path) else {
+            return keymap;
+        };
+
+        match serde_json::from_str::<Keymap>(&contents) {
+            Ok(overrides) => keymap.apply(overrides),
+            Err(e) => eprintln!("Failed to parse keymap {}: {}", path.display(), e),
+        }
+        keymap
+    }
+
+    /**
+        Replace the bindings of every command in `overrides`, skipping
+        unknown commands and keystrokes that don't parse.
+    */
+    fn apply(&mut self, overrides: Keymap) {
+        for (command, keys) in overrides.bindings {
+            if !self.bindings.contains_key(&command) {
+                eprintln!("Unknown command in keymap: {}", command);
+                continue;
+            }
+
+            let keys = keys
+                .into_iter()
+                .filter(|keys| {
+                    let valid = is_valid_keystroke(keys);
+                    if !valid {
+                        eprintln!("Invalid keystroke for {} in keymap: {:?}", command, keys);
+                    }
+                    valid
+                })
+                .collect();
+            self.bindings.insert(command, keys);
+        }
+    }
+
+    /**
+        Get all bindings as (command, keystroke) pairs, in default binding order.
+    */
+    pub fn bindings(&self) -> Vec<(&str, &str)> {
+        DEFAULT_BINDINGS
+            .iter()
+            .filter_map(|(command, _)| self.bindings.get_key_value(*command))
+            .flat_map(|(command, keys)| keys.iter().map(move |k| (command.as_str(), k.as_str())))
+            .collect()
+    }
+}
+
+/**
+    Check that a keystroke, or space-separated sequence of them, parses.
+*/
+fn is_valid_keystroke(keys: &str) -> bool {
+    !keys.trim().is_empty() && keys.split_whitespace().all(|k| Keystroke::parse(k).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let keymap = Keymap::default();
+        let bindings = keymap.bindings();
+        assert_eq!(bindings.first(), Some(&("toggle_pause", "space")));
+        assert!(bindings.contains(&("select_tile_9", "9")));
+        assert!(bindings.contains(&("toggle_focus", "f")));
+    }
+
+    #[test]
+    fn test_overrides() {
+        let mut keymap = Keymap::default();
+        let overrides: Keymap = serde_json::from_str(
+            r#"{
+                "toggle_focus": ["shift-f", "enter"],
+                "toggle_mute": [],
+                "not_a_command": ["x"]
+            }"#,
+        )
+        .unwrap();
+        keymap.apply(overrides);
+
+        let bindings = keymap.bindings();
+        assert!(bindings.contains(&("toggle_focus", "shift-f")));
+        assert!(bindings.contains(&("toggle_focus", "enter")));
+        assert!(!bindings.contains(&("toggle_focus", "f")));
+        assert!(
+            !bindings
+                .iter()
+                .any(|(command, _)| *command == "toggle_mute")
+        );
+        assert!(
+            !bindings
+                .iter()
+                .any(|(command, _)| *command == "not_a_command")
+        );
+        // Commands without overrides keep their defaults
+        assert!(bindings.contains(&("toggle_pause", "space")));
+    }
+}
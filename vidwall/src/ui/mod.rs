@@ -1,14 +1,23 @@
 mod actions;
 mod app_state;
+mod audio_policy;
+mod clip_marks;
 mod grid_config;
 mod grid_view;
+mod keymap;
 mod root_view;
+mod selection;
+mod startup;
 mod video_element;
 mod video_slot;
+mod wall_layout;
 mod welcome_view;
 
 pub use actions::register_shortcuts;
-pub use app_state::AppState;
-pub use grid_config::{GridConfig, VideoOrientation};
+pub use app_state::{AppState, ScalingConfig};
+pub use audio_policy::AudioPolicy;
+pub use grid_config::{GridConfig, TileOptions, VideoOrientation};
 pub use grid_view::GridView;
+pub use keymap::Keymap;
 pub use root_view::RootView;
+pub use wall_layout::{BezelCompensation, WallLayout, WallRect};
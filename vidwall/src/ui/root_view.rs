@@ -5,15 +5,16 @@ use std::time::{Duration, Instant};
 
 use gpui::{
     Context, Entity, IntoElement, Pixels, PlatformDisplay, Point, Render, Size, Timer, Window, div,
-    prelude::*, rgb,
+    prelude::*, px, rgb,
 };
 
 use crate::video::ReadyVideos;
 use crate::window_state::WindowState;
 
 use super::app_state::AppState;
-use super::grid_config::GridConfig;
+use super::grid_config::TileOptions;
 use super::grid_view::GridView;
+use super::wall_layout::WallViewport;
 use super::welcome_view::{VideosSelected, WelcomeView};
 
 /**
@@ -50,6 +51,8 @@ pub struct RootView {
     last_size: Option<Size<Pixels>>,
     last_origin: Option<Point<Pixels>>,
     last_save_time: Option<Instant>,
+    /// Part of the wall shown in this window, when spanning several displays
+    span: Option<WallViewport>,
 }
 
 impl RootView {
@@ -67,6 +70,7 @@ impl RootView {
             last_size: None,
             last_origin: None,
             last_save_time: None,
+            span: None,
         }
     }
 
@@ -89,6 +93,63 @@ impl RootView {
             last_size: None,
             last_origin: None,
             last_save_time: None,
+            span: None,
+        }
+    }
+
+    /**
+        Create a new root view that shows a fixed list of sources, one per tile.
+    */
+    pub fn new_with_tiles(
+        ready_videos: Arc<ReadyVideos>,
+        sources: Vec<PathBuf>,
+        options: TileOptions,
+        cx: &mut Context<Self>,
+    ) -> Self {
+        let ready_videos_clone = Arc::clone(&ready_videos);
+        let grid = cx.new(|cx| GridView::new(ready_videos_clone, cx));
+        grid.update(cx, |grid, cx| grid.load_sources(sources, options, cx));
+
+        Self {
+            state: ViewState::Grid {
+                grid,
+                ready_videos,
+                last_video_count: 0,
+            },
+            last_size: None,
+            last_origin: None,
+            last_save_time: None,
+            span: None,
+        }
+    }
+
+    /**
+        Create a root view showing one display's part of a wall that spans
+        several displays. All windows of the wall share the same grid, which
+        is laid out for the whole wall, so only one of them should poll
+        for new videos.
+    */
+    pub fn new_spanning(
+        grid: Entity<GridView>,
+        ready_videos: Arc<ReadyVideos>,
+        viewport: WallViewport,
+        poll_videos: bool,
+        cx: &mut Context<Self>,
+    ) -> Self {
+        if poll_videos {
+            Self::start_video_polling(cx);
+        }
+
+        Self {
+            state: ViewState::Grid {
+                grid,
+                ready_videos,
+                last_video_count: 0,
+            },
+            last_size: None,
+            last_origin: None,
+            last_save_time: None,
+            span: Some(viewport),
         }
     }
 
@@ -212,12 +273,9 @@ impl RootView {
             return;
         };
 
-        // Calculate optimal grid for new size
-        let new_config = GridConfig::optimal_for_window(size.width.into(), size.height.into());
-
-        // Reconfigure grid if needed
+        // Reconfigure grid for the new size if needed
         grid.update(cx, |grid, cx| {
-            grid.reconfigure(new_config, cx);
+            grid.resize(size.width.into(), size.height.into(), cx);
         });
     }
 
@@ -264,8 +322,11 @@ impl Render for RootView {
         // Check if size changed (before updating last_size in save)
         let size_changed = self.last_size != Some(size);
 
-        // Save window state (common to both views)
-        self.maybe_save_window_state(display, origin, size);
+        // Save window state (common to both views). Windows of a spanning
+        // wall always cover their display, so there is nothing to restore.
+        if self.span.is_none() {
+            self.maybe_save_window_state(display, origin, size);
+        }
 
         match &self.state {
             ViewState::Welcome(welcome) => div()
@@ -274,8 +335,8 @@ impl Render for RootView {
                 .bg(rgb(0x111111))
                 .child(welcome.clone()),
             ViewState::Grid { grid, .. } => {
-                // Handle resize for grid
-                if size_changed {
+                // Handle resize for grid, unless it's laid out for a whole wall
+                if size_changed && self.span.is_none() {
                     self.handle_resize(size, cx);
                 }
 
@@ -288,11 +349,27 @@ impl Render for RootView {
                     });
                 }
 
+                // A spanning wall is drawn whole, shifted so that only this
+                // display's part of it ends up inside the window
+                let content = match self.span {
+                    Some(viewport) => div()
+                        .absolute()
+                        .left(px(-viewport.rect.x))
+                        .top(px(-viewport.rect.y))
+                        .w(px(viewport.wall_width))
+                        .h(px(viewport.wall_height))
+                        .child(grid.clone())
+                        .into_any_element(),
+                    None => grid.clone().into_any_element(),
+                };
+
                 div()
                     .id("root")
                     .size_full()
+                    .relative()
+                    .overflow_hidden()
                     .bg(rgb(0x000000))
-                    .child(grid.clone())
+                    .child(content)
             }
         }
     }
@@ -0,0 +1,133 @@
+/**
+    The tile selected with the keyboard, and whether it is shown in focus mode.
+
+    In focus mode, the selected tile fills the whole grid. Moving the selection
+    keeps focus mode on, switching to the newly selected tile.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TileSelection {
+    /// Index of the selected tile
+    pub selected: Option<usize>,
+    /// Whether the selected tile fills the grid
+    pub focused: bool,
+}
+
+impl TileSelection {
+    /**
+        Move the selection within a grid with `cols` columns and `count` tiles.
+        Selects the first tile if none was selected, and stops at the edges.
+    */
+    pub fn move_by(&mut self, dx: i32, dy: i32, cols: usize, count: usize) {
+        if count == 0 || cols == 0 {
+            return;
+        }
+        let Some(current) = self.selected else {
+            self.selected = Some(0);
+            return;
+        };
+
+        let rows = count.div_ceil(cols);
+        let col = (current % cols) as i32 + dx;
+        let row = (current / cols) as i32 + dy;
+        let col = col.clamp(0, cols as i32 - 1) as usize;
+        let row = row.clamp(0, rows as i32 - 1) as usize;
+
+        let target = row * cols + col;
+        if target < count {
+            self.selected = Some(target);
+        }
+    }
+
+    /**
+        Select the tile at the given index, if there is one.
+    */
+    pub fn select(&mut self, index: usize, count: usize) {
+        if index < count {
+            self.selected = Some(index);
+        }
+    }
+
+    /**
+        Toggle focus mode, selecting the first tile if none was selected.
+        Returns whether focus mode is now on.
+    */
+    pub fn toggle_focus(&mut self, count: usize) -> bool {
+        if self.selected.is_none() && count > 0 {
+            self.selected = Some(0);
+        }
+        self.focused = !self.focused && self.selected.is_some();
+        self.focused
+    }
+
+    /**
+        Clear the selection, leaving focus mode.
+    */
+    pub fn clear(&mut self) {
+        self.selected = None;
+        self.focused = false;
+    }
+
+    /**
+        Get the focused tile, if focus mode is on and it still exists.
+    */
+    pub fn focused_tile(&self, count: usize) -> Option<usize> {
+        self.selected.filter(|&index| self.focused && index < count)
+    }
+
+    /**
+        Check whether a tile is completely hidden behind the focused tile.
+    */
+    pub fn is_occluded(&self, index: usize, count: usize) -> bool {
+        self.focused_tile(count)
+            .is_some_and(|focused| focused != index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move_by() {
+        // 3x2 grid with 5 tiles
+        let mut selection = TileSelection::default();
+        selection.move_by(1, 0, 3, 5);
+        assert_eq!(selection.selected, Some(0));
+
+        selection.move_by(1, 0, 3, 5);
+        selection.move_by(1, 0, 3, 5);
+        selection.move_by(1, 0, 3, 5);
+        assert_eq!(selection.selected, Some(2));
+
+        // The tile below the last column does not exist
+        selection.move_by(0, 1, 3, 5);
+        assert_eq!(selection.selected, Some(2));
+
+        selection.move_by(-1, 1, 3, 5);
+        assert_eq!(selection.selected, Some(4));
+        selection.move_by(0, -5, 3, 5);
+        assert_eq!(selection.selected, Some(1));
+    }
+
+    #[test]
+    fn test_focus() {
+        let mut selection = TileSelection::default();
+        assert!(selection.toggle_focus(4));
+        assert_eq!(selection.focused_tile(4), Some(0));
+
+        selection.select(3, 4);
+        assert_eq!(selection.focused_tile(4), Some(3));
+        assert!(selection.is_occluded(0, 4));
+        assert!(!selection.is_occluded(3, 4));
+        // Tiles beyond a shrunk grid are not focused
+        assert_eq!(selection.focused_tile(2), None);
+
+        assert!(!selection.toggle_focus(4));
+        assert_eq!(selection.focused_tile(4), None);
+        assert!(!selection.is_occluded(0, 4));
+
+        // Nothing to focus in an empty grid
+        let mut selection = TileSelection::default();
+        assert!(!selection.toggle_focus(0));
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::video::VideoInfo;
+
+use super::selection::TileSelection;
+
+/**
+    Most players opened at once. Each one probes its file and spawns its
+    demux and decode threads, so filling a large grid all at once stalls
+    the app while they compete for the disk and CPU.
+*/
+pub const MAX_CONCURRENT_STARTS: usize = 3;
+
+/**
+    Slots waiting for their players to start, started a few at a time.
+
+    Each start gets a ticket, and only the result of the slot's latest
+    ticket is used - players that finish starting after their slot was
+    given another video, or removed, are thrown away.
+*/
+#[derive(Debug)]
+pub struct StartupQueue {
+    limit: usize,
+    next_ticket: u64,
+    pending: Vec<(usize, VideoInfo)>,
+    starting: HashMap<usize, (u64, PathBuf)>,
+}
+
+impl StartupQueue {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit: limit.max(1),
+            next_ticket: 0,
+            pending: Vec::new(),
+            starting: HashMap::new(),
+        }
+    }
+
+    /**
+        Queue a video to start in the given slot, replacing whatever
+        was queued or starting for it.
+    */
+    pub fn push(&mut self, index: usize, info: VideoInfo) {
+        self.cancel(index);
+        self.pending.push((index, info));
+    }
+
+    /**
+        Take the next slot to start, if fewer than the limit are starting.
+        Slots with the lowest priority value go first.
+    */
+    pub fn next<K: Ord>(
+        &mut self,
+        priority: impl Fn(usize) -> K,
+    ) -> Option<(usize, VideoInfo, u64)> {
+        if self.starting.len() >= self.limit {
+            return None;
+        }
+        let position = self
+            .pending
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, (index, _))| priority(*index))
+            .map(|(position, _)| position)?;
+        let (index, info) = self.pending.remove(position);
+
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        self.starting.insert(index, (ticket, info.path.clone()));
+        Some((index, info, ticket))
+    }
+
+    /**
+        Mark a start as done. Returns whether its result should be used.
+    */
+    pub fn finish(&mut self, index: usize, ticket: u64) -> bool {
+        match self.starting.get(&index) {
+            Some((current, _)) if *current == ticket => {
+                self.starting.remove(&index);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /**
+        Forget the queued or starting video of a slot.
+    */
+    pub fn cancel(&mut self, index: usize) {
+        self.pending.retain(|(pending, _)| *pending != index);
+        self.starting.remove(&index);
+    }
+
+    /**
+        Forget every queued and starting video.
+    */
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.starting.clear();
+    }
+
+    /**
+        Check if a slot has a video queued or starting.
+    */
+    pub fn contains(&self, index: usize) -> bool {
+        self.starting.contains_key(&index) || self.pending.iter().any(|(i, _)| *i == index)
+    }
+
+    /**
+        Get the paths of every queued and starting video.
+    */
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        let pending = self.pending.iter().map(|(_, info)| &info.path);
+        pending.chain(self.starting.values().map(|(_, path)| path))
+    }
+}
+
+/**
+    Get the order a slot starts in: the focused tile first, then the
+    selected one, then the visible ones and finally those hidden behind
+    the focused tile, each in grid order.
+*/
+pub fn start_priority(index: usize, selection: TileSelection, count: usize) -> (u8, usize) {
+    let rank = if selection.focused_tile(count) == Some(index) {
+        0
+    } else if selection.selected == Some(index) {
+        1
+    } else if !selection.is_occluded(index, count) {
+        2
+    } else {
+        3
+    };
+    (rank, index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(name: &str) -> VideoInfo {
+        VideoInfo::new(PathBuf::from(name), 1920, 1080, None)
+    }
+
+    #[test]
+    fn test_start_in_priority_order() {
+        let mut queue = StartupQueue::new(2);
+        for index in 0..4 {
+            queue.push(index, info(&format!("{}.mp4", index)));
+        }
+        let selection = TileSelection {
+            selected: Some(2),
+            focused: true,
+        };
+        let priority = |index| start_priority(index, selection, 4);
+
+        let (first, _, first_ticket) = queue.next(priority).unwrap();
+        let (second, _, _) = queue.next(priority).unwrap();
+        assert_eq!((first, second), (2, 0));
+
+        // At the limit until one of them finishes
+        assert!(queue.next(priority).is_none());
+        assert!(queue.finish(first, first_ticket));
+        assert_eq!(queue.next(priority).map(|(index, _, _)| index), Some(1));
+    }
+
+    #[test]
+    fn test_replaced_start_is_discarded() {
+        let mut queue = StartupQueue::new(2);
+        queue.push(0, info("a.mp4"));
+        let (index, _, ticket) = queue.next(|index| index).unwrap();
+
+        queue.push(0, info("b.mp4"));
+        assert!(!queue.finish(index, ticket));
+        assert!(queue.contains(0));
+
+        let (_, info, ticket) = queue.next(|index| index).unwrap();
+        assert_eq!(info.path, PathBuf::from("b.mp4"));
+        assert_eq!(queue.paths().count(), 1);
+        assert!(queue.finish(0, ticket));
+        assert!(!queue.contains(0));
+    }
+}
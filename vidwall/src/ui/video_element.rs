@@ -1,5 +1,7 @@
+use std::f32::consts::TAU;
 use std::panic::Location;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use gpui::{
     Bounds, Corners, ElementId, GlobalElementId, InspectorElementId, LayoutId, Pixels, Point,
@@ -8,6 +10,20 @@ use gpui::{
 
 use crate::playback::VideoPlayer;
 
+use super::app_state::ScalingConfig;
+
+/**
+    Number of dots in the buffering spinner, and how long one turn takes
+*/
+const SPINNER_DOTS: usize = 8;
+const SPINNER_PERIOD_MS: u128 = 800;
+
+/**
+    Decoded frame sizes are rounded up to a multiple of this, so that
+    small layout changes don't recreate the decoder's scaler
+*/
+const OUTPUT_SIZE_STEP: u32 = 16;
+
 /**
     A video element that renders frames from a VideoPlayer with crop-to-fill scaling.
 */
@@ -137,11 +153,22 @@ impl Element for VideoElement {
         &mut self,
         _id: Option<&GlobalElementId>,
         _inspector_id: Option<&InspectorElementId>,
-        _bounds: Bounds<Pixels>,
+        bounds: Bounds<Pixels>,
         _request_layout: &mut Self::RequestLayoutState,
-        _window: &mut Window,
-        _cx: &mut gpui::App,
+        window: &mut Window,
+        cx: &mut gpui::App,
     ) -> Self::PrepaintState {
+        // Decode at the size the frame is drawn at, in device pixels
+        let fill_bounds = self.calculate_fill_bounds(bounds);
+        let scale = window.scale_factor();
+        let step = |size: Pixels| {
+            let device = (f32::from(size) * scale).max(1.0) as u32;
+            device.div_ceil(OUTPUT_SIZE_STEP) * OUTPUT_SIZE_STEP
+        };
+        let (width, height) = (step(fill_bounds.size.width), step(fill_bounds.size.height));
+        let algorithm = cx.global::<ScalingConfig>().algorithm_for(height);
+        self.player.set_output_size(width, height, algorithm);
+
         // Get the cached RenderImage from the player
         // This only creates a new RenderImage when the frame actually changes
         self.player.get_render_image()
@@ -181,11 +208,60 @@ impl Element for VideoElement {
             window.paint_quad(fill(bounds, gpui::rgb(0x000000)));
         }
 
+        if self.player.is_buffering() {
+            paint_spinner(bounds, window);
+        }
+
         // Request continuous animation for video playback
         window.request_animation_frame();
     }
 }
 
+/**
+    Dim the cell and paint a ring of fading dots in its center,
+    shown while the player is stalled waiting for frames.
+*/
+fn paint_spinner(bounds: Bounds<Pixels>, window: &mut Window) {
+    window.paint_quad(fill(bounds, gpui::rgba(0x00000080)));
+
+    let width: f32 = bounds.size.width.into();
+    let height: f32 = bounds.size.height.into();
+    let center_x = f32::from(bounds.origin.x) + width / 2.0;
+    let center_y = f32::from(bounds.origin.y) + height / 2.0;
+    let radius = (width.min(height) / 12.0).clamp(8.0, 32.0);
+    let dot_size = (radius / 3.0).round();
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let head = ((millis % SPINNER_PERIOD_MS) * SPINNER_DOTS as u128 / SPINNER_PERIOD_MS) as usize;
+
+    for dot in 0..SPINNER_DOTS {
+        let angle = TAU * dot as f32 / SPINNER_DOTS as f32;
+        let x = center_x + radius * angle.cos() - dot_size / 2.0;
+        let y = center_y + radius * angle.sin() - dot_size / 2.0;
+        // Dots fade out behind the head of the spinner
+        let age = (head + SPINNER_DOTS - dot) % SPINNER_DOTS;
+        let alpha = 1.0 - age as f32 / SPINNER_DOTS as f32;
+
+        let dot_bounds = Bounds {
+            origin: Point {
+                x: px(x.round()),
+                y: px(y.round()),
+            },
+            size: Size {
+                width: px(dot_size),
+                height: px(dot_size),
+            },
+        };
+        window.paint_quad(
+            fill(dot_bounds, gpui::hsla(0.0, 0.0, 1.0, alpha))
+                .corner_radii(Corners::all(px(dot_size / 2.0))),
+        );
+    }
+}
+
 /**
     Helper function to create a video element
 */
@@ -1,25 +1,44 @@
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use gpui::{AsyncApp, Context, EventEmitter};
 
-use crate::playback::VideoPlayer;
+use crate::playback::{PlaybackError, PlaybackEvent, PlaybackState, VideoPlayer};
 use crate::video::VideoInfo;
 
 /**
-    Interval for checking if a video has ended
+    Interval for checking if a video has ended or is buffering
 */
 const MONITOR_INTERVAL: Duration = Duration::from_millis(100);
 
+/**
+    How long a slot offers to resume its video from where it was last left
+*/
+const RESUME_OFFER_TIMEOUT: Duration = Duration::from_secs(8);
+
 /**
     Event emitted when a video slot's video has finished playing.
 */
 pub struct VideoEnded;
 
+/**
+    Event emitted when a video slot's player has stopped on an error.
+*/
+pub struct VideoFailed(pub PlaybackError);
+
+/**
+    Event emitted when a video slot's player reached the out point of its
+    A/B loop, with the in point to seek back to.
+*/
+pub struct LoopRestart(pub Duration);
+
 /**
     A video slot entity that owns a video player and emits events.
 
-    Each slot monitors its player and emits `VideoEnded` when playback completes.
+    Each slot monitors its player and emits `VideoEnded` when playback completes,
+    `VideoFailed` when it stops on an error, `LoopRestart` when it reaches the
+    end of its A/B loop, and the player's `PlaybackEvent`s as it stalls and
+    rebuffers.
     This allows the parent GridView to subscribe and handle video replacement.
 */
 pub struct VideoSlot {
@@ -29,9 +48,14 @@ pub struct VideoSlot {
     video_info: VideoInfo,
     /// Index of this slot in the grid
     index: usize,
+    /// Position the video was last left at, and when the offer to resume expires
+    resume_offer: Option<(Duration, Instant)>,
 }
 
 impl EventEmitter<VideoEnded> for VideoSlot {}
+impl EventEmitter<VideoFailed> for VideoSlot {}
+impl EventEmitter<LoopRestart> for VideoSlot {}
+impl EventEmitter<PlaybackEvent> for VideoSlot {}
 
 impl VideoSlot {
     /**
@@ -49,6 +73,7 @@ impl VideoSlot {
             player,
             video_info,
             index,
+            resume_offer: None,
         };
         slot.start_monitor(cx);
         slot
@@ -75,6 +100,32 @@ impl VideoSlot {
         self.index
     }
 
+    /**
+        Offer to resume the video from the given position for a while.
+    */
+    pub fn offer_resume(&mut self, position: Duration) {
+        self.resume_offer = Some((position, Instant::now() + RESUME_OFFER_TIMEOUT));
+    }
+
+    /**
+        Get the position this slot offers to resume its video from, if the
+        offer hasn't expired or been taken.
+    */
+    pub fn resume_offer(&self) -> Option<Duration> {
+        self.resume_offer
+            .filter(|(_, expires)| Instant::now() < *expires)
+            .map(|(position, _)| position)
+    }
+
+    /**
+        Take the offer to resume, so that it isn't shown again.
+    */
+    pub fn take_resume_offer(&mut self) -> Option<Duration> {
+        let position = self.resume_offer();
+        self.resume_offer = None;
+        position
+    }
+
     /**
         Pause this slot's video playback.
     */
@@ -104,7 +155,7 @@ impl VideoSlot {
     }
 
     /**
-        Start the background task that monitors for video end.
+        Start the background task that monitors for video end, errors and buffering.
     */
     fn start_monitor(&self, cx: &mut Context<Self>) {
         // Clone the player for the async task to check
@@ -115,6 +166,40 @@ impl VideoSlot {
                 // Wait for the monitoring interval
                 cx.background_executor().timer(MONITOR_INTERVAL).await;
 
+                // Forward buffering events
+                let events = player.take_events();
+                if !events.is_empty() {
+                    let result = this.update(cx, |_slot, cx: &mut Context<VideoSlot>| {
+                        for event in events {
+                            cx.emit(event);
+                        }
+                    });
+                    if result.is_err() {
+                        break; // Entity was dropped
+                    }
+                }
+
+                // Check if the video failed, which ends it just the same
+                if player.state() == PlaybackState::Error
+                    && let Some(error) = player.error()
+                {
+                    let _ = this.update(cx, |_slot, cx: &mut Context<VideoSlot>| {
+                        cx.emit(VideoFailed(error));
+                    });
+                    break;
+                }
+
+                // Go back to the in point of an A/B loop, instead of ending
+                if let Some(start) = player.loop_restart() {
+                    let result = this.update(cx, |_slot, cx: &mut Context<VideoSlot>| {
+                        cx.emit(LoopRestart(start));
+                    });
+                    if result.is_err() {
+                        break; // Entity was dropped
+                    }
+                    continue;
+                }
+
                 // Check if video has ended
                 if player.is_ended() {
                     // Try to emit the event back on the main thread
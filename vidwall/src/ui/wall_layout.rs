@@ -0,0 +1,195 @@
+/**
+    A rectangle in pixels.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct WallRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl WallRect {
+    /**
+        Create a new rectangle.
+    */
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn right(&self) -> f32 {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> f32 {
+        self.y + self.height
+    }
+}
+
+/**
+    Size of the bezels between adjacent displays, in pixels.
+
+    The wall leaves this much room between displays, as if the bezels were
+    part of the picture, so that a video spanning two displays lines up across
+    the gap instead of being squashed together at the seam.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BezelCompensation {
+    /// Gap between displays that are side by side
+    pub horizontal: f32,
+    /// Gap between displays that are stacked
+    pub vertical: f32,
+}
+
+/**
+    The part of a wall shown on one display.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WallViewport {
+    /// Area of the wall covered by the display
+    pub rect: WallRect,
+    /// Width of the whole wall
+    pub wall_width: f32,
+    /// Height of the whole wall
+    pub wall_height: f32,
+}
+
+/**
+    A video wall spanning several displays, laid out as one large canvas.
+
+    Displays keep their arrangement from the OS, shifted apart by the bezel
+    gaps, and the wall is the smallest area containing all of them.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct WallLayout {
+    /// Width of the whole wall, including bezels
+    pub width: f32,
+    /// Height of the whole wall, including bezels
+    pub height: f32,
+    /// Area of the wall covered by each display, in the order they were given
+    pub viewports: Vec<WallRect>,
+}
+
+impl WallLayout {
+    /**
+        Lay out a wall from the bounds of its displays, in OS coordinates.
+    */
+    pub fn new(displays: &[WallRect], bezels: BezelCompensation) -> Self {
+        // Every distinct display edge to the left of (or above) a display adds one bezel
+        let gaps_before = |edges: &[f32], position: f32| {
+            let mut edges: Vec<f32> = edges.iter().copied().filter(|e| *e <= position).collect();
+            edges.sort_by(f32::total_cmp);
+            edges.dedup();
+            edges.len() as f32
+        };
+        let rights: Vec<f32> = displays.iter().map(WallRect::right).collect();
+        let bottoms: Vec<f32> = displays.iter().map(WallRect::bottom).collect();
+
+        let mut viewports: Vec<WallRect> = displays
+            .iter()
+            .map(|display| {
+                WallRect::new(
+                    display.x + bezels.horizontal * gaps_before(&rights, display.x),
+                    display.y + bezels.vertical * gaps_before(&bottoms, display.y),
+                    display.width,
+                    display.height,
+                )
+            })
+            .collect();
+
+        // Move the wall's top left corner to the origin
+        let min_x = viewports.iter().map(|v| v.x).fold(f32::MAX, f32::min);
+        let min_y = viewports.iter().map(|v| v.y).fold(f32::MAX, f32::min);
+        for viewport in &mut viewports {
+            viewport.x -= min_x;
+            viewport.y -= min_y;
+        }
+
+        let width = viewports.iter().map(WallRect::right).fold(0.0, f32::max);
+        let height = viewports.iter().map(WallRect::bottom).fold(0.0, f32::max);
+
+        Self {
+            width,
+            height,
+            viewports,
+        }
+    }
+
+    /**
+        Get the part of the wall shown on the display at the given index.
+    */
+    pub fn viewport(&self, index: usize) -> WallViewport {
+        WallViewport {
+            rect: self.viewports[index],
+            wall_width: self.width,
+            wall_height: self.height,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_side_by_side() {
+        let displays = [
+            WallRect::new(0.0, 0.0, 1920.0, 1080.0),
+            WallRect::new(1920.0, 0.0, 1920.0, 1080.0),
+        ];
+        let bezels = BezelCompensation {
+            horizontal: 40.0,
+            vertical: 0.0,
+        };
+        let layout = WallLayout::new(&displays, bezels);
+        assert_eq!(layout.width, 3880.0);
+        assert_eq!(layout.height, 1080.0);
+        assert_eq!(layout.viewports[0], displays[0]);
+        assert_eq!(
+            layout.viewports[1],
+            WallRect::new(1960.0, 0.0, 1920.0, 1080.0)
+        );
+    }
+
+    #[test]
+    fn test_two_by_two() {
+        let displays = [
+            WallRect::new(0.0, 0.0, 1920.0, 1080.0),
+            WallRect::new(1920.0, 0.0, 1920.0, 1080.0),
+            WallRect::new(0.0, 1080.0, 1920.0, 1080.0),
+            WallRect::new(1920.0, 1080.0, 1920.0, 1080.0),
+        ];
+        let bezels = BezelCompensation {
+            horizontal: 40.0,
+            vertical: 30.0,
+        };
+        let layout = WallLayout::new(&displays, bezels);
+        assert_eq!((layout.width, layout.height), (3880.0, 2190.0));
+        assert_eq!(
+            layout.viewports[3],
+            WallRect::new(1960.0, 1110.0, 1920.0, 1080.0)
+        );
+    }
+
+    #[test]
+    fn test_negative_origin() {
+        // A display left of the primary one has a negative position
+        let displays = [
+            WallRect::new(0.0, 0.0, 1920.0, 1080.0),
+            WallRect::new(-1920.0, 0.0, 1920.0, 1080.0),
+        ];
+        let bezels = BezelCompensation {
+            horizontal: 40.0,
+            vertical: 0.0,
+        };
+        let layout = WallLayout::new(&displays, bezels);
+        assert_eq!(layout.viewports[1].x, 0.0);
+        assert_eq!(layout.viewports[0].x, 1960.0);
+        assert_eq!(layout.width, 3880.0);
+    }
+}
@@ -16,6 +16,10 @@ pub struct VideoInfo {
     pub height: u32,
     /// Video duration (if available)
     pub duration: Option<Duration>,
+    /// Codec of the video stream, as named by FFmpeg (if known)
+    pub video_codec: Option<String>,
+    /// Codec of the first audio stream, as named by FFmpeg (if any)
+    pub audio_codec: Option<String>,
 }
 
 impl VideoInfo {
@@ -28,6 +32,8 @@ impl VideoInfo {
             width,
             height,
             duration,
+            video_codec: None,
+            audio_codec: None,
         }
     }
 
@@ -0,0 +1,219 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::VideoInfo;
+
+/**
+    Size and modification time of a file, which change whenever it is edited or replaced.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub size: u64,
+    /// Milliseconds since the Unix epoch
+    pub modified: u64,
+}
+
+impl FileStamp {
+    /**
+        Get the stamp of a file, if it exists and has a modification time.
+    */
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            size: metadata.len(),
+            modified: modified.as_millis() as u64,
+        })
+    }
+}
+
+/**
+    Metadata of an indexed video, as found by probing it.
+*/
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct IndexedVideo {
+    width: u32,
+    height: u32,
+    duration: Option<Duration>,
+    video_codec: Option<String>,
+    audio_codec: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct LibraryEntry {
+    stamp: FileStamp,
+    /// None if the file is not a playable video, so that it isn't probed again either
+    video: Option<IndexedVideo>,
+}
+
+/**
+    What the library knows about a file.
+*/
+#[derive(Debug)]
+pub enum Lookup {
+    /// A video, unchanged since it was indexed
+    Video(VideoInfo),
+    /// Not a playable video, unchanged since it was indexed
+    Invalid,
+    /// Not indexed yet, or changed since
+    Unknown,
+}
+
+/**
+    Index of the video files found in scanned folders and what probing them
+    found, so that a file is only probed again after it changes.
+
+    Stored as a JSON file in the data directory. Thumbnails of the files are
+    cached separately, by the thumbnail cache.
+*/
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MediaLibrary {
+    entries: HashMap<PathBuf, LibraryEntry>,
+}
+
+impl MediaLibrary {
+    /**
+        Get the path to the library file.
+    */
+    pub fn file_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|p| p.join("vidwall").join("library.json"))
+    }
+
+    /**
+        Load the library from disk, starting out empty if there is none.
+    */
+    pub fn load() -> Self {
+        let Some(path) = Self::file_path() else {
+            return Self::default();
+        };
+        let Ok(contents) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            eprintln!("Failed to parse library {}: {}", path.display(), e);
+            Self::default()
+        })
+    }
+
+    /**
+        Save the library to disk.
+    */
+    pub fn save(&self) {
+        let Some(path) = Self::file_path() else {
+            return;
+        };
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if let Ok(json) = serde_json::to_string(self) {
+            let _ = fs::write(path, json);
+        }
+    }
+
+    /**
+        Look up a file, as long as it still has the stamp it was indexed with.
+    */
+    pub fn lookup(&self, path: &Path, stamp: FileStamp) -> Lookup {
+        let Some(entry) = self.entries.get(path).filter(|e| e.stamp == stamp) else {
+            return Lookup::Unknown;
+        };
+        match entry.video {
+            Some(ref video) => {
+                let mut info = VideoInfo::new(
+                    path.to_path_buf(),
+                    video.width,
+                    video.height,
+                    video.duration,
+                );
+                info.video_codec = video.video_codec.clone();
+                info.audio_codec = video.audio_codec.clone();
+                Lookup::Video(info)
+            }
+            None => Lookup::Invalid,
+        }
+    }
+
+    /**
+        Record what probing a file found, None if it is not a playable video.
+    */
+    pub fn record(&mut self, path: &Path, stamp: FileStamp, info: Option<&VideoInfo>) {
+        let video = info.map(|info| IndexedVideo {
+            width: info.width,
+            height: info.height,
+            duration: info.duration,
+            video_codec: info.video_codec.clone(),
+            audio_codec: info.audio_codec.clone(),
+        });
+        self.entries
+            .insert(path.to_path_buf(), LibraryEntry { stamp, video });
+    }
+
+    /**
+        Forget the files within the scanned paths that weren't found again,
+        returning how many were forgotten. Files elsewhere are kept, for
+        when their folders are scanned again.
+    */
+    pub fn prune(&mut self, scanned: &[PathBuf], found: &HashSet<PathBuf>) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|path, _| {
+            found.contains(path) || !scanned.iter().any(|root| path.starts_with(root))
+        });
+        before - self.entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STAMP: FileStamp = FileStamp {
+        size: 1024,
+        modified: 1_700_000_000_000,
+    };
+
+    #[test]
+    fn test_lookup_requires_same_stamp() {
+        let mut library = MediaLibrary::default();
+        let path = Path::new("/videos/a.mp4");
+        assert!(matches!(library.lookup(path, STAMP), Lookup::Unknown));
+
+        let mut info = VideoInfo::new(path.to_path_buf(), 1920, 1080, None);
+        info.video_codec = Some("h264".to_string());
+        library.record(path, STAMP, Some(&info));
+        match library.lookup(path, STAMP) {
+            Lookup::Video(found) => {
+                assert_eq!((found.width, found.height), (1920, 1080));
+                assert_eq!(found.video_codec.as_deref(), Some("h264"));
+            }
+            other => panic!("expected a video, got {:?}", other),
+        }
+
+        // Edited since
+        let edited = FileStamp {
+            size: 2048,
+            ..STAMP
+        };
+        assert!(matches!(library.lookup(path, edited), Lookup::Unknown));
+
+        library.record(path, edited, None);
+        assert!(matches!(library.lookup(path, edited), Lookup::Invalid));
+    }
+
+    #[test]
+    fn test_prune_only_scanned_paths() {
+        let mut library = MediaLibrary::default();
+        for path in ["/videos/a.mp4", "/videos/b.mp4", "/movies/c.mkv"] {
+            library.record(Path::new(path), STAMP, None);
+        }
+
+        let found = HashSet::from([PathBuf::from("/videos/a.mp4")]);
+        assert_eq!(library.prune(&[PathBuf::from("/videos")], &found), 1);
+        assert!(library.entries.contains_key(Path::new("/videos/a.mp4")));
+        assert!(!library.entries.contains_key(Path::new("/videos/b.mp4")));
+        assert!(library.entries.contains_key(Path::new("/movies/c.mkv")));
+    }
+}
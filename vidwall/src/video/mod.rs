@@ -1,9 +1,13 @@
 mod info;
+mod library;
 mod probe;
 mod ready_videos;
 mod scanner;
+mod source;
 
 pub use info::VideoInfo;
+pub use library::MediaLibrary;
 pub use probe::probe_video;
 pub use ready_videos::ReadyVideos;
 pub use scanner::VideoScanner;
+pub use source::{MediaSource, vidproxy_url};
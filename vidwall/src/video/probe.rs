@@ -34,6 +34,16 @@ impl std::fmt::Display for ProbeError {
 
 impl std::error::Error for ProbeError {}
 
+impl ProbeError {
+    /**
+        Check if the error is about the file itself, rather than ffprobe,
+        so probing it again won't succeed unless the file changes.
+    */
+    pub fn is_invalid_file(&self) -> bool {
+        !matches!(self, ProbeError::ExecutionFailed(_))
+    }
+}
+
 /**
     JSON structure for ffprobe stream output
 */
@@ -45,6 +55,7 @@ struct FfprobeOutput {
 #[derive(Debug, Deserialize)]
 struct FfprobeStream {
     codec_type: String,
+    codec_name: Option<String>,
     width: Option<u32>,
     height: Option<u32>,
     duration: Option<String>,
@@ -54,7 +65,8 @@ struct FfprobeStream {
     Probe a video file using ffprobe to get its metadata.
 
     This function runs ffprobe as a subprocess and parses the JSON output
-    to extract video dimensions and duration.
+    to extract video dimensions, duration, and the codecs of the first
+    video and audio streams.
 
     Returns an error if:
     - ffprobe is not installed or fails to execute
@@ -63,15 +75,7 @@ struct FfprobeStream {
 */
 pub fn probe_video(path: &Path) -> Result<VideoInfo, ProbeError> {
     let output = Command::new("ffprobe")
-        .args([
-            "-v",
-            "quiet",
-            "-print_format",
-            "json",
-            "-show_streams",
-            "-select_streams",
-            "v:0", // Select only the first video stream
-        ])
+        .args(["-v", "quiet", "-print_format", "json", "-show_streams"])
         .arg(path)
         .output()
         .map_err(ProbeError::ExecutionFailed)?;
@@ -83,6 +87,12 @@ pub fn probe_video(path: &Path) -> Result<VideoInfo, ProbeError> {
     let probe_output: FfprobeOutput =
         serde_json::from_slice(&output.stdout).map_err(ProbeError::ParseFailed)?;
 
+    let audio_codec = probe_output
+        .streams
+        .iter()
+        .find(|s| s.codec_type == "audio")
+        .and_then(|s| s.codec_name.clone());
+
     // Find the video stream
     let video_stream = probe_output
         .streams
@@ -100,5 +110,8 @@ pub fn probe_video(path: &Path) -> Result<VideoInfo, ProbeError> {
             .map(|secs| Duration::from_secs_f64(secs))
     });
 
-    Ok(VideoInfo::new(path.to_path_buf(), width, height, duration))
+    let mut info = VideoInfo::new(path.to_path_buf(), width, height, duration);
+    info.video_codec = video_stream.codec_name;
+    info.audio_codec = audio_codec;
+    Ok(info)
 }
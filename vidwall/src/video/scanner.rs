@@ -1,13 +1,15 @@
+use std::collections::HashSet;
 use std::path::PathBuf;
 use std::sync::{
-    Arc,
+    Arc, Mutex,
     atomic::{AtomicUsize, Ordering},
 };
 use std::thread;
 
 use walkdir::WalkDir;
 
-use super::{ReadyVideos, probe_video};
+use super::library::{FileStamp, Lookup};
+use super::{MediaLibrary, MediaSource, ReadyVideos, VideoInfo, probe_video};
 
 /**
     Supported video file extensions for quick pre-filtering.
@@ -84,22 +86,33 @@ impl VideoScanner {
         Probe multiple video files in parallel using a thread pool.
 
         This spawns `NUM_WORKERS` threads that pull from the candidate list
-        and validate videos concurrently.
+        and validate videos concurrently. Files that are unchanged since the
+        library indexed them are taken from it instead of being probed, and
+        the library is updated and saved once all files are done.
     */
-    pub fn probe_all_parallel(ready_videos: Arc<ReadyVideos>, candidates: Vec<PathBuf>) {
+    pub fn probe_all_parallel(
+        ready_videos: Arc<ReadyVideos>,
+        candidates: Vec<PathBuf>,
+        scanned: Vec<PathBuf>,
+        library: MediaLibrary,
+    ) {
         if candidates.is_empty() {
             println!("\nScanning complete. 0 valid videos found.");
             return;
         }
 
         let candidates = Arc::new(candidates);
+        let library = Arc::new(Mutex::new(library));
         let next_index = Arc::new(AtomicUsize::new(0));
+        let probed = Arc::new(AtomicUsize::new(0));
         let mut handles = Vec::with_capacity(NUM_WORKERS);
 
         for _ in 0..NUM_WORKERS {
             let ready_videos = Arc::clone(&ready_videos);
             let candidates = Arc::clone(&candidates);
+            let library = Arc::clone(&library);
             let next_index = Arc::clone(&next_index);
+            let probed = Arc::clone(&probed);
 
             let handle = thread::spawn(move || {
                 loop {
@@ -110,6 +123,18 @@ impl VideoScanner {
                     }
 
                     let path = &candidates[index];
+                    let stamp = FileStamp::of(path);
+                    let known = stamp.map(|stamp| library.lock().unwrap().lookup(path, stamp));
+                    match known {
+                        Some(Lookup::Video(info)) => {
+                            ready_videos.push(info);
+                            continue;
+                        }
+                        Some(Lookup::Invalid) => continue,
+                        Some(Lookup::Unknown) | None => {}
+                    }
+
+                    probed.fetch_add(1, Ordering::Relaxed);
                     match probe_video(path) {
                         Ok(info) => {
                             println!(
@@ -118,6 +143,9 @@ impl VideoScanner {
                                 info.width,
                                 info.height
                             );
+                            if let Some(stamp) = stamp {
+                                library.lock().unwrap().record(path, stamp, Some(&info));
+                            }
                             ready_videos.push(info);
                         }
                         Err(e) => {
@@ -126,6 +154,11 @@ impl VideoScanner {
                                 path.file_name().unwrap_or_default().to_string_lossy(),
                                 e
                             );
+                            if let Some(stamp) = stamp
+                                && e.is_invalid_file()
+                            {
+                                library.lock().unwrap().record(path, stamp, None);
+                            }
                         }
                     }
                 }
@@ -139,10 +172,22 @@ impl VideoScanner {
             let _ = handle.join();
         }
 
+        // Forget files that are gone, and keep the rest for the next launch
+        let found: HashSet<PathBuf> = candidates.iter().cloned().collect();
+        let mut library = library.lock().unwrap();
+        let forgotten = library.prune(&scanned, &found);
+        library.save();
+
+        let probed = probed.load(Ordering::Relaxed);
         println!(
-            "\nScanning complete. {} valid videos found.",
-            ready_videos.len()
+            "\nScanning complete. {} valid videos found ({} probed, {} from the library).",
+            ready_videos.len(),
+            probed,
+            candidates.len() - probed
         );
+        if forgotten > 0 {
+            println!("Removed {} missing file(s) from the library.", forgotten);
+        }
 
         if ready_videos.is_empty() {
             eprintln!("No valid video files found in selected paths.");
@@ -152,6 +197,87 @@ impl VideoScanner {
         }
     }
 
+    /**
+        Collect the sources in a list given for fixed tiles, keeping their order.
+
+        Each entry is parsed as a `MediaSource`. Folders are expanded to the
+        video files inside them, sorted by path, and every other kind of
+        source (streams, cameras, proxied channels) is resolved to the
+        location its player opens.
+    */
+    pub fn collect_sources(paths: &[PathBuf]) -> Vec<PathBuf> {
+        let mut sources = Vec::new();
+
+        for path in paths {
+            let source = match MediaSource::from_path(path) {
+                Ok(source) => source,
+                Err(e) => {
+                    eprintln!("  Skipped: {} ({})", path.display(), e);
+                    continue;
+                }
+            };
+
+            match source {
+                MediaSource::File(file) => {
+                    if file.is_dir() {
+                        sources.extend(Self::collect_video_candidates(std::slice::from_ref(&file)));
+                    } else if file.is_file() && Self::has_video_extension(&file) {
+                        sources.push(file);
+                    } else {
+                        eprintln!("  Skipped: {} (not a video file or URL)", file.display());
+                    }
+                }
+                source => match source.resolve() {
+                    Ok(resolved) => sources.push(resolved),
+                    Err(e) => eprintln!("  Skipped: {} ({})", source, e),
+                },
+            }
+        }
+
+        sources
+    }
+
+    /**
+        Probe all sources in parallel, returning the valid ones in their original order.
+    */
+    pub fn probe_sources(sources: Vec<PathBuf>) -> Vec<VideoInfo> {
+        let results = Mutex::new(vec![None; sources.len()]);
+        let next_index = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            for _ in 0..NUM_WORKERS.min(sources.len()) {
+                scope.spawn(|| {
+                    loop {
+                        let index = next_index.fetch_add(1, Ordering::SeqCst);
+                        let Some(source) = sources.get(index) else {
+                            break;
+                        };
+
+                        match probe_video(source) {
+                            Ok(info) => {
+                                println!(
+                                    "  Validated: {} ({}x{})",
+                                    source.display(),
+                                    info.width,
+                                    info.height
+                                );
+                                results.lock().unwrap()[index] = Some(info);
+                            }
+                            Err(e) => eprintln!("  Skipped: {} ({})", source.display(), e),
+                        }
+                    }
+                });
+            }
+        });
+
+        results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
     /**
         Collect all files with video extensions from the given paths.
     */
@@ -0,0 +1,230 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/**
+    Environment variable with the address of the vidproxy server
+    that `vidproxy://` sources are played from
+*/
+const VIDPROXY_URL_VAR: &str = "VIDPROXY_URL";
+
+/**
+    Address of the vidproxy server when `VIDPROXY_URL` isn't set
+*/
+const DEFAULT_VIDPROXY_URL: &str = "http://localhost:8098";
+
+/**
+    Get the address of the vidproxy server, from `VIDPROXY_URL` or the default.
+*/
+pub fn vidproxy_url() -> String {
+    std::env::var(VIDPROXY_URL_VAR)
+        .ok()
+        .filter(|url| !url.is_empty())
+        .unwrap_or_else(|| DEFAULT_VIDPROXY_URL.to_string())
+}
+
+/**
+    Error type for parsing and resolving media sources.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceError {
+    /// The source is malformed for its scheme
+    Invalid(String),
+    /// The source is valid, but can't be played in a tile
+    Unsupported(String),
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceError::Invalid(reason) => write!(f, "Invalid source: {}", reason),
+            SourceError::Unsupported(reason) => write!(f, "Unsupported source: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+/**
+    Something a tile can show, as given on the command line or dropped
+    onto the grid.
+
+    Every way of referring to a source goes through `MediaSource::parse`,
+    and every source is turned into something the decoder can open by
+    `resolve`, so new kinds of sources only need to be added here.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MediaSource {
+    /// A local video file, given as a path or a `file://` URL
+    File(PathBuf),
+    /// An HTTP(S) stream, such as an HLS playlist or a DASH manifest
+    Http(String),
+    /// An RTSP camera or server
+    Rtsp(String),
+    /// A channel of a vidproxy source, `vidproxy://source/channel`
+    Vidproxy { source: String, channel: String },
+    /// A still image, `image:///path/to/image.png`
+    Image(PathBuf),
+    /// A web page, `web://example.com/page`
+    Web(String),
+    /// Any other URL, for protocols the decoder opens on its own (udp, srt, rtmp)
+    Url(String),
+}
+
+impl MediaSource {
+    /**
+        Parse a source from a path or URL.
+    */
+    pub fn parse(source: &str) -> Result<Self, SourceError> {
+        let Some((scheme, rest)) = source.split_once("://") else {
+            return Ok(Self::File(PathBuf::from(source)));
+        };
+        if rest.is_empty() {
+            return Err(SourceError::Invalid(format!("nothing after {}://", scheme)));
+        }
+
+        match scheme.to_ascii_lowercase().as_str() {
+            "file" => Ok(Self::File(PathBuf::from(rest))),
+            "http" | "https" => Ok(Self::Http(source.to_string())),
+            "rtsp" | "rtsps" => Ok(Self::Rtsp(source.to_string())),
+            "vidproxy" => match rest.trim_end_matches('/').split_once('/') {
+                Some((source, channel))
+                    if !source.is_empty() && !channel.is_empty() && !channel.contains('/') =>
+                {
+                    Ok(Self::Vidproxy {
+                        source: source.to_string(),
+                        channel: channel.to_string(),
+                    })
+                }
+                _ => Err(SourceError::Invalid(format!(
+                    "expected vidproxy://source/channel, got {}",
+                    source
+                ))),
+            },
+            "image" => Ok(Self::Image(PathBuf::from(rest))),
+            "web" => match rest.starts_with("http://") || rest.starts_with("https://") {
+                true => Ok(Self::Web(rest.to_string())),
+                false => Ok(Self::Web(format!("https://{}", rest))),
+            },
+            _ => Ok(Self::Url(source.to_string())),
+        }
+    }
+
+    /**
+        Parse a source given as a path, which may really be a URL.
+    */
+    pub fn from_path(path: &Path) -> Result<Self, SourceError> {
+        match path.to_str() {
+            Some(source) => Self::parse(source),
+            None => Ok(Self::File(path.to_path_buf())),
+        }
+    }
+
+    /**
+        Resolve the source to a location the decoder can open, using the
+        vidproxy server from `VIDPROXY_URL`.
+    */
+    pub fn resolve(&self) -> Result<PathBuf, SourceError> {
+        self.resolve_with(&vidproxy_url())
+    }
+
+    /**
+        Resolve the source to a location the decoder can open, using the
+        vidproxy server at the given address.
+    */
+    pub fn resolve_with(&self, vidproxy_url: &str) -> Result<PathBuf, SourceError> {
+        match self {
+            // Images decode as a video of a single frame
+            Self::File(path) | Self::Image(path) => Ok(path.clone()),
+            Self::Http(url) | Self::Rtsp(url) | Self::Url(url) => Ok(PathBuf::from(url)),
+            Self::Vidproxy { source, channel } => Ok(PathBuf::from(format!(
+                "{}/{}/{}/playlist.m3u8",
+                vidproxy_url.trim_end_matches('/'),
+                source,
+                channel
+            ))),
+            Self::Web(url) => Err(SourceError::Unsupported(format!(
+                "{} is a web page, which tiles can't show yet",
+                url
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for MediaSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Http(url) | Self::Rtsp(url) | Self::Url(url) => write!(f, "{}", url),
+            Self::Vidproxy { source, channel } => write!(f, "vidproxy://{}/{}", source, channel),
+            Self::Image(path) => write!(f, "image://{}", path.display()),
+            Self::Web(url) => write!(f, "web://{}", url),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let parse = |s| MediaSource::parse(s).unwrap();
+        assert_eq!(
+            parse("/videos/a.mp4"),
+            MediaSource::File("/videos/a.mp4".into())
+        );
+        assert_eq!(
+            parse("file:///videos/a.mp4"),
+            MediaSource::File("/videos/a.mp4".into())
+        );
+        assert_eq!(
+            parse("https://cdn.example.com/live.m3u8"),
+            MediaSource::Http("https://cdn.example.com/live.m3u8".into())
+        );
+        assert_eq!(
+            parse("RTSP://cam1/live"),
+            MediaSource::Rtsp("RTSP://cam1/live".into())
+        );
+        assert_eq!(
+            parse("vidproxy://news/channel-1/"),
+            MediaSource::Vidproxy {
+                source: "news".into(),
+                channel: "channel-1".into()
+            }
+        );
+        assert_eq!(
+            parse("web://example.com/page"),
+            MediaSource::Web("https://example.com/page".into())
+        );
+        assert_eq!(
+            parse("srt://host:9000"),
+            MediaSource::Url("srt://host:9000".into())
+        );
+
+        assert!(MediaSource::parse("vidproxy://news").is_err());
+        assert!(MediaSource::parse("vidproxy://news/a/b").is_err());
+        assert!(MediaSource::parse("https://").is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let source = MediaSource::parse("vidproxy://news/channel-1").unwrap();
+        assert_eq!(
+            source.resolve_with("http://proxy:8098/").unwrap(),
+            PathBuf::from("http://proxy:8098/news/channel-1/playlist.m3u8")
+        );
+        assert_eq!(source.to_string(), "vidproxy://news/channel-1");
+
+        let image = MediaSource::parse("image:///pictures/a.png").unwrap();
+        assert_eq!(
+            image.resolve_with("").unwrap(),
+            PathBuf::from("/pictures/a.png")
+        );
+
+        let web = MediaSource::parse("web://example.com").unwrap();
+        assert!(matches!(
+            web.resolve_with(""),
+            Err(SourceError::Unsupported(_))
+        ));
+    }
+}
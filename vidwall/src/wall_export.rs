@@ -0,0 +1,330 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::video::MediaSource;
+
+/**
+    How long to wait on the vidproxy server when exporting or importing channels
+*/
+const VIDPROXY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/**
+    Version of the export format written by this build, newer files are refused
+*/
+const WALL_EXPORT_VERSION: u32 = 1;
+
+/**
+    A wall setup exported to a single JSON file, so that the same wall can
+    be set up on another machine.
+
+    Holds the sources of the wall and the same settings as the command line
+    flags, each left out when it wasn't set. The vidproxy channels the wall
+    plays are listed as "source:id", along with their settings (favorites,
+    hidden channels and segmenting, and keys if asked for) as vidproxy
+    exports them, so that they can be imported into the vidproxy server of
+    the other machine.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WallExport {
+    #[serde(default)]
+    pub version: u32,
+    /// Video files, folders and URLs, in tile order
+    #[serde(default)]
+    pub sources: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tiles: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tiles: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotate_secs: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motion: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub span: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bezel_x: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bezel_y: Option<u32>,
+    /// Name of the audio policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_mb: Option<u32>,
+    /// Name of the scaling algorithm for small tiles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scaling_small: Option<String>,
+    /// Name of the scaling algorithm for large tiles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scaling_large: Option<String>,
+    /// vidproxy channels played by the wall, as "source:id"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vidproxy_channels: Vec<String>,
+    /// Channel set of those channels, as exported by vidproxy, passed on as is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vidproxy_channel_set: Option<serde_json::Value>,
+}
+
+impl WallExport {
+    /**
+        Load an exported wall, resolving relative paths among its sources
+        against the folder the file is in.
+    */
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut wall: Self = serde_json::from_str(&contents)
+            .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+        if wall.version > WALL_EXPORT_VERSION {
+            return Err(format!(
+                "{} was exported by a newer version (format {}, this build reads up to {})",
+                path.display(),
+                wall.version,
+                WALL_EXPORT_VERSION
+            ));
+        }
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        wall.sources = wall
+            .sources
+            .into_iter()
+            .map(|source| resolve_source(&source, base))
+            .collect();
+        Ok(wall)
+    }
+
+    /**
+        Save the wall to a file, with its local paths made absolute and the
+        vidproxy channels it plays listed.
+    */
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut wall = self.clone();
+        wall.version = WALL_EXPORT_VERSION;
+        wall.sources = wall
+            .sources
+            .iter()
+            .map(|source| absolute_source(source))
+            .collect();
+        wall.vidproxy_channels = wall.referenced_channels();
+
+        let json = serde_json::to_string_pretty(&wall)
+            .map_err(|e| format!("Failed to serialize wall: {}", e))?;
+        fs::write(path, json).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /**
+        Combine this wall with an imported one, settling conflicts in favor
+        of this one: its settings replace those of the imported wall, and its
+        sources come after the imported sources, skipping any already there.
+    */
+    pub fn over(self, imported: Self) -> Self {
+        let mut sources = imported.sources;
+        for source in self.sources {
+            if !sources.contains(&source) {
+                sources.push(source);
+            }
+        }
+
+        Self {
+            version: imported.version,
+            sources,
+            tiles: self.tiles.or(imported.tiles),
+            max_tiles: self.max_tiles.or(imported.max_tiles),
+            rotate_secs: self.rotate_secs.or(imported.rotate_secs),
+            motion: self.motion.or(imported.motion),
+            span: self.span.or(imported.span),
+            bezel_x: self.bezel_x.or(imported.bezel_x),
+            bezel_y: self.bezel_y.or(imported.bezel_y),
+            audio: self.audio.or(imported.audio),
+            memory_mb: self.memory_mb.or(imported.memory_mb),
+            scaling_small: self.scaling_small.or(imported.scaling_small),
+            scaling_large: self.scaling_large.or(imported.scaling_large),
+            vidproxy_channels: imported.vidproxy_channels,
+            vidproxy_channel_set: imported.vidproxy_channel_set,
+        }
+    }
+
+    /**
+        Fetch the channel set of the vidproxy channels the wall plays from
+        the vidproxy server at the given address.
+
+        Decryption keys are left out unless `include_keys` is set, since the
+        file is meant to be copied to other machines.
+
+        Does nothing if the wall plays no vidproxy channels.
+    */
+    pub fn fetch_channel_set(
+        &mut self,
+        vidproxy_url: &str,
+        include_keys: bool,
+    ) -> Result<(), String> {
+        let channels = self.referenced_channels();
+        if channels.is_empty() {
+            return Ok(());
+        }
+
+        let url = format!("{}/api/channels/export", vidproxy_url.trim_end_matches('/'));
+        let mut query = vec![("channels", channels.join(","))];
+        if include_keys {
+            query.push(("include_keys", String::from("true")));
+        }
+        let set = vidproxy_client()?
+            .get(&url)
+            .query(&query)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.json::<serde_json::Value>())
+            .map_err(|e| format!("Failed to export channels from {}: {}", url, e))?;
+        self.vidproxy_channel_set = Some(set);
+        Ok(())
+    }
+
+    /**
+        Import the channel set of the wall into the vidproxy server at the
+        given address. Channels that already have settings there keep them.
+
+        Does nothing if the wall has no channel set.
+    */
+    pub fn import_channel_set(&self, vidproxy_url: &str) -> Result<(), String> {
+        let Some(set) = &self.vidproxy_channel_set else {
+            return Ok(());
+        };
+
+        let url = format!("{}/api/channels/import", vidproxy_url.trim_end_matches('/'));
+        let summary = vidproxy_client()?
+            .post(&url)
+            .json(set)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.json::<serde_json::Value>())
+            .map_err(|e| format!("Failed to import channels into {}: {}", url, e))?;
+
+        let count = |key: &str| summary[key].as_array().map_or(0, Vec::len);
+        println!(
+            "Imported channels into vidproxy: {} added, {} kept",
+            count("added"),
+            count("kept")
+        );
+        Ok(())
+    }
+
+    /**
+        Get the vidproxy channels among the sources, as "source:id", without duplicates.
+    */
+    pub fn referenced_channels(&self) -> Vec<String> {
+        let mut channels = Vec::new();
+        for source in &self.sources {
+            if let Ok(MediaSource::Vidproxy { source, channel }) = MediaSource::parse(source) {
+                let id = format!("{}:{}", source, channel);
+                if !channels.contains(&id) {
+                    channels.push(id);
+                }
+            }
+        }
+        channels
+    }
+}
+
+fn vidproxy_client() -> Result<reqwest::blocking::Client, String> {
+    reqwest::blocking::Client::builder()
+        .timeout(VIDPROXY_TIMEOUT)
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))
+}
+
+/**
+    Resolve a relative local path against a folder, leaving URLs alone.
+*/
+fn resolve_source(source: &str, base: &Path) -> String {
+    if source.contains("://") || Path::new(source).is_absolute() {
+        return source.to_string();
+    }
+    base.join(source).to_string_lossy().into_owned()
+}
+
+/**
+    Make a local path absolute against the working directory, leaving URLs alone.
+*/
+fn absolute_source(source: &str) -> String {
+    if source.contains("://") {
+        return source.to_string();
+    }
+    std::path::absolute(source)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| source.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_line_wins_conflicts() {
+        let imported = WallExport {
+            sources: vec!["rtsp://cam1/live".to_string(), "/videos".to_string()],
+            tiles: Some(true),
+            max_tiles: Some(4),
+            audio: Some("focus".to_string()),
+            scaling_small: Some("bicubic".to_string()),
+            vidproxy_channel_set: Some(serde_json::json!({ "favorites": ["news:1"] })),
+            ..Default::default()
+        };
+        let command_line = WallExport {
+            sources: vec!["/videos".to_string(), "vidproxy://news/1".to_string()],
+            max_tiles: Some(9),
+            scaling_small: Some("bilinear".to_string()),
+            ..Default::default()
+        };
+
+        let wall = command_line.over(imported);
+        assert_eq!(
+            wall.sources,
+            vec!["rtsp://cam1/live", "/videos", "vidproxy://news/1"]
+        );
+        assert_eq!(wall.tiles, Some(true));
+        assert_eq!(wall.max_tiles, Some(9));
+        assert_eq!(wall.audio.as_deref(), Some("focus"));
+        assert_eq!(wall.scaling_small.as_deref(), Some("bilinear"));
+        assert!(wall.vidproxy_channel_set.is_some());
+    }
+
+    #[test]
+    fn test_referenced_channels() {
+        let wall = WallExport {
+            sources: vec![
+                "vidproxy://news/1".to_string(),
+                "/videos".to_string(),
+                "vidproxy://sports/main/".to_string(),
+                "vidproxy://news/1".to_string(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(wall.referenced_channels(), vec!["news:1", "sports:main"]);
+    }
+
+    #[test]
+    fn test_resolve_relative_sources() {
+        let base = Path::new("/walls");
+        assert_eq!(resolve_source("clips", base), "/walls/clips");
+        assert_eq!(resolve_source("/videos", base), "/videos");
+        assert_eq!(
+            resolve_source("vidproxy://news/1", base),
+            "vidproxy://news/1"
+        );
+    }
+
+    #[test]
+    fn test_unset_settings_are_left_out() {
+        let wall = WallExport {
+            version: WALL_EXPORT_VERSION,
+            sources: vec!["/videos".to_string()],
+            span: Some(true),
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&wall).unwrap();
+        assert_eq!(json, r#"{"version":1,"sources":["/videos"],"span":true}"#);
+        assert_eq!(serde_json::from_str::<WallExport>(&json).unwrap(), wall);
+    }
+}